@@ -211,28 +211,19 @@ impl vfs::NFSFileSystem for DemoFS {
                 entry.attr.mtime.nseconds = d.subsec_nanos();
             }
         };
-        match setattr.uid {
-            nfs3::set_uid3::Some(u) => {
-                entry.attr.uid = u;
-            }
-            nfs3::set_uid3::None => {}
+        if let nfs3::set_uid3::Some(u) = setattr.uid {
+            entry.attr.uid = u;
         }
-        match setattr.gid {
-            nfs3::set_gid3::Some(u) => {
-                entry.attr.gid = u;
-            }
-            nfs3::set_gid3::None => {}
-        }
-        match setattr.size {
-            nfs3::set_size3::Some(s) => {
-                entry.attr.size = s;
-                entry.attr.used = s;
-                if let FSContents::File(shared_bytes) = &mut entry.contents {
-                    let mut bytes = shared_bytes.write().unwrap();
-                    bytes.resize(s as usize, 0);
-                }
+        if let nfs3::set_gid3::Some(u) = setattr.gid {
+            entry.attr.gid = u;
+        }
+        if let nfs3::set_size3::Some(s) = setattr.size {
+            entry.attr.size = s;
+            entry.attr.used = s;
+            if let FSContents::File(shared_bytes) = &mut entry.contents {
+                let mut bytes = shared_bytes.write().unwrap();
+                bytes.resize(s as usize, 0);
             }
-            nfs3::set_size3::None => {}
         }
         Ok(entry.attr)
     }
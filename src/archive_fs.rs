@@ -0,0 +1,370 @@
+//! A read-only file system that serves the contents of an archive.
+//!
+//! [`ArchiveFS`] indexes a tar file (optionally gzip- or zstd-compressed) or a zip file
+//! once when it is opened and then answers lookups, attribute requests and reads from
+//! that index without unpacking anything to disk. Regular files are read with
+//! positioned reads at the offset recorded in the index. Data that has no stable
+//! offset in the archive (compressed tarballs, deflated zip members and GNU sparse
+//! files) is decompressed once into an unlinked temporary file and served from there.
+//!
+//! Symbolic links, hard links and device nodes are exported as they are recorded in
+//! the archive. File IDs follow archive order, so they are the same every time the
+//! same archive is opened.
+//!
+//! ```ignore
+//! use fernfs::archive_fs::ArchiveFS;
+//! use fernfs::tcp::NFSTcpListener;
+//!
+//! let fs = ArchiveFS::open("artifacts.tar.zst")?;
+//! let listener = NFSTcpListener::bind("127.0.0.1:11111", fs).await?;
+//! ```
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use tracing::debug;
+
+use crate::vfs;
+use crate::xdr::nfs3;
+
+mod spool;
+mod tar_index;
+mod tree;
+mod zip_index;
+
+use spool::Spool;
+use tree::{Kind, Node, TreeBuilder, ROOT_ID};
+
+type NFSResult<T> = Result<T, nfs3::nfsstat3>;
+
+/// Container formats understood by [`ArchiveFS`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    /// Uncompressed tar
+    Tar,
+    /// gzip-compressed tar (`.tar.gz`, `.tgz`)
+    TarGz,
+    /// zstd-compressed tar (`.tar.zst`)
+    TarZstd,
+    /// zip file
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Identifies the format of the file at `path` from its leading bytes.
+    pub fn detect(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut head = Vec::with_capacity(512);
+        File::open(path)?.take(512).read_to_end(&mut head)?;
+        if head.starts_with(&[0x1f, 0x8b]) {
+            Ok(Self::TarGz)
+        } else if head.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Ok(Self::TarZstd)
+        } else if head.starts_with(b"PK\x03\x04") || head.starts_with(b"PK\x05\x06") {
+            Ok(Self::Zip)
+        } else if head.get(257..262) == Some(b"ustar") {
+            Ok(Self::Tar)
+        } else {
+            Err(io::Error::new(io::ErrorKind::InvalidData, "unrecognized archive format"))
+        }
+    }
+}
+
+/// A read-only [`NFSFileSystem`](vfs::NFSFileSystem) backed by an archive.
+///
+/// See the [module documentation](self) for an overview.
+#[derive(Debug)]
+pub struct ArchiveFS {
+    /// All objects, indexed by file ID - 1
+    nodes: Vec<Node>,
+    generation: u64,
+    /// Sum of the sizes of all files, reported by FSSTAT
+    total_bytes: u64,
+}
+
+impl ArchiveFS {
+    /// Opens and indexes the archive at `path`, detecting its format.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let format = ArchiveFormat::detect(&path)?;
+        Self::open_as(path, format)
+    }
+
+    /// Opens and indexes the archive at `path` as `format`.
+    pub fn open_as(path: impl AsRef<Path>, format: ArchiveFormat) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let meta = file.metadata()?;
+        // The root and directories the archive only implies take the archive's owner and
+        // modification time.
+        let mtime =
+            nfs3::nfstime3 { seconds: meta.mtime() as u32, nseconds: meta.mtime_nsec() as u32 };
+        let dir_attr = nfs3::fattr3 {
+            mode: 0o755,
+            uid: meta.uid(),
+            gid: meta.gid(),
+            atime: mtime,
+            mtime,
+            ctime: mtime,
+            ..Default::default()
+        };
+        let mut tree = TreeBuilder::new(dir_attr);
+        let mut spool = Spool::default();
+        let file = Arc::new(file);
+        match format {
+            ArchiveFormat::Tar => tar_index::index(&file, &mut tree, &mut spool)?,
+            ArchiveFormat::Zip => zip_index::index(&file, &mut tree, &mut spool)?,
+            ArchiveFormat::TarGz | ArchiveFormat::TarZstd => {
+                let mut image = Spool::default();
+                if format == ArchiveFormat::TarGz {
+                    image.append(&mut flate2::read::MultiGzDecoder::new(&*file))?;
+                } else {
+                    image.append(&mut zstd::stream::read::Decoder::new(&*file)?)?;
+                }
+                let image = image.into_file().ok_or(io::ErrorKind::UnexpectedEof)?;
+                tar_index::index(&image, &mut tree, &mut spool)?;
+            }
+        }
+        Ok(Self::from_nodes(tree.finish()))
+    }
+
+    fn from_nodes(nodes: Vec<Node>) -> Self {
+        let total_bytes = nodes
+            .iter()
+            .filter(|node| matches!(node.kind, Kind::File(_)))
+            .map(|node| node.attr.size)
+            .sum();
+        let generation = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        Self { nodes, generation, total_bytes }
+    }
+
+    fn node(&self, id: nfs3::fileid3) -> NFSResult<&Node> {
+        id.checked_sub(1)
+            .and_then(|index| self.nodes.get(index as usize))
+            .ok_or(nfs3::nfsstat3::NFS3ERR_STALE)
+    }
+}
+
+#[async_trait]
+impl vfs::NFSFileSystem for ArchiveFS {
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn capabilities(&self) -> vfs::Capabilities {
+        vfs::Capabilities::ReadOnly
+    }
+
+    fn root_dir(&self) -> nfs3::fileid3 {
+        ROOT_ID
+    }
+
+    async fn lookup(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> NFSResult<nfs3::fileid3> {
+        let dir = self.node(dirid)?;
+        let entries = dir.entries()?;
+        match &filename[..] {
+            b"." => Ok(dirid),
+            b".." => Ok(dir.parent),
+            name => entries.get(name).copied().ok_or(nfs3::nfsstat3::NFS3ERR_NOENT),
+        }
+    }
+
+    async fn getattr(&self, id: nfs3::fileid3) -> NFSResult<nfs3::fattr3> {
+        Ok(self.node(id)?.attr)
+    }
+
+    async fn setattr(&self, _id: nfs3::fileid3, _setattr: nfs3::sattr3) -> NFSResult<nfs3::fattr3> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn read(&self, id: nfs3::fileid3, offset: u64, count: u32) -> NFSResult<(Vec<u8>, bool)> {
+        let node = self.node(id)?;
+        let extent = match &node.kind {
+            Kind::File(extent) => extent,
+            Kind::Directory(_) => return Err(nfs3::nfsstat3::NFS3ERR_ISDIR),
+            _ => return Err(nfs3::nfsstat3::NFS3ERR_INVAL),
+        };
+        let size = node.attr.size;
+        let len = size.saturating_sub(offset).min(count as u64) as usize;
+        if len == 0 {
+            return Ok((Vec::new(), true));
+        }
+        let file = extent.file.clone();
+        let start = extent.offset + offset;
+        let buf = tokio::task::spawn_blocking(move || {
+            let mut buf = vec![0; len];
+            file.read_exact_at(&mut buf, start).map(|_| buf)
+        })
+        .await
+        .map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?
+        .map_err(|err| {
+            debug!("archive read failed: {err}");
+            nfs3::nfsstat3::NFS3ERR_IO
+        })?;
+        Ok((buf, offset + len as u64 >= size))
+    }
+
+    async fn write(
+        &self,
+        _id: nfs3::fileid3,
+        _offset: u64,
+        _data: &[u8],
+        _stable: nfs3::file::stable_how,
+    ) -> NFSResult<(nfs3::fattr3, nfs3::file::stable_how, nfs3::count3)> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn create(
+        &self,
+        _dirid: nfs3::fileid3,
+        _filename: &nfs3::filename3,
+        _attr: nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn create_exclusive(
+        &self,
+        _dirid: nfs3::fileid3,
+        _filename: &nfs3::filename3,
+        _verifier: nfs3::createverf3,
+    ) -> NFSResult<nfs3::fileid3> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn mkdir(
+        &self,
+        _dirid: nfs3::fileid3,
+        _dirname: &nfs3::filename3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn remove(&self, _dirid: nfs3::fileid3, _filename: &nfs3::filename3) -> NFSResult<()> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn rename(
+        &self,
+        _from_dirid: nfs3::fileid3,
+        _from_filename: &nfs3::filename3,
+        _to_dirid: nfs3::fileid3,
+        _to_filename: &nfs3::filename3,
+    ) -> NFSResult<()> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn readdir(
+        &self,
+        dirid: nfs3::fileid3,
+        start_after: nfs3::fileid3,
+        max_entries: usize,
+    ) -> NFSResult<vfs::ReadDirResult> {
+        let dir = self.node(dirid)?;
+        let entries = dir.entries()?;
+        // Cookies are indices into ".", "..", then the entries in archive order.
+        let total = entries.len() + 2;
+        let start = usize::try_from(start_after).map_err(|_| nfs3::nfsstat3::NFS3ERR_BAD_COOKIE)?;
+        if start > total {
+            return Err(nfs3::nfsstat3::NFS3ERR_BAD_COOKIE);
+        }
+        let end = total.min(start.saturating_add(max_entries));
+        let mut result = vfs::ReadDirResult { entries: Vec::new(), end: end == total };
+        for index in start..end {
+            let (name, fileid): (&[u8], _) = match index {
+                0 => (b".", dirid),
+                1 => (b"..", dir.parent),
+                _ => {
+                    let (name, fileid) =
+                        entries.get_index(index - 2).ok_or(nfs3::nfsstat3::NFS3ERR_IO)?;
+                    (name, *fileid)
+                }
+            };
+            let attr = self.node(fileid)?.attr;
+            result.entries.push(vfs::DirEntry { fileid, name: name.into(), attr });
+        }
+        Ok(result)
+    }
+
+    async fn readdir_index(
+        &self,
+        dirid: nfs3::fileid3,
+        start_index: usize,
+        max_entries: usize,
+    ) -> NFSResult<vfs::ReadDirResult> {
+        self.readdir(dirid, start_index as nfs3::fileid3, max_entries).await
+    }
+
+    async fn symlink(
+        &self,
+        _dirid: nfs3::fileid3,
+        _linkname: &nfs3::filename3,
+        _symlink: &nfs3::nfspath3,
+        _attr: &nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn readlink(&self, id: nfs3::fileid3) -> NFSResult<nfs3::nfspath3> {
+        match &self.node(id)?.kind {
+            Kind::Symlink(target) => Ok(target.clone().into()),
+            _ => Err(nfs3::nfsstat3::NFS3ERR_INVAL),
+        }
+    }
+
+    async fn link(
+        &self,
+        _file_id: nfs3::fileid3,
+        _link_dir_id: nfs3::fileid3,
+        _link_name: &nfs3::filename3,
+    ) -> NFSResult<nfs3::fattr3> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn mknod(
+        &self,
+        _dir_id: nfs3::fileid3,
+        _name: &nfs3::filename3,
+        _ftype: nfs3::ftype3,
+        _specdata: nfs3::specdata3,
+        _attrs: &nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn commit(
+        &self,
+        _file_id: nfs3::fileid3,
+        _offset: u64,
+        _count: u32,
+    ) -> NFSResult<nfs3::fattr3> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    fn fsinfo_properties(&self) -> u32 {
+        nfs3::fs::FSF_LINK | nfs3::fs::FSF_SYMLINK | nfs3::fs::FSF_HOMOGENEOUS
+    }
+
+    async fn fsstat(&self, root_fileid: nfs3::fileid3) -> NFSResult<nfs3::fs::FSSTAT3resok> {
+        Ok(nfs3::fs::FSSTAT3resok {
+            obj_attributes: self.node(root_fileid).ok().map(|node| node.attr),
+            tbytes: self.total_bytes,
+            fbytes: 0,
+            abytes: 0,
+            tfiles: self.nodes.len() as u64,
+            ffiles: 0,
+            afiles: 0,
+            // The contents never change.
+            invarsec: u32::MAX,
+        })
+    }
+}
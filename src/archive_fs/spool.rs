@@ -0,0 +1,57 @@
+//! Scratch storage for archive members that cannot be read in place.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use super::tree::Extent;
+
+/// An unlinked temporary file that decompressed data is appended to.
+///
+/// Compressed members have no stable byte offset to read from, so they are inflated
+/// here once while indexing and served with positioned reads afterwards. The file
+/// is removed from the directory as soon as it is created and disappears with the
+/// last handle.
+#[derive(Default)]
+pub struct Spool {
+    file: Option<Arc<File>>,
+    len: u64,
+}
+
+fn anonymous_file() -> io::Result<File> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    let name = format!(
+        "fernfs-archive-{}-{}-{}",
+        std::process::id(),
+        nanos.as_nanos(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    let path = std::env::temp_dir().join(name);
+    let file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)?;
+    std::fs::remove_file(&path)?;
+    Ok(file)
+}
+
+impl Spool {
+    /// Copies `src` to the end of the spool, returning where it landed and its length.
+    pub fn append(&mut self, src: &mut impl Read) -> io::Result<(Extent, u64)> {
+        let file = match &self.file {
+            Some(file) => file.clone(),
+            None => self.file.insert(Arc::new(anonymous_file()?)).clone(),
+        };
+        let offset = self.len;
+        let mut writer = &*file;
+        writer.seek(SeekFrom::Start(offset))?;
+        let len = io::copy(src, &mut writer)?;
+        self.len += len;
+        Ok((Extent { file, offset }, len))
+    }
+
+    /// Returns the spooled data as a single file, if anything was written.
+    pub fn into_file(self) -> Option<Arc<File>> {
+        self.file
+    }
+}
@@ -0,0 +1,85 @@
+//! Indexing of tar archives.
+
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom};
+use std::sync::Arc;
+
+use ::tar::{Archive, EntryType};
+
+use super::spool::Spool;
+use super::tree::{Extent, Kind, TreeBuilder};
+use crate::xdr::nfs3;
+
+/// Adds every member of the uncompressed tar `image` to `tree`.
+///
+/// Regular files are served straight from `image`; GNU sparse members, whose data is
+/// not stored contiguously, are expanded into `spool`.
+pub fn index(image: &Arc<File>, tree: &mut TreeBuilder, spool: &mut Spool) -> io::Result<()> {
+    // The image may be a spool that was just written, leaving the cursor at its end.
+    (&**image).seek(SeekFrom::Start(0))?;
+    let mut archive = Archive::new(&**image);
+    for entry in archive.entries_with_seek()? {
+        let mut entry = entry?;
+        let path = entry.path_bytes().into_owned();
+        let header = entry.header();
+        let mut attr = nfs3::fattr3 {
+            mode: header.mode()? & 0o7777,
+            uid: header.uid()? as nfs3::uid3,
+            gid: header.gid()? as nfs3::gid3,
+            size: entry.size(),
+            used: entry.size(),
+            mtime: nfs3::nfstime3 { seconds: header.mtime()? as u32, nseconds: 0 },
+            ..Default::default()
+        };
+        attr.atime = attr.mtime;
+        attr.ctime = attr.mtime;
+
+        let entry_type = header.entry_type();
+        let kind = match entry_type {
+            EntryType::Regular | EntryType::Continuous => {
+                Kind::File(Extent { file: image.clone(), offset: entry.raw_file_position() })
+            }
+            EntryType::GNUSparse => {
+                let (extent, len) = spool.append(&mut entry)?;
+                attr.size = len;
+                attr.used = len;
+                Kind::File(extent)
+            }
+            EntryType::Directory => {
+                attr.size = 0;
+                attr.used = 0;
+                Kind::Directory(Default::default())
+            }
+            EntryType::Symlink => {
+                let target = entry.link_name_bytes().unwrap_or_default().into_owned();
+                attr.size = target.len() as u64;
+                attr.used = attr.size;
+                Kind::Symlink(target)
+            }
+            EntryType::Link => {
+                let target = entry.link_name_bytes().unwrap_or_default().into_owned();
+                tree.add_hard_link(&path, &target);
+                continue;
+            }
+            EntryType::Char | EntryType::Block | EntryType::Fifo => {
+                attr.ftype = match entry_type {
+                    EntryType::Char => nfs3::ftype3::NF3CHR,
+                    EntryType::Block => nfs3::ftype3::NF3BLK,
+                    _ => nfs3::ftype3::NF3FIFO,
+                };
+                attr.rdev = nfs3::specdata3 {
+                    specdata1: header.device_major()?.unwrap_or(0),
+                    specdata2: header.device_minor()?.unwrap_or(0),
+                };
+                attr.size = 0;
+                attr.used = 0;
+                Kind::Special
+            }
+            // Long names and pax headers are folded into the following entry by the tar
+            // reader; anything else has no file system representation.
+            _ => continue,
+        };
+        tree.add(&path, attr, kind);
+    }
+    Ok(())
+}
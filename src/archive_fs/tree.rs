@@ -0,0 +1,214 @@
+//! The directory tree built while indexing an archive.
+
+use std::fs::File;
+use std::sync::Arc;
+
+use indexmap::IndexMap;
+use tracing::warn;
+
+use crate::xdr::nfs3;
+
+/// File ID of the root directory; other objects are numbered in archive order.
+pub const ROOT_ID: nfs3::fileid3 = 1;
+
+/// Where the contents of a regular file live.
+#[derive(Debug, Clone)]
+pub struct Extent {
+    pub file: Arc<File>,
+    pub offset: u64,
+}
+
+#[derive(Debug, Clone)]
+pub enum Kind {
+    /// Entries in archive order, which is also the READDIR order
+    Directory(IndexMap<Vec<u8>, nfs3::fileid3>),
+    File(Extent),
+    Symlink(Vec<u8>),
+    /// Device nodes and FIFOs
+    Special,
+}
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub attr: nfs3::fattr3,
+    pub parent: nfs3::fileid3,
+    pub kind: Kind,
+}
+
+impl Node {
+    pub fn entries(&self) -> Result<&IndexMap<Vec<u8>, nfs3::fileid3>, nfs3::nfsstat3> {
+        match &self.kind {
+            Kind::Directory(entries) => Ok(entries),
+            _ => Err(nfs3::nfsstat3::NFS3ERR_NOTDIR),
+        }
+    }
+}
+
+/// Splits an archive member path into components.
+///
+/// Leading `/` and `.` components are dropped so that `./a/b` and `/a/b` both name
+/// `a/b`. Paths that climb out of the archive with `..` are rejected.
+fn components(path: &[u8]) -> Option<Vec<&[u8]>> {
+    let mut parts = Vec::new();
+    for part in path.split(|b| *b == b'/') {
+        match part {
+            b"" | b"." => {}
+            b".." => return None,
+            _ => parts.push(part),
+        }
+    }
+    Some(parts)
+}
+
+/// Collects archive members into a tree, creating parent directories that the
+/// archive leaves implicit.
+pub struct TreeBuilder {
+    nodes: Vec<Node>,
+    /// Attributes given to directories the archive does not list
+    implicit_dir: nfs3::fattr3,
+}
+
+impl TreeBuilder {
+    /// Starts a tree whose root and implicit directories carry `dir_attr`.
+    pub fn new(dir_attr: nfs3::fattr3) -> Self {
+        let mut builder = Self { nodes: Vec::new(), implicit_dir: dir_attr };
+        builder.push(ROOT_ID, dir_attr, Kind::Directory(IndexMap::new()));
+        builder
+    }
+
+    fn push(&mut self, parent: nfs3::fileid3, mut attr: nfs3::fattr3, kind: Kind) -> nfs3::fileid3 {
+        let id = self.nodes.len() as nfs3::fileid3 + 1;
+        attr.fileid = id;
+        attr.ftype = match kind {
+            Kind::Directory(_) => nfs3::ftype3::NF3DIR,
+            Kind::File(_) => nfs3::ftype3::NF3REG,
+            Kind::Symlink(_) => nfs3::ftype3::NF3LNK,
+            Kind::Special => attr.ftype,
+        };
+        attr.nlink = 1;
+        self.nodes.push(Node { attr, parent, kind });
+        id
+    }
+
+    fn node_mut(&mut self, id: nfs3::fileid3) -> &mut Node {
+        &mut self.nodes[id as usize - 1]
+    }
+
+    fn entries_mut(&mut self, id: nfs3::fileid3) -> &mut IndexMap<Vec<u8>, nfs3::fileid3> {
+        match &mut self.node_mut(id).kind {
+            Kind::Directory(entries) => entries,
+            _ => unreachable!("parent of an archive member is always a directory"),
+        }
+    }
+
+    /// Walks to the directory holding `parts`'s last component, creating missing
+    /// directories and replacing non-directories that are in the way.
+    fn parent_of(&mut self, parts: &[&[u8]]) -> nfs3::fileid3 {
+        let mut dir = ROOT_ID;
+        for part in &parts[..parts.len() - 1] {
+            let existing = self.entries_mut(dir).get(*part).copied();
+            dir = match existing {
+                Some(id) if matches!(self.node_mut(id).kind, Kind::Directory(_)) => id,
+                _ => {
+                    let attr = self.implicit_dir;
+                    let id = self.push(dir, attr, Kind::Directory(IndexMap::new()));
+                    self.link(dir, part, id);
+                    id
+                }
+            };
+        }
+        dir
+    }
+
+    /// Points `dir/name` at `id`, unlinking whatever the name referred to before.
+    fn link(&mut self, dir: nfs3::fileid3, name: &[u8], id: nfs3::fileid3) {
+        if let Some(old) = self.entries_mut(dir).insert(name.to_vec(), id) {
+            let old = self.node_mut(old);
+            old.attr.nlink = old.attr.nlink.saturating_sub(1);
+        }
+    }
+
+    /// Adds a member. Later members replace earlier ones with the same path, as they
+    /// would when extracting, except that a directory listed twice keeps its contents.
+    pub fn add(&mut self, path: &[u8], attr: nfs3::fattr3, kind: Kind) {
+        let Some(parts) = components(path) else {
+            warn!("skipping archive member outside the root: {}", String::from_utf8_lossy(path));
+            return;
+        };
+        if parts.is_empty() {
+            // An entry for the root itself only carries its attributes.
+            if matches!(kind, Kind::Directory(_)) {
+                self.set_dir_attr(ROOT_ID, attr);
+            }
+            return;
+        }
+        let dir = self.parent_of(&parts);
+        let name = parts[parts.len() - 1];
+        let existing = self.entries_mut(dir).get(name).copied();
+        if let (Kind::Directory(_), Some(existing)) = (&kind, existing) {
+            if matches!(self.node_mut(existing).kind, Kind::Directory(_)) {
+                self.set_dir_attr(existing, attr);
+                return;
+            }
+        }
+        let id = self.push(dir, attr, kind);
+        self.link(dir, name, id);
+    }
+
+    fn set_dir_attr(&mut self, id: nfs3::fileid3, attr: nfs3::fattr3) {
+        let node = self.node_mut(id);
+        node.attr = nfs3::fattr3 { ftype: nfs3::ftype3::NF3DIR, fileid: id, ..attr };
+    }
+
+    /// Adds `path` as another name for the member at `target`.
+    pub fn add_hard_link(&mut self, path: &[u8], target: &[u8]) {
+        let target_id = components(target).and_then(|parts| self.resolve(&parts));
+        let Some(target_id) = target_id else {
+            warn!(
+                "skipping hard link {} to missing {}",
+                String::from_utf8_lossy(path),
+                String::from_utf8_lossy(target)
+            );
+            return;
+        };
+        let Some(parts) = components(path).filter(|parts| !parts.is_empty()) else {
+            warn!("skipping archive member outside the root: {}", String::from_utf8_lossy(path));
+            return;
+        };
+        if matches!(self.node_mut(target_id).kind, Kind::Directory(_)) {
+            warn!("skipping hard link to directory: {}", String::from_utf8_lossy(path));
+            return;
+        }
+        let dir = self.parent_of(&parts);
+        self.node_mut(target_id).attr.nlink += 1;
+        self.link(dir, parts[parts.len() - 1], target_id);
+    }
+
+    fn resolve(&mut self, parts: &[&[u8]]) -> Option<nfs3::fileid3> {
+        let mut id = ROOT_ID;
+        for part in parts {
+            id = *self.node_mut(id).entries().ok()?.get(*part)?;
+        }
+        Some(id)
+    }
+
+    /// Finalizes directory link counts and returns the nodes, indexed by file ID - 1.
+    pub fn finish(mut self) -> Vec<Node> {
+        let mut subdirs = vec![0u32; self.nodes.len()];
+        for node in &self.nodes {
+            if let Kind::Directory(entries) = &node.kind {
+                for id in entries.values() {
+                    if matches!(self.nodes[*id as usize - 1].kind, Kind::Directory(_)) {
+                        subdirs[node.attr.fileid as usize - 1] += 1;
+                    }
+                }
+            }
+        }
+        for (node, subdirs) in self.nodes.iter_mut().zip(subdirs) {
+            if matches!(node.kind, Kind::Directory(_)) {
+                node.attr.nlink = 2 + subdirs;
+            }
+        }
+        self.nodes
+    }
+}
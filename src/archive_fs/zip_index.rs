@@ -0,0 +1,89 @@
+//! Indexing of zip archives.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::sync::Arc;
+
+use ::zip::{CompressionMethod, DateTime, ZipArchive};
+use tracing::warn;
+
+use super::spool::Spool;
+use super::tree::{Extent, Kind, TreeBuilder};
+use crate::xdr::nfs3;
+
+fn zip_error(err: ::zip::result::ZipError) -> io::Error {
+    match err {
+        ::zip::result::ZipError::Io(err) => err,
+        err => io::Error::new(io::ErrorKind::InvalidData, err),
+    }
+}
+
+/// Converts a zip timestamp, which has no time zone, to seconds since the epoch as UTC.
+fn unix_time(time: DateTime) -> u32 {
+    // Days since 1970-01-01 for a proleptic Gregorian date (Howard Hinnant's algorithm).
+    let (month, day) = (time.month() as i64, time.day() as i64);
+    let year = time.year() as i64 - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    let seconds = time.hour() as i64 * 3600 + time.minute() as i64 * 60 + time.second() as i64;
+    (days * 86400 + seconds).clamp(0, u32::MAX as i64) as u32
+}
+
+/// Adds every member of the zip file `archive_file` to `tree`.
+///
+/// Stored members are served straight from the archive; compressed members are
+/// inflated into `spool`.
+pub fn index(
+    archive_file: &Arc<File>,
+    tree: &mut TreeBuilder,
+    spool: &mut Spool,
+) -> io::Result<()> {
+    let mut archive = ZipArchive::new(&**archive_file).map_err(zip_error)?;
+    for i in 0..archive.len() {
+        let member = archive.by_index_raw(i).map_err(zip_error)?;
+        let path = member.name_raw().to_vec();
+        if member.encrypted() {
+            warn!("skipping encrypted zip member {}", String::from_utf8_lossy(&path));
+            continue;
+        }
+        let is_dir = member.is_dir();
+        let is_symlink = member.is_symlink();
+        let stored = member.compression() == CompressionMethod::Stored;
+        let default_mode = if is_dir { 0o755 } else { 0o644 };
+        let mtime = member.last_modified().map(unix_time).unwrap_or(0);
+        let mut attr = nfs3::fattr3 {
+            mode: member.unix_mode().map(|mode| mode & 0o7777).unwrap_or(default_mode),
+            size: member.size(),
+            used: member.size(),
+            mtime: nfs3::nfstime3 { seconds: mtime, nseconds: 0 },
+            ..Default::default()
+        };
+        attr.atime = attr.mtime;
+        attr.ctime = attr.mtime;
+        let data_start = member.data_start();
+        drop(member);
+
+        let kind = if is_dir {
+            attr.size = 0;
+            attr.used = 0;
+            Kind::Directory(Default::default())
+        } else if is_symlink {
+            let mut target = Vec::new();
+            archive.by_index(i).map_err(zip_error)?.read_to_end(&mut target)?;
+            Kind::Symlink(target)
+        } else if stored {
+            Kind::File(Extent { file: archive_file.clone(), offset: data_start })
+        } else {
+            let mut member = archive.by_index(i).map_err(zip_error)?;
+            let (extent, len) = spool.append(&mut member)?;
+            attr.size = len;
+            attr.used = len;
+            Kind::File(extent)
+        };
+        tree.add(&path, attr, kind);
+    }
+    Ok(())
+}
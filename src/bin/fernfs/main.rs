@@ -1,36 +1,105 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+#[cfg(feature = "archive")]
+use fernfs::archive_fs::ArchiveFS;
+use fernfs::mirror_fs::{
+    ExcludeController, ExcludeSet, FsyncPolicy, MirrorFS, MountPolicy, SymlinkPolicy,
+};
 use fernfs::tcp::{NFSTcp, NFSTcpListener};
+use fernfs::vfs::NFSFileSystem;
 
 const DEFAULT_HOST: &str = "127.0.0.1";
 const DEFAULT_PORT: u16 = 11111;
 
-pub mod create_fs_object;
-pub mod error_handling;
-pub mod fs;
-pub mod fs_entry;
-pub mod fs_map;
+/// Serves `fs` on `bind_addr` until the process is stopped.
+async fn serve<T: NFSFileSystem + Send + Sync + 'static>(
+    bind_addr: &str,
+    fs: T,
+    require_privileged_source_port: bool,
+) {
+    let mut listener = NFSTcpListener::bind(bind_addr, fs).await.unwrap();
+    listener.require_privileged_source_port(require_privileged_source_port);
+    listener.handle_forever().await.unwrap();
+}
+
+/// Returns `patterns` followed by those in `file`, one per line; blank lines and
+/// lines starting with `#` are skipped.
+fn exclude_patterns(patterns: &[String], file: Option<&Path>) -> std::io::Result<Vec<String>> {
+    let mut all = patterns.to_vec();
+    if let Some(file) = file {
+        let text = std::fs::read_to_string(file)?;
+        let lines = text.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#'));
+        all.extend(lines.map(String::from));
+    }
+    Ok(all)
+}
+
+/// Reloads the exclude patterns whenever the process receives SIGHUP.
+fn reload_excludes_on_hangup(
+    patterns: Vec<String>,
+    file: Option<PathBuf>,
+    excludes: ExcludeController,
+) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(err) => {
+            tracing::warn!("Unable to reload exclude patterns on SIGHUP: {err}");
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            let reloaded = exclude_patterns(&patterns, file.as_deref()).and_then(ExcludeSet::new);
+            match reloaded {
+                Ok(exclude) => {
+                    excludes.set(exclude);
+                    tracing::info!("Reloaded exclude patterns");
+                }
+                Err(err) => tracing::warn!("Keeping the exclude patterns: {err}"),
+            }
+        }
+    });
+}
 
 /// Main entry point for the FernFS CLI (mirror file system)
 ///
 /// This function initializes the tracing subscriber, reads the directory path
-/// from command line arguments, creates a MirrorFS instance, and starts
-/// an NFS server on the specified port.
+/// from command line arguments, creates a MirrorFS instance (or an ArchiveFS
+/// when the path is an archive file), and starts an NFS server on the
+/// specified port.
 #[tokio::main]
 async fn main() {
     fn print_help() {
         eprintln!(
-            "Usage: fernfs [--host <HOST>] [--port <PORT>] [--allow-unprivileged-source-port] <DIRECTORY>\n\
+            "Usage: fernfs [OPTIONS] <DIRECTORY | ARCHIVE>\n\
+             \n\
+             A tar, tar.gz, tar.zst or zip ARCHIVE is exported read-only without unpacking.\n\
              \n\
              Options:\n\
                -h, --host <HOST>                Bind host (default: {DEFAULT_HOST})\n\
                -p, --port <PORT>                Bind port (default: {DEFAULT_PORT})\n\
                -v, --verbose                    Enable debug logging\n\
+               --read-only                      Export the directory read-only\n\
+               --exclude <PATTERN>              Hide entries matching a glob pattern (repeatable)\n\
+               --exclude-from <FILE>            Read exclude patterns from FILE, reloaded on SIGHUP\n\
+               --symlinks <POLICY>              allow, deny-create or hide (default: allow)\n\
+               --fsync <POLICY>                 requested, always or never (default: requested)\n\
+               --mounts <POLICY>                merge, cross or hide (default: merge)\n\
+               --watch                          Follow changes to the directory with inotify (Linux)\n\
+               --as-caller                      Change files with the client's uid and gids (requires root)\n\
                --allow-unprivileged-source-port Allow client source ports >= 1024 (default: require privileged)\n\
                --help                           Show this help and exit"
         );
     }
 
+    fn usage_error(message: &str) -> ! {
+        eprintln!("{message}");
+        eprintln!("Run with --help for usage.");
+        std::process::exit(2);
+    }
+
     fn require_value(flag: &str, args: &mut impl Iterator<Item = String>) -> String {
         args.next().unwrap_or_else(|| {
             eprintln!("Missing value for {flag}");
@@ -56,11 +125,46 @@ async fn main() {
         })
     }
 
+    fn parse_symlinks(value: &str) -> SymlinkPolicy {
+        match value {
+            "allow" => SymlinkPolicy::Allow,
+            "deny-create" => SymlinkPolicy::DenyCreate,
+            "hide" => SymlinkPolicy::Hide,
+            _ => usage_error(&format!("Invalid symlink policy: {value}")),
+        }
+    }
+
+    fn parse_fsync(value: &str) -> FsyncPolicy {
+        match value {
+            "requested" => FsyncPolicy::Requested,
+            "always" => FsyncPolicy::Always,
+            "never" => FsyncPolicy::Never,
+            _ => usage_error(&format!("Invalid fsync policy: {value}")),
+        }
+    }
+
+    fn parse_mounts(value: &str) -> MountPolicy {
+        match value {
+            "merge" => MountPolicy::Merge,
+            "cross" => MountPolicy::Cross,
+            "hide" => MountPolicy::Hide,
+            _ => usage_error(&format!("Invalid mount policy: {value}")),
+        }
+    }
+
     let mut require_privileged_source_port = true;
     let mut host = DEFAULT_HOST.to_string();
     let mut port = DEFAULT_PORT;
     let mut path: Option<PathBuf> = None;
     let mut verbose = false;
+    let mut read_only = false;
+    let mut exclude = Vec::new();
+    let mut exclude_from: Option<PathBuf> = None;
+    let mut symlinks = SymlinkPolicy::default();
+    let mut fsync = FsyncPolicy::default();
+    let mut mounts = MountPolicy::default();
+    let mut watch = false;
+    let mut as_caller = false;
     let mut args = std::env::args().skip(1);
     while let Some(arg) = args.next() {
         match arg.as_str() {
@@ -70,6 +174,30 @@ async fn main() {
             "--allow-unprivileged-source-port" => {
                 require_privileged_source_port = false;
             }
+            "--read-only" => {
+                read_only = true;
+            }
+            "--exclude" => {
+                exclude.push(require_value("--exclude", &mut args));
+            }
+            "--exclude-from" => {
+                exclude_from = Some(PathBuf::from(require_value("--exclude-from", &mut args)));
+            }
+            "--symlinks" => {
+                symlinks = parse_symlinks(&require_value("--symlinks", &mut args));
+            }
+            "--fsync" => {
+                fsync = parse_fsync(&require_value("--fsync", &mut args));
+            }
+            "--mounts" => {
+                mounts = parse_mounts(&require_value("--mounts", &mut args));
+            }
+            "--watch" => {
+                watch = true;
+            }
+            "--as-caller" => {
+                as_caller = true;
+            }
             "--host" | "-h" => {
                 let value = require_value("--host", &mut args);
                 host = parse_host(&value);
@@ -94,6 +222,21 @@ async fn main() {
                 let value = &arg["--port=".len()..];
                 port = parse_port(value);
             }
+            _ if arg.starts_with("--exclude=") => {
+                exclude.push(arg["--exclude=".len()..].to_string());
+            }
+            _ if arg.starts_with("--exclude-from=") => {
+                exclude_from = Some(PathBuf::from(&arg["--exclude-from=".len()..]));
+            }
+            _ if arg.starts_with("--symlinks=") => {
+                symlinks = parse_symlinks(&arg["--symlinks=".len()..]);
+            }
+            _ if arg.starts_with("--fsync=") => {
+                fsync = parse_fsync(&arg["--fsync=".len()..]);
+            }
+            _ if arg.starts_with("--mounts=") => {
+                mounts = parse_mounts(&arg["--mounts=".len()..]);
+            }
             _ if arg.starts_with('-') => {
                 eprintln!("Unknown flag: {arg}");
                 eprintln!("Run with --help for usage.");
@@ -118,7 +261,24 @@ async fn main() {
     let max_level = if verbose { tracing::Level::DEBUG } else { tracing::Level::INFO };
     tracing_subscriber::fmt().with_max_level(max_level).with_writer(std::io::stderr).init();
 
-    let fs = fs::MirrorFS::new(path);
+    #[cfg(feature = "archive")]
+    let archive = if path.is_file() {
+        Some(ArchiveFS::open(&path).unwrap_or_else(|err| {
+            eprintln!("Cannot export {}: {err}", path.display());
+            std::process::exit(1);
+        }))
+    } else {
+        None
+    };
+    #[cfg(not(feature = "archive"))]
+    if path.is_file() {
+        eprintln!(
+            "Cannot export {}: archive support requires the `archive` feature",
+            path.display()
+        );
+        std::process::exit(1);
+    }
+
     let bind_addr = if host.contains(':') {
         if host.starts_with('[') && host.ends_with(']') {
             format!("{host}:{port}")
@@ -128,7 +288,33 @@ async fn main() {
     } else {
         format!("{host}:{port}")
     };
-    let mut listener = NFSTcpListener::bind(&bind_addr, fs).await.unwrap();
-    listener.require_privileged_source_port(require_privileged_source_port);
-    listener.handle_forever().await.unwrap();
+
+    #[cfg(feature = "archive")]
+    if let Some(fs) = archive {
+        serve(&bind_addr, fs, require_privileged_source_port).await;
+        return;
+    }
+
+    let mut builder = MirrorFS::builder(&path)
+        .read_only(read_only)
+        .symlink_policy(symlinks)
+        .fsync_policy(fsync)
+        .mount_policy(mounts)
+        .watch(watch)
+        .caller_credentials(as_caller);
+    let patterns = exclude_patterns(&exclude, exclude_from.as_deref()).unwrap_or_else(|err| {
+        eprintln!("Cannot read {}: {err}", exclude_from.as_deref().unwrap().display());
+        std::process::exit(1);
+    });
+    for pattern in patterns {
+        builder = builder.exclude(pattern);
+    }
+    let fs = builder.build().unwrap_or_else(|err| {
+        eprintln!("Cannot export {}: {err}", path.display());
+        std::process::exit(1);
+    });
+    if exclude_from.is_some() {
+        reload_excludes_on_hangup(exclude, exclude_from, fs.exclude_controller());
+    }
+    serve(&bind_addr, fs, require_privileged_source_port).await;
 }
@@ -0,0 +1,672 @@
+//! Composition of several file systems into a single exported tree.
+//!
+//! [`CompositeFS`] grafts child file systems onto directories of a parent tree, the
+//! way bind mounts do. Looking up a graft point switches to the child's root, and
+//! everything below it is served by the child:
+//!
+//! ```ignore
+//! use std::sync::Arc;
+//! use fernfs::composite_fs::CompositeFS;
+//! use fernfs::mem_fs::MemFS;
+//! use fernfs::mirror_fs::MirrorFS;
+//!
+//! let fs = CompositeFS::new(Arc::new(MemFS::new()))
+//!     .graft("data", Arc::new(MirrorFS::new("/srv/data".into())))
+//!     .graft("data/scratch", Arc::new(MemFS::new()));
+//! ```
+//!
+//! A graft point does not need to exist in the tree it is grafted onto; READDIR of
+//! the directory containing it lists the grafted name either way, with the child
+//! root's attributes. Graft points cannot be removed or renamed, and renames and hard
+//! links between subtrees fail with `NFS3ERR_XDEV`.
+//!
+//! Every subtree reports its own `fsid` in `fattr3`: 0 for the parent tree, then 1, 2,
+//! ... for the grafts in the order they were added, so clients see mount boundaries.
+//! Objects a subtree reports on another file system than its root, such as the mounts
+//! a [`MirrorFS`](crate::mirror_fs::MirrorFS) crosses, keep an `fsid` of their own,
+//! mixed with the subtree number.
+//!
+//! Fileids are namespaced the same way, with the subtree number in the top 16 bits
+//! and the child's fileid below. Fileids of 2^48 and above do not fit; they are
+//! numbered in the order they are first seen instead. The 2^20 most recently used of
+//! those numbers are remembered (see [`CompositeFS::max_wide_ids`]), and handles
+//! carrying older ones go stale. The backends in this crate stay below 2^48.
+//!
+//! The directories that graft points live in are resolved on first use, walking down
+//! from the root through earlier grafts, so grafts may be nested. A graft whose
+//! directory does not exist yet is looked for again on each request until it is found.
+
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use tokio::sync::OnceCell;
+use tracing::{debug, warn};
+
+use crate::protocol::xdr::rpc::auth_unix;
+use crate::vfs::{self, Capabilities, NFSFileSystem};
+use crate::xdr::nfs3;
+
+type NFSResult<T> = Result<T, nfs3::nfsstat3>;
+
+/// Fileid bits left to each subtree; the bits above hold the subtree number.
+const SUBTREE_SHIFT: u32 = 48;
+/// Subtree number that marks fileids numbered by [`WideIds`].
+const WIDE: u64 = (1 << (64 - SUBTREE_SHIFT)) - 1;
+/// Entries requested per call while listing a directory that holds graft points.
+const LIST_PAGE: usize = 1024;
+/// Default number of wide fileids that are remembered
+const MAX_WIDE_IDS: usize = 1 << 20;
+
+/// A file system served below a graft point, or the parent tree.
+struct Subtree {
+    /// Path components of the graft point; empty for the parent tree.
+    path: Vec<Vec<u8>>,
+    fs: Arc<dyn NFSFileSystem + Send + Sync>,
+    /// The `fsid` the subtree reports for its root, learned on first use.
+    root_fsid: OnceCell<u64>,
+}
+
+impl Subtree {
+    fn new(path: Vec<Vec<u8>>, fs: Arc<dyn NFSFileSystem + Send + Sync>) -> Self {
+        Self { path, fs, root_fsid: OnceCell::new() }
+    }
+}
+
+/// Graft points located in the composed tree.
+#[derive(Clone, Default)]
+struct Junctions {
+    /// The graft points in each directory, by name.
+    by_dir: HashMap<nfs3::fileid3, Vec<(Vec<u8>, usize)>>,
+    /// The directory holding each subtree's graft point.
+    parents: HashMap<usize, nfs3::fileid3>,
+    /// Grafts whose directory was not found yet, shallowest first.
+    missing: Vec<usize>,
+    /// Whether the grafts were looked for at least once.
+    searched: bool,
+}
+
+impl Junctions {
+    fn get(&self, dirid: nfs3::fileid3, name: &[u8]) -> Option<usize> {
+        let grafts = self.by_dir.get(&dirid)?;
+        grafts.iter().find(|(graft, _)| graft == name).map(|&(_, subtree)| subtree)
+    }
+}
+
+/// Numbers for fileids of subtrees that do not fit in [`SUBTREE_SHIFT`] bits
+///
+/// Numbers are handed out in order and never reused. Used numbers go into the
+/// recent generation; once it holds half of the limit, it becomes the older
+/// generation and the previous older one is forgotten.
+struct WideIds {
+    next: u64,
+    /// The subtree and child fileid behind each number, by generation.
+    recent: HashMap<u64, (usize, nfs3::fileid3)>,
+    older: HashMap<u64, (usize, nfs3::fileid3)>,
+    numbers: HashMap<(usize, nfs3::fileid3), u64>,
+    generation_size: usize,
+}
+
+impl WideIds {
+    fn new(limit: usize) -> Self {
+        Self {
+            next: 0,
+            recent: HashMap::new(),
+            older: HashMap::new(),
+            numbers: HashMap::new(),
+            generation_size: (limit / 2).max(1),
+        }
+    }
+
+    /// Returns the subtree and fileid behind `number`, marking it as used.
+    fn get(&mut self, number: u64) -> Option<(usize, nfs3::fileid3)> {
+        if let Some(inner) = self.recent.get(&number) {
+            return Some(*inner);
+        }
+        let inner = self.older.remove(&number)?;
+        self.insert(number, inner);
+        Some(inner)
+    }
+
+    /// Returns the number of `inner`, handing out a new one if it has none.
+    fn number(&mut self, inner: (usize, nfs3::fileid3)) -> u64 {
+        if let Some(&number) = self.numbers.get(&inner) {
+            self.get(number);
+            return number;
+        }
+        let number = self.next;
+        self.next += 1;
+        self.numbers.insert(inner, number);
+        self.insert(number, inner);
+        number
+    }
+
+    fn insert(&mut self, number: u64, inner: (usize, nfs3::fileid3)) {
+        self.recent.insert(number, inner);
+        if self.recent.len() >= self.generation_size {
+            for forgotten in mem::take(&mut self.older).into_values() {
+                self.numbers.remove(&forgotten);
+            }
+            self.older = mem::take(&mut self.recent);
+        }
+    }
+}
+
+/// A file system made of child file systems grafted onto a parent tree
+///
+/// See the [module documentation](self) for the semantics.
+pub struct CompositeFS {
+    subtrees: Vec<Subtree>,
+    junctions: Mutex<Arc<Junctions>>,
+    wide_ids: Mutex<WideIds>,
+    generation: u64,
+}
+
+impl CompositeFS {
+    /// Creates a composition whose root is the root of `parent`
+    pub fn new(parent: Arc<dyn NFSFileSystem + Send + Sync>) -> Self {
+        let since_epoch = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
+        Self {
+            subtrees: vec![Subtree::new(Vec::new(), parent)],
+            junctions: Mutex::new(Arc::default()),
+            wide_ids: Mutex::new(WideIds::new(MAX_WIDE_IDS)),
+            generation: since_epoch.as_millis() as u64,
+        }
+    }
+
+    /// Grafts `child` at `path`, a '/'-separated path relative to the root
+    ///
+    /// # Panics
+    ///
+    /// Panics if `path` names the root, contains `..`, or is already a graft point,
+    /// or if there are already 65534 grafts.
+    pub fn graft(
+        mut self,
+        path: impl AsRef<[u8]>,
+        child: Arc<dyn NFSFileSystem + Send + Sync>,
+    ) -> Self {
+        let path: Vec<Vec<u8>> = path
+            .as_ref()
+            .split(|&b| b == b'/')
+            .filter(|part| !part.is_empty() && *part != b".")
+            .map(<[u8]>::to_vec)
+            .collect();
+        assert!(!path.is_empty(), "cannot graft onto the root directory");
+        assert!(path.iter().all(|part| part != b".."), "graft paths cannot contain `..`");
+        assert!(
+            self.subtrees.iter().all(|subtree| subtree.path != path),
+            "graft point {:?} is already in use",
+            String::from_utf8_lossy(&path.join(&b'/'))
+        );
+        assert!((self.subtrees.len() as u64) < WIDE, "too many grafts");
+        self.subtrees.push(Subtree::new(path, child));
+        // Shallow grafts first, so that deeper ones can be reached through them.
+        let mut missing: Vec<usize> = (1..self.subtrees.len()).collect();
+        missing.sort_by_key(|&subtree| self.subtrees[subtree].path.len());
+        self.junctions = Mutex::new(Arc::new(Junctions { missing, ..Default::default() }));
+        self
+    }
+
+    /// Sets how many child fileids of 2^48 and above are remembered, 2^20 by default
+    pub fn max_wide_ids(mut self, limit: usize) -> Self {
+        self.wide_ids = Mutex::new(WideIds::new(limit));
+        self
+    }
+
+    fn pack(&self, subtree: usize, id: nfs3::fileid3) -> nfs3::fileid3 {
+        if id >> SUBTREE_SHIFT == 0 {
+            return (subtree as u64) << SUBTREE_SHIFT | id;
+        }
+        WIDE << SUBTREE_SHIFT | self.wide_ids.lock().unwrap().number((subtree, id))
+    }
+
+    fn unpack(&self, id: nfs3::fileid3) -> NFSResult<(usize, nfs3::fileid3)> {
+        let low = id & ((1 << SUBTREE_SHIFT) - 1);
+        if id >> SUBTREE_SHIFT == WIDE {
+            let mut wide = self.wide_ids.lock().unwrap();
+            return wide.get(low).ok_or(if low < wide.next {
+                nfs3::nfsstat3::NFS3ERR_STALE
+            } else {
+                nfs3::nfsstat3::NFS3ERR_BADHANDLE
+            });
+        }
+        let subtree = (id >> SUBTREE_SHIFT) as usize;
+        if subtree >= self.subtrees.len() {
+            return Err(nfs3::nfsstat3::NFS3ERR_BADHANDLE);
+        }
+        Ok((subtree, low))
+    }
+
+    /// Fixes up attributes returned by a subtree.
+    async fn translate(&self, subtree: usize, mut attr: nfs3::fattr3) -> nfs3::fattr3 {
+        attr.fileid = self.pack(subtree, attr.fileid);
+        attr.fsid = self.fsid(subtree, attr.fsid).await;
+        attr
+    }
+
+    /// Returns the `fsid` of an object that `subtree` reports with `fsid`.
+    ///
+    /// Objects on the file system of the subtree's root get the subtree number;
+    /// others keep their own `fsid`, mixed with it.
+    async fn fsid(&self, subtree: usize, fsid: u64) -> u64 {
+        let Subtree { fs, root_fsid, .. } = &self.subtrees[subtree];
+        let root = root_fsid
+            .get_or_try_init(|| async { fs.getattr(fs.root_dir()).await.map(|attr| attr.fsid) });
+        if root.await.map_or(true, |&root| root == fsid) {
+            return subtree as u64;
+        }
+        let mut hasher = DefaultHasher::new();
+        (subtree, fsid).hash(&mut hasher);
+        // The top bit keeps mixed fsids apart from subtree numbers.
+        hasher.finish() | 1 << 63
+    }
+
+    fn subtree_root(&self, subtree: usize) -> nfs3::fileid3 {
+        self.pack(subtree, self.subtrees[subtree].fs.root_dir())
+    }
+
+    /// Returns the graft points found so far, looking again for those not found yet.
+    async fn junctions(&self) -> Arc<Junctions> {
+        let current = self.junctions.lock().unwrap().clone();
+        if current.missing.is_empty() {
+            return current;
+        }
+        let mut junctions = Junctions { missing: Vec::new(), searched: true, ..(*current).clone() };
+        for &subtree in &current.missing {
+            let (name, parents) = self.subtrees[subtree].path.split_last().unwrap();
+            match self.walk(&junctions, parents).await {
+                Ok(dirid) => {
+                    junctions.by_dir.entry(dirid).or_default().push((name.clone(), subtree));
+                    junctions.parents.insert(subtree, dirid);
+                }
+                Err(err) => {
+                    let path = String::from_utf8_lossy(&self.subtrees[subtree].path.join(&b'/'))
+                        .into_owned();
+                    if current.searched {
+                        debug!("graft at {:?} is still unreachable: {:?}", path, err);
+                    } else {
+                        warn!(
+                            "graft at {:?} is unreachable until its directory exists: {:?}",
+                            path, err
+                        );
+                    }
+                    junctions.missing.push(subtree);
+                }
+            }
+        }
+        let junctions = Arc::new(junctions);
+        *self.junctions.lock().unwrap() = junctions.clone();
+        junctions
+    }
+
+    /// Resolves a path from the root using the graft points found so far.
+    async fn walk(&self, junctions: &Junctions, path: &[Vec<u8>]) -> NFSResult<nfs3::fileid3> {
+        let mut dirid = self.root_dir();
+        for part in path {
+            dirid = self.lookup_in(junctions, dirid, part).await?;
+        }
+        Ok(dirid)
+    }
+
+    async fn lookup_in(
+        &self,
+        junctions: &Junctions,
+        dirid: nfs3::fileid3,
+        name: &[u8],
+    ) -> NFSResult<nfs3::fileid3> {
+        let (subtree, inner) = self.unpack(dirid)?;
+        let fs = &self.subtrees[subtree].fs;
+        if name == b".." && subtree != 0 && inner == fs.root_dir() {
+            return junctions.parents.get(&subtree).copied().ok_or(nfs3::nfsstat3::NFS3ERR_STALE);
+        }
+        if let Some(child) = junctions.get(dirid, name) {
+            return Ok(self.subtree_root(child));
+        }
+        Ok(self.pack(subtree, fs.lookup(inner, &name.into()).await?))
+    }
+
+    /// Fails with `err` if `name` in `dirid` is a graft point.
+    async fn check_not_junction(
+        &self,
+        dirid: nfs3::fileid3,
+        name: &[u8],
+        err: nfs3::nfsstat3,
+    ) -> NFSResult<()> {
+        match self.junctions().await.get(dirid, name) {
+            Some(_) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Unpacks two ids that an operation needs to be in the same subtree.
+    fn same_subtree(
+        &self,
+        a: nfs3::fileid3,
+        b: nfs3::fileid3,
+    ) -> NFSResult<(usize, nfs3::fileid3, nfs3::fileid3)> {
+        let (subtree, a) = self.unpack(a)?;
+        let (other, b) = self.unpack(b)?;
+        if subtree != other {
+            return Err(nfs3::nfsstat3::NFS3ERR_XDEV);
+        }
+        Ok((subtree, a, b))
+    }
+
+    async fn created(
+        &self,
+        subtree: usize,
+        (id, attr): (nfs3::fileid3, nfs3::fattr3),
+    ) -> (nfs3::fileid3, nfs3::fattr3) {
+        (self.pack(subtree, id), self.translate(subtree, attr).await)
+    }
+
+    /// Lists a subtree directory in full.
+    async fn list_all(
+        &self,
+        subtree: usize,
+        inner: nfs3::fileid3,
+    ) -> NFSResult<Vec<vfs::DirEntry>> {
+        let fs = &self.subtrees[subtree].fs;
+        let mut entries = Vec::new();
+        loop {
+            let page = fs.readdir_index(inner, entries.len(), LIST_PAGE).await?;
+            let done = page.end || page.entries.is_empty();
+            entries.extend(page.entries);
+            if done {
+                return Ok(entries);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl NFSFileSystem for CompositeFS {
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn server_id(&self) -> nfs3::cookieverf3 {
+        // Changes whenever the verifier of any subtree does.
+        let mut hasher = DefaultHasher::new();
+        self.generation.hash(&mut hasher);
+        for subtree in &self.subtrees {
+            subtree.fs.server_id().hash(&mut hasher);
+        }
+        hasher.finish().to_le_bytes()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        // Read-only subtrees reject changes themselves.
+        let writable = self
+            .subtrees
+            .iter()
+            .any(|subtree| matches!(subtree.fs.capabilities(), Capabilities::ReadWrite));
+        if writable {
+            Capabilities::ReadWrite
+        } else {
+            Capabilities::ReadOnly
+        }
+    }
+
+    fn root_dir(&self) -> nfs3::fileid3 {
+        self.subtree_root(0)
+    }
+
+    async fn lookup(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> NFSResult<nfs3::fileid3> {
+        let junctions = self.junctions().await;
+        self.lookup_in(&junctions, dirid, filename).await
+    }
+
+    async fn getattr(&self, id: nfs3::fileid3) -> NFSResult<nfs3::fattr3> {
+        let (subtree, inner) = self.unpack(id)?;
+        Ok(self.translate(subtree, self.subtrees[subtree].fs.getattr(inner).await?).await)
+    }
+
+    async fn setattr(&self, id: nfs3::fileid3, setattr: nfs3::sattr3) -> NFSResult<nfs3::fattr3> {
+        let (subtree, inner) = self.unpack(id)?;
+        Ok(self.translate(subtree, self.subtrees[subtree].fs.setattr(inner, setattr).await?).await)
+    }
+
+    async fn read(&self, id: nfs3::fileid3, offset: u64, count: u32) -> NFSResult<(Vec<u8>, bool)> {
+        let (subtree, inner) = self.unpack(id)?;
+        self.subtrees[subtree].fs.read(inner, offset, count).await
+    }
+
+    async fn write(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+        stable: nfs3::file::stable_how,
+    ) -> NFSResult<(nfs3::fattr3, nfs3::file::stable_how, nfs3::count3)> {
+        let (subtree, inner) = self.unpack(id)?;
+        let (attr, stable, count) =
+            self.subtrees[subtree].fs.write(inner, offset, data, stable).await?;
+        Ok((self.translate(subtree, attr).await, stable, count))
+    }
+
+    async fn create(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        attr: nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        self.check_not_junction(dirid, filename, nfs3::nfsstat3::NFS3ERR_EXIST).await?;
+        let (subtree, inner) = self.unpack(dirid)?;
+        Ok(self
+            .created(subtree, self.subtrees[subtree].fs.create(inner, filename, attr).await?)
+            .await)
+    }
+
+    async fn create_exclusive(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        verifier: nfs3::createverf3,
+    ) -> NFSResult<nfs3::fileid3> {
+        self.check_not_junction(dirid, filename, nfs3::nfsstat3::NFS3ERR_EXIST).await?;
+        let (subtree, inner) = self.unpack(dirid)?;
+        let fs = &self.subtrees[subtree].fs;
+        Ok(self.pack(subtree, fs.create_exclusive(inner, filename, verifier).await?))
+    }
+
+    async fn mkdir(
+        &self,
+        dirid: nfs3::fileid3,
+        dirname: &nfs3::filename3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        self.check_not_junction(dirid, dirname, nfs3::nfsstat3::NFS3ERR_EXIST).await?;
+        let (subtree, inner) = self.unpack(dirid)?;
+        Ok(self.created(subtree, self.subtrees[subtree].fs.mkdir(inner, dirname).await?).await)
+    }
+
+    async fn remove(&self, dirid: nfs3::fileid3, filename: &nfs3::filename3) -> NFSResult<()> {
+        self.check_not_junction(dirid, filename, nfs3::nfsstat3::NFS3ERR_ACCES).await?;
+        let (subtree, inner) = self.unpack(dirid)?;
+        self.subtrees[subtree].fs.remove(inner, filename).await
+    }
+
+    async fn rename(
+        &self,
+        from_dirid: nfs3::fileid3,
+        from_filename: &nfs3::filename3,
+        to_dirid: nfs3::fileid3,
+        to_filename: &nfs3::filename3,
+    ) -> NFSResult<()> {
+        let busy = nfs3::nfsstat3::NFS3ERR_ACCES;
+        self.check_not_junction(from_dirid, from_filename, busy).await?;
+        self.check_not_junction(to_dirid, to_filename, busy).await?;
+        let (subtree, from, to) = self.same_subtree(from_dirid, to_dirid)?;
+        self.subtrees[subtree].fs.rename(from, from_filename, to, to_filename).await
+    }
+
+    async fn readdir(
+        &self,
+        dirid: nfs3::fileid3,
+        start_after: nfs3::fileid3,
+        max_entries: usize,
+    ) -> NFSResult<vfs::ReadDirResult> {
+        let start = usize::try_from(start_after).map_err(|_| nfs3::nfsstat3::NFS3ERR_BAD_COOKIE)?;
+        self.readdir_index(dirid, start, max_entries).await
+    }
+
+    async fn readdir_index(
+        &self,
+        dirid: nfs3::fileid3,
+        start_index: usize,
+        max_entries: usize,
+    ) -> NFSResult<vfs::ReadDirResult> {
+        let junctions = self.junctions().await;
+        let (subtree, inner) = self.unpack(dirid)?;
+        let fs = &self.subtrees[subtree].fs;
+        let grafts = junctions.by_dir.get(&dirid).map(Vec::as_slice).unwrap_or_default();
+        let mut result = if grafts.is_empty() {
+            fs.readdir_index(inner, start_index, max_entries).await?
+        } else {
+            // Cookies index the subtree's listing followed by the graft points that
+            // have no entry of their own, so the whole directory is listed and sliced.
+            let mut entries = self.list_all(subtree, inner).await?;
+            for (name, _) in grafts {
+                if !entries.iter().any(|entry| &entry.name[..] == name.as_slice()) {
+                    // The fileid and attributes are filled in below.
+                    let attr = nfs3::fattr3 { fileid: inner, ..Default::default() };
+                    entries.push(vfs::DirEntry {
+                        fileid: inner,
+                        name: name.as_slice().into(),
+                        attr,
+                    });
+                }
+            }
+            if start_index > entries.len() {
+                return Err(nfs3::nfsstat3::NFS3ERR_BAD_COOKIE);
+            }
+            let end = entries.len().min(start_index.saturating_add(max_entries));
+            let done = end == entries.len();
+            vfs::ReadDirResult { entries: entries.drain(start_index..end).collect(), end: done }
+        };
+
+        let at_subtree_root = subtree != 0 && inner == fs.root_dir();
+        for entry in &mut result.entries {
+            let target = match &entry.name[..] {
+                b".." if at_subtree_root => junctions.parents.get(&subtree).copied(),
+                b"." | b".." => None,
+                name => junctions.get(dirid, name).map(|child| self.subtree_root(child)),
+            };
+            match target {
+                Some(id) => {
+                    entry.fileid = id;
+                    entry.attr = self.getattr(id).await?;
+                }
+                None => {
+                    entry.fileid = self.pack(subtree, entry.fileid);
+                    entry.attr = self.translate(subtree, entry.attr).await;
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    async fn symlink(
+        &self,
+        dirid: nfs3::fileid3,
+        linkname: &nfs3::filename3,
+        symlink: &nfs3::nfspath3,
+        attr: &nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        self.check_not_junction(dirid, linkname, nfs3::nfsstat3::NFS3ERR_EXIST).await?;
+        let (subtree, inner) = self.unpack(dirid)?;
+        let fs = &self.subtrees[subtree].fs;
+        Ok(self.created(subtree, fs.symlink(inner, linkname, symlink, attr).await?).await)
+    }
+
+    async fn readlink(&self, id: nfs3::fileid3) -> NFSResult<nfs3::nfspath3> {
+        let (subtree, inner) = self.unpack(id)?;
+        self.subtrees[subtree].fs.readlink(inner).await
+    }
+
+    async fn link(
+        &self,
+        file_id: nfs3::fileid3,
+        link_dir_id: nfs3::fileid3,
+        link_name: &nfs3::filename3,
+    ) -> NFSResult<nfs3::fattr3> {
+        self.check_not_junction(link_dir_id, link_name, nfs3::nfsstat3::NFS3ERR_EXIST).await?;
+        let (subtree, file, dir) = self.same_subtree(file_id, link_dir_id)?;
+        Ok(self
+            .translate(subtree, self.subtrees[subtree].fs.link(file, dir, link_name).await?)
+            .await)
+    }
+
+    async fn mknod(
+        &self,
+        dir_id: nfs3::fileid3,
+        name: &nfs3::filename3,
+        ftype: nfs3::ftype3,
+        specdata: nfs3::specdata3,
+        attrs: &nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        self.check_not_junction(dir_id, name, nfs3::nfsstat3::NFS3ERR_EXIST).await?;
+        let (subtree, inner) = self.unpack(dir_id)?;
+        let fs = &self.subtrees[subtree].fs;
+        Ok(self.created(subtree, fs.mknod(inner, name, ftype, specdata, attrs).await?).await)
+    }
+
+    async fn commit(
+        &self,
+        file_id: nfs3::fileid3,
+        offset: u64,
+        count: u32,
+    ) -> NFSResult<nfs3::fattr3> {
+        let (subtree, inner) = self.unpack(file_id)?;
+        Ok(self
+            .translate(subtree, self.subtrees[subtree].fs.commit(inner, offset, count).await?)
+            .await)
+    }
+
+    async fn check_access(
+        &self,
+        id: nfs3::fileid3,
+        auth: &auth_unix,
+        access: u32,
+    ) -> NFSResult<u32> {
+        let (subtree, inner) = self.unpack(id)?;
+        self.subtrees[subtree].fs.check_access(inner, auth, access).await
+    }
+
+    async fn fsinfo(&self, root_fileid: nfs3::fileid3) -> NFSResult<nfs3::fs::fsinfo3> {
+        let (subtree, inner) = self.unpack(root_fileid)?;
+        let mut info = self.subtrees[subtree].fs.fsinfo(inner).await?;
+        info.obj_attributes = self.getattr(root_fileid).await.ok();
+        Ok(info)
+    }
+
+    async fn fsstat(&self, root_fileid: nfs3::fileid3) -> NFSResult<nfs3::fs::FSSTAT3resok> {
+        let (subtree, inner) = self.unpack(root_fileid)?;
+        let mut stat = self.subtrees[subtree].fs.fsstat(inner).await?;
+        stat.obj_attributes = self.getattr(root_fileid).await.ok();
+        Ok(stat)
+    }
+
+    fn fsinfo_maxfilesize(&self) -> nfs3::size3 {
+        self.subtrees[0].fs.fsinfo_maxfilesize()
+    }
+
+    fn fsinfo_properties(&self) -> u32 {
+        self.subtrees[0].fs.fsinfo_properties()
+    }
+
+    fn pathconf_linkmax(&self) -> u32 {
+        self.subtrees[0].fs.pathconf_linkmax()
+    }
+
+    fn pathconf_name_max(&self) -> u32 {
+        self.subtrees[0].fs.pathconf_name_max()
+    }
+}
@@ -0,0 +1,678 @@
+//! Transparent compression for another file system.
+//!
+//! [`CompressFS`] wraps an `NFSFileSystem` and stores the contents of regular files as
+//! zstd-compressed chunks with an index, so any range of a file can be read by
+//! decompressing only the chunks it covers. Clients see the uncompressed data: reads
+//! and writes work at any offset, and attributes report uncompressed sizes while `used`
+//! reports the space the compressed file takes.
+//!
+//! ```ignore
+//! use std::sync::Arc;
+//! use fernfs::compress_fs::CompressFS;
+//!
+//! let fs = CompressFS::new(Arc::new(MirrorFS::new("/srv/logs".into()))).level(9);
+//! ```
+//!
+//! Writes never modify stored chunks in place. The chunks a write touches are
+//! recompressed and appended. The new index is kept in memory until the writes are
+//! committed, by COMMIT or a stable write; then it is appended and only then does the
+//! header at the start of the file switch to it, so an interrupted write leaves the
+//! last committed contents intact. Once the unreferenced space outgrows the data that
+//! is still in use,
+//! the file is compacted by moving its chunks down and truncating it; compaction is not
+//! atomic. Chunks of zeros take no space, so sparse files stay sparse.
+//!
+//! Regular files that were not written through a `CompressFS` are served and modified
+//! as they are, so a compressed export can be laid over existing data. A file that
+//! happens to start with the compressed file magic is mistaken for a compressed one.
+
+mod layout;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use tracing::{debug, warn};
+
+use crate::protocol::xdr::rpc::auth_unix;
+use crate::vfs::{self, Capabilities, NFSFileSystem};
+use crate::xdr::nfs3;
+
+use layout::{Entry, Meta, HEADER_LEN, MAX_CHUNK_SIZE, MIN_CHUNK_SIZE};
+
+type NFSResult<T> = Result<T, nfs3::nfsstat3>;
+
+/// Uncompressed bytes per chunk unless configured otherwise.
+pub const DEFAULT_CHUNK_SIZE: u32 = 64 * 1024;
+/// zstd compression level unless configured otherwise.
+pub const DEFAULT_LEVEL: i32 = 3;
+
+/// Number of locks ordering reads against read-modify-write cycles, shared by fileid.
+const LOCKS: usize = 64;
+/// Number of file indexes kept in memory.
+const META_CACHE_ENTRIES: usize = 1024;
+/// Unreferenced bytes a file may hold beyond the size of its live data before it is
+/// compacted.
+const COMPACT_SLACK: u64 = 1024 * 1024;
+
+/// The index of a file, valid while the stored file keeps its size and mtime.
+struct CachedMeta {
+    stored_size: u64,
+    mtime: (u32, u32),
+    meta: Arc<Meta>,
+    /// Whether the index refers to chunks written since the stored index
+    unsaved: bool,
+}
+
+/// A file system that compresses the regular files of another
+///
+/// See the [module documentation](self) for how files are stored.
+pub struct CompressFS {
+    inner: Arc<dyn NFSFileSystem + Send + Sync>,
+    chunk_size: u64,
+    level: i32,
+    locks: Vec<tokio::sync::RwLock<()>>,
+    metas: Mutex<HashMap<nfs3::fileid3, CachedMeta>>,
+}
+
+impl CompressFS {
+    /// Wraps `inner`, compressing new data in chunks of [`DEFAULT_CHUNK_SIZE`] bytes
+    pub fn new(inner: Arc<dyn NFSFileSystem + Send + Sync>) -> Self {
+        Self {
+            inner,
+            chunk_size: DEFAULT_CHUNK_SIZE as u64,
+            level: DEFAULT_LEVEL,
+            locks: (0..LOCKS).map(|_| tokio::sync::RwLock::new(())).collect(),
+            metas: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets the number of uncompressed bytes per chunk of new files
+    ///
+    /// Larger chunks compress better; smaller ones make reads and writes of a few bytes
+    /// cheaper. Existing files keep the chunk size they were created with.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is below 4 KiB or above 16 MiB.
+    pub fn chunk_size(mut self, chunk_size: u32) -> Self {
+        assert!(
+            (MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&(chunk_size as u64)),
+            "chunk size must be between {MIN_CHUNK_SIZE} and {MAX_CHUNK_SIZE} bytes"
+        );
+        self.chunk_size = chunk_size as u64;
+        self
+    }
+
+    /// Sets the zstd compression level of newly written chunks
+    pub fn level(mut self, level: i32) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Returns the wrapped file system
+    pub fn inner(&self) -> &Arc<dyn NFSFileSystem + Send + Sync> {
+        &self.inner
+    }
+
+    fn lock(&self, id: nfs3::fileid3) -> &tokio::sync::RwLock<()> {
+        &self.locks[(id % LOCKS as u64) as usize]
+    }
+
+    fn remember(&self, id: nfs3::fileid3, attr: &nfs3::fattr3, meta: Arc<Meta>, unsaved: bool) {
+        let mut metas = self.metas.lock().unwrap();
+        if metas.len() >= META_CACHE_ENTRIES && !metas.contains_key(&id) {
+            // Unsaved indexes are the only record of uncommitted writes.
+            metas.retain(|_, cached| cached.unsaved);
+        }
+        let mtime = (attr.mtime.seconds, attr.mtime.nseconds);
+        metas.insert(id, CachedMeta { stored_size: attr.size, mtime, meta, unsaved });
+    }
+
+    /// Returns true if the remembered index of a file has not been stored.
+    fn unsaved(&self, id: nfs3::fileid3) -> bool {
+        self.metas.lock().unwrap().get(&id).is_some_and(|cached| cached.unsaved)
+    }
+
+    fn forget(&self, id: nfs3::fileid3) {
+        self.metas.lock().unwrap().remove(&id);
+    }
+
+    /// Reads exactly `len` bytes of the stored file, or less at its end.
+    async fn read_stored(&self, id: nfs3::fileid3, offset: u64, len: u64) -> NFSResult<Vec<u8>> {
+        let mut data = Vec::with_capacity(len as usize);
+        while (data.len() as u64) < len {
+            let want = (len - data.len() as u64).min(u32::MAX as u64) as u32;
+            let (chunk, eof) = self.inner.read(id, offset + data.len() as u64, want).await?;
+            data.extend_from_slice(&chunk);
+            if eof || chunk.is_empty() {
+                break;
+            }
+        }
+        Ok(data)
+    }
+
+    async fn write_stored(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+        stable: nfs3::file::stable_how,
+    ) -> NFSResult<(nfs3::fattr3, nfs3::file::stable_how)> {
+        let (attr, committed, count) = self.inner.write(id, offset, data, stable).await?;
+        if count as usize != data.len() {
+            debug!("short write of {} of {} compressed bytes", count, data.len());
+            return Err(nfs3::nfsstat3::NFS3ERR_IO);
+        }
+        Ok((attr, committed))
+    }
+
+    /// Returns the index of a compressed file whose stored attributes are `attr`, or
+    /// `None` if it is not a regular file or is stored uncompressed. The caller holds
+    /// the file's lock.
+    async fn meta(&self, id: nfs3::fileid3, attr: &nfs3::fattr3) -> NFSResult<Option<Arc<Meta>>> {
+        if !matches!(attr.ftype, nfs3::ftype3::NF3REG) || attr.size < HEADER_LEN {
+            return Ok(None);
+        }
+        if let Some(cached) = self.metas.lock().unwrap().get(&id) {
+            let mtime = (attr.mtime.seconds, attr.mtime.nseconds);
+            if cached.stored_size == attr.size && cached.mtime == mtime {
+                return Ok(Some(cached.meta.clone()));
+            }
+            if cached.unsaved {
+                warn!("compressed file {} changed under uncommitted writes, which are lost", id);
+            }
+        }
+        let header = self.read_stored(id, 0, HEADER_LEN).await?;
+        if !Meta::is_compressed(&header) {
+            return Ok(None);
+        }
+        let damaged = |_| {
+            warn!("compressed file {} has a damaged header or index", id);
+            nfs3::nfsstat3::NFS3ERR_IO
+        };
+        let (mut meta, index_offset, index_len) =
+            Meta::parse_header(&header, attr.size).map_err(damaged)?;
+        let index = self.read_stored(id, index_offset, index_len as u64).await?;
+        if index.len() != index_len {
+            return Err(damaged(nfs3::nfsstat3::NFS3ERR_IO));
+        }
+        meta.parse_index(&index).map_err(damaged)?;
+        let meta = Arc::new(meta);
+        self.remember(id, attr, meta.clone(), false);
+        Ok(Some(meta))
+    }
+
+    /// Returns a copy of the index of a regular file to modify, starting a new one for
+    /// an empty file, or `None` if the file is stored uncompressed. The caller holds
+    /// the file's write lock.
+    async fn meta_mut(&self, id: nfs3::fileid3, attr: &nfs3::fattr3) -> NFSResult<Option<Meta>> {
+        match self.meta(id, attr).await? {
+            Some(meta) => Ok(Some(Meta::clone(&meta))),
+            None if attr.size == 0 => Ok(Some(Meta::new(self.chunk_size))),
+            None => Ok(None),
+        }
+    }
+
+    /// Reports the uncompressed size of a compressed file. The caller holds the file's
+    /// lock.
+    async fn logical(&self, id: nfs3::fileid3, mut attr: nfs3::fattr3) -> NFSResult<nfs3::fattr3> {
+        if let Some(meta) = self.meta(id, &attr).await? {
+            attr.size = meta.size;
+        }
+        Ok(attr)
+    }
+
+    /// Returns the `chunk_len` uncompressed bytes of chunk `index`.
+    async fn read_chunk(&self, id: nfs3::fileid3, meta: &Meta, index: u64) -> NFSResult<Vec<u8>> {
+        let entry = meta.chunks[index as usize];
+        let len = meta.chunk_len(index) as usize;
+        if entry.len == 0 {
+            return Ok(vec![0; len]);
+        }
+        let stored = self.read_stored(id, entry.offset, entry.len as u64).await?;
+        let mut plain =
+            zstd::bulk::decompress(&stored, meta.chunk_size as usize).map_err(|err| {
+                warn!("chunk {} of compressed file {} is damaged: {}", index, id, err);
+                nfs3::nfsstat3::NFS3ERR_IO
+            })?;
+        plain.resize(len, 0);
+        Ok(plain)
+    }
+
+    /// Compresses a chunk onto `out`, which will be stored at `base`, and returns where
+    /// it went.
+    fn pack(&self, out: &mut Vec<u8>, base: u64, plain: &[u8]) -> NFSResult<Entry> {
+        if plain.iter().all(|&b| b == 0) {
+            return Ok(Entry::default());
+        }
+        let packed = zstd::bulk::compress(plain, self.level).map_err(|err| {
+            warn!("failed to compress chunk: {}", err);
+            nfs3::nfsstat3::NFS3ERR_IO
+        })?;
+        let entry = Entry { offset: base + out.len() as u64, len: packed.len() as u32 };
+        out.extend_from_slice(&packed);
+        Ok(entry)
+    }
+
+    /// Stores `appended` chunk data after the end of the stored file. Unless `stable`
+    /// is `UNSTABLE`, a new index follows it and the header is pointed at them,
+    /// compacting the file if it has grown too sparse. The caller holds the file's
+    /// write lock.
+    async fn save(
+        &self,
+        id: nfs3::fileid3,
+        meta: &mut Meta,
+        mut appended: Vec<u8>,
+        stable: nfs3::file::stable_how,
+    ) -> NFSResult<(nfs3::fattr3, nfs3::file::stable_how)> {
+        if matches!(stable, nfs3::file::stable_how::UNSTABLE) {
+            let mut offset = meta.stored_end;
+            if offset == HEADER_LEN {
+                // A new file starts with the header of an empty one, so that it reads
+                // as compressed until its index is stored.
+                appended.splice(0..0, Meta::new(meta.chunk_size).header(HEADER_LEN));
+                offset = 0;
+            }
+            let result = self.write_stored(id, offset, &appended, stable).await?;
+            meta.stored_end = offset + appended.len() as u64;
+            self.remember(id, &result.0, Arc::new(meta.clone()), true);
+            return Ok(result);
+        }
+        if self.unsaved(id) {
+            // Chunks written before must be stable before an index refers to them.
+            self.inner.commit(id, 0, 0).await?;
+        }
+        let index_offset = meta.stored_end + appended.len() as u64;
+        appended.extend(meta.index());
+        self.write_stored(id, meta.stored_end, &appended, stable).await?;
+        meta.stored_end += appended.len() as u64;
+        let mut result = self.write_stored(id, 0, &meta.header(index_offset), stable).await?;
+        let live = meta.live_bytes();
+        if meta.stored_end - live > live.max(COMPACT_SLACK) {
+            result = (self.compact(id, meta, stable).await?, result.1);
+        }
+        self.remember(id, &result.0, Arc::new(meta.clone()), false);
+        Ok(result)
+    }
+
+    /// Stores the index of a file written with `UNSTABLE` writes since its stored
+    /// index. The caller holds the file's write lock.
+    async fn save_index(&self, id: nfs3::fileid3) -> NFSResult<()> {
+        let attr = self.inner.getattr(id).await?;
+        let Some(meta) = self.meta(id, &attr).await? else {
+            return Ok(());
+        };
+        if !self.unsaved(id) {
+            return Ok(());
+        }
+        let mut meta = Meta::clone(&meta);
+        self.save(id, &mut meta, Vec::new(), nfs3::file::stable_how::FILE_SYNC).await?;
+        Ok(())
+    }
+
+    /// Moves the chunks of a file down over unreferenced space and truncates it.
+    async fn compact(
+        &self,
+        id: nfs3::fileid3,
+        meta: &mut Meta,
+        stable: nfs3::file::stable_how,
+    ) -> NFSResult<nfs3::fattr3> {
+        debug!("compacting compressed file {}", id);
+        let mut order: Vec<usize> =
+            (0..meta.chunks.len()).filter(|&i| meta.chunks[i].len > 0).collect();
+        order.sort_by_key(|&i| meta.chunks[i].offset);
+        // Chunks only move down, and in order of their offsets, so no chunk is
+        // overwritten before it has been moved.
+        let mut end = HEADER_LEN;
+        for i in order {
+            let entry = meta.chunks[i];
+            if entry.offset != end {
+                let data = self.read_stored(id, entry.offset, entry.len as u64).await?;
+                self.write_stored(id, end, &data, stable).await?;
+                meta.chunks[i].offset = end;
+            }
+            end += entry.len as u64;
+        }
+        let index = meta.index();
+        self.write_stored(id, end, &index, stable).await?;
+        self.write_stored(id, 0, &meta.header(end), stable).await?;
+        meta.stored_end = end + index.len() as u64;
+        let truncate = nfs3::sattr3 { size: Some(meta.stored_end), ..Default::default() };
+        self.inner.setattr(id, truncate).await
+    }
+
+    /// Writes `data` at `offset` of a compressed file, zero filling any gap after its
+    /// end. The caller holds the file's write lock.
+    async fn store(
+        &self,
+        id: nfs3::fileid3,
+        meta: &mut Meta,
+        offset: u64,
+        data: &[u8],
+        stable: nfs3::file::stable_how,
+    ) -> NFSResult<(nfs3::fattr3, nfs3::file::stable_how)> {
+        let end = offset.checked_add(data.len() as u64).ok_or(nfs3::nfsstat3::NFS3ERR_FBIG)?;
+        let chunk_size = meta.chunk_size;
+        let new_size = end.max(meta.size);
+        let mut appended = Vec::new();
+        let mut packed = Vec::new();
+        for index in offset / chunk_size..=(end - 1) / chunk_size {
+            let chunk_start = index * chunk_size;
+            let chunk_end = (chunk_start + chunk_size).min(new_size);
+            let existing = meta.chunk_len(index);
+            // Chunks that the new data covers entirely need not be read first.
+            let covered = offset <= chunk_start && end >= chunk_start + existing;
+            let mut plain = if existing > 0 && !covered {
+                self.read_chunk(id, meta, index).await?
+            } else {
+                Vec::new()
+            };
+            plain.resize((chunk_end - chunk_start) as usize, 0);
+            let lo = offset.max(chunk_start);
+            let hi = end.min(chunk_end);
+            plain[(lo - chunk_start) as usize..(hi - chunk_start) as usize]
+                .copy_from_slice(&data[(lo - offset) as usize..(hi - offset) as usize]);
+            packed.push((index, self.pack(&mut appended, meta.stored_end, &plain)?));
+        }
+        meta.size = new_size;
+        meta.chunks.resize(new_size.div_ceil(chunk_size) as usize, Entry::default());
+        for (index, entry) in packed {
+            meta.chunks[index as usize] = entry;
+        }
+        self.save(id, meta, appended, stable).await
+    }
+
+    /// Changes the uncompressed size of a compressed file. The caller holds the file's
+    /// write lock.
+    async fn resize(&self, id: nfs3::fileid3, mut meta: Meta, new_size: u64) -> NFSResult<()> {
+        if new_size == meta.size {
+            return Ok(());
+        }
+        if new_size == 0 {
+            // An empty stored file is an empty file.
+            self.forget(id);
+            let truncate = nfs3::sattr3 { size: Some(0), ..Default::default() };
+            self.inner.setattr(id, truncate).await?;
+            return Ok(());
+        }
+        let mut appended = Vec::new();
+        if new_size < meta.size && new_size % meta.chunk_size != 0 {
+            // Bytes past the end of the new last chunk must read as zeros if the file
+            // grows again.
+            let index = new_size / meta.chunk_size;
+            let mut plain = self.read_chunk(id, &meta, index).await?;
+            plain.truncate((new_size % meta.chunk_size) as usize);
+            meta.chunks[index as usize] = self.pack(&mut appended, meta.stored_end, &plain)?;
+        }
+        meta.size = new_size;
+        meta.chunks.resize(new_size.div_ceil(meta.chunk_size) as usize, Entry::default());
+        self.save(id, &mut meta, appended, nfs3::file::stable_how::FILE_SYNC).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl NFSFileSystem for CompressFS {
+    fn generation(&self) -> u64 {
+        self.inner.generation()
+    }
+
+    fn server_id(&self) -> nfs3::cookieverf3 {
+        self.inner.server_id()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn root_dir(&self) -> nfs3::fileid3 {
+        self.inner.root_dir()
+    }
+
+    async fn lookup(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> NFSResult<nfs3::fileid3> {
+        self.inner.lookup(dirid, filename).await
+    }
+
+    async fn getattr(&self, id: nfs3::fileid3) -> NFSResult<nfs3::fattr3> {
+        let _lock = self.lock(id).read().await;
+        let attr = self.inner.getattr(id).await?;
+        self.logical(id, attr).await
+    }
+
+    async fn setattr(
+        &self,
+        id: nfs3::fileid3,
+        mut setattr: nfs3::sattr3,
+    ) -> NFSResult<nfs3::fattr3> {
+        let _lock = self.lock(id).write().await;
+        if let Some(size) = setattr.size {
+            let attr = self.inner.getattr(id).await?;
+            if matches!(attr.ftype, nfs3::ftype3::NF3REG) {
+                if let Some(meta) = self.meta_mut(id, &attr).await? {
+                    setattr.size = None;
+                    self.resize(id, meta, size).await?;
+                }
+            }
+        }
+        let attr = self.inner.setattr(id, setattr).await?;
+        self.logical(id, attr).await
+    }
+
+    async fn read(&self, id: nfs3::fileid3, offset: u64, count: u32) -> NFSResult<(Vec<u8>, bool)> {
+        let _lock = self.lock(id).read().await;
+        let attr = self.inner.getattr(id).await?;
+        let Some(meta) = self.meta(id, &attr).await? else {
+            return self.inner.read(id, offset, count).await;
+        };
+        let end = offset.saturating_add(count as u64).min(meta.size);
+        if offset >= end {
+            return Ok((Vec::new(), offset >= meta.size));
+        }
+        let mut data = Vec::with_capacity((end - offset) as usize);
+        for index in offset / meta.chunk_size..=(end - 1) / meta.chunk_size {
+            let chunk = self.read_chunk(id, &meta, index).await?;
+            let chunk_start = index * meta.chunk_size;
+            let lo = offset.max(chunk_start) - chunk_start;
+            let hi = end.min(chunk_start + meta.chunk_size) - chunk_start;
+            data.extend_from_slice(&chunk[lo as usize..hi as usize]);
+        }
+        Ok((data, end == meta.size))
+    }
+
+    async fn write(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+        stable: nfs3::file::stable_how,
+    ) -> NFSResult<(nfs3::fattr3, nfs3::file::stable_how, nfs3::count3)> {
+        let _lock = self.lock(id).write().await;
+        let mut attr = self.inner.getattr(id).await?;
+        if !matches!(attr.ftype, nfs3::ftype3::NF3REG) {
+            return self.inner.write(id, offset, data, stable).await;
+        }
+        let Some(mut meta) = self.meta_mut(id, &attr).await? else {
+            return self.inner.write(id, offset, data, stable).await;
+        };
+        if data.is_empty() {
+            attr.size = meta.size;
+            return Ok((attr, stable, 0));
+        }
+        let (mut attr, committed) = self.store(id, &mut meta, offset, data, stable).await?;
+        attr.size = meta.size;
+        Ok((attr, committed, data.len() as nfs3::count3))
+    }
+
+    async fn create(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        mut attr: nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        // Truncating to zero needs no compression, growing the new file does.
+        let size = attr.size.take_if(|size| *size > 0);
+        let (id, attr) = self.inner.create(dirid, filename, attr).await?;
+        if let Some(size) = size {
+            let resize = nfs3::sattr3 { size: Some(size), ..Default::default() };
+            return Ok((id, self.setattr(id, resize).await?));
+        }
+        let _lock = self.lock(id).read().await;
+        Ok((id, self.logical(id, attr).await?))
+    }
+
+    async fn create_exclusive(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        verifier: nfs3::createverf3,
+    ) -> NFSResult<nfs3::fileid3> {
+        self.inner.create_exclusive(dirid, filename, verifier).await
+    }
+
+    async fn mkdir(
+        &self,
+        dirid: nfs3::fileid3,
+        dirname: &nfs3::filename3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        self.inner.mkdir(dirid, dirname).await
+    }
+
+    async fn remove(&self, dirid: nfs3::fileid3, filename: &nfs3::filename3) -> NFSResult<()> {
+        self.inner.remove(dirid, filename).await
+    }
+
+    async fn rename(
+        &self,
+        from_dirid: nfs3::fileid3,
+        from_filename: &nfs3::filename3,
+        to_dirid: nfs3::fileid3,
+        to_filename: &nfs3::filename3,
+    ) -> NFSResult<()> {
+        self.inner.rename(from_dirid, from_filename, to_dirid, to_filename).await
+    }
+
+    async fn readdir(
+        &self,
+        dirid: nfs3::fileid3,
+        start_after: nfs3::fileid3,
+        max_entries: usize,
+    ) -> NFSResult<vfs::ReadDirResult> {
+        let mut result = self.inner.readdir(dirid, start_after, max_entries).await?;
+        for entry in &mut result.entries {
+            if matches!(entry.attr.ftype, nfs3::ftype3::NF3REG) {
+                entry.attr = self.getattr(entry.fileid).await?;
+            }
+        }
+        Ok(result)
+    }
+
+    async fn readdir_index(
+        &self,
+        dirid: nfs3::fileid3,
+        start_index: usize,
+        max_entries: usize,
+    ) -> NFSResult<vfs::ReadDirResult> {
+        let mut result = self.inner.readdir_index(dirid, start_index, max_entries).await?;
+        for entry in &mut result.entries {
+            if matches!(entry.attr.ftype, nfs3::ftype3::NF3REG) {
+                entry.attr = self.getattr(entry.fileid).await?;
+            }
+        }
+        Ok(result)
+    }
+
+    async fn symlink(
+        &self,
+        dirid: nfs3::fileid3,
+        linkname: &nfs3::filename3,
+        symlink: &nfs3::nfspath3,
+        attr: &nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        self.inner.symlink(dirid, linkname, symlink, attr).await
+    }
+
+    async fn readlink(&self, id: nfs3::fileid3) -> NFSResult<nfs3::nfspath3> {
+        self.inner.readlink(id).await
+    }
+
+    async fn link(
+        &self,
+        file_id: nfs3::fileid3,
+        link_dir_id: nfs3::fileid3,
+        link_name: &nfs3::filename3,
+    ) -> NFSResult<nfs3::fattr3> {
+        let attr = self.inner.link(file_id, link_dir_id, link_name).await?;
+        let _lock = self.lock(file_id).read().await;
+        self.logical(file_id, attr).await
+    }
+
+    async fn mknod(
+        &self,
+        dir_id: nfs3::fileid3,
+        name: &nfs3::filename3,
+        ftype: nfs3::ftype3,
+        specdata: nfs3::specdata3,
+        attrs: &nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        self.inner.mknod(dir_id, name, ftype, specdata, attrs).await
+    }
+
+    async fn commit(
+        &self,
+        file_id: nfs3::fileid3,
+        _offset: u64,
+        _count: u32,
+    ) -> NFSResult<nfs3::fattr3> {
+        // Chunks do not map to the client's ranges, so commit everything.
+        let _lock = self.lock(file_id).write().await;
+        self.save_index(file_id).await?;
+        let attr = self.inner.commit(file_id, 0, 0).await?;
+        self.logical(file_id, attr).await
+    }
+
+    async fn check_access(
+        &self,
+        id: nfs3::fileid3,
+        auth: &auth_unix,
+        access: u32,
+    ) -> NFSResult<u32> {
+        self.inner.check_access(id, auth, access).await
+    }
+
+    async fn fsinfo(&self, root_fileid: nfs3::fileid3) -> NFSResult<nfs3::fs::fsinfo3> {
+        self.inner.fsinfo(root_fileid).await
+    }
+
+    async fn fsstat(&self, root_fileid: nfs3::fileid3) -> NFSResult<nfs3::fs::FSSTAT3resok> {
+        self.inner.fsstat(root_fileid).await
+    }
+
+    fn fsinfo_maxfilesize(&self) -> nfs3::size3 {
+        self.inner.fsinfo_maxfilesize()
+    }
+
+    fn fsinfo_properties(&self) -> u32 {
+        self.inner.fsinfo_properties()
+    }
+
+    fn pathconf_linkmax(&self) -> u32 {
+        self.inner.pathconf_linkmax()
+    }
+
+    fn pathconf_name_max(&self) -> u32 {
+        self.inner.pathconf_name_max()
+    }
+
+    fn id_to_fh(&self, id: nfs3::fileid3) -> nfs3::nfs_fh3 {
+        self.inner.id_to_fh(id)
+    }
+
+    fn fh_to_id(&self, id: &nfs3::nfs_fh3) -> NFSResult<nfs3::fileid3> {
+        self.inner.fh_to_id(id)
+    }
+}
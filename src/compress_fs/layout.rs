@@ -0,0 +1,122 @@
+//! The stored layout of compressed files.
+//!
+//! ```text
+//! header: magic (8) | chunk size (4) | chunk count (4) | logical size (8) | index offset (8)
+//! chunks: zstd frames, one per chunk, in any order
+//! index:  chunk count entries of offset (8) | length (4)
+//! ```
+//!
+//! All integers are little-endian. Chunk `i` holds the logical bytes starting at
+//! `i * chunk size`. A chunk may decompress to fewer bytes than it covers; the rest is
+//! zeros, and an entry of length 0 is a chunk of zeros that takes no space. Writes
+//! append new chunks; committing them appends a new index and then updates the header,
+//! so the header always points at a complete index.
+
+use crate::xdr::nfs3;
+
+type NFSResult<T> = Result<T, nfs3::nfsstat3>;
+
+const MAGIC: &[u8; 8] = b"FERNCMP1";
+pub const HEADER_LEN: u64 = 32;
+const ENTRY_LEN: usize = 12;
+/// Smallest and largest chunk sizes accepted from a header.
+pub const MIN_CHUNK_SIZE: u64 = 4096;
+pub const MAX_CHUNK_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Where one chunk is stored
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Entry {
+    pub offset: u64,
+    pub len: u32,
+}
+
+/// The header and index of a compressed file
+#[derive(Clone, Debug)]
+pub struct Meta {
+    pub chunk_size: u64,
+    pub size: u64,
+    pub chunks: Vec<Entry>,
+    /// Size of the stored file, including chunks that are no longer referenced.
+    pub stored_end: u64,
+}
+
+impl Meta {
+    pub fn new(chunk_size: u64) -> Self {
+        Self { chunk_size, size: 0, chunks: Vec::new(), stored_end: HEADER_LEN }
+    }
+
+    /// Returns true if `header` starts a compressed file.
+    pub fn is_compressed(header: &[u8]) -> bool {
+        header.starts_with(MAGIC)
+    }
+
+    /// Parses a header, returning the metadata without chunks and the location of the
+    /// index.
+    pub fn parse_header(header: &[u8], stored_end: u64) -> NFSResult<(Self, u64, usize)> {
+        if header.len() < HEADER_LEN as usize || !Self::is_compressed(header) {
+            return Err(nfs3::nfsstat3::NFS3ERR_IO);
+        }
+        let u32_at = |at: usize| u32::from_le_bytes(header[at..at + 4].try_into().unwrap());
+        let u64_at = |at: usize| u64::from_le_bytes(header[at..at + 8].try_into().unwrap());
+        let chunk_size = u32_at(8) as u64;
+        let count = u32_at(12) as usize;
+        let index_offset = u64_at(24);
+        let index_end = index_offset.checked_add((count * ENTRY_LEN) as u64);
+        if !(MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&chunk_size)
+            || index_end.is_none_or(|end| end > stored_end)
+        {
+            return Err(nfs3::nfsstat3::NFS3ERR_IO);
+        }
+        let meta = Self { chunk_size, size: u64_at(16), chunks: Vec::new(), stored_end };
+        Ok((meta, index_offset, count * ENTRY_LEN))
+    }
+
+    /// Fills in the chunks from a stored index.
+    pub fn parse_index(&mut self, index: &[u8]) -> NFSResult<()> {
+        self.chunks = index
+            .chunks_exact(ENTRY_LEN)
+            .map(|entry| Entry {
+                offset: u64::from_le_bytes(entry[..8].try_into().unwrap()),
+                len: u32::from_le_bytes(entry[8..].try_into().unwrap()),
+            })
+            .collect();
+        let damaged = self
+            .chunks
+            .iter()
+            .any(|e| e.offset.checked_add(e.len as u64).is_none_or(|end| end > self.stored_end));
+        if damaged || (self.chunks.len() as u64) < self.size.div_ceil(self.chunk_size) {
+            return Err(nfs3::nfsstat3::NFS3ERR_IO);
+        }
+        Ok(())
+    }
+
+    pub fn header(&self, index_offset: u64) -> Vec<u8> {
+        let mut header = Vec::with_capacity(HEADER_LEN as usize);
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&(self.chunk_size as u32).to_le_bytes());
+        header.extend_from_slice(&(self.chunks.len() as u32).to_le_bytes());
+        header.extend_from_slice(&self.size.to_le_bytes());
+        header.extend_from_slice(&index_offset.to_le_bytes());
+        header
+    }
+
+    pub fn index(&self) -> Vec<u8> {
+        let mut index = Vec::with_capacity(self.chunks.len() * ENTRY_LEN);
+        for entry in &self.chunks {
+            index.extend_from_slice(&entry.offset.to_le_bytes());
+            index.extend_from_slice(&entry.len.to_le_bytes());
+        }
+        index
+    }
+
+    /// Returns the number of logical bytes chunk `index` covers.
+    pub fn chunk_len(&self, index: u64) -> u64 {
+        self.size.saturating_sub(index * self.chunk_size).min(self.chunk_size)
+    }
+
+    /// Returns the number of stored bytes still referenced.
+    pub fn live_bytes(&self) -> u64 {
+        let chunks: u64 = self.chunks.iter().map(|entry| entry.len as u64).sum();
+        HEADER_LEN + chunks + (self.chunks.len() * ENTRY_LEN) as u64
+    }
+}
@@ -0,0 +1,176 @@
+//! Reusable conformance checks for `NFSFileSystem` implementations.
+//!
+//! The checks exercise a backend through the real `NFSv3` procedure handlers, so they
+//! verify what a client would observe on the wire rather than what the trait returns
+//! directly. Each check runs against a fresh file system obtained from a caller-supplied
+//! factory and works inside its own directory below the export root.
+//!
+//! This module is only available with the `conformance` feature:
+//!
+//! ```ignore
+//! use fernfs::conformance::ConformanceSuite;
+//!
+//! #[tokio::test]
+//! async fn my_backend_conforms() {
+//!     ConformanceSuite::new(|| MyFS::new()).run().await.assert_passed();
+//! }
+//! ```
+//!
+//! Checks that depend on optional functionality (hard links, exclusive create) report
+//! [`Outcome::Skipped`] when the backend answers `NFS3ERR_NOTSUPP`, and every check is
+//! skipped for read-only backends.
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::vfs::{Capabilities, NFSFileSystem};
+
+mod checks;
+pub mod client;
+
+pub use client::{CallError, Client, CreateHow, DirPage};
+
+/// A single conformance check.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Check {
+    /// Created files are visible through `LOOKUP` and `READDIR` with matching fileids
+    CreateLookupReaddir,
+    /// `RENAME` replaces existing targets atomically and rejects invalid replacements
+    RenameOverExisting,
+    /// `nlink` tracks hard links and subdirectories
+    LinkCounts,
+    /// Retransmitted `EXCLUSIVE` creates succeed, conflicting ones fail
+    ExclusiveCreate,
+    /// Paged `READDIR` returns every entry exactly once with stable cookies
+    ReaddirPagination,
+    /// `sattr3` fields are applied by `CREATE`, `MKDIR`, and `SETATTR`
+    SetattrHandling,
+}
+
+impl Check {
+    /// Every check, in the order [`ConformanceSuite::run`] executes them.
+    pub const ALL: [Check; 6] = [
+        Check::CreateLookupReaddir,
+        Check::RenameOverExisting,
+        Check::LinkCounts,
+        Check::ExclusiveCreate,
+        Check::ReaddirPagination,
+        Check::SetattrHandling,
+    ];
+
+    /// Short name used in reports and as the check's working directory.
+    pub fn name(self) -> &'static str {
+        match self {
+            Check::CreateLookupReaddir => "create-lookup-readdir",
+            Check::RenameOverExisting => "rename-over-existing",
+            Check::LinkCounts => "link-counts",
+            Check::ExclusiveCreate => "exclusive-create",
+            Check::ReaddirPagination => "readdir-pagination",
+            Check::SetattrHandling => "setattr-handling",
+        }
+    }
+}
+
+/// Result of running a single check.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The backend behaved as expected
+    Passed,
+    /// The check does not apply to this backend
+    Skipped(String),
+    /// The backend misbehaved
+    Failed(String),
+}
+
+/// Results of a conformance run.
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    /// Outcome of each executed check, in execution order
+    pub results: Vec<(Check, Outcome)>,
+}
+
+impl Report {
+    /// Returns the outcome recorded for `check`, if it was run.
+    pub fn outcome(&self, check: Check) -> Option<&Outcome> {
+        self.results.iter().find(|(c, _)| *c == check).map(|(_, outcome)| outcome)
+    }
+
+    /// Returns the checks that failed together with their failure messages.
+    pub fn failures(&self) -> Vec<(Check, &str)> {
+        self.results
+            .iter()
+            .filter_map(|(check, outcome)| match outcome {
+                Outcome::Failed(msg) => Some((*check, msg.as_str())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns true if no check failed.
+    pub fn passed(&self) -> bool {
+        self.failures().is_empty()
+    }
+
+    /// Panics with the full report if any check failed.
+    pub fn assert_passed(&self) {
+        assert!(self.passed(), "conformance checks failed:\n{self}");
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (check, outcome) in &self.results {
+            match outcome {
+                Outcome::Passed => writeln!(f, "{}: passed", check.name())?,
+                Outcome::Skipped(reason) => writeln!(f, "{}: skipped ({reason})", check.name())?,
+                Outcome::Failed(msg) => writeln!(f, "{}: FAILED: {msg}", check.name())?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs conformance checks against file systems produced by a factory.
+pub struct ConformanceSuite<F> {
+    factory: F,
+}
+
+impl<F, T> ConformanceSuite<F>
+where
+    F: Fn() -> T,
+    T: NFSFileSystem + Send + Sync + 'static,
+{
+    /// Creates a suite that calls `factory` once per check.
+    pub fn new(factory: F) -> Self {
+        Self { factory }
+    }
+
+    /// Runs every check.
+    pub async fn run(&self) -> Report {
+        self.run_checks(&Check::ALL).await
+    }
+
+    /// Runs the given checks in order.
+    pub async fn run_checks(&self, checks: &[Check]) -> Report {
+        let mut report = Report::default();
+        for check in checks {
+            let outcome = self.run_check(*check).await;
+            report.results.push((*check, outcome));
+        }
+        report
+    }
+
+    /// Runs a single check against a fresh file system.
+    pub async fn run_check(&self, check: Check) -> Outcome {
+        let vfs: Arc<dyn NFSFileSystem + Send + Sync> = Arc::new((self.factory)());
+        if !matches!(vfs.capabilities(), Capabilities::ReadWrite) {
+            return Outcome::Skipped("backend is read-only".to_string());
+        }
+        let client = Client::new(vfs);
+        match checks::run(&client, check).await {
+            Ok(()) => Outcome::Passed,
+            Err(checks::Failure::Skip(reason)) => Outcome::Skipped(reason),
+            Err(checks::Failure::Fail(msg)) => Outcome::Failed(msg),
+        }
+    }
+}
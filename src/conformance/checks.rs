@@ -0,0 +1,455 @@
+//! Implementations of the individual conformance checks.
+
+use std::collections::HashSet;
+
+use super::client::{CallError, Client, CreateHow};
+use super::Check;
+use crate::protocol::xdr::nfs3;
+
+/// Why a check did not pass.
+pub(super) enum Failure {
+    /// The check does not apply to the backend
+    Skip(String),
+    /// The backend misbehaved
+    Fail(String),
+}
+
+impl From<CallError> for Failure {
+    fn from(err: CallError) -> Self {
+        Failure::Fail(format!("unexpected error: {err}"))
+    }
+}
+
+type CheckResult = Result<(), Failure>;
+
+macro_rules! ensure {
+    ($cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            return Err(Failure::Fail(format!($($arg)+)));
+        }
+    };
+}
+
+pub(super) async fn run(client: &Client, check: Check) -> CheckResult {
+    let work = client
+        .mkdir(&client.root(), format!("conformance-{}", check.name()).as_bytes(), mode(0o755))
+        .await
+        .map_err(|err| Failure::Fail(format!("cannot create working directory: {err}")))?
+        .0;
+    match check {
+        Check::CreateLookupReaddir => create_lookup_readdir(client, &work).await,
+        Check::RenameOverExisting => rename_over_existing(client, &work).await,
+        Check::LinkCounts => link_counts(client, &work).await,
+        Check::ExclusiveCreate => exclusive_create(client, &work).await,
+        Check::ReaddirPagination => readdir_pagination(client, &work).await,
+        Check::SetattrHandling => setattr_handling(client, &work).await,
+    }
+}
+
+fn mode(mode: nfs3::mode3) -> nfs3::sattr3 {
+    nfs3::sattr3 { mode: nfs3::set_mode3::Some(mode), ..Default::default() }
+}
+
+fn size(size: u64) -> nfs3::sattr3 {
+    nfs3::sattr3 { size: nfs3::set_size3::Some(size), ..Default::default() }
+}
+
+fn expect_status<T>(
+    result: Result<T, CallError>,
+    allowed: &[nfs3::nfsstat3],
+    what: &str,
+) -> CheckResult {
+    match result {
+        Err(CallError::Status(stat)) if allowed.contains(&stat) => Ok(()),
+        Err(err) => Err(Failure::Fail(format!("{what}: expected {allowed:?}, got {err}"))),
+        Ok(_) => Err(Failure::Fail(format!("{what}: expected {allowed:?}, got success"))),
+    }
+}
+
+fn skip_unsupported<T>(result: Result<T, CallError>, what: &str) -> Result<T, Failure> {
+    match result {
+        Err(CallError::Status(nfs3::nfsstat3::NFS3ERR_NOTSUPP)) => {
+            Err(Failure::Skip(format!("{what} is not supported")))
+        }
+        other => Ok(other?),
+    }
+}
+
+/// Names of a listing, without `.` and `..`.
+fn listed_names(entries: &[nfs3::dir::entry3]) -> Vec<Vec<u8>> {
+    entries
+        .iter()
+        .map(|entry| entry.name.to_vec())
+        .filter(|name| name != b"." && name != b"..")
+        .collect()
+}
+
+fn show(name: &[u8]) -> String {
+    String::from_utf8_lossy(name).into_owned()
+}
+
+async fn create_lookup_readdir(client: &Client, work: &nfs3::nfs_fh3) -> CheckResult {
+    let names: [&[u8]; 3] = [b"alpha", b"beta", b"gamma"];
+    let mut fileids = Vec::new();
+    for name in names {
+        let (fh, attr) = client.create(work, name, CreateHow::Unchecked(mode(0o644))).await?;
+        ensure!(
+            matches!(attr.ftype, nfs3::ftype3::NF3REG),
+            "{} was created as {:?}",
+            show(name),
+            attr.ftype
+        );
+        let (_, looked_up) = client.lookup(work, name).await?;
+        ensure!(
+            looked_up.fileid == attr.fileid,
+            "LOOKUP of {} returned fileid {} but CREATE returned {}",
+            show(name),
+            looked_up.fileid,
+            attr.fileid
+        );
+        let fetched = client.getattr(&fh).await?;
+        ensure!(
+            fetched.fileid == attr.fileid,
+            "GETATTR of {} returned fileid {} but CREATE returned {}",
+            show(name),
+            fetched.fileid,
+            attr.fileid
+        );
+        fileids.push(attr.fileid);
+    }
+    let unique: HashSet<_> = fileids.iter().collect();
+    ensure!(unique.len() == fileids.len(), "distinct files share fileids: {fileids:?}");
+
+    expect_status(
+        client.create(work, b"alpha", CreateHow::Guarded(mode(0o644))).await,
+        &[nfs3::nfsstat3::NFS3ERR_EXIST],
+        "GUARDED create of an existing name",
+    )?;
+    expect_status(
+        client.lookup(work, b"missing").await,
+        &[nfs3::nfsstat3::NFS3ERR_NOENT],
+        "LOOKUP of a missing name",
+    )?;
+
+    let entries = client.readdir_all(work, 4096).await?;
+    let listed = listed_names(&entries);
+    for (name, fileid) in names.iter().zip(&fileids) {
+        let matching: Vec<_> = entries.iter().filter(|e| e.name.as_slice() == *name).collect();
+        ensure!(matching.len() == 1, "READDIR listed {} {} times", show(name), matching.len());
+        ensure!(
+            matching[0].fileid == *fileid,
+            "READDIR reported fileid {} for {} but LOOKUP returned {}",
+            matching[0].fileid,
+            show(name),
+            fileid
+        );
+    }
+    ensure!(
+        listed.len() == names.len(),
+        "READDIR listed unexpected entries: {:?}",
+        listed.iter().map(|n| show(n)).collect::<Vec<_>>()
+    );
+
+    client.remove(work, b"beta").await?;
+    expect_status(
+        client.lookup(work, b"beta").await,
+        &[nfs3::nfsstat3::NFS3ERR_NOENT],
+        "LOOKUP after REMOVE",
+    )?;
+    let listed = listed_names(&client.readdir_all(work, 4096).await?);
+    ensure!(!listed.iter().any(|n| n == b"beta"), "READDIR still lists a removed file");
+    ensure!(listed.len() == 2, "READDIR lists {} entries after REMOVE, expected 2", listed.len());
+    Ok(())
+}
+
+async fn rename_over_existing(client: &Client, work: &nfs3::nfs_fh3) -> CheckResult {
+    let sync = nfs3::file::stable_how::FILE_SYNC;
+    let (source, source_attr) =
+        client.create(work, b"source", CreateHow::Unchecked(mode(0o644))).await?;
+    client.write(&source, 0, b"new contents", sync).await?;
+    let (target, _) = client.create(work, b"target", CreateHow::Unchecked(mode(0o644))).await?;
+    client.write(&target, 0, b"old", sync).await?;
+
+    client.rename(work, b"source", work, b"target").await?;
+    expect_status(
+        client.lookup(work, b"source").await,
+        &[nfs3::nfsstat3::NFS3ERR_NOENT],
+        "LOOKUP of a renamed source",
+    )?;
+    let (renamed, renamed_attr) = client.lookup(work, b"target").await?;
+    ensure!(
+        renamed_attr.fileid == source_attr.fileid,
+        "target has fileid {} after RENAME, expected the source's {}",
+        renamed_attr.fileid,
+        source_attr.fileid
+    );
+    let read = client.read(&renamed, 0, 64).await?;
+    ensure!(
+        read.data == b"new contents",
+        "target contains {:?} after RENAME",
+        String::from_utf8_lossy(&read.data)
+    );
+    ensure!(client.getattr(&target).await.is_err(), "the replaced file is still reachable");
+    let listed = listed_names(&client.readdir_all(work, 4096).await?);
+    ensure!(
+        listed == vec![b"target".to_vec()],
+        "READDIR lists {:?} after RENAME",
+        listed.iter().map(|n| show(n)).collect::<Vec<_>>()
+    );
+
+    // A directory may replace an empty directory but not a populated one.
+    let (full, _) = client.mkdir(work, b"full", mode(0o755)).await?;
+    client.create(&full, b"inner", CreateHow::Unchecked(mode(0o644))).await?;
+    client.mkdir(work, b"empty", mode(0o755)).await?;
+    client.rename(work, b"full", work, b"empty").await?;
+    let (moved, _) = client.lookup(work, b"empty").await?;
+    client.lookup(&moved, b"inner").await?;
+
+    client.mkdir(work, b"other", mode(0o755)).await?;
+    expect_status(
+        client.rename(work, b"other", work, b"empty").await,
+        &[nfs3::nfsstat3::NFS3ERR_NOTEMPTY, nfs3::nfsstat3::NFS3ERR_EXIST],
+        "RENAME of a directory over a non-empty directory",
+    )?;
+    ensure!(
+        client.rename(work, b"target", work, b"other").await.is_err(),
+        "RENAME of a file over a directory succeeded"
+    );
+    ensure!(
+        client.rename(work, b"other", work, b"target").await.is_err(),
+        "RENAME of a directory over a file succeeded"
+    );
+    client.lookup(work, b"other").await?;
+    client.lookup(work, b"target").await?;
+    Ok(())
+}
+
+async fn link_counts(client: &Client, work: &nfs3::nfs_fh3) -> CheckResult {
+    let (file, attr) = client.create(work, b"original", CreateHow::Unchecked(mode(0o644))).await?;
+    ensure!(attr.nlink == 1, "new file has nlink {}", attr.nlink);
+
+    let linked = skip_unsupported(client.link(&file, work, b"alias").await, "LINK")?;
+    if let Some(linked) = linked {
+        ensure!(linked.nlink == 2, "LINK returned nlink {}", linked.nlink);
+    }
+    let attr = client.getattr(&file).await?;
+    ensure!(attr.nlink == 2, "file has nlink {} after LINK", attr.nlink);
+    let (alias, alias_attr) = client.lookup(work, b"alias").await?;
+    ensure!(
+        alias_attr.fileid == attr.fileid,
+        "link has fileid {} but the original has {}",
+        alias_attr.fileid,
+        attr.fileid
+    );
+    expect_status(
+        client.link(&file, work, b"alias").await,
+        &[nfs3::nfsstat3::NFS3ERR_EXIST],
+        "LINK onto an existing name",
+    )?;
+
+    client.remove(work, b"original").await?;
+    let attr = client.getattr(&alias).await?;
+    ensure!(attr.nlink == 1, "link has nlink {} after removing the original", attr.nlink);
+
+    // Directories start at two links and gain one per subdirectory. Backends that cannot
+    // count subdirectories conventionally report 1, which is left alone.
+    let before = client.getattr(work).await?.nlink;
+    let (sub, sub_attr) = client.mkdir(work, b"sub", mode(0o755)).await?;
+    ensure!(sub_attr.nlink == 2, "new directory has nlink {}", sub_attr.nlink);
+    if before >= 2 {
+        let after = client.getattr(work).await?.nlink;
+        ensure!(after == before + 1, "MKDIR changed the parent's nlink from {before} to {after}");
+        client.rmdir(work, b"sub").await?;
+        let after = client.getattr(work).await?.nlink;
+        ensure!(after == before, "RMDIR left the parent's nlink at {after}, expected {before}");
+        client.mkdir(work, b"sub", mode(0o755)).await?;
+    }
+    ensure!(client.link(&sub, work, b"sub-link").await.is_err(), "LINK of a directory succeeded");
+    Ok(())
+}
+
+async fn exclusive_create(client: &Client, work: &nfs3::nfs_fh3) -> CheckResult {
+    let verifier: nfs3::createverf3 = *b"fernfs01";
+    let (_, first) = skip_unsupported(
+        client.create(work, b"exclusive", CreateHow::Exclusive(verifier)).await,
+        "EXCLUSIVE create",
+    )?;
+    ensure!(
+        matches!(first.ftype, nfs3::ftype3::NF3REG),
+        "EXCLUSIVE create made a {:?}",
+        first.ftype
+    );
+
+    let (_, retry) = client.create(work, b"exclusive", CreateHow::Exclusive(verifier)).await?;
+    ensure!(
+        retry.fileid == first.fileid,
+        "retransmitted EXCLUSIVE create returned fileid {} instead of {}",
+        retry.fileid,
+        first.fileid
+    );
+    expect_status(
+        client.create(work, b"exclusive", CreateHow::Exclusive(*b"fernfs02")).await,
+        &[nfs3::nfsstat3::NFS3ERR_EXIST],
+        "EXCLUSIVE create with a different verifier",
+    )?;
+
+    client.create(work, b"plain", CreateHow::Unchecked(mode(0o644))).await?;
+    expect_status(
+        client.create(work, b"plain", CreateHow::Exclusive(verifier)).await,
+        &[nfs3::nfsstat3::NFS3ERR_EXIST],
+        "EXCLUSIVE create over a file created without a verifier",
+    )?;
+
+    // Clients follow an exclusive create with SETATTR to apply the real attributes.
+    let (fh, _) = client.lookup(work, b"exclusive").await?;
+    client.setattr(&fh, mode(0o600), None).await?;
+    let attr = client.getattr(&fh).await?;
+    ensure!(attr.mode & 0o7777 == 0o600, "mode is {:o} after SETATTR", attr.mode & 0o7777);
+    Ok(())
+}
+
+async fn readdir_pagination(client: &Client, work: &nfs3::nfs_fh3) -> CheckResult {
+    const COUNT: usize = 64;
+    // Small enough to need several pages, large enough for a handful of entries each.
+    const DIRCOUNT: u32 = 512;
+
+    for i in 0..COUNT {
+        let name = format!("entry-{i:03}");
+        client.create(work, name.as_bytes(), CreateHow::Unchecked(mode(0o644))).await?;
+    }
+    let full = client.readdir_all(work, 64 * 1024).await?;
+
+    let mut pages = Vec::new();
+    let mut cookie = 0;
+    let mut cookieverf = nfs3::cookieverf3::default();
+    loop {
+        ensure!(pages.len() <= COUNT + 2, "READDIR did not reach eof");
+        let page = client.readdir(work, cookie, cookieverf, DIRCOUNT).await?;
+        ensure!(!page.entries.is_empty() || page.eof, "READDIR returned an empty page");
+        cookieverf = page.cookieverf;
+        let eof = page.eof;
+        if let Some(last) = page.entries.last() {
+            cookie = last.cookie;
+        }
+        pages.push((cookie, page.entries));
+        if eof {
+            break;
+        }
+    }
+    ensure!(pages.len() > 2, "READDIR returned everything in {} pages", pages.len());
+
+    let paged: Vec<_> = pages.iter().flat_map(|(_, entries)| entries).collect();
+    let mut seen = HashSet::new();
+    for entry in &paged {
+        ensure!(seen.insert(entry.name.to_vec()), "READDIR returned {} twice", show(&entry.name));
+        ensure!(entry.cookie != 0, "READDIR returned cookie 0 for {}", show(&entry.name));
+    }
+    let paged_names: Vec<_> = paged.iter().map(|e| e.name.to_vec()).collect();
+    let full_names: Vec<_> = full.iter().map(|e| e.name.to_vec()).collect();
+    ensure!(
+        paged_names == full_names,
+        "paged READDIR returned {} entries, a single READDIR returned {}",
+        paged_names.len(),
+        full_names.len()
+    );
+    ensure!(
+        listed_names(&full).len() == COUNT,
+        "READDIR listed {} files, expected {COUNT}",
+        listed_names(&full).len()
+    );
+
+    // Resuming from an earlier cookie must replay the same page.
+    let (resume_cookie, _) = &pages[0];
+    let (_, expected) = &pages[1];
+    let replay = client.readdir(work, *resume_cookie, cookieverf, DIRCOUNT).await?;
+    let replayed: Vec<_> = replay.entries.iter().map(|e| (e.name.to_vec(), e.cookie)).collect();
+    let original: Vec<_> = expected.iter().map(|e| (e.name.to_vec(), e.cookie)).collect();
+    ensure!(replayed == original, "resuming from cookie {resume_cookie} returned a different page");
+
+    // After the directory changes, an old cookie must either be rejected or continue
+    // without repeating entries the client has already seen.
+    client.create(work, b"aaa-late", CreateHow::Unchecked(mode(0o644))).await?;
+    match client.readdir(work, *resume_cookie, cookieverf, DIRCOUNT).await {
+        Err(CallError::Status(nfs3::nfsstat3::NFS3ERR_BAD_COOKIE)) => {}
+        Err(err) => return Err(err.into()),
+        Ok(page) => {
+            let before: HashSet<_> = pages[0].1.iter().map(|e| e.name.to_vec()).collect();
+            for entry in &page.entries {
+                ensure!(
+                    !before.contains(entry.name.as_slice()),
+                    "stale cookie repeated {} after the directory changed",
+                    show(&entry.name)
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn setattr_handling(client: &Client, work: &nfs3::nfs_fh3) -> CheckResult {
+    let (file, attr) = client.create(work, b"attrs", CreateHow::Unchecked(mode(0o640))).await?;
+    ensure!(attr.mode & 0o7777 == 0o640, "CREATE applied mode {:o}", attr.mode & 0o7777);
+    client.write(&file, 0, b"hello world", nfs3::file::stable_how::FILE_SYNC).await?;
+
+    client.setattr(&file, size(5), None).await?;
+    let attr = client.getattr(&file).await?;
+    ensure!(attr.size == 5, "size is {} after truncating to 5", attr.size);
+    let read = client.read(&file, 0, 64).await?;
+    ensure!(read.data == b"hello", "truncated file contains {:?}", read.data);
+    ensure!(read.eof, "READ past the end did not report eof");
+
+    client.setattr(&file, size(4096), None).await?;
+    let attr = client.getattr(&file).await?;
+    ensure!(attr.size == 4096, "size is {} after extending to 4096", attr.size);
+    let read = client.read(&file, 0, 4096).await?;
+    ensure!(read.data.len() == 4096, "READ of the extended file returned {}", read.data.len());
+    ensure!(
+        read.data[..5] == *b"hello" && read.data[5..].iter().all(|b| *b == 0),
+        "extending a file did not zero-fill the new range"
+    );
+
+    client.setattr(&file, mode(0o600), None).await?;
+    let attr = client.getattr(&file).await?;
+    ensure!(attr.mode & 0o7777 == 0o600, "mode is {:o} after SETATTR", attr.mode & 0o7777);
+    ensure!(attr.size == 4096, "SETATTR of mode changed size to {}", attr.size);
+
+    let stamp = nfs3::nfstime3 { seconds: 1_000_000_000, nseconds: 0 };
+    let times = nfs3::sattr3 {
+        atime: nfs3::set_atime::SET_TO_CLIENT_TIME(stamp),
+        mtime: nfs3::set_mtime::SET_TO_CLIENT_TIME(stamp),
+        ..Default::default()
+    };
+    client.setattr(&file, times, None).await?;
+    let attr = client.getattr(&file).await?;
+    ensure!(
+        attr.mtime.seconds == stamp.seconds && attr.atime.seconds == stamp.seconds,
+        "times are atime={} mtime={} after SETATTR to {}",
+        attr.atime.seconds,
+        attr.mtime.seconds,
+        stamp.seconds
+    );
+
+    let ctime = attr.ctime;
+    let wrong = nfs3::nfstime3 { seconds: ctime.seconds.wrapping_add(1), nseconds: ctime.nseconds };
+    expect_status(
+        client.setattr(&file, mode(0o644), Some(wrong)).await,
+        &[nfs3::nfsstat3::NFS3ERR_NOT_SYNC],
+        "SETATTR guarded by a stale ctime",
+    )?;
+    let attr = client.getattr(&file).await?;
+    ensure!(attr.mode & 0o7777 == 0o600, "failed guarded SETATTR changed mode");
+    client.setattr(&file, mode(0o644), Some(ctime)).await?;
+    let attr = client.getattr(&file).await?;
+    ensure!(attr.mode & 0o7777 == 0o644, "guarded SETATTR did not apply");
+
+    // UNCHECKED create of an existing file applies the attributes to it.
+    let (_, recreated) = client.create(work, b"attrs", CreateHow::Unchecked(size(0))).await?;
+    ensure!(recreated.fileid == attr.fileid, "UNCHECKED create replaced the existing file");
+    let attr = client.getattr(&file).await?;
+    ensure!(attr.size == 0, "UNCHECKED create with size 0 left size {}", attr.size);
+
+    let (_, dir_attr) = client.mkdir(work, b"private", mode(0o700)).await?;
+    ensure!(matches!(dir_attr.ftype, nfs3::ftype3::NF3DIR), "MKDIR made a {:?}", dir_attr.ftype);
+    ensure!(dir_attr.mode & 0o7777 == 0o700, "MKDIR applied mode {:o}", dir_attr.mode & 0o7777);
+    Ok(())
+}
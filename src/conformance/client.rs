@@ -0,0 +1,373 @@
+//! A minimal in-process `NFSv3` client used by the conformance checks.
+//!
+//! Every call is encoded as XDR, dispatched through [`handle_nfs`] exactly as a request
+//! arriving over TCP would be, and the reply is decoded back into typed results.
+
+use std::fmt;
+use std::io::Cursor;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use num_traits::FromPrimitive;
+
+use crate::protocol::nfs::portmap::PortmapTable;
+use crate::protocol::nfs::v3::handle_nfs;
+use crate::protocol::rpc::{Context, TransactionTracker};
+use crate::protocol::xdr::{self, deserialize, nfs3, Serialize};
+use crate::vfs::NFSFileSystem;
+
+/// Error returned by a [`Client`] call.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CallError {
+    /// The server answered with a status other than `NFS3_OK`.
+    Status(nfs3::nfsstat3),
+    /// The request could not be encoded or the reply could not be decoded.
+    Protocol(String),
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallError::Status(stat) => write!(f, "{stat:?}"),
+            CallError::Protocol(msg) => write!(f, "protocol error: {msg}"),
+        }
+    }
+}
+
+impl From<std::io::Error> for CallError {
+    fn from(err: std::io::Error) -> Self {
+        CallError::Protocol(err.to_string())
+    }
+}
+
+/// How a `CREATE` request should be issued.
+#[derive(Clone, Debug)]
+pub enum CreateHow {
+    /// Create or truncate/update an existing file
+    Unchecked(nfs3::sattr3),
+    /// Fail with `NFS3ERR_EXIST` if the name exists
+    Guarded(nfs3::sattr3),
+    /// Exclusive create keyed by a client verifier
+    Exclusive(nfs3::createverf3),
+}
+
+/// One page of `READDIR` results.
+#[derive(Debug, Default)]
+pub struct DirPage {
+    /// Cookie verifier to send with follow-up requests
+    pub cookieverf: nfs3::cookieverf3,
+    /// Entries in the order returned by the server
+    pub entries: Vec<nfs3::dir::entry3>,
+    /// True if the last entry of the directory was returned
+    pub eof: bool,
+}
+
+/// Drives an `NFSFileSystem` through the `NFSv3` procedure handlers.
+pub struct Client {
+    context: Context,
+    xid: AtomicU32,
+}
+
+impl Client {
+    /// Creates a client for `vfs` that authenticates as root.
+    pub fn new(vfs: Arc<dyn NFSFileSystem + Send + Sync>) -> Self {
+        let context = Context {
+            local_port: 0,
+            client_addr: "127.0.0.1:1234".to_string(),
+            auth: xdr::rpc::auth_unix::default(),
+            vfs,
+            mount_signal: None,
+            export_name: Arc::from("/".to_string()),
+            transaction_tracker: Arc::new(TransactionTracker::new(Duration::from_secs(60))),
+            portmap_table: Arc::new(RwLock::new(PortmapTable::default())),
+        };
+        Self { context, xid: AtomicU32::new(1) }
+    }
+
+    /// Returns the file system under test.
+    pub fn vfs(&self) -> &Arc<dyn NFSFileSystem + Send + Sync> {
+        &self.context.vfs
+    }
+
+    /// Returns the file handle of the export root.
+    pub fn root(&self) -> nfs3::nfs_fh3 {
+        self.context.vfs.id_to_fh(self.context.vfs.root_dir())
+    }
+
+    /// Sends a call and returns the reply positioned after the status word.
+    ///
+    /// A status other than `NFS3_OK` is returned as [`CallError::Status`]; the
+    /// remaining failure body is not decoded.
+    async fn call(
+        &self,
+        proc: nfs3::NFSProgram,
+        args: Vec<u8>,
+    ) -> Result<Cursor<Vec<u8>>, CallError> {
+        let xid = self.xid.fetch_add(1, Ordering::Relaxed);
+        let call = xdr::rpc::call_body {
+            rpcvers: 2,
+            prog: nfs3::PROGRAM,
+            vers: nfs3::VERSION,
+            proc: proc as u32,
+            cred: xdr::rpc::opaque_auth::default(),
+            verf: xdr::rpc::opaque_auth::default(),
+        };
+        let mut input = Cursor::new(args);
+        let mut output = Cursor::new(Vec::new());
+        handle_nfs(xid, call, &mut input, &mut output, &self.context)
+            .await
+            .map_err(|err| CallError::Protocol(format!("{proc:?}: {err}")))?;
+
+        output.set_position(0);
+        let msg = deserialize::<xdr::rpc::rpc_msg>(&mut output)?;
+        let accepted = matches!(
+            msg.body,
+            xdr::rpc::rpc_body::REPLY(xdr::rpc::reply_body::MSG_ACCEPTED(
+                xdr::rpc::accepted_reply { reply_data: xdr::rpc::accept_body::SUCCESS, .. }
+            ))
+        );
+        if msg.xid != xid || !accepted {
+            return Err(CallError::Protocol(format!("{proc:?}: unexpected reply {:?}", msg.body)));
+        }
+        let raw = deserialize::<u32>(&mut output)?;
+        match nfs3::nfsstat3::from_u32(raw) {
+            Some(nfs3::nfsstat3::NFS3_OK) => Ok(output),
+            Some(stat) => Err(CallError::Status(stat)),
+            None => Err(CallError::Protocol(format!("{proc:?}: unknown status {raw}"))),
+        }
+    }
+
+    /// `GETATTR`
+    pub async fn getattr(&self, fh: &nfs3::nfs_fh3) -> Result<nfs3::fattr3, CallError> {
+        let mut args = Vec::new();
+        fh.serialize(&mut args)?;
+        let mut reply = self.call(nfs3::NFSProgram::NFSPROC3_GETATTR, args).await?;
+        Ok(deserialize::<nfs3::fattr3>(&mut reply)?)
+    }
+
+    /// `SETATTR`, optionally guarded by the object's current ctime.
+    pub async fn setattr(
+        &self,
+        fh: &nfs3::nfs_fh3,
+        attr: nfs3::sattr3,
+        guard: Option<nfs3::nfstime3>,
+    ) -> Result<nfs3::wcc_data, CallError> {
+        let mut args = Vec::new();
+        nfs3::SETATTR3args { object: fh.clone(), new_attribute: attr, guard }
+            .serialize(&mut args)?;
+        let mut reply = self.call(nfs3::NFSProgram::NFSPROC3_SETATTR, args).await?;
+        Ok(deserialize::<nfs3::wcc_data>(&mut reply)?)
+    }
+
+    /// `LOOKUP`, returning the handle and attributes of the object.
+    pub async fn lookup(
+        &self,
+        dir: &nfs3::nfs_fh3,
+        name: &[u8],
+    ) -> Result<(nfs3::nfs_fh3, nfs3::fattr3), CallError> {
+        let mut args = Vec::new();
+        nfs3::diropargs3 { dir: dir.clone(), name: name.into() }.serialize(&mut args)?;
+        let mut reply = self.call(nfs3::NFSProgram::NFSPROC3_LOOKUP, args).await?;
+        let fh = deserialize::<nfs3::nfs_fh3>(&mut reply)?;
+        match deserialize::<nfs3::post_op_attr>(&mut reply)? {
+            Some(attr) => Ok((fh, attr)),
+            None => {
+                let attr = self.getattr(&fh).await?;
+                Ok((fh, attr))
+            }
+        }
+    }
+
+    /// `CREATE`, returning the handle and attributes of the new file.
+    ///
+    /// Exclusive creates do not return attributes, so they are fetched separately.
+    pub async fn create(
+        &self,
+        dir: &nfs3::nfs_fh3,
+        name: &[u8],
+        how: CreateHow,
+    ) -> Result<(nfs3::nfs_fh3, nfs3::fattr3), CallError> {
+        let mut args = Vec::new();
+        nfs3::diropargs3 { dir: dir.clone(), name: name.into() }.serialize(&mut args)?;
+        match how {
+            CreateHow::Unchecked(attr) => {
+                nfs3::createmode3::UNCHECKED.serialize(&mut args)?;
+                attr.serialize(&mut args)?;
+            }
+            CreateHow::Guarded(attr) => {
+                nfs3::createmode3::GUARDED.serialize(&mut args)?;
+                attr.serialize(&mut args)?;
+            }
+            CreateHow::Exclusive(verf) => {
+                nfs3::createmode3::EXCLUSIVE.serialize(&mut args)?;
+                verf.serialize(&mut args)?;
+            }
+        }
+        let mut reply = self.call(nfs3::NFSProgram::NFSPROC3_CREATE, args).await?;
+        self.new_object(&mut reply, dir, name).await
+    }
+
+    /// `MKDIR`, returning the handle and attributes of the new directory.
+    pub async fn mkdir(
+        &self,
+        dir: &nfs3::nfs_fh3,
+        name: &[u8],
+        attr: nfs3::sattr3,
+    ) -> Result<(nfs3::nfs_fh3, nfs3::fattr3), CallError> {
+        let mut args = Vec::new();
+        nfs3::dir::MKDIR3args {
+            dirops: nfs3::diropargs3 { dir: dir.clone(), name: name.into() },
+            attributes: attr,
+        }
+        .serialize(&mut args)?;
+        let mut reply = self.call(nfs3::NFSProgram::NFSPROC3_MKDIR, args).await?;
+        self.new_object(&mut reply, dir, name).await
+    }
+
+    /// Decodes the `post_op_fh3`/`post_op_attr` pair shared by the creation procedures,
+    /// falling back to `LOOKUP`/`GETATTR` for whatever the server left out.
+    async fn new_object(
+        &self,
+        reply: &mut Cursor<Vec<u8>>,
+        dir: &nfs3::nfs_fh3,
+        name: &[u8],
+    ) -> Result<(nfs3::nfs_fh3, nfs3::fattr3), CallError> {
+        let fh = deserialize::<nfs3::post_op_fh3>(reply)?;
+        let attr = deserialize::<nfs3::post_op_attr>(reply)?;
+        match (fh, attr) {
+            (Some(fh), Some(attr)) => Ok((fh, attr)),
+            (Some(fh), None) => {
+                let attr = self.getattr(&fh).await?;
+                Ok((fh, attr))
+            }
+            (None, _) => self.lookup(dir, name).await,
+        }
+    }
+
+    /// `REMOVE`
+    pub async fn remove(&self, dir: &nfs3::nfs_fh3, name: &[u8]) -> Result<(), CallError> {
+        let mut args = Vec::new();
+        nfs3::diropargs3 { dir: dir.clone(), name: name.into() }.serialize(&mut args)?;
+        self.call(nfs3::NFSProgram::NFSPROC3_REMOVE, args).await?;
+        Ok(())
+    }
+
+    /// `RMDIR`
+    pub async fn rmdir(&self, dir: &nfs3::nfs_fh3, name: &[u8]) -> Result<(), CallError> {
+        let mut args = Vec::new();
+        nfs3::diropargs3 { dir: dir.clone(), name: name.into() }.serialize(&mut args)?;
+        self.call(nfs3::NFSProgram::NFSPROC3_RMDIR, args).await?;
+        Ok(())
+    }
+
+    /// `RENAME`
+    pub async fn rename(
+        &self,
+        from_dir: &nfs3::nfs_fh3,
+        from_name: &[u8],
+        to_dir: &nfs3::nfs_fh3,
+        to_name: &[u8],
+    ) -> Result<(), CallError> {
+        let mut args = Vec::new();
+        nfs3::diropargs3 { dir: from_dir.clone(), name: from_name.into() }.serialize(&mut args)?;
+        nfs3::diropargs3 { dir: to_dir.clone(), name: to_name.into() }.serialize(&mut args)?;
+        self.call(nfs3::NFSProgram::NFSPROC3_RENAME, args).await?;
+        Ok(())
+    }
+
+    /// `LINK`, returning the attributes of the linked file if the server sent them.
+    pub async fn link(
+        &self,
+        file: &nfs3::nfs_fh3,
+        dir: &nfs3::nfs_fh3,
+        name: &[u8],
+    ) -> Result<nfs3::post_op_attr, CallError> {
+        let mut args = Vec::new();
+        nfs3::file::LINK3args {
+            file: file.clone(),
+            link: nfs3::diropargs3 { dir: dir.clone(), name: name.into() },
+        }
+        .serialize(&mut args)?;
+        let mut reply = self.call(nfs3::NFSProgram::NFSPROC3_LINK, args).await?;
+        Ok(deserialize::<nfs3::post_op_attr>(&mut reply)?)
+    }
+
+    /// `READ`
+    pub async fn read(
+        &self,
+        fh: &nfs3::nfs_fh3,
+        offset: u64,
+        count: u32,
+    ) -> Result<nfs3::file::READ3resok, CallError> {
+        let mut args = Vec::new();
+        nfs3::file::READ3args { file: fh.clone(), offset, count }.serialize(&mut args)?;
+        let mut reply = self.call(nfs3::NFSProgram::NFSPROC3_READ, args).await?;
+        Ok(deserialize::<nfs3::file::READ3resok>(&mut reply)?)
+    }
+
+    /// `WRITE`
+    pub async fn write(
+        &self,
+        fh: &nfs3::nfs_fh3,
+        offset: u64,
+        data: &[u8],
+        stable: nfs3::file::stable_how,
+    ) -> Result<nfs3::file::WRITE3resok, CallError> {
+        let mut args = Vec::new();
+        nfs3::file::WRITE3args {
+            file: fh.clone(),
+            offset,
+            count: data.len() as u32,
+            stable: stable as u32,
+            data: data.to_vec(),
+        }
+        .serialize(&mut args)?;
+        let mut reply = self.call(nfs3::NFSProgram::NFSPROC3_WRITE, args).await?;
+        Ok(deserialize::<nfs3::file::WRITE3resok>(&mut reply)?)
+    }
+
+    /// `READDIR`, returning a single page.
+    pub async fn readdir(
+        &self,
+        dir: &nfs3::nfs_fh3,
+        cookie: nfs3::cookie3,
+        cookieverf: nfs3::cookieverf3,
+        dircount: u32,
+    ) -> Result<DirPage, CallError> {
+        let mut args = Vec::new();
+        nfs3::dir::READDIR3args { dir: dir.clone(), cookie, cookieverf, dircount }
+            .serialize(&mut args)?;
+        let mut reply = self.call(nfs3::NFSProgram::NFSPROC3_READDIR, args).await?;
+        let _dir_attr = deserialize::<nfs3::post_op_attr>(&mut reply)?;
+        let cookieverf = deserialize::<nfs3::cookieverf3>(&mut reply)?;
+        let mut entries = Vec::new();
+        while deserialize::<bool>(&mut reply)? {
+            entries.push(deserialize::<nfs3::dir::entry3>(&mut reply)?);
+        }
+        let eof = deserialize::<bool>(&mut reply)?;
+        Ok(DirPage { cookieverf, entries, eof })
+    }
+
+    /// Reads a whole directory, following cookies until the server reports eof.
+    pub async fn readdir_all(
+        &self,
+        dir: &nfs3::nfs_fh3,
+        dircount: u32,
+    ) -> Result<Vec<nfs3::dir::entry3>, CallError> {
+        let mut entries: Vec<nfs3::dir::entry3> = Vec::new();
+        let mut cookieverf = nfs3::cookieverf3::default();
+        loop {
+            let cookie = entries.last().map_or(0, |entry| entry.cookie);
+            let page = self.readdir(dir, cookie, cookieverf, dircount).await?;
+            if page.entries.is_empty() && !page.eof {
+                return Err(CallError::Protocol("READDIR made no progress".to_string()));
+            }
+            cookieverf = page.cookieverf;
+            entries.extend(page.entries);
+            if page.eof {
+                return Ok(entries);
+            }
+        }
+    }
+}
@@ -0,0 +1,597 @@
+//! Encryption at rest for another file system.
+//!
+//! [`CryptFS`] wraps an `NFSFileSystem` and stores the contents of regular files
+//! encrypted in fixed-size authenticated blocks, so the wrapped storage only ever sees
+//! ciphertext. Clients see plaintext: reads decrypt the blocks they cover, writes
+//! re-encrypt every block they touch (reading and decrypting partial blocks first),
+//! and attributes report plaintext sizes. Tampering with a block makes reads of it
+//! fail with `NFS3ERR_IO`.
+//!
+//! File names can be encrypted as well with [`CryptFS::encrypt_names`]. Names are then
+//! stored base64-encoded, which leaves room for shorter names than the wrapped file
+//! system allows; `pathconf` reports the reduced limit. Entries whose stored names
+//! are not ours, such as files placed in the wrapped file system directly, are listed
+//! as `.unencrypted.` followed by the stored name, and can be looked up, renamed and
+//! removed under that name. New names cannot start with `.unencrypted.`.
+//!
+//! Keys come from a [`KeyProvider`]:
+//!
+//! ```ignore
+//! use std::sync::Arc;
+//! use fernfs::crypt_fs::{CryptFS, StaticKey};
+//!
+//! let key = load_key_from_somewhere();
+//! let fs = CryptFS::new(Arc::new(MirrorFS::new("/mnt/shared".into())), Arc::new(StaticKey(key)))
+//!     .encrypt_names(true);
+//! ```
+//!
+//! Directory structure, file sizes (to within a block), timestamps, ownership and
+//! symbolic link targets are not hidden. The stored size of a file determines its
+//! plaintext size, so truncating the stored file to a block boundary is not detected.
+//! Files and names keep the id of the key they were encrypted with and remain readable
+//! after rotation as long as the old key is still provided; with name encryption on,
+//! the provider must also list the old key in [`KeyProvider::key_ids`], since a lookup
+//! that misses under the current key tries each of those keys in turn.
+
+mod cipher;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use tracing::debug;
+
+use crate::protocol::xdr::rpc::auth_unix;
+use crate::vfs::{self, Capabilities, NFSFileSystem};
+use crate::xdr::nfs3;
+
+use cipher::{
+    block_offset, plain_size, stored_size, FileCipher, NameCipher, BLOCK_OVERHEAD, BLOCK_SIZE,
+    HEADER_LEN, SEALED_BLOCK_SIZE,
+};
+pub use cipher::{Key, KeyProvider, StaticKey};
+
+type NFSResult<T> = Result<T, nfs3::nfsstat3>;
+
+/// Number of locks serializing reads and read-modify-write cycles, shared by fileid.
+const FILE_LOCKS: usize = 64;
+/// Largest amount of ciphertext buffered before it is written out.
+const WRITE_CHUNK: usize = 1024 * 1024;
+/// Prefix of the names listed for entries whose stored names do not decrypt.
+///
+/// Stored names are unpadded URL-safe base64, which never contains `.`.
+const UNENCRYPTED_PREFIX: &[u8] = b".unencrypted.";
+
+/// A file system that encrypts the contents, and optionally the names, of another
+///
+/// See the [module documentation](self) for the format and its limits.
+pub struct CryptFS {
+    inner: Arc<dyn NFSFileSystem + Send + Sync>,
+    keys: Arc<dyn KeyProvider>,
+    encrypt_names: bool,
+    file_locks: Vec<tokio::sync::Mutex<()>>,
+}
+
+impl CryptFS {
+    /// Wraps `inner`, encrypting file contents with keys from `keys`
+    pub fn new(inner: Arc<dyn NFSFileSystem + Send + Sync>, keys: Arc<dyn KeyProvider>) -> Self {
+        Self {
+            inner,
+            keys,
+            encrypt_names: false,
+            file_locks: (0..FILE_LOCKS).map(|_| tokio::sync::Mutex::new(())).collect(),
+        }
+    }
+
+    /// Sets whether file names are encrypted too
+    pub fn encrypt_names(mut self, encrypt_names: bool) -> Self {
+        self.encrypt_names = encrypt_names;
+        self
+    }
+
+    /// Returns the wrapped file system
+    pub fn inner(&self) -> &Arc<dyn NFSFileSystem + Send + Sync> {
+        &self.inner
+    }
+
+    fn file_lock(&self, id: nfs3::fileid3) -> &tokio::sync::Mutex<()> {
+        &self.file_locks[(id % FILE_LOCKS as u64) as usize]
+    }
+
+    /// Returns the name cipher of key `id`, or `None` if names are not encrypted.
+    fn name_cipher(&self, id: u32) -> NFSResult<Option<NameCipher>> {
+        self.encrypt_names.then(|| NameCipher::new(self.keys.as_ref(), id)).transpose()
+    }
+
+    /// Returns the name under which the entry `name` of directory `dirid` is stored.
+    ///
+    /// An entry stored under an earlier key keeps that stored name; any other name is
+    /// stored under the current key.
+    async fn stored_name(
+        &self,
+        dirid: nfs3::fileid3,
+        name: &nfs3::filename3,
+    ) -> NFSResult<nfs3::filename3> {
+        let current = self.keys.current_key_id();
+        let Some(names) = self.name_cipher(current)? else {
+            return Ok(name.clone());
+        };
+        if matches!(&name[..], b"." | b"..") {
+            return Ok(name.clone());
+        }
+        if let Some(stored) = name.strip_prefix(UNENCRYPTED_PREFIX) {
+            if matches!(stored, b"" | b"." | b"..") {
+                return Err(nfs3::nfsstat3::NFS3ERR_NOENT);
+            }
+            return Ok(stored.into());
+        }
+        if name.len() > self.pathconf_name_max() as usize {
+            return Err(nfs3::nfsstat3::NFS3ERR_NAMETOOLONG);
+        }
+        let stored: nfs3::filename3 = names.encrypt(name).into();
+        let earlier: Vec<u32> =
+            self.keys.key_ids().into_iter().filter(|&id| id != current).collect();
+        if earlier.is_empty() {
+            return Ok(stored);
+        }
+        match self.inner.lookup(dirid, &stored).await {
+            Err(nfs3::nfsstat3::NFS3ERR_NOENT) => {}
+            Ok(_) => return Ok(stored),
+            Err(err) => return Err(err),
+        }
+        for id in earlier {
+            let old: nfs3::filename3 =
+                NameCipher::new(self.keys.as_ref(), id)?.encrypt(name).into();
+            match self.inner.lookup(dirid, &old).await {
+                Err(nfs3::nfsstat3::NFS3ERR_NOENT) => {}
+                Ok(_) => return Ok(old),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(stored)
+    }
+
+    /// Returns the name under which a new entry `name` of directory `dirid` is to be
+    /// stored, which is that of an existing entry of the same name.
+    async fn new_stored_name(
+        &self,
+        dirid: nfs3::fileid3,
+        name: &nfs3::filename3,
+    ) -> NFSResult<nfs3::filename3> {
+        if self.encrypt_names && name.starts_with(UNENCRYPTED_PREFIX) {
+            return Err(nfs3::nfsstat3::NFS3ERR_ACCES);
+        }
+        self.stored_name(dirid, name).await
+    }
+
+    /// Replaces stored names and sizes in a directory listing with plaintext ones.
+    fn decrypt_entries(&self, mut result: vfs::ReadDirResult) -> NFSResult<vfs::ReadDirResult> {
+        for entry in &mut result.entries {
+            entry.attr = plain_attr(entry.attr);
+        }
+        if !self.encrypt_names {
+            return Ok(result);
+        }
+        // Every entry is kept, since cookies count the entries of the wrapped listing.
+        let mut ciphers: Vec<(u32, Option<NameCipher>)> = Vec::new();
+        for entry in &mut result.entries {
+            if matches!(&entry.name[..], b"." | b"..") {
+                continue;
+            }
+            // Names decrypt with the key they name, if it is still provided.
+            let names = NameCipher::key_id(&entry.name).and_then(|id| {
+                let at = ciphers.iter().position(|(known, _)| *known == id).unwrap_or_else(|| {
+                    ciphers.push((id, NameCipher::new(self.keys.as_ref(), id).ok()));
+                    ciphers.len() - 1
+                });
+                ciphers[at].1.as_ref()
+            });
+            entry.name = match names.and_then(|names| names.decrypt(&entry.name)) {
+                Some(name) => name.into(),
+                None => {
+                    debug!("listing entry {:?} that is not an encrypted name", entry.name);
+                    [UNENCRYPTED_PREFIX, &entry.name[..]].concat().into()
+                }
+            };
+        }
+        Ok(result)
+    }
+
+    /// Reads exactly `len` bytes of the stored file, or less at its end.
+    async fn read_stored(&self, id: nfs3::fileid3, offset: u64, len: u64) -> NFSResult<Vec<u8>> {
+        let mut data = Vec::with_capacity(len as usize);
+        while (data.len() as u64) < len {
+            let want = (len - data.len() as u64).min(u32::MAX as u64) as u32;
+            let (chunk, eof) = self.inner.read(id, offset + data.len() as u64, want).await?;
+            data.extend_from_slice(&chunk);
+            if eof || chunk.is_empty() {
+                break;
+            }
+        }
+        Ok(data)
+    }
+
+    /// Returns the cipher of a stored file that is not empty.
+    async fn open_file(&self, id: nfs3::fileid3) -> NFSResult<FileCipher> {
+        let header = self.read_stored(id, 0, HEADER_LEN).await?;
+        FileCipher::open(self.keys.as_ref(), &header)
+    }
+
+    /// Reads and decrypts block `index` of a file whose plaintext is `size` bytes.
+    async fn read_block(
+        &self,
+        id: nfs3::fileid3,
+        cipher: &FileCipher,
+        index: u64,
+        size: u64,
+    ) -> NFSResult<Vec<u8>> {
+        let len = (size - index * BLOCK_SIZE).min(BLOCK_SIZE) + BLOCK_OVERHEAD;
+        let sealed = self.read_stored(id, block_offset(index), len).await?;
+        cipher.open_block(index, &sealed)
+    }
+
+    async fn write_stored(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+        stable: nfs3::file::stable_how,
+    ) -> NFSResult<(nfs3::fattr3, nfs3::file::stable_how)> {
+        let (attr, committed, count) = self.inner.write(id, offset, data, stable).await?;
+        if count as usize != data.len() {
+            debug!("short write of {} of {} encrypted bytes", count, data.len());
+            return Err(nfs3::nfsstat3::NFS3ERR_IO);
+        }
+        Ok((attr, committed))
+    }
+
+    /// Writes `data` at `offset` of the file, whose stored attributes are `attr`, zero
+    /// filling any gap after the current end. With no data, this extends the file to
+    /// `offset`. The caller holds the file's lock.
+    async fn store(
+        &self,
+        id: nfs3::fileid3,
+        attr: nfs3::fattr3,
+        offset: u64,
+        data: &[u8],
+        stable: nfs3::file::stable_how,
+    ) -> NFSResult<(nfs3::fattr3, nfs3::file::stable_how)> {
+        let size = plain_size(attr.size);
+        let end = offset.checked_add(data.len() as u64).ok_or(nfs3::nfsstat3::NFS3ERR_FBIG)?;
+        let start = offset.min(size);
+        if end <= start {
+            return Ok((attr, stable));
+        }
+        let (mut out, cipher) = if size == 0 {
+            FileCipher::create(self.keys.as_ref())?
+        } else {
+            (Vec::new(), self.open_file(id).await?)
+        };
+        let first = start / BLOCK_SIZE;
+        let mut out_offset = if size == 0 { 0 } else { block_offset(first) };
+        let mut result = (attr, stable);
+        let new_size = end.max(size);
+        for index in first..=(end - 1) / BLOCK_SIZE {
+            let block_start = index * BLOCK_SIZE;
+            let block_end = (block_start + BLOCK_SIZE).min(new_size);
+            let existing = size.saturating_sub(block_start).min(BLOCK_SIZE);
+            // Blocks that the new data covers entirely need not be read first.
+            let covered = offset <= block_start && end >= block_start + existing;
+            let mut plain = if existing > 0 && !covered {
+                self.read_block(id, &cipher, index, size).await?
+            } else {
+                Vec::new()
+            };
+            plain.resize((block_end - block_start) as usize, 0);
+            let lo = offset.max(block_start);
+            let hi = end.min(block_end);
+            if lo < hi {
+                plain[(lo - block_start) as usize..(hi - block_start) as usize]
+                    .copy_from_slice(&data[(lo - offset) as usize..(hi - offset) as usize]);
+            }
+            out.extend(cipher.seal(index, &plain));
+            if out.len() >= WRITE_CHUNK {
+                result = self.write_stored(id, out_offset, &out, stable).await?;
+                out_offset += out.len() as u64;
+                out.clear();
+            }
+        }
+        if !out.is_empty() {
+            result = self.write_stored(id, out_offset, &out, stable).await?;
+        }
+        Ok(result)
+    }
+
+    /// Changes the plaintext size of a regular file.
+    async fn resize(&self, id: nfs3::fileid3, new_size: u64) -> NFSResult<()> {
+        let _lock = self.file_lock(id).lock().await;
+        let attr = self.inner.getattr(id).await?;
+        let size = plain_size(attr.size);
+        if new_size > size {
+            self.store(id, attr, new_size, &[], nfs3::file::stable_how::FILE_SYNC).await?;
+            return Ok(());
+        }
+        if new_size < size && new_size % BLOCK_SIZE != 0 {
+            // Re-seal the new last block at its shorter length.
+            let cipher = self.open_file(id).await?;
+            let index = new_size / BLOCK_SIZE;
+            let mut plain = self.read_block(id, &cipher, index, size).await?;
+            plain.truncate((new_size % BLOCK_SIZE) as usize);
+            let sealed = cipher.seal(index, &plain);
+            self.write_stored(id, block_offset(index), &sealed, nfs3::file::stable_how::FILE_SYNC)
+                .await?;
+        }
+        if new_size < size {
+            let truncate = nfs3::sattr3 { size: Some(stored_size(new_size)), ..Default::default() };
+            self.inner.setattr(id, truncate).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Reports the plaintext size of regular files.
+fn plain_attr(mut attr: nfs3::fattr3) -> nfs3::fattr3 {
+    if matches!(attr.ftype, nfs3::ftype3::NF3REG) {
+        attr.size = plain_size(attr.size);
+    }
+    attr
+}
+
+#[async_trait]
+impl NFSFileSystem for CryptFS {
+    fn generation(&self) -> u64 {
+        self.inner.generation()
+    }
+
+    fn server_id(&self) -> nfs3::cookieverf3 {
+        self.inner.server_id()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn root_dir(&self) -> nfs3::fileid3 {
+        self.inner.root_dir()
+    }
+
+    async fn lookup(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> NFSResult<nfs3::fileid3> {
+        self.inner.lookup(dirid, &self.stored_name(dirid, filename).await?).await
+    }
+
+    async fn getattr(&self, id: nfs3::fileid3) -> NFSResult<nfs3::fattr3> {
+        Ok(plain_attr(self.inner.getattr(id).await?))
+    }
+
+    async fn setattr(
+        &self,
+        id: nfs3::fileid3,
+        mut setattr: nfs3::sattr3,
+    ) -> NFSResult<nfs3::fattr3> {
+        if let Some(size) = setattr.size.take() {
+            if matches!(self.inner.getattr(id).await?.ftype, nfs3::ftype3::NF3REG) {
+                self.resize(id, size).await?;
+            } else {
+                setattr.size = Some(size);
+            }
+        }
+        Ok(plain_attr(self.inner.setattr(id, setattr).await?))
+    }
+
+    async fn read(&self, id: nfs3::fileid3, offset: u64, count: u32) -> NFSResult<(Vec<u8>, bool)> {
+        // A write in progress would change the blocks between reading the size and them.
+        let _lock = self.file_lock(id).lock().await;
+        let attr = self.inner.getattr(id).await?;
+        if !matches!(attr.ftype, nfs3::ftype3::NF3REG) {
+            return self.inner.read(id, offset, count).await;
+        }
+        let size = plain_size(attr.size);
+        let end = offset.saturating_add(count as u64).min(size);
+        if offset >= end {
+            return Ok((Vec::new(), offset >= size));
+        }
+        let cipher = self.open_file(id).await?;
+        let first = offset / BLOCK_SIZE;
+        let last = (end - 1) / BLOCK_SIZE;
+        let stored_end = block_offset(last + 1).min(stored_size(size));
+        let sealed =
+            self.read_stored(id, block_offset(first), stored_end - block_offset(first)).await?;
+        let mut plain = Vec::with_capacity((end - first * BLOCK_SIZE) as usize);
+        for (index, block) in (first..=last).zip(sealed.chunks(SEALED_BLOCK_SIZE as usize)) {
+            plain.extend(cipher.open_block(index, block)?);
+        }
+        let skip = (offset - first * BLOCK_SIZE) as usize;
+        let take = (end - offset) as usize;
+        if plain.len() < skip + take {
+            debug!("encrypted file {} is shorter than its size", id);
+            return Err(nfs3::nfsstat3::NFS3ERR_IO);
+        }
+        Ok((plain[skip..skip + take].to_vec(), end == size))
+    }
+
+    async fn write(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+        stable: nfs3::file::stable_how,
+    ) -> NFSResult<(nfs3::fattr3, nfs3::file::stable_how, nfs3::count3)> {
+        let _lock = self.file_lock(id).lock().await;
+        let attr = self.inner.getattr(id).await?;
+        if !matches!(attr.ftype, nfs3::ftype3::NF3REG) {
+            return self.inner.write(id, offset, data, stable).await;
+        }
+        if data.is_empty() {
+            return Ok((plain_attr(attr), stable, 0));
+        }
+        let (attr, committed) = self.store(id, attr, offset, data, stable).await?;
+        Ok((plain_attr(attr), committed, data.len() as nfs3::count3))
+    }
+
+    async fn create(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        mut attr: nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        let size = attr.size.take();
+        let (id, attr) =
+            self.inner.create(dirid, &self.new_stored_name(dirid, filename).await?, attr).await?;
+        match size {
+            Some(size) if size > 0 => {
+                let resize = nfs3::sattr3 { size: Some(size), ..Default::default() };
+                Ok((id, self.setattr(id, resize).await?))
+            }
+            _ => Ok((id, plain_attr(attr))),
+        }
+    }
+
+    async fn create_exclusive(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        verifier: nfs3::createverf3,
+    ) -> NFSResult<nfs3::fileid3> {
+        let stored = self.new_stored_name(dirid, filename).await?;
+        self.inner.create_exclusive(dirid, &stored, verifier).await
+    }
+
+    async fn mkdir(
+        &self,
+        dirid: nfs3::fileid3,
+        dirname: &nfs3::filename3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        self.inner.mkdir(dirid, &self.new_stored_name(dirid, dirname).await?).await
+    }
+
+    async fn remove(&self, dirid: nfs3::fileid3, filename: &nfs3::filename3) -> NFSResult<()> {
+        self.inner.remove(dirid, &self.stored_name(dirid, filename).await?).await
+    }
+
+    async fn rename(
+        &self,
+        from_dirid: nfs3::fileid3,
+        from_filename: &nfs3::filename3,
+        to_dirid: nfs3::fileid3,
+        to_filename: &nfs3::filename3,
+    ) -> NFSResult<()> {
+        let from = self.stored_name(from_dirid, from_filename).await?;
+        let to = self.new_stored_name(to_dirid, to_filename).await?;
+        self.inner.rename(from_dirid, &from, to_dirid, &to).await
+    }
+
+    async fn readdir(
+        &self,
+        dirid: nfs3::fileid3,
+        start_after: nfs3::fileid3,
+        max_entries: usize,
+    ) -> NFSResult<vfs::ReadDirResult> {
+        self.decrypt_entries(self.inner.readdir(dirid, start_after, max_entries).await?)
+    }
+
+    async fn readdir_index(
+        &self,
+        dirid: nfs3::fileid3,
+        start_index: usize,
+        max_entries: usize,
+    ) -> NFSResult<vfs::ReadDirResult> {
+        self.decrypt_entries(self.inner.readdir_index(dirid, start_index, max_entries).await?)
+    }
+
+    async fn symlink(
+        &self,
+        dirid: nfs3::fileid3,
+        linkname: &nfs3::filename3,
+        symlink: &nfs3::nfspath3,
+        attr: &nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        let stored = self.new_stored_name(dirid, linkname).await?;
+        self.inner.symlink(dirid, &stored, symlink, attr).await
+    }
+
+    async fn readlink(&self, id: nfs3::fileid3) -> NFSResult<nfs3::nfspath3> {
+        self.inner.readlink(id).await
+    }
+
+    async fn link(
+        &self,
+        file_id: nfs3::fileid3,
+        link_dir_id: nfs3::fileid3,
+        link_name: &nfs3::filename3,
+    ) -> NFSResult<nfs3::fattr3> {
+        let stored = self.new_stored_name(link_dir_id, link_name).await?;
+        let attr = self.inner.link(file_id, link_dir_id, &stored).await?;
+        Ok(plain_attr(attr))
+    }
+
+    async fn mknod(
+        &self,
+        dir_id: nfs3::fileid3,
+        name: &nfs3::filename3,
+        ftype: nfs3::ftype3,
+        specdata: nfs3::specdata3,
+        attrs: &nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        let stored = self.new_stored_name(dir_id, name).await?;
+        self.inner.mknod(dir_id, &stored, ftype, specdata, attrs).await
+    }
+
+    async fn commit(
+        &self,
+        file_id: nfs3::fileid3,
+        _offset: u64,
+        _count: u32,
+    ) -> NFSResult<nfs3::fattr3> {
+        // Blocks do not map to the client's ranges, so commit everything.
+        Ok(plain_attr(self.inner.commit(file_id, 0, 0).await?))
+    }
+
+    async fn check_access(
+        &self,
+        id: nfs3::fileid3,
+        auth: &auth_unix,
+        access: u32,
+    ) -> NFSResult<u32> {
+        self.inner.check_access(id, auth, access).await
+    }
+
+    async fn fsinfo(&self, root_fileid: nfs3::fileid3) -> NFSResult<nfs3::fs::fsinfo3> {
+        self.inner.fsinfo(root_fileid).await
+    }
+
+    async fn fsstat(&self, root_fileid: nfs3::fileid3) -> NFSResult<nfs3::fs::FSSTAT3resok> {
+        self.inner.fsstat(root_fileid).await
+    }
+
+    fn fsinfo_maxfilesize(&self) -> nfs3::size3 {
+        plain_size(self.inner.fsinfo_maxfilesize())
+    }
+
+    fn fsinfo_properties(&self) -> u32 {
+        self.inner.fsinfo_properties()
+    }
+
+    fn pathconf_linkmax(&self) -> u32 {
+        self.inner.pathconf_linkmax()
+    }
+
+    fn pathconf_name_max(&self) -> u32 {
+        let name_max = self.inner.pathconf_name_max();
+        if self.encrypt_names {
+            NameCipher::max_name_len(name_max)
+        } else {
+            name_max
+        }
+    }
+
+    fn id_to_fh(&self, id: nfs3::fileid3) -> nfs3::nfs_fh3 {
+        self.inner.id_to_fh(id)
+    }
+
+    fn fh_to_id(&self, id: &nfs3::nfs_fh3) -> NFSResult<nfs3::fileid3> {
+        self.inner.fh_to_id(id)
+    }
+}
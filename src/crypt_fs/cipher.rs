@@ -0,0 +1,232 @@
+//! Keys, the encrypted file layout and name encryption.
+//!
+//! An encrypted file starts with a header, followed by the blocks of the file in order:
+//!
+//! ```text
+//! header: magic (8) | key id (4, LE) | salt (20)
+//! block:  nonce (24) | ciphertext (up to BLOCK_SIZE) | tag (16)
+//! ```
+//!
+//! Every block but the last holds `BLOCK_SIZE` bytes of plaintext, so the plaintext
+//! size follows from the size of the stored file. Blocks are sealed with
+//! XChaCha20-Poly1305 under a key derived from the master key and the file's salt, with
+//! a random nonce and the block index as associated data, so blocks cannot be moved
+//! within a file or between files without failing authentication. An empty stored
+//! file is an empty plaintext file; the header is written with the first data.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tracing::warn;
+
+use crate::xdr::nfs3;
+
+type NFSResult<T> = Result<T, nfs3::nfsstat3>;
+
+/// A 256-bit master key
+pub type Key = [u8; 32];
+
+/// Supplies the master keys of a [`CryptFS`](super::CryptFS)
+///
+/// Every file records the id of the key it was encrypted with, so keys can be rotated:
+/// new files use [`current_key_id`](Self::current_key_id), while existing files stay
+/// readable as long as their key is still provided.
+pub trait KeyProvider: Send + Sync {
+    /// Returns the id of the key to encrypt new files and names with
+    fn current_key_id(&self) -> u32;
+
+    /// Returns the key with the given id, or `None` if it is not available
+    fn key(&self, id: u32) -> Option<Key>;
+
+    /// Returns the ids of every key that names may have been encrypted with
+    ///
+    /// Looking up a name tries each of these keys after the current one, so a provider
+    /// that rotates keys while name encryption is on must list its earlier keys here.
+    fn key_ids(&self) -> Vec<u32> {
+        vec![self.current_key_id()]
+    }
+}
+
+/// A [`KeyProvider`] with a single key, id 0
+pub struct StaticKey(pub Key);
+
+impl KeyProvider for StaticKey {
+    fn current_key_id(&self) -> u32 {
+        0
+    }
+
+    fn key(&self, id: u32) -> Option<Key> {
+        (id == 0).then_some(self.0)
+    }
+}
+
+/// Plaintext bytes per block.
+pub const BLOCK_SIZE: u64 = 4096;
+const MAGIC: &[u8; 8] = b"FERNCRY1";
+const SALT_LEN: usize = 20;
+pub const HEADER_LEN: u64 = (MAGIC.len() + 4 + SALT_LEN) as u64;
+const NONCE_LEN: usize = 24;
+const TAG_LEN: usize = 16;
+/// Bytes added to each block by encryption.
+pub const BLOCK_OVERHEAD: u64 = (NONCE_LEN + TAG_LEN) as u64;
+/// Size of a full block as stored.
+pub const SEALED_BLOCK_SIZE: u64 = BLOCK_SIZE + BLOCK_OVERHEAD;
+
+/// Returns the plaintext size of a stored file of `stored` bytes.
+pub fn plain_size(stored: u64) -> u64 {
+    let Some(body) = stored.checked_sub(HEADER_LEN) else {
+        return 0;
+    };
+    let tail = body % SEALED_BLOCK_SIZE;
+    body / SEALED_BLOCK_SIZE * BLOCK_SIZE + tail.saturating_sub(BLOCK_OVERHEAD)
+}
+
+/// Returns the stored size of a file of `plain` bytes of plaintext.
+pub fn stored_size(plain: u64) -> u64 {
+    if plain == 0 {
+        return 0;
+    }
+    let tail = plain % BLOCK_SIZE;
+    let tail = if tail == 0 { 0 } else { tail + BLOCK_OVERHEAD };
+    HEADER_LEN + plain / BLOCK_SIZE * SEALED_BLOCK_SIZE + tail
+}
+
+/// Returns the offset of a block in the stored file.
+pub fn block_offset(index: u64) -> u64 {
+    HEADER_LEN + index * SEALED_BLOCK_SIZE
+}
+
+fn derive(master: &Key, label: &[u8]) -> Key {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(master).unwrap();
+    mac.update(label);
+    mac.finalize().into_bytes().into()
+}
+
+fn master_key(keys: &dyn KeyProvider, id: u32) -> NFSResult<Key> {
+    keys.key(id).ok_or_else(|| {
+        warn!("encryption key {} is not available", id);
+        nfs3::nfsstat3::NFS3ERR_IO
+    })
+}
+
+/// The cipher of one file's blocks
+pub struct FileCipher {
+    aead: XChaCha20Poly1305,
+}
+
+impl FileCipher {
+    fn new(master: &Key, salt: &[u8]) -> Self {
+        let key = derive(master, &[&b"fernfs file "[..], salt].concat());
+        Self { aead: XChaCha20Poly1305::new(&key.into()) }
+    }
+
+    /// Creates the header and cipher of a new file.
+    pub fn create(keys: &dyn KeyProvider) -> NFSResult<(Vec<u8>, Self)> {
+        let id = keys.current_key_id();
+        let master = master_key(keys, id)?;
+        let mut salt = [0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let header = [&MAGIC[..], &id.to_le_bytes(), &salt].concat();
+        Ok((header, Self::new(&master, &salt)))
+    }
+
+    /// Returns the cipher of an existing file from its header.
+    pub fn open(keys: &dyn KeyProvider, header: &[u8]) -> NFSResult<Self> {
+        if header.len() != HEADER_LEN as usize || !header.starts_with(MAGIC) {
+            warn!("missing or damaged encryption header");
+            return Err(nfs3::nfsstat3::NFS3ERR_IO);
+        }
+        let id = u32::from_le_bytes(header[MAGIC.len()..MAGIC.len() + 4].try_into().unwrap());
+        let master = master_key(keys, id)?;
+        Ok(Self::new(&master, &header[MAGIC.len() + 4..]))
+    }
+
+    /// Encrypts the block at `index`.
+    pub fn seal(&self, index: u64, plain: &[u8]) -> Vec<u8> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let aad = index.to_le_bytes();
+        let sealed = self.aead.encrypt(&nonce, Payload { msg: plain, aad: &aad }).unwrap();
+        [nonce.as_slice(), &sealed].concat()
+    }
+
+    /// Decrypts and authenticates the block at `index`.
+    pub fn open_block(&self, index: u64, sealed: &[u8]) -> NFSResult<Vec<u8>> {
+        if sealed.len() < BLOCK_OVERHEAD as usize {
+            warn!("encrypted block {} is truncated", index);
+            return Err(nfs3::nfsstat3::NFS3ERR_IO);
+        }
+        let (nonce, msg) = sealed.split_at(NONCE_LEN);
+        let aad = index.to_le_bytes();
+        self.aead.decrypt(XNonce::from_slice(nonce), Payload { msg, aad: &aad }).map_err(|_| {
+            warn!("encrypted block {} failed authentication", index);
+            nfs3::nfsstat3::NFS3ERR_IO
+        })
+    }
+}
+
+/// Deterministic encryption of file names
+///
+/// The nonce is a MAC of the name, so a name always encrypts to the same stored name
+/// under a given key and lookups need no directory scan. Equal names therefore produce
+/// equal stored names, which reveals which files share a name but nothing about the
+/// names themselves. Stored names start with the id of their key, so names keep
+/// decrypting after the current key changes.
+pub struct NameCipher {
+    id: u32,
+    aead: XChaCha20Poly1305,
+    mac_key: Key,
+}
+
+impl NameCipher {
+    /// Creates the cipher for the key with the given id.
+    pub fn new(keys: &dyn KeyProvider, id: u32) -> NFSResult<Self> {
+        let master = master_key(keys, id)?;
+        let key = derive(&master, b"fernfs name encryption");
+        Ok(Self {
+            id,
+            aead: XChaCha20Poly1305::new(&key.into()),
+            mac_key: derive(&master, b"fernfs name nonce"),
+        })
+    }
+
+    /// Returns the id of the key a stored name was encrypted with, if it is one of ours.
+    pub fn key_id(stored: &[u8]) -> Option<u32> {
+        let sealed = URL_SAFE_NO_PAD.decode(stored).ok()?;
+        if sealed.len() < 4 + BLOCK_OVERHEAD as usize {
+            return None;
+        }
+        Some(u32::from_le_bytes(sealed[..4].try_into().unwrap()))
+    }
+
+    /// Returns the stored form of `name`.
+    pub fn encrypt(&self, name: &[u8]) -> Vec<u8> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.mac_key).unwrap();
+        mac.update(name);
+        let digest = mac.finalize().into_bytes();
+        let nonce = XNonce::from_slice(&digest[..NONCE_LEN]);
+        let sealed = self.aead.encrypt(nonce, name).unwrap();
+        let stored = [&self.id.to_le_bytes()[..], nonce.as_slice(), &sealed].concat();
+        URL_SAFE_NO_PAD.encode(stored).into_bytes()
+    }
+
+    /// Recovers a name from its stored form, or `None` if it was not encrypted with
+    /// this cipher's key.
+    pub fn decrypt(&self, stored: &[u8]) -> Option<Vec<u8>> {
+        let sealed = URL_SAFE_NO_PAD.decode(stored).ok()?;
+        if sealed.len() < 4 + BLOCK_OVERHEAD as usize || sealed[..4] != self.id.to_le_bytes() {
+            return None;
+        }
+        let (nonce, msg) = sealed[4..].split_at(NONCE_LEN);
+        self.aead.decrypt(XNonce::from_slice(nonce), msg).ok()
+    }
+
+    /// Returns the longest name whose stored form fits in `stored_max` bytes.
+    pub fn max_name_len(stored_max: u32) -> u32 {
+        // Base64 without padding stores 3 bytes in 4 characters.
+        (stored_max / 4 * 3).saturating_sub(4 + BLOCK_OVERHEAD as u32)
+    }
+}
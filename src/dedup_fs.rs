@@ -0,0 +1,1004 @@
+//! A deduplicating file system stored in a local directory.
+//!
+//! [`DedupFS`] cuts file contents into content-defined chunks and stores each distinct
+//! chunk once, as a file named by its SHA-256 hash. Files with the same contents, and
+//! files that share runs of data even at different offsets, take the space of one copy.
+//! The tree itself is kept in memory and persisted in an index next to the chunks:
+//!
+//! ```text
+//! <dir>/index      snapshot of the tree and the chunk list of every file
+//! <dir>/journal    changes since the snapshot, one checksummed frame per operation
+//! <dir>/chunks/    the chunks, in subdirectories by hash prefix
+//! ```
+//!
+//! ```ignore
+//! use fernfs::dedup_fs::DedupFS;
+//!
+//! let fs = DedupFS::open("/var/lib/artifact-cache")?;
+//! // ... serve it, and now and then:
+//! let freed = fs.collect_garbage().await?;
+//! ```
+//!
+//! `UNSTABLE` writes are buffered in memory and cut into chunks when the client sends
+//! COMMIT, so a file written in many small pieces is chunked once. `FILE_SYNC` and
+//! `DATA_SYNC` writes, COMMIT and every change to the tree are on stable storage before
+//! the reply is sent. Buffered writes are lost if the server stops, which clients
+//! detect from the write verifier changing and answer by sending the data again.
+//!
+//! Chunks are reference counted, but a chunk that is no longer used stays on disk
+//! until [`DedupFS::collect_garbage`] runs; that also removes chunks left behind by an
+//! interrupted write. A store must only be opened by one `DedupFS` at a time.
+
+use std::collections::{BTreeSet, HashMap};
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use indexmap::IndexMap;
+use tokio::sync::RwLock;
+use tracing::{debug, warn};
+
+use crate::vfs;
+use crate::xdr::nfs3;
+
+mod chunker;
+mod index;
+mod inode;
+mod options;
+mod store;
+
+use chunker::Chunker;
+use index::{EntryChange, Index, Tree};
+use inode::{ChunkRef, Contents, FileData, Inode, BLOCK_SIZE};
+pub use options::DedupFSBuilder;
+use store::{ChunkStore, Hash};
+
+/// File ID of the root directory.
+const ROOT_ID: nfs3::fileid3 = 1;
+/// Longest accepted file name, in bytes.
+const NAME_MAX: usize = 255;
+/// Buffered writes per file beyond which they are chunked without waiting for COMMIT.
+const DIRTY_LIMIT: u64 = 16 * 1024 * 1024;
+
+type NFSResult<T> = Result<T, nfs3::nfsstat3>;
+
+fn now() -> nfs3::nfstime3 {
+    let since_epoch = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    nfs3::nfstime3 { seconds: since_epoch.as_secs() as u32, nseconds: since_epoch.subsec_nanos() }
+}
+
+fn io_error(err: io::Error) -> nfs3::nfsstat3 {
+    warn!("dedup store error: {}", err);
+    nfs3::nfsstat3::NFS3ERR_IO
+}
+
+/// Rejects names that cannot be created in a directory.
+fn check_new_name(name: &[u8]) -> NFSResult<()> {
+    if name.is_empty() || name.contains(&b'/') || name.contains(&0) {
+        return Err(nfs3::nfsstat3::NFS3ERR_INVAL);
+    }
+    if name.len() > NAME_MAX {
+        return Err(nfs3::nfsstat3::NFS3ERR_NAMETOOLONG);
+    }
+    if name == b"." || name == b".." {
+        return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
+    }
+    Ok(())
+}
+
+/// Rejects `.` and `..` as the object of REMOVE or RENAME.
+fn check_existing_name(name: &[u8]) -> NFSResult<()> {
+    if name == b"." || name == b".." {
+        return Err(nfs3::nfsstat3::NFS3ERR_INVAL);
+    }
+    Ok(())
+}
+
+/// Cuts a file from chunk `start` up to `size` into new chunks, reading its contents
+/// with `read`, and returns its new chunk list. Once a new chunk ends where an old one
+/// did, at or past `unchanged_from`, the old chunks from there on are kept.
+#[allow(clippy::too_many_arguments)]
+fn rechunk(
+    store: &ChunkStore,
+    chunker: &Chunker,
+    data: &FileData,
+    start: usize,
+    size: u64,
+    unchanged_from: u64,
+    read: impl Fn(u64, u64) -> io::Result<Vec<u8>>,
+) -> io::Result<Vec<ChunkRef>> {
+    let mut chunks = data.chunks()[..start].to_vec();
+    let mut pos = data.chunk_start(start);
+    let mut read_pos = pos;
+    let mut buf = Vec::new();
+    while pos < size {
+        if buf.len() < chunker.max_len() && read_pos < size {
+            let len = (4 * chunker.max_len() as u64).min(size - read_pos);
+            buf.extend(read(read_pos, len)?);
+            read_pos += len;
+            continue;
+        }
+        let len = chunker.cut(&buf);
+        let hash = store::hash(&buf[..len]);
+        store.put(&hash, &buf[..len])?;
+        chunks.push(ChunkRef { hash, len: len as u32 });
+        buf.drain(..len);
+        pos += len as u64;
+        if pos >= unchanged_from && size == data.stored_size() {
+            if let Some(index) = data.chunk_ending_at(pos) {
+                chunks.extend_from_slice(&data.chunks()[index + 1..]);
+                break;
+            }
+        }
+    }
+    Ok(chunks)
+}
+
+/// How often a chunk is referenced by the files of the tree
+#[derive(Debug, Clone, Copy)]
+struct ChunkUse {
+    count: u64,
+    len: u32,
+}
+
+/// Space used by a [`DedupFS`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DedupStats {
+    /// Total size of all files, counting each hard-linked file once
+    pub logical_bytes: u64,
+    /// Total size of the distinct chunks those files are made of
+    pub stored_bytes: u64,
+    /// Number of distinct chunks
+    pub chunks: u64,
+}
+
+/// What [`DedupFS::collect_garbage`] removed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GarbageStats {
+    pub chunks: u64,
+    pub bytes: u64,
+}
+
+/// The mutable contents of a [`DedupFS`].
+#[derive(Debug)]
+struct State {
+    tree: Tree,
+    index: Index,
+    refs: HashMap<Hash, ChunkUse>,
+    /// Inodes changed by the current operation, to be journaled
+    touched: BTreeSet<nfs3::fileid3>,
+    /// Directory entry changes of the current operation, to be journaled
+    entry_changes: Vec<EntryChange>,
+}
+
+impl State {
+    fn inode(&self, id: nfs3::fileid3) -> NFSResult<&Inode> {
+        self.tree.inodes.get(&id).ok_or(nfs3::nfsstat3::NFS3ERR_STALE)
+    }
+
+    fn inode_mut(&mut self, id: nfs3::fileid3) -> NFSResult<&mut Inode> {
+        self.tree.inodes.get_mut(&id).ok_or(nfs3::nfsstat3::NFS3ERR_STALE)
+    }
+
+    /// Returns an inode to modify, recording that it changed.
+    fn changed(&mut self, id: nfs3::fileid3) -> NFSResult<&mut Inode> {
+        let inode = self.tree.inodes.get_mut(&id).ok_or(nfs3::nfsstat3::NFS3ERR_STALE)?;
+        self.touched.insert(id);
+        Ok(inode)
+    }
+
+    fn lookup(&self, dirid: nfs3::fileid3, name: &[u8]) -> NFSResult<nfs3::fileid3> {
+        let dir = self.inode(dirid)?;
+        let entries = dir.entries()?;
+        match name {
+            b"." => Ok(dirid),
+            b".." => Ok(dir.parent),
+            _ => entries.get(name).copied().ok_or(nfs3::nfsstat3::NFS3ERR_NOENT),
+        }
+    }
+
+    fn set_entry(&mut self, dirid: nfs3::fileid3, name: &[u8], id: nfs3::fileid3) -> NFSResult<()> {
+        self.inode_mut(dirid)?.entries_mut()?.insert(name.to_vec(), id);
+        self.entry_changes.push(EntryChange::Set(dirid, name.to_vec(), id));
+        Ok(())
+    }
+
+    fn remove_entry(&mut self, dirid: nfs3::fileid3, name: &[u8]) -> NFSResult<()> {
+        self.inode_mut(dirid)?.entries_mut()?.shift_remove(name);
+        self.entry_changes.push(EntryChange::Remove(dirid, name.to_vec()));
+        Ok(())
+    }
+
+    /// Records a change to a directory's entries.
+    fn touch_dir(&mut self, dirid: nfs3::fileid3, time: nfs3::nfstime3) {
+        if let Ok(dir) = self.changed(dirid) {
+            dir.attr.mtime = time;
+            dir.attr.ctime = time;
+        }
+    }
+
+    fn add_refs(&mut self, chunks: &[ChunkRef]) {
+        for chunk in chunks {
+            self.refs.entry(chunk.hash).or_insert(ChunkUse { count: 0, len: chunk.len }).count += 1;
+        }
+    }
+
+    fn drop_refs(&mut self, chunks: &[ChunkRef]) {
+        for chunk in chunks {
+            if let Some(used) = self.refs.get_mut(&chunk.hash) {
+                used.count -= 1;
+                if used.count == 0 {
+                    self.refs.remove(&chunk.hash);
+                }
+            }
+        }
+    }
+
+    /// Writes the changes of the current operation to the journal.
+    fn save(&mut self) -> NFSResult<()> {
+        if self.touched.is_empty() && self.entry_changes.is_empty() {
+            return Ok(());
+        }
+        let touched = std::mem::take(&mut self.touched);
+        let entry_changes = std::mem::take(&mut self.entry_changes);
+        let frame = index::encode_frame(&self.tree, &touched, &entry_changes).map_err(io_error)?;
+        self.index.append(&frame).map_err(io_error)?;
+        if self.index.should_compact() {
+            debug!("compacting dedup index");
+            self.index.compact(&self.tree).map_err(io_error)?;
+        }
+        Ok(())
+    }
+
+    /// Adds a new object named `name` to `dirid`.
+    fn insert(
+        &mut self,
+        dirid: nfs3::fileid3,
+        name: &[u8],
+        mut attr: nfs3::fattr3,
+        contents: Contents,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        check_new_name(name)?;
+        if self.inode(dirid)?.entries()?.contains_key(name) {
+            return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
+        }
+        let id = self.tree.next_id;
+        self.tree.next_id += 1;
+        attr.fileid = id;
+        let is_dir = matches!(contents, Contents::Directory(_));
+        let time = attr.ctime;
+        self.tree.inodes.insert(id, Inode { attr, parent: dirid, verifier: None, contents });
+        self.touched.insert(id);
+
+        self.set_entry(dirid, name, id)?;
+        if is_dir {
+            self.changed(dirid)?.attr.nlink += 1;
+        }
+        self.touch_dir(dirid, time);
+        Ok((id, attr))
+    }
+
+    /// Drops one link to `id`, freeing the inode once nothing refers to it.
+    fn unlink(&mut self, id: nfs3::fileid3, time: nfs3::nfstime3) {
+        let Ok(inode) = self.changed(id) else {
+            return;
+        };
+        if inode.is_dir() {
+            let parent = inode.parent;
+            self.tree.inodes.remove(&id);
+            if let Ok(dir) = self.changed(parent) {
+                dir.attr.nlink -= 1;
+            }
+            return;
+        }
+        inode.attr.nlink -= 1;
+        inode.attr.ctime = time;
+        if inode.attr.nlink == 0 {
+            if let Some(Inode { contents: Contents::File(data), .. }) = self.tree.inodes.remove(&id)
+            {
+                self.drop_refs(data.chunks());
+            }
+        }
+    }
+
+    /// Returns true if `id` is `ancestor` or lies somewhere below it.
+    fn is_within(&self, mut id: nfs3::fileid3, ancestor: nfs3::fileid3) -> bool {
+        loop {
+            if id == ancestor {
+                return true;
+            }
+            match self.tree.inodes.get(&id) {
+                Some(inode) if id != ROOT_ID => id = inode.parent,
+                _ => return false,
+            }
+        }
+    }
+
+    fn file(&self, id: nfs3::fileid3) -> NFSResult<(&nfs3::fattr3, &FileData)> {
+        let inode = self.inode(id)?;
+        match &inode.contents {
+            Contents::File(data) => Ok((&inode.attr, data)),
+            Contents::Directory(_) => Err(nfs3::nfsstat3::NFS3ERR_ISDIR),
+            _ => Err(nfs3::nfsstat3::NFS3ERR_INVAL),
+        }
+    }
+
+    /// Gives a file a new chunk list, dropping its buffered writes.
+    fn replace_chunks(&mut self, id: nfs3::fileid3, chunks: Vec<ChunkRef>) -> NFSResult<()> {
+        self.add_refs(&chunks);
+        let inode = self.changed(id)?;
+        let Contents::File(data) = &mut inode.contents else {
+            return Err(nfs3::nfsstat3::NFS3ERR_INVAL);
+        };
+        let old = std::mem::take(data);
+        data.set_chunks(chunks);
+        inode.attr.size = data.stored_size();
+        inode.attr.used = inode.attr.size;
+        self.drop_refs(old.chunks());
+        Ok(())
+    }
+
+    /// Cuts the buffered writes of a file into chunks.
+    fn flush(&mut self, store: &ChunkStore, chunker: &Chunker, id: nfs3::fileid3) -> NFSResult<()> {
+        let (attr, data) = self.file(id)?;
+        let Some(dirty) = &data.dirty else {
+            return Ok(());
+        };
+        let mut start = data.chunk_at(dirty.from);
+        // The last chunk was cut by the end of the file rather than by its contents.
+        if start == data.chunks().len() {
+            start = start.saturating_sub(1);
+        }
+        let chunks = rechunk(store, chunker, data, start, attr.size, dirty.to, |offset, len| {
+            data.read(store, offset, len)
+        })
+        .map_err(io_error)?;
+        self.replace_chunks(id, chunks)
+    }
+
+    /// Changes the size of a regular file.
+    fn resize(
+        &mut self,
+        store: &ChunkStore,
+        chunker: &Chunker,
+        id: nfs3::fileid3,
+        size: u64,
+    ) -> NFSResult<()> {
+        self.flush(store, chunker, id)?;
+        let (_, data) = self.file(id)?;
+        let stored = data.stored_size();
+        if size == stored {
+            return Ok(());
+        }
+        let start =
+            if size < stored { data.chunk_at(size) } else { data.chunks().len().saturating_sub(1) };
+        let chunks = rechunk(store, chunker, data, start, size, u64::MAX, |offset, len| {
+            data.read_stored(store, offset, len)
+        })
+        .map_err(io_error)?;
+        self.replace_chunks(id, chunks)
+    }
+
+    fn setattr(
+        &mut self,
+        store: &ChunkStore,
+        chunker: &Chunker,
+        id: nfs3::fileid3,
+        setattr: &nfs3::sattr3,
+    ) -> NFSResult<nfs3::fattr3> {
+        let time = now();
+        if let Some(size) = setattr.size {
+            if self.inode(id)?.attr.size != size {
+                self.resize(store, chunker, id, size)?;
+                self.changed(id)?.attr.mtime = time;
+            }
+        }
+        let inode = self.changed(id)?;
+        if let Some(mode) = setattr.mode {
+            inode.attr.mode = mode & 0o7777;
+        }
+        if let Some(uid) = setattr.uid {
+            inode.attr.uid = uid;
+        }
+        if let Some(gid) = setattr.gid {
+            inode.attr.gid = gid;
+        }
+        match setattr.atime {
+            nfs3::set_atime::DONT_CHANGE => {}
+            nfs3::set_atime::SET_TO_SERVER_TIME => inode.attr.atime = time,
+            nfs3::set_atime::SET_TO_CLIENT_TIME(t) => inode.attr.atime = t,
+        }
+        match setattr.mtime {
+            nfs3::set_mtime::DONT_CHANGE => {}
+            nfs3::set_mtime::SET_TO_SERVER_TIME => inode.attr.mtime = time,
+            nfs3::set_mtime::SET_TO_CLIENT_TIME(t) => inode.attr.mtime = t,
+        }
+        inode.attr.ctime = time;
+        Ok(inode.attr)
+    }
+}
+
+/// A deduplicating [`NFSFileSystem`](vfs::NFSFileSystem) stored in a local directory.
+///
+/// See the [module documentation](self) for an overview.
+#[derive(Debug)]
+pub struct DedupFS {
+    /// Operations that read chunks or write the journal hold the lock on a blocking
+    /// thread, so waiting for the disk never stalls the async runtime.
+    state: Arc<RwLock<State>>,
+    store: Arc<ChunkStore>,
+    chunker: Chunker,
+    generation: u64,
+    /// Default owner of new objects when the client does not set one
+    uid: nfs3::uid3,
+    gid: nfs3::gid3,
+}
+
+impl DedupFS {
+    /// Opens the store in `dir` with the default settings, creating it if it does not
+    /// exist.
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        DedupFSBuilder::new().open(dir)
+    }
+
+    /// Starts configuring a store.
+    pub fn builder() -> DedupFSBuilder {
+        DedupFSBuilder::new()
+    }
+
+    fn open_with(options: DedupFSBuilder, dir: PathBuf) -> io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        let store = ChunkStore::open(dir.join("chunks"))?;
+        let (index, tree) = match Index::load(&dir)? {
+            Some(loaded) => loaded,
+            None => {
+                let tree = Self::empty_tree(&options);
+                (Index::create(&dir, &tree)?, tree)
+            }
+        };
+        let avg = tree.avg_chunk_size;
+        if !avg.is_power_of_two() || !(1024..=1024 * 1024).contains(&avg) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid dedup chunk size"));
+        }
+        let mut state = State {
+            tree,
+            index,
+            refs: HashMap::new(),
+            touched: BTreeSet::new(),
+            entry_changes: Vec::new(),
+        };
+        let files: Vec<_> = state
+            .tree
+            .inodes
+            .values()
+            .filter_map(|inode| match &inode.contents {
+                Contents::File(data) => Some(data.chunks().to_vec()),
+                _ => None,
+            })
+            .collect();
+        for chunks in files {
+            state.add_refs(&chunks);
+        }
+        let generation = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        Ok(Self {
+            state: Arc::new(RwLock::new(state)),
+            store: Arc::new(store),
+            chunker: Chunker::new(avg),
+            generation,
+            uid: options.uid,
+            gid: options.gid,
+        })
+    }
+
+    fn empty_tree(options: &DedupFSBuilder) -> Tree {
+        let time = now();
+        let root = Inode {
+            attr: nfs3::fattr3 {
+                ftype: nfs3::ftype3::NF3DIR,
+                mode: options.root_mode,
+                nlink: 2,
+                uid: options.uid,
+                gid: options.gid,
+                size: BLOCK_SIZE,
+                fileid: ROOT_ID,
+                atime: time,
+                mtime: time,
+                ctime: time,
+                ..Default::default()
+            },
+            parent: ROOT_ID,
+            verifier: None,
+            contents: Contents::Directory(IndexMap::new()),
+        };
+        Tree {
+            inodes: HashMap::from([(ROOT_ID, root)]),
+            next_id: ROOT_ID + 1,
+            avg_chunk_size: options.avg_chunk_size,
+        }
+    }
+
+    /// Returns how much data the files hold and how much space their chunks take.
+    pub async fn stats(&self) -> DedupStats {
+        let state = self.state.read().await;
+        let logical_bytes = state
+            .tree
+            .inodes
+            .values()
+            .map(|inode| match &inode.contents {
+                Contents::File(data) => data.stored_size(),
+                _ => 0,
+            })
+            .sum();
+        DedupStats {
+            logical_bytes,
+            stored_bytes: state.refs.values().map(|used| used.len as u64).sum(),
+            chunks: state.refs.len() as u64,
+        }
+    }
+
+    /// Cuts all buffered writes into chunks and puts them on stable storage, as a
+    /// COMMIT of every file would.
+    pub async fn flush(&self) -> io::Result<()> {
+        self.update(|state, store, chunker| {
+            let dirty: Vec<_> = state
+                .tree
+                .inodes
+                .iter()
+                .filter(|(_, inode)| matches!(&inode.contents, Contents::File(data) if data.dirty.is_some()))
+                .map(|(id, _)| *id)
+                .collect();
+            for id in dirty {
+                state.flush(store, chunker, id)?;
+            }
+            state.save()
+        })
+        .await
+        .map_err(|_| io::Error::other("flush failed"))
+    }
+
+    /// Deletes every stored chunk that no file refers to.
+    ///
+    /// Operations that change the tree wait while the chunk directory is scanned.
+    pub async fn collect_garbage(&self) -> io::Result<GarbageStats> {
+        let state = self.state.clone().write_owned().await;
+        let store = self.store.clone();
+        let collect = move || {
+            let mut freed = GarbageStats::default();
+            for hash in store.list()? {
+                if !state.refs.contains_key(&hash) {
+                    freed.bytes += store.remove(&hash)?;
+                    freed.chunks += 1;
+                }
+            }
+            debug!("removed {} unused chunks of {} bytes", freed.chunks, freed.bytes);
+            Ok(freed)
+        };
+        tokio::task::spawn_blocking(collect).await.map_err(io::Error::other)?
+    }
+
+    /// Runs `op` with the state locked for writing, off the async runtime.
+    async fn update<T: Send + 'static>(
+        &self,
+        op: impl FnOnce(&mut State, &ChunkStore, &Chunker) -> NFSResult<T> + Send + 'static,
+    ) -> NFSResult<T> {
+        let mut state = self.state.clone().write_owned().await;
+        let (store, chunker) = (self.store.clone(), self.chunker);
+        tokio::task::spawn_blocking(move || op(&mut state, &store, &chunker))
+            .await
+            .map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?
+    }
+
+    /// Attributes for a new object, before `sattr3` overrides are applied.
+    fn new_attr(
+        &self,
+        ftype: nfs3::ftype3,
+        mode: nfs3::mode3,
+        attr: &nfs3::sattr3,
+    ) -> nfs3::fattr3 {
+        let time = now();
+        let mut new = nfs3::fattr3 {
+            ftype,
+            mode: attr.mode.unwrap_or(mode) & 0o7777,
+            nlink: if matches!(ftype, nfs3::ftype3::NF3DIR) { 2 } else { 1 },
+            uid: attr.uid.unwrap_or(self.uid),
+            gid: attr.gid.unwrap_or(self.gid),
+            atime: time,
+            mtime: time,
+            ctime: time,
+            ..Default::default()
+        };
+        if let nfs3::set_atime::SET_TO_CLIENT_TIME(t) = attr.atime {
+            new.atime = t;
+        }
+        if let nfs3::set_mtime::SET_TO_CLIENT_TIME(t) = attr.mtime {
+            new.mtime = t;
+        }
+        new
+    }
+}
+
+#[async_trait]
+impl vfs::NFSFileSystem for DedupFS {
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn capabilities(&self) -> vfs::Capabilities {
+        vfs::Capabilities::ReadWrite
+    }
+
+    fn root_dir(&self) -> nfs3::fileid3 {
+        ROOT_ID
+    }
+
+    async fn lookup(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> NFSResult<nfs3::fileid3> {
+        self.state.read().await.lookup(dirid, filename)
+    }
+
+    async fn getattr(&self, id: nfs3::fileid3) -> NFSResult<nfs3::fattr3> {
+        Ok(self.state.read().await.inode(id)?.attr)
+    }
+
+    async fn setattr(&self, id: nfs3::fileid3, setattr: nfs3::sattr3) -> NFSResult<nfs3::fattr3> {
+        self.update(move |state, store, chunker| {
+            let result = state.setattr(store, chunker, id, &setattr);
+            state.save()?;
+            result
+        })
+        .await
+    }
+
+    async fn read(&self, id: nfs3::fileid3, offset: u64, count: u32) -> NFSResult<(Vec<u8>, bool)> {
+        let state = self.state.clone().read_owned().await;
+        let store = self.store.clone();
+        let read = move || {
+            let (attr, data) = state.file(id)?;
+            let end = attr.size.min(offset.saturating_add(count as u64));
+            if offset >= end {
+                return Ok((Vec::new(), true));
+            }
+            let bytes = data.read(&store, offset, end - offset).map_err(io_error)?;
+            Ok((bytes, end == attr.size))
+        };
+        tokio::task::spawn_blocking(read).await.map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?
+    }
+
+    async fn write(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+        stable: nfs3::file::stable_how,
+    ) -> NFSResult<(nfs3::fattr3, nfs3::file::stable_how, nfs3::count3)> {
+        let end = offset.checked_add(data.len() as u64).ok_or(nfs3::nfsstat3::NFS3ERR_FBIG)?;
+        let data = data.to_vec();
+        self.update(move |state, store, chunker| {
+            state.file(id)?;
+            let inode = state.inode_mut(id)?;
+            let Contents::File(contents) = &mut inode.contents else {
+                return Err(nfs3::nfsstat3::NFS3ERR_INVAL);
+            };
+            let buffered = contents.write(store, offset, &data).map_err(io_error)?;
+            let time = now();
+            inode.attr.size = inode.attr.size.max(end);
+            inode.attr.used = inode.attr.size;
+            inode.attr.mtime = time;
+            inode.attr.ctime = time;
+            let sync = !matches!(stable, nfs3::file::stable_how::UNSTABLE);
+            if sync || buffered > DIRTY_LIMIT {
+                state.flush(store, chunker, id)?;
+                state.save()?;
+            }
+            let committed = if sync {
+                nfs3::file::stable_how::FILE_SYNC
+            } else {
+                nfs3::file::stable_how::UNSTABLE
+            };
+            Ok((state.inode(id)?.attr, committed, data.len() as nfs3::count3))
+        })
+        .await
+    }
+
+    async fn create(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        attr: nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        let new_attr = self.new_attr(nfs3::ftype3::NF3REG, 0o644, &attr);
+        let filename = filename.clone();
+        self.update(move |state, store, chunker| {
+            let file = Contents::File(FileData::default());
+            let created = state.insert(dirid, &filename, new_attr, file);
+            let result = match (created, attr.size) {
+                (Ok((id, _)), Some(size)) if size > 0 => state
+                    .resize(store, chunker, id, size)
+                    .and_then(|()| Ok((id, state.inode(id)?.attr))),
+                (result, _) => result,
+            };
+            state.save()?;
+            result
+        })
+        .await
+    }
+
+    async fn create_exclusive(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        verifier: nfs3::createverf3,
+    ) -> NFSResult<nfs3::fileid3> {
+        let attr = self.new_attr(nfs3::ftype3::NF3REG, 0o644, &nfs3::sattr3::default());
+        let filename = filename.clone();
+        self.update(move |state, _, _| {
+            if let Ok(existing) = state.lookup(dirid, &filename) {
+                // A retransmitted request finds the file it created the first time.
+                if state.inode(existing)?.verifier == Some(verifier) {
+                    return Ok(existing);
+                }
+                return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
+            }
+            let file = Contents::File(FileData::default());
+            let (id, _) = state.insert(dirid, &filename, attr, file)?;
+            state.changed(id)?.verifier = Some(verifier);
+            state.save()?;
+            Ok(id)
+        })
+        .await
+    }
+
+    async fn mkdir(
+        &self,
+        dirid: nfs3::fileid3,
+        dirname: &nfs3::filename3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        let mut attr = self.new_attr(nfs3::ftype3::NF3DIR, 0o755, &nfs3::sattr3::default());
+        attr.size = BLOCK_SIZE;
+        let dirname = dirname.clone();
+        self.update(move |state, _, _| {
+            let result =
+                state.insert(dirid, &dirname, attr, Contents::Directory(IndexMap::new()))?;
+            state.save()?;
+            Ok(result)
+        })
+        .await
+    }
+
+    async fn remove(&self, dirid: nfs3::fileid3, filename: &nfs3::filename3) -> NFSResult<()> {
+        check_existing_name(filename)?;
+        let filename = filename.clone();
+        self.update(move |state, _, _| {
+            let id = state.lookup(dirid, &filename)?;
+            if state.inode(id)?.entries().is_ok_and(|entries| !entries.is_empty()) {
+                return Err(nfs3::nfsstat3::NFS3ERR_NOTEMPTY);
+            }
+            let time = now();
+            state.remove_entry(dirid, &filename)?;
+            state.touch_dir(dirid, time);
+            state.unlink(id, time);
+            state.save()
+        })
+        .await
+    }
+
+    async fn rename(
+        &self,
+        from_dirid: nfs3::fileid3,
+        from_filename: &nfs3::filename3,
+        to_dirid: nfs3::fileid3,
+        to_filename: &nfs3::filename3,
+    ) -> NFSResult<()> {
+        check_existing_name(from_filename)?;
+        check_existing_name(to_filename)?;
+        check_new_name(to_filename)?;
+        let (from_filename, to_filename) = (from_filename.clone(), to_filename.clone());
+        self.update(move |state, _, _| {
+            let id = state.lookup(from_dirid, &from_filename)?;
+            let is_dir = state.inode(id)?.is_dir();
+            let existing = state.inode(to_dirid)?.entries()?.get(&to_filename[..]).copied();
+            if is_dir && state.is_within(to_dirid, id) {
+                // A directory cannot be moved underneath itself.
+                return Err(nfs3::nfsstat3::NFS3ERR_INVAL);
+            }
+            if let Some(target) = existing {
+                if target == id {
+                    // Both names already refer to the same object.
+                    return Ok(());
+                }
+                let target = state.inode(target)?;
+                match (is_dir, target.entries()) {
+                    (true, Ok(entries)) if !entries.is_empty() => {
+                        return Err(nfs3::nfsstat3::NFS3ERR_NOTEMPTY)
+                    }
+                    (true, Err(_)) => return Err(nfs3::nfsstat3::NFS3ERR_NOTDIR),
+                    (false, Ok(_)) => return Err(nfs3::nfsstat3::NFS3ERR_ISDIR),
+                    _ => {}
+                }
+            }
+
+            let time = now();
+            if let Some(target) = existing {
+                state.unlink(target, time);
+            }
+            state.remove_entry(from_dirid, &from_filename)?;
+            // An overwritten name keeps its position in the listing.
+            state.set_entry(to_dirid, &to_filename, id)?;
+            if is_dir && from_dirid != to_dirid {
+                state.changed(from_dirid)?.attr.nlink -= 1;
+                state.changed(to_dirid)?.attr.nlink += 1;
+            }
+            let inode = state.changed(id)?;
+            if is_dir {
+                inode.parent = to_dirid;
+            }
+            inode.attr.ctime = time;
+            state.touch_dir(from_dirid, time);
+            state.touch_dir(to_dirid, time);
+            state.save()
+        })
+        .await
+    }
+
+    async fn readdir(
+        &self,
+        dirid: nfs3::fileid3,
+        start_after: nfs3::fileid3,
+        max_entries: usize,
+    ) -> NFSResult<vfs::ReadDirResult> {
+        let state = self.state.read().await;
+        let dir = state.inode(dirid)?;
+        let entries = dir.entries()?;
+        // Cookies are indices into ".", "..", then the entries in insertion order.
+        let total = entries.len() + 2;
+        let start = usize::try_from(start_after).map_err(|_| nfs3::nfsstat3::NFS3ERR_BAD_COOKIE)?;
+        if start > total {
+            return Err(nfs3::nfsstat3::NFS3ERR_BAD_COOKIE);
+        }
+        let end = total.min(start.saturating_add(max_entries));
+        let mut result = vfs::ReadDirResult { entries: Vec::new(), end: end == total };
+        for index in start..end {
+            let (name, fileid): (&[u8], _) = match index {
+                0 => (b".", dirid),
+                1 => (b"..", dir.parent),
+                _ => {
+                    let (name, fileid) =
+                        entries.get_index(index - 2).ok_or(nfs3::nfsstat3::NFS3ERR_IO)?;
+                    (name, *fileid)
+                }
+            };
+            let attr = state.inode(fileid).map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?.attr;
+            result.entries.push(vfs::DirEntry { fileid, name: name.into(), attr });
+        }
+        Ok(result)
+    }
+
+    async fn readdir_index(
+        &self,
+        dirid: nfs3::fileid3,
+        start_index: usize,
+        max_entries: usize,
+    ) -> NFSResult<vfs::ReadDirResult> {
+        self.readdir(dirid, start_index as nfs3::fileid3, max_entries).await
+    }
+
+    async fn symlink(
+        &self,
+        dirid: nfs3::fileid3,
+        linkname: &nfs3::filename3,
+        symlink: &nfs3::nfspath3,
+        attr: &nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        let mut new_attr = self.new_attr(nfs3::ftype3::NF3LNK, 0o777, attr);
+        new_attr.size = symlink.len() as u64;
+        let (linkname, target) = (linkname.clone(), symlink.to_vec());
+        self.update(move |state, _, _| {
+            let result = state.insert(dirid, &linkname, new_attr, Contents::Symlink(target))?;
+            state.save()?;
+            Ok(result)
+        })
+        .await
+    }
+
+    async fn readlink(&self, id: nfs3::fileid3) -> NFSResult<nfs3::nfspath3> {
+        match &self.state.read().await.inode(id)?.contents {
+            Contents::Symlink(target) => Ok(target.clone().into()),
+            _ => Err(nfs3::nfsstat3::NFS3ERR_INVAL),
+        }
+    }
+
+    async fn link(
+        &self,
+        file_id: nfs3::fileid3,
+        link_dir_id: nfs3::fileid3,
+        link_name: &nfs3::filename3,
+    ) -> NFSResult<nfs3::fattr3> {
+        check_new_name(link_name)?;
+        let link_name = link_name.clone();
+        self.update(move |state, _, _| {
+            let inode = state.inode(file_id)?;
+            if inode.is_dir() {
+                return Err(nfs3::nfsstat3::NFS3ERR_INVAL);
+            }
+            if inode.attr.nlink == u32::MAX {
+                return Err(nfs3::nfsstat3::NFS3ERR_MLINK);
+            }
+            if state.inode(link_dir_id)?.entries()?.contains_key(&link_name[..]) {
+                return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
+            }
+            state.set_entry(link_dir_id, &link_name, file_id)?;
+            let time = now();
+            state.touch_dir(link_dir_id, time);
+            let inode = state.changed(file_id)?;
+            inode.attr.nlink += 1;
+            inode.attr.ctime = time;
+            let attr = inode.attr;
+            state.save()?;
+            Ok(attr)
+        })
+        .await
+    }
+
+    async fn mknod(
+        &self,
+        dir_id: nfs3::fileid3,
+        name: &nfs3::filename3,
+        ftype: nfs3::ftype3,
+        specdata: nfs3::specdata3,
+        attrs: &nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        let mut attr = self.new_attr(ftype, 0o644, attrs);
+        match ftype {
+            nfs3::ftype3::NF3CHR | nfs3::ftype3::NF3BLK => attr.rdev = specdata,
+            nfs3::ftype3::NF3SOCK | nfs3::ftype3::NF3FIFO => {}
+            _ => return Err(nfs3::nfsstat3::NFS3ERR_BADTYPE),
+        }
+        let name = name.clone();
+        self.update(move |state, _, _| {
+            let result = state.insert(dir_id, &name, attr, Contents::Special)?;
+            state.save()?;
+            Ok(result)
+        })
+        .await
+    }
+
+    async fn commit(
+        &self,
+        file_id: nfs3::fileid3,
+        _offset: u64,
+        _count: u32,
+    ) -> NFSResult<nfs3::fattr3> {
+        self.update(move |state, store, chunker| {
+            if matches!(state.inode(file_id)?.contents, Contents::File(_)) {
+                // Chunks do not line up with the client's ranges, so commit the whole file.
+                state.flush(store, chunker, file_id)?;
+                state.save()?;
+            }
+            Ok(state.inode(file_id)?.attr)
+        })
+        .await
+    }
+
+    fn fsinfo_properties(&self) -> u32 {
+        nfs3::fs::FSF_LINK
+            | nfs3::fs::FSF_SYMLINK
+            | nfs3::fs::FSF_HOMOGENEOUS
+            | nfs3::fs::FSF_CANSETTIME
+    }
+
+    fn pathconf_linkmax(&self) -> u32 {
+        u32::MAX
+    }
+
+    fn pathconf_name_max(&self) -> u32 {
+        NAME_MAX as u32
+    }
+}
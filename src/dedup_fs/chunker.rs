@@ -0,0 +1,68 @@
+//! Content-defined chunking for [`DedupFS`](super::DedupFS).
+//!
+//! Chunk boundaries are placed where a gear rolling hash of the preceding bytes matches
+//! a mask, so they follow the content rather than offsets: inserting or removing bytes
+//! only changes the chunks around the edit, and identical runs of data in different
+//! files are cut into identical chunks. The hash restarts at every boundary, so cutting
+//! the same bytes from the same starting boundary always yields the same chunks.
+
+/// Random values for each byte, derived with splitmix64 so they never change.
+const GEAR: [u64; 256] = {
+    let mut table = [0; 256];
+    let mut state: u64 = 0x6665_726e_6673_6364;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// Splits data into chunks of a target average size
+#[derive(Clone, Copy, Debug)]
+pub struct Chunker {
+    min: usize,
+    max: usize,
+    mask: u64,
+}
+
+impl Chunker {
+    /// Creates a chunker averaging `avg` bytes per chunk, a power of two.
+    ///
+    /// Chunks are between a quarter of and four times the average.
+    pub fn new(avg: u32) -> Self {
+        debug_assert!(avg.is_power_of_two());
+        let bits = avg.trailing_zeros();
+        // The high bits of the hash depend on the most bytes.
+        let mask = ((1u64 << bits) - 1) << (64 - bits);
+        Self { min: avg as usize / 4, max: avg as usize * 4, mask }
+    }
+
+    /// Returns the largest chunk this chunker produces.
+    pub fn max_len(&self) -> usize {
+        self.max
+    }
+
+    /// Returns the length of the chunk at the start of `data`.
+    ///
+    /// `data` must hold at least [`max_len`](Self::max_len) bytes unless it runs to the
+    /// end of the file, in which case the last chunk ends with it.
+    pub fn cut(&self, data: &[u8]) -> usize {
+        if data.len() <= self.min {
+            return data.len();
+        }
+        let end = data.len().min(self.max);
+        let mut hash = 0u64;
+        for (i, &byte) in data[..end].iter().enumerate().skip(self.min) {
+            hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+            if hash & self.mask == 0 {
+                return i + 1;
+            }
+        }
+        end
+    }
+}
@@ -0,0 +1,417 @@
+//! The index of a [`DedupFS`](super::DedupFS): its tree and the chunks of every file.
+//!
+//! The index is kept as a snapshot plus a journal of the changes made since. Both are
+//! XDR streams:
+//!
+//! ```text
+//! snapshot: magic | version | epoch | average chunk size | next file ID | inode count | inodes
+//! journal:  magic | epoch | frames
+//! frame:    opaque(next file ID | record count | records) | checksum (8)
+//! ```
+//!
+//! A frame holds the records of one operation, so an operation is either replayed
+//! completely or not at all; a frame cut short by a crash fails its checksum and ends
+//! the journal. Records replace whole inodes (without directory entries), delete
+//! inodes, or set and remove single directory entries, so large directories do not
+//! have to be rewritten on every change. Once the journal outgrows the snapshot, a new
+//! snapshot with the next epoch replaces both; a journal from an older epoch is
+//! ignored, which makes the switch safe at any point.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use indexmap::IndexMap;
+
+use super::inode::{ChunkRef, Contents, FileData, Inode};
+use super::store::{hash, sync_dir};
+use super::{check_new_name, ROOT_ID};
+use crate::xdr::{deserialize, nfs3, Serialize};
+
+const MAGIC: [u8; 8] = *b"FERNDDP\0";
+const JOURNAL_MAGIC: [u8; 8] = *b"FERNDDJ\0";
+const VERSION: u32 = 1;
+const SNAPSHOT_NAME: &str = "index";
+const JOURNAL_NAME: &str = "journal";
+/// Journal size below which it is never folded into the snapshot.
+const MIN_COMPACT_LEN: u64 = 1024 * 1024;
+
+const KIND_FILE: u32 = 0;
+const KIND_DIRECTORY: u32 = 1;
+const KIND_SYMLINK: u32 = 2;
+const KIND_SPECIAL: u32 = 3;
+
+const RECORD_INODE: u32 = 0;
+const RECORD_DELETE: u32 = 1;
+const RECORD_SET_ENTRY: u32 = 2;
+const RECORD_REMOVE_ENTRY: u32 = 3;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid dedup index: {message}"))
+}
+
+/// The file system tree as stored in the index
+#[derive(Debug)]
+pub struct Tree {
+    pub inodes: HashMap<nfs3::fileid3, Inode>,
+    pub next_id: nfs3::fileid3,
+    pub avg_chunk_size: u32,
+}
+
+/// A change to a directory entry, recorded in the journal
+#[derive(Debug)]
+pub enum EntryChange {
+    Set(nfs3::fileid3, Vec<u8>, nfs3::fileid3),
+    Remove(nfs3::fileid3, Vec<u8>),
+}
+
+fn write_inode(
+    dest: &mut impl Write,
+    id: nfs3::fileid3,
+    inode: &Inode,
+    entries: bool,
+) -> io::Result<()> {
+    id.serialize(dest)?;
+    inode.attr.serialize(dest)?;
+    inode.parent.serialize(dest)?;
+    inode.verifier.is_some().serialize(dest)?;
+    if let Some(verifier) = &inode.verifier {
+        verifier.serialize(dest)?;
+    }
+    match &inode.contents {
+        Contents::File(data) => {
+            KIND_FILE.serialize(dest)?;
+            (data.chunks().len() as u64).serialize(dest)?;
+            for chunk in data.chunks() {
+                chunk.hash.serialize(dest)?;
+                chunk.len.serialize(dest)?;
+            }
+        }
+        Contents::Directory(children) => {
+            KIND_DIRECTORY.serialize(dest)?;
+            if entries {
+                (children.len() as u64).serialize(dest)?;
+                for (name, child) in children {
+                    name[..].serialize(dest)?;
+                    child.serialize(dest)?;
+                }
+            }
+        }
+        Contents::Symlink(target) => {
+            KIND_SYMLINK.serialize(dest)?;
+            target[..].serialize(dest)?;
+        }
+        Contents::Special => KIND_SPECIAL.serialize(dest)?,
+    }
+    Ok(())
+}
+
+fn read_inode(src: &mut impl Read, entries: bool) -> io::Result<(nfs3::fileid3, Inode)> {
+    let id = deserialize::<nfs3::fileid3>(src)?;
+    let mut attr = deserialize::<nfs3::fattr3>(src)?;
+    let parent = deserialize::<nfs3::fileid3>(src)?;
+    let verifier =
+        if deserialize::<bool>(src)? { Some(deserialize::<nfs3::createverf3>(src)?) } else { None };
+    let contents = match deserialize::<u32>(src)? {
+        KIND_FILE => {
+            let mut chunks = Vec::new();
+            for _ in 0..deserialize::<u64>(src)? {
+                let hash = deserialize::<[u8; 32]>(src)?;
+                chunks.push(ChunkRef { hash, len: deserialize::<u32>(src)? });
+            }
+            let data = FileData::new(chunks);
+            // Writes that were never committed are lost, and the size with them.
+            attr.size = data.stored_size();
+            attr.used = attr.size;
+            Contents::File(data)
+        }
+        KIND_DIRECTORY => {
+            let mut children = IndexMap::new();
+            if entries {
+                for _ in 0..deserialize::<u64>(src)? {
+                    let name = deserialize::<Vec<u8>>(src)?;
+                    children.insert(name, deserialize::<nfs3::fileid3>(src)?);
+                }
+            }
+            Contents::Directory(children)
+        }
+        KIND_SYMLINK => Contents::Symlink(deserialize::<Vec<u8>>(src)?),
+        KIND_SPECIAL => Contents::Special,
+        _ => return Err(invalid("unknown inode kind")),
+    };
+    if id == 0 || attr.fileid != id {
+        return Err(invalid("bad file id"));
+    }
+    Ok((id, Inode { attr, parent, verifier, contents }))
+}
+
+/// Encodes the journal frame for one operation: the inodes it touched, as they are
+/// now, and its directory entry changes in order.
+pub fn encode_frame(
+    tree: &Tree,
+    touched: &BTreeSet<nfs3::fileid3>,
+    entries: &[EntryChange],
+) -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    tree.next_id.serialize(&mut body)?;
+    (touched.len() as u64 + entries.len() as u64).serialize(&mut body)?;
+    // Inodes come first so that entries can be added to new directories, deletions
+    // last so that entries can still be removed from deleted ones.
+    for id in touched {
+        if let Some(inode) = tree.inodes.get(id) {
+            RECORD_INODE.serialize(&mut body)?;
+            write_inode(&mut body, *id, inode, false)?;
+        }
+    }
+    for change in entries {
+        match change {
+            EntryChange::Set(dir, name, id) => {
+                RECORD_SET_ENTRY.serialize(&mut body)?;
+                dir.serialize(&mut body)?;
+                name[..].serialize(&mut body)?;
+                id.serialize(&mut body)?;
+            }
+            EntryChange::Remove(dir, name) => {
+                RECORD_REMOVE_ENTRY.serialize(&mut body)?;
+                dir.serialize(&mut body)?;
+                name[..].serialize(&mut body)?;
+            }
+        }
+    }
+    for id in touched {
+        if !tree.inodes.contains_key(id) {
+            RECORD_DELETE.serialize(&mut body)?;
+            id.serialize(&mut body)?;
+        }
+    }
+    let mut frame = Vec::with_capacity(body.len() + 16);
+    body[..].serialize(&mut frame)?;
+    frame.extend_from_slice(&hash(&body)[..8]);
+    Ok(frame)
+}
+
+/// Applies the records of one frame.
+fn replay_frame(tree: &mut Tree, body: &[u8]) -> io::Result<()> {
+    let src = &mut Cursor::new(body);
+    tree.next_id = tree.next_id.max(deserialize::<nfs3::fileid3>(src)?);
+    for _ in 0..deserialize::<u64>(src)? {
+        match deserialize::<u32>(src)? {
+            RECORD_INODE => {
+                let (id, mut inode) = read_inode(src, false)?;
+                // Directory records carry no entries; keep the ones already known.
+                if let (Some(old), Contents::Directory(children)) =
+                    (tree.inodes.remove(&id), &mut inode.contents)
+                {
+                    if let Contents::Directory(old_children) = old.contents {
+                        *children = old_children;
+                    }
+                }
+                tree.inodes.insert(id, inode);
+            }
+            RECORD_DELETE => {
+                tree.inodes.remove(&deserialize::<nfs3::fileid3>(src)?);
+            }
+            RECORD_SET_ENTRY => {
+                let dir = deserialize::<nfs3::fileid3>(src)?;
+                let name = deserialize::<Vec<u8>>(src)?;
+                let id = deserialize::<nfs3::fileid3>(src)?;
+                if let Some(Contents::Directory(children)) =
+                    tree.inodes.get_mut(&dir).map(|dir| &mut dir.contents)
+                {
+                    children.insert(name, id);
+                }
+            }
+            RECORD_REMOVE_ENTRY => {
+                let dir = deserialize::<nfs3::fileid3>(src)?;
+                let name = deserialize::<Vec<u8>>(src)?;
+                if let Some(Contents::Directory(children)) =
+                    tree.inodes.get_mut(&dir).map(|dir| &mut dir.contents)
+                {
+                    children.shift_remove(&name);
+                }
+            }
+            _ => return Err(invalid("unknown journal record")),
+        }
+    }
+    Ok(())
+}
+
+/// Checks that every inode is reachable from the root, each directory through
+/// exactly one entry of its parent, under names `create` would accept. Link counts
+/// are then recomputed from the entries.
+fn validate(tree: &mut Tree) -> io::Result<()> {
+    if !tree.inodes.get(&ROOT_ID).is_some_and(|root| root.is_dir() && root.parent == ROOT_ID) {
+        return Err(invalid("missing root directory"));
+    }
+    if tree.inodes.keys().any(|&id| id >= tree.next_id) {
+        return Err(invalid("bad file id"));
+    }
+    let mut links: HashMap<nfs3::fileid3, u32> = HashMap::from([(ROOT_ID, 0)]);
+    let mut pending = vec![ROOT_ID];
+    while let Some(dirid) = pending.pop() {
+        let Contents::Directory(children) = &tree.inodes[&dirid].contents else {
+            unreachable!("only directories are queued");
+        };
+        for (name, &child) in children {
+            if check_new_name(name).is_err() {
+                return Err(invalid("bad entry name"));
+            }
+            let inode =
+                tree.inodes.get(&child).ok_or_else(|| invalid("dangling directory entry"))?;
+            if !inode.is_dir() {
+                *links.entry(child).or_default() += 1;
+                continue;
+            }
+            if inode.parent != dirid || links.insert(child, 0).is_some() {
+                return Err(invalid("directory with more than one parent"));
+            }
+            *links.get_mut(&dirid).unwrap() += 1;
+            pending.push(child);
+        }
+    }
+    if links.len() != tree.inodes.len() {
+        return Err(invalid("unreachable inode"));
+    }
+    for (id, inode) in tree.inodes.iter_mut() {
+        // Directories are linked from their parent and their own ".", plus the ".."
+        // of each subdirectory.
+        let count = links[id];
+        inode.attr.nlink = if inode.is_dir() { count + 2 } else { count };
+    }
+    Ok(())
+}
+
+/// The snapshot and journal files of an index
+#[derive(Debug)]
+pub struct Index {
+    dir: PathBuf,
+    journal: File,
+    journal_len: u64,
+    snapshot_len: u64,
+    epoch: u64,
+}
+
+impl Index {
+    /// Loads the index in `dir`, or returns `None` if there is none yet.
+    pub fn load(dir: &Path) -> io::Result<Option<(Self, Tree)>> {
+        let snapshot = match fs::read(dir.join(SNAPSHOT_NAME)) {
+            Ok(snapshot) => snapshot,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let src = &mut Cursor::new(&snapshot[..]);
+        if deserialize::<[u8; 8]>(src)? != MAGIC {
+            return Err(invalid("not a dedup index"));
+        }
+        let version = deserialize::<u32>(src)?;
+        if version != VERSION {
+            return Err(invalid(&format!("unsupported version {version}")));
+        }
+        let epoch = deserialize::<u64>(src)?;
+        let avg_chunk_size = deserialize::<u32>(src)?;
+        let next_id = deserialize::<nfs3::fileid3>(src)?;
+        let mut tree = Tree { inodes: HashMap::new(), next_id, avg_chunk_size };
+        for _ in 0..deserialize::<u64>(src)? {
+            let (id, inode) = read_inode(src, true)?;
+            if tree.inodes.insert(id, inode).is_some() {
+                return Err(invalid("duplicate file id"));
+            }
+        }
+
+        let journal_path = dir.join(JOURNAL_NAME);
+        let journal = fs::read(&journal_path).unwrap_or_default();
+        let src = &mut Cursor::new(&journal[..]);
+        let current = deserialize::<[u8; 8]>(src).is_ok_and(|magic| magic == JOURNAL_MAGIC)
+            && deserialize::<u64>(src).is_ok_and(|journal_epoch| journal_epoch == epoch);
+        let mut journal_len = 0;
+        if current {
+            journal_len = src.position();
+            // Replay up to the first frame that is incomplete or damaged.
+            while let Ok(body) = deserialize::<Vec<u8>>(src) {
+                let Ok(checksum) = deserialize::<[u8; 8]>(src) else {
+                    break;
+                };
+                if checksum != hash(&body)[..8] {
+                    break;
+                }
+                replay_frame(&mut tree, &body)?;
+                journal_len = src.position();
+            }
+        }
+        validate(&mut tree)?;
+
+        let mut index = if current {
+            let mut journal = OpenOptions::new().write(true).open(&journal_path)?;
+            journal.set_len(journal_len)?;
+            journal.seek(SeekFrom::End(0))?;
+            let snapshot_len = snapshot.len() as u64;
+            Self { dir: dir.to_owned(), journal, journal_len, snapshot_len, epoch }
+        } else {
+            Self::new_journal(dir, epoch, snapshot.len() as u64)?
+        };
+        if journal_len > 0 && index.should_compact() {
+            index.compact(&tree)?;
+        }
+        Ok(Some((index, tree)))
+    }
+
+    /// Writes a new index for `tree` to `dir`.
+    pub fn create(dir: &Path, tree: &Tree) -> io::Result<Self> {
+        let mut index = Self::new_journal(dir, 0, 0)?;
+        index.compact(tree)?;
+        Ok(index)
+    }
+
+    /// Starts an empty journal for `epoch`.
+    fn new_journal(dir: &Path, epoch: u64, snapshot_len: u64) -> io::Result<Self> {
+        let path = dir.join(JOURNAL_NAME);
+        let tmp_path = dir.join(format!("{JOURNAL_NAME}.tmp"));
+        let mut journal = File::create(&tmp_path)?;
+        JOURNAL_MAGIC.serialize(&mut journal)?;
+        epoch.serialize(&mut journal)?;
+        journal.sync_all()?;
+        fs::rename(&tmp_path, &path)?;
+        sync_dir(dir)?;
+        let journal_len = journal.stream_position()?;
+        Ok(Self { dir: dir.to_owned(), journal, journal_len, snapshot_len, epoch })
+    }
+
+    /// Appends a frame from [`encode_frame`] and waits for it to reach stable storage.
+    pub fn append(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.journal.write_all(frame)?;
+        self.journal.sync_data()?;
+        self.journal_len += frame.len() as u64;
+        Ok(())
+    }
+
+    pub fn should_compact(&self) -> bool {
+        self.journal_len > self.snapshot_len.max(MIN_COMPACT_LEN)
+    }
+
+    /// Replaces the snapshot with one of `tree` and empties the journal.
+    pub fn compact(&mut self, tree: &Tree) -> io::Result<()> {
+        let epoch = self.epoch + 1;
+        let path = self.dir.join(SNAPSHOT_NAME);
+        let tmp_path = self.dir.join(format!("{SNAPSHOT_NAME}.tmp"));
+        let mut file = io::BufWriter::new(File::create(&tmp_path)?);
+        MAGIC.serialize(&mut file)?;
+        VERSION.serialize(&mut file)?;
+        epoch.serialize(&mut file)?;
+        tree.avg_chunk_size.serialize(&mut file)?;
+        tree.next_id.serialize(&mut file)?;
+        (tree.inodes.len() as u64).serialize(&mut file)?;
+        let mut ids: Vec<_> = tree.inodes.keys().copied().collect();
+        ids.sort_unstable();
+        for id in ids {
+            write_inode(&mut file, id, &tree.inodes[&id], true)?;
+        }
+        let file = file.into_inner().map_err(io::IntoInnerError::into_error)?;
+        file.sync_all()?;
+        let snapshot_len = file.metadata()?.len();
+        fs::rename(&tmp_path, &path)?;
+        sync_dir(&self.dir)?;
+        *self = Self::new_journal(&self.dir, epoch, snapshot_len)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+//! Inodes and chunked file contents for [`DedupFS`](super::DedupFS).
+
+use std::collections::BTreeMap;
+use std::io;
+
+use indexmap::IndexMap;
+
+use super::store::{ChunkStore, Hash};
+use crate::xdr::nfs3;
+
+/// Granularity of buffered writes.
+pub const BLOCK_SIZE: u64 = 4096;
+
+/// A file system object.
+#[derive(Debug, Clone)]
+pub struct Inode {
+    pub attr: nfs3::fattr3,
+    /// Containing directory; only meaningful for directories, which cannot be hard linked
+    pub parent: nfs3::fileid3,
+    /// Verifier of the EXCLUSIVE create that made this file, if any
+    pub verifier: Option<nfs3::createverf3>,
+    pub contents: Contents,
+}
+
+/// Type-specific payload of an inode.
+#[derive(Debug, Clone)]
+pub enum Contents {
+    File(FileData),
+    /// Entries in insertion order, which is also the READDIR order
+    Directory(IndexMap<Vec<u8>, nfs3::fileid3>),
+    Symlink(Vec<u8>),
+    /// Device nodes, sockets and FIFOs carry no data beyond their attributes
+    Special,
+}
+
+impl Inode {
+    pub fn entries(&self) -> Result<&IndexMap<Vec<u8>, nfs3::fileid3>, nfs3::nfsstat3> {
+        match &self.contents {
+            Contents::Directory(entries) => Ok(entries),
+            _ => Err(nfs3::nfsstat3::NFS3ERR_NOTDIR),
+        }
+    }
+
+    pub fn entries_mut(&mut self) -> Result<&mut IndexMap<Vec<u8>, nfs3::fileid3>, nfs3::nfsstat3> {
+        match &mut self.contents {
+            Contents::Directory(entries) => Ok(entries),
+            _ => Err(nfs3::nfsstat3::NFS3ERR_NOTDIR),
+        }
+    }
+
+    pub fn is_dir(&self) -> bool {
+        matches!(self.contents, Contents::Directory(_))
+    }
+}
+
+/// One stored chunk of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkRef {
+    pub hash: Hash,
+    pub len: u32,
+}
+
+/// Writes not yet cut into chunks.
+#[derive(Debug, Clone, Default)]
+pub struct Dirty {
+    /// Whole blocks of the file as written, filled in from the chunks where the writes
+    /// did not cover them
+    pub blocks: BTreeMap<u64, Box<[u8]>>,
+    /// Start of the first and end of the last write
+    pub from: u64,
+    pub to: u64,
+}
+
+/// The contents of a regular file: stored chunks, overlaid by buffered writes.
+#[derive(Debug, Clone, Default)]
+pub struct FileData {
+    chunks: Vec<ChunkRef>,
+    /// End offset of each chunk
+    ends: Vec<u64>,
+    pub dirty: Option<Dirty>,
+}
+
+impl FileData {
+    pub fn new(chunks: Vec<ChunkRef>) -> Self {
+        let mut data = Self::default();
+        data.set_chunks(chunks);
+        data
+    }
+
+    pub fn chunks(&self) -> &[ChunkRef] {
+        &self.chunks
+    }
+
+    pub fn set_chunks(&mut self, chunks: Vec<ChunkRef>) {
+        let mut end = 0;
+        self.ends = chunks
+            .iter()
+            .map(|chunk| {
+                end += chunk.len as u64;
+                end
+            })
+            .collect();
+        self.chunks = chunks;
+    }
+
+    /// Size of the file as stored in chunks.
+    pub fn stored_size(&self) -> u64 {
+        self.ends.last().copied().unwrap_or(0)
+    }
+
+    /// Returns the offset at which chunk `index` starts.
+    pub fn chunk_start(&self, index: usize) -> u64 {
+        if index == 0 {
+            0
+        } else {
+            self.ends[index - 1]
+        }
+    }
+
+    /// Returns the index of the chunk holding `offset`, or the number of chunks if it
+    /// is past the end.
+    pub fn chunk_at(&self, offset: u64) -> usize {
+        self.ends.partition_point(|&end| end <= offset)
+    }
+
+    /// Returns the index of the chunk that ends exactly at `offset`, if any.
+    pub fn chunk_ending_at(&self, offset: u64) -> Option<usize> {
+        self.ends.binary_search(&offset).ok()
+    }
+
+    /// Reads `len` bytes at `offset` of the stored chunks, ignoring buffered writes;
+    /// past the end of the chunks the file reads as zeros.
+    pub fn read_stored(&self, store: &ChunkStore, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        let end = offset + len;
+        let mut out = vec![0; len as usize];
+        let mut index = self.chunk_at(offset);
+        while index < self.chunks.len() && self.chunk_start(index) < end {
+            let chunk = &self.chunks[index];
+            let data = store.get(&chunk.hash)?;
+            if data.len() != chunk.len as usize {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "chunk has the wrong size"));
+            }
+            let start = self.chunk_start(index);
+            let from = offset.max(start);
+            let to = end.min(self.ends[index]);
+            out[(from - offset) as usize..(to - offset) as usize]
+                .copy_from_slice(&data[(from - start) as usize..(to - start) as usize]);
+            index += 1;
+        }
+        Ok(out)
+    }
+
+    /// Reads `len` bytes at `offset` with buffered writes applied.
+    pub fn read(&self, store: &ChunkStore, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        let mut out = self.read_stored(store, offset, len)?;
+        let Some(dirty) = &self.dirty else {
+            return Ok(out);
+        };
+        if len == 0 {
+            return Ok(out);
+        }
+        let end = offset + len;
+        for (index, block) in dirty.blocks.range(offset / BLOCK_SIZE..=(end - 1) / BLOCK_SIZE) {
+            let block_start = index * BLOCK_SIZE;
+            let from = offset.max(block_start);
+            let to = end.min(block_start + BLOCK_SIZE);
+            out[(from - offset) as usize..(to - offset) as usize].copy_from_slice(
+                &block[(from - block_start) as usize..(to - block_start) as usize],
+            );
+        }
+        Ok(out)
+    }
+
+    /// Buffers a write. Returns the number of bytes now buffered.
+    pub fn write(&mut self, store: &ChunkStore, offset: u64, data: &[u8]) -> io::Result<u64> {
+        if data.is_empty() {
+            return Ok(self.dirty_bytes());
+        }
+        let end = offset + data.len() as u64;
+        let first = offset / BLOCK_SIZE;
+        let last = (end - 1) / BLOCK_SIZE;
+        let mut missing = Vec::new();
+        for index in first..=last {
+            if !self.dirty.as_ref().is_some_and(|dirty| dirty.blocks.contains_key(&index)) {
+                let block = self.read_stored(store, index * BLOCK_SIZE, BLOCK_SIZE)?;
+                missing.push((index, block.into_boxed_slice()));
+            }
+        }
+        let dirty =
+            self.dirty.get_or_insert_with(|| Dirty { from: offset, to: end, ..Default::default() });
+        dirty.blocks.extend(missing);
+        dirty.from = dirty.from.min(offset);
+        dirty.to = dirty.to.max(end);
+        for (index, block) in dirty.blocks.range_mut(first..=last) {
+            let block_start = index * BLOCK_SIZE;
+            let from = offset.max(block_start);
+            let to = end.min(block_start + BLOCK_SIZE);
+            block[(from - block_start) as usize..(to - block_start) as usize]
+                .copy_from_slice(&data[(from - offset) as usize..(to - offset) as usize]);
+        }
+        Ok(self.dirty_bytes())
+    }
+
+    pub fn dirty_bytes(&self) -> u64 {
+        self.dirty.as_ref().map_or(0, |dirty| dirty.blocks.len() as u64 * BLOCK_SIZE)
+    }
+}
@@ -0,0 +1,66 @@
+//! Configuration for [`DedupFS`](super::DedupFS).
+
+use std::io;
+use std::path::PathBuf;
+
+use super::DedupFS;
+use crate::xdr::nfs3;
+
+/// Builder for [`DedupFS`].
+#[derive(Debug, Clone)]
+pub struct DedupFSBuilder {
+    pub(super) avg_chunk_size: u32,
+    pub(super) uid: nfs3::uid3,
+    pub(super) gid: nfs3::gid3,
+    pub(super) root_mode: nfs3::mode3,
+}
+
+impl Default for DedupFSBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DedupFSBuilder {
+    /// Starts a configuration with 64 KiB chunks and a world-writable root owned by root.
+    pub fn new() -> Self {
+        Self { avg_chunk_size: 64 * 1024, uid: 0, gid: 0, root_mode: 0o777 }
+    }
+
+    /// Sets the average chunk size of a new store (default 64 KiB).
+    ///
+    /// Chunks vary between a quarter of and four times this size. Smaller chunks find
+    /// more duplicate data but make the index larger. A store keeps the chunk size it
+    /// was created with.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bytes` is not a power of two between 1 KiB and 1 MiB.
+    pub fn avg_chunk_size(mut self, bytes: u32) -> Self {
+        assert!(
+            bytes.is_power_of_two() && (1024..=1024 * 1024).contains(&bytes),
+            "average chunk size must be a power of two between 1 KiB and 1 MiB"
+        );
+        self.avg_chunk_size = bytes;
+        self
+    }
+
+    /// Sets the owner of the root directory of a new store and the default owner of new
+    /// objects.
+    pub fn owner(mut self, uid: nfs3::uid3, gid: nfs3::gid3) -> Self {
+        self.uid = uid;
+        self.gid = gid;
+        self
+    }
+
+    /// Sets the permission bits of the root directory of a new store.
+    pub fn root_mode(mut self, mode: nfs3::mode3) -> Self {
+        self.root_mode = mode & 0o7777;
+        self
+    }
+
+    /// Opens the store in `dir`, creating it if it does not exist.
+    pub fn open(self, dir: impl Into<PathBuf>) -> io::Result<DedupFS> {
+        DedupFS::open_with(self, dir.into())
+    }
+}
@@ -0,0 +1,127 @@
+//! The chunk directory of a [`DedupFS`](super::DedupFS).
+//!
+//! Every chunk is a file named by the hex SHA-256 of its contents, in a subdirectory
+//! named by the first two hex digits so no directory grows too large. Chunks are
+//! written to a temporary name and renamed into place, so a chunk file that exists is
+//! always complete, and reads check the hash so damaged chunks are never served.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+/// SHA-256 of a chunk's contents
+pub type Hash = [u8; 32];
+
+const TMP_SUFFIX: &str = ".tmp";
+
+pub fn hash(data: &[u8]) -> Hash {
+    Sha256::digest(data).into()
+}
+
+fn hex(hash: &Hash) -> String {
+    hash.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn parse_hex(name: &str) -> Option<Hash> {
+    if name.len() != 64 {
+        return None;
+    }
+    let mut hash = [0; 32];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(name.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(hash)
+}
+
+/// Flushes a directory's entries, so a rename into it survives a crash.
+pub fn sync_dir(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+/// Chunks stored as files under one directory
+#[derive(Debug)]
+pub struct ChunkStore {
+    dir: PathBuf,
+}
+
+impl ChunkStore {
+    pub fn open(dir: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, hash: &Hash) -> PathBuf {
+        let name = hex(hash);
+        self.dir.join(&name[..2]).join(&name[2..])
+    }
+
+    /// Stores a chunk unless it is already present. The chunk is on stable storage when
+    /// this returns.
+    pub fn put(&self, hash: &Hash, data: &[u8]) -> io::Result<()> {
+        let path = self.path(hash);
+        if path.exists() {
+            return Ok(());
+        }
+        let parent = path.parent().unwrap();
+        fs::create_dir_all(parent)?;
+        let mut tmp_name = path.file_name().unwrap().to_os_string();
+        tmp_name.push(TMP_SUFFIX);
+        let tmp_path = path.with_file_name(tmp_name);
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &path)?;
+        sync_dir(parent)?;
+        Ok(())
+    }
+
+    /// Reads a chunk, failing with `InvalidData` if its contents do not match its hash.
+    pub fn get(&self, hash: &Hash) -> io::Result<Vec<u8>> {
+        let data = fs::read(self.path(hash))?;
+        if self::hash(&data) != *hash {
+            let message = format!("chunk {} is damaged", hex(hash));
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+        Ok(data)
+    }
+
+    /// Deletes a chunk, returning its size.
+    pub fn remove(&self, hash: &Hash) -> io::Result<u64> {
+        let path = self.path(hash);
+        let len = fs::metadata(&path)?.len();
+        fs::remove_file(path)?;
+        Ok(len)
+    }
+
+    /// Lists every stored chunk, deleting temporary files left by interrupted writes.
+    pub fn list(&self) -> io::Result<Vec<Hash>> {
+        let mut hashes = Vec::new();
+        for dir in fs::read_dir(&self.dir)? {
+            let dir = dir?;
+            let Some(prefix) = dir.file_name().to_str().map(str::to_owned) else {
+                continue;
+            };
+            if prefix.len() != 2 || !dir.file_type()?.is_dir() {
+                continue;
+            }
+            for file in fs::read_dir(dir.path())? {
+                let file = file?;
+                let Some(name) = file.file_name().to_str().map(str::to_owned) else {
+                    continue;
+                };
+                if name.ends_with(TMP_SUFFIX) {
+                    fs::remove_file(file.path())?;
+                } else if let Some(hash) = parse_hex(&format!("{prefix}{name}")) {
+                    hashes.push(hash);
+                }
+            }
+        }
+        Ok(hashes)
+    }
+}
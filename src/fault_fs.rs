@@ -0,0 +1,539 @@
+//! A wrapper that injects faults into another file system, for testing clients.
+//!
+//! [`FaultFS`] passes every call through to the wrapped file system unless one of its
+//! [`FaultRule`]s matches. A rule pairs a [`Fault`] with the operations and paths it
+//! applies to, a probability and an optional limit:
+//! - [`Fault::Error`] fails the operation with a chosen status, such as
+//!   `NFS3ERR_JUKEBOX` to make clients back off and retry.
+//! - [`Fault::Latency`] delays the operation by a fixed, uniform or exponential delay.
+//! - [`Fault::DropReply`] performs the operation but sends no reply, so the client has
+//!   to retransmit.
+//! - [`Fault::ShortRead`] and [`Fault::ShortWrite`] transfer less than was asked for.
+//!
+//! Rules are added and removed at runtime through a [`FaultController`], which can be
+//! cloned into the test driving the client. All random choices come from a generator
+//! seeded at construction (or by [`FaultController::reseed`]), so a run with the same
+//! seed and the same sequence of calls injects the same faults.
+//!
+//! ```ignore
+//! use std::sync::Arc;
+//! use fernfs::fault_fs::{Fault, FaultFS, FaultRule, Op};
+//! use fernfs::xdr::nfs3::nfsstat3;
+//!
+//! let fs = FaultFS::new(Arc::new(MemFS::new()), 42);
+//! let faults = fs.controller();
+//! let rule = faults.add(
+//!     FaultRule::new(Fault::Error(nfsstat3::NFS3ERR_JUKEBOX)).on(Op::Write).probability(0.1),
+//! );
+//! // ... run the client ...
+//! println!("injected {} times", faults.injected(rule));
+//! ```
+//!
+//! Paths are learned from the lookups, creations and directory listings that pass
+//! through the wrapper, so a rule restricted with [`FaultRule::under`] only matches
+//! objects the client has reached through the wrapper. Handles are those of the
+//! wrapped file system, so a `FaultFS` can be removed without invalidating them.
+
+mod rules;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use crate::protocol::rpc::discard_reply;
+use crate::protocol::xdr::rpc::auth_unix;
+use crate::vfs::{self, Capabilities, NFSFileSystem};
+use crate::xdr::nfs3;
+
+use rules::Rng;
+pub use rules::{Fault, FaultRule, Latency, Op};
+
+type NFSResult<T> = Result<T, nfs3::nfsstat3>;
+
+/// Identifies a rule added to a [`FaultController`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RuleId(u64);
+
+struct ActiveRule {
+    id: RuleId,
+    rule: FaultRule,
+    injected: u64,
+}
+
+struct State {
+    rules: Vec<ActiveRule>,
+    /// Injection counts of removed rules, so they can still be queried.
+    removed: HashMap<RuleId, u64>,
+    next_id: u64,
+    enabled: bool,
+    rng: Rng,
+}
+
+/// Runtime control over the faults a [`FaultFS`] injects
+///
+/// Clones share the same rules.
+#[derive(Clone)]
+pub struct FaultController {
+    state: Arc<Mutex<State>>,
+}
+
+impl FaultController {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    /// Starts injecting faults according to `rule`
+    ///
+    /// Rules are consulted in the order they were added. The first matching error
+    /// wins; latencies of all matching rules add up.
+    pub fn add(&self, rule: FaultRule) -> RuleId {
+        let mut state = self.state();
+        let id = RuleId(state.next_id);
+        state.next_id += 1;
+        state.rules.push(ActiveRule { id, rule, injected: 0 });
+        id
+    }
+
+    /// Stops injecting faults for a rule; returns false if it was already removed
+    pub fn remove(&self, id: RuleId) -> bool {
+        let mut state = self.state();
+        let Some(index) = state.rules.iter().position(|active| active.id == id) else {
+            return false;
+        };
+        let active = state.rules.remove(index);
+        state.removed.insert(id, active.injected);
+        true
+    }
+
+    /// Removes all rules
+    pub fn clear(&self) {
+        let mut state = self.state();
+        let removed: Vec<_> = state.rules.drain(..).map(|a| (a.id, a.injected)).collect();
+        state.removed.extend(removed);
+    }
+
+    /// Suspends or resumes fault injection without changing the rules
+    pub fn set_enabled(&self, enabled: bool) {
+        self.state().enabled = enabled;
+    }
+
+    /// Restarts the random number generator from `seed`
+    pub fn reseed(&self, seed: u64) {
+        self.state().rng = Rng::new(seed);
+    }
+
+    /// Returns how many times a rule has injected its fault
+    pub fn injected(&self, id: RuleId) -> u64 {
+        let state = self.state();
+        let active = state.rules.iter().find(|active| active.id == id);
+        active.map(|active| active.injected).or(state.removed.get(&id).copied()).unwrap_or(0)
+    }
+
+    /// Decides which faults to inject into `op` on the object at `path`.
+    fn plan(&self, op: Op, path: Option<&[u8]>) -> Plan {
+        let mut plan = Plan::default();
+        let mut state = self.state();
+        if !state.enabled {
+            return plan;
+        }
+        let State { rules, rng, .. } = &mut *state;
+        for active in rules.iter_mut() {
+            let rule = &active.rule;
+            if !rule.matches(op, path) || rule.times.is_some_and(|times| active.injected >= times) {
+                continue;
+            }
+            if rule.probability < 1.0 && rng.next_f64() >= rule.probability {
+                continue;
+            }
+            match rule.fault {
+                Fault::Error(_) if plan.error.is_some() => continue,
+                Fault::Error(status) => plan.error = Some(status),
+                Fault::Latency(latency) => plan.delay += latency.sample(rng),
+                Fault::DropReply => plan.drop_reply = true,
+                Fault::ShortRead | Fault::ShortWrite => plan.shorten = Some(rng.next_f64()),
+            }
+            active.injected += 1;
+        }
+        plan
+    }
+}
+
+/// The faults chosen for one operation.
+#[derive(Default)]
+struct Plan {
+    delay: Duration,
+    error: Option<nfs3::nfsstat3>,
+    drop_reply: bool,
+    /// The fraction of a READ or WRITE to transfer.
+    shorten: Option<f64>,
+}
+
+impl Plan {
+    /// Applies the delay and returns the planned error, if any.
+    async fn begin(&self) -> NFSResult<()> {
+        if !self.delay.is_zero() {
+            tokio::time::sleep(self.delay).await;
+        }
+        if self.drop_reply {
+            discard_reply();
+        }
+        self.error.map_or(Ok(()), Err)
+    }
+
+    /// Shortens a transfer of `len` bytes, keeping at least one byte.
+    fn shortened(&self, len: usize) -> usize {
+        match self.shorten {
+            Some(fraction) if len > 1 => ((len as f64 * fraction) as usize).clamp(1, len - 1),
+            _ => len,
+        }
+    }
+}
+
+/// A file system that injects faults into another
+///
+/// See the [module documentation](self) for an overview.
+pub struct FaultFS {
+    inner: Arc<dyn NFSFileSystem + Send + Sync>,
+    controller: FaultController,
+    /// The path of every object seen through the wrapper, relative to the root.
+    paths: Mutex<HashMap<nfs3::fileid3, Vec<u8>>>,
+}
+
+impl FaultFS {
+    /// Wraps `inner`, drawing random choices from a generator seeded with `seed`
+    pub fn new(inner: Arc<dyn NFSFileSystem + Send + Sync>, seed: u64) -> Self {
+        let root = inner.root_dir();
+        let state = State {
+            rules: Vec::new(),
+            removed: HashMap::new(),
+            next_id: 0,
+            enabled: true,
+            rng: Rng::new(seed),
+        };
+        Self {
+            inner,
+            controller: FaultController { state: Arc::new(Mutex::new(state)) },
+            paths: Mutex::new(HashMap::from([(root, Vec::new())])),
+        }
+    }
+
+    /// Returns a handle for adding and removing faults at runtime
+    pub fn controller(&self) -> FaultController {
+        self.controller.clone()
+    }
+
+    /// Returns the wrapped file system
+    pub fn inner(&self) -> &Arc<dyn NFSFileSystem + Send + Sync> {
+        &self.inner
+    }
+
+    fn paths(&self) -> MutexGuard<'_, HashMap<nfs3::fileid3, Vec<u8>>> {
+        self.paths.lock().unwrap()
+    }
+
+    fn path_of(&self, id: nfs3::fileid3) -> Option<Vec<u8>> {
+        self.paths().get(&id).cloned()
+    }
+
+    /// Returns the path of `name` in the directory `dirid`, if the directory is known.
+    fn child_path(&self, dirid: nfs3::fileid3, name: &[u8]) -> Option<Vec<u8>> {
+        let mut path = self.path_of(dirid)?;
+        if !matches!(name, b"." | b"..") {
+            if !path.is_empty() {
+                path.push(b'/');
+            }
+            path.extend_from_slice(name);
+        }
+        Some(path)
+    }
+
+    fn record(&self, id: nfs3::fileid3, path: Option<Vec<u8>>) -> nfs3::fileid3 {
+        if let Some(path) = path {
+            self.paths().entry(id).or_insert(path);
+        }
+        id
+    }
+
+    fn record_entries(&self, dirid: nfs3::fileid3, result: &vfs::ReadDirResult) {
+        for entry in &result.entries {
+            if !matches!(&entry.name[..], b"." | b"..") {
+                self.record(entry.fileid, self.child_path(dirid, &entry.name));
+            }
+        }
+    }
+
+    fn plan(&self, op: Op, path: Option<&[u8]>) -> Plan {
+        self.controller.plan(op, path)
+    }
+
+    fn plan_for(&self, op: Op, id: nfs3::fileid3) -> Plan {
+        self.plan(op, self.path_of(id).as_deref())
+    }
+}
+
+#[async_trait]
+impl NFSFileSystem for FaultFS {
+    fn generation(&self) -> u64 {
+        self.inner.generation()
+    }
+
+    fn server_id(&self) -> nfs3::cookieverf3 {
+        self.inner.server_id()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn root_dir(&self) -> nfs3::fileid3 {
+        self.inner.root_dir()
+    }
+
+    async fn lookup(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> NFSResult<nfs3::fileid3> {
+        let path = self.child_path(dirid, filename);
+        self.plan(Op::Lookup, path.as_deref()).begin().await?;
+        let id = self.inner.lookup(dirid, filename).await?;
+        if matches!(&filename[..], b"." | b"..") {
+            return Ok(id);
+        }
+        Ok(self.record(id, path))
+    }
+
+    async fn getattr(&self, id: nfs3::fileid3) -> NFSResult<nfs3::fattr3> {
+        self.plan_for(Op::Getattr, id).begin().await?;
+        self.inner.getattr(id).await
+    }
+
+    async fn setattr(&self, id: nfs3::fileid3, setattr: nfs3::sattr3) -> NFSResult<nfs3::fattr3> {
+        self.plan_for(Op::Setattr, id).begin().await?;
+        self.inner.setattr(id, setattr).await
+    }
+
+    async fn read(&self, id: nfs3::fileid3, offset: u64, count: u32) -> NFSResult<(Vec<u8>, bool)> {
+        let plan = self.plan_for(Op::Read, id);
+        plan.begin().await?;
+        // Asking the wrapped file system for less keeps EOF accurate.
+        let count = plan.shortened(count as usize) as u32;
+        self.inner.read(id, offset, count).await
+    }
+
+    async fn write(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+        stable: nfs3::file::stable_how,
+    ) -> NFSResult<(nfs3::fattr3, nfs3::file::stable_how, nfs3::count3)> {
+        let plan = self.plan_for(Op::Write, id);
+        plan.begin().await?;
+        self.inner.write(id, offset, &data[..plan.shortened(data.len())], stable).await
+    }
+
+    async fn create(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        attr: nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        let path = self.child_path(dirid, filename);
+        self.plan(Op::Create, path.as_deref()).begin().await?;
+        let (id, attr) = self.inner.create(dirid, filename, attr).await?;
+        Ok((self.record(id, path), attr))
+    }
+
+    async fn create_exclusive(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        verifier: nfs3::createverf3,
+    ) -> NFSResult<nfs3::fileid3> {
+        let path = self.child_path(dirid, filename);
+        self.plan(Op::CreateExclusive, path.as_deref()).begin().await?;
+        let id = self.inner.create_exclusive(dirid, filename, verifier).await?;
+        Ok(self.record(id, path))
+    }
+
+    async fn mkdir(
+        &self,
+        dirid: nfs3::fileid3,
+        dirname: &nfs3::filename3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        let path = self.child_path(dirid, dirname);
+        self.plan(Op::Mkdir, path.as_deref()).begin().await?;
+        let (id, attr) = self.inner.mkdir(dirid, dirname).await?;
+        Ok((self.record(id, path), attr))
+    }
+
+    async fn remove(&self, dirid: nfs3::fileid3, filename: &nfs3::filename3) -> NFSResult<()> {
+        let path = self.child_path(dirid, filename);
+        self.plan(Op::Remove, path.as_deref()).begin().await?;
+        self.inner.remove(dirid, filename).await?;
+        if let Some(path) = path {
+            self.paths().retain(|_, known| *known != path);
+        }
+        Ok(())
+    }
+
+    async fn rename(
+        &self,
+        from_dirid: nfs3::fileid3,
+        from_filename: &nfs3::filename3,
+        to_dirid: nfs3::fileid3,
+        to_filename: &nfs3::filename3,
+    ) -> NFSResult<()> {
+        // Rules match on the source path.
+        let from = self.child_path(from_dirid, from_filename);
+        self.plan(Op::Rename, from.as_deref()).begin().await?;
+        self.inner.rename(from_dirid, from_filename, to_dirid, to_filename).await?;
+        let to = self.child_path(to_dirid, to_filename);
+        let mut paths = self.paths();
+        if let Some(to) = &to {
+            paths.retain(|_, known| known != to);
+        }
+        if let Some(from) = from {
+            // Move the renamed object and everything known below it.
+            let moved: Vec<_> = paths
+                .iter()
+                .filter_map(|(&id, known)| {
+                    let rest = known.strip_prefix(from.as_slice())?;
+                    (rest.is_empty() || rest[0] == b'/').then(|| (id, rest.to_vec()))
+                })
+                .collect();
+            for (id, rest) in moved {
+                match &to {
+                    Some(to) => paths.insert(id, [to.as_slice(), &rest].concat()),
+                    None => paths.remove(&id),
+                };
+            }
+        }
+        Ok(())
+    }
+
+    async fn readdir(
+        &self,
+        dirid: nfs3::fileid3,
+        start_after: nfs3::fileid3,
+        max_entries: usize,
+    ) -> NFSResult<vfs::ReadDirResult> {
+        self.plan_for(Op::Readdir, dirid).begin().await?;
+        let result = self.inner.readdir(dirid, start_after, max_entries).await?;
+        self.record_entries(dirid, &result);
+        Ok(result)
+    }
+
+    async fn readdir_index(
+        &self,
+        dirid: nfs3::fileid3,
+        start_index: usize,
+        max_entries: usize,
+    ) -> NFSResult<vfs::ReadDirResult> {
+        self.plan_for(Op::Readdir, dirid).begin().await?;
+        let result = self.inner.readdir_index(dirid, start_index, max_entries).await?;
+        self.record_entries(dirid, &result);
+        Ok(result)
+    }
+
+    async fn symlink(
+        &self,
+        dirid: nfs3::fileid3,
+        linkname: &nfs3::filename3,
+        symlink: &nfs3::nfspath3,
+        attr: &nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        let path = self.child_path(dirid, linkname);
+        self.plan(Op::Symlink, path.as_deref()).begin().await?;
+        let (id, attr) = self.inner.symlink(dirid, linkname, symlink, attr).await?;
+        Ok((self.record(id, path), attr))
+    }
+
+    async fn readlink(&self, id: nfs3::fileid3) -> NFSResult<nfs3::nfspath3> {
+        self.plan_for(Op::Readlink, id).begin().await?;
+        self.inner.readlink(id).await
+    }
+
+    async fn link(
+        &self,
+        file_id: nfs3::fileid3,
+        link_dir_id: nfs3::fileid3,
+        link_name: &nfs3::filename3,
+    ) -> NFSResult<nfs3::fattr3> {
+        // Rules match on the new name.
+        let path = self.child_path(link_dir_id, link_name);
+        self.plan(Op::Link, path.as_deref()).begin().await?;
+        self.inner.link(file_id, link_dir_id, link_name).await
+    }
+
+    async fn mknod(
+        &self,
+        dir_id: nfs3::fileid3,
+        name: &nfs3::filename3,
+        ftype: nfs3::ftype3,
+        specdata: nfs3::specdata3,
+        attrs: &nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        let path = self.child_path(dir_id, name);
+        self.plan(Op::Mknod, path.as_deref()).begin().await?;
+        let (id, attr) = self.inner.mknod(dir_id, name, ftype, specdata, attrs).await?;
+        Ok((self.record(id, path), attr))
+    }
+
+    async fn commit(
+        &self,
+        file_id: nfs3::fileid3,
+        offset: u64,
+        count: u32,
+    ) -> NFSResult<nfs3::fattr3> {
+        self.plan_for(Op::Commit, file_id).begin().await?;
+        self.inner.commit(file_id, offset, count).await
+    }
+
+    async fn check_access(
+        &self,
+        id: nfs3::fileid3,
+        auth: &auth_unix,
+        access: u32,
+    ) -> NFSResult<u32> {
+        self.plan_for(Op::Access, id).begin().await?;
+        self.inner.check_access(id, auth, access).await
+    }
+
+    async fn fsinfo(&self, root_fileid: nfs3::fileid3) -> NFSResult<nfs3::fs::fsinfo3> {
+        self.plan_for(Op::Fsinfo, root_fileid).begin().await?;
+        self.inner.fsinfo(root_fileid).await
+    }
+
+    async fn fsstat(&self, root_fileid: nfs3::fileid3) -> NFSResult<nfs3::fs::FSSTAT3resok> {
+        self.plan_for(Op::Fsstat, root_fileid).begin().await?;
+        self.inner.fsstat(root_fileid).await
+    }
+
+    fn fsinfo_maxfilesize(&self) -> nfs3::size3 {
+        self.inner.fsinfo_maxfilesize()
+    }
+
+    fn fsinfo_properties(&self) -> u32 {
+        self.inner.fsinfo_properties()
+    }
+
+    fn pathconf_linkmax(&self) -> u32 {
+        self.inner.pathconf_linkmax()
+    }
+
+    fn pathconf_name_max(&self) -> u32 {
+        self.inner.pathconf_name_max()
+    }
+
+    fn id_to_fh(&self, id: nfs3::fileid3) -> nfs3::nfs_fh3 {
+        self.inner.id_to_fh(id)
+    }
+
+    fn fh_to_id(&self, id: &nfs3::nfs_fh3) -> NFSResult<nfs3::fileid3> {
+        self.inner.fh_to_id(id)
+    }
+}
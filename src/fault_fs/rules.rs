@@ -0,0 +1,161 @@
+//! Fault descriptions and the rules that select where they apply.
+
+use std::time::Duration;
+
+use crate::xdr::nfs3;
+
+/// A file system operation that faults can be attached to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Op {
+    Lookup,
+    Getattr,
+    Setattr,
+    Read,
+    Write,
+    Create,
+    CreateExclusive,
+    Mkdir,
+    Remove,
+    Rename,
+    Readdir,
+    Symlink,
+    Readlink,
+    Link,
+    Mknod,
+    Commit,
+    Access,
+    Fsinfo,
+    Fsstat,
+}
+
+/// A distribution of added delays.
+#[derive(Clone, Copy, Debug)]
+pub enum Latency {
+    /// Always the same delay
+    Fixed(Duration),
+    /// A delay drawn uniformly between `min` and `max`
+    Uniform { min: Duration, max: Duration },
+    /// An exponentially distributed delay with the given mean, which gives the long
+    /// tail typical of a busy server
+    Exponential { mean: Duration },
+}
+
+impl Latency {
+    pub(super) fn sample(&self, rng: &mut Rng) -> Duration {
+        match *self {
+            Latency::Fixed(delay) => delay,
+            Latency::Uniform { min, max } => min + max.saturating_sub(min).mul_f64(rng.next_f64()),
+            Latency::Exponential { mean } => mean.mul_f64(-(1.0 - rng.next_f64()).ln()),
+        }
+    }
+}
+
+/// Something that can go wrong with an operation.
+#[derive(Clone, Copy, Debug)]
+pub enum Fault {
+    /// Fail with the given status without calling the wrapped file system, for
+    /// example `NFS3ERR_IO`, `NFS3ERR_JUKEBOX`, `NFS3ERR_STALE` or `NFS3ERR_NOSPC`
+    Error(nfs3::nfsstat3),
+    /// Delay the operation
+    Latency(Latency),
+    /// Perform the operation but never send the reply, so the client has to
+    /// retransmit; the retransmission is answered from the reply cache
+    DropReply,
+    /// Return only part of the data a READ asked for
+    ShortRead,
+    /// Write only part of the data of a WRITE and report the shorter count
+    ShortWrite,
+}
+
+/// Where and how often a [`Fault`] is injected.
+///
+/// A rule applies to every operation unless restricted with [`on`](Self::on), and
+/// to every object unless restricted with [`under`](Self::under).
+#[derive(Clone, Debug)]
+pub struct FaultRule {
+    pub(super) fault: Fault,
+    pub(super) ops: Vec<Op>,
+    pub(super) path: Option<Vec<u8>>,
+    pub(super) probability: f64,
+    pub(super) times: Option<u64>,
+}
+
+impl FaultRule {
+    /// Creates a rule that injects `fault` into every operation
+    pub fn new(fault: Fault) -> Self {
+        Self { fault, ops: Vec::new(), path: None, probability: 1.0, times: None }
+    }
+
+    /// Restricts the rule to `op`; may be called several times to add operations
+    pub fn on(mut self, op: Op) -> Self {
+        self.ops.push(op);
+        self
+    }
+
+    /// Restricts the rule to objects at or below `path`, relative to the root
+    ///
+    /// Operations on a name in a directory match on the path of that name, so a rule
+    /// under `logs` covers creating `logs` as well as everything inside it.
+    pub fn under(mut self, path: impl AsRef<[u8]>) -> Self {
+        let path = path.as_ref();
+        let start = path.iter().position(|&b| b != b'/').unwrap_or(path.len());
+        let end = path.iter().rposition(|&b| b != b'/').map_or(start, |i| i + 1);
+        self.path = Some(path[start..end].to_vec());
+        self
+    }
+
+    /// Injects the fault into only this fraction of matching operations
+    pub fn probability(mut self, probability: f64) -> Self {
+        self.probability = probability.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Stops injecting after the fault has been injected `times` times
+    pub fn times(mut self, times: u64) -> Self {
+        self.times = Some(times);
+        self
+    }
+
+    /// Returns true if the rule covers `op` on the object at `path`.
+    pub(super) fn matches(&self, op: Op, path: Option<&[u8]>) -> bool {
+        let op_matches = match self.fault {
+            Fault::ShortRead => op == Op::Read,
+            Fault::ShortWrite => op == Op::Write,
+            _ => true,
+        } && (self.ops.is_empty() || self.ops.contains(&op));
+        op_matches
+            && match (&self.path, path) {
+                (None, _) => true,
+                (Some(prefix), Some(path)) => {
+                    prefix.is_empty()
+                        || path
+                            .strip_prefix(prefix.as_slice())
+                            .is_some_and(|rest| rest.is_empty() || rest.first() == Some(&b'/'))
+                }
+                (Some(_), None) => false,
+            }
+    }
+}
+
+/// A small deterministic random number generator (SplitMix64).
+#[derive(Clone, Debug)]
+pub(super) struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
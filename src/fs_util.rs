@@ -11,7 +11,7 @@ use std::fs::Metadata;
 use std::fs::Permissions;
 
 #[cfg(unix)]
-use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
 use std::path::Path;
 
 use tokio::fs::OpenOptions;
@@ -92,7 +92,7 @@ pub fn exists_no_traverse(path: &Path) -> bool {
 /// # Returns
 ///
 /// Normalized file mode with appropriate permissions
-fn mode_unmask(mode: u32) -> u32 {
+pub(crate) fn mode_unmask(mode: u32) -> u32 {
     let mode = Permissions::from_mode(mode);
     mode.mode() & 0x1FF
 }
@@ -112,85 +112,66 @@ fn mode_unmask(mode: u32) -> u32 {
 /// NFS file attributes structure
 pub fn metadata_to_fattr3(fid: nfs3::fileid3, meta: &Metadata) -> nfs3::fattr3 {
     let size = meta.size();
-    let file_mode = mode_unmask(meta.mode());
-    if meta.is_file() {
-        nfs3::fattr3 {
-            ftype: nfs3::ftype3::NF3REG,
-            mode: file_mode,
-            nlink: meta.nlink() as u32,
-            uid: meta.uid(),
-            gid: meta.gid(),
-            size,
-            used: size,
-            rdev: nfs3::specdata3::default(),
-            fsid: 0,
-            fileid: fid,
-            atime: nfs3::nfstime3 {
-                seconds: meta.atime() as u32,
-                nseconds: meta.atime_nsec() as u32,
-            },
-            mtime: nfs3::nfstime3 {
-                seconds: meta.mtime() as u32,
-                nseconds: meta.mtime_nsec() as u32,
-            },
-            ctime: nfs3::nfstime3 {
-                seconds: meta.ctime() as u32,
-                nseconds: meta.ctime_nsec() as u32,
-            },
-        }
-    } else if meta.is_symlink() {
-        nfs3::fattr3 {
-            ftype: nfs3::ftype3::NF3LNK,
-            mode: file_mode,
-            nlink: meta.nlink() as u32,
-            uid: meta.uid(),
-            gid: meta.gid(),
-            size,
-            used: size,
-            rdev: nfs3::specdata3::default(),
-            fsid: 0,
-            fileid: fid,
-            atime: nfs3::nfstime3 {
-                seconds: meta.atime() as u32,
-                nseconds: meta.atime_nsec() as u32,
-            },
-            mtime: nfs3::nfstime3 {
-                seconds: meta.mtime() as u32,
-                nseconds: meta.mtime_nsec() as u32,
-            },
-            ctime: nfs3::nfstime3 {
-                seconds: meta.ctime() as u32,
-                nseconds: meta.ctime_nsec() as u32,
-            },
-        }
+    let file_type = meta.file_type();
+    let (ftype, rdev) = if file_type.is_file() {
+        (nfs3::ftype3::NF3REG, nfs3::specdata3::default())
+    } else if file_type.is_symlink() {
+        (nfs3::ftype3::NF3LNK, nfs3::specdata3::default())
+    } else if file_type.is_char_device() {
+        (nfs3::ftype3::NF3CHR, rdev_to_specdata3(meta.rdev()))
+    } else if file_type.is_block_device() {
+        (nfs3::ftype3::NF3BLK, rdev_to_specdata3(meta.rdev()))
+    } else if file_type.is_fifo() {
+        (nfs3::ftype3::NF3FIFO, nfs3::specdata3::default())
+    } else if file_type.is_socket() {
+        (nfs3::ftype3::NF3SOCK, nfs3::specdata3::default())
     } else {
-        nfs3::fattr3 {
-            ftype: nfs3::ftype3::NF3DIR,
-            mode: file_mode,
-            nlink: meta.nlink() as u32,
-            uid: meta.uid(),
-            gid: meta.gid(),
-            size,
-            used: size,
-            rdev: nfs3::specdata3::default(),
-            fsid: 0,
-            fileid: fid,
-            atime: nfs3::nfstime3 {
-                seconds: meta.atime() as u32,
-                nseconds: meta.atime_nsec() as u32,
-            },
-            mtime: nfs3::nfstime3 {
-                seconds: meta.mtime() as u32,
-                nseconds: meta.mtime_nsec() as u32,
-            },
-            ctime: nfs3::nfstime3 {
-                seconds: meta.ctime() as u32,
-                nseconds: meta.ctime_nsec() as u32,
-            },
-        }
+        (nfs3::ftype3::NF3DIR, nfs3::specdata3::default())
+    };
+    nfs3::fattr3 {
+        ftype,
+        mode: mode_unmask(meta.mode()),
+        nlink: meta.nlink() as u32,
+        uid: meta.uid(),
+        gid: meta.gid(),
+        size,
+        used: size,
+        rdev,
+        fsid: 0,
+        fileid: fid,
+        atime: nfs3::nfstime3 { seconds: meta.atime() as u32, nseconds: meta.atime_nsec() as u32 },
+        mtime: nfs3::nfstime3 { seconds: meta.mtime() as u32, nseconds: meta.mtime_nsec() as u32 },
+        ctime: nfs3::nfstime3 { seconds: meta.ctime() as u32, nseconds: meta.ctime_nsec() as u32 },
     }
 }
 
+/// Converts a device number to the major and minor numbers of NFS attributes
+///
+/// # Arguments
+///
+/// * `rdev` - Device number, as in `st_rdev`
+///
+/// # Returns
+///
+/// The major number as `specdata1` and the minor number as `specdata2`
+pub fn rdev_to_specdata3(rdev: u64) -> nfs3::specdata3 {
+    let rdev = rdev as libc::dev_t;
+    nfs3::specdata3 { specdata1: libc::major(rdev) as u32, specdata2: libc::minor(rdev) as u32 }
+}
+
+/// Converts the major and minor numbers of NFS attributes to a device number
+///
+/// # Arguments
+///
+/// * `spec` - Major number in `specdata1` and minor number in `specdata2`
+///
+/// # Returns
+///
+/// The device number, as passed to `mknod`
+pub fn specdata3_to_rdev(spec: nfs3::specdata3) -> u64 {
+    libc::makedev(spec.specdata1 as _, spec.specdata2 as _) as u64
+}
+
 /// Sets attributes of a file path based on NFS `SETATTR` operation
 ///
 /// This function applies the attributes specified in an NFS `SETATTR` request
@@ -0,0 +1,630 @@
+//! A read-only file system that serves the branches and tags of a Git repository.
+//!
+//! [`GitFS`] reads objects straight from a repository's object database, so any
+//! revision can be read without a checkout. Each branch and tag is a directory holding
+//! the tree of the commit it points to, and refs with `/` in their names are nested:
+//!
+//! ```text
+//! /branches/main/src/lib.rs
+//! /branches/feature/login/README.md    branch "feature/login"
+//! /tags/v1.2/Cargo.toml
+//! ```
+//!
+//! Blobs are served as files, with the executable bit and symbolic links taken from
+//! the tree entry modes. Submodules appear as empty directories, as in a checkout that
+//! has not initialized them. Everything in a tree takes the time of its commit.
+//!
+//! Refs are read again when they are older than the refresh interval, so a branch
+//! that moves shows the new commit. File IDs are derived from object ids: a blob has
+//! the same ID wherever it appears, so clients can share cached data between
+//! revisions, while a directory's ID also depends on its parent and name so that `..`
+//! is unambiguous. A branch that moves therefore gets a new directory, and handles
+//! into the old commit keep working until the server restarts. IDs are 48-bit hashes;
+//! should two objects hash to the same ID, the one met second fails with `NFS3ERR_IO`.
+//!
+//! To answer for the handles it has given out, a `GitFS` remembers every directory,
+//! file and symbolic link that has been looked up or listed, a hundred or so bytes
+//! each, until it is dropped. Serving a large history for a long time therefore takes
+//! memory in proportion to the number of objects clients have visited.
+//!
+//! ```ignore
+//! use fernfs::git_fs::GitFS;
+//! use fernfs::tcp::NFSTcpListener;
+//!
+//! let fs = GitFS::open("/srv/git/project.git")?;
+//! let listener = NFSTcpListener::bind("127.0.0.1:11111", fs).await?;
+//! ```
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+use async_trait::async_trait;
+use git2::{ObjectType, Oid, Repository, Tree, TreeEntry};
+use tracing::warn;
+
+use crate::vfs;
+use crate::xdr::nfs3;
+
+mod blob_cache;
+mod refs;
+
+use blob_cache::BlobCache;
+use refs::{RefEntry, Refs, Target, HEADS, TAGS};
+
+type NFSResult<T> = Result<T, nfs3::nfsstat3>;
+
+const ROOT_ID: nfs3::fileid3 = 1;
+const BRANCHES_ID: nfs3::fileid3 = 2;
+const TAGS_ID: nfs3::fileid3 = 3;
+/// Lowest file ID given to objects; the ones below are the fixed directories.
+const FIRST_OBJECT_ID: nfs3::fileid3 = 16;
+/// Bits of the IDs given to objects, leaving room for a
+/// [`CompositeFS`](crate::composite_fs::CompositeFS) to number its subtrees above them.
+const OBJECT_ID_BITS: u32 = 48;
+
+/// Default for [`GitFS::refresh_interval`]
+const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+/// Bytes of inflated blobs kept for reads
+const BLOB_CACHE_BYTES: usize = 64 << 20;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Kind {
+    Root,
+    /// Refs below a namespace such as `refs/heads/` or `refs/heads/feature/`
+    Refs(String),
+    Tree(Oid),
+    File(Oid),
+    Symlink(Oid),
+    /// A submodule, shown as an empty directory
+    Submodule(Oid),
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    kind: Kind,
+    parent: nfs3::fileid3,
+    /// Attributes of objects; the directories listing refs fill in times and link
+    /// counts from the current refs
+    attr: nfs3::fattr3,
+}
+
+#[derive(Debug)]
+struct State {
+    /// Every object handed out so far
+    nodes: HashMap<nfs3::fileid3, Node>,
+    refs: Refs,
+    /// When `refs` was read
+    loaded: Instant,
+    /// When the refs last changed, the modification time of the directories listing them
+    changed: nfs3::nfstime3,
+}
+
+impl State {
+    /// Adds a node to the table, unless it is there already.
+    ///
+    /// File IDs are truncated hashes, so two objects may end up with the same one;
+    /// such an ID is refused rather than handed out for both.
+    fn insert(&mut self, id: nfs3::fileid3, node: Node) -> NFSResult<()> {
+        match self.nodes.entry(id) {
+            Entry::Vacant(vacant) => {
+                vacant.insert(node);
+            }
+            Entry::Occupied(known) => {
+                let known = known.get();
+                if known.kind != node.kind || known.attr.mode != node.attr.mode {
+                    warn!("file id {} is shared by {:?} and {:?}", id, known.kind, node.kind);
+                    return Err(nfs3::nfsstat3::NFS3ERR_IO);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A read-only [`NFSFileSystem`](vfs::NFSFileSystem) serving the refs of a Git repository.
+///
+/// See the [module documentation](self) for an overview.
+pub struct GitFS {
+    repo: Mutex<Repository>,
+    state: RwLock<State>,
+    blobs: Mutex<BlobCache>,
+    refresh_interval: Duration,
+    uid: u32,
+    gid: u32,
+    /// When the repository was opened, the time of tags that point to bare trees
+    opened: nfs3::nfstime3,
+    generation: u64,
+}
+
+fn git_error(err: git2::Error) -> nfs3::nfsstat3 {
+    warn!("git repository error: {}", err);
+    nfs3::nfsstat3::NFS3ERR_IO
+}
+
+fn commit_time(seconds: i64) -> nfs3::nfstime3 {
+    nfs3::nfstime3 { seconds: seconds.clamp(0, u32::MAX as i64) as u32, nseconds: 0 }
+}
+
+fn now() -> nfs3::nfstime3 {
+    let since_epoch = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    nfs3::nfstime3 { seconds: since_epoch.as_secs() as u32, nseconds: since_epoch.subsec_nanos() }
+}
+
+/// Derives a file ID from an object id and `context`, which tells apart the uses of
+/// an object that must not share an ID.
+fn object_fileid(oid: Oid, context: &[u8]) -> nfs3::fileid3 {
+    let mut leading = [0; 8];
+    leading.copy_from_slice(&oid.as_bytes()[..8]);
+    let id = context.iter().fold(u64::from_be_bytes(leading), |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    (id & ((1 << OBJECT_ID_BITS) - 1)).max(FIRST_OBJECT_ID)
+}
+
+/// Derives the ID of directory `name` of `parent` holding tree (or submodule) `oid`.
+fn dir_fileid(parent: nfs3::fileid3, name: &[u8], oid: Oid) -> nfs3::fileid3 {
+    object_fileid(oid, &[&parent.to_be_bytes()[..], name].concat())
+}
+
+/// Number of entries of `tree` that are shown as directories
+fn subdirs(tree: &Tree) -> u32 {
+    let is_dir =
+        |entry: &TreeEntry| matches!(entry.kind(), Some(ObjectType::Tree | ObjectType::Commit));
+    tree.iter().filter(is_dir).count() as u32
+}
+
+impl GitFS {
+    /// Opens the repository at `path`, which may be bare or a working tree.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let repo = Repository::open(path.as_ref()).map_err(io::Error::other)?;
+        let meta = std::fs::metadata(repo.path())?;
+        let opened = now();
+        let refs = Refs::load(&repo, opened.seconds as i64).map_err(io::Error::other)?;
+        let generation = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let fs = Self {
+            repo: Mutex::new(repo),
+            state: RwLock::new(State {
+                nodes: HashMap::new(),
+                refs,
+                loaded: Instant::now(),
+                changed: opened,
+            }),
+            blobs: Mutex::new(BlobCache::new(BLOB_CACHE_BYTES)),
+            refresh_interval: DEFAULT_REFRESH_INTERVAL,
+            uid: meta.uid(),
+            gid: meta.gid(),
+            opened,
+            generation,
+        };
+        {
+            let mut state = fs.state.write().unwrap();
+            for (id, kind) in [
+                (ROOT_ID, Kind::Root),
+                (BRANCHES_ID, Kind::Refs(HEADS.to_string())),
+                (TAGS_ID, Kind::Refs(TAGS.to_string())),
+            ] {
+                state.nodes.insert(id, fs.dir_node(id, ROOT_ID, kind, 2, opened));
+            }
+        }
+        Ok(fs)
+    }
+
+    /// Sets how long the refs are trusted before the repository is checked for moved,
+    /// new or deleted branches and tags (one second by default).
+    pub fn refresh_interval(mut self, interval: Duration) -> Self {
+        self.refresh_interval = interval;
+        self
+    }
+
+    fn attr(
+        &self,
+        fileid: nfs3::fileid3,
+        ftype: nfs3::ftype3,
+        mode: u32,
+        size: u64,
+        nlink: u32,
+        time: nfs3::nfstime3,
+    ) -> nfs3::fattr3 {
+        nfs3::fattr3 {
+            ftype,
+            mode,
+            nlink,
+            uid: self.uid,
+            gid: self.gid,
+            size,
+            used: size,
+            fileid,
+            atime: time,
+            mtime: time,
+            ctime: time,
+            ..Default::default()
+        }
+    }
+
+    fn dir_node(
+        &self,
+        id: nfs3::fileid3,
+        parent: nfs3::fileid3,
+        kind: Kind,
+        nlink: u32,
+        time: nfs3::nfstime3,
+    ) -> Node {
+        Node { kind, parent, attr: self.attr(id, nfs3::ftype3::NF3DIR, 0o755, 0, nlink, time) }
+    }
+
+    fn node(&self, id: nfs3::fileid3) -> NFSResult<Node> {
+        self.state.read().unwrap().nodes.get(&id).cloned().ok_or(nfs3::nfsstat3::NFS3ERR_STALE)
+    }
+
+    /// Reads the refs again if they are older than the refresh interval.
+    fn refresh_refs(&self) -> NFSResult<()> {
+        if self.state.read().unwrap().loaded.elapsed() < self.refresh_interval {
+            return Ok(());
+        }
+        let repo = self.repo.lock().unwrap();
+        let refs = Refs::load(&repo, self.opened.seconds as i64).map_err(git_error)?;
+        let mut state = self.state.write().unwrap();
+        if refs != state.refs {
+            state.refs = refs;
+            state.changed = now();
+        }
+        state.loaded = Instant::now();
+        Ok(())
+    }
+
+    /// Returns the current attributes of `node`.
+    fn node_attr(&self, node: &Node) -> NFSResult<nfs3::fattr3> {
+        let prefix = match &node.kind {
+            Kind::Root => None,
+            Kind::Refs(prefix) => Some(prefix),
+            _ => return Ok(node.attr),
+        };
+        self.refresh_refs()?;
+        let state = self.state.read().unwrap();
+        // Everything listed in these directories is a directory.
+        let subdirs = prefix.map_or(2, |prefix| state.refs.entries(prefix).len() as u32);
+        Ok(nfs3::fattr3 {
+            nlink: 2 + subdirs,
+            atime: state.changed,
+            mtime: state.changed,
+            ctime: state.changed,
+            ..node.attr
+        })
+    }
+
+    /// Makes the node for an entry of the tree shown as directory `dirid`.
+    fn tree_child(
+        &self,
+        repo: &Repository,
+        dirid: nfs3::fileid3,
+        entry: &TreeEntry,
+        time: nfs3::nfstime3,
+    ) -> Result<(nfs3::fileid3, Node), git2::Error> {
+        let oid = entry.id();
+        match entry.kind() {
+            Some(ObjectType::Tree) => {
+                let id = dir_fileid(dirid, entry.name_bytes(), oid);
+                let nlink = 2 + subdirs(&repo.find_tree(oid)?);
+                Ok((id, self.dir_node(id, dirid, Kind::Tree(oid), nlink, time)))
+            }
+            Some(ObjectType::Commit) => {
+                let id = dir_fileid(dirid, entry.name_bytes(), oid);
+                Ok((id, self.dir_node(id, dirid, Kind::Submodule(oid), 2, time)))
+            }
+            _ => {
+                let size = repo.odb()?.read_header(oid)?.0 as u64;
+                let mode = entry.filemode() as u32;
+                let (id, kind, attr) = if mode & 0o170000 == 0o120000 {
+                    let id = object_fileid(oid, b"l");
+                    (
+                        id,
+                        Kind::Symlink(oid),
+                        self.attr(id, nfs3::ftype3::NF3LNK, 0o777, size, 1, time),
+                    )
+                } else if mode & 0o111 != 0 {
+                    let id = object_fileid(oid, b"x");
+                    (id, Kind::File(oid), self.attr(id, nfs3::ftype3::NF3REG, 0o755, size, 1, time))
+                } else {
+                    let id = object_fileid(oid, b"");
+                    (id, Kind::File(oid), self.attr(id, nfs3::ftype3::NF3REG, 0o644, size, 1, time))
+                };
+                Ok((id, Node { kind, parent: dirid, attr }))
+            }
+        }
+    }
+
+    /// Makes the node for an entry of the directory `dirid` listing refs.
+    fn ref_child(
+        &self,
+        repo: &Repository,
+        dirid: nfs3::fileid3,
+        prefix: &str,
+        entry: &RefEntry,
+    ) -> Result<(Vec<u8>, nfs3::fileid3, Node), git2::Error> {
+        match entry {
+            RefEntry::Ref(name, Target { tree, time }) => {
+                let id = dir_fileid(dirid, name.as_bytes(), *tree);
+                let nlink = 2 + subdirs(&repo.find_tree(*tree)?);
+                let node = self.dir_node(id, dirid, Kind::Tree(*tree), nlink, commit_time(*time));
+                Ok((name.as_bytes().to_vec(), id, node))
+            }
+            RefEntry::Namespace(name) => {
+                let namespace = format!("{prefix}{name}/");
+                let id = object_fileid(Oid::zero(), namespace.as_bytes());
+                let node = self.dir_node(id, dirid, Kind::Refs(namespace), 2, self.opened);
+                Ok((name.as_bytes().to_vec(), id, node))
+            }
+        }
+    }
+
+    /// Lists the entries of directory `dirid` in READDIR order, adding them to the
+    /// node table.
+    fn children(
+        &self,
+        dirid: nfs3::fileid3,
+        dir: &Node,
+    ) -> NFSResult<Vec<(Vec<u8>, nfs3::fileid3)>> {
+        let repo = self.repo.lock().unwrap();
+        let children = match &dir.kind {
+            Kind::Root => {
+                return Ok(vec![(b"branches".to_vec(), BRANCHES_ID), (b"tags".to_vec(), TAGS_ID)])
+            }
+            Kind::Refs(prefix) => {
+                drop(repo);
+                self.refresh_refs()?;
+                let entries = self.state.read().unwrap().refs.entries(prefix);
+                let repo = self.repo.lock().unwrap();
+                entries
+                    .iter()
+                    .map(|entry| self.ref_child(&repo, dirid, prefix, entry))
+                    .collect::<Result<Vec<_>, _>>()
+            }
+            Kind::Tree(oid) => repo.find_tree(*oid).and_then(|tree| {
+                tree.iter()
+                    .map(|entry| {
+                        let (id, node) = self.tree_child(&repo, dirid, &entry, dir.attr.mtime)?;
+                        Ok((entry.name_bytes().to_vec(), id, node))
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            }),
+            Kind::Submodule(_) => Ok(Vec::new()),
+            Kind::File(_) | Kind::Symlink(_) => return Err(nfs3::nfsstat3::NFS3ERR_NOTDIR),
+        }
+        .map_err(git_error)?;
+        let mut state = self.state.write().unwrap();
+        children
+            .into_iter()
+            .map(|(name, id, node)| {
+                state.insert(id, node)?;
+                Ok((name, id))
+            })
+            .collect()
+    }
+
+    /// Returns the contents of a blob, from the cache if it was read recently.
+    fn blob(&self, oid: Oid) -> NFSResult<Arc<[u8]>> {
+        if let Some(data) = self.blobs.lock().unwrap().get(oid) {
+            return Ok(data);
+        }
+        let data: Arc<[u8]> = {
+            let repo = self.repo.lock().unwrap();
+            let blob = repo.find_blob(oid).map_err(git_error)?;
+            blob.content().into()
+        };
+        self.blobs.lock().unwrap().insert(oid, data.clone());
+        Ok(data)
+    }
+}
+
+#[async_trait]
+impl vfs::NFSFileSystem for GitFS {
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn capabilities(&self) -> vfs::Capabilities {
+        vfs::Capabilities::ReadOnly
+    }
+
+    fn root_dir(&self) -> nfs3::fileid3 {
+        ROOT_ID
+    }
+
+    async fn lookup(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> NFSResult<nfs3::fileid3> {
+        let dir = self.node(dirid)?;
+        match &filename[..] {
+            b"." => return Ok(dirid),
+            b".." => return Ok(dir.parent),
+            _ => {}
+        }
+        if let Kind::Tree(oid) = dir.kind {
+            // Avoid building nodes for the whole tree.
+            let (id, node) = {
+                let repo = self.repo.lock().unwrap();
+                let tree = repo.find_tree(oid).map_err(git_error)?;
+                let entry = tree.get_name_bytes(filename).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?;
+                self.tree_child(&repo, dirid, &entry, dir.attr.mtime).map_err(git_error)?
+            };
+            self.state.write().unwrap().insert(id, node)?;
+            return Ok(id);
+        }
+        let children = self.children(dirid, &dir)?;
+        children
+            .into_iter()
+            .find(|(name, _)| name[..] == filename[..])
+            .map(|(_, id)| id)
+            .ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)
+    }
+
+    async fn getattr(&self, id: nfs3::fileid3) -> NFSResult<nfs3::fattr3> {
+        self.node_attr(&self.node(id)?)
+    }
+
+    async fn setattr(&self, _id: nfs3::fileid3, _setattr: nfs3::sattr3) -> NFSResult<nfs3::fattr3> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn read(&self, id: nfs3::fileid3, offset: u64, count: u32) -> NFSResult<(Vec<u8>, bool)> {
+        let oid = match self.node(id)?.kind {
+            Kind::File(oid) => oid,
+            Kind::Symlink(_) => return Err(nfs3::nfsstat3::NFS3ERR_INVAL),
+            _ => return Err(nfs3::nfsstat3::NFS3ERR_ISDIR),
+        };
+        let data = self.blob(oid)?;
+        let start = offset.min(data.len() as u64) as usize;
+        let end = offset.saturating_add(count as u64).min(data.len() as u64) as usize;
+        Ok((data[start..end].to_vec(), end == data.len()))
+    }
+
+    async fn write(
+        &self,
+        _id: nfs3::fileid3,
+        _offset: u64,
+        _data: &[u8],
+        _stable: nfs3::file::stable_how,
+    ) -> NFSResult<(nfs3::fattr3, nfs3::file::stable_how, nfs3::count3)> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn create(
+        &self,
+        _dirid: nfs3::fileid3,
+        _filename: &nfs3::filename3,
+        _attr: nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn create_exclusive(
+        &self,
+        _dirid: nfs3::fileid3,
+        _filename: &nfs3::filename3,
+        _verifier: nfs3::createverf3,
+    ) -> NFSResult<nfs3::fileid3> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn mkdir(
+        &self,
+        _dirid: nfs3::fileid3,
+        _dirname: &nfs3::filename3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn remove(&self, _dirid: nfs3::fileid3, _filename: &nfs3::filename3) -> NFSResult<()> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn rename(
+        &self,
+        _from_dirid: nfs3::fileid3,
+        _from_filename: &nfs3::filename3,
+        _to_dirid: nfs3::fileid3,
+        _to_filename: &nfs3::filename3,
+    ) -> NFSResult<()> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn readdir(
+        &self,
+        dirid: nfs3::fileid3,
+        start_after: nfs3::fileid3,
+        max_entries: usize,
+    ) -> NFSResult<vfs::ReadDirResult> {
+        let dir = self.node(dirid)?;
+        let entries = self.children(dirid, &dir)?;
+        // Cookies are indices into ".", "..", then the entries in tree or ref name order.
+        let total = entries.len() + 2;
+        let start = usize::try_from(start_after).map_err(|_| nfs3::nfsstat3::NFS3ERR_BAD_COOKIE)?;
+        if start > total {
+            return Err(nfs3::nfsstat3::NFS3ERR_BAD_COOKIE);
+        }
+        let end = total.min(start.saturating_add(max_entries));
+        let mut result = vfs::ReadDirResult { entries: Vec::new(), end: end == total };
+        for index in start..end {
+            let (name, fileid): (&[u8], _) = match index {
+                0 => (b".", dirid),
+                1 => (b"..", dir.parent),
+                _ => {
+                    let (name, fileid) = &entries[index - 2];
+                    (name, *fileid)
+                }
+            };
+            let attr = self.node_attr(&self.node(fileid)?)?;
+            result.entries.push(vfs::DirEntry { fileid, name: name.into(), attr });
+        }
+        Ok(result)
+    }
+
+    async fn readdir_index(
+        &self,
+        dirid: nfs3::fileid3,
+        start_index: usize,
+        max_entries: usize,
+    ) -> NFSResult<vfs::ReadDirResult> {
+        self.readdir(dirid, start_index as nfs3::fileid3, max_entries).await
+    }
+
+    async fn symlink(
+        &self,
+        _dirid: nfs3::fileid3,
+        _linkname: &nfs3::filename3,
+        _symlink: &nfs3::nfspath3,
+        _attr: &nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn readlink(&self, id: nfs3::fileid3) -> NFSResult<nfs3::nfspath3> {
+        match self.node(id)?.kind {
+            Kind::Symlink(oid) => Ok(self.blob(oid)?.to_vec().into()),
+            _ => Err(nfs3::nfsstat3::NFS3ERR_INVAL),
+        }
+    }
+
+    async fn link(
+        &self,
+        _file_id: nfs3::fileid3,
+        _link_dir_id: nfs3::fileid3,
+        _link_name: &nfs3::filename3,
+    ) -> NFSResult<nfs3::fattr3> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn mknod(
+        &self,
+        _dir_id: nfs3::fileid3,
+        _name: &nfs3::filename3,
+        _ftype: nfs3::ftype3,
+        _specdata: nfs3::specdata3,
+        _attrs: &nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn commit(
+        &self,
+        _file_id: nfs3::fileid3,
+        _offset: u64,
+        _count: u32,
+    ) -> NFSResult<nfs3::fattr3> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    fn fsinfo_properties(&self) -> u32 {
+        nfs3::fs::FSF_SYMLINK | nfs3::fs::FSF_HOMOGENEOUS
+    }
+}
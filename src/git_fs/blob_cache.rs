@@ -0,0 +1,45 @@
+//! Contents of recently read blobs.
+//!
+//! Blobs are stored compressed, often as deltas, so they cannot be read at an offset.
+//! Clients read large files in many small requests; keeping the inflated blob around
+//! means each one is only inflated once.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use git2::Oid;
+
+/// Blobs in least recently used order, holding at most `max_bytes` unless a single
+/// blob is larger than that.
+#[derive(Debug)]
+pub struct BlobCache {
+    blobs: VecDeque<(Oid, Arc<[u8]>)>,
+    bytes: usize,
+    max_bytes: usize,
+}
+
+impl BlobCache {
+    pub fn new(max_bytes: usize) -> Self {
+        Self { blobs: VecDeque::new(), bytes: 0, max_bytes }
+    }
+
+    pub fn get(&mut self, oid: Oid) -> Option<Arc<[u8]>> {
+        let index = self.blobs.iter().position(|(cached, _)| *cached == oid)?;
+        let entry = self.blobs.remove(index)?;
+        let data = entry.1.clone();
+        self.blobs.push_back(entry);
+        Some(data)
+    }
+
+    pub fn insert(&mut self, oid: Oid, data: Arc<[u8]>) {
+        if self.blobs.iter().any(|(cached, _)| *cached == oid) {
+            return;
+        }
+        self.bytes += data.len();
+        self.blobs.push_back((oid, data));
+        while self.bytes > self.max_bytes && self.blobs.len() > 1 {
+            let (_, evicted) = self.blobs.pop_front().unwrap();
+            self.bytes -= evicted.len();
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! The branches and tags of a repository at one point in time.
+
+use std::collections::BTreeMap;
+
+use git2::{Oid, Repository};
+use tracing::debug;
+
+/// Namespace of the refs exported under `/branches`
+pub const HEADS: &str = "refs/heads/";
+/// Namespace of the refs exported under `/tags`
+pub const TAGS: &str = "refs/tags/";
+
+/// What a branch or tag resolves to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Target {
+    pub tree: Oid,
+    /// Commit time in seconds since the epoch, given to everything in the tree
+    pub time: i64,
+}
+
+/// An entry of a directory listing refs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefEntry {
+    /// A branch or tag, which is the directory of its tree
+    Ref(String, Target),
+    /// A deeper namespace, such as `feature` for the branch `feature/login`
+    Namespace(String),
+}
+
+/// Branches and tags by full ref name, such as `refs/heads/main`
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Refs {
+    targets: BTreeMap<String, Target>,
+}
+
+impl Refs {
+    /// Reads the branches and tags of `repo`. Tags are peeled to the commit or tree
+    /// they point to; refs to anything else, and refs whose names are not UTF-8, are
+    /// skipped. `fallback_time` is used for tags of trees, which carry no time.
+    pub fn load(repo: &Repository, fallback_time: i64) -> Result<Self, git2::Error> {
+        let mut targets = BTreeMap::new();
+        for reference in repo.references()? {
+            let reference = reference?;
+            let Some(name) = reference.name() else {
+                continue;
+            };
+            if !name.starts_with(HEADS) && !name.starts_with(TAGS) {
+                continue;
+            }
+            let target = match reference.peel_to_commit() {
+                Ok(commit) => Target { tree: commit.tree_id(), time: commit.time().seconds() },
+                Err(_) => match reference.peel_to_tree() {
+                    Ok(tree) => Target { tree: tree.id(), time: fallback_time },
+                    Err(err) => {
+                        debug!("skipping {name}: {err}");
+                        continue;
+                    }
+                },
+            };
+            targets.insert(name.to_string(), target);
+        }
+        Ok(Self { targets })
+    }
+
+    /// Lists the refs and namespaces directly below `prefix`, which ends in `/`, in
+    /// name order.
+    pub fn entries(&self, prefix: &str) -> Vec<RefEntry> {
+        let mut entries = Vec::new();
+        let below = self.targets.range(prefix.to_string()..);
+        for (name, target) in below.take_while(|(name, _)| name.starts_with(prefix)) {
+            let rest = &name[prefix.len()..];
+            match rest.split_once('/') {
+                None => entries.push(RefEntry::Ref(rest.to_string(), *target)),
+                // Names sharing a namespace are adjacent in sorted order.
+                Some((namespace, _)) => {
+                    if entries.last() != Some(&RefEntry::Namespace(namespace.to_string())) {
+                        entries.push(RefEntry::Namespace(namespace.to_string()));
+                    }
+                }
+            }
+        }
+        entries
+    }
+}
@@ -26,6 +26,47 @@
 //!
 //! - `fs_util`: Utility functions for working with file systems.
 //!
+//! - `mirror_fs`: A ready-made `NFSFileSystem` that exports a local directory.
+//!
+//! - `mem_fs`: An in-memory `NFSFileSystem` with size limits and snapshots, for test fixtures
+//!   and scratch exports.
+//!
+//! - `archive_fs`: A read-only `NFSFileSystem` that serves tar, tar.gz, tar.zst and zip files
+//!   without unpacking them (requires the `archive` feature).
+//!
+//! - `union_fs`: An `NFSFileSystem` that stacks a writable file system over read-only ones,
+//!   with copy-up and whiteouts.
+//!
+//! - `composite_fs`: An `NFSFileSystem` that grafts other file systems onto directories of a
+//!   parent tree, like bind mounts.
+//!
+//! - `subtree_fs`: An `NFSFileSystem` that exports one directory of another file system as its
+//!   root, rejecting handles for anything outside it.
+//!
+//! - `fault_fs`: An `NFSFileSystem` wrapper that injects errors, latency, dropped replies and
+//!   short reads and writes, for testing clients.
+//!
+//! - `crypt_fs`: An `NFSFileSystem` wrapper that encrypts file contents, and optionally names,
+//!   before they reach another file system (requires the `encryption` feature).
+//!
+//! - `compress_fs`: An `NFSFileSystem` wrapper that stores file contents in compressed chunks
+//!   with random access (requires the `compression` feature).
+//!
+//! - `dedup_fs`: An `NFSFileSystem` stored in a local directory that keeps each distinct
+//!   content-defined chunk of file data once (requires the `dedup` feature).
+//!
+//! - `git_fs`: A read-only `NFSFileSystem` that serves the branches and tags of a Git
+//!   repository as directories (requires the `git` feature).
+//!
+//! - `object_fs`: An `NFSFileSystem` that serves an S3-compatible object store through a small
+//!   `ObjectStore` trait, with a local-directory store for offline use.
+//!
+//! - `trace_fs`: An `NFSFileSystem` wrapper that records every call to a trace file, and a
+//!   replayer that runs a trace against another backend and diffs the results.
+//!
+//! - `conformance`: Reusable checks that drive any `NFSFileSystem` through the `NFSv3`
+//!   handlers (requires the `conformance` feature).
+//!
 //! ## Standards Compliance
 //!
 //! This implementation follows these RFCs:
@@ -45,6 +86,36 @@ mod write_counter;
 #[cfg(not(target_os = "windows"))]
 pub mod fs_util;
 
+#[cfg(not(target_os = "windows"))]
+pub mod mirror_fs;
+
+pub mod mem_fs;
+
+#[cfg(all(feature = "archive", not(target_os = "windows")))]
+pub mod archive_fs;
+
+#[cfg(feature = "conformance")]
+pub mod conformance;
+
+#[cfg(feature = "compression")]
+pub mod compress_fs;
+
+#[cfg(feature = "encryption")]
+pub mod crypt_fs;
+
+#[cfg(feature = "dedup")]
+pub mod dedup_fs;
+
+#[cfg(all(feature = "git", not(target_os = "windows")))]
+pub mod git_fs;
+
+pub mod composite_fs;
+pub mod fault_fs;
+pub mod object_fs;
+pub mod subtree_fs;
+pub mod trace_fs;
+pub mod union_fs;
+
 pub mod tcp;
 pub mod vfs;
 
@@ -0,0 +1,735 @@
+//! An in-memory file system.
+//!
+//! [`MemFS`] keeps every object in a hash table keyed by file ID, with each directory
+//! holding a name index, so lookups are constant time regardless of tree size. It
+//! supports hard links, symbolic links and special files with POSIX link counts and
+//! change times, stores files sparsely in fixed-size blocks, and can enforce limits on
+//! total data, object count and file size. The whole tree can be saved to a snapshot
+//! file and restored later, which makes it suitable both for test fixtures and for
+//! ephemeral scratch exports.
+//!
+//! ```ignore
+//! use fernfs::mem_fs::MemFS;
+//!
+//! let fs = MemFS::builder().max_bytes(1 << 30).max_inodes(100_000).build();
+//! // ... serve it, then keep the contents for next time:
+//! fs.save_snapshot("/var/lib/scratch.snapshot")?;
+//! let restored = MemFS::builder().load_snapshot("/var/lib/scratch.snapshot")?;
+//! ```
+//!
+//! Access times only change through SETATTR, as on a `noatime` mount.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use indexmap::IndexMap;
+
+use crate::vfs;
+use crate::xdr::nfs3;
+
+mod inode;
+mod options;
+mod snapshot;
+
+use inode::{Contents, Inode, SparseData, BLOCK_SIZE};
+use options::Limits;
+pub use options::MemFSBuilder;
+
+/// File ID of the root directory.
+const ROOT_ID: nfs3::fileid3 = 1;
+/// Longest accepted file name, in bytes.
+const NAME_MAX: usize = 255;
+
+type NFSResult<T> = Result<T, nfs3::nfsstat3>;
+
+fn now() -> nfs3::nfstime3 {
+    let since_epoch = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    nfs3::nfstime3 { seconds: since_epoch.as_secs() as u32, nseconds: since_epoch.subsec_nanos() }
+}
+
+/// Rejects names that cannot be created in a directory.
+fn check_new_name(name: &[u8]) -> NFSResult<()> {
+    if name.is_empty() || name.contains(&b'/') || name.contains(&0) {
+        return Err(nfs3::nfsstat3::NFS3ERR_INVAL);
+    }
+    if name.len() > NAME_MAX {
+        return Err(nfs3::nfsstat3::NFS3ERR_NAMETOOLONG);
+    }
+    if name == b"." || name == b".." {
+        return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
+    }
+    Ok(())
+}
+
+/// Rejects `.` and `..` as the object of REMOVE or RENAME.
+fn check_existing_name(name: &[u8]) -> NFSResult<()> {
+    if name == b"." || name == b".." {
+        return Err(nfs3::nfsstat3::NFS3ERR_INVAL);
+    }
+    Ok(())
+}
+
+/// The mutable contents of a [`MemFS`].
+#[derive(Debug)]
+struct State {
+    inodes: HashMap<nfs3::fileid3, Inode>,
+    next_id: nfs3::fileid3,
+    /// Sum of allocated file blocks, charged against `Limits::max_bytes`
+    used_bytes: u64,
+}
+
+impl State {
+    fn inode(&self, id: nfs3::fileid3) -> NFSResult<&Inode> {
+        self.inodes.get(&id).ok_or(nfs3::nfsstat3::NFS3ERR_STALE)
+    }
+
+    fn inode_mut(&mut self, id: nfs3::fileid3) -> NFSResult<&mut Inode> {
+        self.inodes.get_mut(&id).ok_or(nfs3::nfsstat3::NFS3ERR_STALE)
+    }
+
+    fn lookup(&self, dirid: nfs3::fileid3, name: &[u8]) -> NFSResult<nfs3::fileid3> {
+        let dir = self.inode(dirid)?;
+        let entries = dir.entries()?;
+        match name {
+            b"." => Ok(dirid),
+            b".." => Ok(dir.parent),
+            _ => entries.get(name).copied().ok_or(nfs3::nfsstat3::NFS3ERR_NOENT),
+        }
+    }
+
+    /// Records a change to a directory's entries.
+    fn touch_dir(&mut self, dirid: nfs3::fileid3, time: nfs3::nfstime3) {
+        if let Some(dir) = self.inodes.get_mut(&dirid) {
+            dir.attr.mtime = time;
+            dir.attr.ctime = time;
+        }
+    }
+
+    /// Adds a new object named `name` to `dirid`.
+    fn insert(
+        &mut self,
+        limits: &Limits,
+        dirid: nfs3::fileid3,
+        name: &[u8],
+        mut attr: nfs3::fattr3,
+        contents: Contents,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        check_new_name(name)?;
+        if self.inode(dirid)?.entries()?.contains_key(name) {
+            return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
+        }
+        if limits.max_inodes.is_some_and(|max| self.inodes.len() as u64 >= max) {
+            return Err(nfs3::nfsstat3::NFS3ERR_NOSPC);
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        attr.fileid = id;
+        let is_dir = matches!(contents, Contents::Directory(_));
+        let time = attr.ctime;
+        self.inodes.insert(id, Inode { attr, parent: dirid, verifier: None, contents });
+
+        let dir = self.inode_mut(dirid)?;
+        dir.entries_mut()?.insert(name.to_vec(), id);
+        if is_dir {
+            dir.attr.nlink += 1;
+        }
+        self.touch_dir(dirid, time);
+        Ok((id, attr))
+    }
+
+    /// Drops one link to `id`, freeing the inode once nothing refers to it.
+    fn unlink(&mut self, id: nfs3::fileid3, time: nfs3::nfstime3) {
+        let Some(inode) = self.inodes.get_mut(&id) else {
+            return;
+        };
+        if inode.is_dir() {
+            let parent = inode.parent;
+            self.inodes.remove(&id);
+            if let Some(dir) = self.inodes.get_mut(&parent) {
+                dir.attr.nlink -= 1;
+            }
+            return;
+        }
+        inode.attr.nlink -= 1;
+        inode.attr.ctime = time;
+        if inode.attr.nlink == 0 {
+            let freed = inode.allocated();
+            self.inodes.remove(&id);
+            self.used_bytes -= freed;
+        }
+    }
+
+    /// Returns true if `id` is `ancestor` or lies somewhere below it.
+    fn is_within(&self, mut id: nfs3::fileid3, ancestor: nfs3::fileid3) -> bool {
+        loop {
+            if id == ancestor {
+                return true;
+            }
+            match self.inodes.get(&id) {
+                Some(inode) if id != ROOT_ID => id = inode.parent,
+                _ => return false,
+            }
+        }
+    }
+
+    /// Changes the size of a regular file, charging or refunding allocated blocks.
+    fn resize(&mut self, limits: &Limits, id: nfs3::fileid3, size: u64) -> NFSResult<()> {
+        if size > limits.max_file_size {
+            return Err(nfs3::nfsstat3::NFS3ERR_FBIG);
+        }
+        let inode = self.inode_mut(id)?;
+        let Contents::File(data) = &mut inode.contents else {
+            return Err(if inode.is_dir() {
+                nfs3::nfsstat3::NFS3ERR_ISDIR
+            } else {
+                nfs3::nfsstat3::NFS3ERR_INVAL
+            });
+        };
+        let before = data.allocated();
+        data.truncate(size);
+        let after = data.allocated();
+        inode.attr.size = size;
+        inode.attr.used = after;
+        self.used_bytes -= before - after;
+        Ok(())
+    }
+
+    fn setattr(
+        &mut self,
+        limits: &Limits,
+        id: nfs3::fileid3,
+        setattr: &nfs3::sattr3,
+    ) -> NFSResult<nfs3::fattr3> {
+        let time = now();
+        if let Some(size) = setattr.size {
+            if self.inode(id)?.attr.size != size {
+                self.resize(limits, id, size)?;
+                self.inode_mut(id)?.attr.mtime = time;
+            }
+        }
+        let inode = self.inode_mut(id)?;
+        if let Some(mode) = setattr.mode {
+            inode.attr.mode = mode & 0o7777;
+        }
+        if let Some(uid) = setattr.uid {
+            inode.attr.uid = uid;
+        }
+        if let Some(gid) = setattr.gid {
+            inode.attr.gid = gid;
+        }
+        match setattr.atime {
+            nfs3::set_atime::DONT_CHANGE => {}
+            nfs3::set_atime::SET_TO_SERVER_TIME => inode.attr.atime = time,
+            nfs3::set_atime::SET_TO_CLIENT_TIME(t) => inode.attr.atime = t,
+        }
+        match setattr.mtime {
+            nfs3::set_mtime::DONT_CHANGE => {}
+            nfs3::set_mtime::SET_TO_SERVER_TIME => inode.attr.mtime = time,
+            nfs3::set_mtime::SET_TO_CLIENT_TIME(t) => inode.attr.mtime = t,
+        }
+        inode.attr.ctime = time;
+        Ok(inode.attr)
+    }
+}
+
+/// An in-memory [`NFSFileSystem`](vfs::NFSFileSystem).
+///
+/// See the [module documentation](self) for an overview.
+#[derive(Debug)]
+pub struct MemFS {
+    state: RwLock<State>,
+    limits: Limits,
+    generation: u64,
+    /// Default owner of new objects when the client does not set one
+    uid: nfs3::uid3,
+    gid: nfs3::gid3,
+}
+
+impl Default for MemFS {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemFS {
+    /// Creates an empty file system without limits.
+    pub fn new() -> Self {
+        MemFSBuilder::new().build()
+    }
+
+    /// Starts configuring a new file system.
+    pub fn builder() -> MemFSBuilder {
+        MemFSBuilder::new()
+    }
+
+    fn empty(limits: Limits, uid: nfs3::uid3, gid: nfs3::gid3, root_mode: nfs3::mode3) -> Self {
+        let time = now();
+        let root = Inode {
+            attr: nfs3::fattr3 {
+                ftype: nfs3::ftype3::NF3DIR,
+                mode: root_mode,
+                nlink: 2,
+                uid,
+                gid,
+                size: BLOCK_SIZE,
+                fileid: ROOT_ID,
+                atime: time,
+                mtime: time,
+                ctime: time,
+                ..Default::default()
+            },
+            parent: ROOT_ID,
+            verifier: None,
+            contents: Contents::Directory(IndexMap::new()),
+        };
+        let state =
+            State { inodes: HashMap::from([(ROOT_ID, root)]), next_id: ROOT_ID + 1, used_bytes: 0 };
+        Self::from_state(state, limits, uid, gid)
+    }
+
+    fn from_state(state: State, limits: Limits, uid: nfs3::uid3, gid: nfs3::gid3) -> Self {
+        let generation = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        Self { state: RwLock::new(state), limits, generation, uid, gid }
+    }
+
+    /// Writes the whole tree to `path`.
+    ///
+    /// The snapshot is written to a temporary file next to `path` and renamed into place,
+    /// so an existing snapshot is never left half-written.
+    pub fn save_snapshot(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp_path = path.with_file_name(tmp_name);
+        let mut file = io::BufWriter::new(std::fs::File::create(&tmp_path)?);
+        self.write_snapshot(&mut file)?;
+        file.into_inner().map_err(io::IntoInnerError::into_error)?.sync_all()?;
+        std::fs::rename(&tmp_path, path)
+    }
+
+    /// Serializes the whole tree to `dest`; see [`MemFSBuilder::read_snapshot`].
+    pub fn write_snapshot(&self, dest: &mut impl Write) -> io::Result<()> {
+        snapshot::write(&self.read_state(), dest)
+    }
+
+    /// Returns the number of bytes of file data currently allocated.
+    pub fn used_bytes(&self) -> u64 {
+        self.read_state().used_bytes
+    }
+
+    fn read_state(&self) -> RwLockReadGuard<'_, State> {
+        self.state.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn write_state(&self) -> RwLockWriteGuard<'_, State> {
+        self.state.write().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Attributes for a new object, before `sattr3` overrides are applied.
+    fn new_attr(
+        &self,
+        ftype: nfs3::ftype3,
+        mode: nfs3::mode3,
+        attr: &nfs3::sattr3,
+    ) -> nfs3::fattr3 {
+        let time = now();
+        let mut new = nfs3::fattr3 {
+            ftype,
+            mode: attr.mode.unwrap_or(mode) & 0o7777,
+            nlink: if matches!(ftype, nfs3::ftype3::NF3DIR) { 2 } else { 1 },
+            uid: attr.uid.unwrap_or(self.uid),
+            gid: attr.gid.unwrap_or(self.gid),
+            atime: time,
+            mtime: time,
+            ctime: time,
+            ..Default::default()
+        };
+        if let nfs3::set_atime::SET_TO_CLIENT_TIME(t) = attr.atime {
+            new.atime = t;
+        }
+        if let nfs3::set_mtime::SET_TO_CLIENT_TIME(t) = attr.mtime {
+            new.mtime = t;
+        }
+        new
+    }
+}
+
+#[async_trait]
+impl vfs::NFSFileSystem for MemFS {
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn capabilities(&self) -> vfs::Capabilities {
+        vfs::Capabilities::ReadWrite
+    }
+
+    fn root_dir(&self) -> nfs3::fileid3 {
+        ROOT_ID
+    }
+
+    async fn lookup(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> NFSResult<nfs3::fileid3> {
+        self.read_state().lookup(dirid, filename)
+    }
+
+    async fn getattr(&self, id: nfs3::fileid3) -> NFSResult<nfs3::fattr3> {
+        Ok(self.read_state().inode(id)?.attr)
+    }
+
+    async fn setattr(&self, id: nfs3::fileid3, setattr: nfs3::sattr3) -> NFSResult<nfs3::fattr3> {
+        self.write_state().setattr(&self.limits, id, &setattr)
+    }
+
+    async fn read(&self, id: nfs3::fileid3, offset: u64, count: u32) -> NFSResult<(Vec<u8>, bool)> {
+        let state = self.read_state();
+        let inode = state.inode(id)?;
+        match &inode.contents {
+            Contents::File(data) => {
+                let size = inode.attr.size;
+                let bytes = data.read(offset, count, size);
+                let eof = offset.saturating_add(bytes.len() as u64) >= size;
+                Ok((bytes, eof))
+            }
+            Contents::Directory(_) => Err(nfs3::nfsstat3::NFS3ERR_ISDIR),
+            _ => Err(nfs3::nfsstat3::NFS3ERR_INVAL),
+        }
+    }
+
+    async fn write(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+        _stable: nfs3::file::stable_how,
+    ) -> NFSResult<(nfs3::fattr3, nfs3::file::stable_how, nfs3::count3)> {
+        let end = offset.checked_add(data.len() as u64).ok_or(nfs3::nfsstat3::NFS3ERR_FBIG)?;
+        if end > self.limits.max_file_size {
+            return Err(nfs3::nfsstat3::NFS3ERR_FBIG);
+        }
+        let mut state = self.write_state();
+        let used_bytes = state.used_bytes;
+        let inode = state.inode_mut(id)?;
+        let contents = match &mut inode.contents {
+            Contents::File(contents) => contents,
+            Contents::Directory(_) => return Err(nfs3::nfsstat3::NFS3ERR_ISDIR),
+            _ => return Err(nfs3::nfsstat3::NFS3ERR_INVAL),
+        };
+        let needed = contents.blocks_needed(offset, data.len() as u64) * BLOCK_SIZE;
+        if self.limits.max_bytes.is_some_and(|max| used_bytes + needed > max) {
+            return Err(nfs3::nfsstat3::NFS3ERR_NOSPC);
+        }
+        contents.write(offset, data);
+        let time = now();
+        inode.attr.size = inode.attr.size.max(end);
+        inode.attr.used = contents.allocated();
+        inode.attr.mtime = time;
+        inode.attr.ctime = time;
+        let attr = inode.attr;
+        state.used_bytes += needed;
+        // Memory is as stable as this file system gets.
+        Ok((attr, nfs3::file::stable_how::FILE_SYNC, data.len() as nfs3::count3))
+    }
+
+    async fn create(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        attr: nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        // A requested initial size is just a hole; nothing is allocated.
+        let size = attr.size.unwrap_or(0);
+        if size > self.limits.max_file_size {
+            return Err(nfs3::nfsstat3::NFS3ERR_FBIG);
+        }
+        let mut new_attr = self.new_attr(nfs3::ftype3::NF3REG, 0o644, &attr);
+        new_attr.size = size;
+        self.write_state().insert(
+            &self.limits,
+            dirid,
+            filename,
+            new_attr,
+            Contents::File(SparseData::default()),
+        )
+    }
+
+    async fn create_exclusive(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        verifier: nfs3::createverf3,
+    ) -> NFSResult<nfs3::fileid3> {
+        let mut state = self.write_state();
+        if let Ok(existing) = state.lookup(dirid, filename) {
+            // A retransmitted request finds the file it created the first time.
+            if state.inode(existing)?.verifier == Some(verifier) {
+                return Ok(existing);
+            }
+            return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
+        }
+        let attr = self.new_attr(nfs3::ftype3::NF3REG, 0o644, &nfs3::sattr3::default());
+        let (id, _) = state.insert(
+            &self.limits,
+            dirid,
+            filename,
+            attr,
+            Contents::File(SparseData::default()),
+        )?;
+        state.inode_mut(id)?.verifier = Some(verifier);
+        Ok(id)
+    }
+
+    async fn mkdir(
+        &self,
+        dirid: nfs3::fileid3,
+        dirname: &nfs3::filename3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        let mut attr = self.new_attr(nfs3::ftype3::NF3DIR, 0o755, &nfs3::sattr3::default());
+        attr.size = BLOCK_SIZE;
+        self.write_state().insert(
+            &self.limits,
+            dirid,
+            dirname,
+            attr,
+            Contents::Directory(IndexMap::new()),
+        )
+    }
+
+    async fn remove(&self, dirid: nfs3::fileid3, filename: &nfs3::filename3) -> NFSResult<()> {
+        check_existing_name(filename)?;
+        let mut state = self.write_state();
+        let id = state.lookup(dirid, filename)?;
+        if state.inode(id)?.entries().is_ok_and(|entries| !entries.is_empty()) {
+            return Err(nfs3::nfsstat3::NFS3ERR_NOTEMPTY);
+        }
+        let time = now();
+        state.inode_mut(dirid)?.entries_mut()?.shift_remove(&filename[..]);
+        state.touch_dir(dirid, time);
+        state.unlink(id, time);
+        Ok(())
+    }
+
+    async fn rename(
+        &self,
+        from_dirid: nfs3::fileid3,
+        from_filename: &nfs3::filename3,
+        to_dirid: nfs3::fileid3,
+        to_filename: &nfs3::filename3,
+    ) -> NFSResult<()> {
+        check_existing_name(from_filename)?;
+        check_existing_name(to_filename)?;
+        check_new_name(to_filename)?;
+        let mut state = self.write_state();
+        let id = state.lookup(from_dirid, from_filename)?;
+        let is_dir = state.inode(id)?.is_dir();
+        let existing = state.inode(to_dirid)?.entries()?.get(&to_filename[..]).copied();
+        if is_dir && state.is_within(to_dirid, id) {
+            // A directory cannot be moved underneath itself.
+            return Err(nfs3::nfsstat3::NFS3ERR_INVAL);
+        }
+        if let Some(target) = existing {
+            if target == id {
+                // Both names already refer to the same object.
+                return Ok(());
+            }
+            let target = state.inode(target)?;
+            match (is_dir, target.entries()) {
+                (true, Ok(entries)) if !entries.is_empty() => {
+                    return Err(nfs3::nfsstat3::NFS3ERR_NOTEMPTY)
+                }
+                (true, Err(_)) => return Err(nfs3::nfsstat3::NFS3ERR_NOTDIR),
+                (false, Ok(_)) => return Err(nfs3::nfsstat3::NFS3ERR_ISDIR),
+                _ => {}
+            }
+        }
+
+        let time = now();
+        if let Some(target) = existing {
+            state.unlink(target, time);
+        }
+        state.inode_mut(from_dirid)?.entries_mut()?.shift_remove(&from_filename[..]);
+        // An overwritten name keeps its position in the listing.
+        state.inode_mut(to_dirid)?.entries_mut()?.insert(to_filename.to_vec(), id);
+        if is_dir && from_dirid != to_dirid {
+            state.inode_mut(from_dirid)?.attr.nlink -= 1;
+            state.inode_mut(to_dirid)?.attr.nlink += 1;
+        }
+        let inode = state.inode_mut(id)?;
+        if is_dir {
+            inode.parent = to_dirid;
+        }
+        inode.attr.ctime = time;
+        state.touch_dir(from_dirid, time);
+        state.touch_dir(to_dirid, time);
+        Ok(())
+    }
+
+    async fn readdir(
+        &self,
+        dirid: nfs3::fileid3,
+        start_after: nfs3::fileid3,
+        max_entries: usize,
+    ) -> NFSResult<vfs::ReadDirResult> {
+        let state = self.read_state();
+        let dir = state.inode(dirid)?;
+        let entries = dir.entries()?;
+        // Cookies are indices into ".", "..", then the entries in insertion order.
+        let total = entries.len() + 2;
+        let start = usize::try_from(start_after).map_err(|_| nfs3::nfsstat3::NFS3ERR_BAD_COOKIE)?;
+        if start > total {
+            return Err(nfs3::nfsstat3::NFS3ERR_BAD_COOKIE);
+        }
+        let end = total.min(start.saturating_add(max_entries));
+        let mut result = vfs::ReadDirResult { entries: Vec::new(), end: end == total };
+        for index in start..end {
+            let (name, fileid): (&[u8], _) = match index {
+                0 => (b".", dirid),
+                1 => (b"..", dir.parent),
+                _ => {
+                    let (name, fileid) =
+                        entries.get_index(index - 2).ok_or(nfs3::nfsstat3::NFS3ERR_IO)?;
+                    (name, *fileid)
+                }
+            };
+            let attr = state.inode(fileid).map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?.attr;
+            result.entries.push(vfs::DirEntry { fileid, name: name.into(), attr });
+        }
+        Ok(result)
+    }
+
+    async fn readdir_index(
+        &self,
+        dirid: nfs3::fileid3,
+        start_index: usize,
+        max_entries: usize,
+    ) -> NFSResult<vfs::ReadDirResult> {
+        self.readdir(dirid, start_index as nfs3::fileid3, max_entries).await
+    }
+
+    async fn symlink(
+        &self,
+        dirid: nfs3::fileid3,
+        linkname: &nfs3::filename3,
+        symlink: &nfs3::nfspath3,
+        attr: &nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        let mut new_attr = self.new_attr(nfs3::ftype3::NF3LNK, 0o777, attr);
+        new_attr.size = symlink.len() as u64;
+        self.write_state().insert(
+            &self.limits,
+            dirid,
+            linkname,
+            new_attr,
+            Contents::Symlink(symlink.to_vec()),
+        )
+    }
+
+    async fn readlink(&self, id: nfs3::fileid3) -> NFSResult<nfs3::nfspath3> {
+        match &self.read_state().inode(id)?.contents {
+            Contents::Symlink(target) => Ok(target.clone().into()),
+            _ => Err(nfs3::nfsstat3::NFS3ERR_INVAL),
+        }
+    }
+
+    async fn link(
+        &self,
+        file_id: nfs3::fileid3,
+        link_dir_id: nfs3::fileid3,
+        link_name: &nfs3::filename3,
+    ) -> NFSResult<nfs3::fattr3> {
+        check_new_name(link_name)?;
+        let mut state = self.write_state();
+        let inode = state.inode(file_id)?;
+        if inode.is_dir() {
+            return Err(nfs3::nfsstat3::NFS3ERR_INVAL);
+        }
+        if inode.attr.nlink == u32::MAX {
+            return Err(nfs3::nfsstat3::NFS3ERR_MLINK);
+        }
+        let entries = state.inode_mut(link_dir_id)?.entries_mut()?;
+        if entries.contains_key(&link_name[..]) {
+            return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
+        }
+        entries.insert(link_name.to_vec(), file_id);
+        let time = now();
+        state.touch_dir(link_dir_id, time);
+        let inode = state.inode_mut(file_id)?;
+        inode.attr.nlink += 1;
+        inode.attr.ctime = time;
+        Ok(inode.attr)
+    }
+
+    async fn mknod(
+        &self,
+        dir_id: nfs3::fileid3,
+        name: &nfs3::filename3,
+        ftype: nfs3::ftype3,
+        specdata: nfs3::specdata3,
+        attrs: &nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        let mut attr = self.new_attr(ftype, 0o644, attrs);
+        match ftype {
+            nfs3::ftype3::NF3CHR | nfs3::ftype3::NF3BLK => attr.rdev = specdata,
+            nfs3::ftype3::NF3SOCK | nfs3::ftype3::NF3FIFO => {}
+            _ => return Err(nfs3::nfsstat3::NFS3ERR_BADTYPE),
+        }
+        self.write_state().insert(&self.limits, dir_id, name, attr, Contents::Special)
+    }
+
+    async fn commit(
+        &self,
+        file_id: nfs3::fileid3,
+        _offset: u64,
+        _count: u32,
+    ) -> NFSResult<nfs3::fattr3> {
+        Ok(self.read_state().inode(file_id)?.attr)
+    }
+
+    fn fsinfo_maxfilesize(&self) -> nfs3::size3 {
+        self.limits.max_file_size
+    }
+
+    fn fsinfo_properties(&self) -> u32 {
+        nfs3::fs::FSF_LINK
+            | nfs3::fs::FSF_SYMLINK
+            | nfs3::fs::FSF_HOMOGENEOUS
+            | nfs3::fs::FSF_CANSETTIME
+    }
+
+    fn pathconf_linkmax(&self) -> u32 {
+        u32::MAX
+    }
+
+    fn pathconf_name_max(&self) -> u32 {
+        NAME_MAX as u32
+    }
+
+    async fn fsstat(&self, root_fileid: nfs3::fileid3) -> NFSResult<nfs3::fs::FSSTAT3resok> {
+        let state = self.read_state();
+        let obj_attributes = state.inode(root_fileid).ok().map(|inode| inode.attr);
+        let tbytes = self.limits.max_bytes.unwrap_or(u64::MAX);
+        let fbytes = tbytes.saturating_sub(state.used_bytes);
+        let tfiles = self.limits.max_inodes.unwrap_or(u64::MAX);
+        let ffiles = tfiles.saturating_sub(state.inodes.len() as u64);
+        Ok(nfs3::fs::FSSTAT3resok {
+            obj_attributes,
+            tbytes,
+            fbytes,
+            abytes: fbytes,
+            tfiles,
+            ffiles,
+            afiles: ffiles,
+            invarsec: 0,
+        })
+    }
+}
@@ -0,0 +1,130 @@
+//! Inodes and sparse file storage for [`MemFS`](super::MemFS).
+
+use std::collections::BTreeMap;
+
+use indexmap::IndexMap;
+
+use crate::xdr::nfs3;
+
+/// Granularity of file storage; also the unit reported in `fattr3::used`.
+pub const BLOCK_SIZE: u64 = 4096;
+
+/// A file system object.
+#[derive(Debug, Clone)]
+pub struct Inode {
+    pub attr: nfs3::fattr3,
+    /// Containing directory; only meaningful for directories, which cannot be hard linked
+    pub parent: nfs3::fileid3,
+    /// Verifier of the EXCLUSIVE create that made this file, if any
+    pub verifier: Option<nfs3::createverf3>,
+    pub contents: Contents,
+}
+
+/// Type-specific payload of an inode.
+#[derive(Debug, Clone)]
+pub enum Contents {
+    File(SparseData),
+    /// Entries in insertion order, which is also the READDIR order
+    Directory(IndexMap<Vec<u8>, nfs3::fileid3>),
+    Symlink(Vec<u8>),
+    /// Device nodes, sockets and FIFOs carry no data beyond their attributes
+    Special,
+}
+
+impl Inode {
+    pub fn entries(&self) -> Result<&IndexMap<Vec<u8>, nfs3::fileid3>, nfs3::nfsstat3> {
+        match &self.contents {
+            Contents::Directory(entries) => Ok(entries),
+            _ => Err(nfs3::nfsstat3::NFS3ERR_NOTDIR),
+        }
+    }
+
+    pub fn entries_mut(&mut self) -> Result<&mut IndexMap<Vec<u8>, nfs3::fileid3>, nfs3::nfsstat3> {
+        match &mut self.contents {
+            Contents::Directory(entries) => Ok(entries),
+            _ => Err(nfs3::nfsstat3::NFS3ERR_NOTDIR),
+        }
+    }
+
+    pub fn is_dir(&self) -> bool {
+        matches!(self.contents, Contents::Directory(_))
+    }
+
+    /// Bytes of storage charged against the file system size limit.
+    pub fn allocated(&self) -> u64 {
+        match &self.contents {
+            Contents::File(data) => data.allocated(),
+            _ => 0,
+        }
+    }
+}
+
+/// File contents stored as fixed-size blocks; missing blocks are holes that read as zeros.
+#[derive(Debug, Clone, Default)]
+pub struct SparseData {
+    pub blocks: BTreeMap<u64, Box<[u8]>>,
+}
+
+impl SparseData {
+    pub fn allocated(&self) -> u64 {
+        self.blocks.len() as u64 * BLOCK_SIZE
+    }
+
+    /// Reads up to `count` bytes at `offset` from a file of `size` bytes.
+    pub fn read(&self, offset: u64, count: u32, size: u64) -> Vec<u8> {
+        let end = size.min(offset.saturating_add(count as u64));
+        if offset >= end {
+            return Vec::new();
+        }
+        let mut out = vec![0; (end - offset) as usize];
+        let first = offset / BLOCK_SIZE;
+        let last = (end - 1) / BLOCK_SIZE;
+        for (index, block) in self.blocks.range(first..=last) {
+            let block_start = index * BLOCK_SIZE;
+            let from = offset.max(block_start);
+            let to = end.min(block_start + BLOCK_SIZE);
+            out[(from - offset) as usize..(to - offset) as usize].copy_from_slice(
+                &block[(from - block_start) as usize..(to - block_start) as usize],
+            );
+        }
+        out
+    }
+
+    /// Number of blocks a write of `len` bytes at `offset` would have to allocate.
+    pub fn blocks_needed(&self, offset: u64, len: u64) -> u64 {
+        if len == 0 {
+            return 0;
+        }
+        let first = offset / BLOCK_SIZE;
+        let last = (offset + len - 1) / BLOCK_SIZE;
+        let present = self.blocks.range(first..=last).count() as u64;
+        last - first + 1 - present
+    }
+
+    pub fn write(&mut self, offset: u64, data: &[u8]) {
+        let mut pos = 0usize;
+        while pos < data.len() {
+            let at = offset + pos as u64;
+            let index = at / BLOCK_SIZE;
+            let within = (at % BLOCK_SIZE) as usize;
+            let len = (BLOCK_SIZE as usize - within).min(data.len() - pos);
+            let block = self
+                .blocks
+                .entry(index)
+                .or_insert_with(|| vec![0; BLOCK_SIZE as usize].into_boxed_slice());
+            block[within..within + len].copy_from_slice(&data[pos..pos + len]);
+            pos += len;
+        }
+    }
+
+    /// Drops everything past `size` so that a later extension reads back as zeros.
+    pub fn truncate(&mut self, size: u64) {
+        let keep = size.div_ceil(BLOCK_SIZE);
+        self.blocks.split_off(&keep);
+        if size % BLOCK_SIZE != 0 {
+            if let Some(block) = self.blocks.get_mut(&(size / BLOCK_SIZE)) {
+                block[(size % BLOCK_SIZE) as usize..].fill(0);
+            }
+        }
+    }
+}
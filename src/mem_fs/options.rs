@@ -0,0 +1,98 @@
+//! Configuration for [`MemFS`](super::MemFS).
+
+use std::io::{self, Read};
+use std::path::Path;
+
+use super::{snapshot, MemFS};
+use crate::xdr::nfs3;
+
+/// Resource limits enforced by a [`MemFS`].
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Limits {
+    pub max_bytes: Option<u64>,
+    pub max_inodes: Option<u64>,
+    pub max_file_size: u64,
+}
+
+/// Builder for [`MemFS`].
+#[derive(Debug, Clone)]
+pub struct MemFSBuilder {
+    limits: Limits,
+    uid: nfs3::uid3,
+    gid: nfs3::gid3,
+    root_mode: nfs3::mode3,
+}
+
+impl Default for MemFSBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemFSBuilder {
+    /// Starts a configuration with no size limits and a world-writable root owned by root.
+    pub fn new() -> Self {
+        Self {
+            limits: Limits { max_bytes: None, max_inodes: None, max_file_size: 128 << 30 },
+            uid: 0,
+            gid: 0,
+            root_mode: 0o777,
+        }
+    }
+
+    /// Caps the total size of file data; writes past it fail with `NFS3ERR_NOSPC`.
+    ///
+    /// Usage is counted in allocated blocks, so holes in sparse files are free.
+    pub fn max_bytes(mut self, bytes: u64) -> Self {
+        self.limits.max_bytes = Some(bytes);
+        self
+    }
+
+    /// Caps the number of objects (including the root); creates past it fail with
+    /// `NFS3ERR_NOSPC`.
+    pub fn max_inodes(mut self, inodes: u64) -> Self {
+        self.limits.max_inodes = Some(inodes);
+        self
+    }
+
+    /// Sets the largest file size clients may write or truncate to (default 128 GiB).
+    ///
+    /// Larger sizes fail with `NFS3ERR_FBIG`.
+    pub fn max_file_size(mut self, bytes: u64) -> Self {
+        self.limits.max_file_size = bytes;
+        self
+    }
+
+    /// Sets the owner of the root directory and the default owner of new objects.
+    pub fn owner(mut self, uid: nfs3::uid3, gid: nfs3::gid3) -> Self {
+        self.uid = uid;
+        self.gid = gid;
+        self
+    }
+
+    /// Sets the permission bits of the root directory.
+    pub fn root_mode(mut self, mode: nfs3::mode3) -> Self {
+        self.root_mode = mode & 0o7777;
+        self
+    }
+
+    /// Creates an empty file system.
+    pub fn build(self) -> MemFS {
+        MemFS::empty(self.limits, self.uid, self.gid, self.root_mode)
+    }
+
+    /// Restores a file system saved with [`MemFS::save_snapshot`].
+    ///
+    /// File IDs are preserved; the owner and root mode settings are ignored in favour of
+    /// the saved attributes, while limits apply to subsequent operations.
+    pub fn load_snapshot(self, path: impl AsRef<Path>) -> io::Result<MemFS> {
+        let file = std::fs::File::open(path)?;
+        self.read_snapshot(io::BufReader::new(file))
+    }
+
+    /// Restores a file system from a snapshot stream produced by [`MemFS::write_snapshot`].
+    pub fn read_snapshot(self, mut src: impl Read) -> io::Result<MemFS> {
+        let state = snapshot::read(&mut src)?;
+        Ok(MemFS::from_state(state, self.limits, self.uid, self.gid))
+    }
+}
@@ -0,0 +1,171 @@
+//! Snapshot encoding for [`MemFS`](super::MemFS).
+//!
+//! A snapshot is an XDR stream: a magic number and version, the next free file ID,
+//! then one record per inode carrying its attributes and type-specific contents.
+//! Only allocated blocks of sparse files are stored.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+use indexmap::IndexMap;
+
+use super::inode::{Contents, Inode, SparseData, BLOCK_SIZE};
+use super::{check_new_name, State, ROOT_ID};
+use crate::xdr::{deserialize, nfs3, Serialize};
+
+const MAGIC: [u8; 8] = *b"FERNMEM\0";
+const VERSION: u32 = 1;
+
+const KIND_FILE: u32 = 0;
+const KIND_DIRECTORY: u32 = 1;
+const KIND_SYMLINK: u32 = 2;
+const KIND_SPECIAL: u32 = 3;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid snapshot: {message}"))
+}
+
+pub(super) fn write(state: &State, dest: &mut impl Write) -> io::Result<()> {
+    MAGIC.serialize(dest)?;
+    VERSION.serialize(dest)?;
+    state.next_id.serialize(dest)?;
+    (state.inodes.len() as u64).serialize(dest)?;
+    let mut ids: Vec<_> = state.inodes.keys().copied().collect();
+    ids.sort_unstable();
+    for id in ids {
+        let inode = &state.inodes[&id];
+        id.serialize(dest)?;
+        inode.attr.serialize(dest)?;
+        inode.parent.serialize(dest)?;
+        inode.verifier.is_some().serialize(dest)?;
+        if let Some(verifier) = &inode.verifier {
+            verifier.serialize(dest)?;
+        }
+        match &inode.contents {
+            Contents::File(data) => {
+                KIND_FILE.serialize(dest)?;
+                (data.blocks.len() as u64).serialize(dest)?;
+                for (index, block) in &data.blocks {
+                    index.serialize(dest)?;
+                    block[..].serialize(dest)?;
+                }
+            }
+            Contents::Directory(entries) => {
+                KIND_DIRECTORY.serialize(dest)?;
+                (entries.len() as u64).serialize(dest)?;
+                for (name, child) in entries {
+                    name[..].serialize(dest)?;
+                    child.serialize(dest)?;
+                }
+            }
+            Contents::Symlink(target) => {
+                KIND_SYMLINK.serialize(dest)?;
+                target[..].serialize(dest)?;
+            }
+            Contents::Special => KIND_SPECIAL.serialize(dest)?,
+        }
+    }
+    Ok(())
+}
+
+pub(super) fn read(src: &mut impl Read) -> io::Result<State> {
+    if deserialize::<[u8; 8]>(src)? != MAGIC {
+        return Err(invalid("not a MemFS snapshot"));
+    }
+    let version = deserialize::<u32>(src)?;
+    if version != VERSION {
+        return Err(invalid(&format!("unsupported version {version}")));
+    }
+    let next_id = deserialize::<u64>(src)?;
+    let count = deserialize::<u64>(src)?;
+
+    let mut inodes = HashMap::new();
+    let mut used_bytes = 0;
+    for _ in 0..count {
+        let id = deserialize::<nfs3::fileid3>(src)?;
+        let attr = deserialize::<nfs3::fattr3>(src)?;
+        let parent = deserialize::<nfs3::fileid3>(src)?;
+        let verifier = if deserialize::<bool>(src)? {
+            Some(deserialize::<nfs3::createverf3>(src)?)
+        } else {
+            None
+        };
+        let contents = match deserialize::<u32>(src)? {
+            KIND_FILE => {
+                let mut data = SparseData::default();
+                for _ in 0..deserialize::<u64>(src)? {
+                    let index = deserialize::<u64>(src)?;
+                    let block = deserialize::<Vec<u8>>(src)?;
+                    if block.len() as u64 != BLOCK_SIZE {
+                        return Err(invalid("bad block size"));
+                    }
+                    data.blocks.insert(index, block.into_boxed_slice());
+                }
+                Contents::File(data)
+            }
+            KIND_DIRECTORY => {
+                let mut entries = IndexMap::new();
+                for _ in 0..deserialize::<u64>(src)? {
+                    let name = deserialize::<Vec<u8>>(src)?;
+                    entries.insert(name, deserialize::<nfs3::fileid3>(src)?);
+                }
+                Contents::Directory(entries)
+            }
+            KIND_SYMLINK => Contents::Symlink(deserialize::<Vec<u8>>(src)?),
+            KIND_SPECIAL => Contents::Special,
+            _ => return Err(invalid("unknown inode kind")),
+        };
+        if id == 0 || id >= next_id || attr.fileid != id {
+            return Err(invalid("bad file id"));
+        }
+        let inode = Inode { attr, parent, verifier, contents };
+        used_bytes += inode.allocated();
+        if inodes.insert(id, inode).is_some() {
+            return Err(invalid("duplicate file id"));
+        }
+    }
+
+    if !inodes.get(&ROOT_ID).is_some_and(|root| root.is_dir() && root.parent == ROOT_ID) {
+        return Err(invalid("missing root directory"));
+    }
+    check_tree(&mut inodes)?;
+    Ok(State { inodes, next_id, used_bytes })
+}
+
+/// Checks that every inode is reachable from the root, each directory through
+/// exactly one entry of its parent, under names `create` would accept. Link counts
+/// are then recomputed from the entries.
+fn check_tree(inodes: &mut HashMap<nfs3::fileid3, Inode>) -> io::Result<()> {
+    let mut links: HashMap<nfs3::fileid3, u32> = HashMap::from([(ROOT_ID, 0)]);
+    let mut pending = vec![ROOT_ID];
+    while let Some(dirid) = pending.pop() {
+        let Contents::Directory(entries) = &inodes[&dirid].contents else {
+            unreachable!("only directories are queued");
+        };
+        for (name, &child) in entries {
+            if check_new_name(name).is_err() {
+                return Err(invalid("bad entry name"));
+            }
+            let inode = inodes.get(&child).ok_or_else(|| invalid("dangling directory entry"))?;
+            if !inode.is_dir() {
+                *links.entry(child).or_default() += 1;
+                continue;
+            }
+            if inode.parent != dirid || links.insert(child, 0).is_some() {
+                return Err(invalid("directory with more than one parent"));
+            }
+            *links.get_mut(&dirid).unwrap() += 1;
+            pending.push(child);
+        }
+    }
+    if links.len() != inodes.len() {
+        return Err(invalid("unreachable inode"));
+    }
+    for (id, inode) in inodes.iter_mut() {
+        // Directories are linked from their parent and their own ".", plus the ".."
+        // of each subdirectory.
+        let count = links[id];
+        inode.attr.nlink = if inode.is_dir() { count + 2 } else { count };
+    }
+    Ok(())
+}
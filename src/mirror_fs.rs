@@ -0,0 +1,1170 @@
+//! A file system that mirrors a local directory tree.
+//!
+//! [`MirrorFS`] exports an existing directory as-is. File IDs are assigned lazily as
+//! clients walk the tree and are kept in an [`FSMap`], which tracks path aliases
+//! (hard links) and revalidates cached entries against the disk on every access so
+//! that changes made outside the server are picked up. The map is only locked to
+//! read or update it; disk I/O happens outside the lock, so requests run in parallel.
+//! Directories are listed incrementally, so reading the first entries of a huge
+//! directory does not read all of it. On Linux, [`MirrorFSBuilder::watch`] applies
+//! changes reported by inotify instead of revalidating, and keeps complete listings
+//! in the map.
+//! Paths from the map are only ever used through an [`ExportRoot`], which resolves
+//! them below the root without following symlinks.
+//! Entries matching the exclude patterns are hidden from clients; an
+//! [`ExcludeController`] replaces the patterns while the file system is served.
+//!
+//! ```ignore
+//! use fernfs::mirror_fs::{MirrorFS, SymlinkPolicy};
+//!
+//! let fs = MirrorFS::builder("/srv/export")
+//!     .read_only(true)
+//!     .exclude(".git")
+//!     .symlink_policy(SymlinkPolicy::Hide)
+//!     .build()?;
+//! ```
+
+use std::ffi::OsStr;
+use std::io::{self, ErrorKind};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use tracing::{debug, warn};
+
+use crate::fs_util::{file_setattr, mode_unmask, specdata3_to_rdev};
+use crate::protocol::rpc;
+use crate::vfs;
+use crate::xdr;
+use crate::xdr::nfs3;
+
+pub mod create_fs_object;
+mod credentials;
+mod dir_cursors;
+mod dir_locks;
+pub mod error_handling;
+mod exclude;
+pub mod export_root;
+mod fd_cache;
+pub mod fs_entry;
+pub mod fs_map;
+mod options;
+#[cfg(target_os = "linux")]
+mod watch;
+
+use create_fs_object::CreateFSObject;
+use credentials::Caller;
+use dir_cursors::DirCursors;
+use dir_locks::DirLocks;
+use error_handling::{io_error, NFSResult, RefreshResult};
+pub use exclude::ExcludeSet;
+use export_root::ExportRoot;
+use fd_cache::{Access, FdCache};
+pub use fs_entry::FSEntry;
+pub use fs_map::FSMap;
+use options::MirrorOptions;
+pub use options::{FsyncPolicy, MirrorFSBuilder, MountPolicy, SymlinkPolicy};
+#[cfg(target_os = "linux")]
+use watch::Watcher;
+
+/// Replaces the exclude patterns of a [`MirrorFS`] at runtime
+///
+/// Entries that become hidden disappear from listings and their file handles go
+/// stale; entries that become visible show up when their directory is next listed.
+#[derive(Debug, Clone)]
+pub struct ExcludeController {
+    fsmap: Arc<RwLock<FSMap>>,
+    cursors: Arc<DirCursors>,
+    #[cfg(target_os = "linux")]
+    watcher: Option<Arc<Watcher>>,
+}
+
+impl ExcludeController {
+    /// Hides entries matching `exclude` instead of the current patterns
+    pub fn set(&self, exclude: ExcludeSet) {
+        self.fsmap.write().unwrap().set_exclude(exclude);
+        self.cursors.clear();
+        // Listings kept by the watcher were filtered with the old patterns.
+        #[cfg(target_os = "linux")]
+        if let Some(watcher) = &self.watcher {
+            watcher.unsync_all();
+        }
+    }
+}
+
+/// A file system implementation that mirrors a local directory
+#[derive(Debug)]
+pub struct MirrorFS {
+    /// The file system map that tracks files and directories
+    fsmap: Arc<RwLock<FSMap>>,
+    /// Descriptor of the root, through which every file is reached
+    export: Arc<ExportRoot>,
+    /// Serialize changes to each directory with updates of its listing
+    dirs: Arc<DirLocks>,
+    /// Applies changes made outside the server, if enabled
+    #[cfg(target_os = "linux")]
+    watcher: Option<Arc<Watcher>>,
+    /// Descriptors of recently read and written files
+    files: FdCache,
+    /// Positions in the listings of recently read directories
+    cursors: Arc<DirCursors>,
+    generation: u64,
+    /// Write verifier; changes whenever unstable writes may have been lost
+    write_verifier: AtomicU64,
+    read_only: bool,
+    symlinks: SymlinkPolicy,
+    fsync: FsyncPolicy,
+    /// Whether files are changed with the credentials of the calling user
+    caller_credentials: bool,
+}
+
+impl MirrorFS {
+    /// Creates a new mirror file system with the given root path and default options
+    ///
+    /// Panics if the root cannot be accessed; use [`MirrorFS::builder`] to handle errors.
+    pub fn new(root: PathBuf) -> Self {
+        Self::builder(root).build().expect("mirror root must be an accessible directory")
+    }
+
+    /// Returns a builder for configuring a mirror of `root`
+    pub fn builder(root: impl Into<PathBuf>) -> MirrorFSBuilder {
+        MirrorFSBuilder::new(root)
+    }
+
+    pub(crate) fn from_options(options: MirrorOptions) -> io::Result<Self> {
+        let now = SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis();
+        let mut fsmap = FSMap::new(options.root)?;
+        fsmap.exclude = options.exclude;
+        fsmap.hide_symlinks = options.symlinks == SymlinkPolicy::Hide;
+        fsmap.mounts = options.mounts;
+        let export = fsmap.export();
+        let fsmap = Arc::new(RwLock::new(fsmap));
+        let dirs = Arc::new(DirLocks::new());
+        #[cfg(target_os = "linux")]
+        let watcher = if options.watch {
+            match Watcher::new() {
+                Ok(watcher) => {
+                    watcher.spawn(Arc::downgrade(&fsmap), dirs.clone());
+                    Some(watcher)
+                }
+                Err(err) => {
+                    warn!("Unable to watch for changes, polling instead: {err}");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        Ok(Self {
+            fsmap,
+            export,
+            dirs,
+            #[cfg(target_os = "linux")]
+            watcher,
+            files: FdCache::new(options.open_files),
+            cursors: Arc::new(DirCursors::new()),
+            generation: now as u64,
+            write_verifier: AtomicU64::new(now as u64),
+            read_only: options.read_only,
+            symlinks: options.symlinks,
+            fsync: options.fsync,
+            caller_credentials: options.caller_credentials,
+        })
+    }
+
+    /// Returns a handle for replacing the exclude patterns while the file system is
+    /// being served
+    pub fn exclude_controller(&self) -> ExcludeController {
+        ExcludeController {
+            fsmap: self.fsmap.clone(),
+            cursors: self.cursors.clone(),
+            #[cfg(target_os = "linux")]
+            watcher: self.watcher.clone(),
+        }
+    }
+
+    /// Flushes `file` to disk, only the data and the metadata needed to read it back
+    /// if `data_only` is set.
+    ///
+    /// A failed sync can leave the kernel with clean pages that never reached the
+    /// disk, so earlier unstable writes may be gone. The write verifier then changes,
+    /// which makes clients send everything they have not seen committed again.
+    async fn sync(&self, file: Arc<std::fs::File>, data_only: bool) -> NFSResult<()> {
+        let sync = move || if data_only { file.sync_data() } else { file.sync_all() };
+        let result =
+            tokio::task::spawn_blocking(sync).await.map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
+        result.map_err(|e| {
+            warn!("Unable to sync {:?}", e);
+            self.write_verifier.fetch_add(1, Ordering::Relaxed);
+            nfs3::nfsstat3::NFS3ERR_IO
+        })
+    }
+
+    /// Returns the path of entry `id`
+    fn path_of(&self, id: nfs3::fileid3) -> NFSResult<PathBuf> {
+        self.fsmap.read().unwrap().entry_path(id)
+    }
+
+    /// Checks whether the watcher keeps entry `id` current, so that it need not be
+    /// checked against the disk
+    #[cfg(target_os = "linux")]
+    fn is_watched(&self, id: nfs3::fileid3) -> bool {
+        let Some(watcher) = &self.watcher else {
+            return false;
+        };
+        let fsmap = self.fsmap.read().unwrap();
+        let Some(entry) = fsmap.id_to_path.get(&id) else {
+            return false;
+        };
+        if entry.is_directory() {
+            return watcher.is_synced(id);
+        }
+        // Changes made through another link are only reported to its directory.
+        entry.fsmeta.nlink == 1 && fsmap.parent_id(id).is_some_and(|dir| watcher.is_synced(dir))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn is_watched(&self, _id: nfs3::fileid3) -> bool {
+        false
+    }
+
+    /// Checks whether changes outside the server are applied by a watcher
+    #[cfg(target_os = "linux")]
+    fn is_watching(&self) -> bool {
+        self.watcher.is_some()
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn is_watching(&self) -> bool {
+        false
+    }
+
+    /// Starts watching directory `id`, returning whether it is watched
+    #[cfg(target_os = "linux")]
+    fn watch(&self, id: nfs3::fileid3, path: &Path) -> bool {
+        let Some(watcher) = &self.watcher else {
+            return false;
+        };
+        match watcher.watch(id, &self.export, path) {
+            Ok(()) => true,
+            Err(err) => {
+                debug!("Unable to watch {:?}, polling it instead: {:?}", path, err);
+                false
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn watch(&self, _id: nfs3::fileid3, _path: &Path) -> bool {
+        false
+    }
+
+    /// Checks entry `id` against the disk, see [`FSMap::refresh_entry`]
+    async fn refresh_entry(&self, id: nfs3::fileid3) -> NFSResult<RefreshResult> {
+        if self.is_watched(id) {
+            return Ok(RefreshResult::Noop);
+        }
+        self.reload_entry(id).await
+    }
+
+    /// Checks entry `id` against the disk even if it is watched, for changes made by
+    /// this server that are not yet reported
+    async fn reload_entry(&self, id: nfs3::fileid3) -> NFSResult<RefreshResult> {
+        let paths = self.fsmap.read().unwrap().entry_paths(id)?;
+        let state = paths.stat().await;
+        self.fsmap.write().unwrap().apply_entry(id, state)
+    }
+
+    /// Reads the children of directory `id` from disk, see [`FSMap::refresh_dir_list`]
+    async fn refresh_dir_list(&self, id: nfs3::fileid3) -> NFSResult<()> {
+        if self.is_watched(id) {
+            return Ok(());
+        }
+        self.relist(id).await
+    }
+
+    /// Reads the children of directory `id` from disk even if it is watched
+    async fn relist(&self, id: nfs3::fileid3) -> NFSResult<()> {
+        let _dir = self.dirs.lock(id).await;
+        let Some(path) = self.fsmap.read().unwrap().dir_path(id)? else {
+            return Ok(());
+        };
+        // Watch before listing, so that no change made in between is missed.
+        let watched = self.watch(id, &path);
+        let listing = fs_map::read_listing(self.export.clone(), path).await?;
+        self.fsmap.write().unwrap().apply_dir_list(id, listing)?;
+        #[cfg(target_os = "linux")]
+        if let (true, Some(watcher)) = (watched, &self.watcher) {
+            watcher.mark_synced(id);
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = watched;
+        Ok(())
+    }
+
+    /// Returns the identity to act as, if files are changed with the credentials of
+    /// the calling user
+    fn caller(&self) -> Option<Caller> {
+        self.caller_credentials.then(Caller::current)
+    }
+
+    /// Runs the disk operation `op` as the calling user, if so configured
+    fn as_caller<T>(&self, op: impl FnOnce() -> io::Result<T>) -> io::Result<T> {
+        match self.caller() {
+            Some(caller) => caller.run(op),
+            None => op(),
+        }
+    }
+
+    /// Returns a descriptor of file `id` allowing `access`, with the file's metadata.
+    async fn open_file(
+        &self,
+        id: nfs3::fileid3,
+        access: Access,
+    ) -> NFSResult<(Arc<std::fs::File>, std::fs::Metadata)> {
+        let path = self.path_of(id)?;
+        let opener = self.caller();
+        self.files.open(&self.export, id, &path, access, opener.as_ref()).map_err(|e| {
+            debug!("Unable to open {:?}: {:?}", path, e);
+            match (e.kind(), e.raw_os_error()) {
+                (ErrorKind::NotFound, _) => nfs3::nfsstat3::NFS3ERR_NOENT,
+                (_, Some(libc::EISDIR)) => nfs3::nfsstat3::NFS3ERR_ISDIR,
+                (_, Some(libc::EINVAL)) => nfs3::nfsstat3::NFS3ERR_INVAL,
+                _ => io_error(e),
+            }
+        })
+    }
+
+    /// Applies the attributes of a `SETATTR` request to the file at `path`
+    fn set_attributes(&self, path: &Path, setattr: &nfs3::sattr3) -> NFSResult<()> {
+        self.as_caller(|| self.export.set_times(path, setattr.atime, setattr.mtime))
+            .map_err(io_error)?;
+
+        if let nfs3::set_mode3::Some(mode) = setattr.mode {
+            debug!(" -- set permissions {:?} {:?}", path, mode);
+            self.as_caller(|| self.export.set_mode(path, mode_unmask(mode))).map_err(io_error)?;
+        }
+
+        if setattr.uid.is_some() || setattr.gid.is_some() {
+            debug!(" -- set owner {:?} {:?} {:?}", path, setattr.uid, setattr.gid);
+            self.as_caller(|| self.export.set_owner(path, setattr.uid, setattr.gid))
+                .map_err(io_error)?;
+        }
+
+        if let nfs3::set_size3::Some(size3) = setattr.size {
+            debug!(" -- set size {:?} {:?}", path, size3);
+            // Only regular files have a size to set; devices are never opened.
+            let meta = self.export.metadata(path).map_err(io_error)?;
+            if meta.is_dir() {
+                return Err(nfs3::nfsstat3::NFS3ERR_ISDIR);
+            } else if !meta.is_file() {
+                return Err(nfs3::nfsstat3::NFS3ERR_INVAL);
+            }
+            let flags = libc::O_RDWR | libc::O_NONBLOCK | libc::O_NOCTTY;
+            self.as_caller(|| self.export.open_file(path, flags)?.set_len(size3))
+                .map_err(io_error)?;
+        }
+
+        Ok(())
+    }
+
+    fn check_writable(&self) -> NFSResult<()> {
+        if self.read_only {
+            return Err(nfs3::nfsstat3::NFS3ERR_ROFS);
+        }
+        Ok(())
+    }
+
+    /// Returns the file made by an earlier exclusive create with `verifier`, if any
+    fn check_exclusive_existing(
+        &self,
+        dirid: nfs3::fileid3,
+        objectname: &nfs3::filename3,
+        verifier: &nfs3::createverf3,
+        path: &Path,
+    ) -> NFSResult<Option<(nfs3::fileid3, nfs3::fattr3)>> {
+        let existing = {
+            let fsmap = self.fsmap.read().unwrap();
+            fsmap.find_child(dirid, objectname.as_ref()).ok().filter(|id| {
+                fsmap
+                    .id_to_path
+                    .get(id)
+                    .is_some_and(|entry| entry.exclusive_verifier == Some(*verifier))
+            })
+        };
+        if let Some(existing_id) = existing {
+            let meta = self.export.metadata(path).map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
+            return Ok(Some((existing_id, self.attributes(existing_id, &meta))));
+        }
+        Ok(None)
+    }
+
+    /// Returns the attributes of entry `id` with metadata `meta`
+    fn attributes(&self, id: nfs3::fileid3, meta: &std::fs::Metadata) -> nfs3::fattr3 {
+        self.fsmap.read().unwrap().attributes(id, meta)
+    }
+
+    /// Records a new object `name` in directory `dirid`, returning its file ID
+    fn add_child(
+        &self,
+        dirid: nfs3::fileid3,
+        name: &OsStr,
+        meta: std::fs::Metadata,
+    ) -> NFSResult<nfs3::fileid3> {
+        self.fsmap.write().unwrap().add_child(dirid, name, meta)
+    }
+
+    /// Returns the `.` and `..` entries of directory `dirid`, which are the first two
+    /// of every listing
+    fn dot_entries(&self, dirid: nfs3::fileid3) -> NFSResult<Vec<vfs::DirEntry>> {
+        let fsmap = self.fsmap.read().unwrap();
+        let entry = fsmap.find_entry(dirid)?;
+        if !entry.is_directory() {
+            return Err(nfs3::nfsstat3::NFS3ERR_NOTDIR);
+        }
+        let mut self_attr = entry.fsmeta;
+        self_attr.fileid = dirid;
+
+        let parent_id = if entry.name.is_empty() {
+            dirid
+        } else {
+            let mut parent_name = entry.name.clone();
+            parent_name.pop();
+            *fsmap.path_to_id.get(&parent_name).ok_or(nfs3::nfsstat3::NFS3ERR_IO)?
+        };
+        let parent_entry = fsmap.find_entry(parent_id)?;
+        let mut parent_attr = parent_entry.fsmeta;
+        parent_attr.fileid = parent_id;
+        Ok(vec![
+            vfs::DirEntry { fileid: dirid, name: b".".to_vec().into(), attr: self_attr },
+            vfs::DirEntry { fileid: parent_id, name: b"..".to_vec().into(), attr: parent_attr },
+        ])
+    }
+
+    /// Reads directory entries from the children kept in the map, listing the
+    /// directory first unless the watcher keeps them current
+    async fn readdir_listed(
+        &self,
+        dirid: nfs3::fileid3,
+        start_index: usize,
+        max_entries: usize,
+    ) -> NFSResult<vfs::ReadDirResult> {
+        self.refresh_entry(dirid).await?;
+        self.refresh_dir_list(dirid).await?;
+
+        let mut all_entries = self.dot_entries(dirid)?;
+        let fsmap = self.fsmap.read().unwrap();
+        let entry = fsmap.find_entry(dirid)?;
+        debug!("readdir({:?}, {:?})", entry, start_index);
+        // we must have children here
+        let children = entry.children.ok_or(nfs3::nfsstat3::NFS3ERR_IO)?;
+        debug!("children len: {:?}", children.len());
+        for (name_sym, fileid) in children.iter() {
+            let fileid = *fileid;
+            let fileent = fsmap.find_entry(fileid)?;
+            let name = fsmap.intern.get(*name_sym).unwrap();
+            debug!("\t --- {:?} {:?}", fileid, name);
+            let mut attr = fileent.fsmeta;
+            attr.fileid = fileid;
+            all_entries.push(vfs::DirEntry { fileid, name: name.as_bytes().into(), attr });
+        }
+
+        if start_index > all_entries.len() {
+            return Err(nfs3::nfsstat3::NFS3ERR_BAD_COOKIE);
+        }
+        let remaining_length = all_entries.len() - start_index;
+        debug!("remaining_len : {:?}", remaining_length);
+        let entries: Vec<_> = all_entries.into_iter().skip(start_index).take(max_entries).collect();
+        let ret = vfs::ReadDirResult { end: entries.len() == remaining_length, entries };
+        debug!("readdir_result:{:?}", ret);
+        Ok(ret)
+    }
+
+    /// Reads directory entries through the directory's cursor, reading names from
+    /// disk only up to the end of the page and statting only the entries returned
+    async fn readdir_streamed(
+        &self,
+        dirid: nfs3::fileid3,
+        start_index: usize,
+        max_entries: usize,
+    ) -> NFSResult<vfs::ReadDirResult> {
+        self.refresh_entry(dirid).await?;
+        let dots = self.dot_entries(dirid)?;
+        let path = self.path_of(dirid)?;
+        let mtime = dots[0].attr.mtime;
+        let version = ((mtime.seconds as u64) << 32) | (mtime.nseconds as u64);
+        debug!("readdir({:?}, {:?})", path, start_index);
+
+        let cursor = self.cursors.get(dirid);
+        let mut cursor = cursor.lock().await;
+        if start_index == 0 || !cursor.is_current(version) {
+            let stream = fs_map::open_dir_stream(self.export.clone(), path.clone()).await?;
+            cursor.restart(version, stream);
+        }
+        // Names follow the two dot entries. One more than the page is read to tell
+        // whether the page ends the listing.
+        let end_index = start_index.saturating_add(max_entries);
+        let first = start_index.saturating_sub(dots.len());
+        let last = end_index.saturating_sub(dots.len());
+        cursor.fill(last.saturating_add(1), &path, &self.fsmap).await?;
+        if first > cursor.len() {
+            return Err(nfs3::nfsstat3::NFS3ERR_BAD_COOKIE);
+        }
+        let (page, end) = cursor.page(first, last, &path, &self.fsmap).await?;
+        drop(cursor);
+
+        let mut entries: Vec<_> = dots.into_iter().skip(start_index).take(max_entries).collect();
+        let mut fsmap = self.fsmap.write().unwrap();
+        for (name, meta) in page {
+            let fileid = fsmap.add_child(dirid, &name, meta.clone())?;
+            let attr = fsmap.attributes(fileid, &meta);
+            entries.push(vfs::DirEntry { fileid, name: name.as_bytes().into(), attr });
+        }
+        debug!("readdir {:?}: {} entries, end {}", path, entries.len(), end);
+        Ok(vfs::ReadDirResult { entries, end })
+    }
+
+    /// Creates a file system object in a given directory and of a given type
+    /// Updates as much metadata as we can in-place
+    async fn create_fs_object(
+        &self,
+        dirid: nfs3::fileid3,
+        objectname: &nfs3::filename3,
+        object: &CreateFSObject,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        self.check_writable()?;
+        let _dir = self.dirs.lock(dirid).await;
+        let mut path = self.path_of(dirid)?;
+        let objectname_osstr = OsStr::from_bytes(objectname);
+        path.push(objectname_osstr);
+        if self.fsmap.read().unwrap().is_excluded(&path) {
+            return Err(nfs3::nfsstat3::NFS3ERR_ACCES);
+        }
+
+        if let CreateFSObject::Exclusive(verifier) = object {
+            if self.export.exists(&path) {
+                if let Some(existing) =
+                    self.check_exclusive_existing(dirid, objectname, verifier, &path)?
+                {
+                    return Ok(existing);
+                }
+                return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
+            }
+        }
+
+        match object {
+            CreateFSObject::Directory => {
+                debug!("mkdir {:?}", path);
+                if self.export.exists(&path) {
+                    return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
+                }
+                self.as_caller(|| self.export.create_dir(&path)).map_err(io_error)?;
+            }
+            CreateFSObject::File(setattr) => {
+                debug!("create {:?}", path);
+                let flags = libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC;
+                let file =
+                    self.as_caller(|| self.export.open_file(&path, flags)).map_err(io_error)?;
+                let _ = file_setattr(&file, setattr).await;
+            }
+            CreateFSObject::Exclusive(verifier) => {
+                debug!("create exclusive {:?}", path);
+                let flags = libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL;
+                match self.as_caller(|| self.export.open_file(&path, flags)) {
+                    Ok(_) => {}
+                    Err(err) => {
+                        if err.kind() == ErrorKind::AlreadyExists {
+                            if let Some(existing) =
+                                self.check_exclusive_existing(dirid, objectname, verifier, &path)?
+                            {
+                                return Ok(existing);
+                            }
+                            return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
+                        }
+                        return Err(io_error(err));
+                    }
+                }
+            }
+            CreateFSObject::Symlink((_, target)) => {
+                debug!("symlink {:?} {:?}", path, target);
+                if self.export.exists(&path) {
+                    return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
+                }
+                self.as_caller(|| self.export.symlink(OsStr::from_bytes(target), &path))
+                    .map_err(io_error)?;
+                // we do not set attributes on symlinks
+            }
+        }
+
+        let _ = self.reload_entry(dirid).await?;
+
+        let meta = self.export.metadata(&path).map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
+        let fileid = self.add_child(dirid, objectname_osstr, meta.clone())?;
+        if let CreateFSObject::Exclusive(verifier) = object {
+            if let Some(entry) = self.fsmap.write().unwrap().id_to_path.get_mut(&fileid) {
+                entry.exclusive_verifier = Some(*verifier);
+            }
+        }
+        Ok((fileid, self.attributes(fileid, &meta)))
+    }
+}
+
+#[async_trait]
+impl vfs::NFSFileSystem for MirrorFS {
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn server_id(&self) -> nfs3::cookieverf3 {
+        self.write_verifier.load(Ordering::Relaxed).to_le_bytes()
+    }
+
+    /// Returns the root directory file ID
+    fn root_dir(&self) -> nfs3::fileid3 {
+        0
+    }
+
+    /// Returns the capabilities of this file system
+    fn capabilities(&self) -> vfs::Capabilities {
+        if self.read_only {
+            vfs::Capabilities::ReadOnly
+        } else {
+            vfs::Capabilities::ReadWrite
+        }
+    }
+
+    /// Looks up a file in a directory
+    async fn lookup(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> NFSResult<nfs3::fileid3> {
+        let cached = self.fsmap.read().unwrap().find_child(dirid, filename);
+        if let Ok(id) = cached {
+            match self.refresh_entry(id).await {
+                Ok(RefreshResult::Delete) | Err(nfs3::nfsstat3::NFS3ERR_NOENT) => {
+                    // Fall through to refresh the directory listing below.
+                }
+                Err(err) => return Err(err),
+                Ok(_) => {
+                    if self.fsmap.read().unwrap().find_child(dirid, filename).is_ok() {
+                        return Ok(id);
+                    }
+                    return Err(nfs3::nfsstat3::NFS3ERR_NOENT);
+                }
+            }
+        }
+        // Optimize for negative lookups.
+        // See if the file actually exists on the filesystem
+        let mut path = self.path_of(dirid)?;
+        path.push(OsStr::from_bytes(filename));
+        if !self.export.exists(&path) {
+            return Err(nfs3::nfsstat3::NFS3ERR_NOENT);
+        }
+
+        // The file exists on disk but not in our cache, so refresh the directory
+        if let RefreshResult::Delete = self.refresh_entry(dirid).await? {
+            return Err(nfs3::nfsstat3::NFS3ERR_NOENT);
+        }
+        self.relist(dirid).await?;
+
+        self.fsmap.read().unwrap().find_child(dirid, filename)
+    }
+
+    /// Gets the attributes of a file
+    async fn getattr(&self, id: nfs3::fileid3) -> NFSResult<nfs3::fattr3> {
+        if let RefreshResult::Delete = self.refresh_entry(id).await? {
+            return Err(nfs3::nfsstat3::NFS3ERR_NOENT);
+        }
+        let fsmap = self.fsmap.read().unwrap();
+        let ent = fsmap.id_to_path.get(&id).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?;
+        debug!("Stat {:?}: {:?}", fsmap.sym_to_path(&ent.name), ent.fsmeta);
+        Ok(ent.fsmeta)
+    }
+
+    async fn check_access(
+        &self,
+        id: nfs3::fileid3,
+        auth: &xdr::rpc::auth_unix,
+        access: u32,
+    ) -> NFSResult<u32> {
+        let path = self.path_of(id)?;
+        let meta = self.export.metadata(&path).map_err(|err| match err.kind() {
+            ErrorKind::NotFound => nfs3::nfsstat3::NFS3ERR_NOENT,
+            ErrorKind::PermissionDenied => nfs3::nfsstat3::NFS3ERR_ACCES,
+            _ => nfs3::nfsstat3::NFS3ERR_IO,
+        })?;
+
+        let attr = self.attributes(id, &meta);
+        Ok(vfs::permissions::access_mask(&attr, auth, self.capabilities(), access))
+    }
+
+    /// Reads data from a file
+    async fn read(&self, id: nfs3::fileid3, offset: u64, count: u32) -> NFSResult<(Vec<u8>, bool)> {
+        let (f, meta) = self.open_file(id, Access::Read).await?;
+        let len = meta.len();
+        let start = offset.min(len);
+        let end = offset.saturating_add(count as u64).min(len);
+        let eof = offset.saturating_add(count as u64) >= len;
+        let buf = tokio::task::spawn_blocking(move || {
+            let mut buf = vec![0; (end - start) as usize];
+            f.read_exact_at(&mut buf, start).map(|_| buf)
+        })
+        .await
+        .or(Err(nfs3::nfsstat3::NFS3ERR_IO))?
+        .or(Err(nfs3::nfsstat3::NFS3ERR_IO))?;
+        Ok((buf, eof))
+    }
+
+    /// Reads directory entries
+    async fn readdir(
+        &self,
+        dirid: nfs3::fileid3,
+        start_after: nfs3::fileid3,
+        max_entries: usize,
+    ) -> NFSResult<vfs::ReadDirResult> {
+        let start_index =
+            usize::try_from(start_after).map_err(|_| nfs3::nfsstat3::NFS3ERR_BAD_COOKIE)?;
+        self.readdir_index(dirid, start_index, max_entries).await
+    }
+
+    /// Reads directory entries from a cookie index, without listing the whole
+    /// directory unless it is watched
+    async fn readdir_index(
+        &self,
+        dirid: nfs3::fileid3,
+        start_index: usize,
+        max_entries: usize,
+    ) -> NFSResult<vfs::ReadDirResult> {
+        if self.is_watching() {
+            self.readdir_listed(dirid, start_index, max_entries).await
+        } else {
+            self.readdir_streamed(dirid, start_index, max_entries).await
+        }
+    }
+
+    async fn fsstat(&self, root_fileid: nfs3::fileid3) -> NFSResult<nfs3::fs::FSSTAT3resok> {
+        let obj_attr = self.getattr(root_fileid).await.ok();
+        // The field types of `statvfs` differ between platforms.
+        #[cfg(unix)]
+        #[allow(clippy::unnecessary_cast)]
+        {
+            // Mounted file systems exported as such are described separately.
+            let id = match self.fsmap.read().unwrap().mounts {
+                MountPolicy::Cross => root_fileid,
+                _ => self.root_dir(),
+            };
+            let path = self.path_of(id)?;
+            let stats = self.export.statvfs(&path).map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
+            let block_size =
+                if stats.f_frsize > 0 { stats.f_frsize as u64 } else { stats.f_bsize as u64 };
+            return Ok(nfs3::fs::FSSTAT3resok {
+                obj_attributes: obj_attr,
+                tbytes: block_size.saturating_mul(stats.f_blocks as u64),
+                fbytes: block_size.saturating_mul(stats.f_bfree as u64),
+                abytes: block_size.saturating_mul(stats.f_bavail as u64),
+                tfiles: stats.f_files as u64,
+                ffiles: stats.f_ffree as u64,
+                afiles: stats.f_favail as u64,
+                invarsec: 0,
+            });
+        }
+        #[cfg(not(unix))]
+        {
+            Ok(nfs3::fs::FSSTAT3resok {
+                obj_attributes: obj_attr,
+                tbytes: 0,
+                fbytes: 0,
+                abytes: 0,
+                tfiles: 0,
+                ffiles: 0,
+                afiles: 0,
+                invarsec: 0,
+            })
+        }
+    }
+
+    /// Sets attributes of a file
+    async fn setattr(&self, id: nfs3::fileid3, setattr: nfs3::sattr3) -> NFSResult<nfs3::fattr3> {
+        self.check_writable()?;
+        let path = self.path_of(id)?;
+        // Reopen after truncation, and after mode changes so that new permissions apply.
+        if setattr.size.is_some() || setattr.mode.is_some() {
+            self.files.remove(id);
+        }
+        self.set_attributes(&path, &setattr)?;
+
+        // I have to lookup a second time to update
+        let metadata = self.export.metadata(&path).or(Err(nfs3::nfsstat3::NFS3ERR_IO))?;
+        let attr = self.attributes(id, &metadata);
+        if let Ok(entry) = self.fsmap.write().unwrap().find_entry_mut(id) {
+            entry.fsmeta = attr;
+        }
+        Ok(attr)
+    }
+
+    /// Writes data to a file
+    async fn write(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+        stable: nfs3::file::stable_how,
+    ) -> NFSResult<(nfs3::fattr3, nfs3::file::stable_how, nfs3::count3)> {
+        self.check_writable()?;
+        let (f, _) = self.open_file(id, Access::Write).await?;
+        let written = f.clone();
+        let buf = data.to_vec();
+        tokio::task::spawn_blocking(move || written.write_all_at(&buf, offset))
+            .await
+            .or(Err(nfs3::nfsstat3::NFS3ERR_IO))?
+            .map_err(|e| {
+                debug!("Unable to write {:?}", e);
+                nfs3::nfsstat3::NFS3ERR_IO
+            })?;
+        debug!("write to {:?} {:?} {:?}", id, offset, data.len());
+        let committed = match (self.fsync, stable) {
+            (FsyncPolicy::Never, _)
+            | (FsyncPolicy::Requested, nfs3::file::stable_how::UNSTABLE) => {
+                nfs3::file::stable_how::UNSTABLE
+            }
+            (FsyncPolicy::Requested, nfs3::file::stable_how::DATA_SYNC) => {
+                self.sync(f.clone(), true).await?;
+                nfs3::file::stable_how::DATA_SYNC
+            }
+            _ => {
+                self.sync(f.clone(), false).await?;
+                nfs3::file::stable_how::FILE_SYNC
+            }
+        };
+        let meta = f.metadata().or(Err(nfs3::nfsstat3::NFS3ERR_IO))?;
+        let attr = self.attributes(id, &meta);
+        if let Ok(entry) = self.fsmap.write().unwrap().find_entry_mut(id) {
+            entry.fsmeta = attr;
+        }
+        Ok((attr, committed, data.len() as nfs3::count3))
+    }
+
+    /// Creates a file in a directory
+    async fn create(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        setattr: nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        self.create_fs_object(dirid, filename, &CreateFSObject::File(setattr)).await
+    }
+
+    /// Creates an exclusive file in a directory
+    async fn create_exclusive(
+        &self,
+        _dirid: nfs3::fileid3,
+        _filename: &nfs3::filename3,
+        _verifier: nfs3::createverf3,
+    ) -> NFSResult<nfs3::fileid3> {
+        // RFC 1813 requires storing the EXCLUSIVE verifier in stable storage;
+        // MirrorFS does not persist it, so we must report NOTSUPP.
+        Err(nfs3::nfsstat3::NFS3ERR_NOTSUPP)
+    }
+
+    /// Removes a file from a directory
+    async fn remove(&self, dirid: nfs3::fileid3, filename: &nfs3::filename3) -> NFSResult<()> {
+        self.check_writable()?;
+        let _dir = self.dirs.lock(dirid).await;
+        let mut path = self.path_of(dirid)?;
+        path.push(OsStr::from_bytes(filename));
+        let meta = self.export.metadata(&path).ok();
+        if let Some(meta) = meta.filter(|meta| !self.fsmap.read().unwrap().is_hidden(&path, meta)) {
+            self.as_caller(|| self.export.remove(&path, meta.is_dir())).map_err(io_error)?;
+
+            {
+                let mut fsmap = self.fsmap.write().unwrap();
+                let filesym =
+                    fsmap.intern.intern(OsStr::from_bytes(filename).to_os_string()).unwrap();
+                let mut sympath = fsmap.find_entry_mut(dirid)?.name.clone();
+                sympath.push(filesym);
+                if let Some(fileid) = fsmap.remove_path(&sympath) {
+                    self.files.remove(fileid);
+                }
+                // we need to update the children listing for the directories
+                if let Ok(dirent_mut) = fsmap.find_entry_mut(dirid) {
+                    if let Some(ref mut fromch) = dirent_mut.children {
+                        fromch.remove(&filesym);
+                    }
+                }
+            }
+
+            let _ = self.reload_entry(dirid).await;
+        } else {
+            return Err(nfs3::nfsstat3::NFS3ERR_NOENT);
+        }
+
+        Ok(())
+    }
+
+    /// Renames a file
+    async fn rename(
+        &self,
+        from_dirid: nfs3::fileid3,
+        from_filename: &nfs3::filename3,
+        to_dirid: nfs3::fileid3,
+        to_filename: &nfs3::filename3,
+    ) -> NFSResult<()> {
+        self.check_writable()?;
+        let _dirs = self.dirs.lock_pair(from_dirid, to_dirid).await;
+
+        let mut from_path = self.path_of(from_dirid)?;
+        from_path.push(OsStr::from_bytes(from_filename));
+
+        let mut to_path = self.path_of(to_dirid)?;
+        // to folder must exist
+        if !self.export.exists(&to_path) {
+            return Err(nfs3::nfsstat3::NFS3ERR_NOENT);
+        }
+        to_path.push(OsStr::from_bytes(to_filename));
+        let to_meta = self.export.metadata(&to_path).ok();
+        {
+            let fsmap = self.fsmap.read().unwrap();
+            let to_hidden = match &to_meta {
+                Some(meta) => fsmap.is_hidden(&to_path, meta),
+                None => fsmap.is_excluded(&to_path),
+            };
+            if to_hidden {
+                return Err(nfs3::nfsstat3::NFS3ERR_ACCES);
+            }
+        }
+
+        // src path must exist, and hidden entries cannot be moved into view
+        let from_meta = match self.export.metadata(&from_path) {
+            Ok(meta) if !self.fsmap.read().unwrap().is_hidden(&from_path, &meta) => meta,
+            _ => return Err(nfs3::nfsstat3::NFS3ERR_NOENT),
+        };
+        if from_path == to_path {
+            return Ok(());
+        }
+        if let Some(to_meta) = to_meta {
+            if from_meta.dev() == to_meta.dev() && from_meta.ino() == to_meta.ino() {
+                return Ok(());
+            }
+        }
+        debug!("Rename {:?} to {:?}", from_path, to_path);
+        self.as_caller(|| self.export.rename(&from_path, &to_path)).map_err(io_error)?;
+
+        {
+            let mut fsmap = self.fsmap.write().unwrap();
+            let oldsym =
+                fsmap.intern.intern(OsStr::from_bytes(from_filename).to_os_string()).unwrap();
+            let newsym =
+                fsmap.intern.intern(OsStr::from_bytes(to_filename).to_os_string()).unwrap();
+
+            let mut from_sympath = fsmap.find_entry_mut(from_dirid)?.name.clone();
+            from_sympath.push(oldsym);
+            let mut to_sympath = fsmap.find_entry_mut(to_dirid)?.name.clone();
+            to_sympath.push(newsym);
+            if let Some(from_id) = fsmap.path_to_id.get(&from_sympath).copied() {
+                self.files.remove(from_id);
+                if let Some(target_id) = fsmap.path_to_id.get(&to_sympath).copied() {
+                    self.files.remove(target_id);
+                    let target_is_dir = fsmap
+                        .id_to_path
+                        .get(&target_id)
+                        .map(|entry| entry.is_directory())
+                        .unwrap_or(false);
+                    if target_is_dir {
+                        fsmap.remove_path_tree(to_sympath.clone(), target_id);
+                    } else {
+                        let _ = fsmap.remove_path(&to_sympath);
+                    }
+                }
+
+                fsmap.rename_path_prefix(&from_sympath, &to_sympath);
+
+                let new_id = fsmap.path_to_id.get(&to_sympath).copied();
+                if let Ok(from_dirent_mut) = fsmap.find_entry_mut(from_dirid) {
+                    if let Some(ref mut fromch) = from_dirent_mut.children {
+                        fromch.remove(&oldsym);
+                    }
+                }
+                if let (Some(fileid), Ok(to_dirent_mut)) = (new_id, fsmap.find_entry_mut(to_dirid))
+                {
+                    if let Some(ref mut toch) = to_dirent_mut.children {
+                        toch.insert(newsym, fileid);
+                    }
+                }
+            }
+        }
+        let _ = self.reload_entry(from_dirid).await;
+        if to_dirid != from_dirid {
+            let _ = self.reload_entry(to_dirid).await;
+        }
+
+        Ok(())
+    }
+
+    /// Creates a directory
+    async fn mkdir(
+        &self,
+        dirid: nfs3::fileid3,
+        dirname: &nfs3::filename3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        self.create_fs_object(dirid, dirname, &CreateFSObject::Directory).await
+    }
+
+    /// Creates a symlink
+    async fn symlink(
+        &self,
+        dirid: nfs3::fileid3,
+        linkname: &nfs3::filename3,
+        symlink: &nfs3::nfspath3,
+        attr: &nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        if self.symlinks != SymlinkPolicy::Allow {
+            return Err(nfs3::nfsstat3::NFS3ERR_NOTSUPP);
+        }
+        self.create_fs_object(dirid, linkname, &CreateFSObject::Symlink((*attr, symlink.clone())))
+            .await
+    }
+
+    /// Reads a symlink
+    async fn readlink(&self, id: nfs3::fileid3) -> NFSResult<nfs3::nfspath3> {
+        let path = self.path_of(id)?;
+        match self.export.read_link(&path) {
+            Ok(target) => Ok(target.as_os_str().as_bytes().into()),
+            Err(err) => match err.kind() {
+                ErrorKind::InvalidInput => Err(nfs3::nfsstat3::NFS3ERR_BADTYPE),
+                ErrorKind::NotFound => Err(nfs3::nfsstat3::NFS3ERR_NOENT),
+                _ => Err(nfs3::nfsstat3::NFS3ERR_IO),
+            },
+        }
+    }
+
+    /// Creates a hard link
+    async fn link(
+        &self,
+        file_id: nfs3::fileid3,
+        link_dir_id: nfs3::fileid3,
+        link_name: &nfs3::filename3,
+    ) -> NFSResult<nfs3::fattr3> {
+        self.check_writable()?;
+        let _dir = self.dirs.lock(link_dir_id).await;
+
+        // Get the source file entry
+        let source_path = self.path_of(file_id)?;
+
+        // Get the target directory entry
+        let mut target_path = self.path_of(link_dir_id)?;
+        let link_name_osstr = OsStr::from_bytes(link_name);
+        target_path.push(link_name_osstr);
+        if self.fsmap.read().unwrap().is_excluded(&target_path) {
+            return Err(nfs3::nfsstat3::NFS3ERR_ACCES);
+        }
+
+        // Check if the target already exists
+        if self.export.exists(&target_path) {
+            return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
+        }
+
+        // Create the hard link
+        self.as_caller(|| self.export.hard_link(&source_path, &target_path)).map_err(io_error)?;
+
+        // Update the directory listing
+        let meta = self.export.metadata(&target_path).map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
+        self.add_child(link_dir_id, link_name_osstr, meta)?;
+
+        let source_meta =
+            self.export.metadata(&source_path).map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
+        let source_attr = self.attributes(file_id, &source_meta);
+        if let Ok(entry) = self.fsmap.write().unwrap().find_entry_mut(file_id) {
+            entry.fsmeta = source_attr;
+        }
+
+        // Return the updated attributes of the source file
+        Ok(source_attr)
+    }
+
+    /// Creates a special file (device, socket, etc.)
+    async fn mknod(
+        &self,
+        dir_id: nfs3::fileid3,
+        name: &nfs3::filename3,
+        ftype: nfs3::ftype3,
+        specdata: nfs3::specdata3,
+        attrs: &nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        self.check_writable()?;
+        let (kind, rdev) = match ftype {
+            nfs3::ftype3::NF3CHR => (libc::S_IFCHR, specdata3_to_rdev(specdata)),
+            nfs3::ftype3::NF3BLK => (libc::S_IFBLK, specdata3_to_rdev(specdata)),
+            nfs3::ftype3::NF3FIFO => (libc::S_IFIFO, 0),
+            nfs3::ftype3::NF3SOCK => (libc::S_IFSOCK, 0),
+            _ => return Err(nfs3::nfsstat3::NFS3ERR_BADTYPE),
+        };
+        // A device node gives access to the device behind it, so only root may make one.
+        let device = matches!(ftype, nfs3::ftype3::NF3CHR | nfs3::ftype3::NF3BLK);
+        if device && rpc::caller().map(|auth| auth.uid) != Some(0) {
+            return Err(nfs3::nfsstat3::NFS3ERR_PERM);
+        }
+
+        let _dir = self.dirs.lock(dir_id).await;
+        let mut path = self.path_of(dir_id)?;
+        let name_osstr = OsStr::from_bytes(name);
+        path.push(name_osstr);
+        if self.fsmap.read().unwrap().is_excluded(&path) {
+            return Err(nfs3::nfsstat3::NFS3ERR_ACCES);
+        }
+
+        // Check if the target already exists
+        if self.export.exists(&path) {
+            return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
+        }
+
+        debug!("mknod {:?} {:?} {:?}", path, ftype, specdata);
+        let mode = match attrs.mode {
+            nfs3::set_mode3::Some(mode) => mode_unmask(mode),
+            _ => 0o666,
+        };
+        self.as_caller(|| self.export.mknod(&path, kind | mode as libc::mode_t, rdev))
+            .map_err(io_error)?;
+        // Creating the node applied the umask; the mode is set as given.
+        self.as_caller(|| self.export.set_mode(&path, mode)).map_err(io_error)?;
+        if attrs.uid.is_some() || attrs.gid.is_some() {
+            self.as_caller(|| self.export.set_owner(&path, attrs.uid, attrs.gid))
+                .map_err(io_error)?;
+        }
+        let _ = self.reload_entry(dir_id).await?;
+
+        // Update the directory listing
+        let meta = self.export.metadata(&path).map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
+        let fileid = self.add_child(dir_id, name_osstr, meta.clone())?;
+
+        // Return the file ID and attributes
+        Ok((fileid, self.attributes(fileid, &meta)))
+    }
+
+    /// Commits changes to a file
+    async fn commit(
+        &self,
+        file_id: nfs3::fileid3,
+        _offset: u64,
+        _count: u32,
+    ) -> NFSResult<nfs3::fattr3> {
+        // With FsyncPolicy::Always every write is already synced; with
+        // FsyncPolicy::Never data is deliberately left to the page cache.
+        if self.fsync == FsyncPolicy::Requested {
+            // Files whose mode was changed after writing may only be openable one way.
+            let f = match self.open_file(file_id, Access::Read).await {
+                Err(nfs3::nfsstat3::NFS3ERR_ACCES) => self.open_file(file_id, Access::Write).await,
+                result => result,
+            }?
+            .0;
+            // There is no durable way to sync only the requested range: sync_file_range
+            // flushes neither metadata nor the disk cache. Syncing the whole file covers it.
+            self.sync(f, true).await?;
+        }
+        self.getattr(file_id).await
+    }
+}
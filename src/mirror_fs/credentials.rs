@@ -0,0 +1,110 @@
+//! Disk operations performed with the credentials of the calling user.
+//!
+//! By default the server touches files as whichever user it runs as. With
+//! [`MirrorFSBuilder::caller_credentials`](super::MirrorFSBuilder::caller_credentials),
+//! operations that open, create, change or remove files run with the `AUTH_UNIX`
+//! identity of the call instead, so the kernel's permission checks, ACLs and quotas
+//! apply to the caller and new files belong to them. Calls without `AUTH_UNIX`
+//! credentials act as `nobody`.
+//!
+//! On Linux the file system user and group and the supplementary groups of the
+//! current thread are switched for the duration of the operation, with `setfsuid`,
+//! `setfsgid` and the raw `setgroups` system call (the libc wrapper changes every
+//! thread of the process). Other systems cannot change credentials per thread, so
+//! the builder refuses the option there.
+
+use std::io;
+
+use crate::protocol::rpc;
+
+/// User and group ID of `nobody`, used for calls without credentials
+const NOBODY: u32 = 65534;
+
+/// The identity a call is served with
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub struct Caller {
+    uid: u32,
+    gid: u32,
+    groups: Vec<u32>,
+}
+
+impl Caller {
+    /// Returns the credentials of the call being handled.
+    pub fn current() -> Self {
+        match rpc::caller() {
+            Some(auth) => Self { uid: auth.uid, gid: auth.gid, groups: auth.gids },
+            None => Self { uid: NOBODY, gid: NOBODY, groups: Vec::new() },
+        }
+    }
+
+    /// Runs `op` on the current thread with these credentials. `op` must not hand
+    /// the thread to other tasks, so it cannot await.
+    #[cfg(target_os = "linux")]
+    pub fn run<T>(&self, op: impl FnOnce() -> io::Result<T>) -> io::Result<T> {
+        let _switched = Switched::to(self)?;
+        op()
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn run<T>(&self, op: impl FnOnce() -> io::Result<T>) -> io::Result<T> {
+        op()
+    }
+}
+
+/// Credentials of the current thread before they were switched, restored on drop
+#[cfg(target_os = "linux")]
+struct Switched {
+    uid: u32,
+    gid: u32,
+    groups: Vec<libc::gid_t>,
+}
+
+#[cfg(target_os = "linux")]
+impl Switched {
+    fn to(caller: &Caller) -> io::Result<Self> {
+        let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+        if count < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut groups = vec![0; count as usize];
+        let count = unsafe { libc::getgroups(count, groups.as_mut_ptr()) };
+        if count < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        groups.truncate(count as usize);
+
+        set_groups(&caller.groups)?;
+        let gid = unsafe { libc::setfsgid(caller.gid) } as u32;
+        let uid = unsafe { libc::setfsuid(caller.uid) } as u32;
+        let switched = Self { uid, gid, groups };
+        // Neither call reports failure; an invalid ID reads back the current one.
+        let current_gid = unsafe { libc::setfsgid(u32::MAX) } as u32;
+        let current_uid = unsafe { libc::setfsuid(u32::MAX) } as u32;
+        if current_uid != caller.uid || current_gid != caller.gid {
+            return Err(io::Error::from_raw_os_error(libc::EPERM));
+        }
+        Ok(switched)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Switched {
+    fn drop(&mut self) {
+        unsafe {
+            libc::setfsuid(self.uid);
+            libc::setfsgid(self.gid);
+        }
+        let _ = set_groups(&self.groups);
+    }
+}
+
+/// Sets the supplementary groups of the current thread only
+#[cfg(target_os = "linux")]
+fn set_groups(groups: &[libc::gid_t]) -> io::Result<()> {
+    let result = unsafe { libc::syscall(libc::SYS_setgroups, groups.len(), groups.as_ptr()) };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
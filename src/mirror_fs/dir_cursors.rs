@@ -0,0 +1,178 @@
+//! Directories being listed, read from disk only as far as clients have asked.
+//!
+//! Clients page through a directory with cookies that count the entries before each
+//! page. Rather than listing and statting the whole directory for every page, a
+//! cursor keeps the directory open along with the names read so far, in the order
+//! the file system returns them. A page then reads names up to its end and stats
+//! only the entries it returns, so the first page of a huge directory costs as much
+//! as that of a small one.
+//!
+//! Cursors are kept for the most recently listed directories. A cursor starts over
+//! when a listing restarts at the first cookie and when the directory's modification
+//! time, which is also the cookie verifier clients hold, changes, and they are all
+//! dropped when the exclude patterns change.
+
+use std::ffi::OsString;
+use std::fs::Metadata;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+
+use indexmap::IndexMap;
+
+use crate::xdr::nfs3;
+
+use super::error_handling::{io_error, NFSResult};
+use super::export_root::DirStream;
+use super::fs_map::FSMap;
+
+/// Number of directories whose cursors are kept
+const CAPACITY: usize = 64;
+
+/// Cursors of recently listed directories, in least recently used order
+#[derive(Debug)]
+pub struct DirCursors {
+    cursors: Mutex<IndexMap<nfs3::fileid3, Arc<tokio::sync::Mutex<DirCursor>>>>,
+}
+
+impl DirCursors {
+    pub fn new() -> Self {
+        Self { cursors: Mutex::new(IndexMap::new()) }
+    }
+
+    /// Returns the cursor of directory `dir`, creating an empty one if there is none.
+    pub fn get(&self, dir: nfs3::fileid3) -> Arc<tokio::sync::Mutex<DirCursor>> {
+        let mut cursors = self.cursors.lock().unwrap();
+        if let Some(index) = cursors.get_index_of(&dir) {
+            let last = cursors.len() - 1;
+            cursors.move_index(index, last);
+            return cursors[last].clone();
+        }
+        if cursors.len() == CAPACITY {
+            cursors.shift_remove_index(0);
+        }
+        let cursor = Arc::new(tokio::sync::Mutex::new(DirCursor::default()));
+        cursors.insert(dir, cursor.clone());
+        cursor
+    }
+
+    /// Drops every cursor, so that listings start over.
+    pub fn clear(&self) {
+        self.cursors.lock().unwrap().clear();
+    }
+}
+
+/// A position in the listing of one directory
+#[derive(Debug, Default)]
+pub struct DirCursor {
+    /// Modification time of the directory when the stream was opened
+    version: u64,
+    /// The open directory; missing until the cursor is started
+    stream: Option<DirStream>,
+    /// Names read so far that clients may see
+    names: Vec<OsString>,
+    /// Whether the stream reached the end of the directory
+    done: bool,
+}
+
+impl DirCursor {
+    /// Checks whether the cursor continues a listing of the directory at `version`
+    pub fn is_current(&self, version: u64) -> bool {
+        self.stream.is_some() && self.version == version
+    }
+
+    /// Starts the cursor over on `stream`, opened at directory `version`.
+    pub fn restart(&mut self, version: u64, stream: DirStream) {
+        *self = Self { version, stream: Some(stream), names: Vec::new(), done: false };
+    }
+
+    /// Number of names read so far
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Reads names until `count` are known or the directory at `path` ends, leaving
+    /// out those the map hides from clients.
+    pub async fn fill(
+        &mut self,
+        count: usize,
+        path: &Path,
+        fsmap: &RwLock<FSMap>,
+    ) -> NFSResult<()> {
+        while self.names.len() < count && !self.done {
+            let wanted = count - self.names.len();
+            let read = self.with_stream(move |stream| stream.read(wanted)).await?;
+            self.done = read.len() < wanted;
+            let fsmap = fsmap.read().unwrap();
+            for (name, symlink) in read {
+                if (symlink && fsmap.hide_symlinks) || fsmap.is_excluded(&path.join(&name)) {
+                    continue;
+                }
+                self.names.push(name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the names from `start` up to `end` of the directory at `path` with
+    /// their metadata, and whether they end the listing.
+    ///
+    /// Names that were removed since they were read, or turn out to be hidden once
+    /// their metadata is known, are dropped from the cursor, so that the entries
+    /// after them keep matching the cookies handed out for this page.
+    pub async fn page(
+        &mut self,
+        start: usize,
+        end: usize,
+        path: &Path,
+        fsmap: &RwLock<FSMap>,
+    ) -> NFSResult<(Vec<(OsString, Metadata)>, bool)> {
+        let end = end.min(self.names.len());
+        let start = start.min(end);
+        let names = self.names[start..end].to_vec();
+        let found = self
+            .with_stream(move |stream| {
+                let mut found = Vec::with_capacity(names.len());
+                for name in names {
+                    found.push((stream.metadata(&name)?, name));
+                }
+                Ok(found)
+            })
+            .await?;
+        let mut entries = Vec::with_capacity(found.len());
+        {
+            let fsmap = fsmap.read().unwrap();
+            for (meta, name) in found {
+                match meta {
+                    Some(meta) if !fsmap.is_hidden(&path.join(&name), &meta) => {
+                        entries.push((name, meta))
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if entries.len() < end - start {
+            let kept: Vec<_> = entries.iter().map(|(name, _)| name.clone()).collect();
+            self.names.splice(start..end, kept);
+        }
+        let end = start + entries.len();
+        Ok((entries, self.done && end >= self.names.len()))
+    }
+
+    /// Runs `op` on the stream on a blocking thread.
+    async fn with_stream<T: Send + 'static>(
+        &mut self,
+        op: impl FnOnce(&mut DirStream) -> io::Result<T> + Send + 'static,
+    ) -> NFSResult<T> {
+        // Should the request be dropped meanwhile, the cursor is left to be restarted.
+        let mut stream = self.stream.take().ok_or(nfs3::nfsstat3::NFS3ERR_IO)?;
+        let (stream, result) = tokio::task::spawn_blocking(move || {
+            let result = op(&mut stream);
+            (stream, result)
+        })
+        .await
+        .map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
+        self.stream = Some(stream);
+        result.map_err(io_error)
+    }
+}
@@ -0,0 +1,71 @@
+//! Locks serializing changes to a directory.
+//!
+//! The [`FSMap`](super::FSMap) is only locked while it is read or updated, never
+//! across disk I/O. Requests that change a directory, and relists that replace its
+//! children, instead hold the lock of that directory from the disk operation until
+//! the map is updated, so that a relist cannot drop a file created meanwhile.
+//! Directories share a fixed number of locks, so unrelated directories rarely wait
+//! on each other.
+
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::xdr::nfs3;
+
+/// Number of locks shared by all directories
+const STRIPES: usize = 64;
+
+/// Per-directory locks
+#[derive(Debug)]
+pub struct DirLocks {
+    stripes: Vec<Mutex<()>>,
+}
+
+impl DirLocks {
+    pub fn new() -> Self {
+        Self { stripes: (0..STRIPES).map(|_| Mutex::new(())).collect() }
+    }
+
+    fn stripe(&self, dir: nfs3::fileid3) -> usize {
+        (dir % STRIPES as u64) as usize
+    }
+
+    /// Locks directory `dir`.
+    pub async fn lock(&self, dir: nfs3::fileid3) -> MutexGuard<'_, ()> {
+        self.stripes[self.stripe(dir)].lock().await
+    }
+
+    /// Returns the stripes of directories `a` and `b` in locking order, which cannot
+    /// deadlock.
+    fn ordered(&self, a: nfs3::fileid3, b: nfs3::fileid3) -> (usize, Option<usize>) {
+        let (a, b) = (self.stripe(a), self.stripe(b));
+        (a.min(b), (a != b).then(|| a.max(b)))
+    }
+
+    /// Locks directories `a` and `b`.
+    pub async fn lock_pair(
+        &self,
+        a: nfs3::fileid3,
+        b: nfs3::fileid3,
+    ) -> (MutexGuard<'_, ()>, Option<MutexGuard<'_, ()>>) {
+        let (first, second) = self.ordered(a, b);
+        let first_guard = self.stripes[first].lock().await;
+        let second_guard = match second {
+            Some(second) => Some(self.stripes[second].lock().await),
+            None => None,
+        };
+        (first_guard, second_guard)
+    }
+
+    /// Locks directories `a` and `b` from a thread outside the runtime.
+    #[cfg(target_os = "linux")]
+    pub fn blocking_lock_pair(
+        &self,
+        a: nfs3::fileid3,
+        b: nfs3::fileid3,
+    ) -> (MutexGuard<'_, ()>, Option<MutexGuard<'_, ()>>) {
+        let (first, second) = self.ordered(a, b);
+        let first_guard = self.stripes[first].blocking_lock();
+        let second_guard = second.map(|second| self.stripes[second].blocking_lock());
+        (first_guard, second_guard)
+    }
+}
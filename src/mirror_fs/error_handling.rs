@@ -1,6 +1,6 @@
-use std::{io, path::Path};
+use std::io;
 
-use fernfs::xdr::nfs3::nfsstat3;
+use crate::xdr::nfs3::nfsstat3;
 
 /// Result type for NFS operations
 pub type NFSResult<T> = Result<T, nfsstat3>;
@@ -17,6 +17,19 @@ impl<T> ResultExt<T> for Result<T, io::Error> {
     }
 }
 
+/// Converts the error of an operation on a file, keeping the reasons a client can act
+/// on: missing permissions, a full disk or quota, and a read-only file system
+pub fn io_error(err: io::Error) -> nfsstat3 {
+    match err.raw_os_error() {
+        Some(libc::EACCES) => nfsstat3::NFS3ERR_ACCES,
+        Some(libc::EPERM) => nfsstat3::NFS3ERR_PERM,
+        Some(libc::ENOSPC) => nfsstat3::NFS3ERR_NOSPC,
+        Some(libc::EDQUOT) => nfsstat3::NFS3ERR_DQUOT,
+        Some(libc::EROFS) => nfsstat3::NFS3ERR_ROFS,
+        _ => nfsstat3::NFS3ERR_IO,
+    }
+}
+
 /// Extension trait for Option to convert to NFS errors
 pub trait OptionExt<T> {
     /// Convert an Option to an NFS Result
@@ -39,8 +52,3 @@ pub enum RefreshResult {
     /// Nothing has changed
     Noop,
 }
-
-/// Helper function to check if a path exists without traversing symlinks
-pub fn exists_no_traverse(path: &Path) -> bool {
-    std::fs::symlink_metadata(path).is_ok()
-}
@@ -0,0 +1,228 @@
+//! Glob patterns used to hide parts of a mirrored directory tree.
+//!
+//! Patterns follow the familiar gitignore-style conventions:
+//! - A pattern without a `/` matches the name of an entry at any depth
+//!   (`*.tmp`, `.git`).
+//! - A pattern containing a `/` matches the path relative to the export root
+//!   (`build/output`, `/cache`); a leading `/` is ignored.
+//! - `*` matches any run of characters except `/`, `**` also matches `/`,
+//!   `?` matches one character and `[a-z]` / `[!a-z]` match character classes.
+//!
+//! - A pattern starting with `!` makes entries it matches visible again; the last
+//!   pattern that matches an entry decides.
+//!
+//! A directory that is hidden hides everything below it, whatever the patterns say
+//! about the entries inside.
+
+use std::io;
+
+#[derive(Debug, Clone)]
+struct Pattern {
+    glob: Vec<u8>,
+    anchored: bool,
+    negated: bool,
+}
+
+/// A set of exclude patterns.
+#[derive(Debug, Clone, Default)]
+pub struct ExcludeSet {
+    patterns: Vec<Pattern>,
+}
+
+impl ExcludeSet {
+    /// Compiles a list of patterns, rejecting malformed character classes.
+    pub fn new<I, S>(patterns: I) -> io::Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut compiled = Vec::new();
+        for pattern in patterns {
+            let pattern = pattern.as_ref();
+            let (negated, glob) = match pattern.strip_prefix('!') {
+                Some(glob) => (true, glob),
+                None => (false, pattern),
+            };
+            let trimmed = glob.trim_start_matches('/').trim_end_matches('/');
+            if trimmed.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("empty exclude pattern: {pattern:?}"),
+                ));
+            }
+            if !classes_are_closed(trimmed.as_bytes()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unterminated character class in exclude pattern: {pattern:?}"),
+                ));
+            }
+            compiled.push(Pattern {
+                glob: trimmed.as_bytes().to_vec(),
+                anchored: glob.contains('/'),
+                negated,
+            });
+        }
+        Ok(Self { patterns: compiled })
+    }
+
+    /// Returns true if no patterns are configured.
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Checks a `/`-separated path relative to the export root.
+    pub fn is_excluded(&self, relative_path: &[u8]) -> bool {
+        let name = match relative_path.iter().rposition(|b| *b == b'/') {
+            Some(pos) => &relative_path[pos + 1..],
+            None => relative_path,
+        };
+        let last_match = self.patterns.iter().rev().find(|pattern| {
+            let subject = if pattern.anchored { relative_path } else { name };
+            glob_match(&pattern.glob, subject)
+        });
+        last_match.is_some_and(|pattern| !pattern.negated)
+    }
+}
+
+fn classes_are_closed(glob: &[u8]) -> bool {
+    let mut i = 0;
+    while i < glob.len() {
+        if glob[i] == b'[' {
+            match class_end(glob, i) {
+                Some(end) => i = end,
+                None => return false,
+            }
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Returns the index of the `]` closing the class that opens at `start`.
+fn class_end(glob: &[u8], start: usize) -> Option<usize> {
+    let mut i = start + 1;
+    if glob.get(i) == Some(&b'!') {
+        i += 1;
+    }
+    // A leading `]` is a literal member of the class.
+    if glob.get(i) == Some(&b']') {
+        i += 1;
+    }
+    glob[i..].iter().position(|b| *b == b']').map(|pos| i + pos)
+}
+
+fn class_matches(class: &[u8], ch: u8) -> bool {
+    let (negated, members) = match class.first() {
+        Some(b'!') => (true, &class[1..]),
+        _ => (false, class),
+    };
+    let mut found = false;
+    let mut i = 0;
+    while i < members.len() {
+        if i + 2 < members.len() && members[i + 1] == b'-' {
+            found |= members[i] <= ch && ch <= members[i + 2];
+            i += 3;
+        } else {
+            found |= members[i] == ch;
+            i += 1;
+        }
+    }
+    found != negated
+}
+
+fn glob_match(glob: &[u8], text: &[u8]) -> bool {
+    match glob.first() {
+        None => text.is_empty(),
+        Some(b'*') if glob.get(1) == Some(&b'*') => {
+            let rest = &glob[2..];
+            // `**/` also matches zero directories.
+            if let Some(after_slash) = rest.strip_prefix(b"/") {
+                if glob_match(after_slash, text) {
+                    return true;
+                }
+            }
+            (0..=text.len()).any(|skip| glob_match(rest, &text[skip..]))
+        }
+        Some(b'*') => {
+            let rest = &glob[1..];
+            for skip in 0..=text.len() {
+                if glob_match(rest, &text[skip..]) {
+                    return true;
+                }
+                if text.get(skip) == Some(&b'/') {
+                    break;
+                }
+            }
+            false
+        }
+        Some(b'?') => match text.first() {
+            Some(ch) if *ch != b'/' => glob_match(&glob[1..], &text[1..]),
+            _ => false,
+        },
+        Some(b'[') => {
+            let end = match class_end(glob, 0) {
+                Some(end) => end,
+                None => return false,
+            };
+            match text.first() {
+                Some(ch) if *ch != b'/' && class_matches(&glob[1..end], *ch) => {
+                    glob_match(&glob[end + 1..], &text[1..])
+                }
+                _ => false,
+            }
+        }
+        Some(ch) => text.first() == Some(ch) && glob_match(&glob[1..], &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(patterns: &[&str]) -> ExcludeSet {
+        ExcludeSet::new(patterns).unwrap()
+    }
+
+    #[test]
+    fn unanchored_patterns_match_names_at_any_depth() {
+        let excludes = set(&["*.tmp", ".git"]);
+        assert!(excludes.is_excluded(b"a.tmp"));
+        assert!(excludes.is_excluded(b"dir/sub/b.tmp"));
+        assert!(excludes.is_excluded(b"dir/.git"));
+        assert!(!excludes.is_excluded(b"a.tmp.keep"));
+        assert!(!excludes.is_excluded(b".github"));
+    }
+
+    #[test]
+    fn anchored_patterns_match_relative_paths() {
+        let excludes = set(&["/cache", "build/*.o", "logs/**/old"]);
+        assert!(excludes.is_excluded(b"cache"));
+        assert!(!excludes.is_excluded(b"dir/cache"));
+        assert!(excludes.is_excluded(b"build/main.o"));
+        assert!(!excludes.is_excluded(b"build/sub/main.o"));
+        assert!(excludes.is_excluded(b"logs/old"));
+        assert!(excludes.is_excluded(b"logs/2024/01/old"));
+    }
+
+    #[test]
+    fn character_classes() {
+        let excludes = set(&["file[0-9]", "[!a-z]*"]);
+        assert!(excludes.is_excluded(b"file7"));
+        assert!(!excludes.is_excluded(b"filex"));
+        assert!(excludes.is_excluded(b"Upper"));
+        assert!(!excludes.is_excluded(b"lower"));
+        assert!(ExcludeSet::new(["bad["]).is_err());
+    }
+
+    #[test]
+    fn negated_patterns_reinclude_entries() {
+        let excludes = set(&["*.env", "!example.env", "/secrets", "!secrets"]);
+        assert!(excludes.is_excluded(b"prod.env"));
+        assert!(!excludes.is_excluded(b"config/example.env"));
+        // The last matching pattern wins.
+        assert!(!excludes.is_excluded(b"secrets"));
+        assert!(!set(&["!*.log"]).is_excluded(b"a.log"));
+        assert!(set(&["!keep.log", "*.log"]).is_excluded(b"keep.log"));
+        assert!(ExcludeSet::new(["!"]).is_err());
+    }
+}
@@ -0,0 +1,545 @@
+//! Access to the files below the export root that cannot leave it.
+//!
+//! Cached paths may stop naming what they named when they were cached: anyone who can
+//! write to the tree, a client included, can swap a directory on the path for a
+//! symlink to somewhere else. So files are never reached by absolute path. Every path
+//! is resolved from a descriptor of the root without following symlinks, using
+//! `openat2(RESOLVE_BENEATH | RESOLVE_NO_SYMLINKS)` where the kernel supports it and
+//! opening one component at a time with `O_NOFOLLOW` otherwise. The last component
+//! is then used relative to its directory's descriptor, with calls that do not follow
+//! symlinks either.
+
+use std::ffi::{CString, OsStr, OsString};
+use std::fs::{File, Metadata};
+use std::io;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+#[cfg(not(target_os = "linux"))]
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+#[cfg(target_os = "linux")]
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::xdr::nfs3;
+
+/// Flags for a descriptor used only to name a file
+#[cfg(target_os = "linux")]
+const PATH_ONLY: libc::c_int = libc::O_PATH;
+#[cfg(not(target_os = "linux"))]
+const PATH_ONLY: libc::c_int = libc::O_RDONLY | libc::O_NONBLOCK;
+
+/// The export root of a mirror
+#[derive(Debug)]
+pub struct ExportRoot {
+    path: PathBuf,
+    fd: OwnedFd,
+    /// Cleared when the kernel lacks `openat2` or a sandbox filters it
+    #[cfg(target_os = "linux")]
+    openat2: AtomicBool,
+}
+
+impl ExportRoot {
+    /// Opens the directory at `path`, which may itself be reached through symlinks.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let c_path = CString::new(path.as_os_str().as_bytes())?;
+        let fd =
+            unsafe { libc::open(c_path.as_ptr(), PATH_ONLY | libc::O_DIRECTORY | libc::O_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let root = Self {
+            path: path.to_path_buf(),
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            #[cfg(target_os = "linux")]
+            openat2: AtomicBool::new(true),
+        };
+        // A seccomp filter rejecting the call shows up as EPERM, which later calls
+        // cannot tell apart from a permission error on a file, so it is probed once here.
+        #[cfg(target_os = "linux")]
+        if let Err(err) = root.openat2(&[OsStr::new(".")], PATH_ONLY | libc::O_DIRECTORY, 0) {
+            if matches!(err.raw_os_error(), Some(libc::ENOSYS | libc::EPERM)) {
+                root.openat2.store(false, Ordering::Relaxed);
+            }
+        }
+        Ok(root)
+    }
+
+    /// Splits `path`, which must be the root or below it, into the names leading to it
+    fn names<'a>(&self, path: &'a Path) -> io::Result<Vec<&'a OsStr>> {
+        let escape = || io::Error::new(io::ErrorKind::InvalidInput, "path leaves the export");
+        let relative = path.strip_prefix(&self.path).map_err(|_| escape())?;
+        relative
+            .components()
+            .map(|component| match component {
+                Component::Normal(name) => Ok(name),
+                _ => Err(escape()),
+            })
+            .collect()
+    }
+
+    /// Opens the file at `names` with `flags`, following no symlink
+    fn open_names(
+        &self,
+        names: &[&OsStr],
+        flags: libc::c_int,
+        mode: libc::c_uint,
+    ) -> io::Result<OwnedFd> {
+        let flags = flags | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+        let Some((last, parents)) = names.split_last() else {
+            return openat(self.fd.as_fd(), OsStr::new("."), flags & !libc::O_NOFOLLOW, mode);
+        };
+        #[cfg(target_os = "linux")]
+        if self.openat2.load(Ordering::Relaxed) {
+            match self.openat2(names, flags, mode) {
+                // Missing; a sandbox filtering it was caught when the root was opened
+                Err(err) if err.raw_os_error() == Some(libc::ENOSYS) => {
+                    self.openat2.store(false, Ordering::Relaxed);
+                }
+                result => return result,
+            }
+        }
+        let mut dir: Option<OwnedFd> = None;
+        for name in parents {
+            let at = dir.as_ref().map_or(self.fd.as_fd(), |fd| fd.as_fd());
+            let flags = PATH_ONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+            dir = Some(openat(at, name, flags, 0)?);
+        }
+        openat(dir.as_ref().map_or(self.fd.as_fd(), |fd| fd.as_fd()), last, flags, mode)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn openat2(
+        &self,
+        names: &[&OsStr],
+        flags: libc::c_int,
+        mode: libc::c_uint,
+    ) -> io::Result<OwnedFd> {
+        /// `struct open_how`, which libc cannot construct
+        #[repr(C)]
+        struct OpenHow {
+            flags: u64,
+            mode: u64,
+            resolve: u64,
+        }
+        let mut relative = Vec::new();
+        for name in names {
+            if !relative.is_empty() {
+                relative.push(b'/');
+            }
+            relative.extend_from_slice(name.as_bytes());
+        }
+        let relative = CString::new(relative)?;
+        let how = OpenHow {
+            flags: flags as u64,
+            mode: if flags & libc::O_CREAT != 0 { mode as u64 } else { 0 },
+            resolve: libc::RESOLVE_BENEATH | libc::RESOLVE_NO_SYMLINKS,
+        };
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_openat2,
+                self.fd.as_raw_fd(),
+                relative.as_ptr(),
+                &how as *const OpenHow,
+                std::mem::size_of::<OpenHow>(),
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(unsafe { OwnedFd::from_raw_fd(fd as libc::c_int) })
+    }
+
+    /// Opens the directory holding `path` and returns it with the last name of `path`
+    fn parent<'a>(&self, path: &'a Path) -> io::Result<(OwnedFd, &'a OsStr)> {
+        let names = self.names(path)?;
+        let Some((last, parents)) = names.split_last() else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the export root has no name"));
+        };
+        Ok((self.open_names(parents, PATH_ONLY | libc::O_DIRECTORY, 0)?, last))
+    }
+
+    /// Opens the file at `path` with the `open(2)` flags `flags`, creating it with
+    /// permissions 0o666 (less the umask) if `O_CREAT` is among them.
+    pub fn open_file(&self, path: &Path, flags: libc::c_int) -> io::Result<File> {
+        let fd = self.open_names(&self.names(path)?, flags, 0o666)?;
+        Ok(File::from(fd))
+    }
+
+    /// Opens the directory at `path` to refer to it.
+    pub fn open_dir(&self, path: &Path) -> io::Result<OwnedFd> {
+        self.open_names(&self.names(path)?, PATH_ONLY | libc::O_DIRECTORY, 0)
+    }
+
+    /// Returns the metadata of `path` itself, not of what a symlink points to.
+    pub fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let names = self.names(path)?;
+        // Without O_PATH, opening a FIFO or a device could block or act on it, and a
+        // symlink cannot be opened at all, so files are examined from their directory.
+        #[cfg(not(target_os = "linux"))]
+        if let Some((last, parents)) = names.split_last() {
+            let dir = self.open_names(parents, PATH_ONLY | libc::O_DIRECTORY, 0)?;
+            return metadata_at(dir.as_fd(), last, path);
+        }
+        File::from(self.open_names(&names, PATH_ONLY, 0)?).metadata()
+    }
+
+    /// Describes the file system holding `path`.
+    pub fn statvfs(&self, path: &Path) -> io::Result<libc::statvfs> {
+        let names = self.names(path)?;
+        #[cfg(target_os = "linux")]
+        let fd = self.open_names(&names, PATH_ONLY, 0)?;
+        // Without O_PATH only directories are opened; other files are described by
+        // the file system of their directory.
+        #[cfg(not(target_os = "linux"))]
+        let fd = match names.split_last() {
+            Some((last, parents)) => {
+                let dir = self.open_names(parents, PATH_ONLY | libc::O_DIRECTORY, 0)?;
+                if stat_at(dir.as_fd(), last)?.st_mode & libc::S_IFMT != libc::S_IFDIR {
+                    dir
+                } else {
+                    let flags = PATH_ONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+                    openat(dir.as_fd(), last, flags, 0)?
+                }
+            }
+            None => self.open_names(&names, PATH_ONLY, 0)?,
+        };
+        let mut stats = unsafe { std::mem::zeroed::<libc::statvfs>() };
+        cvt(unsafe { libc::fstatvfs(fd.as_raw_fd(), &mut stats) })?;
+        Ok(stats)
+    }
+
+    /// Checks whether `path` names a file.
+    pub fn exists(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok()
+    }
+
+    /// Lists the directory at `path` with the metadata of each entry.
+    pub fn read_dir(&self, path: &Path) -> io::Result<Vec<(OsString, Metadata)>> {
+        let mut stream = self.open_dir_stream(path)?;
+        let names = stream.read(usize::MAX)?;
+        let mut entries = Vec::with_capacity(names.len());
+        for (name, _) in names {
+            if let Some(meta) = stream.metadata(&name)? {
+                entries.push((name, meta));
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Opens the directory at `path` to be read a few entries at a time.
+    pub fn open_dir_stream(&self, path: &Path) -> io::Result<DirStream> {
+        let fd = self.open_names(&self.names(path)?, libc::O_RDONLY | libc::O_DIRECTORY, 0)?;
+        let dir = unsafe { libc::fdopendir(fd.as_raw_fd()) };
+        if dir.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        // The stream owns the descriptor from here on.
+        let fd = fd.into_raw_fd();
+        Ok(DirStream {
+            dir,
+            fd,
+            #[cfg(not(target_os = "linux"))]
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Creates the directory `path`.
+    pub fn create_dir(&self, path: &Path) -> io::Result<()> {
+        let (dir, name) = self.parent(path)?;
+        let name = CString::new(name.as_bytes())?;
+        cvt(unsafe { libc::mkdirat(dir.as_raw_fd(), name.as_ptr(), 0o777) })
+    }
+
+    /// Creates the special file `path`, whose type is given by `mode` and whose
+    /// device number is `rdev` if it is a device.
+    pub fn mknod(&self, path: &Path, mode: libc::mode_t, rdev: u64) -> io::Result<()> {
+        let (dir, name) = self.parent(path)?;
+        let name = CString::new(name.as_bytes())?;
+        cvt(unsafe { libc::mknodat(dir.as_raw_fd(), name.as_ptr(), mode, rdev as libc::dev_t) })
+    }
+
+    /// Creates a symlink at `path` pointing to `target`.
+    pub fn symlink(&self, target: &OsStr, path: &Path) -> io::Result<()> {
+        let (dir, name) = self.parent(path)?;
+        let name = CString::new(name.as_bytes())?;
+        let target = CString::new(target.as_bytes())?;
+        cvt(unsafe { libc::symlinkat(target.as_ptr(), dir.as_raw_fd(), name.as_ptr()) })
+    }
+
+    /// Reads the target of the symlink at `path`.
+    pub fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        let (dir, name) = self.parent(path)?;
+        let name = CString::new(name.as_bytes())?;
+        let mut buf = vec![0u8; libc::PATH_MAX as usize];
+        let len = unsafe {
+            libc::readlinkat(
+                dir.as_raw_fd(),
+                name.as_ptr(),
+                buf.as_mut_ptr() as *mut libc::c_char,
+                buf.len(),
+            )
+        };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        buf.truncate(len as usize);
+        Ok(PathBuf::from(OsString::from_vec(buf)))
+    }
+
+    /// Removes the file, or empty directory if `is_dir` is set, at `path`.
+    pub fn remove(&self, path: &Path, is_dir: bool) -> io::Result<()> {
+        let (dir, name) = self.parent(path)?;
+        let name = CString::new(name.as_bytes())?;
+        let flags = if is_dir { libc::AT_REMOVEDIR } else { 0 };
+        cvt(unsafe { libc::unlinkat(dir.as_raw_fd(), name.as_ptr(), flags) })
+    }
+
+    /// Renames `from` to `to`, replacing `to` if it exists.
+    pub fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let (from_dir, from_name) = self.parent(from)?;
+        let (to_dir, to_name) = self.parent(to)?;
+        let from_name = CString::new(from_name.as_bytes())?;
+        let to_name = CString::new(to_name.as_bytes())?;
+        cvt(unsafe {
+            libc::renameat(
+                from_dir.as_raw_fd(),
+                from_name.as_ptr(),
+                to_dir.as_raw_fd(),
+                to_name.as_ptr(),
+            )
+        })
+    }
+
+    /// Makes `link` another name of the file at `original`.
+    pub fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()> {
+        let (from_dir, from_name) = self.parent(original)?;
+        let (to_dir, to_name) = self.parent(link)?;
+        let from_name = CString::new(from_name.as_bytes())?;
+        let to_name = CString::new(to_name.as_bytes())?;
+        cvt(unsafe {
+            libc::linkat(
+                from_dir.as_raw_fd(),
+                from_name.as_ptr(),
+                to_dir.as_raw_fd(),
+                to_name.as_ptr(),
+                0,
+            )
+        })
+    }
+
+    /// Changes the permission bits of `path`; symlinks, which have none, are left as
+    /// they are.
+    pub fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()> {
+        let names = self.names(path)?;
+        // Without O_PATH, files are changed through their directory rather than
+        // opened. A symlink swapped in after the check is changed, not followed.
+        #[cfg(not(target_os = "linux"))]
+        if let Some((last, parents)) = names.split_last() {
+            let dir = self.open_names(parents, PATH_ONLY | libc::O_DIRECTORY, 0)?;
+            if stat_at(dir.as_fd(), last)?.st_mode & libc::S_IFMT == libc::S_IFLNK {
+                return Ok(());
+            }
+            let name = CString::new(last.as_bytes())?;
+            return cvt(unsafe {
+                libc::fchmodat(
+                    dir.as_raw_fd(),
+                    name.as_ptr(),
+                    mode as libc::mode_t,
+                    libc::AT_SYMLINK_NOFOLLOW,
+                )
+            });
+        }
+        let file = File::from(self.open_names(&names, PATH_ONLY, 0)?);
+        if file.metadata()?.file_type().is_symlink() {
+            return Ok(());
+        }
+        // A descriptor opened with O_PATH cannot be changed through, but its entry
+        // in /proc names exactly the file it refers to.
+        #[cfg(target_os = "linux")]
+        {
+            let proc_path = CString::new(format!("/proc/self/fd/{}", file.as_raw_fd()))?;
+            cvt(unsafe { libc::chmod(proc_path.as_ptr(), mode as libc::mode_t) })
+        }
+        #[cfg(not(target_os = "linux"))]
+        cvt(unsafe { libc::fchmod(file.as_raw_fd(), mode as libc::mode_t) })
+    }
+
+    /// Changes the owner and group of `path` itself; `None` leaves either as it is.
+    pub fn set_owner(&self, path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+        let (dir, name) = self.parent(path)?;
+        let name = CString::new(name.as_bytes())?;
+        cvt(unsafe {
+            libc::fchownat(
+                dir.as_raw_fd(),
+                name.as_ptr(),
+                uid.unwrap_or(u32::MAX),
+                gid.unwrap_or(u32::MAX),
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        })
+    }
+
+    /// Sets the access and modification times of `path` itself.
+    pub fn set_times(
+        &self,
+        path: &Path,
+        atime: nfs3::set_atime,
+        mtime: nfs3::set_mtime,
+    ) -> io::Result<()> {
+        let omit = libc::timespec { tv_sec: 0, tv_nsec: libc::UTIME_OMIT };
+        let now = libc::timespec { tv_sec: 0, tv_nsec: libc::UTIME_NOW };
+        let at = |time: nfs3::nfstime3| libc::timespec {
+            tv_sec: time.seconds as libc::time_t,
+            tv_nsec: time.nseconds as _,
+        };
+        let times = [
+            match atime {
+                nfs3::set_atime::DONT_CHANGE => omit,
+                nfs3::set_atime::SET_TO_SERVER_TIME => now,
+                nfs3::set_atime::SET_TO_CLIENT_TIME(time) => at(time),
+            },
+            match mtime {
+                nfs3::set_mtime::DONT_CHANGE => omit,
+                nfs3::set_mtime::SET_TO_SERVER_TIME => now,
+                nfs3::set_mtime::SET_TO_CLIENT_TIME(time) => at(time),
+            },
+        ];
+        let (dir, name) = self.parent(path)?;
+        let name = CString::new(name.as_bytes())?;
+        cvt(unsafe {
+            libc::utimensat(
+                dir.as_raw_fd(),
+                name.as_ptr(),
+                times.as_ptr(),
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        })
+    }
+}
+
+/// An open directory, read in the order the file system returns its entries
+#[derive(Debug)]
+pub struct DirStream {
+    dir: *mut libc::DIR,
+    fd: libc::c_int,
+    #[cfg(not(target_os = "linux"))]
+    path: PathBuf,
+}
+
+// The stream is only ever used by one thread at a time, through `&mut self`.
+unsafe impl Send for DirStream {}
+
+impl DirStream {
+    /// Reads up to `max` more entries, other than `.` and `..`, with whether each
+    /// is a symlink. Fewer are returned only at the end of the directory.
+    pub fn read(&mut self, max: usize) -> io::Result<Vec<(OsString, bool)>> {
+        let mut names = Vec::new();
+        while names.len() < max {
+            clear_errno();
+            let entry = unsafe { libc::readdir(self.dir) };
+            if entry.is_null() {
+                let err = io::Error::last_os_error();
+                if err.raw_os_error() != Some(0) {
+                    return Err(err);
+                }
+                break;
+            }
+            let name = unsafe { std::ffi::CStr::from_ptr((*entry).d_name.as_ptr()) };
+            let name = OsStr::from_bytes(name.to_bytes());
+            if name == "." || name == ".." {
+                continue;
+            }
+            let symlink = match unsafe { (*entry).d_type } {
+                libc::DT_LNK => true,
+                // Not every file system reports types in the listing.
+                libc::DT_UNKNOWN => {
+                    self.metadata(name)?.is_some_and(|meta| meta.file_type().is_symlink())
+                }
+                _ => false,
+            };
+            names.push((name.to_os_string(), symlink));
+        }
+        Ok(names)
+    }
+
+    /// Returns the metadata of entry `name`, or `None` if it was removed since it
+    /// was listed.
+    pub fn metadata(&self, name: &OsStr) -> io::Result<Option<Metadata>> {
+        let dir = unsafe { BorrowedFd::borrow_raw(self.fd) };
+        #[cfg(target_os = "linux")]
+        let meta = openat(dir, name, PATH_ONLY | libc::O_NOFOLLOW | libc::O_CLOEXEC, 0)
+            .and_then(|fd| File::from(fd).metadata());
+        #[cfg(not(target_os = "linux"))]
+        let meta = metadata_at(dir, name, &self.path.join(name));
+        match meta {
+            Ok(meta) => Ok(Some(meta)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl Drop for DirStream {
+    fn drop(&mut self) {
+        unsafe { libc::closedir(self.dir) };
+    }
+}
+
+fn openat(
+    dir: BorrowedFd<'_>,
+    name: &OsStr,
+    flags: libc::c_int,
+    mode: libc::c_uint,
+) -> io::Result<OwnedFd> {
+    let name = CString::new(name.as_bytes())?;
+    let fd = unsafe { libc::openat(dir.as_raw_fd(), name.as_ptr(), flags, mode) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Returns the status of entry `name` of `dir` itself, without opening it
+#[cfg(not(target_os = "linux"))]
+fn stat_at(dir: BorrowedFd<'_>, name: &OsStr) -> io::Result<libc::stat> {
+    let name = CString::new(name.as_bytes())?;
+    let mut stat = unsafe { std::mem::zeroed::<libc::stat>() };
+    cvt(unsafe {
+        libc::fstatat(dir.as_raw_fd(), name.as_ptr(), &mut stat, libc::AT_SYMLINK_NOFOLLOW)
+    })?;
+    Ok(stat)
+}
+
+/// Returns the metadata of entry `name` of `dir`, which is at `path`, without
+/// opening it
+///
+/// `Metadata` can only come from the standard library, which looks files up by
+/// path. The file `path` names is used only if it is the entry found from `dir`.
+#[cfg(not(target_os = "linux"))]
+fn metadata_at(dir: BorrowedFd<'_>, name: &OsStr, path: &Path) -> io::Result<Metadata> {
+    let stat = stat_at(dir, name)?;
+    let meta = std::fs::symlink_metadata(path)?;
+    if meta.dev() != stat.st_dev as u64 || meta.ino() != stat.st_ino as u64 {
+        return Err(io::Error::other("path changed while it was examined"));
+    }
+    Ok(meta)
+}
+
+/// Resets `errno`, which tells the end of a directory stream from an error
+fn clear_errno() {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        *libc::__errno_location() = 0
+    };
+    #[cfg(not(target_os = "linux"))]
+    unsafe {
+        *libc::__error() = 0
+    };
+}
+
+fn cvt(result: libc::c_int) -> io::Result<()> {
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,160 @@
+//! Open files kept between requests.
+//!
+//! Clients read and write large files in many small requests; reopening the file for
+//! each one costs more than the I/O itself. The cache keeps the most recently used
+//! descriptors open, keyed by file ID. Callers use positioned reads and writes
+//! (`pread`/`pwrite`), so concurrent requests can share one descriptor. Files are
+//! checked and opened without holding the cache's lock.
+
+use std::fs::{File, Metadata};
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use indexmap::IndexMap;
+
+use crate::xdr::nfs3;
+
+use super::credentials::Caller;
+use super::export_root::ExportRoot;
+
+/// What a descriptor is needed for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Debug)]
+struct OpenFile {
+    file: Arc<File>,
+    /// Identity of the file, to notice when the path names a different one
+    dev: u64,
+    ino: u64,
+    readable: bool,
+    writable: bool,
+    /// Who opened the file, if not the server
+    opener: Option<Caller>,
+}
+
+impl OpenFile {
+    fn allows(&self, access: Access) -> bool {
+        match access {
+            Access::Read => self.readable,
+            Access::Write => self.writable,
+        }
+    }
+}
+
+/// Open descriptors in least recently used order
+#[derive(Debug)]
+pub struct FdCache {
+    files: Mutex<IndexMap<nfs3::fileid3, OpenFile>>,
+    capacity: usize,
+}
+
+impl FdCache {
+    /// Creates a cache holding up to `capacity` descriptors; 0 disables caching.
+    pub fn new(capacity: usize) -> Self {
+        Self { files: Mutex::new(IndexMap::new()), capacity }
+    }
+
+    /// Returns a descriptor for file `id` at `path` below `export` that allows
+    /// `access`, with the file's current metadata.
+    ///
+    /// A cached descriptor is only used while `path` still names the same file, so
+    /// files replaced outside the server are reopened, and only by whoever opened it,
+    /// so one caller cannot use another's access. Files are opened as `opener` if
+    /// given. Writing creates the file if it is missing. Only regular files are
+    /// opened: opening a device or a FIFO on a client's behalf would act on the
+    /// server's hardware or block.
+    pub fn open(
+        &self,
+        export: &ExportRoot,
+        id: nfs3::fileid3,
+        path: &Path,
+        access: Access,
+        opener: Option<&Caller>,
+    ) -> io::Result<(Arc<File>, Metadata)> {
+        let current = match export.metadata(path) {
+            Ok(meta) => Some(meta),
+            Err(err) if err.kind() == io::ErrorKind::NotFound && access == Access::Write => None,
+            Err(err) => return Err(err),
+        };
+        if let Some(meta) = &current {
+            check_regular(meta)?;
+        }
+        if let Some(meta) = current {
+            let mut files = self.files.lock().unwrap();
+            if let Some(index) = files.get_index_of(&id) {
+                let cached = &files[index];
+                if cached.dev == meta.dev()
+                    && cached.ino == meta.ino()
+                    && cached.allows(access)
+                    && cached.opener.as_ref() == opener
+                {
+                    let file = cached.file.clone();
+                    let last = files.len() - 1;
+                    files.move_index(index, last);
+                    return Ok((file, meta));
+                }
+            }
+        }
+
+        // Writers also get read access when the permissions allow it, so that the
+        // descriptor serves the reads that usually follow.
+        // Should the file have been replaced by a FIFO since, do not wait for a writer.
+        let open_file = |flags| {
+            let flags = flags | libc::O_NONBLOCK | libc::O_NOCTTY;
+            match opener {
+                Some(opener) => opener.run(|| export.open_file(path, flags)),
+                None => export.open_file(path, flags),
+            }
+        };
+        let (file, readable) = match access {
+            Access::Read => (open_file(libc::O_RDONLY)?, true),
+            Access::Write => match open_file(libc::O_RDWR | libc::O_CREAT) {
+                Ok(file) => (file, true),
+                Err(err) if err.kind() == io::ErrorKind::PermissionDenied => {
+                    (open_file(libc::O_WRONLY | libc::O_CREAT)?, false)
+                }
+                Err(err) => return Err(err),
+            },
+        };
+        let meta = file.metadata()?;
+        check_regular(&meta)?;
+        let file = Arc::new(file);
+        let mut files = self.files.lock().unwrap();
+        files.shift_remove(&id);
+        if self.capacity > 0 {
+            if files.len() == self.capacity {
+                files.shift_remove_index(0);
+            }
+            let open = OpenFile {
+                file: file.clone(),
+                dev: meta.dev(),
+                ino: meta.ino(),
+                readable,
+                writable: access == Access::Write,
+                opener: opener.cloned(),
+            };
+            files.insert(id, open);
+        }
+        Ok((file, meta))
+    }
+
+    /// Closes the descriptor of file `id`, if one is open.
+    pub fn remove(&self, id: nfs3::fileid3) {
+        self.files.lock().unwrap().shift_remove(&id);
+    }
+}
+
+/// Fails unless `meta` is that of a regular file.
+fn check_regular(meta: &Metadata) -> io::Result<()> {
+    if meta.is_file() {
+        return Ok(());
+    }
+    let errno = if meta.is_dir() { libc::EISDIR } else { libc::EINVAL };
+    Err(io::Error::from_raw_os_error(errno))
+}
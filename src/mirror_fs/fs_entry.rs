@@ -1,8 +1,9 @@
 use std::collections::{BTreeMap, BTreeSet};
 
-use fernfs::xdr::nfs3::{createverf3, fattr3, fileid3, ftype3};
 use intaglio::Symbol;
 
+use crate::xdr::nfs3::{createverf3, fattr3, fileid3, ftype3};
+
 /// A file system entry representing a file or directory
 #[derive(Debug, Clone)]
 pub struct FSEntry {
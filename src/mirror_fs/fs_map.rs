@@ -0,0 +1,656 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::fs::Metadata;
+use std::io::{self, ErrorKind};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use intaglio::osstr::SymbolTable;
+use intaglio::Symbol;
+use tracing::debug;
+
+use crate::fs_util::*;
+use crate::xdr::nfs3;
+
+use super::error_handling::{NFSResult, RefreshResult};
+use super::exclude::ExcludeSet;
+use super::export_root::{DirStream, ExportRoot};
+use super::fs_entry::FSEntry;
+use super::options::MountPolicy;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct InodeKey {
+    dev: u64,
+    ino: u64,
+}
+
+impl InodeKey {
+    fn from_meta(meta: &Metadata) -> Self {
+        Self { dev: meta.dev(), ino: meta.ino() }
+    }
+}
+
+/// A file system mapping structure that maintains the relationship between file IDs and paths
+#[derive(Debug)]
+pub struct FSMap {
+    /// The root directory path
+    pub root: PathBuf,
+    /// The next available file ID
+    pub next_fileid: AtomicU64,
+    /// Symbol table for string internment
+    pub intern: SymbolTable,
+    /// Mapping from file ID to file system entry
+    pub id_to_path: HashMap<nfs3::fileid3, FSEntry>,
+    /// Mapping from path symbols to file ID
+    pub path_to_id: HashMap<Vec<Symbol>, nfs3::fileid3>,
+    /// Mapping from inode keys to file ID
+    inode_to_id: HashMap<InodeKey, nfs3::fileid3>,
+    /// Mapping from file ID to inode keys
+    id_to_inode: HashMap<nfs3::fileid3, InodeKey>,
+    /// Patterns for entries hidden from clients
+    pub exclude: ExcludeSet,
+    /// Whether symlinks are hidden from clients
+    pub hide_symlinks: bool,
+    /// How file systems mounted below the root are exported
+    pub mounts: MountPolicy,
+    /// Device of the root's file system
+    root_dev: u64,
+    /// Descriptor of the root, through which all paths are resolved
+    export: Arc<ExportRoot>,
+}
+
+impl FSMap {
+    /// Creates a new file system map with the given root path
+    ///
+    /// Fails if the root cannot be opened as a directory.
+    pub fn new(root: PathBuf) -> io::Result<Self> {
+        // create root entry
+        let root_meta = root.metadata()?;
+        let root_entry = FSEntry::new(Vec::new(), metadata_to_fattr3(1, &root_meta));
+        let root_inode = InodeKey::from_meta(&root_meta);
+        let export = Arc::new(ExportRoot::open(&root)?);
+
+        Ok(Self {
+            root,
+            next_fileid: AtomicU64::new(1),
+            intern: SymbolTable::new(),
+            id_to_path: HashMap::from([(0, root_entry)]),
+            path_to_id: HashMap::from([(Vec::new(), 0)]),
+            inode_to_id: HashMap::from([(root_inode, 0)]),
+            id_to_inode: HashMap::from([(0, root_inode)]),
+            exclude: ExcludeSet::default(),
+            hide_symlinks: false,
+            mounts: MountPolicy::default(),
+            root_dev: root_meta.dev(),
+            export,
+        })
+    }
+
+    /// Returns the export root, through which paths from the map are to be used
+    pub fn export(&self) -> Arc<ExportRoot> {
+        self.export.clone()
+    }
+
+    /// Checks whether a path below the root is hidden from clients
+    pub fn is_hidden(&self, path: &Path, meta: &Metadata) -> bool {
+        (self.hide_symlinks && meta.file_type().is_symlink())
+            || (self.mounts == MountPolicy::Hide && meta.dev() != self.root_dev)
+            || self.is_excluded(path)
+    }
+
+    /// Returns the attributes of entry `id` with metadata `meta`.
+    ///
+    /// Files on the root's file system have `fsid` 0; with [`MountPolicy::Cross`],
+    /// those on other file systems have their device number.
+    pub fn attributes(&self, id: nfs3::fileid3, meta: &Metadata) -> nfs3::fattr3 {
+        let mut attr = metadata_to_fattr3(id, meta);
+        if self.mounts == MountPolicy::Cross && meta.dev() != self.root_dev {
+            attr.fsid = meta.dev();
+        }
+        attr
+    }
+
+    /// Checks whether a path below the root matches an exclude pattern
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        if self.exclude.is_empty() {
+            return false;
+        }
+        match path.strip_prefix(&self.root) {
+            Ok(relative) => self.exclude.is_excluded(relative.as_os_str().as_bytes()),
+            Err(_) => false,
+        }
+    }
+
+    /// Replaces the exclude patterns, forgetting entries that they hide.
+    ///
+    /// Entries that become visible are found when their directories are next listed.
+    pub fn set_exclude(&mut self, exclude: ExcludeSet) {
+        self.exclude = exclude;
+        let mut hidden: Vec<Vec<Symbol>> = self
+            .path_to_id
+            .keys()
+            .filter(|path| !path.is_empty() && self.is_excluded(&self.sym_to_path(path)))
+            .cloned()
+            .collect();
+        // Parents go first, taking the entries below them along.
+        hidden.sort_by_key(|path| path.len());
+        for path in hidden {
+            let Some(&id) = self.path_to_id.get(&path) else {
+                continue;
+            };
+            self.remove_path_tree(path.clone(), id);
+            let (name, dir) = path.split_last().unwrap();
+            if let Some(dirid) = self.path_to_id.get(dir).copied() {
+                if let Some(entry) = self.id_to_path.get_mut(&dirid) {
+                    entry.remove_child(*name);
+                }
+            }
+        }
+    }
+
+    /// Converts a list of symbols to a full path
+    pub fn sym_to_path(&self, symlist: &[Symbol]) -> PathBuf {
+        let mut ret = self.root.clone();
+        for i in symlist.iter() {
+            ret.push(self.intern.get(*i).unwrap());
+        }
+        ret
+    }
+
+    /// Converts a list of symbols to a file name
+    pub fn sym_to_fname(&self, symlist: &[Symbol]) -> OsString {
+        if let Some(x) = symlist.last() {
+            self.intern.get(*x).unwrap().into()
+        } else {
+            "".into()
+        }
+    }
+
+    /// Removes a single path mapping and drops the entry if no aliases remain.
+    pub fn remove_path(&mut self, path: &[Symbol]) -> Option<nfs3::fileid3> {
+        let fileid = self.path_to_id.remove(path)?;
+        if let Some(entry) = self.id_to_path.get_mut(&fileid) {
+            entry.aliases.remove(path);
+            if entry.name == path {
+                if let Some(new_primary) = entry.aliases.iter().next().cloned() {
+                    entry.name = new_primary;
+                }
+            }
+            if entry.aliases.is_empty() {
+                self.id_to_path.remove(&fileid);
+                if let Some(inode) = self.id_to_inode.remove(&fileid) {
+                    self.inode_to_id.remove(&inode);
+                }
+            }
+        }
+        Some(fileid)
+    }
+
+    /// Renames a path prefix for an entry subtree.
+    pub fn rename_path_prefix(&mut self, from_prefix: &[Symbol], to_prefix: &[Symbol]) {
+        if from_prefix == to_prefix {
+            return;
+        }
+
+        let mut updates: Vec<(Vec<Symbol>, Vec<Symbol>, nfs3::fileid3)> = Vec::new();
+        for (path, fileid) in self.path_to_id.iter() {
+            if path.starts_with(from_prefix) {
+                let mut new_path = to_prefix.to_vec();
+                new_path.extend_from_slice(&path[from_prefix.len()..]);
+                updates.push((path.clone(), new_path, *fileid));
+            }
+        }
+
+        for (old_path, new_path, fileid) in updates {
+            self.path_to_id.remove(&old_path);
+            self.path_to_id.insert(new_path.clone(), fileid);
+            if let Some(entry) = self.id_to_path.get_mut(&fileid) {
+                if entry.aliases.remove(&old_path) {
+                    entry.aliases.insert(new_path.clone());
+                }
+                if entry.name == old_path {
+                    entry.name = new_path;
+                }
+            }
+        }
+    }
+
+    /// Removes a directory entry subtree by path.
+    pub fn remove_path_tree(&mut self, path: Vec<Symbol>, id: nfs3::fileid3) {
+        let entry = match self.id_to_path.get(&id).cloned() {
+            Some(entry) => entry,
+            None => return,
+        };
+        if entry.is_directory() {
+            if let Some(children) = entry.children {
+                for (name, child_id) in children {
+                    let mut child_path = path.clone();
+                    child_path.push(name);
+                    if let Some(child_entry) = self.id_to_path.get(&child_id) {
+                        if child_entry.is_directory() {
+                            self.remove_path_tree(child_path.clone(), child_id);
+                        } else {
+                            self.remove_path(&child_path);
+                        }
+                    } else {
+                        self.remove_path(&child_path);
+                    }
+                }
+            }
+        }
+        self.remove_path(&path);
+    }
+
+    /// Deletes an entry and all its children from the file system map
+    pub fn delete_entry(&mut self, id: nfs3::fileid3) {
+        if let Some(entry) = self.id_to_path.get(&id).cloned() {
+            self.remove_path_tree(entry.name, id);
+        }
+    }
+
+    /// Finds an entry by its file ID
+    pub fn find_entry(&self, id: nfs3::fileid3) -> NFSResult<FSEntry> {
+        Ok(self.id_to_path.get(&id).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?.clone())
+    }
+
+    /// Finds a mutable entry by its file ID
+    pub fn find_entry_mut(&mut self, id: nfs3::fileid3) -> NFSResult<&mut FSEntry> {
+        self.id_to_path.get_mut(&id).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)
+    }
+
+    /// Finds a child entry by its parent ID and filename
+    pub fn find_child(&self, id: nfs3::fileid3, filename: &[u8]) -> NFSResult<nfs3::fileid3> {
+        let mut name = self.id_to_path.get(&id).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?.name.clone();
+        name.push(
+            self.intern
+                .check_interned(OsStr::from_bytes(filename))
+                .ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?,
+        );
+        Ok(*self.path_to_id.get(&name).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?)
+    }
+
+    /// Returns the directory holding the primary name of an entry
+    pub fn parent_id(&self, id: nfs3::fileid3) -> Option<nfs3::fileid3> {
+        let name = &self.id_to_path.get(&id)?.name;
+        let (_, parent) = name.split_last()?;
+        self.path_to_id.get(parent).copied()
+    }
+
+    /// Returns the full path of an entry
+    pub fn entry_path(&self, id: nfs3::fileid3) -> NFSResult<PathBuf> {
+        let entry = self.id_to_path.get(&id).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?;
+        Ok(self.sym_to_path(&entry.name))
+    }
+
+    /// Refreshes an entry by checking if it still exists and updating its metadata
+    pub async fn refresh_entry(&mut self, id: nfs3::fileid3) -> NFSResult<RefreshResult> {
+        let state = self.entry_paths(id)?.stat().await;
+        self.apply_entry(id, state)
+    }
+
+    /// Returns the paths of an entry, to be checked with [`EntryPaths::stat`] without
+    /// holding the map
+    pub fn entry_paths(&self, id: nfs3::fileid3) -> NFSResult<EntryPaths> {
+        let entry = self.id_to_path.get(&id).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?;
+        let aliases =
+            entry.aliases.iter().map(|alias| (alias.clone(), self.sym_to_path(alias))).collect();
+        Ok(EntryPaths { export: self.export.clone(), aliases })
+    }
+
+    /// Updates an entry from what [`EntryPaths::stat`] found on disk
+    pub fn apply_entry(
+        &mut self,
+        id: nfs3::fileid3,
+        state: EntryState,
+    ) -> NFSResult<RefreshResult> {
+        let entry = self.id_to_path.get(&id).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?.clone();
+        let Some((primary, meta)) = state.found else {
+            self.delete_entry(id);
+            for alias in state.missing.iter() {
+                if self.path_to_id.get(alias) == Some(&id) {
+                    self.remove_path(alias);
+                }
+            }
+            debug!("Deleting entry A {:?}: {:?}. Ent: {:?}", id, entry.name, entry);
+            return Ok(RefreshResult::Delete);
+        };
+
+        for alias in state.missing.iter() {
+            if self.path_to_id.get(alias) == Some(&id) {
+                self.remove_path(alias);
+            }
+        }
+        let entry_mut = self.id_to_path.get_mut(&id).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?;
+        if entry_mut.aliases.contains(&primary) {
+            entry_mut.name = primary.clone();
+        }
+
+        let path = self.sym_to_path(&primary);
+        let meta = meta.map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
+        let inode_key = InodeKey::from_meta(&meta);
+        if self.id_to_inode.get(&id).copied() != Some(inode_key) {
+            self.delete_entry(id);
+            debug!("Deleting entry B {:?}: {:?}. Ent: {:?}", id, path, entry);
+            return Ok(RefreshResult::Delete);
+        }
+        self.id_to_inode.insert(id, inode_key);
+        self.inode_to_id.insert(inode_key, id);
+        let meta = self.attributes(id, &meta);
+        if !fattr3_differ(&meta, &entry.fsmeta) {
+            return Ok(RefreshResult::Noop);
+        }
+
+        // If we get here we have modifications
+        if entry.fsmeta.ftype as u32 != meta.ftype as u32 {
+            // if the file type changed ex: file->dir or dir->file
+            // really the entire file has been replaced.
+            // we expire the entire id
+            debug!("File Type Mismatch FT {:?} : {:?} vs {:?}", id, entry.fsmeta.ftype, meta.ftype);
+            debug!("File Type Mismatch META {:?} : {:?} vs {:?}", id, entry.fsmeta, meta);
+            self.delete_entry(id);
+            debug!("Deleting entry C {:?}: {:?}. Ent: {:?}", id, path, entry);
+            return Ok(RefreshResult::Delete);
+        }
+
+        // inplace modification.
+        // update metadata
+        self.id_to_path.get_mut(&id).unwrap().fsmeta = meta;
+        debug!("Reloading entry {:?}: {:?}. Ent: {:?}", id, path, entry);
+        Ok(RefreshResult::Reload)
+    }
+
+    /// Refreshes the directory listing for a given directory ID
+    pub async fn refresh_dir_list(&mut self, id: nfs3::fileid3) -> NFSResult<()> {
+        let Some(path) = self.dir_path(id)? else {
+            return Ok(());
+        };
+        let listing = read_listing(self.export.clone(), path).await?;
+        self.apply_dir_list(id, listing)
+    }
+
+    /// Returns the path of a directory to be listed with [`read_listing`], or `None`
+    /// if the entry is not a directory
+    pub fn dir_path(&self, id: nfs3::fileid3) -> NFSResult<Option<PathBuf>> {
+        let entry = self.id_to_path.get(&id).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?;
+        if !entry.is_directory() {
+            return Ok(None);
+        }
+        Ok(Some(self.sym_to_path(&entry.name)))
+    }
+
+    /// Replaces the children of a directory with a listing from [`read_listing`]
+    pub fn apply_dir_list(&mut self, id: nfs3::fileid3, listing: Listing) -> NFSResult<()> {
+        let entry = self.id_to_path.get(&id).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?.clone();
+        if !entry.is_directory() {
+            return Ok(());
+        }
+
+        let mut cur_path = entry.name.clone();
+        let path = self.sym_to_path(&entry.name);
+        let mut new_children: BTreeMap<Symbol, u64> = BTreeMap::new();
+        debug!("Relisting entry {:?}: {:?}. Ent: {:?}", id, path, entry);
+
+        for (name, meta) in listing {
+            if self.is_hidden(&path.join(&name), &meta) {
+                continue;
+            }
+            let sym = self.intern.intern(name).unwrap();
+            cur_path.push(sym);
+            let next_id = self.create_entry(&cur_path, meta);
+            new_children.insert(sym, next_id);
+            cur_path.pop();
+        }
+
+        if let Some(old_children) = entry.children {
+            for (name_sym, _) in old_children {
+                if !new_children.contains_key(&name_sym) {
+                    let mut old_path = entry.name.clone();
+                    old_path.push(name_sym);
+                    self.remove_path(&old_path);
+                }
+            }
+        }
+
+        if let Some(entry_mut) = self.id_to_path.get_mut(&id) {
+            entry_mut.children = Some(new_children);
+            entry_mut.children_meta = entry_mut.fsmeta;
+        }
+
+        Ok(())
+    }
+
+    /// Records entry `name` of directory `dirid`, returning its file ID
+    pub fn add_child(
+        &mut self,
+        dirid: nfs3::fileid3,
+        name: &OsStr,
+        meta: Metadata,
+    ) -> NFSResult<nfs3::fileid3> {
+        let sym = self.intern.intern(name.to_os_string()).unwrap();
+        let mut full_name =
+            self.id_to_path.get(&dirid).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?.name.clone();
+        full_name.push(sym);
+        let fileid = self.create_entry(&full_name, meta);
+
+        // update the children list
+        if let Some(ref mut children) =
+            self.id_to_path.get_mut(&dirid).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?.children
+        {
+            children.insert(sym, fileid);
+        }
+        Ok(fileid)
+    }
+
+    /// Updates the attributes of an entry, unless `meta` belongs to another file
+    pub fn update_attributes(&mut self, id: nfs3::fileid3, meta: &Metadata) {
+        if self.id_to_inode.get(&id) != Some(&InodeKey::from_meta(meta)) {
+            return;
+        }
+        let attr = self.attributes(id, meta);
+        if let Some(entry) = self.id_to_path.get_mut(&id) {
+            entry.fsmeta = attr;
+        }
+    }
+
+    /// Applies a change to entry `name` of a listed directory, which now has metadata
+    /// `meta` or no longer exists
+    pub fn update_child(&mut self, dirid: nfs3::fileid3, name: &OsStr, meta: Option<Metadata>) {
+        let Some(dir) = self.id_to_path.get(&dirid) else {
+            return;
+        };
+        if dir.children.is_none() {
+            return;
+        }
+        let mut path = dir.name.clone();
+        let hidden = match &meta {
+            Some(meta) => self.is_hidden(&self.sym_to_path(&path).join(name), meta),
+            None => true,
+        };
+        if let (Some(meta), false) = (meta, hidden) {
+            let sym = self.intern.intern(name.to_os_string()).unwrap();
+            path.push(sym);
+            let id = self.create_entry(&path, meta);
+            if let Some(children) =
+                self.id_to_path.get_mut(&dirid).and_then(|d| d.children.as_mut())
+            {
+                children.insert(sym, id);
+            }
+            return;
+        }
+
+        let Some(sym) = self.intern.check_interned(name) else {
+            return;
+        };
+        path.push(sym);
+        if let Some(id) = self.path_to_id.get(&path).copied() {
+            self.remove_path_tree(path, id);
+        }
+        if let Some(dir) = self.id_to_path.get_mut(&dirid) {
+            dir.remove_child(sym);
+        }
+    }
+
+    /// Applies the rename of `from_name` in directory `from_dir` to `to_name` in
+    /// `to_dir`, keeping the file IDs of the renamed entry and everything below it
+    pub fn rename_child(
+        &mut self,
+        from_dir: nfs3::fileid3,
+        from_name: &OsStr,
+        to_dir: nfs3::fileid3,
+        to_name: &OsStr,
+        meta: Option<Metadata>,
+    ) {
+        let from_path =
+            self.id_to_path.get(&from_dir).zip(self.intern.check_interned(from_name)).map(
+                |(dir, sym)| {
+                    let mut path = dir.name.clone();
+                    path.push(sym);
+                    path
+                },
+            );
+        let Some(from_id) = from_path.as_ref().and_then(|path| self.path_to_id.get(path)).copied()
+        else {
+            self.update_child(to_dir, to_name, meta);
+            return;
+        };
+        let Some(to_parent) = self.id_to_path.get(&to_dir).map(|dir| dir.name.clone()) else {
+            self.update_child(from_dir, from_name, None);
+            return;
+        };
+        let from_path = from_path.unwrap();
+        let to_sym = self.intern.intern(to_name.to_os_string()).unwrap();
+        let mut to_path = to_parent;
+        to_path.push(to_sym);
+
+        if let Some(target_id) = self.path_to_id.get(&to_path).copied() {
+            if target_id != from_id {
+                self.remove_path_tree(to_path.clone(), target_id);
+            }
+        }
+        self.rename_path_prefix(&from_path, &to_path);
+        if let Some(dir) = self.id_to_path.get_mut(&from_dir) {
+            dir.remove_child(*from_path.last().unwrap());
+        }
+        if let Some(children) = self.id_to_path.get_mut(&to_dir).and_then(|d| d.children.as_mut()) {
+            children.insert(to_sym, from_id);
+        }
+        // The renamed entry may now be hidden, or may have been replaced meanwhile.
+        self.update_child(to_dir, to_name, meta);
+    }
+
+    /// Creates a new entry in the file system map
+    pub fn create_entry(&mut self, fullpath: &Vec<Symbol>, meta: Metadata) -> nfs3::fileid3 {
+        let inode_key = InodeKey::from_meta(&meta);
+        if let Some(chid) = self.path_to_id.get(fullpath).copied() {
+            if self.id_to_inode.get(&chid).copied() == Some(inode_key) {
+                let attr = self.attributes(chid, &meta);
+                if let Some(chent) = self.id_to_path.get_mut(&chid) {
+                    chent.fsmeta = attr;
+                }
+                return chid;
+            }
+            // Path was reused for a different inode (e.g. atomic replace); detach old mapping.
+            let _ = self.remove_path(fullpath);
+        }
+
+        if let Some(existing_id) = self.inode_to_id.get(&inode_key).copied() {
+            let attr = self.attributes(existing_id, &meta);
+            if let Some(entry) = self.id_to_path.get_mut(&existing_id) {
+                entry.fsmeta = attr;
+                entry.aliases.insert(fullpath.clone());
+            }
+            self.path_to_id.insert(fullpath.clone(), existing_id);
+            self.id_to_inode.entry(existing_id).or_insert(inode_key);
+            return existing_id;
+        }
+
+        // path does not exist and inode is new
+        let next_id = self.next_fileid.fetch_add(1, Ordering::Relaxed);
+        let metafattr = self.attributes(next_id, &meta);
+        let new_entry = FSEntry::new(fullpath.clone(), metafattr);
+        debug!("creating new entry {:?}: {:?}", next_id, meta);
+        self.id_to_path.insert(next_id, new_entry);
+        self.path_to_id.insert(fullpath.clone(), next_id);
+        self.inode_to_id.insert(inode_key, next_id);
+        self.id_to_inode.insert(next_id, inode_key);
+        next_id
+    }
+}
+
+/// The paths of an entry, taken from the map so that they can be checked on disk
+/// while other requests use it
+#[derive(Debug)]
+pub struct EntryPaths {
+    export: Arc<ExportRoot>,
+    aliases: Vec<(Vec<Symbol>, PathBuf)>,
+}
+
+impl EntryPaths {
+    /// Checks which of the paths still exist and reads the metadata of the first one
+    pub async fn stat(self) -> EntryState {
+        let stat = move || {
+            let mut state = EntryState { missing: Vec::new(), found: None };
+            for (alias, path) in self.aliases {
+                if state.found.is_some() {
+                    if !self.export.exists(&path) {
+                        state.missing.push(alias);
+                    }
+                    continue;
+                }
+                match self.export.metadata(&path) {
+                    Err(err) if is_gone(&err) => state.missing.push(alias),
+                    meta => state.found = Some((alias, meta)),
+                }
+            }
+            state
+        };
+        tokio::task::spawn_blocking(stat).await.expect("stat task panicked")
+    }
+}
+
+/// Checks whether an error resolving a path means nothing is there any more: a
+/// directory on the path may also have been replaced by a file or a symlink.
+fn is_gone(err: &std::io::Error) -> bool {
+    err.kind() == ErrorKind::NotFound
+        || matches!(err.raw_os_error(), Some(libc::ENOTDIR | libc::ELOOP | libc::EXDEV))
+}
+
+/// What [`EntryPaths::stat`] found on disk
+#[derive(Debug)]
+pub struct EntryState {
+    /// Paths that no longer exist
+    missing: Vec<Vec<Symbol>>,
+    /// The first path that still exists, with its metadata
+    found: Option<(Vec<Symbol>, std::io::Result<Metadata>)>,
+}
+
+/// Names and metadata of the entries of a directory
+pub type Listing = Vec<(OsString, Metadata)>;
+
+/// Lists the directory at `path`
+pub async fn read_listing(export: Arc<ExportRoot>, path: PathBuf) -> NFSResult<Listing> {
+    tokio::task::spawn_blocking(move || export.read_dir(&path))
+        .await
+        .map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?
+        .map_err(listing_error)
+}
+
+/// Opens the directory at `path` to be listed incrementally
+pub async fn open_dir_stream(export: Arc<ExportRoot>, path: PathBuf) -> NFSResult<DirStream> {
+    tokio::task::spawn_blocking(move || export.open_dir_stream(&path))
+        .await
+        .map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?
+        .map_err(listing_error)
+}
+
+fn listing_error(err: std::io::Error) -> nfs3::nfsstat3 {
+    match err.kind() {
+        ErrorKind::NotFound => nfs3::nfsstat3::NFS3ERR_NOENT,
+        ErrorKind::PermissionDenied => nfs3::nfsstat3::NFS3ERR_ACCES,
+        ErrorKind::NotADirectory => nfs3::nfsstat3::NFS3ERR_NOTDIR,
+        _ => nfs3::nfsstat3::NFS3ERR_IO,
+    }
+}
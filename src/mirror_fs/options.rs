@@ -0,0 +1,190 @@
+//! Configuration for [`MirrorFS`](super::MirrorFS).
+
+use std::io;
+use std::path::PathBuf;
+
+use super::exclude::ExcludeSet;
+use super::MirrorFS;
+
+/// Default for [`MirrorFSBuilder::open_files`]
+const DEFAULT_OPEN_FILES: usize = 256;
+
+/// How symbolic links inside the mirrored tree are handled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Symlinks are exported as symlinks and clients may create new ones
+    #[default]
+    Allow,
+    /// Existing symlinks are exported but `SYMLINK` fails with `NFS3ERR_NOTSUPP`
+    DenyCreate,
+    /// Symlinks are invisible to clients and cannot be created
+    Hide,
+}
+
+/// When written data is flushed to stable storage.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// Writes are synced as the client asks: `UNSTABLE` writes stay in the page cache
+    /// until the client sends `COMMIT`, while `DATA_SYNC` and `FILE_SYNC` writes are
+    /// synced before they are acknowledged
+    #[default]
+    Requested,
+    /// Every write is synced before it is acknowledged as `FILE_SYNC`
+    Always,
+    /// Writes are left in the page cache and acknowledged as `UNSTABLE`.
+    ///
+    /// Data may be lost if the host crashes; only suitable for scratch exports.
+    Never,
+}
+
+/// How file systems mounted inside the mirrored tree are exported.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MountPolicy {
+    /// The tree is exported as one file system: every entry has `fsid` 0 and `FSSTAT`
+    /// reports the usage of the root's file system
+    #[default]
+    Merge,
+    /// Each mounted file system is exported as its own, like with the `crossmnt`
+    /// export option: entries report an `fsid` derived from their device and `FSSTAT`
+    /// the usage of the file system holding the object asked about
+    Cross,
+    /// Lookups do not cross into other file systems: mount points and everything
+    /// below them are invisible to clients
+    Hide,
+}
+
+/// Builder for [`MirrorFS`].
+#[derive(Debug, Clone)]
+pub struct MirrorFSBuilder {
+    root: PathBuf,
+    read_only: bool,
+    exclude: Vec<String>,
+    symlinks: SymlinkPolicy,
+    fsync: FsyncPolicy,
+    open_files: usize,
+    watch: bool,
+    caller_credentials: bool,
+    mounts: MountPolicy,
+}
+
+impl MirrorFSBuilder {
+    /// Starts a configuration that mirrors `root`.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            read_only: false,
+            exclude: Vec::new(),
+            symlinks: SymlinkPolicy::default(),
+            fsync: FsyncPolicy::default(),
+            open_files: DEFAULT_OPEN_FILES,
+            watch: false,
+            caller_credentials: false,
+            mounts: MountPolicy::default(),
+        }
+    }
+
+    /// Exports the tree read-only.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Hides entries matching `pattern` (see [`ExcludeSet`] for the syntax).
+    pub fn exclude(mut self, pattern: impl Into<String>) -> Self {
+        self.exclude.push(pattern.into());
+        self
+    }
+
+    /// Sets how symbolic links are handled.
+    pub fn symlink_policy(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlinks = policy;
+        self
+    }
+
+    /// Sets when written data is flushed to disk.
+    pub fn fsync_policy(mut self, policy: FsyncPolicy) -> Self {
+        self.fsync = policy;
+        self
+    }
+
+    /// Sets how many files are kept open between reads and writes (256 by default);
+    /// 0 opens the file for every request.
+    pub fn open_files(mut self, count: usize) -> Self {
+        self.open_files = count;
+        self
+    }
+
+    /// Follows changes made outside the server through inotify instead of checking
+    /// the disk on every access (Linux only; elsewhere, and for directories that
+    /// cannot be watched, the disk is still checked).
+    pub fn watch(mut self, watch: bool) -> Self {
+        self.watch = watch;
+        self
+    }
+
+    /// Opens, creates, changes and removes files with the credentials of the calling
+    /// user rather than the server's, so that the kernel checks their permissions and
+    /// new files belong to them. Requires running as root, on Linux.
+    pub fn caller_credentials(mut self, enabled: bool) -> Self {
+        self.caller_credentials = enabled;
+        self
+    }
+
+    /// Sets how file systems mounted below the root are exported.
+    pub fn mount_policy(mut self, policy: MountPolicy) -> Self {
+        self.mounts = policy;
+        self
+    }
+
+    /// Validates the configuration and creates the file system.
+    ///
+    /// Fails if the root is not an accessible directory, an exclude pattern is
+    /// malformed, or caller credentials are requested without running as root or on
+    /// a system other than Linux.
+    pub fn build(self) -> io::Result<MirrorFS> {
+        let meta = std::fs::metadata(&self.root)?;
+        if !meta.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a directory", self.root.display()),
+            ));
+        }
+        if self.caller_credentials && cfg!(not(target_os = "linux")) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "acting with the credentials of the caller is only supported on Linux",
+            ));
+        }
+        if self.caller_credentials && unsafe { libc::geteuid() } != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "acting with the credentials of the caller requires running as root",
+            ));
+        }
+        let exclude = ExcludeSet::new(&self.exclude)?;
+        MirrorFS::from_options(MirrorOptions {
+            root: self.root,
+            read_only: self.read_only,
+            exclude,
+            symlinks: self.symlinks,
+            fsync: self.fsync,
+            open_files: self.open_files,
+            watch: self.watch,
+            caller_credentials: self.caller_credentials,
+            mounts: self.mounts,
+        })
+    }
+}
+
+/// Validated options handed from the builder to the file system.
+pub(crate) struct MirrorOptions {
+    pub root: PathBuf,
+    pub read_only: bool,
+    pub exclude: ExcludeSet,
+    pub symlinks: SymlinkPolicy,
+    pub fsync: FsyncPolicy,
+    pub open_files: usize,
+    pub watch: bool,
+    pub caller_credentials: bool,
+    pub mounts: MountPolicy,
+}
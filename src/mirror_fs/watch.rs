@@ -0,0 +1,294 @@
+//! Cache invalidation driven by inotify.
+//!
+//! Without a watcher every access re-stats the entry and every `READDIR` reads the
+//! directory from disk again. With one, each directory gets an inotify watch when it
+//! is first listed in full, and a background thread applies the create, delete,
+//! rename and modify events of the watched directories to the [`FSMap`] as they
+//! happen. Entries in a directory whose events are being applied ("synced") are
+//! served from the map.
+//!
+//! When the kernel's event queue overflows, events have been lost: every directory
+//! stops being synced, so entries and listings are read from disk again (which
+//! yields their current attributes and cookie verifiers) until each directory is
+//! listed and synced anew. Directories that cannot be watched are always polled.
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::{CString, OsStr, OsString};
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::Duration;
+
+use tracing::{debug, warn};
+
+use crate::xdr::nfs3;
+
+use super::dir_locks::DirLocks;
+use super::export_root::ExportRoot;
+use super::fs_map::FSMap;
+
+/// Events of the children of a directory, and of the directory itself
+const WATCH_MASK: u32 = libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO
+    | libc::IN_MODIFY
+    | libc::IN_ATTRIB
+    | libc::IN_DELETE_SELF
+    | libc::IN_MOVE_SELF
+    | libc::IN_ONLYDIR
+    | libc::IN_EXCL_UNLINK;
+
+/// How often the event thread checks whether the file system is still in use
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// One inotify event
+#[derive(Debug)]
+struct Event {
+    wd: i32,
+    mask: u32,
+    cookie: u32,
+    name: OsString,
+}
+
+#[derive(Debug, Default)]
+struct WatchState {
+    /// Directory of each watch descriptor
+    dirs: HashMap<i32, nfs3::fileid3>,
+    /// Watch descriptor of each directory
+    watches: HashMap<nfs3::fileid3, i32>,
+    /// Directories whose listing is kept current by events
+    synced: HashSet<nfs3::fileid3>,
+}
+
+/// An inotify instance watching the listed directories of a mirror
+#[derive(Debug)]
+pub struct Watcher {
+    fd: OwnedFd,
+    state: Mutex<WatchState>,
+}
+
+impl Watcher {
+    /// Creates an inotify instance.
+    pub fn new() -> io::Result<Arc<Self>> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        Ok(Arc::new(Self { fd, state: Mutex::new(WatchState::default()) }))
+    }
+
+    /// Starts applying events to `fsmap` on a background thread, which ends once the
+    /// map is dropped.
+    pub fn spawn(self: &Arc<Self>, fsmap: Weak<RwLock<FSMap>>, dirs: Arc<DirLocks>) {
+        let watcher = self.clone();
+        let spawned = std::thread::Builder::new()
+            .name("fernfs-watch".into())
+            .spawn(move || watcher.run(fsmap, dirs));
+        if let Err(err) = spawned {
+            warn!("Unable to start the watcher thread, polling instead: {err}");
+        }
+    }
+
+    /// Watches directory `id` at `path` below `export`. It is synced once
+    /// [`Watcher::mark_synced`] is called after listing it.
+    pub fn watch(&self, id: nfs3::fileid3, export: &ExportRoot, path: &Path) -> io::Result<()> {
+        // inotify only takes paths; the descriptor's entry in /proc names the directory
+        // that was resolved below the root.
+        let dir = export.open_dir(path)?;
+        let c_path = CString::new(format!("/proc/self/fd/{}", dir.as_raw_fd()))?;
+        let wd =
+            unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), c_path.as_ptr(), WATCH_MASK) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut state = self.state.lock().unwrap();
+        if let Some(old) = state.dirs.insert(wd, id) {
+            // The directory was known under another ID, which no longer names it.
+            if old != id {
+                state.watches.remove(&old);
+                state.synced.remove(&old);
+            }
+        }
+        state.watches.insert(id, wd);
+        Ok(())
+    }
+
+    /// Records that the listing of directory `id` in the map is current.
+    pub fn mark_synced(&self, id: nfs3::fileid3) {
+        let mut state = self.state.lock().unwrap();
+        if state.watches.contains_key(&id) {
+            state.synced.insert(id);
+        }
+    }
+
+    /// Stops serving every directory from the map until it is listed again.
+    pub fn unsync_all(&self) {
+        self.state.lock().unwrap().synced.clear();
+    }
+
+    /// Checks whether events keep the listing of directory `id` current.
+    pub fn is_synced(&self, id: nfs3::fileid3) -> bool {
+        self.state.lock().unwrap().synced.contains(&id)
+    }
+
+    fn run(&self, fsmap: Weak<RwLock<FSMap>>, dirs: Arc<DirLocks>) {
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let mut pollfd =
+                libc::pollfd { fd: self.fd.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+            let ready = unsafe { libc::poll(&mut pollfd, 1, POLL_INTERVAL.as_millis() as i32) };
+            let Some(fsmap) = fsmap.upgrade() else {
+                return;
+            };
+            if ready <= 0 {
+                continue;
+            }
+            let read = unsafe {
+                libc::read(self.fd.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, buf.len())
+            };
+            if read < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::WouldBlock
+                    && err.kind() != io::ErrorKind::Interrupted
+                {
+                    warn!("Unable to read watch events, polling instead: {err}");
+                    self.state.lock().unwrap().synced.clear();
+                    return;
+                }
+                continue;
+            }
+            let events = parse_events(&buf[..read as usize]);
+            self.apply(&fsmap, &dirs, events);
+        }
+    }
+
+    /// Applies a batch of events to the map.
+    fn apply(&self, fsmap: &RwLock<FSMap>, dirs: &DirLocks, events: Vec<Event>) {
+        let mut paired = HashSet::new();
+        for (index, event) in events.iter().enumerate() {
+            if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                debug!("Watch queue overflowed, polling until directories are listed again");
+                self.unsync_all();
+                continue;
+            }
+            if event.mask & libc::IN_IGNORED != 0 {
+                let mut state = self.state.lock().unwrap();
+                if let Some(id) = state.dirs.remove(&event.wd) {
+                    state.watches.remove(&id);
+                    state.synced.remove(&id);
+                }
+                continue;
+            }
+            if paired.contains(&index) {
+                continue;
+            }
+            let Some(dirid) = self.state.lock().unwrap().dirs.get(&event.wd).copied() else {
+                continue;
+            };
+            if event.name.is_empty() {
+                // The directory itself was changed, moved or deleted.
+                if event.mask & libc::IN_ATTRIB != 0 {
+                    let _dir = dirs.blocking_lock_pair(dirid, dirid);
+                    update_dir(fsmap, dirid);
+                } else {
+                    self.state.lock().unwrap().synced.remove(&dirid);
+                }
+                continue;
+            }
+
+            debug!("Watch event {:#x} in {:?}: {:?}", event.mask, dirid, event.name);
+            if event.mask & libc::IN_MOVED_FROM != 0 {
+                // A rename within the watched directories arrives as a pair of events.
+                let target = events.iter().enumerate().skip(index + 1).find(|(_, other)| {
+                    other.mask & libc::IN_MOVED_TO != 0 && other.cookie == event.cookie
+                });
+                let target_dir = target.and_then(|(target_index, other)| {
+                    let to_dir = self.state.lock().unwrap().dirs.get(&other.wd).copied()?;
+                    Some((target_index, to_dir, other))
+                });
+                if let Some((target_index, to_dir, other)) = target_dir {
+                    paired.insert(target_index);
+                    let _dirs = dirs.blocking_lock_pair(dirid, to_dir);
+                    let meta = child_metadata(fsmap, to_dir, &other.name);
+                    let reused = child_metadata(fsmap, dirid, &event.name);
+                    let mut map = fsmap.write().unwrap();
+                    map.rename_child(dirid, &event.name, to_dir, &other.name, meta);
+                    map.update_child(dirid, &event.name, reused);
+                    drop(map);
+                    update_dir(fsmap, dirid);
+                    if to_dir != dirid {
+                        update_dir(fsmap, to_dir);
+                    }
+                    continue;
+                }
+            }
+
+            // The name may have been reused since; what counts is what it names now.
+            let _dir = dirs.blocking_lock_pair(dirid, dirid);
+            let meta = child_metadata(fsmap, dirid, &event.name);
+            fsmap.write().unwrap().update_child(dirid, &event.name, meta);
+            if event.mask
+                & (libc::IN_CREATE | libc::IN_DELETE | libc::IN_MOVED_FROM | libc::IN_MOVED_TO)
+                != 0
+            {
+                update_dir(fsmap, dirid);
+            }
+        }
+    }
+}
+
+/// Reads the metadata of `name` in directory `dirid`, or `None` if it is gone.
+fn child_metadata(
+    fsmap: &RwLock<FSMap>,
+    dirid: nfs3::fileid3,
+    name: &OsStr,
+) -> Option<std::fs::Metadata> {
+    let (export, path) = {
+        let fsmap = fsmap.read().unwrap();
+        (fsmap.export(), fsmap.entry_path(dirid).ok()?.join(name))
+    };
+    export.metadata(&path).ok()
+}
+
+/// Updates the attributes of directory `dirid`, whose modification time changes with
+/// its entries.
+fn update_dir(fsmap: &RwLock<FSMap>, dirid: nfs3::fileid3) {
+    let (export, path) = {
+        let fsmap = fsmap.read().unwrap();
+        let Ok(path) = fsmap.entry_path(dirid) else {
+            return;
+        };
+        (fsmap.export(), path)
+    };
+    if let Ok(meta) = export.metadata(&path) {
+        fsmap.write().unwrap().update_attributes(dirid, &meta);
+    }
+}
+
+/// Splits a buffer read from an inotify descriptor into events.
+fn parse_events(mut buf: &[u8]) -> Vec<Event> {
+    const HEADER: usize = std::mem::size_of::<libc::inotify_event>();
+    let mut events = Vec::new();
+    while buf.len() >= HEADER {
+        let header =
+            unsafe { std::ptr::read_unaligned(buf.as_ptr() as *const libc::inotify_event) };
+        let end = HEADER + header.len as usize;
+        if buf.len() < end {
+            break;
+        }
+        let name = &buf[HEADER..end];
+        let name = &name[..name.iter().position(|b| *b == 0).unwrap_or(name.len())];
+        events.push(Event {
+            wd: header.wd,
+            mask: header.mask,
+            cookie: header.cookie,
+            name: OsStr::from_bytes(name).to_os_string(),
+        });
+        buf = &buf[end..];
+    }
+    events
+}
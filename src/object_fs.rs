@@ -0,0 +1,1074 @@
+//! An `NFSFileSystem` that serves an S3-compatible object store.
+//!
+//! [`ObjectFS`] maps the keys of a bucket onto a tree: a key `photos/2024/a.jpg` is the
+//! file `a.jpg` in the directory `photos/2024`. Directories exist while keys below them
+//! do; `mkdir` stores an empty marker object `photos/2024/`, as the S3 console does, so
+//! empty directories survive. Ownership, permissions and times are kept in the user
+//! metadata of each object, with defaults for objects uploaded by other tools.
+//!
+//! The store is reached through the [`ObjectStore`] trait, which covers the handful of
+//! S3 calls needed. Wrap an S3 client in it to serve a real bucket, or use
+//! [`LocalObjectStore`] to work offline:
+//!
+//! ```ignore
+//! use fernfs::object_fs::{LocalObjectStore, ObjectFS};
+//!
+//! let store = LocalObjectStore::new("/tmp/bucket")?;
+//! let fs = ObjectFS::new(Arc::new(store)).prefix("exports/").part_size(16 * 1024 * 1024);
+//! ```
+//!
+//! Reads become ranged GETs. Objects cannot be changed in place, so writes to a file
+//! are collected and sent as a multipart upload, one part at a time as parts fill up,
+//! and the new object replaces the old one on COMMIT or a `FILE_SYNC` write. Until then
+//! other clients of the bucket see the old contents; uncommitted writes are lost if
+//! the server stops, which clients notice from the changed write verifier. Writes that
+//! cannot be published stay pending for the next COMMIT. Rewriting
+//! a part that was already uploaded finishes the upload first, so random writes to a
+//! large file are slow.
+//!
+//! Renaming a directory copies every object below it, which is neither atomic nor
+//! cheap on a large tree. Symlinks, hard links and special files are not supported.
+//! If a bucket holds both an object `a` and keys below `a/`, the object is shown.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use tracing::{debug, warn};
+
+use crate::vfs;
+use crate::xdr::nfs3;
+
+mod local;
+mod store;
+mod upload;
+
+pub use local::LocalObjectStore;
+pub use store::{CompletedPart, ListPage, Metadata, ObjectMeta, ObjectStore, ObjectSummary};
+use upload::Upload;
+
+/// Pending writes to a file, locked while they change
+type UploadSlot = Arc<tokio::sync::Mutex<Option<Upload>>>;
+type LockedUpload = tokio::sync::OwnedMutexGuard<Option<Upload>>;
+
+/// File ID of the root directory.
+const ROOT_ID: nfs3::fileid3 = 1;
+/// Longest accepted file name, in bytes.
+const NAME_MAX: usize = 255;
+/// Multipart uploads have at most this many parts.
+const MAX_PARTS: u64 = 10_000;
+const DEFAULT_PART_SIZE: u64 = 8 * 1024 * 1024;
+/// Size reported for directories
+const DIR_SIZE: u64 = 4096;
+
+/// Metadata entries holding the attributes of an object
+const META_MODE: &str = "mode";
+const META_UID: &str = "uid";
+const META_GID: &str = "gid";
+const META_ATIME: &str = "atime";
+const META_MTIME: &str = "mtime";
+const META_CTIME: &str = "ctime";
+const META_VERIFIER: &str = "create-verifier";
+
+type NFSResult<T> = Result<T, nfs3::nfsstat3>;
+
+fn io_error(err: io::Error) -> nfs3::nfsstat3 {
+    if err.kind() == io::ErrorKind::NotFound {
+        debug!("object disappeared: {}", err);
+        return nfs3::nfsstat3::NFS3ERR_STALE;
+    }
+    warn!("object store error: {}", err);
+    nfs3::nfsstat3::NFS3ERR_IO
+}
+
+fn nfstime(time: SystemTime) -> nfs3::nfstime3 {
+    let since_epoch = time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    nfs3::nfstime3 { seconds: since_epoch.as_secs() as u32, nseconds: since_epoch.subsec_nanos() }
+}
+
+fn format_time(time: nfs3::nfstime3) -> String {
+    format!("{}.{:09}", time.seconds, time.nseconds)
+}
+
+fn parse_time(text: &str) -> Option<nfs3::nfstime3> {
+    let (seconds, nseconds) = text.split_once('.')?;
+    Some(nfs3::nfstime3 { seconds: seconds.parse().ok()?, nseconds: nseconds.parse().ok()? })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Checks a name for a new object; keys are UTF-8.
+fn check_new_name(name: &[u8]) -> NFSResult<&str> {
+    let name = std::str::from_utf8(name).map_err(|_| nfs3::nfsstat3::NFS3ERR_INVAL)?;
+    if name.is_empty() || name.contains('/') || name.contains('\0') {
+        return Err(nfs3::nfsstat3::NFS3ERR_INVAL);
+    }
+    if name.len() > NAME_MAX {
+        return Err(nfs3::nfsstat3::NFS3ERR_NAMETOOLONG);
+    }
+    if name == "." || name == ".." {
+        return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
+    }
+    Ok(name)
+}
+
+/// Checks the name of an existing object; names that cannot be keys do not exist.
+fn existing_name(name: &[u8]) -> NFSResult<&str> {
+    match std::str::from_utf8(name) {
+        Ok("." | "..") => Err(nfs3::nfsstat3::NFS3ERR_INVAL),
+        Ok(name) if !name.is_empty() && !name.contains('/') => Ok(name),
+        _ => Err(nfs3::nfsstat3::NFS3ERR_NOENT),
+    }
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{dir}/{name}")
+    }
+}
+
+fn parent(path: &str) -> &str {
+    path.rfind('/').map_or("", |i| &path[..i])
+}
+
+/// Returns true if `path` is `ancestor` or lies below it.
+fn is_below(path: &str, ancestor: &str) -> bool {
+    ancestor.is_empty()
+        || path == ancestor
+        || path.strip_prefix(ancestor).is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// File IDs handed out for paths, which live as long as the server.
+#[derive(Debug)]
+struct Names {
+    paths: HashMap<nfs3::fileid3, String>,
+    ids: HashMap<String, nfs3::fileid3>,
+    next_id: nfs3::fileid3,
+    /// Last change made through this server to each directory, so its mtime moves
+    /// even when only a removal changed it
+    dir_times: HashMap<nfs3::fileid3, SystemTime>,
+}
+
+impl Names {
+    fn new() -> Self {
+        Self {
+            paths: HashMap::from([(ROOT_ID, String::new())]),
+            ids: HashMap::from([(String::new(), ROOT_ID)]),
+            next_id: ROOT_ID + 1,
+            dir_times: HashMap::new(),
+        }
+    }
+
+    fn id(&mut self, path: &str) -> nfs3::fileid3 {
+        if let Some(id) = self.ids.get(path) {
+            return *id;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.paths.insert(id, path.to_string());
+        self.ids.insert(path.to_string(), id);
+        id
+    }
+
+    fn path(&self, id: nfs3::fileid3) -> NFSResult<String> {
+        self.paths.get(&id).cloned().ok_or(nfs3::nfsstat3::NFS3ERR_STALE)
+    }
+
+    /// Invalidates the IDs of `path` and everything below it.
+    fn forget(&mut self, path: &str) {
+        let gone: Vec<_> = self.ids.keys().filter(|p| is_below(p, path)).cloned().collect();
+        for path in gone {
+            if let Some(id) = self.ids.remove(&path) {
+                self.paths.remove(&id);
+                self.dir_times.remove(&id);
+            }
+        }
+    }
+
+    /// Moves the IDs of `from` and everything below it to `to`, replacing those of `to`.
+    fn rename(&mut self, from: &str, to: &str) {
+        self.forget(to);
+        let moved: Vec<_> = self.ids.keys().filter(|p| is_below(p, from)).cloned().collect();
+        for path in moved {
+            let id = self.ids.remove(&path).unwrap();
+            let new_path = format!("{to}{}", &path[from.len()..]);
+            self.paths.insert(id, new_path.clone());
+            self.ids.insert(new_path, id);
+        }
+    }
+}
+
+/// The entries of a directory
+#[derive(Debug, Default)]
+struct Listing {
+    /// Names in key order, with the object for files and `None` for directories
+    entries: Vec<(String, Option<ObjectSummary>)>,
+    /// Whether the directory has a marker object
+    marker: bool,
+    /// Most recent modification of the marker or a file in the directory
+    newest: Option<SystemTime>,
+}
+
+/// An [`NFSFileSystem`](vfs::NFSFileSystem) backed by an [`ObjectStore`].
+///
+/// See the [module documentation](self) for how keys map onto files.
+pub struct ObjectFS {
+    store: Arc<dyn ObjectStore>,
+    /// Prefix of every key served, empty or ending in `/`
+    prefix: String,
+    part_size: u64,
+    /// Owner of objects that do not record one
+    uid: nfs3::uid3,
+    gid: nfs3::gid3,
+    generation: u64,
+    started: SystemTime,
+    names: Mutex<Names>,
+    /// Pending writes by file, for files being written
+    uploads: Mutex<HashMap<nfs3::fileid3, UploadSlot>>,
+}
+
+impl ObjectFS {
+    /// Serves every key of `store`.
+    pub fn new(store: Arc<dyn ObjectStore>) -> Self {
+        let started = SystemTime::now();
+        let generation =
+            started.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+        Self {
+            store,
+            prefix: String::new(),
+            part_size: DEFAULT_PART_SIZE,
+            uid: 0,
+            gid: 0,
+            generation,
+            started,
+            names: Mutex::new(Names::new()),
+            uploads: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Serves only the keys below `prefix`, like one folder of the bucket.
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        let prefix = prefix.trim_matches('/');
+        self.prefix = if prefix.is_empty() { String::new() } else { format!("{prefix}/") };
+        self
+    }
+
+    /// Sets the size of the parts of multipart uploads (default 8 MiB).
+    ///
+    /// Files can grow to 10,000 parts. S3 rejects parts smaller than 5 MiB other than
+    /// the last one, so smaller sizes only suit stores without that limit.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bytes` is less than 4 KiB.
+    pub fn part_size(mut self, bytes: u64) -> Self {
+        assert!(bytes >= 4096, "part size must be at least 4 KiB");
+        self.part_size = bytes;
+        self
+    }
+
+    /// Sets the owner reported for objects that do not record one, and given to new
+    /// objects unless the client sets one.
+    pub fn owner(mut self, uid: nfs3::uid3, gid: nfs3::gid3) -> Self {
+        self.uid = uid;
+        self.gid = gid;
+        self
+    }
+
+    fn names(&self) -> std::sync::MutexGuard<'_, Names> {
+        self.names.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn path(&self, id: nfs3::fileid3) -> NFSResult<String> {
+        self.names().path(id)
+    }
+
+    fn file_key(&self, path: &str) -> String {
+        format!("{}{path}", self.prefix)
+    }
+
+    /// Prefix of the keys in a directory, which is also the key of its marker.
+    fn dir_prefix(&self, path: &str) -> String {
+        if path.is_empty() {
+            self.prefix.clone()
+        } else {
+            format!("{}{path}/", self.prefix)
+        }
+    }
+
+    /// Records a change to a directory.
+    fn touch(&self, dirid: nfs3::fileid3) {
+        self.names().dir_times.insert(dirid, SystemTime::now());
+    }
+
+    fn upload_slot(&self, id: nfs3::fileid3) -> UploadSlot {
+        let mut uploads = self.uploads.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        uploads.entry(id).or_default().clone()
+    }
+
+    fn existing_slot(&self, id: nfs3::fileid3) -> Option<UploadSlot> {
+        let uploads = self.uploads.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        uploads.get(&id).cloned()
+    }
+
+    /// Locks the pending writes to a file, creating its slot if needed.
+    async fn lock_upload(&self, id: nfs3::fileid3) -> LockedUpload {
+        loop {
+            let slot = self.upload_slot(id);
+            let pending = slot.clone().lock_owned().await;
+            // The slot may have been released while we waited for it.
+            if self.existing_slot(id).is_some_and(|current| Arc::ptr_eq(&current, &slot)) {
+                return pending;
+            }
+        }
+    }
+
+    /// Drops the slot of a file once it has no pending writes.
+    fn release_upload(&self, id: nfs3::fileid3, pending: LockedUpload) {
+        if pending.is_some() {
+            return;
+        }
+        let mut uploads = self.uploads.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let slot = tokio::sync::OwnedMutexGuard::mutex(&pending);
+        if uploads.get(&id).is_some_and(|current| Arc::ptr_eq(current, slot)) {
+            uploads.remove(&id);
+        }
+    }
+
+    /// Publishes pending writes, which stay pending if that fails.
+    async fn publish(&self, pending: &mut Option<Upload>) -> NFSResult<()> {
+        if let Some(upload) = pending {
+            upload.finish(&*self.store).await.map_err(io_error)?;
+            *pending = None;
+        }
+        Ok(())
+    }
+
+    /// Publishes the pending writes to a file.
+    async fn finish_upload(&self, id: nfs3::fileid3) -> NFSResult<()> {
+        if let Some(slot) = self.existing_slot(id) {
+            let mut pending = slot.lock_owned().await;
+            self.publish(&mut pending).await?;
+            self.release_upload(id, pending);
+        }
+        Ok(())
+    }
+
+    /// Discards the pending writes to a file that is going away.
+    async fn abort_upload(&self, id: nfs3::fileid3) {
+        let slot = self.uploads.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).remove(&id);
+        if let Some(slot) = slot {
+            if let Some(upload) = slot.lock().await.take() {
+                upload.abort(&*self.store).await;
+            }
+        }
+    }
+
+    /// Publishes the pending writes to every file at or below `path`.
+    async fn finish_uploads_below(&self, path: &str) -> NFSResult<()> {
+        let ids: Vec<_> = {
+            let names = self.names();
+            names.ids.iter().filter(|(p, _)| is_below(p, path)).map(|(_, id)| *id).collect()
+        };
+        for id in ids {
+            self.finish_upload(id).await?;
+        }
+        Ok(())
+    }
+
+    fn attr(
+        &self,
+        id: nfs3::fileid3,
+        ftype: nfs3::ftype3,
+        size: u64,
+        modified: SystemTime,
+        metadata: &Metadata,
+    ) -> nfs3::fattr3 {
+        let modified = nfstime(modified);
+        let time = |name| metadata.get(name).and_then(|t| parse_time(t));
+        let mtime = time(META_MTIME).unwrap_or(modified);
+        let default_mode = if matches!(ftype, nfs3::ftype3::NF3DIR) { 0o755 } else { 0o644 };
+        nfs3::fattr3 {
+            ftype,
+            mode: metadata
+                .get(META_MODE)
+                .and_then(|mode| u32::from_str_radix(mode, 8).ok())
+                .unwrap_or(default_mode)
+                & 0o7777,
+            nlink: 1,
+            uid: metadata.get(META_UID).and_then(|uid| uid.parse().ok()).unwrap_or(self.uid),
+            gid: metadata.get(META_GID).and_then(|gid| gid.parse().ok()).unwrap_or(self.gid),
+            size,
+            used: size,
+            fileid: id,
+            atime: time(META_ATIME).unwrap_or(mtime),
+            mtime,
+            ctime: time(META_CTIME).unwrap_or(modified),
+            ..Default::default()
+        }
+    }
+
+    fn file_attr(&self, id: nfs3::fileid3, meta: &ObjectMeta) -> nfs3::fattr3 {
+        self.attr(id, nfs3::ftype3::NF3REG, meta.size, meta.modified, &meta.metadata)
+    }
+
+    /// Returns the object of a file, as it stands with pending writes.
+    async fn file_meta(&self, id: nfs3::fileid3, path: &str) -> NFSResult<Option<ObjectMeta>> {
+        if path.is_empty() {
+            return Ok(None);
+        }
+        if let Some(slot) = self.existing_slot(id) {
+            if let Some(upload) = &*slot.lock().await {
+                return Ok(Some(ObjectMeta {
+                    size: upload.size,
+                    modified: upload.modified,
+                    metadata: upload.metadata.clone(),
+                }));
+            }
+        }
+        self.store.head(&self.file_key(path)).await.map_err(io_error)
+    }
+
+    async fn dir_exists(&self, path: &str) -> NFSResult<bool> {
+        if path.is_empty() {
+            return Ok(true);
+        }
+        let page = self.store.list(&self.dir_prefix(path), None).await.map_err(io_error)?;
+        Ok(!page.objects.is_empty() || !page.prefixes.is_empty())
+    }
+
+    async fn list_dir(&self, path: &str) -> NFSResult<Listing> {
+        let prefix = self.dir_prefix(path);
+        let mut listing = Listing::default();
+        let mut continuation = None;
+        loop {
+            let page = self.store.list(&prefix, continuation.as_deref()).await.map_err(io_error)?;
+            for object in page.objects {
+                listing.newest = listing.newest.max(Some(object.modified));
+                let name = &object.key[prefix.len()..];
+                if name.is_empty() {
+                    listing.marker = true;
+                } else {
+                    listing.entries.push((name.to_string(), Some(object)));
+                }
+            }
+            for dir in page.prefixes {
+                let name = &dir[prefix.len()..dir.len() - 1];
+                if !name.is_empty() {
+                    listing.entries.push((name.to_string(), None));
+                }
+            }
+            continuation = page.next;
+            if continuation.is_none() {
+                break;
+            }
+        }
+        // Where an object and a directory share a name, the object wins.
+        listing.entries.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.is_none().cmp(&b.1.is_none())));
+        listing.entries.dedup_by(|later, earlier| later.0 == earlier.0);
+        Ok(listing)
+    }
+
+    async fn dir_attr_from(
+        &self,
+        id: nfs3::fileid3,
+        path: &str,
+        listing: &Listing,
+    ) -> NFSResult<nfs3::fattr3> {
+        let marker = match listing.marker {
+            true => self.store.head(&self.dir_prefix(path)).await.map_err(io_error)?,
+            false => None,
+        };
+        let metadata = marker.map(|marker| marker.metadata).unwrap_or_default();
+        let touched = self.names().dir_times.get(&id).copied();
+        let modified = listing.newest.max(touched).unwrap_or(self.started);
+        let mut attr = self.attr(id, nfs3::ftype3::NF3DIR, DIR_SIZE, modified, &metadata);
+        // Changes to the entries move the mtime past one set explicitly, so READDIR
+        // cookies from before the change are refused.
+        let changed = nfstime(modified);
+        if (changed.seconds, changed.nseconds) > (attr.mtime.seconds, attr.mtime.nseconds) {
+            attr.mtime = changed;
+        }
+        let subdirs = listing.entries.iter().filter(|(_, object)| object.is_none()).count();
+        attr.nlink = 2 + subdirs as u32;
+        Ok(attr)
+    }
+
+    /// Returns the attributes of a directory, or `None` if there is none at `path`.
+    async fn dir_attr(&self, id: nfs3::fileid3, path: &str) -> NFSResult<Option<nfs3::fattr3>> {
+        let listing = self.list_dir(path).await?;
+        if !path.is_empty() && !listing.marker && listing.entries.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(self.dir_attr_from(id, path, &listing).await?))
+    }
+
+    /// Returns whether a file, a directory or nothing is at `path`.
+    async fn kind(&self, path: &str) -> NFSResult<Option<nfs3::ftype3>> {
+        if !path.is_empty()
+            && self.store.head(&self.file_key(path)).await.map_err(io_error)?.is_some()
+        {
+            return Ok(Some(nfs3::ftype3::NF3REG));
+        }
+        Ok(self.dir_exists(path).await?.then_some(nfs3::ftype3::NF3DIR))
+    }
+
+    /// Stores a marker for a directory, so it outlives its last entry.
+    async fn keep_dir(&self, path: &str) -> NFSResult<()> {
+        if path.is_empty() {
+            return Ok(());
+        }
+        let marker = self.dir_prefix(path);
+        if self.store.head(&marker).await.map_err(io_error)?.is_none() {
+            self.store.put(&marker, Vec::new(), &Metadata::new()).await.map_err(io_error)?;
+        }
+        Ok(())
+    }
+
+    /// Moves every object at or below directory `from` to `to`.
+    async fn move_tree(&self, from: &str, to: &str) -> NFSResult<()> {
+        let mut dirs = vec![(self.dir_prefix(from), self.dir_prefix(to))];
+        while let Some((from, to)) = dirs.pop() {
+            let mut continuation = None;
+            loop {
+                let page =
+                    self.store.list(&from, continuation.as_deref()).await.map_err(io_error)?;
+                for object in page.objects {
+                    let target = format!("{to}{}", &object.key[from.len()..]);
+                    self.store.copy(&object.key, &target, None).await.map_err(io_error)?;
+                    self.store.delete(&object.key).await.map_err(io_error)?;
+                }
+                for dir in page.prefixes {
+                    dirs.push((dir.clone(), format!("{to}{}", &dir[from.len()..])));
+                }
+                continuation = page.next;
+                if continuation.is_none() {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the attributes of a SETATTR or CREATE to an object's metadata.
+    fn apply_sattr(metadata: &mut Metadata, setattr: &nfs3::sattr3) {
+        let now = nfstime(SystemTime::now());
+        if let Some(mode) = setattr.mode {
+            metadata.insert(META_MODE.to_string(), format!("{:o}", mode & 0o7777));
+        }
+        if let Some(uid) = setattr.uid {
+            metadata.insert(META_UID.to_string(), uid.to_string());
+        }
+        if let Some(gid) = setattr.gid {
+            metadata.insert(META_GID.to_string(), gid.to_string());
+        }
+        match setattr.atime {
+            nfs3::set_atime::DONT_CHANGE => {}
+            nfs3::set_atime::SET_TO_SERVER_TIME => {
+                metadata.insert(META_ATIME.to_string(), format_time(now));
+            }
+            nfs3::set_atime::SET_TO_CLIENT_TIME(t) => {
+                metadata.insert(META_ATIME.to_string(), format_time(t));
+            }
+        }
+        match setattr.mtime {
+            nfs3::set_mtime::DONT_CHANGE => {}
+            nfs3::set_mtime::SET_TO_SERVER_TIME => {
+                metadata.insert(META_MTIME.to_string(), format_time(now));
+            }
+            nfs3::set_mtime::SET_TO_CLIENT_TIME(t) => {
+                metadata.insert(META_MTIME.to_string(), format_time(t));
+            }
+        }
+        metadata.insert(META_CTIME.to_string(), format_time(now));
+    }
+
+    async fn setattr_file(
+        &self,
+        path: &str,
+        meta: ObjectMeta,
+        setattr: &nfs3::sattr3,
+    ) -> NFSResult<()> {
+        let key = self.file_key(path);
+        let mut metadata = meta.metadata.clone();
+        Self::apply_sattr(&mut metadata, setattr);
+        match setattr.size {
+            Some(size) if size != meta.size => {
+                if size > self.part_size * MAX_PARTS {
+                    return Err(nfs3::nfsstat3::NFS3ERR_FBIG);
+                }
+                if matches!(setattr.mtime, nfs3::set_mtime::DONT_CHANGE) {
+                    metadata.remove(META_MTIME);
+                }
+                let mut upload = Upload::new(key, self.part_size, meta);
+                upload.metadata = metadata;
+                upload.truncate(size);
+                upload.finish(&*self.store).await.map_err(io_error)
+            }
+            _ => self.store.copy(&key, &key, Some(&metadata)).await.map_err(io_error),
+        }
+    }
+
+    /// Applies `setattr` to the object or directory at `path`.
+    async fn setattr_path(&self, path: &str, setattr: &nfs3::sattr3) -> NFSResult<()> {
+        let meta = match path.is_empty() {
+            true => None,
+            false => self.store.head(&self.file_key(path)).await.map_err(io_error)?,
+        };
+        match meta {
+            Some(meta) => self.setattr_file(path, meta, setattr).await,
+            None if self.dir_exists(path).await? => self.setattr_dir(path, setattr).await,
+            None => Err(nfs3::nfsstat3::NFS3ERR_STALE),
+        }
+    }
+
+    /// Starts collecting writes to the file at `path`.
+    async fn new_upload(&self, path: &str) -> NFSResult<Upload> {
+        let key = self.file_key(path);
+        match self.store.head(&key).await.map_err(io_error)? {
+            Some(base) => Ok(Upload::new(key, self.part_size, base)),
+            None if self.dir_exists(path).await? => Err(nfs3::nfsstat3::NFS3ERR_ISDIR),
+            None => Err(nfs3::nfsstat3::NFS3ERR_STALE),
+        }
+    }
+
+    async fn setattr_dir(&self, path: &str, setattr: &nfs3::sattr3) -> NFSResult<()> {
+        if setattr.size.is_some() {
+            return Err(nfs3::nfsstat3::NFS3ERR_ISDIR);
+        }
+        let marker = self.dir_prefix(path);
+        if marker.is_empty() {
+            // The root of a whole bucket has no object to keep attributes in.
+            return Err(nfs3::nfsstat3::NFS3ERR_PERM);
+        }
+        let mut metadata = match self.store.head(&marker).await.map_err(io_error)? {
+            Some(meta) => meta.metadata,
+            None => Metadata::new(),
+        };
+        Self::apply_sattr(&mut metadata, setattr);
+        self.store.put(&marker, Vec::new(), &metadata).await.map_err(io_error)
+    }
+}
+
+#[async_trait]
+impl vfs::NFSFileSystem for ObjectFS {
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn capabilities(&self) -> vfs::Capabilities {
+        vfs::Capabilities::ReadWrite
+    }
+
+    fn root_dir(&self) -> nfs3::fileid3 {
+        ROOT_ID
+    }
+
+    async fn lookup(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> NFSResult<nfs3::fileid3> {
+        let dir = self.path(dirid)?;
+        match &filename[..] {
+            b"." => return Ok(dirid),
+            b".." => return Ok(self.names().id(parent(&dir))),
+            _ => {}
+        }
+        let Ok(name) = existing_name(filename) else {
+            return Err(nfs3::nfsstat3::NFS3ERR_NOENT);
+        };
+        let path = join(&dir, name);
+        match self.kind(&path).await? {
+            Some(_) => Ok(self.names().id(&path)),
+            None => Err(nfs3::nfsstat3::NFS3ERR_NOENT),
+        }
+    }
+
+    async fn getattr(&self, id: nfs3::fileid3) -> NFSResult<nfs3::fattr3> {
+        let path = self.path(id)?;
+        if let Some(meta) = self.file_meta(id, &path).await? {
+            return Ok(self.file_attr(id, &meta));
+        }
+        self.dir_attr(id, &path).await?.ok_or(nfs3::nfsstat3::NFS3ERR_STALE)
+    }
+
+    async fn setattr(&self, id: nfs3::fileid3, setattr: nfs3::sattr3) -> NFSResult<nfs3::fattr3> {
+        let path = self.path(id)?;
+        // Hold off writes while the object is replaced.
+        let mut pending = self.lock_upload(id).await;
+        let result = match self.publish(&mut pending).await {
+            Ok(()) => self.setattr_path(&path, &setattr).await,
+            Err(err) => Err(err),
+        };
+        self.release_upload(id, pending);
+        result?;
+        self.getattr(id).await
+    }
+
+    async fn read(&self, id: nfs3::fileid3, offset: u64, count: u32) -> NFSResult<(Vec<u8>, bool)> {
+        let path = self.path(id)?;
+        if let Some(slot) = self.existing_slot(id) {
+            let mut pending = slot.lock_owned().await;
+            if let Some(upload) = &*pending {
+                if !upload.touches_uploaded(offset, count as u64) {
+                    let data =
+                        upload.read(&*self.store, offset, count as u64).await.map_err(io_error)?;
+                    let eof = offset + data.len() as u64 >= upload.size;
+                    return Ok((data, eof));
+                }
+            }
+            // Uploaded parts can only be read back from the finished object.
+            self.publish(&mut pending).await?;
+            self.release_upload(id, pending);
+        }
+        if path.is_empty() {
+            return Err(nfs3::nfsstat3::NFS3ERR_ISDIR);
+        }
+        match self.store.get_range(&self.file_key(&path), offset, count as u64).await {
+            Ok(data) => {
+                let eof = data.len() < count as usize;
+                Ok((data, eof))
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound && self.dir_exists(&path).await? => {
+                Err(nfs3::nfsstat3::NFS3ERR_ISDIR)
+            }
+            Err(err) => Err(io_error(err)),
+        }
+    }
+
+    async fn write(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+        stable: nfs3::file::stable_how,
+    ) -> NFSResult<(nfs3::fattr3, nfs3::file::stable_how, nfs3::count3)> {
+        let len = data.len() as u64;
+        offset
+            .checked_add(len)
+            .filter(|end| *end <= self.part_size * MAX_PARTS)
+            .ok_or(nfs3::nfsstat3::NFS3ERR_FBIG)?;
+        let path = self.path(id)?;
+        if path.is_empty() {
+            return Err(nfs3::nfsstat3::NFS3ERR_ISDIR);
+        }
+        let mut pending = self.lock_upload(id).await;
+        if pending.as_ref().is_some_and(|upload| upload.touches_uploaded(offset, len)) {
+            // Uploaded parts cannot change, so publish them and start again from there.
+            self.publish(&mut pending).await?;
+        }
+        let upload = match pending.take() {
+            Some(upload) => upload,
+            None => match self.new_upload(&path).await {
+                Ok(upload) => upload,
+                Err(err) => {
+                    self.release_upload(id, pending);
+                    return Err(err);
+                }
+            },
+        };
+        let upload = pending.insert(upload);
+        // Writes move the times on; the new object's own modification time reports them.
+        upload.metadata.remove(META_MTIME);
+        upload.metadata.remove(META_CTIME);
+        upload.write(&*self.store, offset, data).await.map_err(io_error)?;
+        let meta = ObjectMeta {
+            size: upload.size,
+            modified: upload.modified,
+            metadata: upload.metadata.clone(),
+        };
+        let committed = if matches!(stable, nfs3::file::stable_how::UNSTABLE) {
+            nfs3::file::stable_how::UNSTABLE
+        } else {
+            self.publish(&mut pending).await?;
+            self.release_upload(id, pending);
+            nfs3::file::stable_how::FILE_SYNC
+        };
+        Ok((self.file_attr(id, &meta), committed, data.len() as nfs3::count3))
+    }
+
+    async fn create(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        attr: nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        let name = check_new_name(filename)?;
+        let path = join(&self.path(dirid)?, name);
+        if self.kind(&path).await?.is_some() {
+            return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
+        }
+        let mut metadata = Metadata::new();
+        Self::apply_sattr(&mut metadata, &nfs3::sattr3 { size: None, ..attr });
+        self.store.put(&self.file_key(&path), Vec::new(), &metadata).await.map_err(io_error)?;
+        self.touch(dirid);
+        let id = self.names().id(&path);
+        let attr = match attr.size {
+            Some(size) if size > 0 => {
+                let sized = nfs3::sattr3 { size: Some(size), ..Default::default() };
+                self.setattr(id, sized).await?
+            }
+            _ => self.getattr(id).await?,
+        };
+        Ok((id, attr))
+    }
+
+    async fn create_exclusive(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        verifier: nfs3::createverf3,
+    ) -> NFSResult<nfs3::fileid3> {
+        let name = check_new_name(filename)?;
+        let path = join(&self.path(dirid)?, name);
+        let key = self.file_key(&path);
+        let verifier = hex(&verifier);
+        if let Some(existing) = self.store.head(&key).await.map_err(io_error)? {
+            // A retransmitted request finds the file it created the first time.
+            if existing.metadata.get(META_VERIFIER) == Some(&verifier) {
+                return Ok(self.names().id(&path));
+            }
+            return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
+        }
+        if self.dir_exists(&path).await? {
+            return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
+        }
+        let metadata = Metadata::from([(META_VERIFIER.to_string(), verifier)]);
+        self.store.put(&key, Vec::new(), &metadata).await.map_err(io_error)?;
+        self.touch(dirid);
+        Ok(self.names().id(&path))
+    }
+
+    async fn mkdir(
+        &self,
+        dirid: nfs3::fileid3,
+        dirname: &nfs3::filename3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        let name = check_new_name(dirname)?;
+        let path = join(&self.path(dirid)?, name);
+        if self.kind(&path).await?.is_some() {
+            return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
+        }
+        let marker = self.dir_prefix(&path);
+        self.store.put(&marker, Vec::new(), &Metadata::new()).await.map_err(io_error)?;
+        self.touch(dirid);
+        let id = self.names().id(&path);
+        Ok((id, self.getattr(id).await?))
+    }
+
+    async fn remove(&self, dirid: nfs3::fileid3, filename: &nfs3::filename3) -> NFSResult<()> {
+        let name = existing_name(filename)?;
+        let dir = self.path(dirid)?;
+        let path = join(&dir, name);
+        match self.kind(&path).await? {
+            Some(nfs3::ftype3::NF3REG) => {
+                self.keep_dir(&dir).await?;
+                let id = self.names().ids.get(&path).copied();
+                if let Some(id) = id {
+                    self.abort_upload(id).await;
+                }
+                self.store.delete(&self.file_key(&path)).await.map_err(io_error)?;
+            }
+            Some(_) => {
+                if !self.list_dir(&path).await?.entries.is_empty() {
+                    return Err(nfs3::nfsstat3::NFS3ERR_NOTEMPTY);
+                }
+                self.keep_dir(&dir).await?;
+                self.store.delete(&self.dir_prefix(&path)).await.map_err(io_error)?;
+            }
+            None => return Err(nfs3::nfsstat3::NFS3ERR_NOENT),
+        }
+        self.names().forget(&path);
+        self.touch(dirid);
+        Ok(())
+    }
+
+    async fn rename(
+        &self,
+        from_dirid: nfs3::fileid3,
+        from_filename: &nfs3::filename3,
+        to_dirid: nfs3::fileid3,
+        to_filename: &nfs3::filename3,
+    ) -> NFSResult<()> {
+        let from_name = existing_name(from_filename)?;
+        existing_name(to_filename)?;
+        let to_name = check_new_name(to_filename)?;
+        let from_dir = self.path(from_dirid)?;
+        let from = join(&from_dir, from_name);
+        let to = join(&self.path(to_dirid)?, to_name);
+        let source = self.kind(&from).await?.ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?;
+        if from == to {
+            return Ok(());
+        }
+        let is_dir = matches!(source, nfs3::ftype3::NF3DIR);
+        if is_dir && is_below(&to, &from) {
+            // A directory cannot be moved underneath itself.
+            return Err(nfs3::nfsstat3::NFS3ERR_INVAL);
+        }
+        let target = self.kind(&to).await?;
+        match (is_dir, target) {
+            (false, Some(nfs3::ftype3::NF3DIR)) => return Err(nfs3::nfsstat3::NFS3ERR_ISDIR),
+            (true, Some(nfs3::ftype3::NF3REG)) => return Err(nfs3::nfsstat3::NFS3ERR_NOTDIR),
+            (true, Some(_)) if !self.list_dir(&to).await?.entries.is_empty() => {
+                return Err(nfs3::nfsstat3::NFS3ERR_NOTEMPTY)
+            }
+            _ => {}
+        }
+
+        self.keep_dir(&from_dir).await?;
+        self.finish_uploads_below(&from).await?;
+        let replaced = self.names().ids.get(&to).copied();
+        if let Some(replaced) = replaced {
+            self.abort_upload(replaced).await;
+        }
+        if is_dir {
+            if target.is_some() {
+                self.store.delete(&self.dir_prefix(&to)).await.map_err(io_error)?;
+            }
+            self.move_tree(&from, &to).await?;
+        } else {
+            let (from_key, to_key) = (self.file_key(&from), self.file_key(&to));
+            self.store.copy(&from_key, &to_key, None).await.map_err(io_error)?;
+            self.store.delete(&from_key).await.map_err(io_error)?;
+        }
+        self.names().rename(&from, &to);
+        self.touch(from_dirid);
+        self.touch(to_dirid);
+        Ok(())
+    }
+
+    async fn readdir(
+        &self,
+        dirid: nfs3::fileid3,
+        start_after: nfs3::fileid3,
+        max_entries: usize,
+    ) -> NFSResult<vfs::ReadDirResult> {
+        let path = self.path(dirid)?;
+        if self.file_meta(dirid, &path).await?.is_some() {
+            return Err(nfs3::nfsstat3::NFS3ERR_NOTDIR);
+        }
+        let listing = self.list_dir(&path).await?;
+        if !path.is_empty() && !listing.marker && listing.entries.is_empty() {
+            return Err(nfs3::nfsstat3::NFS3ERR_STALE);
+        }
+        // Cookies are indices into ".", "..", then the entries in key order.
+        let total = listing.entries.len() + 2;
+        let start = usize::try_from(start_after).map_err(|_| nfs3::nfsstat3::NFS3ERR_BAD_COOKIE)?;
+        if start > total {
+            return Err(nfs3::nfsstat3::NFS3ERR_BAD_COOKIE);
+        }
+        let end = total.min(start.saturating_add(max_entries));
+        let mut result = vfs::ReadDirResult { entries: Vec::new(), end: end == total };
+        for index in start..end {
+            let (name, fileid, attr) = match index {
+                0 => (".".to_string(), dirid, self.dir_attr_from(dirid, &path, &listing).await?),
+                1 => {
+                    let parent_path = parent(&path);
+                    let parent_id = self.names().id(parent_path);
+                    let attr = self.dir_attr(parent_id, parent_path).await?;
+                    ("..".to_string(), parent_id, attr.ok_or(nfs3::nfsstat3::NFS3ERR_STALE)?)
+                }
+                _ => {
+                    let (name, object) = &listing.entries[index - 2];
+                    let child = join(&path, name);
+                    let id = self.names().id(&child);
+                    let attr = match object {
+                        Some(object) => match self.file_meta(id, &child).await? {
+                            Some(meta) => self.file_attr(id, &meta),
+                            // Removed since the listing; report what was listed.
+                            None => self.attr(
+                                id,
+                                nfs3::ftype3::NF3REG,
+                                object.size,
+                                object.modified,
+                                &Metadata::new(),
+                            ),
+                        },
+                        None => match self.dir_attr(id, &child).await? {
+                            Some(attr) => attr,
+                            None => self.attr(
+                                id,
+                                nfs3::ftype3::NF3DIR,
+                                DIR_SIZE,
+                                self.started,
+                                &Metadata::new(),
+                            ),
+                        },
+                    };
+                    (name.clone(), id, attr)
+                }
+            };
+            result.entries.push(vfs::DirEntry { fileid, name: name.into_bytes().into(), attr });
+        }
+        Ok(result)
+    }
+
+    async fn readdir_index(
+        &self,
+        dirid: nfs3::fileid3,
+        start_index: usize,
+        max_entries: usize,
+    ) -> NFSResult<vfs::ReadDirResult> {
+        self.readdir(dirid, start_index as nfs3::fileid3, max_entries).await
+    }
+
+    async fn symlink(
+        &self,
+        _dirid: nfs3::fileid3,
+        _linkname: &nfs3::filename3,
+        _symlink: &nfs3::nfspath3,
+        _attr: &nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        Err(nfs3::nfsstat3::NFS3ERR_NOTSUPP)
+    }
+
+    async fn readlink(&self, _id: nfs3::fileid3) -> NFSResult<nfs3::nfspath3> {
+        Err(nfs3::nfsstat3::NFS3ERR_INVAL)
+    }
+
+    async fn link(
+        &self,
+        _file_id: nfs3::fileid3,
+        _link_dir_id: nfs3::fileid3,
+        _link_name: &nfs3::filename3,
+    ) -> NFSResult<nfs3::fattr3> {
+        Err(nfs3::nfsstat3::NFS3ERR_NOTSUPP)
+    }
+
+    async fn mknod(
+        &self,
+        _dir_id: nfs3::fileid3,
+        _name: &nfs3::filename3,
+        _ftype: nfs3::ftype3,
+        _specdata: nfs3::specdata3,
+        _attrs: &nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        Err(nfs3::nfsstat3::NFS3ERR_NOTSUPP)
+    }
+
+    async fn commit(
+        &self,
+        file_id: nfs3::fileid3,
+        _offset: u64,
+        _count: u32,
+    ) -> NFSResult<nfs3::fattr3> {
+        // Objects are replaced whole, so commit everything written to the file.
+        self.finish_upload(file_id).await?;
+        self.getattr(file_id).await
+    }
+
+    fn fsinfo_properties(&self) -> u32 {
+        nfs3::fs::FSF_HOMOGENEOUS | nfs3::fs::FSF_CANSETTIME
+    }
+
+    fn pathconf_name_max(&self) -> u32 {
+        NAME_MAX as u32
+    }
+}
@@ -0,0 +1,435 @@
+//! An [`ObjectStore`] kept in a local directory, for tests and offline use.
+//!
+//! Keys are split at `/` into a tree so listings only read one directory:
+//!
+//! ```text
+//! <root>/objects/logs.d/app.log.o    contents of "logs/app.log"
+//! <root>/objects/logs.d/app.log.m    its metadata, one "name=value" line per entry
+//! <root>/objects/logs.d/.o           the empty object "logs/"
+//! <root>/uploads/<id>/               parts of a multipart upload in progress
+//! ```
+//!
+//! Key segments are percent-encoded, so any key maps to valid file names, and the
+//! `.o`/`.d` suffixes keep an object `a` apart from the keys below `a/`. Objects are
+//! written to a temporary file and renamed into place.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+
+use super::store::{CompletedPart, ListPage, Metadata, ObjectMeta, ObjectStore, ObjectSummary};
+
+/// Entries per listing page, as in S3
+const PAGE_SIZE: usize = 1000;
+
+fn encode(segment: &str) -> String {
+    let mut out = String::new();
+    for byte in segment.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_.~".contains(&byte) {
+            out.push(byte as char);
+        } else {
+            out.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    out
+}
+
+fn decode(name: &str) -> Option<String> {
+    let bytes = name.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            out.push(u8::from_str_radix(name.get(i + 1..i + 3)?, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+fn not_found(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("no such {what}"))
+}
+
+/// A cheap content checksum standing in for S3's MD5 ETags (FNV-1a).
+fn etag(data: &[u8]) -> String {
+    let hash = data.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{hash:016x}")
+}
+
+fn write_metadata(path: &Path, metadata: &Metadata) -> io::Result<()> {
+    let text: String = metadata
+        .iter()
+        .map(|(name, value)| format!("{}={}\n", encode(name), encode(value)))
+        .collect();
+    fs::write(path, text)
+}
+
+fn read_metadata(path: &Path) -> io::Result<Metadata> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Metadata::new()),
+        Err(err) => return Err(err),
+    };
+    text.lines()
+        .map(|line| {
+            let (name, value) = line.split_once('=')?;
+            Some((decode(name)?, decode(value)?))
+        })
+        .collect::<Option<_>>()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed object metadata"))
+}
+
+/// Files holding one object
+struct ObjectPaths {
+    data: PathBuf,
+    metadata: PathBuf,
+}
+
+struct Local {
+    root: PathBuf,
+    next_id: AtomicU64,
+}
+
+impl Local {
+    fn objects(&self) -> PathBuf {
+        self.root.join("objects")
+    }
+
+    fn tmp_path(&self) -> PathBuf {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.root.join("tmp").join(format!("{}-{id}", std::process::id()))
+    }
+
+    fn upload_dir(&self, upload_id: &str) -> PathBuf {
+        self.root.join("uploads").join(encode(upload_id))
+    }
+
+    /// Directory of the keys below `dir_prefix`, which is empty or ends in `/`.
+    fn dir_path(&self, dir_prefix: &str) -> PathBuf {
+        let mut path = self.objects();
+        let segments = dir_prefix.strip_suffix('/').map(|dirs| dirs.split('/'));
+        for segment in segments.into_iter().flatten() {
+            path.push(format!("{}.d", encode(segment)));
+        }
+        path
+    }
+
+    fn paths(&self, key: &str) -> io::Result<ObjectPaths> {
+        if key.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "empty object key"));
+        }
+        let split = key.rfind('/').map_or(0, |i| i + 1);
+        let dir = self.dir_path(&key[..split]);
+        let name = encode(&key[split..]);
+        Ok(ObjectPaths {
+            data: dir.join(format!("{name}.o")),
+            metadata: dir.join(format!("{name}.m")),
+        })
+    }
+
+    /// Moves a finished temporary file into place as `key`.
+    fn install(&self, tmp: &Path, key: &str, metadata: &Metadata) -> io::Result<()> {
+        let paths = self.paths(key)?;
+        fs::create_dir_all(paths.data.parent().unwrap())?;
+        let tmp_metadata = self.tmp_path();
+        write_metadata(&tmp_metadata, metadata)?;
+        fs::rename(&tmp_metadata, &paths.metadata)?;
+        fs::rename(tmp, &paths.data)
+    }
+
+    fn head(&self, key: &str) -> io::Result<Option<ObjectMeta>> {
+        let paths = self.paths(key)?;
+        let stat = match fs::metadata(&paths.data) {
+            Ok(stat) => stat,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        Ok(Some(ObjectMeta {
+            size: stat.len(),
+            modified: stat.modified()?,
+            metadata: read_metadata(&paths.metadata)?,
+        }))
+    }
+
+    fn get_range(&self, key: &str, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        let mut file = File::open(self.paths(key)?.data)?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut data = Vec::new();
+        file.take(len).read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn put(&self, key: &str, data: &[u8], metadata: &Metadata) -> io::Result<()> {
+        let tmp = self.tmp_path();
+        fs::write(&tmp, data)?;
+        self.install(&tmp, key, metadata)
+    }
+
+    fn copy(&self, from: &str, to: &str, metadata: Option<&Metadata>) -> io::Result<()> {
+        let source = self.paths(from)?;
+        let metadata = match metadata {
+            Some(metadata) => metadata.clone(),
+            None => read_metadata(&source.metadata)?,
+        };
+        let tmp = self.tmp_path();
+        fs::copy(&source.data, &tmp)?;
+        self.install(&tmp, to, &metadata)
+    }
+
+    fn delete(&self, key: &str) -> io::Result<()> {
+        let paths = self.paths(key)?;
+        for path in [&paths.data, &paths.metadata] {
+            match fs::remove_file(path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
+        }
+        // Directories exist only while keys below them do.
+        let objects = self.objects();
+        let mut dir = paths.data.parent();
+        while let Some(path) = dir.filter(|path| *path != objects) {
+            if fs::remove_dir(path).is_err() {
+                break;
+            }
+            dir = path.parent();
+        }
+        Ok(())
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<&str>) -> io::Result<ListPage> {
+        let split = prefix.rfind('/').map_or(0, |i| i + 1);
+        let (dir_prefix, partial) = prefix.split_at(split);
+        let entries = match fs::read_dir(self.dir_path(dir_prefix)) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(ListPage::default()),
+            Err(err) => return Err(err),
+        };
+        // Objects and prefixes share one key order.
+        let mut items: Vec<(String, Option<ObjectSummary>)> = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            let Some(file_name) = file_name.to_str() else {
+                continue;
+            };
+            let Some((name, suffix)) = file_name.rsplit_once('.') else {
+                continue;
+            };
+            let Some(name) = decode(name).filter(|name| name.starts_with(partial)) else {
+                continue;
+            };
+            match suffix {
+                "o" => {
+                    let stat = entry.metadata()?;
+                    let key = format!("{dir_prefix}{name}");
+                    let summary = ObjectSummary {
+                        key: key.clone(),
+                        size: stat.len(),
+                        modified: stat.modified()?,
+                    };
+                    items.push((key, Some(summary)));
+                }
+                "d" => items.push((format!("{dir_prefix}{name}/"), None)),
+                _ => {}
+            }
+        }
+        items.sort_by(|a, b| a.0.cmp(&b.0));
+        if let Some(after) = continuation {
+            items.retain(|(key, _)| key.as_str() > after);
+        }
+        let mut page = ListPage::default();
+        if items.len() > PAGE_SIZE {
+            items.truncate(PAGE_SIZE);
+            page.next = items.last().map(|(key, _)| key.clone());
+        }
+        for (key, summary) in items {
+            match summary {
+                Some(summary) => page.objects.push(summary),
+                None => page.prefixes.push(key),
+            }
+        }
+        Ok(page)
+    }
+
+    fn create_multipart(&self, key: &str, metadata: &Metadata) -> io::Result<String> {
+        self.paths(key)?;
+        let nanos =
+            SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_nanos();
+        let upload_id = format!("{nanos:x}-{}", self.next_id.fetch_add(1, Ordering::Relaxed));
+        let dir = self.upload_dir(&upload_id);
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("key"), key)?;
+        write_metadata(&dir.join("metadata"), metadata)?;
+        Ok(upload_id)
+    }
+
+    /// Returns the directory of an upload after checking it belongs to `key`.
+    fn upload(&self, key: &str, upload_id: &str) -> io::Result<PathBuf> {
+        let dir = self.upload_dir(upload_id);
+        match fs::read_to_string(dir.join("key")) {
+            Ok(stored) if stored == key => Ok(dir),
+            Ok(_) => Err(not_found("upload for this key")),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Err(not_found("upload")),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: u32,
+        data: &[u8],
+    ) -> io::Result<String> {
+        let dir = self.upload(key, upload_id)?;
+        fs::write(dir.join(format!("{part_number}.part")), data)?;
+        Ok(etag(data))
+    }
+
+    fn complete_multipart(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: &[CompletedPart],
+    ) -> io::Result<()> {
+        let dir = self.upload(key, upload_id)?;
+        if parts.is_empty() || parts.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "parts must be listed in ascending order",
+            ));
+        }
+        let tmp = self.tmp_path();
+        let mut out = File::create(&tmp)?;
+        for (number, expected) in parts {
+            let data = fs::read(dir.join(format!("{number}.part")))?;
+            if etag(&data) != *expected {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("part {number} has a different ETag"),
+                ));
+            }
+            out.write_all(&data)?;
+        }
+        drop(out);
+        self.install(&tmp, key, &read_metadata(&dir.join("metadata"))?)?;
+        fs::remove_dir_all(dir)
+    }
+
+    fn abort_multipart(&self, key: &str, upload_id: &str) -> io::Result<()> {
+        fs::remove_dir_all(self.upload(key, upload_id)?)
+    }
+}
+
+/// An [`ObjectStore`] in a local directory.
+///
+/// It behaves like a bucket closely enough to develop and test against [`ObjectFS`]
+/// offline: keys and prefixes, ranged reads, metadata, and multipart uploads whose
+/// parts are checked against their ETags when the upload completes.
+///
+/// [`ObjectFS`]: super::ObjectFS
+#[derive(Clone)]
+pub struct LocalObjectStore {
+    local: Arc<Local>,
+}
+
+impl std::fmt::Debug for LocalObjectStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LocalObjectStore").field("root", &self.local.root).finish()
+    }
+}
+
+impl LocalObjectStore {
+    /// Opens the store in `root`, creating it if it does not exist.
+    pub fn new(root: impl Into<PathBuf>) -> io::Result<Self> {
+        let root = root.into();
+        for dir in ["objects", "uploads", "tmp"] {
+            fs::create_dir_all(root.join(dir))?;
+        }
+        Ok(Self { local: Arc::new(Local { root, next_id: AtomicU64::new(0) }) })
+    }
+
+    /// Runs file operations off the async runtime.
+    async fn blocking<T: Send + 'static>(
+        &self,
+        op: impl FnOnce(&Local) -> io::Result<T> + Send + 'static,
+    ) -> io::Result<T> {
+        let local = self.local.clone();
+        tokio::task::spawn_blocking(move || op(&local)).await.map_err(io::Error::other)?
+    }
+}
+
+#[async_trait]
+impl ObjectStore for LocalObjectStore {
+    async fn head(&self, key: &str) -> io::Result<Option<ObjectMeta>> {
+        let key = key.to_owned();
+        self.blocking(move |local| local.head(&key)).await
+    }
+
+    async fn get_range(&self, key: &str, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        let key = key.to_owned();
+        self.blocking(move |local| local.get_range(&key, offset, len)).await
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>, metadata: &Metadata) -> io::Result<()> {
+        let (key, metadata) = (key.to_owned(), metadata.clone());
+        self.blocking(move |local| local.put(&key, &data, &metadata)).await
+    }
+
+    async fn copy(&self, from: &str, to: &str, metadata: Option<&Metadata>) -> io::Result<()> {
+        let (from, to, metadata) = (from.to_owned(), to.to_owned(), metadata.cloned());
+        self.blocking(move |local| local.copy(&from, &to, metadata.as_ref())).await
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        let key = key.to_owned();
+        self.blocking(move |local| local.delete(&key)).await
+    }
+
+    async fn list(&self, prefix: &str, continuation: Option<&str>) -> io::Result<ListPage> {
+        let (prefix, continuation) = (prefix.to_owned(), continuation.map(str::to_owned));
+        self.blocking(move |local| local.list(&prefix, continuation.as_deref())).await
+    }
+
+    async fn create_multipart(&self, key: &str, metadata: &Metadata) -> io::Result<String> {
+        let (key, metadata) = (key.to_owned(), metadata.clone());
+        self.blocking(move |local| local.create_multipart(&key, &metadata)).await
+    }
+
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: u32,
+        data: Vec<u8>,
+    ) -> io::Result<String> {
+        let (key, upload_id) = (key.to_owned(), upload_id.to_owned());
+        self.blocking(move |local| local.upload_part(&key, &upload_id, part_number, &data)).await
+    }
+
+    async fn complete_multipart(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: &[CompletedPart],
+    ) -> io::Result<()> {
+        let (key, upload_id, parts) = (key.to_owned(), upload_id.to_owned(), parts.to_vec());
+        self.blocking(move |local| local.complete_multipart(&key, &upload_id, &parts)).await
+    }
+
+    async fn abort_multipart(&self, key: &str, upload_id: &str) -> io::Result<()> {
+        let (key, upload_id) = (key.to_owned(), upload_id.to_owned());
+        self.blocking(move |local| local.abort_multipart(&key, &upload_id)).await
+    }
+}
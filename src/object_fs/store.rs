@@ -0,0 +1,97 @@
+//! The interface [`ObjectFS`](super::ObjectFS) needs from an object store.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+
+/// User metadata of an object, as sent in `x-amz-meta-*` headers
+pub type Metadata = BTreeMap<String, String>;
+
+/// What a HEAD request returns for an object
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectMeta {
+    pub size: u64,
+    pub modified: SystemTime,
+    pub metadata: Metadata,
+}
+
+/// An object as listed; listings do not carry user metadata
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectSummary {
+    pub key: String,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+/// One page of a listing, in key order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ListPage {
+    /// Objects directly under the prefix
+    pub objects: Vec<ObjectSummary>,
+    /// Keys under the prefix cut after the next `/`, each ending in `/`
+    pub prefixes: Vec<String>,
+    /// Token for the next page, if the listing is truncated
+    pub next: Option<String>,
+}
+
+/// A part of a multipart upload, numbered from 1, and the ETag returned for it
+pub type CompletedPart = (u32, String);
+
+/// The subset of the S3 API that [`ObjectFS`](super::ObjectFS) uses.
+///
+/// Implement this over an S3 client to serve a bucket; [`LocalObjectStore`] implements it
+/// on a local directory. Keys are UTF-8 and use `/` to separate directories. Errors of
+/// kind [`io::ErrorKind::NotFound`] mean the object does not exist.
+///
+/// [`LocalObjectStore`]: super::LocalObjectStore
+#[async_trait]
+pub trait ObjectStore: Send + Sync {
+    /// Returns the size, modification time and metadata of an object, or `None` if it
+    /// does not exist (HeadObject).
+    async fn head(&self, key: &str) -> io::Result<Option<ObjectMeta>>;
+
+    /// Reads up to `len` bytes at `offset`; the result is short at the end of the object
+    /// (GetObject with a Range header).
+    async fn get_range(&self, key: &str, offset: u64, len: u64) -> io::Result<Vec<u8>>;
+
+    /// Creates or replaces an object (PutObject).
+    async fn put(&self, key: &str, data: Vec<u8>, metadata: &Metadata) -> io::Result<()>;
+
+    /// Copies an object, replacing its metadata if `metadata` is given (CopyObject).
+    /// Copying an object onto itself with new metadata is how metadata changes.
+    async fn copy(&self, from: &str, to: &str, metadata: Option<&Metadata>) -> io::Result<()>;
+
+    /// Deletes an object; deleting a missing object succeeds (DeleteObject).
+    async fn delete(&self, key: &str) -> io::Result<()>;
+
+    /// Lists the keys starting with `prefix`, grouping those with a further `/` into
+    /// prefixes (ListObjectsV2 with delimiter `/`). Pass the previous page's `next` as
+    /// `continuation` for the following page.
+    async fn list(&self, prefix: &str, continuation: Option<&str>) -> io::Result<ListPage>;
+
+    /// Starts a multipart upload and returns its ID (CreateMultipartUpload).
+    async fn create_multipart(&self, key: &str, metadata: &Metadata) -> io::Result<String>;
+
+    /// Uploads one part and returns its ETag (UploadPart). Uploading a part number again
+    /// replaces it.
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: u32,
+        data: Vec<u8>,
+    ) -> io::Result<String>;
+
+    /// Assembles the parts, in order, into the object (CompleteMultipartUpload).
+    async fn complete_multipart(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: &[CompletedPart],
+    ) -> io::Result<()>;
+
+    /// Discards a multipart upload and its parts (AbortMultipartUpload).
+    async fn abort_multipart(&self, key: &str, upload_id: &str) -> io::Result<()>;
+}
@@ -0,0 +1,219 @@
+//! Writes to one object, buffered and sent as a multipart upload.
+//!
+//! Objects cannot be modified in place, so writes to a file collect in an [`Upload`]
+//! that replaces the whole object when it is finished. The file is divided into parts
+//! of a fixed size; a part is kept in memory while it is being written and uploaded as
+//! soon as it is full, so sequential writes of a large file hold at most a part or two
+//! per file. Parts that were never written are copied from the old object with ranged
+//! GETs when the upload finishes.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::ops::RangeInclusive;
+use std::time::SystemTime;
+
+use super::store::{Metadata, ObjectMeta, ObjectStore};
+
+/// Pending writes to one object
+#[derive(Debug)]
+pub struct Upload {
+    key: String,
+    part_size: u64,
+    /// Size of the object the writes apply to
+    base_size: u64,
+    pub size: u64,
+    pub modified: SystemTime,
+    pub metadata: Metadata,
+    upload_id: Option<String>,
+    /// ETags of the parts already uploaded, by part index
+    uploaded: BTreeMap<u64, String>,
+    /// Parts being written, each from the start of its part and possibly shorter
+    pending: BTreeMap<u64, Vec<u8>>,
+}
+
+impl Upload {
+    pub fn new(key: String, part_size: u64, base: ObjectMeta) -> Self {
+        Self {
+            key,
+            part_size,
+            base_size: base.size,
+            size: base.size,
+            modified: base.modified,
+            metadata: base.metadata,
+            upload_id: None,
+            uploaded: BTreeMap::new(),
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// Indices of the parts holding `len > 0` bytes at `offset`.
+    fn parts(&self, offset: u64, len: u64) -> RangeInclusive<u64> {
+        offset / self.part_size..=(offset + len - 1) / self.part_size
+    }
+
+    /// Returns true if the range lies in parts that were already uploaded, which can
+    /// neither be read back nor changed until the upload is finished.
+    pub fn touches_uploaded(&self, offset: u64, len: u64) -> bool {
+        len > 0 && self.uploaded.range(self.parts(offset, len)).next().is_some()
+    }
+
+    /// Appends bytes `from..to` of the file, all within part `index`, to `out`.
+    async fn read_part(
+        &self,
+        store: &dyn ObjectStore,
+        index: u64,
+        from: u64,
+        to: u64,
+        out: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        let start = out.len();
+        match self.pending.get(&index) {
+            Some(part) => {
+                let part_start = index * self.part_size;
+                let available = (part.len() as u64).min(to - part_start);
+                if from - part_start < available {
+                    out.extend_from_slice(&part[(from - part_start) as usize..available as usize]);
+                }
+            }
+            None if from < self.base_size => {
+                let len = to.min(self.base_size) - from;
+                out.extend(store.get_range(&self.key, from, len).await?);
+            }
+            None => {}
+        }
+        // Whatever neither the writes nor the old object cover reads as zeros.
+        out.resize(start + (to - from) as usize, 0);
+        Ok(())
+    }
+
+    /// Reads the file as written; the range must not touch uploaded parts.
+    pub async fn read(
+        &self,
+        store: &dyn ObjectStore,
+        offset: u64,
+        len: u64,
+    ) -> io::Result<Vec<u8>> {
+        let end = self.size.min(offset.saturating_add(len));
+        let mut out = Vec::new();
+        if offset >= end {
+            return Ok(out);
+        }
+        for index in self.parts(offset, end - offset) {
+            let part_start = index * self.part_size;
+            let from = offset.max(part_start);
+            let to = end.min(part_start + self.part_size);
+            self.read_part(store, index, from, to, &mut out).await?;
+        }
+        Ok(out)
+    }
+
+    /// Applies a write; the range must not touch uploaded parts.
+    pub async fn write(
+        &mut self,
+        store: &dyn ObjectStore,
+        offset: u64,
+        data: &[u8],
+    ) -> io::Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let end = offset + data.len() as u64;
+        for index in self.parts(offset, data.len() as u64) {
+            let part_start = index * self.part_size;
+            if !self.pending.contains_key(&index) {
+                let mut part = Vec::new();
+                let to = (part_start + self.part_size).min(self.base_size);
+                if part_start < to {
+                    self.read_part(store, index, part_start, to, &mut part).await?;
+                }
+                self.pending.insert(index, part);
+            }
+            let part = self.pending.get_mut(&index).unwrap();
+            let from = offset.max(part_start);
+            let to = end.min(part_start + self.part_size);
+            if (part.len() as u64) < to - part_start {
+                part.resize((to - part_start) as usize, 0);
+            }
+            part[(from - part_start) as usize..(to - part_start) as usize]
+                .copy_from_slice(&data[(from - offset) as usize..(to - offset) as usize]);
+        }
+        self.size = self.size.max(end);
+        self.modified = SystemTime::now();
+        for index in self.parts(offset, data.len() as u64) {
+            if self.pending[&index].len() as u64 == self.part_size {
+                // The part stays pending until it is uploaded, so a failed upload loses
+                // nothing.
+                self.upload_part(store, index, self.pending[&index].clone()).await?;
+                self.pending.remove(&index);
+            }
+        }
+        Ok(())
+    }
+
+    /// Changes the size of the file; no parts may have been uploaded yet.
+    pub fn truncate(&mut self, size: u64) {
+        let part_size = self.part_size;
+        self.pending.retain(|index, _| index * part_size < size);
+        if let Some(part) = self.pending.get_mut(&(size / part_size)) {
+            part.truncate((size % part_size) as usize);
+        }
+        self.base_size = self.base_size.min(size);
+        self.size = size;
+        self.modified = SystemTime::now();
+    }
+
+    async fn upload_part(
+        &mut self,
+        store: &dyn ObjectStore,
+        index: u64,
+        data: Vec<u8>,
+    ) -> io::Result<()> {
+        let upload_id = match &self.upload_id {
+            Some(upload_id) => upload_id.clone(),
+            None => {
+                let upload_id = store.create_multipart(&self.key, &self.metadata).await?;
+                self.upload_id.insert(upload_id).clone()
+            }
+        };
+        let number = u32::try_from(index + 1)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too many parts"))?;
+        let etag = store.upload_part(&self.key, &upload_id, number, data).await?;
+        self.uploaded.insert(index, etag);
+        Ok(())
+    }
+
+    async fn complete(&mut self, store: &dyn ObjectStore) -> io::Result<()> {
+        if self.upload_id.is_none() && self.size <= self.part_size {
+            let data = self.read(store, 0, self.size).await?;
+            return store.put(&self.key, data, &self.metadata).await;
+        }
+        for index in 0..self.size.div_ceil(self.part_size) {
+            if !self.uploaded.contains_key(&index) {
+                let start = index * self.part_size;
+                let data = self.read(store, start, self.part_size.min(self.size - start)).await?;
+                self.upload_part(store, index, data).await?;
+            }
+        }
+        let parts: Vec<_> =
+            self.uploaded.iter().map(|(index, etag)| (*index as u32 + 1, etag.clone())).collect();
+        let upload_id = self.upload_id.as_deref().unwrap_or_default();
+        store.complete_multipart(&self.key, upload_id, &parts).await
+    }
+
+    /// Replaces the object with the written contents.
+    ///
+    /// If that fails the writes are kept, so that finishing can be tried again or the
+    /// upload aborted.
+    pub async fn finish(&mut self, store: &dyn ObjectStore) -> io::Result<()> {
+        self.complete(store).await
+    }
+
+    /// Discards the writes, leaving the object as it was.
+    pub async fn abort(self, store: &dyn ObjectStore) {
+        if let Some(upload_id) = &self.upload_id {
+            if let Err(err) = store.abort_multipart(&self.key, upload_id).await {
+                tracing::debug!("cannot abort upload of {}: {}", self.key, err);
+            }
+        }
+    }
+}
@@ -51,6 +51,10 @@ fn export_subpath<'a>(requested: &'a [u8], export: &[u8]) -> Option<&'a [u8]> {
 /// Function returns file handle for the requested
 /// mount point and supported authentication flavors.
 ///
+/// Mounting a subdirectory of the export only picks the starting handle: clients can
+/// still walk up with `..` and use handles for directories above it. Export a
+/// [`SubtreeFS`](crate::subtree_fs::SubtreeFS) to confine clients to a directory.
+///
 /// TODO: Currently there is only one mount point, to support
 /// full functionality we need to extend support for multiple mount points.
 ///
@@ -168,6 +168,6 @@ impl CommandQueue {
     ) -> Result<(), anyhow::Error> {
         self.command_sender
             .send(RpcCommand { data, context })
-            .map_err(|e| anyhow!("Failed to send command: {}", e))
+            .map_err(|e| anyhow!("Failed to send command: {e}"))
     }
 }
@@ -31,7 +31,7 @@ mod wire;
 
 pub use context::Context;
 pub use transaction_tracker::{TransactionStatus, TransactionTracker};
-pub use wire::{write_fragment, SocketMessageHandler};
+pub use wire::{caller, discard_reply, with_caller, write_fragment, SocketMessageHandler};
 
 /// Linux-compatible max NFS block size (RPCSVC_MAXPAYLOAD).
 pub const MAX_BLOCK_SIZE: usize = 4 * 1024 * 1024;
@@ -17,6 +17,8 @@
 //! This module is essential for maintaining proper message boundaries in TCP
 //! while providing efficient transmission of RPC messages of any size.
 
+use std::cell::{Cell, RefCell};
+use std::future::Future;
 use std::io::Cursor;
 use std::io::{Read, Write};
 use std::sync::Arc;
@@ -48,6 +50,40 @@ const NFS_METADATA_PROGRAM: u32 = 200024;
 /// Initial size of RPC response buffer
 const DEFAULT_RESPONSE_BUFFER_CAPACITY: usize = 8192;
 
+tokio::task_local! {
+    /// Set by [`discard_reply`] while a call is being handled.
+    static DISCARD_REPLY: Cell<bool>;
+    /// Credentials of the call being handled, if it carries `AUTH_UNIX` credentials.
+    static CALLER: RefCell<Option<xdr::rpc::auth_unix>>;
+}
+
+/// Returns the `AUTH_UNIX` credentials of the RPC call currently being handled
+///
+/// `None` outside of a call and for calls made with another flavor, such as
+/// `AUTH_NONE`. Lets file systems act on behalf of the caller without every method of
+/// the VFS taking credentials.
+pub fn caller() -> Option<xdr::rpc::auth_unix> {
+    CALLER.try_with(|caller| caller.borrow().clone()).ok().flatten()
+}
+
+/// Runs `future` as if it were handling a call made with the credentials `auth`
+///
+/// For code that drives a file system directly, such as tests, and needs
+/// [`caller`] to report a particular user.
+pub async fn with_caller<F: Future>(auth: xdr::rpc::auth_unix, future: F) -> F::Output {
+    CALLER.scope(RefCell::new(Some(auth)), future).await
+}
+
+/// Drops the reply to the RPC call currently being handled
+///
+/// The call keeps its effects and its reply is still recorded for retransmission
+/// detection, so the client's retransmission is answered from the cache, as if the
+/// original reply had been lost on the network. Meant for fault injection in tests;
+/// has no effect when called outside of a call.
+pub fn discard_reply() {
+    let _ = DISCARD_REPLY.try_with(|discard| discard.set(true));
+}
+
 /// Outcome of handling an RPC message.
 enum RpcOutcome {
     /// A response was written to the output buffer.
@@ -80,6 +116,7 @@ async fn handle_rpc(
     if let xdr::rpc::rpc_body::CALL(call) = recv.body {
         if let xdr::rpc::auth_flavor::AUTH_UNIX = call.cred.flavor {
             context.auth = deserialize(&mut Cursor::new(&call.cred.body))?;
+            let _ = CALLER.try_with(|caller| *caller.borrow_mut() = Some(context.auth.clone()));
         }
         let status = context.transaction_tracker.check(xid, &context.client_addr);
         match status {
@@ -332,7 +369,7 @@ impl SocketMessageHandler {
             // Submit command to queue for ordered processing
             if let Err(e) = self.command_queue.submit_command(fragment_data, context) {
                 error!("Failed to submit command to queue: {:?}", e);
-                return Err(anyhow::anyhow!("Command queue error: {}", e));
+                return Err(anyhow::anyhow!("Command queue error: {e}"));
             }
         }
         Ok(())
@@ -376,14 +413,18 @@ pub fn process_rpc_command<'a>(
         let tracker = context.transaction_tracker.clone();
         let client_addr = context.client_addr.clone();
         // Call RPC handler
-        let result = handle_rpc(&mut input_cursor, &mut output_cursor, context).await?;
+        let call = DISCARD_REPLY.scope(Cell::new(false), async {
+            let result = handle_rpc(&mut input_cursor, &mut output_cursor, context).await;
+            (result, DISCARD_REPLY.with(Cell::get))
+        });
+        let (result, discarded) = CALLER.scope(RefCell::new(None), call).await;
 
-        match result {
+        match result? {
             RpcOutcome::Send { xid, record_response } => {
                 if record_response && !output_buffer.is_empty() {
                     tracker.record_response(xid, &client_addr, Arc::new(output_buffer.clone()));
                 }
-                Ok(true)
+                Ok(!discarded)
             }
             RpcOutcome::Drop => Ok(false),
         }
@@ -0,0 +1,439 @@
+//! A view of a single directory of another file system.
+//!
+//! [`SubtreeFS`] exports one directory of a larger file system as if it were the root,
+//! like a chroot. Clients cannot reach anything above it:
+//! - `root_dir()` is the chosen directory, and `..` in it resolves to itself, both in
+//!   LOOKUP and in READDIR.
+//! - Only fileids that were handed out through the view are accepted. The view tracks
+//!   the parent of every object it has returned, so a handle naming a directory above
+//!   the subtree, or anything else outside it, is rejected as stale even though it is
+//!   a valid fileid of the wrapped file system.
+//!
+//! ```ignore
+//! use std::sync::Arc;
+//! use fernfs::subtree_fs::SubtreeFS;
+//!
+//! let fs = SubtreeFS::open(Arc::new(MirrorFS::new("/srv".into())), b"projects/foo").await?;
+//! ```
+//!
+//! This differs from mounting a subpath through MOUNT, which returns a handle for the
+//! subdirectory but leaves the rest of the export reachable through `..` and handles
+//! for parent directories.
+//!
+//! Objects that are moved out of the subtree by another client of the wrapped file
+//! system, rather than through the view, stay reachable through handles the view has
+//! already issued.
+//!
+//! The view remembers the parents of the 2^20 or so objects used most recently (see
+//! [`SubtreeFS::max_tracked`]). A handle for an object it has forgotten is stale until
+//! the object is looked up or listed again.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::mem;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+
+use crate::protocol::xdr::rpc::auth_unix;
+use crate::vfs::{self, Capabilities, NFSFileSystem};
+use crate::xdr::nfs3;
+
+type NFSResult<T> = Result<T, nfs3::nfsstat3>;
+
+/// Default number of objects whose parents are remembered
+const MAX_TRACKED: usize = 1 << 20;
+
+/// The parents of recently used objects, in two generations
+///
+/// New and used entries go into the recent generation. Once it holds half of the
+/// limit, it becomes the older generation and the previous older one is dropped, so
+/// entries unused for that long are forgotten.
+struct Parents {
+    root: nfs3::fileid3,
+    recent: HashMap<nfs3::fileid3, nfs3::fileid3>,
+    older: HashMap<nfs3::fileid3, nfs3::fileid3>,
+    generation_size: usize,
+}
+
+impl Parents {
+    fn new(root: nfs3::fileid3, limit: usize) -> Self {
+        Self {
+            root,
+            recent: HashMap::new(),
+            older: HashMap::new(),
+            generation_size: (limit / 2).max(1),
+        }
+    }
+
+    /// Returns the parent of `id`, marking it as used; the root is its own parent.
+    fn get(&mut self, id: nfs3::fileid3) -> Option<nfs3::fileid3> {
+        if id == self.root {
+            return Some(id);
+        }
+        if let Some(parent) = self.recent.get(&id) {
+            return Some(*parent);
+        }
+        let parent = self.older.remove(&id)?;
+        self.insert(id, parent);
+        Some(parent)
+    }
+
+    fn insert(&mut self, id: nfs3::fileid3, parent: nfs3::fileid3) {
+        if id == self.root {
+            return;
+        }
+        self.older.remove(&id);
+        self.recent.insert(id, parent);
+        if self.recent.len() >= self.generation_size {
+            self.older = mem::take(&mut self.recent);
+        }
+    }
+
+    fn remove(&mut self, id: nfs3::fileid3) {
+        self.recent.remove(&id);
+        self.older.remove(&id);
+    }
+}
+
+/// A file system restricted to one of its directories
+///
+/// See the [module documentation](self) for the semantics.
+pub struct SubtreeFS {
+    inner: Arc<dyn NFSFileSystem + Send + Sync>,
+    root: nfs3::fileid3,
+    /// The parents of objects handed out through the view
+    parents: Mutex<Parents>,
+    generation: u64,
+}
+
+impl SubtreeFS {
+    /// Creates a view of the directory at `path` in `inner`
+    ///
+    /// `path` is '/'-separated and relative to the root of `inner`. Fails with
+    /// `NFS3ERR_NOTDIR` if it does not name a directory.
+    pub async fn open(
+        inner: Arc<dyn NFSFileSystem + Send + Sync>,
+        path: impl AsRef<[u8]>,
+    ) -> NFSResult<Self> {
+        let root = inner.path_to_id(path.as_ref()).await?;
+        if !matches!(inner.getattr(root).await?.ftype, nfs3::ftype3::NF3DIR) {
+            return Err(nfs3::nfsstat3::NFS3ERR_NOTDIR);
+        }
+        let since_epoch = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
+        Ok(Self {
+            inner,
+            root,
+            parents: Mutex::new(Parents::new(root, MAX_TRACKED)),
+            generation: since_epoch.as_millis() as u64,
+        })
+    }
+
+    /// Remembers the parents of about `limit` objects instead of 2^20
+    pub fn max_tracked(self, limit: usize) -> Self {
+        Self { parents: Mutex::new(Parents::new(self.root, limit)), ..self }
+    }
+
+    /// Returns the wrapped file system
+    pub fn inner(&self) -> &Arc<dyn NFSFileSystem + Send + Sync> {
+        &self.inner
+    }
+
+    fn parents(&self) -> MutexGuard<'_, Parents> {
+        self.parents.lock().unwrap()
+    }
+
+    /// Rejects ids that were not handed out through the view, or have been forgotten.
+    fn check(&self, id: nfs3::fileid3) -> NFSResult<()> {
+        if self.parents().get(id).is_none() {
+            return Err(nfs3::nfsstat3::NFS3ERR_STALE);
+        }
+        Ok(())
+    }
+
+    /// Records that `id` was found in `dirid` and may be handed out.
+    fn record(&self, dirid: nfs3::fileid3, id: nfs3::fileid3) -> nfs3::fileid3 {
+        self.parents().insert(id, dirid);
+        id
+    }
+
+    /// Forgets `id` if it no longer exists.
+    async fn forget_if_gone(&self, id: nfs3::fileid3) {
+        if self.inner.getattr(id).await.is_err() {
+            self.parents().remove(id);
+        }
+    }
+
+    fn recorded(
+        &self,
+        dirid: nfs3::fileid3,
+        (id, attr): (nfs3::fileid3, nfs3::fattr3),
+    ) -> (nfs3::fileid3, nfs3::fattr3) {
+        (self.record(dirid, id), attr)
+    }
+
+    /// Records the entries of a READDIR reply and hides what lies above the root.
+    async fn fix_entries(
+        &self,
+        dirid: nfs3::fileid3,
+        mut result: vfs::ReadDirResult,
+    ) -> NFSResult<vfs::ReadDirResult> {
+        for entry in &mut result.entries {
+            match &entry.name[..] {
+                b"." => {}
+                b".." if dirid == self.root => {
+                    entry.fileid = self.root;
+                    entry.attr = self.inner.getattr(self.root).await?;
+                }
+                b".." => {}
+                _ => {
+                    self.record(dirid, entry.fileid);
+                }
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[async_trait]
+impl NFSFileSystem for SubtreeFS {
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn server_id(&self) -> nfs3::cookieverf3 {
+        self.inner.server_id()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn root_dir(&self) -> nfs3::fileid3 {
+        self.root
+    }
+
+    async fn lookup(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> NFSResult<nfs3::fileid3> {
+        self.check(dirid)?;
+        match &filename[..] {
+            b"." => Ok(dirid),
+            // Parents come from the view's own records, so ".." never leaves the subtree,
+            // even for a directory that was moved out of it behind the view's back.
+            b".." => self.parents().get(dirid).ok_or(nfs3::nfsstat3::NFS3ERR_STALE),
+            _ => Ok(self.record(dirid, self.inner.lookup(dirid, filename).await?)),
+        }
+    }
+
+    async fn getattr(&self, id: nfs3::fileid3) -> NFSResult<nfs3::fattr3> {
+        self.check(id)?;
+        self.inner.getattr(id).await
+    }
+
+    async fn setattr(&self, id: nfs3::fileid3, setattr: nfs3::sattr3) -> NFSResult<nfs3::fattr3> {
+        self.check(id)?;
+        self.inner.setattr(id, setattr).await
+    }
+
+    async fn read(&self, id: nfs3::fileid3, offset: u64, count: u32) -> NFSResult<(Vec<u8>, bool)> {
+        self.check(id)?;
+        self.inner.read(id, offset, count).await
+    }
+
+    async fn write(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+        stable: nfs3::file::stable_how,
+    ) -> NFSResult<(nfs3::fattr3, nfs3::file::stable_how, nfs3::count3)> {
+        self.check(id)?;
+        self.inner.write(id, offset, data, stable).await
+    }
+
+    async fn create(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        attr: nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        self.check(dirid)?;
+        Ok(self.recorded(dirid, self.inner.create(dirid, filename, attr).await?))
+    }
+
+    async fn create_exclusive(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        verifier: nfs3::createverf3,
+    ) -> NFSResult<nfs3::fileid3> {
+        self.check(dirid)?;
+        Ok(self.record(dirid, self.inner.create_exclusive(dirid, filename, verifier).await?))
+    }
+
+    async fn mkdir(
+        &self,
+        dirid: nfs3::fileid3,
+        dirname: &nfs3::filename3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        self.check(dirid)?;
+        Ok(self.recorded(dirid, self.inner.mkdir(dirid, dirname).await?))
+    }
+
+    async fn remove(&self, dirid: nfs3::fileid3, filename: &nfs3::filename3) -> NFSResult<()> {
+        self.check(dirid)?;
+        let id = self.inner.lookup(dirid, filename).await.ok();
+        self.inner.remove(dirid, filename).await?;
+        // Forget the object once its last link is gone.
+        if let Some(id) = id {
+            self.forget_if_gone(id).await;
+        }
+        Ok(())
+    }
+
+    async fn rename(
+        &self,
+        from_dirid: nfs3::fileid3,
+        from_filename: &nfs3::filename3,
+        to_dirid: nfs3::fileid3,
+        to_filename: &nfs3::filename3,
+    ) -> NFSResult<()> {
+        self.check(from_dirid)?;
+        self.check(to_dirid)?;
+        let replaced = self.inner.lookup(to_dirid, to_filename).await.ok();
+        self.inner.rename(from_dirid, from_filename, to_dirid, to_filename).await?;
+        let renamed = self.inner.lookup(to_dirid, to_filename).await.ok();
+        if let Some(id) = renamed {
+            self.record(to_dirid, id);
+        }
+        if let Some(id) = replaced.filter(|id| Some(*id) != renamed) {
+            self.forget_if_gone(id).await;
+        }
+        Ok(())
+    }
+
+    async fn readdir(
+        &self,
+        dirid: nfs3::fileid3,
+        start_after: nfs3::fileid3,
+        max_entries: usize,
+    ) -> NFSResult<vfs::ReadDirResult> {
+        self.check(dirid)?;
+        let result = self.inner.readdir(dirid, start_after, max_entries).await?;
+        self.fix_entries(dirid, result).await
+    }
+
+    async fn readdir_index(
+        &self,
+        dirid: nfs3::fileid3,
+        start_index: usize,
+        max_entries: usize,
+    ) -> NFSResult<vfs::ReadDirResult> {
+        self.check(dirid)?;
+        let result = self.inner.readdir_index(dirid, start_index, max_entries).await?;
+        self.fix_entries(dirid, result).await
+    }
+
+    async fn symlink(
+        &self,
+        dirid: nfs3::fileid3,
+        linkname: &nfs3::filename3,
+        symlink: &nfs3::nfspath3,
+        attr: &nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        self.check(dirid)?;
+        Ok(self.recorded(dirid, self.inner.symlink(dirid, linkname, symlink, attr).await?))
+    }
+
+    async fn readlink(&self, id: nfs3::fileid3) -> NFSResult<nfs3::nfspath3> {
+        self.check(id)?;
+        self.inner.readlink(id).await
+    }
+
+    async fn link(
+        &self,
+        file_id: nfs3::fileid3,
+        link_dir_id: nfs3::fileid3,
+        link_name: &nfs3::filename3,
+    ) -> NFSResult<nfs3::fattr3> {
+        self.check(file_id)?;
+        self.check(link_dir_id)?;
+        self.inner.link(file_id, link_dir_id, link_name).await
+    }
+
+    async fn mknod(
+        &self,
+        dir_id: nfs3::fileid3,
+        name: &nfs3::filename3,
+        ftype: nfs3::ftype3,
+        specdata: nfs3::specdata3,
+        attrs: &nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        self.check(dir_id)?;
+        Ok(self.recorded(dir_id, self.inner.mknod(dir_id, name, ftype, specdata, attrs).await?))
+    }
+
+    async fn commit(
+        &self,
+        file_id: nfs3::fileid3,
+        offset: u64,
+        count: u32,
+    ) -> NFSResult<nfs3::fattr3> {
+        self.check(file_id)?;
+        self.inner.commit(file_id, offset, count).await
+    }
+
+    async fn check_access(
+        &self,
+        id: nfs3::fileid3,
+        auth: &auth_unix,
+        access: u32,
+    ) -> NFSResult<u32> {
+        self.check(id)?;
+        self.inner.check_access(id, auth, access).await
+    }
+
+    async fn fsinfo(&self, root_fileid: nfs3::fileid3) -> NFSResult<nfs3::fs::fsinfo3> {
+        self.check(root_fileid)?;
+        self.inner.fsinfo(root_fileid).await
+    }
+
+    async fn fsstat(&self, root_fileid: nfs3::fileid3) -> NFSResult<nfs3::fs::FSSTAT3resok> {
+        self.check(root_fileid)?;
+        self.inner.fsstat(root_fileid).await
+    }
+
+    fn fsinfo_maxfilesize(&self) -> nfs3::size3 {
+        self.inner.fsinfo_maxfilesize()
+    }
+
+    fn fsinfo_properties(&self) -> u32 {
+        self.inner.fsinfo_properties()
+    }
+
+    fn pathconf_linkmax(&self) -> u32 {
+        self.inner.pathconf_linkmax()
+    }
+
+    fn pathconf_name_max(&self) -> u32 {
+        self.inner.pathconf_name_max()
+    }
+
+    fn fh_to_id(&self, id: &nfs3::nfs_fh3) -> NFSResult<nfs3::fileid3> {
+        if id.data.len() != 16 {
+            return Err(nfs3::nfsstat3::NFS3ERR_BADHANDLE);
+        }
+        let gen = u64::from_le_bytes(id.data[0..8].try_into().unwrap());
+        let fileid = u64::from_le_bytes(id.data[8..16].try_into().unwrap());
+        match gen.cmp(&self.generation) {
+            Ordering::Less => Err(nfs3::nfsstat3::NFS3ERR_STALE),
+            Ordering::Greater => Err(nfs3::nfsstat3::NFS3ERR_BADHANDLE),
+            // Handles for objects outside the subtree are well formed but never issued.
+            Ordering::Equal => self.check(fileid).map(|()| fileid),
+        }
+    }
+}
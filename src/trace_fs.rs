@@ -0,0 +1,437 @@
+//! Recording and replaying the calls made to a file system.
+//!
+//! [`TraceFS`] wraps an `NFSFileSystem` and appends every call it passes through, with
+//! its arguments and result, to a trace file. Wrapping the file system given to the
+//! server captures the traffic of real clients as the NFSv3 handlers see it. A
+//! [`Replayer`] later drives the same calls against another file system and reports
+//! every result that differs:
+//!
+//! ```ignore
+//! use std::fs::File;
+//! use std::io::BufReader;
+//! use std::sync::Arc;
+//! use fernfs::trace_fs::{Replayer, TraceFS, TraceReader};
+//!
+//! // On the customer's server:
+//! let fs = TraceFS::new(Arc::new(MirrorFS::new("/srv".into())), File::create("calls.trace")?)?;
+//!
+//! // Later, against a fresh copy of the data:
+//! let trace = TraceReader::new(BufReader::new(File::open("calls.trace")?))?;
+//! let report = Replayer::new(Arc::new(MirrorFS::new("/tmp/copy".into()))).replay(trace).await?;
+//! for mismatch in &report.mismatches {
+//!     println!("{mismatch}");
+//! }
+//! ```
+//!
+//! Records are queued when a call completes and written by a thread of their own, so
+//! tracing never blocks the runtime on the trace file. The thread flushes whenever the
+//! queue runs dry, before a COMMIT returns, and when the `TraceFS` is dropped; a crash
+//! of the server loses at most the records still queued. A replay only reproduces the original results if
+//! the file system under test starts with the same contents the traced one had when
+//! recording began.
+
+mod format;
+mod replay;
+
+use std::io::{BufWriter, Write};
+use std::mem;
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+use async_trait::async_trait;
+use tracing::warn;
+
+use crate::protocol::xdr::rpc::auth_unix;
+use crate::vfs::{self, Capabilities, NFSFileSystem};
+use crate::xdr::nfs3;
+
+pub use format::{Call, Record, Reply, TraceReader, TraceWriter};
+pub use replay::{Mismatch, ReplayReport, Replayer};
+
+type NFSResult<T> = Result<T, nfs3::nfsstat3>;
+type Trace = TraceWriter<BufWriter<Box<dyn Write + Send>>>;
+
+/// Work for the thread writing the trace
+enum Message {
+    Record(Box<Record>),
+    /// Flush the records before, then report back
+    Flush(tokio::sync::oneshot::Sender<()>),
+}
+
+/// A file system that records the calls made to another
+///
+/// See the [module documentation](self) for an overview.
+pub struct TraceFS {
+    inner: Arc<dyn NFSFileSystem + Send + Sync>,
+    messages: mpsc::Sender<Message>,
+    writer: Option<thread::JoinHandle<()>>,
+}
+
+impl TraceFS {
+    /// Wraps `inner`, writing the trace to `dest`
+    ///
+    /// Fails if the trace header cannot be written. Later write errors are logged and
+    /// do not affect the calls being traced.
+    pub fn new(
+        inner: Arc<dyn NFSFileSystem + Send + Sync>,
+        dest: impl Write + Send + 'static,
+    ) -> std::io::Result<Self> {
+        let dest: Box<dyn Write + Send> = Box::new(dest);
+        let mut trace = TraceWriter::new(BufWriter::new(dest), inner.root_dir())?;
+        trace.flush()?;
+        let (messages, queue) = mpsc::channel();
+        let writer = thread::Builder::new()
+            .name("trace-writer".into())
+            .spawn(|| write_trace(trace, queue))?;
+        Ok(Self { inner, messages, writer: Some(writer) })
+    }
+
+    /// Returns the wrapped file system
+    pub fn inner(&self) -> &Arc<dyn NFSFileSystem + Send + Sync> {
+        &self.inner
+    }
+
+    /// Appends a call and its result to the trace.
+    fn record<T>(&self, call: Call, result: &NFSResult<T>, reply: impl FnOnce(&T) -> Reply) {
+        let record = Record { call, result: result.as_ref().map(reply).map_err(|status| *status) };
+        // The writer only stops once the sender is dropped.
+        let _ = self.messages.send(Message::Record(Box::new(record)));
+    }
+
+    /// Waits until the records so far have been written and flushed.
+    async fn flush(&self) {
+        let (done, flushed) = tokio::sync::oneshot::channel();
+        if self.messages.send(Message::Flush(done)).is_ok() {
+            let _ = flushed.await;
+        }
+    }
+}
+
+impl Drop for TraceFS {
+    fn drop(&mut self) {
+        // Closing the queue lets the writer flush the last records and stop.
+        drop(mem::replace(&mut self.messages, mpsc::channel().0));
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+/// Writes queued records to `trace` until the queue is closed.
+fn write_trace(mut trace: Trace, queue: mpsc::Receiver<Message>) {
+    let flush = |trace: &mut Trace| {
+        if let Err(err) = trace.flush() {
+            warn!("failed to flush the trace: {}", err);
+        }
+    };
+    loop {
+        let message = match queue.try_recv() {
+            Ok(message) => message,
+            Err(mpsc::TryRecvError::Empty) => {
+                flush(&mut trace);
+                match queue.recv() {
+                    Ok(message) => message,
+                    Err(mpsc::RecvError) => break,
+                }
+            }
+            Err(mpsc::TryRecvError::Disconnected) => break,
+        };
+        match message {
+            Message::Record(record) => {
+                if let Err(err) = trace.write(&record) {
+                    warn!("failed to record {:?}: {}", record.call, err);
+                }
+            }
+            Message::Flush(done) => {
+                flush(&mut trace);
+                let _ = done.send(());
+            }
+        }
+    }
+    flush(&mut trace);
+}
+
+fn copy_entries(result: &vfs::ReadDirResult) -> vfs::ReadDirResult {
+    let entries = result
+        .entries
+        .iter()
+        .map(|entry| vfs::DirEntry {
+            fileid: entry.fileid,
+            name: entry.name.clone(),
+            attr: entry.attr,
+        })
+        .collect();
+    vfs::ReadDirResult { entries, end: result.end }
+}
+
+fn copy_fsinfo(info: &nfs3::fs::fsinfo3) -> nfs3::fs::fsinfo3 {
+    nfs3::fs::fsinfo3 { ..*info }
+}
+
+fn copy_fsstat(stat: &nfs3::fs::FSSTAT3resok) -> nfs3::fs::FSSTAT3resok {
+    nfs3::fs::FSSTAT3resok { ..*stat }
+}
+
+#[async_trait]
+impl NFSFileSystem for TraceFS {
+    fn generation(&self) -> u64 {
+        self.inner.generation()
+    }
+
+    fn server_id(&self) -> nfs3::cookieverf3 {
+        self.inner.server_id()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn root_dir(&self) -> nfs3::fileid3 {
+        self.inner.root_dir()
+    }
+
+    async fn lookup(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> NFSResult<nfs3::fileid3> {
+        let result = self.inner.lookup(dirid, filename).await;
+        self.record(Call::Lookup { dirid, name: filename.clone() }, &result, |id| Reply::Id(*id));
+        result
+    }
+
+    async fn getattr(&self, id: nfs3::fileid3) -> NFSResult<nfs3::fattr3> {
+        let result = self.inner.getattr(id).await;
+        self.record(Call::Getattr { id }, &result, |attr| Reply::Attr(*attr));
+        result
+    }
+
+    async fn setattr(&self, id: nfs3::fileid3, setattr: nfs3::sattr3) -> NFSResult<nfs3::fattr3> {
+        let result = self.inner.setattr(id, setattr).await;
+        self.record(Call::Setattr { id, attr: setattr }, &result, |attr| Reply::Attr(*attr));
+        result
+    }
+
+    async fn read(&self, id: nfs3::fileid3, offset: u64, count: u32) -> NFSResult<(Vec<u8>, bool)> {
+        let result = self.inner.read(id, offset, count).await;
+        self.record(Call::Read { id, offset, count }, &result, |(data, eof)| Reply::Read {
+            data: data.clone(),
+            eof: *eof,
+        });
+        result
+    }
+
+    async fn write(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+        stable: nfs3::file::stable_how,
+    ) -> NFSResult<(nfs3::fattr3, nfs3::file::stable_how, nfs3::count3)> {
+        let result = self.inner.write(id, offset, data, stable).await;
+        let call = Call::Write { id, offset, data: data.to_vec(), stable };
+        self.record(call, &result, |&(attr, committed, count)| Reply::Write {
+            attr,
+            committed,
+            count,
+        });
+        result
+    }
+
+    async fn create(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        attr: nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        let result = self.inner.create(dirid, filename, attr).await;
+        let call = Call::Create { dirid, name: filename.clone(), attr };
+        self.record(call, &result, |&(id, attr)| Reply::Created(id, attr));
+        result
+    }
+
+    async fn create_exclusive(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        verifier: nfs3::createverf3,
+    ) -> NFSResult<nfs3::fileid3> {
+        let result = self.inner.create_exclusive(dirid, filename, verifier).await;
+        let call = Call::CreateExclusive { dirid, name: filename.clone(), verifier };
+        self.record(call, &result, |id| Reply::Id(*id));
+        result
+    }
+
+    async fn mkdir(
+        &self,
+        dirid: nfs3::fileid3,
+        dirname: &nfs3::filename3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        let result = self.inner.mkdir(dirid, dirname).await;
+        let call = Call::Mkdir { dirid, name: dirname.clone() };
+        self.record(call, &result, |&(id, attr)| Reply::Created(id, attr));
+        result
+    }
+
+    async fn remove(&self, dirid: nfs3::fileid3, filename: &nfs3::filename3) -> NFSResult<()> {
+        let result = self.inner.remove(dirid, filename).await;
+        self.record(Call::Remove { dirid, name: filename.clone() }, &result, |()| Reply::Done);
+        result
+    }
+
+    async fn rename(
+        &self,
+        from_dirid: nfs3::fileid3,
+        from_filename: &nfs3::filename3,
+        to_dirid: nfs3::fileid3,
+        to_filename: &nfs3::filename3,
+    ) -> NFSResult<()> {
+        let result = self.inner.rename(from_dirid, from_filename, to_dirid, to_filename).await;
+        let call = Call::Rename {
+            from_dirid,
+            from_name: from_filename.clone(),
+            to_dirid,
+            to_name: to_filename.clone(),
+        };
+        self.record(call, &result, |()| Reply::Done);
+        result
+    }
+
+    async fn readdir(
+        &self,
+        dirid: nfs3::fileid3,
+        start_after: nfs3::fileid3,
+        max_entries: usize,
+    ) -> NFSResult<vfs::ReadDirResult> {
+        let result = self.inner.readdir(dirid, start_after, max_entries).await;
+        let call = Call::Readdir { dirid, start_after, max_entries: max_entries as u64 };
+        self.record(call, &result, |result| Reply::Readdir(copy_entries(result)));
+        result
+    }
+
+    async fn readdir_index(
+        &self,
+        dirid: nfs3::fileid3,
+        start_index: usize,
+        max_entries: usize,
+    ) -> NFSResult<vfs::ReadDirResult> {
+        let result = self.inner.readdir_index(dirid, start_index, max_entries).await;
+        let call = Call::ReaddirIndex {
+            dirid,
+            start_index: start_index as u64,
+            max_entries: max_entries as u64,
+        };
+        self.record(call, &result, |result| Reply::Readdir(copy_entries(result)));
+        result
+    }
+
+    async fn symlink(
+        &self,
+        dirid: nfs3::fileid3,
+        linkname: &nfs3::filename3,
+        symlink: &nfs3::nfspath3,
+        attr: &nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        let result = self.inner.symlink(dirid, linkname, symlink, attr).await;
+        let call =
+            Call::Symlink { dirid, name: linkname.clone(), target: symlink.clone(), attr: *attr };
+        self.record(call, &result, |&(id, attr)| Reply::Created(id, attr));
+        result
+    }
+
+    async fn readlink(&self, id: nfs3::fileid3) -> NFSResult<nfs3::nfspath3> {
+        let result = self.inner.readlink(id).await;
+        self.record(Call::Readlink { id }, &result, |path| Reply::Readlink(path.clone()));
+        result
+    }
+
+    async fn link(
+        &self,
+        file_id: nfs3::fileid3,
+        link_dir_id: nfs3::fileid3,
+        link_name: &nfs3::filename3,
+    ) -> NFSResult<nfs3::fattr3> {
+        let result = self.inner.link(file_id, link_dir_id, link_name).await;
+        let call = Call::Link { id: file_id, dirid: link_dir_id, name: link_name.clone() };
+        self.record(call, &result, |attr| Reply::Attr(*attr));
+        result
+    }
+
+    async fn mknod(
+        &self,
+        dir_id: nfs3::fileid3,
+        name: &nfs3::filename3,
+        ftype: nfs3::ftype3,
+        specdata: nfs3::specdata3,
+        attrs: &nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        let result = self.inner.mknod(dir_id, name, ftype, specdata, attrs).await;
+        let call =
+            Call::Mknod { dirid: dir_id, name: name.clone(), ftype, spec: specdata, attr: *attrs };
+        self.record(call, &result, |&(id, attr)| Reply::Created(id, attr));
+        result
+    }
+
+    async fn commit(
+        &self,
+        file_id: nfs3::fileid3,
+        offset: u64,
+        count: u32,
+    ) -> NFSResult<nfs3::fattr3> {
+        let result = self.inner.commit(file_id, offset, count).await;
+        let call = Call::Commit { id: file_id, offset, count };
+        self.record(call, &result, |attr| Reply::Attr(*attr));
+        self.flush().await;
+        result
+    }
+
+    async fn check_access(
+        &self,
+        id: nfs3::fileid3,
+        auth: &auth_unix,
+        access: u32,
+    ) -> NFSResult<u32> {
+        let result = self.inner.check_access(id, auth, access).await;
+        let call = Call::Access { id, auth: auth.clone(), access };
+        self.record(call, &result, |granted| Reply::Access(*granted));
+        result
+    }
+
+    async fn fsinfo(&self, root_fileid: nfs3::fileid3) -> NFSResult<nfs3::fs::fsinfo3> {
+        let result = self.inner.fsinfo(root_fileid).await;
+        let call = Call::Fsinfo { id: root_fileid };
+        self.record(call, &result, |info| Reply::Fsinfo(copy_fsinfo(info)));
+        result
+    }
+
+    async fn fsstat(&self, root_fileid: nfs3::fileid3) -> NFSResult<nfs3::fs::FSSTAT3resok> {
+        let result = self.inner.fsstat(root_fileid).await;
+        let call = Call::Fsstat { id: root_fileid };
+        self.record(call, &result, |stat| Reply::Fsstat(copy_fsstat(stat)));
+        result
+    }
+
+    fn fsinfo_maxfilesize(&self) -> nfs3::size3 {
+        self.inner.fsinfo_maxfilesize()
+    }
+
+    fn fsinfo_properties(&self) -> u32 {
+        self.inner.fsinfo_properties()
+    }
+
+    fn pathconf_linkmax(&self) -> u32 {
+        self.inner.pathconf_linkmax()
+    }
+
+    fn pathconf_name_max(&self) -> u32 {
+        self.inner.pathconf_name_max()
+    }
+
+    fn id_to_fh(&self, id: nfs3::fileid3) -> nfs3::nfs_fh3 {
+        self.inner.id_to_fh(id)
+    }
+
+    fn fh_to_id(&self, id: &nfs3::nfs_fh3) -> NFSResult<nfs3::fileid3> {
+        self.inner.fh_to_id(id)
+    }
+}
@@ -0,0 +1,513 @@
+//! The trace file format.
+//!
+//! A trace starts with a header: the magic bytes `FERNTRC1` followed by the root fileid
+//! of the traced file system. Each call follows as a [`Record`], XDR encoded: the call
+//! with its arguments, then either `NFS3_OK` and the reply or the error status.
+
+use std::io::{self, Read, Write};
+
+use crate::protocol::xdr::rpc::auth_unix;
+use crate::vfs::{DirEntry, ReadDirResult};
+use crate::xdr::{self, nfs3, Deserialize, Serialize};
+
+const MAGIC: &[u8; 8] = b"FERNTRC1";
+
+/// A traced `NFSFileSystem` call and its arguments
+#[derive(Clone, Debug)]
+pub enum Call {
+    Lookup {
+        dirid: nfs3::fileid3,
+        name: nfs3::filename3,
+    },
+    Getattr {
+        id: nfs3::fileid3,
+    },
+    Setattr {
+        id: nfs3::fileid3,
+        attr: nfs3::sattr3,
+    },
+    Read {
+        id: nfs3::fileid3,
+        offset: u64,
+        count: u32,
+    },
+    Write {
+        id: nfs3::fileid3,
+        offset: u64,
+        data: Vec<u8>,
+        stable: nfs3::file::stable_how,
+    },
+    Create {
+        dirid: nfs3::fileid3,
+        name: nfs3::filename3,
+        attr: nfs3::sattr3,
+    },
+    CreateExclusive {
+        dirid: nfs3::fileid3,
+        name: nfs3::filename3,
+        verifier: nfs3::createverf3,
+    },
+    Mkdir {
+        dirid: nfs3::fileid3,
+        name: nfs3::filename3,
+    },
+    Remove {
+        dirid: nfs3::fileid3,
+        name: nfs3::filename3,
+    },
+    Rename {
+        from_dirid: nfs3::fileid3,
+        from_name: nfs3::filename3,
+        to_dirid: nfs3::fileid3,
+        to_name: nfs3::filename3,
+    },
+    Readdir {
+        dirid: nfs3::fileid3,
+        start_after: nfs3::fileid3,
+        max_entries: u64,
+    },
+    ReaddirIndex {
+        dirid: nfs3::fileid3,
+        start_index: u64,
+        max_entries: u64,
+    },
+    Symlink {
+        dirid: nfs3::fileid3,
+        name: nfs3::filename3,
+        target: nfs3::nfspath3,
+        attr: nfs3::sattr3,
+    },
+    Readlink {
+        id: nfs3::fileid3,
+    },
+    Link {
+        id: nfs3::fileid3,
+        dirid: nfs3::fileid3,
+        name: nfs3::filename3,
+    },
+    Mknod {
+        dirid: nfs3::fileid3,
+        name: nfs3::filename3,
+        ftype: nfs3::ftype3,
+        spec: nfs3::specdata3,
+        attr: nfs3::sattr3,
+    },
+    Commit {
+        id: nfs3::fileid3,
+        offset: u64,
+        count: u32,
+    },
+    Access {
+        id: nfs3::fileid3,
+        auth: auth_unix,
+        access: u32,
+    },
+    Fsinfo {
+        id: nfs3::fileid3,
+    },
+    Fsstat {
+        id: nfs3::fileid3,
+    },
+}
+
+/// The successful result of a traced call
+#[derive(Debug)]
+pub enum Reply {
+    /// LOOKUP and CREATE_EXCLUSIVE
+    Id(nfs3::fileid3),
+    /// GETATTR, SETATTR, LINK and COMMIT
+    Attr(nfs3::fattr3),
+    /// CREATE, MKDIR, SYMLINK and MKNOD
+    Created(nfs3::fileid3, nfs3::fattr3),
+    Read {
+        data: Vec<u8>,
+        eof: bool,
+    },
+    Write {
+        attr: nfs3::fattr3,
+        committed: nfs3::file::stable_how,
+        count: nfs3::count3,
+    },
+    /// REMOVE and RENAME
+    Done,
+    Readdir(ReadDirResult),
+    Readlink(nfs3::nfspath3),
+    Access(u32),
+    Fsinfo(nfs3::fs::fsinfo3),
+    Fsstat(nfs3::fs::FSSTAT3resok),
+}
+
+/// A call together with its outcome
+#[derive(Debug)]
+pub struct Record {
+    pub call: Call,
+    pub result: Result<Reply, nfs3::nfsstat3>,
+}
+
+fn read<T: Deserialize + Default>(src: &mut impl Read) -> io::Result<T> {
+    xdr::deserialize(src)
+}
+
+fn invalid(what: &str, tag: u32) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("unknown {what} tag {tag} in trace"))
+}
+
+impl Serialize for Call {
+    fn serialize<W: Write>(&self, dest: &mut W) -> io::Result<()> {
+        match self {
+            Call::Lookup { dirid, name } => {
+                0u32.serialize(dest)?;
+                dirid.serialize(dest)?;
+                name.serialize(dest)
+            }
+            Call::Getattr { id } => {
+                1u32.serialize(dest)?;
+                id.serialize(dest)
+            }
+            Call::Setattr { id, attr } => {
+                2u32.serialize(dest)?;
+                id.serialize(dest)?;
+                attr.serialize(dest)
+            }
+            Call::Read { id, offset, count } => {
+                3u32.serialize(dest)?;
+                id.serialize(dest)?;
+                offset.serialize(dest)?;
+                count.serialize(dest)
+            }
+            Call::Write { id, offset, data, stable } => {
+                4u32.serialize(dest)?;
+                id.serialize(dest)?;
+                offset.serialize(dest)?;
+                data.serialize(dest)?;
+                stable.serialize(dest)
+            }
+            Call::Create { dirid, name, attr } => {
+                5u32.serialize(dest)?;
+                dirid.serialize(dest)?;
+                name.serialize(dest)?;
+                attr.serialize(dest)
+            }
+            Call::CreateExclusive { dirid, name, verifier } => {
+                6u32.serialize(dest)?;
+                dirid.serialize(dest)?;
+                name.serialize(dest)?;
+                verifier.serialize(dest)
+            }
+            Call::Mkdir { dirid, name } => {
+                7u32.serialize(dest)?;
+                dirid.serialize(dest)?;
+                name.serialize(dest)
+            }
+            Call::Remove { dirid, name } => {
+                8u32.serialize(dest)?;
+                dirid.serialize(dest)?;
+                name.serialize(dest)
+            }
+            Call::Rename { from_dirid, from_name, to_dirid, to_name } => {
+                9u32.serialize(dest)?;
+                from_dirid.serialize(dest)?;
+                from_name.serialize(dest)?;
+                to_dirid.serialize(dest)?;
+                to_name.serialize(dest)
+            }
+            Call::Readdir { dirid, start_after, max_entries } => {
+                10u32.serialize(dest)?;
+                dirid.serialize(dest)?;
+                start_after.serialize(dest)?;
+                max_entries.serialize(dest)
+            }
+            Call::ReaddirIndex { dirid, start_index, max_entries } => {
+                11u32.serialize(dest)?;
+                dirid.serialize(dest)?;
+                start_index.serialize(dest)?;
+                max_entries.serialize(dest)
+            }
+            Call::Symlink { dirid, name, target, attr } => {
+                12u32.serialize(dest)?;
+                dirid.serialize(dest)?;
+                name.serialize(dest)?;
+                target.serialize(dest)?;
+                attr.serialize(dest)
+            }
+            Call::Readlink { id } => {
+                13u32.serialize(dest)?;
+                id.serialize(dest)
+            }
+            Call::Link { id, dirid, name } => {
+                14u32.serialize(dest)?;
+                id.serialize(dest)?;
+                dirid.serialize(dest)?;
+                name.serialize(dest)
+            }
+            Call::Mknod { dirid, name, ftype, spec, attr } => {
+                15u32.serialize(dest)?;
+                dirid.serialize(dest)?;
+                name.serialize(dest)?;
+                ftype.serialize(dest)?;
+                spec.serialize(dest)?;
+                attr.serialize(dest)
+            }
+            Call::Commit { id, offset, count } => {
+                16u32.serialize(dest)?;
+                id.serialize(dest)?;
+                offset.serialize(dest)?;
+                count.serialize(dest)
+            }
+            Call::Access { id, auth, access } => {
+                17u32.serialize(dest)?;
+                id.serialize(dest)?;
+                auth.serialize(dest)?;
+                access.serialize(dest)
+            }
+            Call::Fsinfo { id } => {
+                18u32.serialize(dest)?;
+                id.serialize(dest)
+            }
+            Call::Fsstat { id } => {
+                19u32.serialize(dest)?;
+                id.serialize(dest)
+            }
+        }
+    }
+}
+
+impl Call {
+    fn read(src: &mut impl Read) -> io::Result<Self> {
+        Ok(match read::<u32>(src)? {
+            0 => Call::Lookup { dirid: read(src)?, name: read(src)? },
+            1 => Call::Getattr { id: read(src)? },
+            2 => Call::Setattr { id: read(src)?, attr: read(src)? },
+            3 => Call::Read { id: read(src)?, offset: read(src)?, count: read(src)? },
+            4 => Call::Write {
+                id: read(src)?,
+                offset: read(src)?,
+                data: read(src)?,
+                stable: read(src)?,
+            },
+            5 => Call::Create { dirid: read(src)?, name: read(src)?, attr: read(src)? },
+            6 => {
+                Call::CreateExclusive { dirid: read(src)?, name: read(src)?, verifier: read(src)? }
+            }
+            7 => Call::Mkdir { dirid: read(src)?, name: read(src)? },
+            8 => Call::Remove { dirid: read(src)?, name: read(src)? },
+            9 => Call::Rename {
+                from_dirid: read(src)?,
+                from_name: read(src)?,
+                to_dirid: read(src)?,
+                to_name: read(src)?,
+            },
+            10 => Call::Readdir {
+                dirid: read(src)?,
+                start_after: read(src)?,
+                max_entries: read(src)?,
+            },
+            11 => Call::ReaddirIndex {
+                dirid: read(src)?,
+                start_index: read(src)?,
+                max_entries: read(src)?,
+            },
+            12 => Call::Symlink {
+                dirid: read(src)?,
+                name: read(src)?,
+                target: read(src)?,
+                attr: read(src)?,
+            },
+            13 => Call::Readlink { id: read(src)? },
+            14 => Call::Link { id: read(src)?, dirid: read(src)?, name: read(src)? },
+            15 => Call::Mknod {
+                dirid: read(src)?,
+                name: read(src)?,
+                ftype: read(src)?,
+                spec: read(src)?,
+                attr: read(src)?,
+            },
+            16 => Call::Commit { id: read(src)?, offset: read(src)?, count: read(src)? },
+            17 => Call::Access { id: read(src)?, auth: read(src)?, access: read(src)? },
+            18 => Call::Fsinfo { id: read(src)? },
+            19 => Call::Fsstat { id: read(src)? },
+            tag => return Err(invalid("call", tag)),
+        })
+    }
+}
+
+impl Serialize for Reply {
+    fn serialize<W: Write>(&self, dest: &mut W) -> io::Result<()> {
+        match self {
+            Reply::Id(id) => {
+                0u32.serialize(dest)?;
+                id.serialize(dest)
+            }
+            Reply::Attr(attr) => {
+                1u32.serialize(dest)?;
+                attr.serialize(dest)
+            }
+            Reply::Created(id, attr) => {
+                2u32.serialize(dest)?;
+                id.serialize(dest)?;
+                attr.serialize(dest)
+            }
+            Reply::Read { data, eof } => {
+                3u32.serialize(dest)?;
+                data.serialize(dest)?;
+                eof.serialize(dest)
+            }
+            Reply::Write { attr, committed, count } => {
+                4u32.serialize(dest)?;
+                attr.serialize(dest)?;
+                committed.serialize(dest)?;
+                count.serialize(dest)
+            }
+            Reply::Done => 5u32.serialize(dest),
+            Reply::Readdir(result) => {
+                6u32.serialize(dest)?;
+                (result.entries.len() as u32).serialize(dest)?;
+                for entry in &result.entries {
+                    entry.fileid.serialize(dest)?;
+                    entry.name.serialize(dest)?;
+                    entry.attr.serialize(dest)?;
+                }
+                result.end.serialize(dest)
+            }
+            Reply::Readlink(path) => {
+                7u32.serialize(dest)?;
+                path.serialize(dest)
+            }
+            Reply::Access(mask) => {
+                8u32.serialize(dest)?;
+                mask.serialize(dest)
+            }
+            Reply::Fsinfo(info) => {
+                9u32.serialize(dest)?;
+                info.serialize(dest)
+            }
+            Reply::Fsstat(stat) => {
+                10u32.serialize(dest)?;
+                stat.serialize(dest)
+            }
+        }
+    }
+}
+
+impl Reply {
+    fn read(src: &mut impl Read) -> io::Result<Self> {
+        Ok(match read::<u32>(src)? {
+            0 => Reply::Id(read(src)?),
+            1 => Reply::Attr(read(src)?),
+            2 => Reply::Created(read(src)?, read(src)?),
+            3 => Reply::Read { data: read(src)?, eof: read(src)? },
+            4 => Reply::Write { attr: read(src)?, committed: read(src)?, count: read(src)? },
+            5 => Reply::Done,
+            6 => {
+                let count = read::<u32>(src)?;
+                let mut entries = Vec::new();
+                for _ in 0..count {
+                    entries.push(DirEntry {
+                        fileid: read(src)?,
+                        name: read(src)?,
+                        attr: read(src)?,
+                    });
+                }
+                Reply::Readdir(ReadDirResult { entries, end: read(src)? })
+            }
+            7 => Reply::Readlink(read(src)?),
+            8 => Reply::Access(read(src)?),
+            9 => Reply::Fsinfo(read(src)?),
+            10 => Reply::Fsstat(read(src)?),
+            tag => return Err(invalid("reply", tag)),
+        })
+    }
+}
+
+impl Serialize for Record {
+    fn serialize<W: Write>(&self, dest: &mut W) -> io::Result<()> {
+        self.call.serialize(dest)?;
+        match &self.result {
+            Ok(reply) => {
+                nfs3::nfsstat3::NFS3_OK.serialize(dest)?;
+                reply.serialize(dest)
+            }
+            Err(status) => status.serialize(dest),
+        }
+    }
+}
+
+impl Record {
+    fn read(src: &mut impl Read) -> io::Result<Self> {
+        let call = Call::read(src)?;
+        let mut status = nfs3::nfsstat3::NFS3_OK;
+        status.deserialize(src)?;
+        let result = match status {
+            nfs3::nfsstat3::NFS3_OK => Ok(Reply::read(src)?),
+            status => Err(status),
+        };
+        Ok(Record { call, result })
+    }
+}
+
+/// Writes a trace file
+pub struct TraceWriter<W: Write> {
+    dest: W,
+}
+
+impl<W: Write> TraceWriter<W> {
+    /// Starts a trace of a file system whose root directory is `root`
+    pub fn new(mut dest: W, root: nfs3::fileid3) -> io::Result<Self> {
+        dest.write_all(MAGIC)?;
+        root.serialize(&mut dest)?;
+        Ok(Self { dest })
+    }
+
+    /// Appends a record
+    pub fn write(&mut self, record: &Record) -> io::Result<()> {
+        record.serialize(&mut self.dest)
+    }
+
+    /// Flushes the underlying writer
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.dest.flush()
+    }
+}
+
+/// Reads the records of a trace file, in the order they were written
+pub struct TraceReader<R: Read> {
+    src: R,
+    root: nfs3::fileid3,
+}
+
+impl<R: Read> TraceReader<R> {
+    /// Reads the header of a trace
+    pub fn new(mut src: R) -> io::Result<Self> {
+        let mut magic = [0; MAGIC.len()];
+        src.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a trace file"));
+        }
+        let root = read(&mut src)?;
+        Ok(Self { src, root })
+    }
+
+    /// Returns the root fileid of the traced file system
+    pub fn root(&self) -> nfs3::fileid3 {
+        self.root
+    }
+
+    /// Reads the next record, or `None` at the end of the trace
+    pub fn next_record(&mut self) -> io::Result<Option<Record>> {
+        // Tell a clean end of the trace from one cut off in the middle of a record.
+        let mut tag = [0; 4];
+        match self.src.read(&mut tag[..1])? {
+            0 => return Ok(None),
+            _ => self.src.read_exact(&mut tag[1..])?,
+        }
+        Record::read(&mut io::Cursor::new(tag).chain(&mut self.src)).map(Some)
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
@@ -0,0 +1,302 @@
+//! Replaying a trace against another file system.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::Arc;
+
+use super::format::{Call, Record, Reply, TraceReader};
+use crate::vfs::{NFSFileSystem, ReadDirResult};
+use crate::xdr::nfs3;
+
+/// A call whose result differs from the recorded one
+#[derive(Debug)]
+pub struct Mismatch {
+    /// Position of the call in the trace, starting at 0
+    pub index: usize,
+    /// The call as recorded
+    pub call: Call,
+    /// The recorded result
+    pub expected: Result<Reply, nfs3::nfsstat3>,
+    /// The result of the file system under test
+    pub actual: Result<Reply, nfs3::nfsstat3>,
+    /// What differs
+    pub reason: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "call {} {:?}: {}", self.index, self.call, self.reason)
+    }
+}
+
+/// The outcome of replaying a trace
+#[derive(Debug, Default)]
+pub struct ReplayReport {
+    /// Number of calls replayed
+    pub calls: usize,
+    /// Calls whose results differed, in trace order
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl ReplayReport {
+    /// Returns true if every call produced the recorded result
+    pub fn is_clean(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+/// Drives the calls of a trace against a file system and compares the results
+///
+/// Fileids are not expected to match between backends. The replayer maps each
+/// recorded fileid to the one the file system under test returned for the same call,
+/// translates the arguments of later calls, and compares fileids through that map.
+/// Fileids that never appeared in a result are passed through unchanged.
+///
+/// Attributes are compared on type, mode, link count, owner, size, device numbers and
+/// fileid. Timestamps are only compared if enabled with
+/// [`compare_times`](Self::compare_times), since they differ between runs; `used`
+/// and `fsid` are never compared. FSINFO and FSSTAT describe the backend rather than
+/// the files in it, so only their status is compared. Calls are replayed one at a
+/// time in trace order, so a trace of concurrent clients is replayed in the order
+/// the calls completed.
+pub struct Replayer {
+    fs: Arc<dyn NFSFileSystem + Send + Sync>,
+    ids: HashMap<nfs3::fileid3, nfs3::fileid3>,
+    compare_times: bool,
+}
+
+impl Replayer {
+    /// Creates a replayer that drives `fs`
+    pub fn new(fs: Arc<dyn NFSFileSystem + Send + Sync>) -> Self {
+        Self { fs, ids: HashMap::new(), compare_times: false }
+    }
+
+    /// Sets whether access, modification and change times must match
+    pub fn compare_times(mut self, compare_times: bool) -> Self {
+        self.compare_times = compare_times;
+        self
+    }
+
+    /// Replays a whole trace
+    ///
+    /// Fails only if the trace cannot be read; differences are collected in the report.
+    pub async fn replay<R: Read>(&mut self, mut trace: TraceReader<R>) -> io::Result<ReplayReport> {
+        self.ids.insert(trace.root(), self.fs.root_dir());
+        let mut report = ReplayReport::default();
+        while let Some(record) = trace.next_record()? {
+            if let Some(mismatch) = self.step(report.calls, record).await {
+                report.mismatches.push(mismatch);
+            }
+            report.calls += 1;
+        }
+        Ok(report)
+    }
+
+    /// Replays a single record, returning how its result differs if it does
+    pub async fn step(&mut self, index: usize, record: Record) -> Option<Mismatch> {
+        let actual = self.call(&record.call).await;
+        let reason = match (&record.result, &actual) {
+            (Err(expected), Err(actual)) if expected == actual => return None,
+            (Ok(expected), Ok(actual)) => self.compare(expected, actual)?,
+            (expected, actual) => {
+                format!("expected {}, got {}", status(expected), status(actual))
+            }
+        };
+        Some(Mismatch { index, call: record.call, expected: record.result, actual, reason })
+    }
+
+    fn id(&self, recorded: nfs3::fileid3) -> nfs3::fileid3 {
+        self.ids.get(&recorded).copied().unwrap_or(recorded)
+    }
+
+    async fn call(&self, call: &Call) -> Result<Reply, nfs3::nfsstat3> {
+        let fs = &self.fs;
+        Ok(match call.clone() {
+            Call::Lookup { dirid, name } => Reply::Id(fs.lookup(self.id(dirid), &name).await?),
+            Call::Getattr { id } => Reply::Attr(fs.getattr(self.id(id)).await?),
+            Call::Setattr { id, attr } => Reply::Attr(fs.setattr(self.id(id), attr).await?),
+            Call::Read { id, offset, count } => {
+                let (data, eof) = fs.read(self.id(id), offset, count).await?;
+                Reply::Read { data, eof }
+            }
+            Call::Write { id, offset, data, stable } => {
+                let (attr, committed, count) = fs.write(self.id(id), offset, &data, stable).await?;
+                Reply::Write { attr, committed, count }
+            }
+            Call::Create { dirid, name, attr } => {
+                let (id, attr) = fs.create(self.id(dirid), &name, attr).await?;
+                Reply::Created(id, attr)
+            }
+            Call::CreateExclusive { dirid, name, verifier } => {
+                Reply::Id(fs.create_exclusive(self.id(dirid), &name, verifier).await?)
+            }
+            Call::Mkdir { dirid, name } => {
+                let (id, attr) = fs.mkdir(self.id(dirid), &name).await?;
+                Reply::Created(id, attr)
+            }
+            Call::Remove { dirid, name } => {
+                fs.remove(self.id(dirid), &name).await?;
+                Reply::Done
+            }
+            Call::Rename { from_dirid, from_name, to_dirid, to_name } => {
+                fs.rename(self.id(from_dirid), &from_name, self.id(to_dirid), &to_name).await?;
+                Reply::Done
+            }
+            Call::Readdir { dirid, start_after, max_entries } => {
+                let start_after = if start_after == 0 { 0 } else { self.id(start_after) };
+                Reply::Readdir(fs.readdir(self.id(dirid), start_after, max_entries as usize).await?)
+            }
+            Call::ReaddirIndex { dirid, start_index, max_entries } => {
+                let dirid = self.id(dirid);
+                let result =
+                    fs.readdir_index(dirid, start_index as usize, max_entries as usize).await?;
+                Reply::Readdir(result)
+            }
+            Call::Symlink { dirid, name, target, attr } => {
+                let (id, attr) = fs.symlink(self.id(dirid), &name, &target, &attr).await?;
+                Reply::Created(id, attr)
+            }
+            Call::Readlink { id } => Reply::Readlink(fs.readlink(self.id(id)).await?),
+            Call::Link { id, dirid, name } => {
+                Reply::Attr(fs.link(self.id(id), self.id(dirid), &name).await?)
+            }
+            Call::Mknod { dirid, name, ftype, spec, attr } => {
+                let (id, attr) = fs.mknod(self.id(dirid), &name, ftype, spec, &attr).await?;
+                Reply::Created(id, attr)
+            }
+            Call::Commit { id, offset, count } => {
+                Reply::Attr(fs.commit(self.id(id), offset, count).await?)
+            }
+            Call::Access { id, auth, access } => {
+                Reply::Access(fs.check_access(self.id(id), &auth, access).await?)
+            }
+            Call::Fsinfo { id } => Reply::Fsinfo(fs.fsinfo(self.id(id)).await?),
+            Call::Fsstat { id } => Reply::Fsstat(fs.fsstat(self.id(id)).await?),
+        })
+    }
+
+    /// Learns the fileid mapping from a pair of results and returns what differs.
+    fn compare(&mut self, expected: &Reply, actual: &Reply) -> Option<String> {
+        match (expected, actual) {
+            (Reply::Id(expected), Reply::Id(actual)) => self.same_id(*expected, *actual),
+            (Reply::Attr(expected), Reply::Attr(actual)) => self.compare_attr(expected, actual),
+            (Reply::Created(expected_id, expected), Reply::Created(actual_id, actual)) => self
+                .same_id(*expected_id, *actual_id)
+                .or_else(|| self.compare_attr(expected, actual)),
+            (
+                Reply::Read { data: expected, eof: expected_eof },
+                Reply::Read { data: actual, eof: actual_eof },
+            ) => {
+                if expected != actual {
+                    let at = expected.iter().zip(actual).position(|(a, b)| a != b);
+                    let at = at.unwrap_or(expected.len().min(actual.len()));
+                    Some(format!(
+                        "read {} bytes differing from the recorded {} at byte {}",
+                        actual.len(),
+                        expected.len(),
+                        at
+                    ))
+                } else if expected_eof != actual_eof {
+                    Some(format!("expected eof {expected_eof}, got {actual_eof}"))
+                } else {
+                    None
+                }
+            }
+            (
+                Reply::Write { attr: expected, count: expected_count, .. },
+                Reply::Write { attr: actual, count: actual_count, .. },
+            ) => {
+                if expected_count != actual_count {
+                    return Some(format!("wrote {actual_count} bytes, expected {expected_count}"));
+                }
+                self.compare_attr(expected, actual)
+            }
+            (Reply::Done, Reply::Done) => None,
+            (Reply::Readdir(expected), Reply::Readdir(actual)) => {
+                self.compare_entries(expected, actual)
+            }
+            (Reply::Readlink(expected), Reply::Readlink(actual)) => (expected.0 != actual.0)
+                .then(|| format!("expected link target {expected:?}, got {actual:?}")),
+            (Reply::Access(expected), Reply::Access(actual)) => (expected != actual)
+                .then(|| format!("expected access {expected:#x}, got {actual:#x}")),
+            (Reply::Fsinfo(_), Reply::Fsinfo(_)) | (Reply::Fsstat(_), Reply::Fsstat(_)) => None,
+            _ => Some("reply of a different kind".to_string()),
+        }
+    }
+
+    /// Checks that `actual` is the fileid that corresponds to `expected`, learning the
+    /// correspondence if it is new.
+    fn same_id(&mut self, expected: nfs3::fileid3, actual: nfs3::fileid3) -> Option<String> {
+        let known = *self.ids.entry(expected).or_insert(actual);
+        (known != actual).then(|| {
+            format!(
+                "fileid {actual} does not match recorded fileid {expected} (replayed as {known})"
+            )
+        })
+    }
+
+    fn compare_attr(&mut self, expected: &nfs3::fattr3, actual: &nfs3::fattr3) -> Option<String> {
+        if let Some(reason) = self.same_id(expected.fileid, actual.fileid) {
+            return Some(reason);
+        }
+        let mut differences = Vec::new();
+        let mut check = |field: &str, expected: String, actual: String| {
+            if expected != actual {
+                differences.push(format!("{field} {actual} (expected {expected})"));
+            }
+        };
+        check("type", format!("{:?}", expected.ftype), format!("{:?}", actual.ftype));
+        check("mode", format!("{:o}", expected.mode), format!("{:o}", actual.mode));
+        check("nlink", expected.nlink.to_string(), actual.nlink.to_string());
+        check("uid", expected.uid.to_string(), actual.uid.to_string());
+        check("gid", expected.gid.to_string(), actual.gid.to_string());
+        check("size", expected.size.to_string(), actual.size.to_string());
+        check("rdev", format!("{:?}", expected.rdev), format!("{:?}", actual.rdev));
+        if self.compare_times {
+            check("atime", format!("{:?}", expected.atime), format!("{:?}", actual.atime));
+            check("mtime", format!("{:?}", expected.mtime), format!("{:?}", actual.mtime));
+            check("ctime", format!("{:?}", expected.ctime), format!("{:?}", actual.ctime));
+        }
+        (!differences.is_empty()).then(|| differences.join(", "))
+    }
+
+    fn compare_entries(
+        &mut self,
+        expected: &ReadDirResult,
+        actual: &ReadDirResult,
+    ) -> Option<String> {
+        let names = |result: &ReadDirResult| -> Vec<Vec<u8>> {
+            result.entries.iter().map(|entry| entry.name.0.clone()).collect()
+        };
+        if names(expected) != names(actual) {
+            let show = |names: Vec<Vec<u8>>| -> Vec<String> {
+                names.iter().map(|name| String::from_utf8_lossy(name).into_owned()).collect()
+            };
+            return Some(format!(
+                "listed {:?}, expected {:?}",
+                show(names(actual)),
+                show(names(expected))
+            ));
+        }
+        if expected.end != actual.end {
+            return Some(format!("expected end {}, got {}", expected.end, actual.end));
+        }
+        for (expected, actual) in expected.entries.iter().zip(&actual.entries) {
+            if let Some(reason) = self.compare_attr(&expected.attr, &actual.attr) {
+                return Some(format!("entry {:?}: {reason}", expected.name));
+            }
+            if let Some(reason) = self.same_id(expected.fileid, actual.fileid) {
+                return Some(format!("entry {:?}: {reason}", expected.name));
+            }
+        }
+        None
+    }
+}
+
+fn status(result: &Result<Reply, nfs3::nfsstat3>) -> String {
+    match result {
+        Ok(_) => "NFS3_OK".to_string(),
+        Err(status) => format!("{status:?}"),
+    }
+}
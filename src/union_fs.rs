@@ -0,0 +1,783 @@
+//! A union of a writable file system stacked over read-only ones.
+//!
+//! [`UnionFS`] presents a single tree made of an upper layer, which receives every
+//! change, and one or more lower layers, which are never modified. This gives each
+//! client a private writable view of a shared tree, such as a CI job working on top of
+//! a common toolchain export:
+//!
+//! ```ignore
+//! use std::sync::Arc;
+//! use fernfs::archive_fs::ArchiveFS;
+//! use fernfs::mem_fs::MemFS;
+//! use fernfs::union_fs::{Layer, UnionFS};
+//!
+//! let toolchain: Layer = Arc::new(ArchiveFS::open("toolchain.tar.zst")?);
+//! let job = UnionFS::new(Arc::new(MemFS::new())).with_lower(toolchain.clone());
+//! ```
+//!
+//! The layers behave like overlayfs:
+//! - A name resolves to the topmost layer that has it. Directories merge with
+//!   same-named directories in the layers below; any other object hides them.
+//! - Writing to or changing the attributes of a lower object first copies it, and
+//!   any missing parent directories, into the upper layer.
+//! - Removing or renaming away a name that exists in a lower layer leaves a whiteout
+//!   in the upper layer: an empty file named `.wh.<name>`. A directory created over a
+//!   whiteout is marked opaque with a `.wh..wh..opq` file so that it does not merge
+//!   with the lower directory it replaces. Both are stored as ordinary files, so the
+//!   upper layer can be reused later, and names starting with `.wh.` are reserved.
+//! - Directories that exist in a lower layer cannot be renamed; this fails with
+//!   `NFS3ERR_XDEV`, and clients fall back to copying.
+//!
+//! Every layer numbers its objects independently, so the union allocates its own
+//! fileids and never passes a layer's fileids through. Copying an object up keeps
+//! its fileid. Hard links between lower files are broken by copy-up.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use indexmap::IndexSet;
+use tokio::sync::RwLock;
+use tracing::debug;
+
+use crate::vfs::{self, Capabilities, NFSFileSystem};
+use crate::xdr::nfs3;
+
+mod table;
+
+use table::{Instance, Table, UPPER};
+
+/// A layer of a [`UnionFS`].
+///
+/// Layers are shared, so a single read-only tree can sit under many unions.
+pub type Layer = Arc<dyn NFSFileSystem + Send + Sync>;
+
+/// File ID of the root directory.
+const ROOT_ID: nfs3::fileid3 = 1;
+/// Prefix of whiteout names in the upper layer.
+pub const WHITEOUT_PREFIX: &[u8] = b".wh.";
+/// Name of the file that marks an upper directory as opaque.
+pub const OPAQUE_MARKER: &[u8] = b".wh..wh..opq";
+/// Bytes copied per read while copying a file up.
+const COPY_CHUNK: u32 = 1 << 20;
+/// Entries requested per call while listing a layer's directory.
+const LIST_PAGE: usize = 1024;
+
+type NFSResult<T> = Result<T, nfs3::nfsstat3>;
+
+fn whiteout_name(name: &[u8]) -> Vec<u8> {
+    [WHITEOUT_PREFIX, name].concat()
+}
+
+fn is_reserved(name: &[u8]) -> bool {
+    name.starts_with(WHITEOUT_PREFIX)
+}
+
+fn is_dir(attr: &nfs3::fattr3) -> bool {
+    matches!(attr.ftype, nfs3::ftype3::NF3DIR)
+}
+
+/// Rejects reserved names as the target of an operation that creates an entry.
+fn check_new_name(name: &[u8]) -> NFSResult<()> {
+    if is_reserved(name) {
+        return Err(nfs3::nfsstat3::NFS3ERR_INVAL);
+    }
+    Ok(())
+}
+
+/// Rejects `.` and `..` as the object of REMOVE or RENAME.
+fn check_existing_name(name: &[u8]) -> NFSResult<()> {
+    if name == b"." || name == b".." {
+        return Err(nfs3::nfsstat3::NFS3ERR_INVAL);
+    }
+    Ok(())
+}
+
+/// Turns `NFS3ERR_NOENT` into `None`.
+fn found<T>(result: NFSResult<T>) -> NFSResult<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(nfs3::nfsstat3::NFS3ERR_NOENT) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn with_fileid(mut attr: nfs3::fattr3, id: nfs3::fileid3) -> nfs3::fattr3 {
+    attr.fileid = id;
+    attr
+}
+
+/// A writable file system layered over read-only ones
+///
+/// See the [module documentation](self) for the semantics.
+pub struct UnionFS {
+    /// The upper layer followed by the lower layers, topmost first.
+    layers: Vec<Layer>,
+    table: Mutex<Table>,
+    /// Held shared by lookups and exclusively by operations that change the namespace,
+    /// so that multi-step updates such as copy-up are never observed half done.
+    namespace: RwLock<()>,
+    generation: u64,
+}
+
+impl UnionFS {
+    /// Creates a union with `upper` as its only layer
+    pub fn new(upper: Layer) -> Self {
+        let root = vec![(UPPER, upper.root_dir())];
+        let since_epoch = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
+        Self {
+            layers: vec![upper],
+            table: Mutex::new(Table::new(ROOT_ID, root)),
+            namespace: RwLock::new(()),
+            generation: since_epoch.as_millis() as u64,
+        }
+    }
+
+    /// Adds a read-only layer below the existing ones
+    ///
+    /// Lower layers are never written to, whatever their capabilities.
+    pub fn with_lower(mut self, lower: Layer) -> Self {
+        self.layers.push(lower);
+        let root = self.layers.iter().enumerate().map(|(i, fs)| (i, fs.root_dir())).collect();
+        self.table = Mutex::new(Table::new(ROOT_ID, root));
+        self
+    }
+
+    /// Returns the writable layer
+    pub fn upper(&self) -> &Layer {
+        &self.layers[UPPER]
+    }
+
+    fn table(&self) -> MutexGuard<'_, Table> {
+        self.table.lock().unwrap()
+    }
+
+    fn instances(&self, id: nfs3::fileid3) -> NFSResult<Vec<Instance>> {
+        Ok(self.table().node(id)?.instances.clone())
+    }
+
+    fn top(&self, id: nfs3::fileid3) -> NFSResult<Instance> {
+        Ok(self.table().node(id)?.top())
+    }
+
+    async fn upper_has(&self, dirid: nfs3::fileid3, name: &[u8]) -> NFSResult<bool> {
+        Ok(found(self.layers[UPPER].lookup(dirid, &name.into()).await)?.is_some())
+    }
+
+    /// Looks `name` up in the merged directory made of `dir`, returning the object's
+    /// instances from the top down and the attributes of the topmost one.
+    async fn resolve(
+        &self,
+        dir: &[Instance],
+        name: &[u8],
+    ) -> NFSResult<(Vec<Instance>, nfs3::fattr3)> {
+        if is_reserved(name) {
+            return Err(nfs3::nfsstat3::NFS3ERR_NOENT);
+        }
+        let filename: nfs3::filename3 = name.into();
+        let mut instances = Vec::new();
+        let mut top_attr = None;
+        for &(layer, dirid) in dir {
+            let fs = &self.layers[layer];
+            if let Some(id) = found(fs.lookup(dirid, &filename).await)? {
+                let attr = fs.getattr(id).await?;
+                let dir = is_dir(&attr);
+                // Only directories merge; anything else hides what lies below it.
+                if top_attr.is_some() && !dir {
+                    break;
+                }
+                top_attr.get_or_insert(attr);
+                instances.push((layer, id));
+                if !dir || (layer == UPPER && self.upper_has(id, OPAQUE_MARKER).await?) {
+                    break;
+                }
+            }
+            if layer == UPPER && self.upper_has(dirid, &whiteout_name(name)).await? {
+                break;
+            }
+        }
+        top_attr.map(|attr| (instances, attr)).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)
+    }
+
+    /// Returns whether a lower layer of the merged directory `dir` has `name`.
+    ///
+    /// [`resolve`](Self::resolve) stops at the first object that is not a directory,
+    /// so an upper file, copied up or not, can hide lower ones it does not report.
+    /// Removing or renaming the name must leave a whiteout for those.
+    async fn lower_has(&self, dir: &[Instance], name: &[u8]) -> NFSResult<bool> {
+        let filename: nfs3::filename3 = name.into();
+        for &(layer, dirid) in dir.iter().filter(|(layer, _)| *layer != UPPER) {
+            if found(self.layers[layer].lookup(dirid, &filename).await)?.is_some() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Returns the names in one layer's directory, without "." and "..".
+    async fn list_layer(&self, layer: usize, dirid: nfs3::fileid3) -> NFSResult<Vec<Vec<u8>>> {
+        let fs = &self.layers[layer];
+        let mut names = Vec::new();
+        let mut index = 0;
+        loop {
+            let page = fs.readdir_simple_index(dirid, index, LIST_PAGE).await?;
+            index += page.entries.len();
+            let empty = page.entries.is_empty();
+            names.extend(
+                page.entries
+                    .into_iter()
+                    .map(|entry| entry.name.0)
+                    .filter(|n| n != b"." && n != b".."),
+            );
+            if page.end || empty {
+                return Ok(names);
+            }
+        }
+    }
+
+    /// Returns the visible names of the merged directory made of `dir`.
+    ///
+    /// Names are listed layer by layer from the top, each in the layer's own order,
+    /// so the listing and the READDIR cookies derived from it are stable for as long
+    /// as the layers are unchanged.
+    async fn list_merged(&self, dir: &[Instance]) -> NFSResult<IndexSet<Vec<u8>>> {
+        let mut names = IndexSet::new();
+        let mut hidden = HashSet::new();
+        for &(layer, dirid) in dir {
+            for name in self.list_layer(layer, dirid).await? {
+                if let Some(target) = name.strip_prefix(WHITEOUT_PREFIX) {
+                    if layer == UPPER && name != OPAQUE_MARKER {
+                        hidden.insert(target.to_vec());
+                    }
+                } else if !hidden.contains(&name) {
+                    names.insert(name);
+                }
+            }
+        }
+        Ok(names)
+    }
+
+    /// Removes the whiteout for `name` from an upper directory, returning whether there was one.
+    async fn clear_whiteout(&self, upper_dir: nfs3::fileid3, name: &[u8]) -> NFSResult<bool> {
+        let whiteout = whiteout_name(name);
+        if !self.upper_has(upper_dir, &whiteout).await? {
+            return Ok(false);
+        }
+        self.layers[UPPER].remove(upper_dir, &whiteout.as_slice().into()).await?;
+        Ok(true)
+    }
+
+    /// Removes the whiteouts and opaque marker in an upper directory so it can be removed.
+    async fn clear_markers(&self, upper_dir: nfs3::fileid3) -> NFSResult<()> {
+        for name in self.list_layer(UPPER, upper_dir).await? {
+            if is_reserved(&name) {
+                self.layers[UPPER].remove(upper_dir, &name.as_slice().into()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates an empty marker file in an upper directory.
+    async fn create_marker(&self, upper_dir: nfs3::fileid3, name: &[u8]) -> NFSResult<()> {
+        let attr = nfs3::sattr3 { mode: Some(0), ..Default::default() };
+        self.layers[UPPER].create(upper_dir, &name.into(), attr).await?;
+        Ok(())
+    }
+
+    /// Returns the upper instance of `id`, copying the object up if it has none.
+    ///
+    /// Must be called with the namespace lock held exclusively. Missing parent
+    /// directories are copied up first. With `with_data` false, regular files are
+    /// copied up empty, for callers that are about to truncate them.
+    async fn copy_up(&self, id: nfs3::fileid3, with_data: bool) -> NFSResult<nfs3::fileid3> {
+        let mut pending = Vec::new();
+        let mut current = id;
+        let mut upper_id = loop {
+            let node = self.table().node(current)?.clone();
+            let (layer, layer_id) = node.top();
+            if layer == UPPER {
+                break layer_id;
+            }
+            pending.push(current);
+            // The root always has an upper instance, which ends the walk.
+            current = node.parent;
+        };
+        for obj in pending.into_iter().rev() {
+            upper_id = self.copy_up_one(obj, upper_id, with_data || obj != id).await?;
+        }
+        Ok(upper_id)
+    }
+
+    /// Copies a single object into the upper directory `upper_dir`.
+    async fn copy_up_one(
+        &self,
+        id: nfs3::fileid3,
+        upper_dir: nfs3::fileid3,
+        with_data: bool,
+    ) -> NFSResult<nfs3::fileid3> {
+        let node = self.table().node(id)?.clone();
+        let (layer, src) = node.top();
+        let (lower, upper) = (&self.layers[layer], &self.layers[UPPER]);
+        let attr = lower.getattr(src).await?;
+        let name: nfs3::filename3 = node.name.as_slice().into();
+        debug!("copying up {:?} from layer {}", name, layer);
+        let copy = match attr.ftype {
+            nfs3::ftype3::NF3DIR => upper.mkdir(upper_dir, &name).await?.0,
+            nfs3::ftype3::NF3REG => {
+                let (copy, _) = upper.create(upper_dir, &name, nfs3::sattr3::default()).await?;
+                if with_data {
+                    self.copy_data(lower, src, copy, attr.size).await?;
+                }
+                copy
+            }
+            nfs3::ftype3::NF3LNK => {
+                let target = lower.readlink(src).await?;
+                upper.symlink(upper_dir, &name, &target, &nfs3::sattr3::default()).await?.0
+            }
+            ftype => upper.mknod(upper_dir, &name, ftype, attr.rdev, &Default::default()).await?.0,
+        };
+        if !matches!(attr.ftype, nfs3::ftype3::NF3LNK) {
+            self.copy_attributes(&attr, copy, with_data).await?;
+        }
+        self.table().copied_up(id, copy, is_dir(&attr));
+        Ok(copy)
+    }
+
+    async fn copy_data(
+        &self,
+        lower: &Layer,
+        src: nfs3::fileid3,
+        copy: nfs3::fileid3,
+        size: u64,
+    ) -> NFSResult<()> {
+        let mut offset = 0;
+        while offset < size {
+            let (data, eof) = lower.read(src, offset, COPY_CHUNK).await?;
+            // Runs of zeros are left as holes; the final size is set afterwards.
+            if data.iter().any(|&b| b != 0) {
+                let stable = nfs3::file::stable_how::FILE_SYNC;
+                self.layers[UPPER].write(copy, offset, &data, stable).await?;
+            }
+            offset += data.len() as u64;
+            if eof || data.is_empty() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Gives a copied-up object the ownership, mode, size and times of the original.
+    async fn copy_attributes(
+        &self,
+        attr: &nfs3::fattr3,
+        copy: nfs3::fileid3,
+        with_size: bool,
+    ) -> NFSResult<()> {
+        let upper = &self.layers[UPPER];
+        let current = upper.getattr(copy).await?;
+        if (current.uid, current.gid) != (attr.uid, attr.gid) {
+            let owner =
+                nfs3::sattr3 { uid: Some(attr.uid), gid: Some(attr.gid), ..Default::default() };
+            // The upper layer may not be allowed to give files away.
+            match upper.setattr(copy, owner).await {
+                Ok(_) | Err(nfs3::nfsstat3::NFS3ERR_PERM) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        let rest = nfs3::sattr3 {
+            mode: Some(attr.mode),
+            size: (with_size && matches!(attr.ftype, nfs3::ftype3::NF3REG)).then_some(attr.size),
+            atime: nfs3::set_atime::SET_TO_CLIENT_TIME(attr.atime),
+            mtime: nfs3::set_mtime::SET_TO_CLIENT_TIME(attr.mtime),
+            ..Default::default()
+        };
+        upper.setattr(copy, rest).await?;
+        Ok(())
+    }
+
+    /// Returns the upper instance of `id`, copying it up if necessary.
+    async fn writable(&self, id: nfs3::fileid3, with_data: bool) -> NFSResult<nfs3::fileid3> {
+        let (layer, layer_id) = self.top(id)?;
+        if layer == UPPER {
+            return Ok(layer_id);
+        }
+        let _namespace = self.namespace.write().await;
+        self.copy_up(id, with_data).await
+    }
+
+    /// Prepares `name` in `dirid` for a new entry, returning the upper directory and
+    /// whether a whiteout had to be removed.
+    ///
+    /// Must be called with the namespace lock held exclusively.
+    async fn prepare_new_entry(
+        &self,
+        dirid: nfs3::fileid3,
+        name: &[u8],
+    ) -> NFSResult<(nfs3::fileid3, bool)> {
+        check_new_name(name)?;
+        let dir = self.instances(dirid)?;
+        if found(self.resolve(&dir, name).await)?.is_some() {
+            return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
+        }
+        let upper_dir = self.copy_up(dirid, true).await?;
+        let whiteout = self.clear_whiteout(upper_dir, name).await?;
+        Ok((upper_dir, whiteout))
+    }
+
+    /// Records a newly created upper object and relabels its attributes.
+    fn created(
+        &self,
+        dirid: nfs3::fileid3,
+        name: &[u8],
+        (upper_id, attr): (nfs3::fileid3, nfs3::fattr3),
+    ) -> (nfs3::fileid3, nfs3::fattr3) {
+        let id = self.table().register(dirid, name, vec![(UPPER, upper_id)]);
+        (id, with_fileid(attr, id))
+    }
+
+    /// Drops `id` from the table unless its upper instance is still linked elsewhere.
+    async fn forget_if_unlinked(&self, id: nfs3::fileid3, top: Instance, was_dir: bool) {
+        let linked = !was_dir
+            && top.0 == UPPER
+            && self.layers[UPPER].getattr(top.1).await.is_ok_and(|attr| attr.nlink > 0);
+        if !linked {
+            self.table().forget(id);
+        }
+    }
+}
+
+#[async_trait]
+impl NFSFileSystem for UnionFS {
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn server_id(&self) -> nfs3::cookieverf3 {
+        // Changes go to the upper layer, so its verifier is the one that moves.
+        self.layers[UPPER].server_id()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::ReadWrite
+    }
+
+    fn root_dir(&self) -> nfs3::fileid3 {
+        ROOT_ID
+    }
+
+    async fn lookup(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> NFSResult<nfs3::fileid3> {
+        let _namespace = self.namespace.read().await;
+        let dir = self.table().node(dirid)?.clone();
+        match &filename[..] {
+            b"." => return Ok(dirid),
+            b".." => return Ok(dir.parent),
+            _ => {}
+        }
+        let (instances, _) = self.resolve(&dir.instances, filename).await?;
+        Ok(self.table().register(dirid, filename, instances))
+    }
+
+    async fn getattr(&self, id: nfs3::fileid3) -> NFSResult<nfs3::fattr3> {
+        let (layer, layer_id) = self.top(id)?;
+        Ok(with_fileid(self.layers[layer].getattr(layer_id).await?, id))
+    }
+
+    async fn setattr(&self, id: nfs3::fileid3, setattr: nfs3::sattr3) -> NFSResult<nfs3::fattr3> {
+        let upper_id = self.writable(id, setattr.size != Some(0)).await?;
+        Ok(with_fileid(self.layers[UPPER].setattr(upper_id, setattr).await?, id))
+    }
+
+    async fn read(&self, id: nfs3::fileid3, offset: u64, count: u32) -> NFSResult<(Vec<u8>, bool)> {
+        let (layer, layer_id) = self.top(id)?;
+        self.layers[layer].read(layer_id, offset, count).await
+    }
+
+    async fn write(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+        stable: nfs3::file::stable_how,
+    ) -> NFSResult<(nfs3::fattr3, nfs3::file::stable_how, nfs3::count3)> {
+        let upper_id = self.writable(id, true).await?;
+        let (attr, stable, count) =
+            self.layers[UPPER].write(upper_id, offset, data, stable).await?;
+        Ok((with_fileid(attr, id), stable, count))
+    }
+
+    async fn create(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        attr: nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        let _namespace = self.namespace.write().await;
+        let (upper_dir, _) = self.prepare_new_entry(dirid, filename).await?;
+        let created = self.layers[UPPER].create(upper_dir, filename, attr).await?;
+        Ok(self.created(dirid, filename, created))
+    }
+
+    async fn create_exclusive(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        verifier: nfs3::createverf3,
+    ) -> NFSResult<nfs3::fileid3> {
+        let _namespace = self.namespace.write().await;
+        check_new_name(filename)?;
+        let dir = self.instances(dirid)?;
+        // A retransmitted request finds its own file in the upper layer, which checks
+        // the verifier; a lower file is never the result of an exclusive create.
+        let upper_dir = match found(self.resolve(&dir, filename).await)? {
+            Some((instances, _)) if instances[0].0 != UPPER => {
+                return Err(nfs3::nfsstat3::NFS3ERR_EXIST)
+            }
+            Some(_) => self.copy_up(dirid, true).await?,
+            None => self.prepare_new_entry(dirid, filename).await?.0,
+        };
+        let upper_id = self.layers[UPPER].create_exclusive(upper_dir, filename, verifier).await?;
+        Ok(self.table().register(dirid, filename, vec![(UPPER, upper_id)]))
+    }
+
+    async fn mkdir(
+        &self,
+        dirid: nfs3::fileid3,
+        dirname: &nfs3::filename3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        let _namespace = self.namespace.write().await;
+        let (upper_dir, whiteout) = self.prepare_new_entry(dirid, dirname).await?;
+        let created = self.layers[UPPER].mkdir(upper_dir, dirname).await?;
+        if whiteout {
+            // The name was removed from a lower layer; keep that directory's entries hidden.
+            self.create_marker(created.0, OPAQUE_MARKER).await?;
+        }
+        Ok(self.created(dirid, dirname, created))
+    }
+
+    async fn remove(&self, dirid: nfs3::fileid3, filename: &nfs3::filename3) -> NFSResult<()> {
+        check_existing_name(filename)?;
+        let _namespace = self.namespace.write().await;
+        let dir = self.instances(dirid)?;
+        let (instances, attr) = self.resolve(&dir, filename).await?;
+        if is_dir(&attr) && !self.list_merged(&instances).await?.is_empty() {
+            return Err(nfs3::nfsstat3::NFS3ERR_NOTEMPTY);
+        }
+        let upper_dir = self.copy_up(dirid, true).await?;
+        let top = instances[0];
+        if top.0 == UPPER {
+            if is_dir(&attr) {
+                self.clear_markers(top.1).await?;
+            }
+            self.layers[UPPER].remove(upper_dir, filename).await?;
+        }
+        if self.lower_has(&dir, filename).await? {
+            self.create_marker(upper_dir, &whiteout_name(filename)).await?;
+        }
+        let id = self.table().id_of(top);
+        if let Some(id) = id {
+            self.forget_if_unlinked(id, top, is_dir(&attr)).await;
+        }
+        Ok(())
+    }
+
+    async fn rename(
+        &self,
+        from_dirid: nfs3::fileid3,
+        from_filename: &nfs3::filename3,
+        to_dirid: nfs3::fileid3,
+        to_filename: &nfs3::filename3,
+    ) -> NFSResult<()> {
+        check_existing_name(from_filename)?;
+        check_existing_name(to_filename)?;
+        check_new_name(to_filename)?;
+        let _namespace = self.namespace.write().await;
+        let from_dir = self.instances(from_dirid)?;
+        let to_dir = self.instances(to_dirid)?;
+        let (source, source_attr) = self.resolve(&from_dir, from_filename).await?;
+        let target = found(self.resolve(&to_dir, to_filename).await)?;
+        let source_is_dir = is_dir(&source_attr);
+        if let Some((target, target_attr)) = &target {
+            if target[0] == source[0] {
+                return Ok(());
+            }
+            match (source_is_dir, is_dir(target_attr)) {
+                (true, false) => return Err(nfs3::nfsstat3::NFS3ERR_NOTDIR),
+                (false, true) => return Err(nfs3::nfsstat3::NFS3ERR_ISDIR),
+                (true, true) if !self.list_merged(target).await?.is_empty() => {
+                    return Err(nfs3::nfsstat3::NFS3ERR_NOTEMPTY)
+                }
+                _ => {}
+            }
+        }
+        if source_is_dir && source.iter().any(|&(layer, _)| layer != UPPER) {
+            // Moving a merged directory would mean copying up its whole subtree.
+            return Err(nfs3::nfsstat3::NFS3ERR_XDEV);
+        }
+        let id = self.table().register(from_dirid, from_filename, source.clone());
+        if source_is_dir && self.table().is_within(to_dirid, id) {
+            return Err(nfs3::nfsstat3::NFS3ERR_INVAL);
+        }
+
+        let upper_source = self.copy_up(id, true).await?;
+        let from_upper = self.copy_up(from_dirid, true).await?;
+        let to_upper = self.copy_up(to_dirid, true).await?;
+        let source_has_lower = self.lower_has(&from_dir, from_filename).await?;
+        let target_has_lower = self.clear_whiteout(to_upper, to_filename).await?
+            | self.lower_has(&to_dir, to_filename).await?;
+        if let Some((target, target_attr)) = &target {
+            if target[0].0 == UPPER && is_dir(target_attr) {
+                self.clear_markers(target[0].1).await?;
+            }
+        }
+        let upper = &self.layers[UPPER];
+        upper.rename(from_upper, from_filename, to_upper, to_filename).await?;
+        if source_is_dir && target_has_lower {
+            self.create_marker(upper_source, OPAQUE_MARKER).await?;
+        }
+        if source_has_lower {
+            self.create_marker(from_upper, &whiteout_name(from_filename)).await?;
+        }
+
+        if let Some((target, target_attr)) = target {
+            let target_id = self.table().id_of(target[0]);
+            if let Some(target_id) = target_id {
+                self.forget_if_unlinked(target_id, target[0], is_dir(&target_attr)).await;
+            }
+        }
+        self.table().moved(id, to_dirid, to_filename);
+        Ok(())
+    }
+
+    async fn readdir(
+        &self,
+        dirid: nfs3::fileid3,
+        start_after: nfs3::fileid3,
+        max_entries: usize,
+    ) -> NFSResult<vfs::ReadDirResult> {
+        let _namespace = self.namespace.read().await;
+        let dir = self.table().node(dirid)?.clone();
+        let names = self.list_merged(&dir.instances).await?;
+        // Cookies are indices into ".", "..", then the merged names.
+        let total = names.len() + 2;
+        let start = usize::try_from(start_after).map_err(|_| nfs3::nfsstat3::NFS3ERR_BAD_COOKIE)?;
+        if start > total {
+            return Err(nfs3::nfsstat3::NFS3ERR_BAD_COOKIE);
+        }
+        let end = total.min(start.saturating_add(max_entries));
+        let mut result = vfs::ReadDirResult { entries: Vec::new(), end: end == total };
+        for index in start..end {
+            let (name, fileid, attr) = match index {
+                0 => (b".".to_vec(), dirid, self.getattr(dirid).await?),
+                1 => (b"..".to_vec(), dir.parent, self.getattr(dir.parent).await?),
+                _ => {
+                    let name = names[index - 2].clone();
+                    let (instances, attr) = self.resolve(&dir.instances, &name).await?;
+                    let fileid = self.table().register(dirid, &name, instances);
+                    (name, fileid, with_fileid(attr, fileid))
+                }
+            };
+            result.entries.push(vfs::DirEntry { fileid, name: name.into(), attr });
+        }
+        Ok(result)
+    }
+
+    async fn readdir_index(
+        &self,
+        dirid: nfs3::fileid3,
+        start_index: usize,
+        max_entries: usize,
+    ) -> NFSResult<vfs::ReadDirResult> {
+        self.readdir(dirid, start_index as nfs3::fileid3, max_entries).await
+    }
+
+    async fn symlink(
+        &self,
+        dirid: nfs3::fileid3,
+        linkname: &nfs3::filename3,
+        symlink: &nfs3::nfspath3,
+        attr: &nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        let _namespace = self.namespace.write().await;
+        let (upper_dir, _) = self.prepare_new_entry(dirid, linkname).await?;
+        let created = self.layers[UPPER].symlink(upper_dir, linkname, symlink, attr).await?;
+        Ok(self.created(dirid, linkname, created))
+    }
+
+    async fn readlink(&self, id: nfs3::fileid3) -> NFSResult<nfs3::nfspath3> {
+        let (layer, layer_id) = self.top(id)?;
+        self.layers[layer].readlink(layer_id).await
+    }
+
+    async fn link(
+        &self,
+        file_id: nfs3::fileid3,
+        link_dir_id: nfs3::fileid3,
+        link_name: &nfs3::filename3,
+    ) -> NFSResult<nfs3::fattr3> {
+        let _namespace = self.namespace.write().await;
+        if is_dir(&self.getattr(file_id).await?) {
+            return Err(nfs3::nfsstat3::NFS3ERR_ISDIR);
+        }
+        let (upper_dir, _) = self.prepare_new_entry(link_dir_id, link_name).await?;
+        let upper_file = self.copy_up(file_id, true).await?;
+        let attr = self.layers[UPPER].link(upper_file, upper_dir, link_name).await?;
+        Ok(with_fileid(attr, file_id))
+    }
+
+    async fn mknod(
+        &self,
+        dir_id: nfs3::fileid3,
+        name: &nfs3::filename3,
+        ftype: nfs3::ftype3,
+        specdata: nfs3::specdata3,
+        attrs: &nfs3::sattr3,
+    ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
+        let _namespace = self.namespace.write().await;
+        let (upper_dir, _) = self.prepare_new_entry(dir_id, name).await?;
+        let created = self.layers[UPPER].mknod(upper_dir, name, ftype, specdata, attrs).await?;
+        Ok(self.created(dir_id, name, created))
+    }
+
+    async fn commit(
+        &self,
+        file_id: nfs3::fileid3,
+        offset: u64,
+        count: u32,
+    ) -> NFSResult<nfs3::fattr3> {
+        match self.top(file_id)? {
+            (UPPER, upper_id) => {
+                Ok(with_fileid(self.layers[UPPER].commit(upper_id, offset, count).await?, file_id))
+            }
+            _ => self.getattr(file_id).await,
+        }
+    }
+
+    fn fsinfo_maxfilesize(&self) -> nfs3::size3 {
+        self.layers[UPPER].fsinfo_maxfilesize()
+    }
+
+    fn fsinfo_properties(&self) -> u32 {
+        self.layers[UPPER].fsinfo_properties()
+    }
+
+    fn pathconf_linkmax(&self) -> u32 {
+        self.layers[UPPER].pathconf_linkmax()
+    }
+
+    fn pathconf_name_max(&self) -> u32 {
+        self.layers[UPPER].pathconf_name_max()
+    }
+
+    async fn fsstat(&self, root_fileid: nfs3::fileid3) -> NFSResult<nfs3::fs::FSSTAT3resok> {
+        // Free space is what the upper layer has left.
+        let upper = &self.layers[UPPER];
+        let mut stat = upper.fsstat(upper.root_dir()).await?;
+        stat.obj_attributes = self.getattr(root_fileid).await.ok();
+        Ok(stat)
+    }
+}
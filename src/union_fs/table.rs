@@ -0,0 +1,139 @@
+//! The union-level fileid table.
+
+use std::collections::HashMap;
+
+use crate::xdr::nfs3;
+
+/// An object's presence in one layer: the layer index, where 0 is the upper layer, and
+/// the fileid that layer uses for it.
+pub type Instance = (usize, nfs3::fileid3);
+
+/// Index of the writable layer.
+pub const UPPER: usize = 0;
+
+/// A file or directory as seen through the union.
+#[derive(Debug, Clone)]
+pub struct Node {
+    /// Union fileid of the directory the object was last found in.
+    pub parent: nfs3::fileid3,
+    /// Name the object was last found under.
+    pub name: Vec<u8>,
+    /// The object's instances from the top down. Only directories have more than one:
+    /// a directory merges with same-named directories in the layers below it.
+    pub instances: Vec<Instance>,
+}
+
+impl Node {
+    /// Returns the instance that supplies the object's attributes and contents.
+    pub fn top(&self) -> Instance {
+        self.instances[0]
+    }
+}
+
+/// Maps union fileids to layer instances and back.
+///
+/// Every layer numbers its objects independently, so union fileids are allocated
+/// here and keyed by the object's topmost instance. Copying an object up registers
+/// its new upper instance under the same union fileid, which keeps file handles
+/// valid across the copy.
+#[derive(Debug)]
+pub struct Table {
+    nodes: HashMap<nfs3::fileid3, Node>,
+    ids: HashMap<Instance, nfs3::fileid3>,
+    next_id: nfs3::fileid3,
+}
+
+impl Table {
+    /// Creates a table holding only the root directory.
+    pub fn new(root_id: nfs3::fileid3, root: Vec<Instance>) -> Self {
+        let mut table = Self { nodes: HashMap::new(), ids: HashMap::new(), next_id: root_id + 1 };
+        table.ids.insert(root[0], root_id);
+        table.nodes.insert(root_id, Node { parent: root_id, name: Vec::new(), instances: root });
+        table
+    }
+
+    /// Returns the node for a union fileid.
+    pub fn node(&self, id: nfs3::fileid3) -> Result<&Node, nfs3::nfsstat3> {
+        self.nodes.get(&id).ok_or(nfs3::nfsstat3::NFS3ERR_STALE)
+    }
+
+    /// Returns the union fileid of an already known instance.
+    pub fn id_of(&self, instance: Instance) -> Option<nfs3::fileid3> {
+        self.ids.get(&instance).copied()
+    }
+
+    /// Records that `instances` were found as `name` in `parent`, returning the
+    /// object's union fileid and allocating one on first sight.
+    pub fn register(
+        &mut self,
+        parent: nfs3::fileid3,
+        name: &[u8],
+        instances: Vec<Instance>,
+    ) -> nfs3::fileid3 {
+        let id = match self.ids.get(&instances[0]) {
+            Some(&id) => id,
+            None => {
+                let id = self.next_id;
+                self.next_id += 1;
+                self.ids.insert(instances[0], id);
+                id
+            }
+        };
+        self.nodes.insert(id, Node { parent, name: name.to_vec(), instances });
+        id
+    }
+
+    /// Records that `id` now has the upper instance `upper`.
+    ///
+    /// Directories keep merging with their lower instances. Other objects are served
+    /// from the copy alone, and their lower instance is released so that further names
+    /// of a lower hard link resolve to the original rather than to the copy.
+    pub fn copied_up(&mut self, id: nfs3::fileid3, upper: nfs3::fileid3, is_dir: bool) {
+        let Some(node) = self.nodes.get_mut(&id) else {
+            return;
+        };
+        if is_dir {
+            node.instances.insert(0, (UPPER, upper));
+        } else {
+            for instance in std::mem::replace(&mut node.instances, vec![(UPPER, upper)]) {
+                if self.ids.get(&instance) == Some(&id) {
+                    self.ids.remove(&instance);
+                }
+            }
+        }
+        self.ids.insert((UPPER, upper), id);
+    }
+
+    /// Moves `id` to `name` in `parent`, leaving it with the upper instance only.
+    pub fn moved(&mut self, id: nfs3::fileid3, parent: nfs3::fileid3, name: &[u8]) {
+        if let Some(node) = self.nodes.get_mut(&id) {
+            node.parent = parent;
+            node.name = name.to_vec();
+            node.instances.retain(|&(layer, _)| layer == UPPER);
+        }
+    }
+
+    /// Drops an object that is no longer reachable, making its handles stale.
+    pub fn forget(&mut self, id: nfs3::fileid3) {
+        if let Some(node) = self.nodes.remove(&id) {
+            for instance in node.instances {
+                if self.ids.get(&instance) == Some(&id) {
+                    self.ids.remove(&instance);
+                }
+            }
+        }
+    }
+
+    /// Returns true if `id` is `ancestor` or lies below it.
+    pub fn is_within(&self, mut id: nfs3::fileid3, ancestor: nfs3::fileid3) -> bool {
+        loop {
+            if id == ancestor {
+                return true;
+            }
+            match self.nodes.get(&id) {
+                Some(node) if node.parent != id => id = node.parent,
+                _ => return false,
+            }
+        }
+    }
+}
@@ -6,6 +6,7 @@
 //! - Support structures and enumerations for directory entries and file operations
 //! - File handle management with generation numbers for stale handle detection
 //! - Default implementations for common operations to simplify custom implementations
+//! - A path-addressed API (`path_fs`) with an adapter that manages fileids on behalf of backends
 //!
 //! The VFS layer abstracts the file system operations required by NFS v3 protocol (RFC 1813)
 //! and allows different storage backends to be used with the server. It translates between
@@ -24,6 +25,7 @@ use async_trait::async_trait;
 
 use crate::protocol::xdr::nfs3;
 
+pub mod path_fs;
 pub mod permissions;
 
 /// Simplified directory entry containing only file ID and name
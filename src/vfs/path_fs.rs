@@ -0,0 +1,699 @@
+//! Path-addressed file system API and an adapter that exposes it as an [`NFSFileSystem`].
+//!
+//! Many storage backends naturally address objects by path rather than by a
+//! stable numeric identifier. Implementing [`NFSFileSystem`] directly for them
+//! means inventing a fileid table, keeping it in sync across renames and
+//! detecting when paths disappear underneath the server.
+//!
+//! This module provides:
+//! - The [`PathFileSystem`] trait, whose operations take '/'-separated paths
+//!   relative to the export root (the root itself is the empty path)
+//! - The [`PathFSAdapter`] type, which implements [`NFSFileSystem`] on top of any
+//!   [`PathFileSystem`] by maintaining the fileid table on its behalf
+//!
+//! The adapter takes care of:
+//! - Allocating fileids for paths on first use and keeping them stable
+//! - Rewriting every cached path below a renamed directory
+//! - Aliasing hard links: paths that report the same backend inode number in
+//!   `fattr3::fileid` share a single NFS fileid
+//! - Evicting ids whose paths no longer exist in the backend
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use tracing::debug;
+
+use super::{Capabilities, DirEntry, NFSFileSystem, ReadDirResult};
+use crate::protocol::xdr::nfs3;
+
+/// The basic API to implement to provide a path-addressed file system
+///
+/// Paths are byte strings of '/'-separated components relative to the export
+/// root, without leading or trailing separators. The root directory is the
+/// empty path. Path components never contain '/' and are never "." or "..".
+///
+/// Attributes returned by the backend are translated by [`PathFSAdapter`]
+/// before they reach clients. The `fileid` field is interpreted as a backend
+/// inode number: two paths reporting the same non-zero value are treated as
+/// hard links to the same object. Backends without a notion of inodes should
+/// report 0, in which case every path gets its own fileid.
+///
+/// Write operations have default implementations returning `NFS3ERR_ROFS` or
+/// `NFS3ERR_NOTSUPP`, so read-only backends only need to implement the
+/// lookup and read side of the API.
+#[async_trait]
+pub trait PathFileSystem: Sync {
+    /// Returns the set of capabilities supported by this file system implementation
+    fn capabilities(&self) -> Capabilities;
+
+    /// Returns the attributes of the object at `path`
+    ///
+    /// This is also used to resolve lookups, so it must return `NFS3ERR_NOENT`
+    /// for paths that do not exist.
+    async fn getattr(&self, path: &[u8]) -> Result<nfs3::fattr3, nfs3::nfsstat3>;
+
+    /// Lists the contents of the directory at `path`
+    ///
+    /// The returned entries must not include "." or "..", and should be in a
+    /// stable order across calls so that READDIR cookies remain meaningful.
+    async fn readdir(
+        &self,
+        path: &[u8],
+    ) -> Result<Vec<(nfs3::filename3, nfs3::fattr3)>, nfs3::nfsstat3>;
+
+    /// Reads up to `count` bytes from the file at `path` starting at `offset`
+    ///
+    /// The returned boolean indicates whether the end of file was reached.
+    async fn read(
+        &self,
+        path: &[u8],
+        offset: u64,
+        count: u32,
+    ) -> Result<(Vec<u8>, bool), nfs3::nfsstat3>;
+
+    /// Sets the attributes of the object at `path`
+    async fn setattr(
+        &self,
+        _path: &[u8],
+        _setattr: nfs3::sattr3,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    /// Writes `data` to the file at `path` starting at `offset`
+    async fn write(
+        &self,
+        _path: &[u8],
+        _offset: u64,
+        _data: &[u8],
+        _stable: nfs3::file::stable_how,
+    ) -> Result<(nfs3::fattr3, nfs3::file::stable_how, nfs3::count3), nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    /// Creates a regular file at `path`
+    async fn create(
+        &self,
+        _path: &[u8],
+        _attr: nfs3::sattr3,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    /// Creates a regular file at `path` if it does not exist yet
+    ///
+    /// Implementations must store the verifier so that a retried request with
+    /// the same verifier succeeds instead of returning `NFS3ERR_EXIST`.
+    async fn create_exclusive(
+        &self,
+        _path: &[u8],
+        _verifier: nfs3::createverf3,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_NOTSUPP)
+    }
+
+    /// Creates a directory at `path`
+    async fn mkdir(&self, _path: &[u8]) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    /// Removes the file or empty directory at `path`
+    async fn remove(&self, _path: &[u8]) -> Result<(), nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    /// Renames `from` to `to`, replacing `to` if it exists
+    async fn rename(&self, _from: &[u8], _to: &[u8]) -> Result<(), nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    /// Creates a symbolic link at `path` pointing to `target`
+    async fn symlink(
+        &self,
+        _path: &[u8],
+        _target: &nfs3::nfspath3,
+        _attr: &nfs3::sattr3,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_NOTSUPP)
+    }
+
+    /// Reads the target of the symbolic link at `path`
+    async fn readlink(&self, _path: &[u8]) -> Result<nfs3::nfspath3, nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_NOTSUPP)
+    }
+
+    /// Creates a hard link at `to` for the existing object at `from`
+    ///
+    /// Returns the updated attributes of the linked object. The returned
+    /// `fileid` should be the shared backend inode number.
+    async fn link(&self, _from: &[u8], _to: &[u8]) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_NOTSUPP)
+    }
+
+    /// Creates a special node at `path`
+    async fn mknod(
+        &self,
+        _path: &[u8],
+        _ftype: nfs3::ftype3,
+        _specdata: nfs3::specdata3,
+        _attrs: &nfs3::sattr3,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_NOTSUPP)
+    }
+
+    /// Commits previously written data of the file at `path` to stable storage
+    async fn commit(
+        &self,
+        path: &[u8],
+        _offset: u64,
+        _count: u32,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        self.getattr(path).await
+    }
+}
+
+/// Joins a directory path and a single component
+fn join_path(dir: &[u8], name: &[u8]) -> Vec<u8> {
+    let mut path = Vec::with_capacity(dir.len() + name.len() + 1);
+    path.extend_from_slice(dir);
+    if !dir.is_empty() {
+        path.push(b'/');
+    }
+    path.extend_from_slice(name);
+    path
+}
+
+/// Returns the parent of a path, or the root for top-level entries
+fn parent_path(path: &[u8]) -> &[u8] {
+    match path.iter().rposition(|&c| c == b'/') {
+        Some(idx) => &path[..idx],
+        None => &[],
+    }
+}
+
+/// Returns `true` if `path` is `prefix` or lies below it
+fn path_has_prefix(path: &[u8], prefix: &[u8]) -> bool {
+    if prefix.is_empty() {
+        return true;
+    }
+    path.starts_with(prefix) && (path.len() == prefix.len() || path[prefix.len()] == b'/')
+}
+
+/// Rejects names that cannot be used as a single path component
+fn check_component(name: &[u8]) -> Result<(), nfs3::nfsstat3> {
+    if name.is_empty() || name.contains(&b'/') || name.contains(&0) {
+        return Err(nfs3::nfsstat3::NFS3ERR_INVAL);
+    }
+    Ok(())
+}
+
+/// A known object and all the paths it is reachable through
+#[derive(Debug)]
+struct PathNode {
+    /// Preferred path used to address the object in the backend
+    primary: Vec<u8>,
+    /// All known paths (hard link aliases), including the primary one
+    aliases: BTreeSet<Vec<u8>>,
+    /// Backend inode number, 0 if the backend does not report one
+    inode: u64,
+}
+
+/// Bidirectional mapping between fileids and backend paths
+#[derive(Debug)]
+struct PathTable {
+    next_fileid: nfs3::fileid3,
+    id_to_node: HashMap<nfs3::fileid3, PathNode>,
+    path_to_id: HashMap<Vec<u8>, nfs3::fileid3>,
+    inode_to_id: HashMap<u64, nfs3::fileid3>,
+}
+
+impl PathTable {
+    fn new(root_id: nfs3::fileid3) -> Self {
+        let root =
+            PathNode { primary: Vec::new(), aliases: BTreeSet::from([Vec::new()]), inode: 0 };
+        Self {
+            next_fileid: root_id + 1,
+            id_to_node: HashMap::from([(root_id, root)]),
+            path_to_id: HashMap::from([(Vec::new(), root_id)]),
+            inode_to_id: HashMap::new(),
+        }
+    }
+
+    fn path_of(&self, id: nfs3::fileid3) -> Result<Vec<u8>, nfs3::nfsstat3> {
+        self.id_to_node
+            .get(&id)
+            .map(|node| node.primary.clone())
+            .ok_or(nfs3::nfsstat3::NFS3ERR_STALE)
+    }
+
+    fn aliases_of(&self, id: nfs3::fileid3) -> Result<Vec<Vec<u8>>, nfs3::nfsstat3> {
+        let node = self.id_to_node.get(&id).ok_or(nfs3::nfsstat3::NFS3ERR_STALE)?;
+        let mut aliases = vec![node.primary.clone()];
+        aliases.extend(node.aliases.iter().filter(|alias| **alias != node.primary).cloned());
+        Ok(aliases)
+    }
+
+    /// Records that `path` currently refers to an object with the given backend inode
+    fn register(&mut self, path: &[u8], inode: u64) -> nfs3::fileid3 {
+        if let Some(id) = self.path_to_id.get(path).copied() {
+            let node = &self.id_to_node[&id];
+            let known = inode != 0 && self.inode_to_id.contains_key(&inode);
+            if path.is_empty() || inode == 0 || node.inode == inode {
+                return id;
+            }
+            if node.inode == 0 && !known {
+                self.id_to_node.get_mut(&id).unwrap().inode = inode;
+                self.inode_to_id.insert(inode, id);
+                return id;
+            }
+            // The path now refers to a different object (e.g. it was replaced), or
+            // to one already known under another name.
+            self.unregister_path(path);
+        }
+
+        if inode != 0 {
+            if let Some(id) = self.inode_to_id.get(&inode).copied() {
+                if let Some(node) = self.id_to_node.get_mut(&id) {
+                    node.aliases.insert(path.to_vec());
+                    self.path_to_id.insert(path.to_vec(), id);
+                    return id;
+                }
+            }
+        }
+
+        let id = self.next_fileid;
+        self.next_fileid += 1;
+        let node =
+            PathNode { primary: path.to_vec(), aliases: BTreeSet::from([path.to_vec()]), inode };
+        self.id_to_node.insert(id, node);
+        self.path_to_id.insert(path.to_vec(), id);
+        if inode != 0 {
+            self.inode_to_id.insert(inode, id);
+        }
+        id
+    }
+
+    /// Forgets a single path; the id is evicted once it has no paths left
+    fn unregister_path(&mut self, path: &[u8]) -> Option<nfs3::fileid3> {
+        if path.is_empty() {
+            return None;
+        }
+        let id = self.path_to_id.remove(path)?;
+        if let Some(node) = self.id_to_node.get_mut(&id) {
+            node.aliases.remove(path);
+            if node.primary == path {
+                if let Some(next) = node.aliases.iter().next() {
+                    node.primary = next.clone();
+                }
+            }
+            if node.aliases.is_empty() {
+                let node = self.id_to_node.remove(&id).unwrap();
+                if node.inode != 0 {
+                    self.inode_to_id.remove(&node.inode);
+                }
+                debug!("evicting fileid {:?}", id);
+            }
+        }
+        Some(id)
+    }
+
+    /// Forgets `path` and every path below it
+    fn unregister_tree(&mut self, path: &[u8]) {
+        let doomed: Vec<Vec<u8>> =
+            self.path_to_id.keys().filter(|p| path_has_prefix(p, path)).cloned().collect();
+        for p in doomed {
+            self.unregister_path(&p);
+        }
+    }
+
+    /// Rewrites `from` and every path below it to live under `to`
+    fn rename_prefix(&mut self, from: &[u8], to: &[u8]) {
+        let moved: Vec<(Vec<u8>, nfs3::fileid3)> = self
+            .path_to_id
+            .iter()
+            .filter(|(p, _)| path_has_prefix(p, from))
+            .map(|(p, id)| (p.clone(), *id))
+            .collect();
+        for (old_path, id) in moved {
+            let mut new_path = to.to_vec();
+            new_path.extend_from_slice(&old_path[from.len()..]);
+            self.path_to_id.remove(&old_path);
+            self.path_to_id.insert(new_path.clone(), id);
+            if let Some(node) = self.id_to_node.get_mut(&id) {
+                node.aliases.remove(&old_path);
+                node.aliases.insert(new_path.clone());
+                if node.primary == old_path {
+                    node.primary = new_path;
+                }
+            }
+        }
+    }
+}
+
+/// Exposes a [`PathFileSystem`] as an [`NFSFileSystem`]
+///
+/// The adapter owns the fileid table. Fileids are allocated on first sight of
+/// a path and remain stable for the lifetime of the adapter, which makes them
+/// valid for as long as the generation number used in file handles.
+#[derive(Debug)]
+pub struct PathFSAdapter<T: PathFileSystem> {
+    inner: T,
+    table: Mutex<PathTable>,
+    generation: u64,
+}
+
+/// The fileid assigned to the export root
+const ROOT_ID: nfs3::fileid3 = 1;
+
+impl<T: PathFileSystem> PathFSAdapter<T> {
+    /// Creates an adapter for the given path-addressed file system
+    pub fn new(inner: T) -> Self {
+        let now = SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis();
+        Self { inner, table: Mutex::new(PathTable::new(ROOT_ID)), generation: now as u64 }
+    }
+
+    /// Returns a reference to the wrapped file system
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns the current path of a fileid, if it is known
+    pub fn id_to_path(&self, id: nfs3::fileid3) -> Option<Vec<u8>> {
+        self.table.lock().unwrap().path_of(id).ok()
+    }
+
+    fn child_path(&self, dirid: nfs3::fileid3, name: &[u8]) -> Result<Vec<u8>, nfs3::nfsstat3> {
+        check_component(name)?;
+        let dir = self.table.lock().unwrap().path_of(dirid)?;
+        Ok(join_path(&dir, name))
+    }
+
+    /// Registers a freshly observed path and rewrites the attribute fileid
+    fn register(&self, path: &[u8], mut attr: nfs3::fattr3) -> (nfs3::fileid3, nfs3::fattr3) {
+        let id = self.table.lock().unwrap().register(path, attr.fileid);
+        attr.fileid = id;
+        (id, attr)
+    }
+
+    /// Fetches attributes for an id, trying every alias and evicting dead ones
+    async fn resolve(&self, id: nfs3::fileid3) -> Result<(Vec<u8>, nfs3::fattr3), nfs3::nfsstat3> {
+        let aliases = self.table.lock().unwrap().aliases_of(id)?;
+        for path in aliases {
+            match self.inner.getattr(&path).await {
+                Ok(mut attr) => {
+                    let mut table = self.table.lock().unwrap();
+                    if table.register(&path, attr.fileid) != id {
+                        // Replaced by a different object since we last looked.
+                        continue;
+                    }
+                    if let Some(node) = table.id_to_node.get_mut(&id) {
+                        node.primary = path.clone();
+                    }
+                    attr.fileid = id;
+                    return Ok((path, attr));
+                }
+                Err(nfs3::nfsstat3::NFS3ERR_NOENT) => {
+                    self.table.lock().unwrap().unregister_path(&path);
+                }
+                Err(stat) => return Err(stat),
+            }
+        }
+        Err(nfs3::nfsstat3::NFS3ERR_STALE)
+    }
+}
+
+#[async_trait]
+impl<T: PathFileSystem + Send + Sync> NFSFileSystem for PathFSAdapter<T> {
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn root_dir(&self) -> nfs3::fileid3 {
+        ROOT_ID
+    }
+
+    async fn lookup(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        let (dir, dir_attr) = self.resolve(dirid).await?;
+        if !matches!(dir_attr.ftype, nfs3::ftype3::NF3DIR) {
+            return Err(nfs3::nfsstat3::NFS3ERR_NOTDIR);
+        }
+        match filename.as_ref() {
+            b"." => return Ok(dirid),
+            b".." => {
+                let parent = parent_path(&dir).to_vec();
+                let attr = self.inner.getattr(&parent).await?;
+                return Ok(self.register(&parent, attr).0);
+            }
+            _ => {}
+        }
+        check_component(filename)?;
+        let path = join_path(&dir, filename);
+        match self.inner.getattr(&path).await {
+            Ok(attr) => Ok(self.register(&path, attr).0),
+            Err(nfs3::nfsstat3::NFS3ERR_NOENT) => {
+                self.table.lock().unwrap().unregister_tree(&path);
+                Err(nfs3::nfsstat3::NFS3ERR_NOENT)
+            }
+            Err(stat) => Err(stat),
+        }
+    }
+
+    async fn getattr(&self, id: nfs3::fileid3) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        Ok(self.resolve(id).await?.1)
+    }
+
+    async fn setattr(
+        &self,
+        id: nfs3::fileid3,
+        setattr: nfs3::sattr3,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        let (path, _) = self.resolve(id).await?;
+        let mut attr = self.inner.setattr(&path, setattr).await?;
+        attr.fileid = id;
+        Ok(attr)
+    }
+
+    async fn read(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        count: u32,
+    ) -> Result<(Vec<u8>, bool), nfs3::nfsstat3> {
+        let (path, _) = self.resolve(id).await?;
+        self.inner.read(&path, offset, count).await
+    }
+
+    async fn write(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+        stable: nfs3::file::stable_how,
+    ) -> Result<(nfs3::fattr3, nfs3::file::stable_how, nfs3::count3), nfs3::nfsstat3> {
+        let (path, _) = self.resolve(id).await?;
+        let (mut attr, committed, count) = self.inner.write(&path, offset, data, stable).await?;
+        attr.fileid = id;
+        Ok((attr, committed, count))
+    }
+
+    async fn create(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        attr: nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        let path = self.child_path(dirid, filename)?;
+        let attr = self.inner.create(&path, attr).await?;
+        Ok(self.register(&path, attr))
+    }
+
+    async fn create_exclusive(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        verifier: nfs3::createverf3,
+    ) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        let path = self.child_path(dirid, filename)?;
+        let attr = self.inner.create_exclusive(&path, verifier).await?;
+        Ok(self.register(&path, attr).0)
+    }
+
+    async fn mkdir(
+        &self,
+        dirid: nfs3::fileid3,
+        dirname: &nfs3::filename3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        let path = self.child_path(dirid, dirname)?;
+        let attr = self.inner.mkdir(&path).await?;
+        Ok(self.register(&path, attr))
+    }
+
+    async fn remove(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> Result<(), nfs3::nfsstat3> {
+        let path = self.child_path(dirid, filename)?;
+        self.inner.remove(&path).await?;
+        self.table.lock().unwrap().unregister_tree(&path);
+        Ok(())
+    }
+
+    async fn rename(
+        &self,
+        from_dirid: nfs3::fileid3,
+        from_filename: &nfs3::filename3,
+        to_dirid: nfs3::fileid3,
+        to_filename: &nfs3::filename3,
+    ) -> Result<(), nfs3::nfsstat3> {
+        let from = self.child_path(from_dirid, from_filename)?;
+        let to = self.child_path(to_dirid, to_filename)?;
+        if from == to {
+            self.inner.getattr(&from).await?;
+            return Ok(());
+        }
+        if path_has_prefix(&to, &from) {
+            // Cannot move a directory into itself.
+            return Err(nfs3::nfsstat3::NFS3ERR_INVAL);
+        }
+        self.inner.rename(&from, &to).await?;
+        let mut table = self.table.lock().unwrap();
+        table.unregister_tree(&to);
+        table.rename_prefix(&from, &to);
+        Ok(())
+    }
+
+    async fn readdir(
+        &self,
+        dirid: nfs3::fileid3,
+        start_after: nfs3::fileid3,
+        max_entries: usize,
+    ) -> Result<ReadDirResult, nfs3::nfsstat3> {
+        let (dir, dir_attr) = self.resolve(dirid).await?;
+        if !matches!(dir_attr.ftype, nfs3::ftype3::NF3DIR) {
+            return Err(nfs3::nfsstat3::NFS3ERR_NOTDIR);
+        }
+        let listing = self.inner.readdir(&dir).await?;
+
+        let parent = parent_path(&dir).to_vec();
+        let (parent_id, parent_attr) = if dir.is_empty() {
+            (dirid, dir_attr)
+        } else {
+            let attr = self.inner.getattr(&parent).await?;
+            self.register(&parent, attr)
+        };
+
+        let mut all_entries = Vec::with_capacity(listing.len() + 2);
+        all_entries.push(DirEntry { fileid: dirid, name: b".".as_slice().into(), attr: dir_attr });
+        all_entries.push(DirEntry {
+            fileid: parent_id,
+            name: b"..".as_slice().into(),
+            attr: parent_attr,
+        });
+        for (name, attr) in listing {
+            let path = join_path(&dir, &name);
+            let (fileid, attr) = self.register(&path, attr);
+            all_entries.push(DirEntry { fileid, name, attr });
+        }
+
+        let start_index =
+            usize::try_from(start_after).map_err(|_| nfs3::nfsstat3::NFS3ERR_BAD_COOKIE)?;
+        if start_index > all_entries.len() {
+            return Err(nfs3::nfsstat3::NFS3ERR_BAD_COOKIE);
+        }
+        let remaining = all_entries.len() - start_index;
+        let entries: Vec<DirEntry> =
+            all_entries.into_iter().skip(start_index).take(max_entries).collect();
+        let end = entries.len() == remaining;
+        Ok(ReadDirResult { entries, end })
+    }
+
+    async fn readdir_index(
+        &self,
+        dirid: nfs3::fileid3,
+        start_index: usize,
+        max_entries: usize,
+    ) -> Result<ReadDirResult, nfs3::nfsstat3> {
+        self.readdir(dirid, start_index as nfs3::fileid3, max_entries).await
+    }
+
+    async fn symlink(
+        &self,
+        dirid: nfs3::fileid3,
+        linkname: &nfs3::filename3,
+        symlink: &nfs3::nfspath3,
+        attr: &nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        let path = self.child_path(dirid, linkname)?;
+        let attr = self.inner.symlink(&path, symlink, attr).await?;
+        Ok(self.register(&path, attr))
+    }
+
+    async fn readlink(&self, id: nfs3::fileid3) -> Result<nfs3::nfspath3, nfs3::nfsstat3> {
+        let (path, _) = self.resolve(id).await?;
+        self.inner.readlink(&path).await
+    }
+
+    async fn link(
+        &self,
+        file_id: nfs3::fileid3,
+        link_dir_id: nfs3::fileid3,
+        link_name: &nfs3::filename3,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        let (from, _) = self.resolve(file_id).await?;
+        let to = self.child_path(link_dir_id, link_name)?;
+        let mut attr = self.inner.link(&from, &to).await?;
+        let mut table = self.table.lock().unwrap();
+        if table.path_to_id.get(&to).is_some_and(|id| *id != file_id) {
+            // The name was replaced behind the adapter's back; forget what it named.
+            table.unregister_tree(&to);
+        }
+        if let Some(node) = table.id_to_node.get_mut(&file_id) {
+            if node.inode == 0 && attr.fileid != 0 {
+                node.inode = attr.fileid;
+            }
+            node.aliases.insert(to.clone());
+        }
+        if attr.fileid != 0 {
+            table.inode_to_id.insert(attr.fileid, file_id);
+        }
+        table.path_to_id.insert(to, file_id);
+        attr.fileid = file_id;
+        Ok(attr)
+    }
+
+    async fn mknod(
+        &self,
+        dir_id: nfs3::fileid3,
+        name: &nfs3::filename3,
+        ftype: nfs3::ftype3,
+        specdata: nfs3::specdata3,
+        attrs: &nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        let path = self.child_path(dir_id, name)?;
+        let attr = self.inner.mknod(&path, ftype, specdata, attrs).await?;
+        Ok(self.register(&path, attr))
+    }
+
+    async fn commit(
+        &self,
+        file_id: nfs3::fileid3,
+        offset: u64,
+        count: u32,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        let (path, _) = self.resolve(file_id).await?;
+        let mut attr = self.inner.commit(&path, offset, count).await?;
+        attr.fileid = file_id;
+        Ok(attr)
+    }
+}
@@ -0,0 +1,160 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use fernfs::archive_fs::{ArchiveFS, ArchiveFormat};
+use fernfs::vfs::{Capabilities, NFSFileSystem};
+use fernfs::xdr::nfs3;
+
+struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    fn new(prefix: &str) -> std::io::Result<Self> {
+        let mut path = std::env::temp_dir();
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+        path.push(format!("fernfs_{prefix}_{nanos}"));
+        std::fs::create_dir(&path)?;
+        Ok(Self { path })
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Contents of `big.bin`: large enough to need several reads, with a recognizable pattern.
+fn big_contents() -> Vec<u8> {
+    (0..300_000u32).map(|i| (i % 251) as u8).collect()
+}
+
+fn header(entry_type: tar::EntryType, size: u64, mode: u32) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(entry_type);
+    header.set_size(size);
+    header.set_mode(mode);
+    header.set_uid(1000);
+    header.set_gid(100);
+    header.set_mtime(1_700_000_000);
+    header
+}
+
+fn tar_image() -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    let tool = b"#!/bin/sh\necho hi\n";
+    let mut append = |entry_type, path: &str, data: &[u8], mode| {
+        let mut header = header(entry_type, data.len() as u64, mode);
+        builder.append_data(&mut header, path, data).unwrap();
+    };
+    append(tar::EntryType::Directory, "./bin/", b"", 0o750);
+    append(tar::EntryType::Regular, "./bin/tool", tool, 0o755);
+    append(tar::EntryType::Regular, "docs/guide/big.bin", &big_contents(), 0o644);
+    let mut link = header(tar::EntryType::Symlink, 0, 0o777);
+    builder.append_link(&mut link, "latest", "bin/tool").unwrap();
+    let mut hard = header(tar::EntryType::Link, 0, 0o755);
+    builder.append_link(&mut hard, "bin/tool-copy", "bin/tool").unwrap();
+    builder.into_inner().unwrap()
+}
+
+async fn check_tree(fs: &ArchiveFS) -> Result<(), nfs3::nfsstat3> {
+    assert!(matches!(fs.capabilities(), Capabilities::ReadOnly));
+    let root = fs.root_dir();
+    let tool = fs.path_to_id(b"bin/tool").await?;
+    let attr = fs.getattr(tool).await?;
+    assert_eq!((attr.mode, attr.uid, attr.gid, attr.nlink), (0o755, 1000, 100, 2));
+    assert_eq!(attr.mtime.seconds, 1_700_000_000);
+    assert_eq!(fs.read(tool, 0, 100).await?, (b"#!/bin/sh\necho hi\n".to_vec(), true));
+    assert_eq!(fs.path_to_id(b"bin/tool-copy").await?, tool);
+
+    let bin = fs.lookup(root, &b"bin".as_slice().into()).await?;
+    assert_eq!(fs.getattr(bin).await?.mode, 0o750);
+    assert_eq!(fs.lookup(bin, &b"..".as_slice().into()).await?, root);
+
+    // "docs" and "docs/guide" only exist implicitly.
+    let big = fs.path_to_id(b"docs/guide/big.bin").await?;
+    let expected = big_contents();
+    let (data, eof) = fs.read(big, 123_456, 70_000).await?;
+    assert_eq!(data, &expected[123_456..193_456]);
+    assert!(!eof);
+    let (data, eof) = fs.read(big, 299_990, 100).await?;
+    assert_eq!(data, &expected[299_990..]);
+    assert!(eof);
+
+    let latest = fs.lookup(root, &b"latest".as_slice().into()).await?;
+    assert!(matches!(fs.getattr(latest).await?.ftype, nfs3::ftype3::NF3LNK));
+    assert_eq!(fs.readlink(latest).await?.as_slice(), b"bin/tool");
+
+    let names: Vec<_> =
+        fs.readdir(root, 0, 10).await?.entries.into_iter().map(|e| e.name.to_vec()).collect();
+    assert_eq!(names, [&b"."[..], b"..", b"bin", b"docs", b"latest"]);
+
+    let stable = nfs3::file::stable_how::FILE_SYNC;
+    assert_eq!(fs.write(tool, 0, b"x", stable).await.unwrap_err(), nfs3::nfsstat3::NFS3ERR_ROFS);
+    let err = fs.remove(root, &b"latest".as_slice().into()).await.unwrap_err();
+    assert_eq!(err, nfs3::nfsstat3::NFS3ERR_ROFS);
+    Ok(())
+}
+
+#[tokio::test]
+async fn serves_plain_and_compressed_tarballs() -> Result<(), nfs3::nfsstat3> {
+    let temp = TempDir::new("archive_tar").expect("temp dir");
+    let image = tar_image();
+
+    let plain = temp.path.join("artifacts.tar");
+    std::fs::write(&plain, &image).expect("write tar");
+    let gz = temp.path.join("artifacts.tar.gz");
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&image).expect("gzip");
+    std::fs::write(&gz, encoder.finish().expect("gzip")).expect("write tar.gz");
+    let zst = temp.path.join("artifacts.tar.zst");
+    std::fs::write(&zst, zstd::encode_all(&image[..], 3).expect("zstd")).expect("write tar.zst");
+
+    for (path, format) in
+        [(plain, ArchiveFormat::Tar), (gz, ArchiveFormat::TarGz), (zst, ArchiveFormat::TarZstd)]
+    {
+        assert_eq!(ArchiveFormat::detect(&path).expect("detect"), format);
+        check_tree(&ArchiveFS::open(&path).expect("open archive")).await?;
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn serves_zip_files() -> Result<(), nfs3::nfsstat3> {
+    let temp = TempDir::new("archive_zip").expect("temp dir");
+    let path = temp.path.join("site.zip");
+    let mut writer = zip::ZipWriter::new(std::fs::File::create(&path).expect("create zip"));
+    let stored =
+        zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    let deflated = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated)
+        .unix_permissions(0o600);
+    writer.add_directory("assets/", stored).expect("add dir");
+    writer.start_file("assets/raw.txt", stored).expect("add file");
+    writer.write_all(b"stored bytes").expect("write");
+    writer.start_file("index.html", deflated).expect("add file");
+    writer.write_all(&big_contents()).expect("write");
+    writer.add_symlink("home.html", "index.html", stored).expect("add symlink");
+    writer.finish().expect("finish zip");
+
+    let fs = ArchiveFS::open(&path).expect("open zip");
+    let raw = fs.path_to_id(b"assets/raw.txt").await?;
+    assert_eq!(fs.read(raw, 7, 100).await?, (b"bytes".to_vec(), true));
+    let index = fs.path_to_id(b"index.html").await?;
+    assert_eq!(fs.getattr(index).await?.mode, 0o600);
+    assert_eq!(fs.read(index, 1000, 500).await?.0, &big_contents()[1000..1500]);
+    let home = fs.path_to_id(b"home.html").await?;
+    assert_eq!(fs.readlink(home).await?.as_slice(), b"index.html");
+    Ok(())
+}
+
+#[test]
+fn rejects_unknown_formats() {
+    let temp = TempDir::new("archive_unknown").expect("temp dir");
+    let path = temp.path.join("notes.txt");
+    std::fs::write(&path, b"just some text").expect("write");
+    let err = ArchiveFS::open(&path).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,375 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use fernfs::composite_fs::CompositeFS;
+use fernfs::conformance::ConformanceSuite;
+use fernfs::mem_fs::MemFS;
+use fernfs::vfs::{Capabilities, NFSFileSystem, ReadDirResult};
+use fernfs::xdr::nfs3;
+
+fn name(name: &str) -> nfs3::filename3 {
+    name.as_bytes().into()
+}
+
+/// A MemFS holding a single file whose name is also its contents.
+async fn child(file: &str) -> Result<Arc<MemFS>, nfs3::nfsstat3> {
+    let fs = MemFS::new();
+    let (id, _) = fs.create(fs.root_dir(), &name(file), nfs3::sattr3::default()).await?;
+    fs.write(id, 0, file.as_bytes(), nfs3::file::stable_how::FILE_SYNC).await?;
+    Ok(Arc::new(fs))
+}
+
+#[tokio::test]
+async fn passes_conformance_suite() {
+    ConformanceSuite::new(|| {
+        CompositeFS::new(Arc::new(MemFS::new())).graft("mnt", Arc::new(MemFS::new()))
+    })
+    .run()
+    .await
+    .assert_passed();
+}
+
+#[tokio::test]
+async fn lookups_cross_graft_points() -> Result<(), nfs3::nfsstat3> {
+    let parent = MemFS::new();
+    let (srv, _) = parent.mkdir(parent.root_dir(), &name("srv")).await?;
+    parent.create(srv, &name("readme"), nfs3::sattr3::default()).await?;
+    // "data" exists in the parent and is shadowed; "data/nested" exists nowhere.
+    parent.mkdir(srv, &name("data")).await?;
+    let fs = CompositeFS::new(Arc::new(parent))
+        .graft("srv/data/nested", child("inner").await?)
+        .graft("/srv/data/", child("outer").await?);
+
+    let srv = fs.path_to_id(b"srv").await?;
+    let data = fs.lookup(srv, &name("data")).await?;
+    let outer = fs.path_to_id(b"srv/data/outer").await?;
+    let inner = fs.path_to_id(b"srv/data/nested/inner").await?;
+    assert_eq!(fs.read(outer, 0, 10).await?.0, b"outer");
+    assert_eq!(fs.read(inner, 0, 10).await?.0, b"inner");
+    let mut attrs = Vec::new();
+    for id in [srv, data, outer, inner] {
+        attrs.push(fs.getattr(id).await?);
+    }
+    assert_eq!(attrs.iter().map(|a| a.fsid).collect::<Vec<_>>(), [0, 2, 2, 1]);
+    assert_eq!(attrs.iter().map(|a| a.fileid).collect::<Vec<_>>(), [srv, data, outer, inner]);
+
+    // ".." at a subtree root leads back to the graft point's directory.
+    let nested = fs.path_to_id(b"srv/data/nested").await?;
+    assert_eq!(fs.lookup(nested, &name("..")).await?, data);
+    assert_eq!(fs.lookup(data, &name("..")).await?, srv);
+    Ok(())
+}
+
+#[tokio::test]
+async fn readdir_lists_graft_points() -> Result<(), nfs3::nfsstat3> {
+    let parent = MemFS::new();
+    parent.mkdir(parent.root_dir(), &name("existing")).await?;
+    let fs = CompositeFS::new(Arc::new(parent))
+        .graft("existing", child("a").await?)
+        .graft("synthetic", child("b").await?);
+    let root = fs.root_dir();
+
+    let entries = fs.readdir(root, 0, 10).await?.entries;
+    let listed: Vec<_> =
+        entries.iter().map(|e| String::from_utf8_lossy(&e.name).into_owned()).collect();
+    assert_eq!(listed, [".", "..", "existing", "synthetic"]);
+    for entry in &entries[2..] {
+        assert_eq!(fs.lookup(root, &entry.name).await?, entry.fileid);
+        assert_eq!(
+            (entry.attr.fileid, entry.attr.fsid),
+            (entry.fileid, fs.getattr(entry.fileid).await?.fsid)
+        );
+        assert_ne!(entry.attr.fsid, 0);
+    }
+    let page = fs.readdir(root, 3, 10).await?;
+    assert_eq!(page.entries.len(), 1);
+    assert_eq!(&page.entries[0].name[..], b"synthetic");
+    assert!(page.end);
+
+    let synthetic = entries[3].fileid;
+    let child_entries = fs.readdir(synthetic, 0, 10).await?.entries;
+    assert_eq!(child_entries[1].fileid, root, "\"..\" must leave the subtree");
+    Ok(())
+}
+
+#[tokio::test]
+async fn graft_points_are_protected() -> Result<(), nfs3::nfsstat3> {
+    let fs = CompositeFS::new(Arc::new(MemFS::new())).graft("mnt", child("file").await?);
+    let root = fs.root_dir();
+    let mnt = fs.lookup(root, &name("mnt")).await?;
+    let file = fs.lookup(mnt, &name("file")).await?;
+
+    let err = fs.remove(root, &name("mnt")).await.unwrap_err();
+    assert_eq!(err, nfs3::nfsstat3::NFS3ERR_ACCES);
+    let err = fs.rename(root, &name("mnt"), root, &name("moved")).await.unwrap_err();
+    assert_eq!(err, nfs3::nfsstat3::NFS3ERR_ACCES);
+    let err = fs.mkdir(root, &name("mnt")).await.unwrap_err();
+    assert_eq!(err, nfs3::nfsstat3::NFS3ERR_EXIST);
+
+    fs.create(root, &name("top"), nfs3::sattr3::default()).await?;
+    let err = fs.rename(root, &name("top"), mnt, &name("top")).await.unwrap_err();
+    assert_eq!(err, nfs3::nfsstat3::NFS3ERR_XDEV);
+    let err = fs.link(file, root, &name("alias")).await.unwrap_err();
+    assert_eq!(err, nfs3::nfsstat3::NFS3ERR_XDEV);
+    Ok(())
+}
+
+/// A MemFS whose fileids need all 64 bits and whose regular files report
+/// another fsid than its directories, like mounts below an exported tree.
+struct WideFS(MemFS);
+
+const WIDE: nfs3::fileid3 = 1 << 63;
+
+fn narrow(id: nfs3::fileid3) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+    if id & WIDE == 0 {
+        return Err(nfs3::nfsstat3::NFS3ERR_STALE);
+    }
+    Ok(id & !WIDE)
+}
+
+fn widen(mut attr: nfs3::fattr3) -> nfs3::fattr3 {
+    attr.fileid |= WIDE;
+    if !matches!(attr.ftype, nfs3::ftype3::NF3DIR) {
+        attr.fsid += 1;
+    }
+    attr
+}
+
+fn widen_new((id, attr): (nfs3::fileid3, nfs3::fattr3)) -> (nfs3::fileid3, nfs3::fattr3) {
+    (id | WIDE, widen(attr))
+}
+
+#[async_trait]
+impl NFSFileSystem for WideFS {
+    fn generation(&self) -> u64 {
+        self.0.generation()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.0.capabilities()
+    }
+
+    fn root_dir(&self) -> nfs3::fileid3 {
+        self.0.root_dir() | WIDE
+    }
+
+    async fn lookup(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        Ok(self.0.lookup(narrow(dirid)?, filename).await? | WIDE)
+    }
+
+    async fn getattr(&self, id: nfs3::fileid3) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        self.0.getattr(narrow(id)?).await.map(widen)
+    }
+
+    async fn setattr(
+        &self,
+        id: nfs3::fileid3,
+        setattr: nfs3::sattr3,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        self.0.setattr(narrow(id)?, setattr).await.map(widen)
+    }
+
+    async fn read(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        count: u32,
+    ) -> Result<(Vec<u8>, bool), nfs3::nfsstat3> {
+        self.0.read(narrow(id)?, offset, count).await
+    }
+
+    async fn write(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+        stable: nfs3::file::stable_how,
+    ) -> Result<(nfs3::fattr3, nfs3::file::stable_how, nfs3::count3), nfs3::nfsstat3> {
+        let (attr, stable, count) = self.0.write(narrow(id)?, offset, data, stable).await?;
+        Ok((widen(attr), stable, count))
+    }
+
+    async fn create(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        attr: nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        self.0.create(narrow(dirid)?, filename, attr).await.map(widen_new)
+    }
+
+    async fn create_exclusive(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        verifier: nfs3::createverf3,
+    ) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        Ok(self.0.create_exclusive(narrow(dirid)?, filename, verifier).await? | WIDE)
+    }
+
+    async fn mkdir(
+        &self,
+        dirid: nfs3::fileid3,
+        dirname: &nfs3::filename3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        self.0.mkdir(narrow(dirid)?, dirname).await.map(widen_new)
+    }
+
+    async fn remove(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> Result<(), nfs3::nfsstat3> {
+        self.0.remove(narrow(dirid)?, filename).await
+    }
+
+    async fn rename(
+        &self,
+        from_dirid: nfs3::fileid3,
+        from_filename: &nfs3::filename3,
+        to_dirid: nfs3::fileid3,
+        to_filename: &nfs3::filename3,
+    ) -> Result<(), nfs3::nfsstat3> {
+        self.0.rename(narrow(from_dirid)?, from_filename, narrow(to_dirid)?, to_filename).await
+    }
+
+    async fn readdir(
+        &self,
+        dirid: nfs3::fileid3,
+        start_after: nfs3::fileid3,
+        max_entries: usize,
+    ) -> Result<ReadDirResult, nfs3::nfsstat3> {
+        let mut result = self.0.readdir(narrow(dirid)?, start_after, max_entries).await?;
+        for entry in &mut result.entries {
+            entry.fileid |= WIDE;
+            entry.attr = widen(entry.attr);
+        }
+        Ok(result)
+    }
+
+    async fn symlink(
+        &self,
+        dirid: nfs3::fileid3,
+        linkname: &nfs3::filename3,
+        symlink: &nfs3::nfspath3,
+        attr: &nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        self.0.symlink(narrow(dirid)?, linkname, symlink, attr).await.map(widen_new)
+    }
+
+    async fn readlink(&self, id: nfs3::fileid3) -> Result<nfs3::nfspath3, nfs3::nfsstat3> {
+        self.0.readlink(narrow(id)?).await
+    }
+
+    async fn link(
+        &self,
+        file_id: nfs3::fileid3,
+        link_dir_id: nfs3::fileid3,
+        link_name: &nfs3::filename3,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        self.0.link(narrow(file_id)?, narrow(link_dir_id)?, link_name).await.map(widen)
+    }
+
+    async fn mknod(
+        &self,
+        dir_id: nfs3::fileid3,
+        name: &nfs3::filename3,
+        ftype: nfs3::ftype3,
+        specdata: nfs3::specdata3,
+        attrs: &nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        self.0.mknod(narrow(dir_id)?, name, ftype, specdata, attrs).await.map(widen_new)
+    }
+
+    async fn commit(
+        &self,
+        file_id: nfs3::fileid3,
+        offset: u64,
+        count: u32,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        self.0.commit(narrow(file_id)?, offset, count).await.map(widen)
+    }
+}
+
+/// A WideFS holding the files `names`, each containing its own name.
+async fn wide(names: &[&str]) -> Result<Arc<WideFS>, nfs3::nfsstat3> {
+    let fs = WideFS(MemFS::new());
+    for file in names {
+        let (id, _) = fs.create(fs.root_dir(), &name(file), nfs3::sattr3::default()).await?;
+        fs.write(id, 0, file.as_bytes(), nfs3::file::stable_how::FILE_SYNC).await?;
+    }
+    Ok(Arc::new(fs))
+}
+
+#[tokio::test]
+async fn grafts_with_fileids_beyond_48_bits_are_served() -> Result<(), nfs3::nfsstat3> {
+    let fs = CompositeFS::new(Arc::new(MemFS::new())).graft("wide", wide(&["file"]).await?);
+    let root = fs.root_dir();
+    let wide = fs.lookup(root, &name("wide")).await?;
+    let file = fs.lookup(wide, &name("file")).await?;
+    assert_ne!(wide, file);
+    assert_eq!(fs.read(file, 0, 10).await?.0, b"file");
+    assert_eq!(fs.getattr(file).await?.fileid, file);
+    assert_eq!(fs.lookup(wide, &name("file")).await?, file, "ids must be stable");
+
+    let entries = fs.readdir(wide, 0, 10).await?.entries;
+    assert_eq!(entries[0].fileid, wide);
+    assert_eq!(entries[1].fileid, root, "\"..\" must leave the subtree");
+    assert_eq!((entries[2].fileid, entries[2].attr.fileid), (file, file));
+    Ok(())
+}
+
+#[tokio::test]
+async fn child_fsids_that_differ_from_their_root_are_kept_apart() -> Result<(), nfs3::nfsstat3> {
+    let fs = CompositeFS::new(Arc::new(MemFS::new())).graft("wide", wide(&["a", "b"]).await?);
+    let root = fs.root_dir();
+    let wide = fs.lookup(root, &name("wide")).await?;
+    let a = fs.lookup(wide, &name("a")).await?;
+    let b = fs.lookup(wide, &name("b")).await?;
+
+    let (root, wide) = (fs.getattr(root).await?.fsid, fs.getattr(wide).await?.fsid);
+    let (a, b) = (fs.getattr(a).await?.fsid, fs.getattr(b).await?.fsid);
+    assert_eq!((root, wide), (0, 1));
+    assert_eq!(a, b, "objects of one child file system share an fsid");
+    assert!(a != root && a != wide, "a file system below the graft keeps its own fsid");
+    Ok(())
+}
+
+#[tokio::test]
+async fn grafts_below_missing_directories_appear_once_created() -> Result<(), nfs3::nfsstat3> {
+    let fs = CompositeFS::new(Arc::new(MemFS::new())).graft("later/mnt", child("file").await?);
+    let root = fs.root_dir();
+    let err = fs.lookup(root, &name("later")).await.unwrap_err();
+    assert_eq!(err, nfs3::nfsstat3::NFS3ERR_NOENT);
+
+    let (later, _) = fs.mkdir(root, &name("later")).await?;
+    let mnt = fs.lookup(later, &name("mnt")).await?;
+    let file = fs.lookup(mnt, &name("file")).await?;
+    assert_eq!(fs.read(file, 0, 10).await?.0, b"file");
+    assert_eq!(fs.getattr(mnt).await?.fsid, 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn forgotten_wide_fileids_are_stale() -> Result<(), nfs3::nfsstat3> {
+    let fs = CompositeFS::new(Arc::new(MemFS::new()))
+        .graft("wide", wide(&["a", "b", "c"]).await?)
+        .max_wide_ids(4);
+    let root = fs.root_dir();
+    let wide = fs.lookup(root, &name("wide")).await?;
+    let a = fs.lookup(wide, &name("a")).await?;
+    fs.lookup(wide, &name("b")).await?;
+    let c = fs.lookup(wide, &name("c")).await?;
+
+    assert_eq!(fs.getattr(a).await.unwrap_err(), nfs3::nfsstat3::NFS3ERR_STALE);
+    assert_eq!(fs.read(c, 0, 10).await?.0, b"c");
+    let again = fs.lookup(wide, &name("a")).await?;
+    assert_ne!(again, a, "a forgotten fileid is never handed out again");
+    assert_eq!(fs.read(again, 0, 10).await?.0, b"a");
+    Ok(())
+}
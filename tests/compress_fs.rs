@@ -0,0 +1,189 @@
+use std::sync::Arc;
+
+use fernfs::compress_fs::CompressFS;
+use fernfs::conformance::ConformanceSuite;
+use fernfs::mem_fs::MemFS;
+use fernfs::vfs::NFSFileSystem;
+use fernfs::xdr::nfs3;
+
+fn name(name: &str) -> nfs3::filename3 {
+    name.as_bytes().into()
+}
+
+fn compress(inner: &Arc<MemFS>) -> CompressFS {
+    CompressFS::new(inner.clone()).chunk_size(4096)
+}
+
+/// Deterministic test data that differs in every chunk.
+fn pattern(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i * 31 + seed as usize) as u8).collect()
+}
+
+/// Log-like data that compresses well.
+fn log_lines(lines: usize) -> Vec<u8> {
+    (0..lines)
+        .flat_map(|i| format!("2026-10-18 INFO request {} served in 3ms\n", i % 50).into_bytes())
+        .collect()
+}
+
+/// Data that does not compress.
+fn noise(len: usize, seed: u64) -> Vec<u8> {
+    let mut state = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn passes_conformance_suite() {
+    ConformanceSuite::new(|| CompressFS::new(Arc::new(MemFS::new()))).run().await.assert_passed();
+}
+
+#[tokio::test]
+async fn random_access_writes_and_truncation() -> Result<(), nfs3::nfsstat3> {
+    let inner = Arc::new(MemFS::new());
+    let fs = compress(&inner);
+    let (id, _) = fs.create(fs.root_dir(), &name("file"), nfs3::sattr3::default()).await?;
+    let mut expected = pattern(10_000, 1);
+    fs.write(id, 0, &expected, nfs3::file::stable_how::FILE_SYNC).await?;
+
+    // Unaligned writes inside, across and past the end of chunks, leaving a gap.
+    for (offset, len, seed) in [(100, 50, 2), (4000, 200, 3), (9990, 30, 4), (20_000, 5, 5)] {
+        let data = pattern(len, seed);
+        let (attr, _, count) =
+            fs.write(id, offset as u64, &data, nfs3::file::stable_how::FILE_SYNC).await?;
+        assert_eq!(count as usize, len);
+        if expected.len() < offset + len {
+            expected.resize(offset + len, 0);
+        }
+        expected[offset..offset + len].copy_from_slice(&data);
+        assert_eq!(attr.size, expected.len() as u64);
+    }
+    assert_eq!(fs.read(id, 0, u32::MAX).await?, (expected.clone(), true));
+    assert_eq!(fs.read(id, 4090, 20).await?.0, expected[4090..4110]);
+    assert_eq!(fs.read(id, 15_000, 10).await?.0, [0; 10]);
+    assert_eq!(fs.getattr(id).await?.size, 20_005);
+
+    // Shrinking then growing again must not bring back the cut-off bytes.
+    for size in [9000, 8192, 20_000, 0, 5000] {
+        let attr = fs.setattr(id, nfs3::sattr3 { size: Some(size), ..Default::default() }).await?;
+        assert_eq!(attr.size, size);
+        expected.resize(size as usize, 0);
+        assert_eq!(fs.read(id, 0, u32::MAX).await?, (expected.clone(), true));
+    }
+    assert_eq!(fs.readdir(fs.root_dir(), 0, 10).await?.entries[2].attr.size, 5000);
+    Ok(())
+}
+
+#[tokio::test]
+async fn stores_logs_compactly() -> Result<(), nfs3::nfsstat3> {
+    let inner = Arc::new(MemFS::new());
+    let fs = CompressFS::new(inner.clone());
+    let log = log_lines(50_000);
+    let (id, _) = fs.create(fs.root_dir(), &name("app.log"), nfs3::sattr3::default()).await?;
+    // Appended in small pieces, the way a logger writes.
+    for (i, piece) in log.chunks(8000).enumerate() {
+        fs.write(id, (i * 8000) as u64, piece, nfs3::file::stable_how::UNSTABLE).await?;
+    }
+
+    let attr = fs.getattr(id).await?;
+    assert_eq!(attr.size, log.len() as u64);
+    let stored = inner.getattr(id).await?.size;
+    assert!(stored * 10 < log.len() as u64, "{} bytes stored for {}", stored, log.len());
+    assert_eq!(fs.read(id, 0, u32::MAX).await?.0, log);
+    assert_eq!(fs.read(id, 1_000_000, 100).await?.0, log[1_000_000..1_000_100]);
+
+    // Until the writes are committed, the stored index still describes an empty file.
+    assert_eq!(CompressFS::new(inner.clone()).getattr(id).await?.size, 0);
+    fs.commit(id, 0, 0).await?;
+    // A fresh wrapper reads the same data from the stored layout alone.
+    let reopened = CompressFS::new(inner.clone());
+    assert_eq!(reopened.read(id, 123_456, 1000).await?.0, log[123_456..124_456]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn uncompressed_files_pass_through() -> Result<(), nfs3::nfsstat3> {
+    let inner = Arc::new(MemFS::new());
+    let (id, _) = inner.create(inner.root_dir(), &name("plain"), nfs3::sattr3::default()).await?;
+    inner.write(id, 0, b"written before compression", nfs3::file::stable_how::FILE_SYNC).await?;
+
+    let fs = compress(&inner);
+    assert_eq!(fs.read(id, 0, 100).await?.0, b"written before compression");
+    fs.write(id, 0, b"WRITTEN", nfs3::file::stable_how::FILE_SYNC).await?;
+    assert_eq!(inner.read(id, 0, 100).await?.0, b"WRITTEN before compression");
+    Ok(())
+}
+
+#[tokio::test]
+async fn damaged_chunks_fail_reads() -> Result<(), nfs3::nfsstat3> {
+    let inner = Arc::new(MemFS::new());
+    let fs = compress(&inner);
+    let (id, _) = fs.create(fs.root_dir(), &name("file"), nfs3::sattr3::default()).await?;
+    fs.write(id, 0, &pattern(8192, 1), nfs3::file::stable_how::FILE_SYNC).await?;
+
+    // The first chunk starts right after the 32-byte header.
+    inner.write(id, 32, &[0xff; 16], nfs3::file::stable_how::FILE_SYNC).await?;
+    let fs = compress(&inner);
+    assert_eq!(fs.read(id, 0, 10).await.unwrap_err(), nfs3::nfsstat3::NFS3ERR_IO);
+    assert_eq!(fs.read(id, 4096, 10).await?.0, pattern(8192, 1)[4096..4106]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn rewrites_are_compacted() -> Result<(), nfs3::nfsstat3> {
+    let inner = Arc::new(MemFS::new());
+    let fs = compress(&inner);
+    let (id, _) = fs.create(fs.root_dir(), &name("file"), nfs3::sattr3::default()).await?;
+    let mut expected = noise(64 * 1024, 1);
+    fs.write(id, 0, &expected, nfs3::file::stable_how::FILE_SYNC).await?;
+
+    // Every rewrite leaves an incompressible chunk behind, 2 MiB in all.
+    for seed in 2..514 {
+        let data = noise(4096, seed);
+        fs.write(id, 8192, &data, nfs3::file::stable_how::FILE_SYNC).await?;
+        expected[8192..12_288].copy_from_slice(&data);
+    }
+    let stored = inner.getattr(id).await?.size;
+    assert!(stored < 1024 * 1024 + 2 * expected.len() as u64, "{stored} bytes stored");
+    assert_eq!(fs.read(id, 0, u32::MAX).await?.0, expected);
+    assert_eq!(compress(&inner).read(id, 0, u32::MAX).await?.0, expected);
+    Ok(())
+}
+
+#[tokio::test]
+async fn unstable_writes_store_the_index_once_committed() -> Result<(), nfs3::nfsstat3> {
+    let inner = Arc::new(MemFS::new());
+    let fs = compress(&inner);
+    let (id, _) = fs.create(fs.root_dir(), &name("file"), nfs3::sattr3::default()).await?;
+    let data = noise(256 * 4096, 1);
+    for (i, chunk) in data.chunks(4096).enumerate() {
+        fs.write(id, (i * 4096) as u64, chunk, nfs3::file::stable_how::UNSTABLE).await?;
+    }
+    // Storing an index per write would have added 12 bytes per chunk each time.
+    let stored = inner.getattr(id).await?.size;
+    assert!(stored < data.len() as u64 + 4096, "{stored} bytes stored");
+
+    fs.commit(id, 0, 0).await?;
+    assert_eq!(compress(&inner).read(id, 0, u32::MAX).await?, (data, true));
+    Ok(())
+}
+
+#[tokio::test]
+async fn headers_pointing_past_the_end_are_damaged() -> Result<(), nfs3::nfsstat3> {
+    let inner = Arc::new(MemFS::new());
+    let fs = compress(&inner);
+    let (id, _) = fs.create(fs.root_dir(), &name("file"), nfs3::sattr3::default()).await?;
+    fs.write(id, 0, &pattern(8192, 1), nfs3::file::stable_how::FILE_SYNC).await?;
+
+    // An index offset that overflows when the index length is added to it.
+    inner.write(id, 24, &u64::MAX.to_le_bytes(), nfs3::file::stable_how::FILE_SYNC).await?;
+    let fs = compress(&inner);
+    assert_eq!(fs.read(id, 0, 10).await.unwrap_err(), nfs3::nfsstat3::NFS3ERR_IO);
+    Ok(())
+}
@@ -0,0 +1,454 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use fernfs::conformance::{Check, ConformanceSuite, Outcome};
+use fernfs::vfs::{Capabilities, DirEntry, NFSFileSystem, ReadDirResult};
+use fernfs::xdr::nfs3;
+
+const ROOT_ID: nfs3::fileid3 = 1;
+
+struct Node {
+    attr: nfs3::fattr3,
+    parent: nfs3::fileid3,
+    data: Vec<u8>,
+    children: BTreeMap<Vec<u8>, nfs3::fileid3>,
+    verifier: Option<nfs3::createverf3>,
+}
+
+struct State {
+    nodes: HashMap<nfs3::fileid3, Node>,
+    next_id: nfs3::fileid3,
+    clock: u32,
+}
+
+impl State {
+    fn now(&mut self) -> nfs3::nfstime3 {
+        self.clock += 1;
+        nfs3::nfstime3 { seconds: self.clock, nseconds: 0 }
+    }
+
+    fn node(&mut self, id: nfs3::fileid3) -> Result<&mut Node, nfs3::nfsstat3> {
+        self.nodes.get_mut(&id).ok_or(nfs3::nfsstat3::NFS3ERR_STALE)
+    }
+
+    fn dir(&mut self, id: nfs3::fileid3) -> Result<&mut Node, nfs3::nfsstat3> {
+        let node = self.node(id)?;
+        if !matches!(node.attr.ftype, nfs3::ftype3::NF3DIR) {
+            return Err(nfs3::nfsstat3::NFS3ERR_NOTDIR);
+        }
+        Ok(node)
+    }
+
+    fn touch(&mut self, id: nfs3::fileid3) {
+        let now = self.now();
+        if let Some(node) = self.nodes.get_mut(&id) {
+            node.attr.mtime = now;
+            node.attr.ctime = now;
+        }
+    }
+
+    fn insert(
+        &mut self,
+        dirid: nfs3::fileid3,
+        name: &[u8],
+        ftype: nfs3::ftype3,
+    ) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        if self.dir(dirid)?.children.contains_key(name) {
+            return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        let now = self.now();
+        let is_dir = matches!(ftype, nfs3::ftype3::NF3DIR);
+        let attr = nfs3::fattr3 {
+            ftype,
+            mode: if is_dir { 0o755 } else { 0o644 },
+            nlink: if is_dir { 2 } else { 1 },
+            fileid: id,
+            atime: now,
+            mtime: now,
+            ctime: now,
+            ..Default::default()
+        };
+        let node = Node {
+            attr,
+            parent: dirid,
+            data: Vec::new(),
+            children: BTreeMap::new(),
+            verifier: None,
+        };
+        self.nodes.insert(id, node);
+        let parent = self.dir(dirid)?;
+        parent.children.insert(name.to_vec(), id);
+        if is_dir {
+            parent.attr.nlink += 1;
+        }
+        self.touch(dirid);
+        Ok(id)
+    }
+
+    /// Drops one name of `id`, releasing the node once nothing refers to it.
+    fn unlink(&mut self, dirid: nfs3::fileid3, id: nfs3::fileid3) {
+        let now = self.now();
+        let node = self.nodes.get_mut(&id).unwrap();
+        let is_dir = matches!(node.attr.ftype, nfs3::ftype3::NF3DIR);
+        node.attr.nlink = if is_dir { 0 } else { node.attr.nlink - 1 };
+        node.attr.ctime = now;
+        if node.attr.nlink == 0 {
+            self.nodes.remove(&id);
+        }
+        if is_dir {
+            self.nodes.get_mut(&dirid).unwrap().attr.nlink -= 1;
+        }
+    }
+}
+
+/// A small but well-behaved in-memory file system.
+struct MemStore {
+    state: Mutex<State>,
+    read_only: bool,
+    /// Reports every object with a single link, like a backend that does not track links
+    flat_link_counts: bool,
+}
+
+impl MemStore {
+    fn new() -> Self {
+        let root = Node {
+            attr: nfs3::fattr3 {
+                ftype: nfs3::ftype3::NF3DIR,
+                mode: 0o755,
+                nlink: 2,
+                fileid: ROOT_ID,
+                ..Default::default()
+            },
+            parent: ROOT_ID,
+            data: Vec::new(),
+            children: BTreeMap::new(),
+            verifier: None,
+        };
+        let state =
+            State { nodes: HashMap::from([(ROOT_ID, root)]), next_id: ROOT_ID + 1, clock: 1000 };
+        Self { state: Mutex::new(state), read_only: false, flat_link_counts: false }
+    }
+
+    fn attr(&self, node: &Node) -> nfs3::fattr3 {
+        let mut attr = node.attr;
+        attr.size = node.data.len() as u64;
+        attr.used = attr.size;
+        if self.flat_link_counts {
+            attr.nlink = 1;
+        }
+        attr
+    }
+}
+
+#[async_trait]
+impl NFSFileSystem for MemStore {
+    fn generation(&self) -> u64 {
+        7
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        if self.read_only {
+            Capabilities::ReadOnly
+        } else {
+            Capabilities::ReadWrite
+        }
+    }
+
+    fn root_dir(&self) -> nfs3::fileid3 {
+        ROOT_ID
+    }
+
+    async fn lookup(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        let mut state = self.state.lock().unwrap();
+        let dir = state.dir(dirid)?;
+        match filename.as_slice() {
+            b"." => Ok(dirid),
+            b".." => Ok(dir.parent),
+            name => dir.children.get(name).copied().ok_or(nfs3::nfsstat3::NFS3ERR_NOENT),
+        }
+    }
+
+    async fn getattr(&self, id: nfs3::fileid3) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        let mut state = self.state.lock().unwrap();
+        Ok(self.attr(state.node(id)?))
+    }
+
+    async fn setattr(
+        &self,
+        id: nfs3::fileid3,
+        setattr: nfs3::sattr3,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        let mut state = self.state.lock().unwrap();
+        let now = state.now();
+        let node = state.node(id)?;
+        if let nfs3::set_mode3::Some(mode) = setattr.mode {
+            node.attr.mode = mode;
+        }
+        if let nfs3::set_size3::Some(size) = setattr.size {
+            node.data.resize(size as usize, 0);
+        }
+        match setattr.atime {
+            nfs3::set_atime::SET_TO_CLIENT_TIME(time) => node.attr.atime = time,
+            nfs3::set_atime::SET_TO_SERVER_TIME => node.attr.atime = now,
+            nfs3::set_atime::DONT_CHANGE => {}
+        }
+        match setattr.mtime {
+            nfs3::set_mtime::SET_TO_CLIENT_TIME(time) => node.attr.mtime = time,
+            nfs3::set_mtime::SET_TO_SERVER_TIME => node.attr.mtime = now,
+            nfs3::set_mtime::DONT_CHANGE => {}
+        }
+        node.attr.ctime = now;
+        Ok(self.attr(node))
+    }
+
+    async fn read(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        count: u32,
+    ) -> Result<(Vec<u8>, bool), nfs3::nfsstat3> {
+        let mut state = self.state.lock().unwrap();
+        let data = &state.node(id)?.data;
+        let start = (offset as usize).min(data.len());
+        let end = start.saturating_add(count as usize).min(data.len());
+        Ok((data[start..end].to_vec(), end == data.len()))
+    }
+
+    async fn write(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+        _stable: nfs3::file::stable_how,
+    ) -> Result<(nfs3::fattr3, nfs3::file::stable_how, nfs3::count3), nfs3::nfsstat3> {
+        let mut state = self.state.lock().unwrap();
+        let now = state.now();
+        let node = state.node(id)?;
+        let end = offset as usize + data.len();
+        if node.data.len() < end {
+            node.data.resize(end, 0);
+        }
+        node.data[offset as usize..end].copy_from_slice(data);
+        node.attr.mtime = now;
+        node.attr.ctime = now;
+        Ok((self.attr(node), nfs3::file::stable_how::FILE_SYNC, data.len() as nfs3::count3))
+    }
+
+    async fn create(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        attr: nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        let id = self.state.lock().unwrap().insert(dirid, filename, nfs3::ftype3::NF3REG)?;
+        Ok((id, self.setattr(id, attr).await?))
+    }
+
+    async fn create_exclusive(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        verifier: nfs3::createverf3,
+    ) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(id) = state.dir(dirid)?.children.get(filename.as_slice()).copied() {
+            return match state.node(id)?.verifier {
+                Some(existing) if existing == verifier => Ok(id),
+                _ => Err(nfs3::nfsstat3::NFS3ERR_EXIST),
+            };
+        }
+        let id = state.insert(dirid, filename, nfs3::ftype3::NF3REG)?;
+        state.node(id)?.verifier = Some(verifier);
+        Ok(id)
+    }
+
+    async fn mkdir(
+        &self,
+        dirid: nfs3::fileid3,
+        dirname: &nfs3::filename3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        let mut state = self.state.lock().unwrap();
+        let id = state.insert(dirid, dirname, nfs3::ftype3::NF3DIR)?;
+        Ok((id, self.attr(state.node(id)?)))
+    }
+
+    async fn remove(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> Result<(), nfs3::nfsstat3> {
+        let mut state = self.state.lock().unwrap();
+        let id = state
+            .dir(dirid)?
+            .children
+            .get(filename.as_slice())
+            .copied()
+            .ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?;
+        if !state.node(id)?.children.is_empty() {
+            return Err(nfs3::nfsstat3::NFS3ERR_NOTEMPTY);
+        }
+        state.dir(dirid)?.children.remove(filename.as_slice());
+        state.unlink(dirid, id);
+        state.touch(dirid);
+        Ok(())
+    }
+
+    async fn rename(
+        &self,
+        from_dirid: nfs3::fileid3,
+        from_filename: &nfs3::filename3,
+        to_dirid: nfs3::fileid3,
+        to_filename: &nfs3::filename3,
+    ) -> Result<(), nfs3::nfsstat3> {
+        let mut state = self.state.lock().unwrap();
+        let id = state
+            .dir(from_dirid)?
+            .children
+            .get(from_filename.as_slice())
+            .copied()
+            .ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?;
+        let is_dir = matches!(state.node(id)?.attr.ftype, nfs3::ftype3::NF3DIR);
+        if let Some(target) = state.dir(to_dirid)?.children.get(to_filename.as_slice()).copied() {
+            if target == id {
+                return Ok(());
+            }
+            let target_node = state.node(target)?;
+            match (is_dir, matches!(target_node.attr.ftype, nfs3::ftype3::NF3DIR)) {
+                (true, false) => return Err(nfs3::nfsstat3::NFS3ERR_NOTDIR),
+                (false, true) => return Err(nfs3::nfsstat3::NFS3ERR_ISDIR),
+                (true, true) if !target_node.children.is_empty() => {
+                    return Err(nfs3::nfsstat3::NFS3ERR_NOTEMPTY)
+                }
+                _ => {}
+            }
+            state.dir(to_dirid)?.children.remove(to_filename.as_slice());
+            state.unlink(to_dirid, target);
+        }
+        state.dir(from_dirid)?.children.remove(from_filename.as_slice());
+        state.dir(to_dirid)?.children.insert(to_filename.to_vec(), id);
+        if is_dir {
+            state.node(id)?.parent = to_dirid;
+            state.dir(from_dirid)?.attr.nlink -= 1;
+            state.dir(to_dirid)?.attr.nlink += 1;
+        }
+        state.touch(from_dirid);
+        state.touch(to_dirid);
+        Ok(())
+    }
+
+    async fn readdir(
+        &self,
+        dirid: nfs3::fileid3,
+        start_after: nfs3::fileid3,
+        max_entries: usize,
+    ) -> Result<ReadDirResult, nfs3::nfsstat3> {
+        let mut state = self.state.lock().unwrap();
+        let dir = state.dir(dirid)?;
+        let mut names = vec![(b".".to_vec(), dirid), (b"..".to_vec(), dir.parent)];
+        names.extend(dir.children.iter().map(|(name, id)| (name.clone(), *id)));
+        let start = (start_after as usize).min(names.len());
+        let end = start.saturating_add(max_entries).min(names.len());
+        let entries = names[start..end]
+            .iter()
+            .map(|(name, id)| DirEntry {
+                fileid: *id,
+                name: name.as_slice().into(),
+                attr: self.attr(&state.nodes[id]),
+            })
+            .collect();
+        Ok(ReadDirResult { entries, end: end == names.len() })
+    }
+
+    async fn symlink(
+        &self,
+        _dirid: nfs3::fileid3,
+        _linkname: &nfs3::filename3,
+        _symlink: &nfs3::nfspath3,
+        _attr: &nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_NOTSUPP)
+    }
+
+    async fn readlink(&self, _id: nfs3::fileid3) -> Result<nfs3::nfspath3, nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_NOTSUPP)
+    }
+
+    async fn link(
+        &self,
+        fileid: nfs3::fileid3,
+        linkdirid: nfs3::fileid3,
+        linkname: &nfs3::filename3,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        let mut state = self.state.lock().unwrap();
+        if matches!(state.node(fileid)?.attr.ftype, nfs3::ftype3::NF3DIR) {
+            return Err(nfs3::nfsstat3::NFS3ERR_ISDIR);
+        }
+        let dir = state.dir(linkdirid)?;
+        if dir.children.contains_key(linkname.as_slice()) {
+            return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
+        }
+        dir.children.insert(linkname.to_vec(), fileid);
+        state.touch(linkdirid);
+        let now = state.now();
+        let node = state.node(fileid)?;
+        node.attr.nlink += 1;
+        node.attr.ctime = now;
+        Ok(self.attr(node))
+    }
+
+    async fn mknod(
+        &self,
+        _dirid: nfs3::fileid3,
+        _filename: &nfs3::filename3,
+        _ftype: nfs3::ftype3,
+        _specdata: nfs3::specdata3,
+        _attr: &nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_NOTSUPP)
+    }
+
+    async fn commit(
+        &self,
+        fileid: nfs3::fileid3,
+        _offset: u64,
+        _count: u32,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        self.getattr(fileid).await
+    }
+}
+
+#[tokio::test]
+async fn well_behaved_backend_passes_every_check() {
+    let report = ConformanceSuite::new(MemStore::new).run().await;
+    report.assert_passed();
+    assert_eq!(report.results.len(), Check::ALL.len());
+    assert!(report.results.iter().all(|(_, outcome)| *outcome == Outcome::Passed), "{report}");
+}
+
+#[tokio::test]
+async fn missing_link_counts_are_reported() {
+    let suite = ConformanceSuite::new(|| MemStore { flat_link_counts: true, ..MemStore::new() });
+    let report = suite.run_checks(&[Check::LinkCounts, Check::CreateLookupReaddir]).await;
+    assert!(!report.passed());
+    assert!(matches!(report.outcome(Check::LinkCounts), Some(Outcome::Failed(_))), "{report}");
+    assert_eq!(report.outcome(Check::CreateLookupReaddir), Some(&Outcome::Passed));
+}
+
+#[tokio::test]
+async fn read_only_backends_are_skipped() {
+    let suite = ConformanceSuite::new(|| MemStore { read_only: true, ..MemStore::new() });
+    let report = suite.run().await;
+    assert!(report.passed());
+    assert!(
+        report.results.iter().all(|(_, outcome)| matches!(outcome, Outcome::Skipped(_))),
+        "{report}"
+    );
+}
@@ -0,0 +1,197 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use fernfs::conformance::ConformanceSuite;
+use fernfs::crypt_fs::{CryptFS, Key, KeyProvider, StaticKey};
+use fernfs::mem_fs::MemFS;
+use fernfs::vfs::NFSFileSystem;
+use fernfs::xdr::nfs3;
+
+const KEY: Key = [7; 32];
+
+fn name(name: &str) -> nfs3::filename3 {
+    name.as_bytes().into()
+}
+
+fn crypt(inner: &Arc<MemFS>) -> CryptFS {
+    CryptFS::new(inner.clone(), Arc::new(StaticKey(KEY)))
+}
+
+/// Reads the whole stored (encrypted) contents of a file.
+async fn stored(inner: &MemFS, id: nfs3::fileid3) -> Result<Vec<u8>, nfs3::nfsstat3> {
+    Ok(inner.read(id, 0, u32::MAX).await?.0)
+}
+
+/// Deterministic test data that differs in every block.
+fn pattern(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i * 31 + seed as usize) as u8).collect()
+}
+
+#[tokio::test]
+async fn passes_conformance_suite() {
+    ConformanceSuite::new(|| CryptFS::new(Arc::new(MemFS::new()), Arc::new(StaticKey(KEY))))
+        .run()
+        .await
+        .assert_passed();
+    ConformanceSuite::new(|| {
+        CryptFS::new(Arc::new(MemFS::new()), Arc::new(StaticKey(KEY))).encrypt_names(true)
+    })
+    .run()
+    .await
+    .assert_passed();
+}
+
+#[tokio::test]
+async fn partial_block_writes_and_truncation() -> Result<(), nfs3::nfsstat3> {
+    let inner = Arc::new(MemFS::new());
+    let fs = crypt(&inner);
+    let (id, _) = fs.create(fs.root_dir(), &name("file"), nfs3::sattr3::default()).await?;
+    let mut expected = pattern(10_000, 1);
+    fs.write(id, 0, &expected, nfs3::file::stable_how::FILE_SYNC).await?;
+
+    // Unaligned writes inside, across and past the end of blocks, leaving a gap.
+    for (offset, len, seed) in [(100, 50, 2), (4000, 200, 3), (9990, 30, 4), (12_000, 5, 5)] {
+        let data = pattern(len, seed);
+        let (attr, _, count) =
+            fs.write(id, offset as u64, &data, nfs3::file::stable_how::FILE_SYNC).await?;
+        assert_eq!(count as usize, len);
+        if expected.len() < offset + len {
+            expected.resize(offset + len, 0);
+        }
+        expected[offset..offset + len].copy_from_slice(&data);
+        assert_eq!(attr.size, expected.len() as u64);
+    }
+    assert_eq!(fs.read(id, 0, u32::MAX).await?, (expected.clone(), true));
+    assert_eq!(fs.read(id, 4090, 20).await?.0, expected[4090..4110]);
+    assert_eq!(fs.getattr(id).await?.size, 12_005);
+    assert!(inner.getattr(id).await?.size > 12_005);
+
+    for size in [9000, 8192, 20_000, 0] {
+        let attr = fs.setattr(id, nfs3::sattr3 { size: Some(size), ..Default::default() }).await?;
+        assert_eq!(attr.size, size);
+        expected.resize(size as usize, 0);
+        assert_eq!(fs.read(id, 0, u32::MAX).await?, (expected.clone(), true));
+    }
+    assert_eq!(inner.getattr(id).await?.size, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn stored_data_is_encrypted_and_authenticated() -> Result<(), nfs3::nfsstat3> {
+    let inner = Arc::new(MemFS::new());
+    let fs = crypt(&inner);
+    let secret = b"attack at dawn, attack at dawn".repeat(300);
+    let (id, _) = fs.create(fs.root_dir(), &name("plan"), nfs3::sattr3::default()).await?;
+    fs.write(id, 0, &secret, nfs3::file::stable_how::FILE_SYNC).await?;
+
+    let raw = stored(&inner, id).await?;
+    assert!(!raw.windows(6).any(|w| w == b"attack"));
+
+    // Another key cannot read it, and a flipped bit in the second block is detected
+    // without affecting the first.
+    let other = CryptFS::new(inner.clone(), Arc::new(StaticKey([8; 32])));
+    assert_eq!(other.read(id, 0, 10).await.unwrap_err(), nfs3::nfsstat3::NFS3ERR_IO);
+    inner.write(id, 5000, &[raw[5000] ^ 1], nfs3::file::stable_how::FILE_SYNC).await?;
+    assert_eq!(fs.read(id, 0, 100).await?.0, secret[..100]);
+    assert_eq!(fs.read(id, 4096, 100).await.unwrap_err(), nfs3::nfsstat3::NFS3ERR_IO);
+    Ok(())
+}
+
+/// Keys 1 and 2, starting with 1 for new files.
+struct Rotating(AtomicU32);
+
+impl KeyProvider for Rotating {
+    fn current_key_id(&self) -> u32 {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    fn key(&self, id: u32) -> Option<Key> {
+        matches!(id, 1 | 2).then_some([id as u8; 32])
+    }
+
+    fn key_ids(&self) -> Vec<u32> {
+        vec![1, 2]
+    }
+}
+
+#[tokio::test]
+async fn unencrypted_names_keep_listing_positions() -> Result<(), nfs3::nfsstat3> {
+    let inner = Arc::new(MemFS::new());
+    let fs = crypt(&inner).encrypt_names(true);
+    let root = fs.root_dir();
+    for i in 0..5 {
+        fs.create(root, &name(&format!("file{i}")), nfs3::sattr3::default()).await?;
+    }
+    // A file placed in the wrapped file system directly.
+    let (stray, _) = inner.create(root, &name("stray"), nfs3::sattr3::default()).await?;
+
+    let mut listed = Vec::new();
+    loop {
+        let page = fs.readdir_index(root, listed.len(), 2).await?;
+        listed.extend(page.entries.iter().map(|e| String::from_utf8_lossy(&e.name).into_owned()));
+        if page.end {
+            break;
+        }
+    }
+    listed.sort();
+    let mut expected = vec![".", "..", ".unencrypted.stray"];
+    expected.extend(["file0", "file1", "file2", "file3", "file4"]);
+    assert_eq!(listed, expected);
+
+    assert_eq!(fs.lookup(root, &name(".unencrypted.stray")).await?, stray);
+    let err = fs.create(root, &name(".unencrypted.new"), nfs3::sattr3::default()).await;
+    assert_eq!(err.unwrap_err(), nfs3::nfsstat3::NFS3ERR_ACCES);
+    fs.remove(root, &name(".unencrypted.stray")).await?;
+    assert_eq!(inner.lookup(root, &name("stray")).await, Err(nfs3::nfsstat3::NFS3ERR_NOENT));
+    Ok(())
+}
+
+#[tokio::test]
+async fn names_are_encrypted_and_keys_rotate() -> Result<(), nfs3::nfsstat3> {
+    let inner = Arc::new(MemFS::new());
+    let fs = crypt(&inner).encrypt_names(true);
+    let root = fs.root_dir();
+    let (docs, _) = fs.mkdir(root, &name("docs")).await?;
+    let (file, _) = fs.create(docs, &name("salaries.csv"), nfs3::sattr3::default()).await?;
+    fs.write(file, 0, b"alice,1", nfs3::file::stable_how::FILE_SYNC).await?;
+    fs.rename(docs, &name("salaries.csv"), root, &name("moved.csv")).await?;
+
+    assert_eq!(fs.lookup(root, &name("moved.csv")).await?, file);
+    let listed: Vec<_> =
+        fs.readdir(root, 0, 10).await?.entries.into_iter().map(|e| e.name.0).collect();
+    assert_eq!(listed, [&b"."[..], b"..", b"docs", b"moved.csv"]);
+    let stored_names: Vec<_> =
+        inner.readdir(root, 0, 10).await?.entries.into_iter().map(|e| e.name.0).collect();
+    assert!(!stored_names.iter().any(|n| n == b"docs" || n == b"moved.csv"));
+    let long = "x".repeat(fs.pathconf_name_max() as usize + 1);
+    let err = fs.create(root, &name(&long), nfs3::sattr3::default()).await.unwrap_err();
+    assert_eq!(err, nfs3::nfsstat3::NFS3ERR_NAMETOOLONG);
+
+    // Files and names written under key 1 stay readable after new ones move to key 2,
+    // and need that key.
+    let keys = Arc::new(Rotating(AtomicU32::new(1)));
+    let fs = CryptFS::new(inner.clone(), keys.clone()).encrypt_names(true);
+    let (dir, _) = fs.mkdir(root, &name("rotated")).await?;
+    let (old, _) = fs.create(dir, &name("old"), nfs3::sattr3::default()).await?;
+    fs.write(old, 0, b"before", nfs3::file::stable_how::FILE_SYNC).await?;
+    keys.0.store(2, Ordering::SeqCst);
+    let (new, _) = fs.create(dir, &name("new"), nfs3::sattr3::default()).await?;
+    fs.write(new, 0, b"after", nfs3::file::stable_how::FILE_SYNC).await?;
+    assert_eq!(fs.read(old, 0, 100).await?.0, b"before");
+    assert_eq!(fs.read(new, 0, 100).await?.0, b"after");
+    assert_eq!(fs.lookup(root, &name("rotated")).await?, dir);
+    assert_eq!(fs.lookup(dir, &name("old")).await?, old);
+    let listed: Vec<_> =
+        fs.readdir(dir, 0, 10).await?.entries.into_iter().map(|e| e.name.0).collect();
+    assert_eq!(listed, [&b"."[..], b"..", b"old", b"new"]);
+    let only_new = CryptFS::new(inner.clone(), Arc::new(StaticKey([2; 32])));
+    assert_eq!(only_new.read(old, 0, 100).await.unwrap_err(), nfs3::nfsstat3::NFS3ERR_IO);
+
+    // An entry stored under the old key is still the one a new entry of that name meets.
+    let err = fs.create(dir, &name("old"), nfs3::sattr3::default()).await.unwrap_err();
+    assert_eq!(err, nfs3::nfsstat3::NFS3ERR_EXIST);
+    fs.rename(dir, &name("new"), dir, &name("old")).await?;
+    assert_eq!(fs.lookup(dir, &name("old")).await?, new);
+    assert_eq!(fs.readdir(dir, 0, 10).await?.entries.len(), 3);
+    Ok(())
+}
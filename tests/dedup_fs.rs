@@ -0,0 +1,254 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use fernfs::conformance::ConformanceSuite;
+use fernfs::dedup_fs::DedupFS;
+use fernfs::vfs::NFSFileSystem;
+use fernfs::xdr::nfs3;
+use sha2::{Digest, Sha256};
+
+struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    fn new(prefix: &str) -> std::io::Result<Self> {
+        let mut path = std::env::temp_dir();
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+        path.push(format!("fernfs_{prefix}_{nanos}"));
+        std::fs::create_dir(&path)?;
+        Ok(Self { path })
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+fn name(name: &str) -> nfs3::filename3 {
+    name.as_bytes().into()
+}
+
+fn open(dir: &TempDir) -> DedupFS {
+    DedupFS::builder().avg_chunk_size(1024).open(&dir.path).expect("open store")
+}
+
+/// Data that does not repeat, so every chunk of it is distinct.
+fn noise(len: usize, seed: u64) -> Vec<u8> {
+    let mut state = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+async fn write_file(
+    fs: &DedupFS,
+    file: &str,
+    data: &[u8],
+) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+    let (id, _) = fs.create(fs.root_dir(), &name(file), nfs3::sattr3::default()).await?;
+    fs.write(id, 0, data, nfs3::file::stable_how::FILE_SYNC).await?;
+    Ok(id)
+}
+
+#[tokio::test]
+async fn passes_conformance_suite() {
+    let temp = TempDir::new("dedupfs_conformance").expect("temp dir");
+    let count = AtomicUsize::new(0);
+    ConformanceSuite::new(|| {
+        let dir = temp.path.join(count.fetch_add(1, Ordering::Relaxed).to_string());
+        DedupFS::open(dir).expect("open store")
+    })
+    .run()
+    .await
+    .assert_passed();
+}
+
+#[tokio::test]
+async fn identical_and_shifted_data_is_stored_once() -> Result<(), nfs3::nfsstat3> {
+    let temp = TempDir::new("dedupfs_shared").expect("temp dir");
+    let fs = open(&temp);
+    let data = noise(200_000, 1);
+    write_file(&fs, "a", &data).await?;
+    let first = fs.stats().await;
+    assert_eq!(first.logical_bytes, 200_000);
+    assert_eq!(first.stored_bytes, 200_000);
+
+    write_file(&fs, "copy", &data).await?;
+    let stats = fs.stats().await;
+    assert_eq!(stats.logical_bytes, 400_000);
+    assert_eq!((stats.stored_bytes, stats.chunks), (first.stored_bytes, first.chunks));
+
+    // Content-defined boundaries resynchronise after an insertion at the front.
+    let mut shifted = b"a short header".to_vec();
+    shifted.extend_from_slice(&data);
+    let id = write_file(&fs, "shifted", &shifted).await?;
+    let added = fs.stats().await.stored_bytes - stats.stored_bytes;
+    assert!(added < 20_000, "{added} bytes added for a 14-byte insertion");
+    assert_eq!(fs.read(id, 0, u32::MAX).await?, (shifted, true));
+    Ok(())
+}
+
+#[tokio::test]
+async fn unstable_writes_are_durable_after_commit() -> Result<(), nfs3::nfsstat3> {
+    let temp = TempDir::new("dedupfs_commit").expect("temp dir");
+    let data = noise(50_000, 2);
+    let (id, dir) = {
+        let fs = open(&temp);
+        let dir = fs.mkdir(fs.root_dir(), &name("dir")).await?.0;
+        let (id, _) = fs.create(dir, &name("file"), nfs3::sattr3::default()).await?;
+        for (i, piece) in data.chunks(3000).enumerate() {
+            let (attr, committed, _) =
+                fs.write(id, i as u64 * 3000, piece, nfs3::file::stable_how::UNSTABLE).await?;
+            assert_eq!(committed, nfs3::file::stable_how::UNSTABLE);
+            assert_eq!(attr.size, (i * 3000 + piece.len()) as u64);
+        }
+        // Buffered writes are visible before they are committed.
+        assert_eq!(fs.read(id, 0, u32::MAX).await?, (data.clone(), true));
+        assert_eq!(fs.stats().await.stored_bytes, 0);
+        assert_eq!(fs.commit(id, 0, 0).await?.size, 50_000);
+        assert_eq!(fs.stats().await.stored_bytes, 50_000);
+
+        // Uncommitted writes are lost with the server, as the write verifier tells clients.
+        fs.write(id, 10, b"lost", nfs3::file::stable_how::UNSTABLE).await?;
+        (id, dir)
+    };
+
+    let fs = open(&temp);
+    assert_eq!(fs.lookup(dir, &name("file")).await?, id);
+    assert_eq!(fs.read(id, 0, u32::MAX).await?, (data, true));
+    assert_eq!(fs.getattr(id).await?.size, 50_000);
+    Ok(())
+}
+
+#[tokio::test]
+async fn partial_writes_and_truncation_survive_reopening() -> Result<(), nfs3::nfsstat3> {
+    let temp = TempDir::new("dedupfs_edit").expect("temp dir");
+    let mut expected = noise(100_000, 3);
+    let id = {
+        let fs = open(&temp);
+        let id = write_file(&fs, "file", &expected).await?;
+        for (offset, len, seed) in [(0, 10, 4), (50_000, 3000, 5), (99_990, 30, 6), (130_000, 5, 7)]
+        {
+            let data = noise(len, seed);
+            fs.write(id, offset as u64, &data, nfs3::file::stable_how::DATA_SYNC).await?;
+            if expected.len() < offset + len {
+                expected.resize(offset + len, 0);
+            }
+            expected[offset..offset + len].copy_from_slice(&data);
+        }
+        assert_eq!(fs.read(id, 0, u32::MAX).await?, (expected.clone(), true));
+        for size in [70_000, 80_000] {
+            fs.setattr(id, nfs3::sattr3 { size: Some(size), ..Default::default() }).await?;
+            expected.resize(size as usize, 0);
+        }
+        assert_eq!(fs.read(id, 0, u32::MAX).await?, (expected.clone(), true));
+        id
+    };
+
+    let fs = open(&temp);
+    assert_eq!(fs.getattr(id).await?.size, 80_000);
+    assert_eq!(fs.read(id, 0, u32::MAX).await?, (expected, true));
+    Ok(())
+}
+
+#[tokio::test]
+async fn hard_links_share_contents_until_the_last_is_removed() -> Result<(), nfs3::nfsstat3> {
+    let temp = TempDir::new("dedupfs_link").expect("temp dir");
+    let fs = open(&temp);
+    let data = noise(20_000, 8);
+    let id = write_file(&fs, "a", &data).await?;
+    assert_eq!(fs.link(id, fs.root_dir(), &name("b")).await?.nlink, 2);
+    assert_eq!(fs.stats().await.logical_bytes, 20_000);
+
+    fs.remove(fs.root_dir(), &name("a")).await?;
+    assert_eq!(fs.lookup(fs.root_dir(), &name("b")).await?, id);
+    assert_eq!(fs.read(id, 0, u32::MAX).await?, (data, true));
+    assert_eq!(fs.stats().await.stored_bytes, 20_000);
+
+    fs.remove(fs.root_dir(), &name("b")).await?;
+    assert!(matches!(fs.getattr(id).await, Err(nfs3::nfsstat3::NFS3ERR_STALE)));
+    assert_eq!(fs.stats().await.stored_bytes, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn garbage_collection_keeps_chunks_in_use() -> Result<(), nfs3::nfsstat3> {
+    let temp = TempDir::new("dedupfs_gc").expect("temp dir");
+    let fs = open(&temp);
+    let kept = noise(30_000, 9);
+    let id = write_file(&fs, "kept", &kept).await?;
+    write_file(&fs, "copy", &kept).await?;
+    write_file(&fs, "dropped", &noise(40_000, 10)).await?;
+    fs.remove(fs.root_dir(), &name("copy")).await?;
+    fs.remove(fs.root_dir(), &name("dropped")).await?;
+
+    let freed = fs.collect_garbage().await.expect("collect garbage");
+    assert_eq!(freed.bytes, 40_000);
+    assert_eq!(fs.collect_garbage().await.expect("collect garbage").chunks, 0);
+    assert_eq!(fs.read(id, 0, u32::MAX).await?, (kept, true));
+    Ok(())
+}
+
+/// Appends a journal frame that sets (`Some(id)`) or removes directory entries.
+fn append_entries(dir: &TempDir, changes: &[(nfs3::fileid3, &str, Option<nfs3::fileid3>)]) {
+    let mut body = [0u64.to_be_bytes(), (changes.len() as u64).to_be_bytes()].concat();
+    for &(dirid, entry, id) in changes {
+        body.extend((if id.is_some() { 2u32 } else { 3u32 }).to_be_bytes());
+        body.extend(dirid.to_be_bytes());
+        body.extend((entry.len() as u32).to_be_bytes());
+        body.extend(entry.as_bytes());
+        body.resize(body.len().next_multiple_of(4), 0);
+        body.extend(id.map(u64::to_be_bytes).unwrap_or_default());
+    }
+    let mut frame = (body.len() as u32).to_be_bytes().to_vec();
+    frame.extend(&body);
+    frame.extend(&Sha256::digest(&body)[..8]);
+    let mut journal = std::fs::read(dir.path.join("journal")).expect("read journal");
+    journal.extend(frame);
+    std::fs::write(dir.path.join("journal"), journal).expect("write journal");
+}
+
+#[tokio::test]
+async fn inconsistent_indexes_are_rejected() -> Result<(), nfs3::nfsstat3> {
+    let temp = TempDir::new("dedupfs_validate").expect("temp dir");
+    let (root, a, b, file) = {
+        let fs = open(&temp);
+        let root = fs.root_dir();
+        let (a, _) = fs.mkdir(root, &name("a")).await?;
+        let (b, _) = fs.mkdir(a, &name("b")).await?;
+        let (file, _) = fs.create(root, &name("xx"), nfs3::sattr3::default()).await?;
+        (root, a, b, file)
+    };
+    let journal = std::fs::read(temp.path.join("journal")).expect("read journal");
+    let reopen = |changes: &[(nfs3::fileid3, &str, Option<nfs3::fileid3>)]| {
+        std::fs::write(temp.path.join("journal"), &journal).expect("write journal");
+        append_entries(&temp, changes);
+        DedupFS::builder().avg_chunk_size(1024).open(&temp.path)
+    };
+
+    // A file named "..".
+    let err = reopen(&[(root, "..", Some(file))]).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+    // "a" moved below its own child, detached from the root.
+    let err = reopen(&[(root, "a", None), (b, "a", Some(a))]).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+    // Link counts come from the directory entries, not the stored attributes.
+    let fs = reopen(&[(root, "yy", Some(file))]).expect("open store");
+    assert_eq!(fs.getattr(file).await?.nlink, 2);
+    assert_eq!(fs.getattr(root).await?.nlink, 3);
+    fs.remove(root, &name("xx")).await?;
+    fs.remove(root, &name("yy")).await?;
+    assert_eq!(fs.getattr(file).await.unwrap_err(), nfs3::nfsstat3::NFS3ERR_STALE);
+    Ok(())
+}
@@ -0,0 +1,186 @@
+use std::io::Cursor;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use tokio::io::AsyncWriteExt;
+use tokio::time::timeout;
+
+use fernfs::fault_fs::{Fault, FaultFS, FaultRule, Latency, Op};
+use fernfs::mem_fs::MemFS;
+use fernfs::protocol::nfs::portmap::PortmapTable;
+use fernfs::protocol::rpc::{Context, SocketMessageHandler, TransactionTracker};
+use fernfs::vfs::NFSFileSystem;
+use fernfs::xdr::{self, nfs3, Serialize};
+
+fn name(name: &str) -> nfs3::filename3 {
+    name.as_bytes().into()
+}
+
+/// A FaultFS over a MemFS with `logs/app.log` and `data` holding "0123456789".
+async fn fixture(seed: u64) -> Result<FaultFS, nfs3::nfsstat3> {
+    let fs = FaultFS::new(Arc::new(MemFS::new()), seed);
+    let root = fs.root_dir();
+    let (logs, _) = fs.mkdir(root, &name("logs")).await?;
+    fs.create(logs, &name("app.log"), nfs3::sattr3::default()).await?;
+    let (data, _) = fs.create(root, &name("data"), nfs3::sattr3::default()).await?;
+    fs.write(data, 0, b"0123456789", nfs3::file::stable_how::FILE_SYNC).await?;
+    Ok(fs)
+}
+
+/// Returns which of `n` GETATTRs of the root failed.
+async fn failures(fs: &FaultFS, n: usize) -> Vec<bool> {
+    let mut failed = Vec::new();
+    for _ in 0..n {
+        failed.push(fs.getattr(fs.root_dir()).await.is_err());
+    }
+    failed
+}
+
+#[tokio::test]
+async fn errors_are_deterministic_under_a_seed() -> Result<(), nfs3::nfsstat3> {
+    let rule = FaultRule::new(Fault::Error(nfs3::nfsstat3::NFS3ERR_JUKEBOX))
+        .on(Op::Getattr)
+        .probability(0.5);
+    let first = fixture(7).await?;
+    let id = first.controller().add(rule.clone());
+    let pattern = failures(&first, 64).await;
+    assert!(pattern.contains(&true) && pattern.contains(&false));
+    let count = pattern.iter().filter(|&&failed| failed).count() as u64;
+    assert_eq!(first.controller().injected(id), count);
+
+    let second = fixture(7).await?;
+    second.controller().add(rule.clone());
+    assert_eq!(failures(&second, 64).await, pattern);
+    second.controller().reseed(7);
+    assert_eq!(failures(&second, 64).await, pattern);
+
+    first.controller().set_enabled(false);
+    assert_eq!(failures(&first, 64).await, vec![false; 64]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn rules_select_operations_paths_and_counts() -> Result<(), nfs3::nfsstat3> {
+    let fs = fixture(1).await?;
+    let faults = fs.controller();
+    let root = fs.root_dir();
+    let stale = faults.add(
+        FaultRule::new(Fault::Error(nfs3::nfsstat3::NFS3ERR_STALE)).on(Op::Getattr).under("/logs/"),
+    );
+    let nospc = faults
+        .add(FaultRule::new(Fault::Error(nfs3::nfsstat3::NFS3ERR_NOSPC)).on(Op::Create).times(2));
+
+    let logs = fs.lookup(root, &name("logs")).await?;
+    let log = fs.lookup(logs, &name("app.log")).await?;
+    let data = fs.lookup(root, &name("data")).await?;
+    assert_eq!(fs.getattr(logs).await.unwrap_err(), nfs3::nfsstat3::NFS3ERR_STALE);
+    assert_eq!(fs.getattr(log).await.unwrap_err(), nfs3::nfsstat3::NFS3ERR_STALE);
+    fs.getattr(data).await?;
+    fs.getattr(root).await?;
+    assert_eq!(faults.injected(stale), 2);
+
+    // Renaming out of the subtree moves the object out of the rule's reach.
+    fs.rename(logs, &name("app.log"), root, &name("app.log")).await?;
+    fs.getattr(log).await?;
+
+    for _ in 0..2 {
+        let err = fs.create(root, &name("new"), nfs3::sattr3::default()).await.unwrap_err();
+        assert_eq!(err, nfs3::nfsstat3::NFS3ERR_NOSPC);
+    }
+    fs.create(root, &name("new"), nfs3::sattr3::default()).await?;
+    assert_eq!(faults.injected(nospc), 2);
+
+    assert!(faults.remove(stale));
+    assert!(!faults.remove(stale));
+    fs.getattr(logs).await?;
+    assert_eq!(faults.injected(stale), 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn short_transfers_and_latency() -> Result<(), nfs3::nfsstat3> {
+    let fs = fixture(3).await?;
+    let faults = fs.controller();
+    let data = fs.lookup(fs.root_dir(), &name("data")).await?;
+
+    let short = faults.add(FaultRule::new(Fault::ShortRead));
+    let (read, eof) = fs.read(data, 0, 10).await?;
+    assert!(!read.is_empty() && read.len() < 10, "read {} bytes", read.len());
+    assert_eq!(read, b"0123456789"[..read.len()]);
+    assert!(!eof);
+    // A short read rule leaves other operations alone.
+    fs.getattr(data).await?;
+    assert_eq!(faults.injected(short), 1);
+    faults.clear();
+
+    faults.add(FaultRule::new(Fault::ShortWrite));
+    let (attr, _, count) =
+        fs.write(data, 0, b"abcdefghij", nfs3::file::stable_how::FILE_SYNC).await?;
+    assert!(count > 0 && count < 10, "wrote {count} bytes");
+    assert_eq!(attr.size, 10);
+    faults.clear();
+    let (read, _) = fs.read(data, 0, 10).await?;
+    assert_eq!(read[..count as usize], b"abcdefghij"[..count as usize]);
+    assert_eq!(read[count as usize..], b"0123456789"[count as usize..]);
+
+    let delay = Duration::from_millis(50);
+    faults.add(FaultRule::new(Fault::Latency(Latency::Fixed(delay))).on(Op::Read));
+    let start = Instant::now();
+    fs.read(data, 0, 10).await?;
+    assert!(start.elapsed() >= delay);
+    Ok(())
+}
+
+fn getattr_call(xid: u32, fh: nfs3::nfs_fh3) -> Vec<u8> {
+    let call = xdr::rpc::call_body {
+        rpcvers: 2,
+        prog: nfs3::PROGRAM,
+        vers: nfs3::VERSION,
+        proc: nfs3::NFSProgram::NFSPROC3_GETATTR as u32,
+        cred: xdr::rpc::opaque_auth::default(),
+        verf: xdr::rpc::opaque_auth::default(),
+    };
+    let msg = xdr::rpc::rpc_msg { xid, body: xdr::rpc::rpc_body::CALL(call) };
+    let mut body = Vec::new();
+    msg.serialize(&mut body).expect("serialize rpc_msg");
+    fh.serialize(&mut body).expect("serialize handle");
+    let mut fragment = ((1_u32 << 31) | (body.len() as u32)).to_be_bytes().to_vec();
+    fragment.extend(body);
+    fragment
+}
+
+#[tokio::test]
+async fn dropped_replies_are_answered_on_retransmission() -> Result<(), nfs3::nfsstat3> {
+    let fs = Arc::new(fixture(5).await?);
+    let rule = fs.controller().add(FaultRule::new(Fault::DropReply).on(Op::Getattr).times(1));
+    let context = Context {
+        local_port: 0,
+        client_addr: "127.0.0.1:1234".to_string(),
+        auth: xdr::rpc::auth_unix::default(),
+        vfs: fs.clone(),
+        mount_signal: None,
+        export_name: Arc::from("/".to_string()),
+        transaction_tracker: Arc::new(TransactionTracker::new(Duration::from_secs(60))),
+        portmap_table: Arc::new(RwLock::new(PortmapTable::default())),
+    };
+    let (mut handler, mut socksend, mut msgrecv) = SocketMessageHandler::new(&context);
+    let call = getattr_call(11, fs.id_to_fh(fs.root_dir()));
+
+    socksend.write_all(&call).await.expect("write call");
+    handler.read().await.expect("handler read");
+    let dropped = timeout(Duration::from_millis(200), msgrecv.recv()).await;
+    assert!(dropped.is_err(), "reply should have been dropped");
+    assert_eq!(fs.controller().injected(rule), 1);
+
+    socksend.write_all(&call).await.expect("write retransmission");
+    handler.read().await.expect("handler read");
+    let response = timeout(Duration::from_secs(1), msgrecv.recv())
+        .await
+        .expect("response timeout")
+        .expect("response channel closed")
+        .expect("response error");
+    let reply = xdr::deserialize::<xdr::rpc::rpc_msg>(&mut Cursor::new(response))
+        .expect("deserialize reply");
+    assert_eq!(reply.xid, 11);
+    Ok(())
+}
@@ -0,0 +1,217 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use fernfs::composite_fs::CompositeFS;
+use fernfs::git_fs::GitFS;
+use fernfs::mem_fs::MemFS;
+use fernfs::vfs::{Capabilities, NFSFileSystem};
+use fernfs::xdr::nfs3;
+use git2::{Oid, Repository, Signature, Time};
+
+struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    fn new(prefix: &str) -> std::io::Result<Self> {
+        let mut path = std::env::temp_dir();
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+        path.push(format!("fernfs_{prefix}_{nanos}"));
+        std::fs::create_dir(&path)?;
+        Ok(Self { path })
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Commits `files` (path, contents, mode) as the whole tree, on top of `parent`.
+fn commit(
+    repo: &Repository,
+    files: &[(&str, &[u8], i32)],
+    parent: Option<Oid>,
+    seconds: i64,
+) -> Oid {
+    let mut index = git2::Index::new().unwrap();
+    for (path, data, mode) in files {
+        let entry = git2::IndexEntry {
+            ctime: git2::IndexTime::new(0, 0),
+            mtime: git2::IndexTime::new(0, 0),
+            dev: 0,
+            ino: 0,
+            mode: *mode as u32,
+            uid: 0,
+            gid: 0,
+            file_size: data.len() as u32,
+            id: repo.blob(data).unwrap(),
+            flags: 0,
+            flags_extended: 0,
+            path: path.as_bytes().to_vec(),
+        };
+        index.add(&entry).unwrap();
+    }
+    let tree = repo.find_tree(index.write_tree_to(repo).unwrap()).unwrap();
+    let author = Signature::new("Dev", "dev@example.com", &Time::new(seconds, 0)).unwrap();
+    let parents: Vec<_> = parent.map(|oid| repo.find_commit(oid).unwrap()).into_iter().collect();
+    let parents: Vec<_> = parents.iter().collect();
+    repo.commit(None, &author, &author, "commit", &tree, &parents).unwrap()
+}
+
+fn init(path: &Path) -> (Repository, Oid) {
+    let repo = Repository::init_bare(path).unwrap();
+    let first = commit(
+        &repo,
+        &[
+            ("README", b"hello\n", 0o100644),
+            ("bin/run.sh", b"#!/bin/sh\n", 0o100755),
+            ("docs/README", b"hello\n", 0o100644),
+            ("latest", b"bin/run.sh", 0o120000),
+        ],
+        None,
+        1_700_000_000,
+    );
+    repo.reference("refs/heads/main", first, true, "").unwrap();
+    {
+        let target = repo.find_object(first, None).unwrap();
+        let tagger =
+            Signature::new("Dev", "dev@example.com", &Time::new(1_700_000_100, 0)).unwrap();
+        repo.tag("v1.0", &target, &tagger, "release", false).unwrap();
+    }
+    (repo, first)
+}
+
+fn names(entries: &[fernfs::vfs::DirEntry]) -> Vec<String> {
+    entries.iter().map(|e| String::from_utf8_lossy(&e.name).to_string()).collect()
+}
+
+#[tokio::test]
+async fn serves_branches_and_tags() -> Result<(), nfs3::nfsstat3> {
+    let temp = TempDir::new("gitfs_refs").expect("temp dir");
+    let (repo, first) = init(&temp.path);
+    let second = commit(
+        &repo,
+        &[("README", b"login\n", 0o100644), ("bin/run.sh", b"#!/bin/sh\n", 0o100755)],
+        Some(first),
+        1_700_000_200,
+    );
+    repo.reference("refs/heads/feature/login", second, true, "").unwrap();
+
+    let fs = GitFS::open(&temp.path).expect("open repository");
+    assert!(matches!(fs.capabilities(), Capabilities::ReadOnly));
+    let root = fs.root_dir();
+    assert_eq!(names(&fs.readdir(root, 0, 10).await?.entries), [".", "..", "branches", "tags"]);
+    let branches = fs.lookup(root, &b"branches".as_slice().into()).await?;
+    assert_eq!(names(&fs.readdir(branches, 0, 10).await?.entries), [".", "..", "feature", "main"]);
+    assert_eq!(fs.getattr(branches).await?.nlink, 4);
+
+    let readme = fs.path_to_id(b"branches/main/README").await?;
+    assert_eq!(fs.read(readme, 0, 100).await?, (b"hello\n".to_vec(), true));
+    let attr = fs.getattr(readme).await?;
+    assert_eq!((attr.mode, attr.size, attr.mtime.seconds), (0o644, 6, 1_700_000_000));
+    assert_eq!(
+        fs.read(fs.path_to_id(b"branches/feature/login/README").await?, 0, 100).await?.0,
+        b"login\n"
+    );
+
+    // Blobs keep their ID wherever they appear; directories do not.
+    assert_eq!(fs.path_to_id(b"branches/main/docs/README").await?, readme);
+    assert_eq!(fs.path_to_id(b"tags/v1.0/README").await?, readme);
+    let main_bin = fs.path_to_id(b"branches/main/bin").await?;
+    let login_bin = fs.path_to_id(b"branches/feature/login/bin").await?;
+    assert_ne!(main_bin, login_bin);
+    let login = fs.path_to_id(b"branches/feature/login").await?;
+    assert_eq!(fs.lookup(login_bin, &b"..".as_slice().into()).await?, login);
+
+    let script = fs.path_to_id(b"tags/v1.0/bin/run.sh").await?;
+    assert_eq!(fs.getattr(script).await?.mode, 0o755);
+    let latest = fs.path_to_id(b"tags/v1.0/latest").await?;
+    assert!(matches!(fs.getattr(latest).await?.ftype, nfs3::ftype3::NF3LNK));
+    assert_eq!(fs.readlink(latest).await?.as_slice(), b"bin/run.sh");
+    let main = fs.path_to_id(b"branches/main").await?;
+    assert_eq!(fs.getattr(main).await?.nlink, 4);
+
+    let stable = nfs3::file::stable_how::FILE_SYNC;
+    assert_eq!(fs.write(readme, 0, b"x", stable).await.unwrap_err(), nfs3::nfsstat3::NFS3ERR_ROFS);
+    let err = fs.remove(main, &b"README".as_slice().into()).await.unwrap_err();
+    assert_eq!(err, nfs3::nfsstat3::NFS3ERR_ROFS);
+    Ok(())
+}
+
+#[tokio::test]
+async fn follows_moving_refs() -> Result<(), nfs3::nfsstat3> {
+    let temp = TempDir::new("gitfs_moving").expect("temp dir");
+    let (repo, first) = init(&temp.path);
+    let fs = GitFS::open(&temp.path).expect("open repository").refresh_interval(Duration::ZERO);
+    let branches = fs.path_to_id(b"branches").await?;
+    let before = fs.getattr(branches).await?.mtime;
+    let old_readme = fs.path_to_id(b"branches/main/README").await?;
+    let old_docs = fs.path_to_id(b"branches/main/docs").await?;
+
+    let second = commit(&repo, &[("README", b"v2\n", 0o100644)], Some(first), 1_700_000_300);
+    repo.reference("refs/heads/main", second, true, "").unwrap();
+    repo.reference("refs/heads/next", second, true, "").unwrap();
+
+    let after = fs.getattr(branches).await?.mtime;
+    assert!((after.seconds, after.nseconds) > (before.seconds, before.nseconds));
+    assert_eq!(names(&fs.readdir(branches, 0, 10).await?.entries), [".", "..", "main", "next"]);
+    let readme = fs.path_to_id(b"branches/main/README").await?;
+    assert_eq!(fs.read(readme, 0, 100).await?.0, b"v2\n");
+    assert_eq!(fs.getattr(readme).await?.mtime.seconds, 1_700_000_300);
+    assert!(matches!(
+        fs.path_to_id(b"branches/main/docs").await,
+        Err(nfs3::nfsstat3::NFS3ERR_NOENT)
+    ));
+
+    // Handles into the old commit still work.
+    assert_eq!(fs.read(old_readme, 0, 100).await?.0, b"hello\n");
+    assert_eq!(names(&fs.readdir(old_docs, 0, 10).await?.entries), [".", "..", "README"]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn reads_large_blobs_in_pieces() -> Result<(), nfs3::nfsstat3> {
+    let temp = TempDir::new("gitfs_large").expect("temp dir");
+    let repo = Repository::init_bare(&temp.path).unwrap();
+    let data: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+    let oid = commit(&repo, &[("big.bin", &data, 0o100644)], None, 1_700_000_000);
+    repo.reference("refs/tags/build-1", oid, true, "").unwrap();
+
+    let fs = GitFS::open(&temp.path).expect("open repository");
+    let big = fs.path_to_id(b"tags/build-1/big.bin").await?;
+    assert_eq!(fs.getattr(big).await?.size, 300_000);
+    let mut read = Vec::new();
+    loop {
+        let (chunk, eof) = fs.read(big, read.len() as u64, 65_536).await?;
+        read.extend(chunk);
+        if eof {
+            break;
+        }
+    }
+    assert_eq!(read, data);
+    assert_eq!(fs.read(big, 400_000, 10).await?, (Vec::new(), true));
+    Ok(())
+}
+
+#[tokio::test]
+async fn can_be_grafted_into_a_composite() -> Result<(), nfs3::nfsstat3> {
+    let temp = TempDir::new("gitfs_composite").expect("temp dir");
+    init(&temp.path);
+    let git = GitFS::open(&temp.path).expect("open repository");
+    let fs = CompositeFS::new(Arc::new(MemFS::new())).graft("repo", Arc::new(git));
+
+    let mut ids = Vec::new();
+    for path in ["repo/branches/main/README", "repo/tags/v1.0/bin", "repo/tags/v1.0/latest"] {
+        let id = fs.path_to_id(path.as_bytes()).await?;
+        assert_eq!(fs.getattr(id).await?.fileid, id);
+        ids.push(id);
+    }
+    assert_eq!(fs.read(ids[0], 0, 100).await?.0, b"hello\n");
+    let bin = fs.readdir(ids[1], 0, 10).await?.entries;
+    assert_eq!(names(&bin), [".", "..", "run.sh"]);
+    assert_eq!(fs.lookup(ids[1], &b"run.sh".as_slice().into()).await?, bin[2].fileid);
+    Ok(())
+}
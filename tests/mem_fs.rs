@@ -0,0 +1,183 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use fernfs::conformance::ConformanceSuite;
+use fernfs::mem_fs::MemFS;
+use fernfs::vfs::NFSFileSystem;
+use fernfs::xdr::nfs3;
+
+fn name(name: &str) -> nfs3::filename3 {
+    name.as_bytes().into()
+}
+
+fn time(time: nfs3::nfstime3) -> (u32, u32) {
+    (time.seconds, time.nseconds)
+}
+
+fn size(size: u64) -> nfs3::sattr3 {
+    nfs3::sattr3 { size: Some(size), ..Default::default() }
+}
+
+#[tokio::test]
+async fn passes_conformance_suite() {
+    ConformanceSuite::new(MemFS::new).run().await.assert_passed();
+}
+
+#[tokio::test]
+async fn link_counts_and_change_times() -> Result<(), nfs3::nfsstat3> {
+    let fs = MemFS::new();
+    let root = fs.root_dir();
+    let (dir, _) = fs.mkdir(root, &name("dir")).await?;
+    assert_eq!(fs.getattr(root).await?.nlink, 3);
+
+    let (file, created) = fs.create(dir, &name("a"), nfs3::sattr3::default()).await?;
+    std::thread::sleep(std::time::Duration::from_millis(2));
+    let linked = fs.link(file, root, &name("b")).await?;
+    assert_eq!(linked.nlink, 2);
+    assert!(time(linked.ctime) > time(created.ctime), "LINK must update ctime");
+    assert_eq!(time(linked.mtime), time(created.mtime));
+
+    // Renaming a directory moves its ".." link between parents.
+    let (sub, _) = fs.mkdir(root, &name("sub")).await?;
+    fs.rename(root, &name("sub"), dir, &name("sub")).await?;
+    assert_eq!(fs.getattr(root).await?.nlink, 3);
+    assert_eq!(fs.getattr(dir).await?.nlink, 3);
+    assert_eq!(fs.lookup(sub, &name("..")).await?, dir);
+    let err = fs.rename(root, &name("dir"), sub, &name("loop")).await.unwrap_err();
+    assert_eq!(err, nfs3::nfsstat3::NFS3ERR_INVAL);
+
+    // Overwriting one name of a hard-linked file drops a single link.
+    fs.create(root, &name("c"), nfs3::sattr3::default()).await?;
+    fs.rename(root, &name("c"), root, &name("b")).await?;
+    assert_eq!(fs.getattr(file).await?.nlink, 1);
+    fs.remove(dir, &name("a")).await?;
+    assert_eq!(fs.getattr(file).await.unwrap_err(), nfs3::nfsstat3::NFS3ERR_STALE);
+
+    let err = fs.remove(root, &name("dir")).await.unwrap_err();
+    assert_eq!(err, nfs3::nfsstat3::NFS3ERR_NOTEMPTY);
+    Ok(())
+}
+
+#[tokio::test]
+async fn sparse_files_and_limits() -> Result<(), nfs3::nfsstat3> {
+    let fs = MemFS::builder().max_bytes(64 * 1024).max_inodes(3).max_file_size(1 << 40).build();
+    let root = fs.root_dir();
+    let stable = nfs3::file::stable_how::FILE_SYNC;
+
+    let (file, attr) = fs.create(root, &name("sparse"), size(1 << 30)).await?;
+    assert_eq!((attr.size, attr.used), (1 << 30, 0));
+    let (attr, _, _) = fs.write(file, (1 << 30) - 2, b"end", stable).await?;
+    assert_eq!(attr.size, (1 << 30) + 1);
+    assert_eq!(fs.used_bytes(), 8192, "a write straddling two blocks allocates both");
+    let (data, eof) = fs.read(file, (1 << 30) - 6, 100).await?;
+    assert_eq!(data, b"\0\0\0\0end");
+    assert!(eof);
+
+    // Truncating zeroes the tail so re-extending reads back a hole.
+    fs.setattr(file, size((1 << 30) - 1)).await?;
+    fs.setattr(file, size(1 << 30)).await?;
+    assert_eq!(fs.read(file, (1 << 30) - 2, 2).await?.0, b"e\0");
+    fs.setattr(file, size(0)).await?;
+    assert_eq!(fs.used_bytes(), 0);
+
+    let err = fs.write(file, 0, &vec![1; 64 * 1024 + 1], stable).await.unwrap_err();
+    assert_eq!(err, nfs3::nfsstat3::NFS3ERR_NOSPC);
+    assert_eq!(fs.getattr(file).await?.size, 0, "a rejected write changes nothing");
+    fs.write(file, 0, &vec![1; 64 * 1024], stable).await?;
+
+    let err = fs.setattr(file, size((1 << 40) + 1)).await.unwrap_err();
+    assert_eq!(err, nfs3::nfsstat3::NFS3ERR_FBIG);
+
+    fs.mkdir(root, &name("dir")).await?;
+    let err = fs.create(root, &name("full"), nfs3::sattr3::default()).await.unwrap_err();
+    assert_eq!(err, nfs3::nfsstat3::NFS3ERR_NOSPC);
+    let stat = fs.fsstat(root).await?;
+    assert_eq!((stat.tbytes, stat.fbytes, stat.ffiles), (64 * 1024, 0, 0));
+    Ok(())
+}
+
+/// Returns the offset of the snapshot record of inode `id` with attributes `attr`.
+fn record(snapshot: &[u8], id: nfs3::fileid3, attr: &nfs3::fattr3) -> usize {
+    let mut head = id.to_be_bytes().to_vec();
+    head.extend((attr.ftype as u32).to_be_bytes());
+    head.extend(attr.mode.to_be_bytes());
+    snapshot.windows(head.len()).position(|w| w == head).expect("record")
+}
+
+#[tokio::test]
+async fn snapshot_round_trip() -> Result<(), nfs3::nfsstat3> {
+    let fs = MemFS::new();
+    let root = fs.root_dir();
+    let stable = nfs3::file::stable_how::FILE_SYNC;
+    let (dir, _) = fs.mkdir(root, &name("dir")).await?;
+    let (file, _) = fs.create(dir, &name("file"), size(1 << 20)).await?;
+    fs.write(file, 12345, b"payload", stable).await?;
+    fs.link(file, root, &name("alias")).await?;
+    fs.symlink(root, &name("link"), &b"dir/file".as_slice().into(), &nfs3::sattr3::default())
+        .await?;
+    let rdev = nfs3::specdata3 { specdata1: 1, specdata2: 3 };
+    let sattr = nfs3::sattr3::default();
+    fs.mknod(root, &name("null"), nfs3::ftype3::NF3CHR, rdev, &sattr).await?;
+
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+    let path = std::env::temp_dir().join(format!("fernfs_memfs_{nanos}"));
+    fs.save_snapshot(&path).expect("save snapshot");
+    let restored = MemFS::builder().load_snapshot(&path);
+    let _ = std::fs::remove_file(&path);
+    let restored = restored.expect("load snapshot");
+
+    for id in [root, dir, file] {
+        let (saved, loaded) = (fs.getattr(id).await?, restored.getattr(id).await?);
+        assert_eq!(format!("{loaded:?}"), format!("{saved:?}"));
+    }
+    assert_eq!(restored.lookup(root, &name("alias")).await?, file);
+    assert_eq!(restored.read(file, 12340, 16).await?.0, b"\0\0\0\0\0payload\0\0\0\0");
+    assert_eq!(restored.used_bytes(), fs.used_bytes());
+    let link = restored.lookup(root, &name("link")).await?;
+    assert_eq!(restored.readlink(link).await?.as_slice(), b"dir/file");
+    let null = restored.lookup(root, &name("null")).await?;
+    let loaded = restored.getattr(null).await?.rdev;
+    assert_eq!((loaded.specdata1, loaded.specdata2), (1, 3));
+
+    // New objects never reuse a saved file ID.
+    let (fresh, _) = restored.create(root, &name("fresh"), sattr).await?;
+    assert!(fresh > file);
+
+    let err = MemFS::builder().read_snapshot(&b"not a snapshot"[..]).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    Ok(())
+}
+
+#[tokio::test]
+async fn inconsistent_snapshots_are_rejected() -> Result<(), nfs3::nfsstat3> {
+    let fs = MemFS::new();
+    let root = fs.root_dir();
+    let (a, _) = fs.mkdir(root, &name("a")).await?;
+    let (b, _) = fs.mkdir(a, &name("b")).await?;
+    let (file, _) = fs.create(root, &name("xx"), nfs3::sattr3::default()).await?;
+    let mut snapshot = Vec::new();
+    fs.write_snapshot(&mut snapshot).expect("write snapshot");
+    let load = |snapshot: Vec<u8>| MemFS::builder().read_snapshot(&snapshot[..]);
+
+    // A file named "..".
+    let mut bad = snapshot.clone();
+    let at = bad.windows(6).position(|w| w == b"\0\0\0\x02xx").expect("name") + 4;
+    bad[at..at + 2].copy_from_slice(b"..");
+    assert_eq!(load(bad).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+
+    // "a" claiming "b" as its parent, which would make ancestor walks loop.
+    let mut bad = snapshot.clone();
+    let at = record(&bad, a, &fs.getattr(a).await?) + 8 + 84;
+    bad[at..at + 8].copy_from_slice(&b.to_be_bytes());
+    assert_eq!(load(bad).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+
+    // Link counts come from the directory entries, not the stored attributes.
+    let mut bad = snapshot.clone();
+    let at = record(&bad, file, &fs.getattr(file).await?) + 16;
+    bad[at..at + 4].copy_from_slice(&0u32.to_be_bytes());
+    let restored = load(bad).expect("load snapshot");
+    assert_eq!(restored.getattr(file).await?.nlink, 1);
+    assert_eq!(restored.getattr(root).await?.nlink, 3);
+    restored.remove(root, &name("xx")).await?;
+    assert_eq!(restored.getattr(file).await.unwrap_err(), nfs3::nfsstat3::NFS3ERR_STALE);
+    Ok(())
+}
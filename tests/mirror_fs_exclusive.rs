@@ -1,25 +1,10 @@
 use std::path::PathBuf;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use fernfs::mirror_fs::MirrorFS;
 use fernfs::vfs::NFSFileSystem;
 use fernfs::xdr::nfs3;
 
-#[allow(dead_code)]
-#[path = "../src/bin/fernfs/create_fs_object.rs"]
-mod create_fs_object;
-#[allow(dead_code)]
-#[path = "../src/bin/fernfs/error_handling.rs"]
-mod error_handling;
-#[allow(dead_code)]
-#[path = "../src/bin/fernfs/fs_entry.rs"]
-mod fs_entry;
-#[allow(dead_code)]
-#[path = "../src/bin/fernfs/fs_map.rs"]
-mod fs_map;
-#[allow(dead_code)]
-#[path = "../src/bin/fernfs/fs.rs"]
-mod mirror_fs;
-
 struct TempDir {
     path: PathBuf,
 }
@@ -43,7 +28,7 @@ impl Drop for TempDir {
 #[tokio::test]
 async fn mirrorfs_exclusive_create_returns_not_supported() {
     let temp = TempDir::new("mirrorfs_exclusive").expect("temp dir");
-    let fs = mirror_fs::MirrorFS::new(temp.path.clone());
+    let fs = MirrorFS::new(temp.path.clone());
     let name: nfs3::filename3 = b"exclusive_file".as_ref().into();
     let verifier: nfs3::createverf3 = [1, 2, 3, 4, 5, 6, 7, 8];
 
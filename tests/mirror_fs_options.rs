@@ -0,0 +1,462 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use fernfs::composite_fs::CompositeFS;
+use fernfs::mem_fs::MemFS;
+use fernfs::mirror_fs::{ExcludeSet, FsyncPolicy, MirrorFS, MountPolicy, SymlinkPolicy};
+use fernfs::subtree_fs::SubtreeFS;
+use fernfs::union_fs::UnionFS;
+use fernfs::vfs::{Capabilities, NFSFileSystem};
+use fernfs::xdr::nfs3;
+
+struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    fn new(prefix: &str) -> std::io::Result<Self> {
+        let mut path = std::env::temp_dir();
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+        path.push(format!("fernfs_{prefix}_{nanos}"));
+        std::fs::create_dir(&path)?;
+        Ok(Self { path })
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Sorted entry names, since listing order follows the file system.
+async fn names(fs: &MirrorFS, dir: nfs3::fileid3) -> Vec<String> {
+    let listing = fs.readdir(dir, 0, 100).await.expect("readdir");
+    let mut names: Vec<String> =
+        listing.entries.iter().map(|e| String::from_utf8_lossy(&e.name).to_string()).collect();
+    names.sort();
+    names
+}
+
+#[tokio::test]
+async fn builder_rejects_missing_root_and_bad_patterns() {
+    let temp = TempDir::new("mirrorfs_builder").expect("temp dir");
+    assert!(MirrorFS::builder(temp.path.join("missing")).build().is_err());
+    std::fs::write(temp.path.join("file"), b"x").expect("write file");
+    assert!(MirrorFS::builder(temp.path.join("file")).build().is_err());
+    assert!(MirrorFS::builder(&temp.path).exclude("[unterminated").build().is_err());
+    assert!(MirrorFS::builder(&temp.path).exclude("*.tmp").build().is_ok());
+}
+
+#[tokio::test]
+async fn read_only_mirror_rejects_modifications() -> Result<(), nfs3::nfsstat3> {
+    let temp = TempDir::new("mirrorfs_read_only").expect("temp dir");
+    std::fs::write(temp.path.join("existing"), b"data").expect("write file");
+    let fs = MirrorFS::builder(&temp.path).read_only(true).build().expect("build");
+    let root = fs.root_dir();
+    assert!(matches!(fs.capabilities(), Capabilities::ReadOnly));
+
+    let existing = fs.lookup(root, &b"existing".as_slice().into()).await?;
+    assert_eq!(fs.read(existing, 0, 16).await?.0, b"data");
+
+    let name: nfs3::filename3 = b"new".as_slice().into();
+    let err = fs.create(root, &name, nfs3::sattr3::default()).await.unwrap_err();
+    assert_eq!(err, nfs3::nfsstat3::NFS3ERR_ROFS);
+    let stable = nfs3::file::stable_how::FILE_SYNC;
+    assert_eq!(
+        fs.write(existing, 0, b"x", stable).await.unwrap_err(),
+        nfs3::nfsstat3::NFS3ERR_ROFS
+    );
+    let err = fs.remove(root, &b"existing".as_slice().into()).await.unwrap_err();
+    assert_eq!(err, nfs3::nfsstat3::NFS3ERR_ROFS);
+    assert!(temp.path.join("existing").exists());
+    Ok(())
+}
+
+#[tokio::test]
+async fn excluded_entries_are_invisible() -> Result<(), nfs3::nfsstat3> {
+    let temp = TempDir::new("mirrorfs_exclude").expect("temp dir");
+    std::fs::create_dir(temp.path.join(".git")).expect("mkdir");
+    std::fs::create_dir(temp.path.join("build")).expect("mkdir");
+    std::fs::write(temp.path.join("build/out.o"), b"").expect("write");
+    std::fs::write(temp.path.join("build/keep.txt"), b"").expect("write");
+    std::fs::write(temp.path.join("notes.tmp"), b"").expect("write");
+    std::fs::write(temp.path.join("notes.txt"), b"").expect("write");
+    let fs = MirrorFS::builder(&temp.path)
+        .exclude(".git")
+        .exclude("*.tmp")
+        .exclude("build/*.o")
+        .build()
+        .expect("build");
+    let root = fs.root_dir();
+
+    assert_eq!(names(&fs, root).await, vec![".", "..", "build", "notes.txt"]);
+    let build = fs.lookup(root, &b"build".as_slice().into()).await?;
+    assert_eq!(names(&fs, build).await, vec![".", "..", "keep.txt"]);
+
+    for (dir, name) in [(root, b".git".as_slice()), (root, b"notes.tmp"), (build, b"out.o")] {
+        assert_eq!(fs.lookup(dir, &name.into()).await.unwrap_err(), nfs3::nfsstat3::NFS3ERR_NOENT);
+    }
+
+    let err = fs.create(root, &b"new.tmp".as_slice().into(), nfs3::sattr3::default()).await;
+    assert_eq!(err.unwrap_err(), nfs3::nfsstat3::NFS3ERR_ACCES);
+    assert!(!temp.path.join("new.tmp").exists());
+    Ok(())
+}
+
+#[tokio::test]
+async fn exclude_patterns_protect_hidden_entries_and_reload() -> Result<(), nfs3::nfsstat3> {
+    for watch in [false, true] {
+        let temp = TempDir::new("mirrorfs_exclude_reload").expect("temp dir");
+        for name in ["prod.env", "example.env", "notes.txt", "draft.md"] {
+            std::fs::write(temp.path.join(name), name).expect("write");
+        }
+        let fs = MirrorFS::builder(&temp.path)
+            .exclude("*.env")
+            .exclude("!example.env")
+            .watch(watch)
+            .build()
+            .expect("build");
+        let root = fs.root_dir();
+        assert_eq!(names(&fs, root).await, [".", "..", "draft.md", "example.env", "notes.txt"]);
+
+        // Hidden entries can be neither removed, renamed nor replaced.
+        let name = |name: &'static str| -> nfs3::filename3 { name.as_bytes().into() };
+        let err = fs.remove(root, &name("prod.env")).await.unwrap_err();
+        assert_eq!(err, nfs3::nfsstat3::NFS3ERR_NOENT);
+        let err = fs.rename(root, &name("prod.env"), root, &name("visible")).await.unwrap_err();
+        assert_eq!(err, nfs3::nfsstat3::NFS3ERR_NOENT);
+        let err = fs.rename(root, &name("notes.txt"), root, &name("prod.env")).await.unwrap_err();
+        assert_eq!(err, nfs3::nfsstat3::NFS3ERR_ACCES);
+        assert_eq!(std::fs::read(temp.path.join("prod.env")).expect("read"), b"prod.env");
+        assert!(temp.path.join("notes.txt").exists());
+
+        let draft = fs.lookup(root, &name("draft.md")).await?;
+        let excludes = fs.exclude_controller();
+        excludes.set(ExcludeSet::new(["*.md"]).expect("patterns"));
+        assert_eq!(names(&fs, root).await, [".", "..", "example.env", "notes.txt", "prod.env"]);
+        assert!(fs.getattr(draft).await.is_err());
+        let err = fs.lookup(root, &name("draft.md")).await.unwrap_err();
+        assert_eq!(err, nfs3::nfsstat3::NFS3ERR_NOENT);
+        let prod = fs.lookup(root, &name("prod.env")).await?;
+        assert_eq!(fs.read(prod, 0, 100).await?.0, b"prod.env");
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn symlink_and_fsync_policies() -> Result<(), nfs3::nfsstat3> {
+    let temp = TempDir::new("mirrorfs_policies").expect("temp dir");
+    std::fs::write(temp.path.join("target"), b"").expect("write");
+    std::os::unix::fs::symlink("target", temp.path.join("link")).expect("symlink");
+
+    let hidden = MirrorFS::builder(&temp.path)
+        .symlink_policy(SymlinkPolicy::Hide)
+        .fsync_policy(FsyncPolicy::Never)
+        .build()
+        .expect("build");
+    let root = hidden.root_dir();
+    assert_eq!(names(&hidden, root).await, vec![".", "..", "target"]);
+    let err = hidden.lookup(root, &b"link".as_slice().into()).await.unwrap_err();
+    assert_eq!(err, nfs3::nfsstat3::NFS3ERR_NOENT);
+    let target: nfs3::nfspath3 = b"target".as_slice().into();
+    let err = hidden
+        .symlink(root, &b"other".as_slice().into(), &target, &nfs3::sattr3::default())
+        .await
+        .unwrap_err();
+    assert_eq!(err, nfs3::nfsstat3::NFS3ERR_NOTSUPP);
+
+    let file = hidden.lookup(root, &b"target".as_slice().into()).await?;
+    let (_, committed, _) =
+        hidden.write(file, 0, b"data", nfs3::file::stable_how::FILE_SYNC).await?;
+    assert!(matches!(committed, nfs3::file::stable_how::UNSTABLE));
+
+    let deny = MirrorFS::builder(&temp.path)
+        .symlink_policy(SymlinkPolicy::DenyCreate)
+        .build()
+        .expect("build");
+    let root = deny.root_dir();
+    let link = deny.lookup(root, &b"link".as_slice().into()).await?;
+    assert_eq!(deny.readlink(link).await?.as_slice(), b"target");
+    let err = deny
+        .symlink(root, &b"other".as_slice().into(), &target, &nfs3::sattr3::default())
+        .await
+        .unwrap_err();
+    assert_eq!(err, nfs3::nfsstat3::NFS3ERR_NOTSUPP);
+    Ok(())
+}
+
+#[tokio::test]
+async fn writes_are_synced_as_requested() -> Result<(), nfs3::nfsstat3> {
+    let temp = TempDir::new("mirrorfs_stable").expect("temp dir");
+    let fs = MirrorFS::builder(&temp.path).build().expect("build");
+    let root = fs.root_dir();
+    let (file, _) = fs.create(root, &b"log".as_slice().into(), nfs3::sattr3::default()).await?;
+    let verifier = fs.server_id();
+
+    for (offset, stable) in [
+        (0, nfs3::file::stable_how::UNSTABLE),
+        (4, nfs3::file::stable_how::DATA_SYNC),
+        (8, nfs3::file::stable_how::FILE_SYNC),
+    ] {
+        let (attr, committed, count) = fs.write(file, offset, b"data", stable).await?;
+        assert_eq!(committed as u32, stable as u32);
+        assert_eq!((attr.size, count), (offset + 4, 4));
+    }
+    fs.write(file, 12, b"tail", nfs3::file::stable_how::UNSTABLE).await?;
+    assert_eq!(fs.commit(file, 0, 0).await?.size, 16);
+    assert_eq!(std::fs::read(temp.path.join("log")).expect("read"), b"datadatadatatail");
+    assert_eq!(fs.server_id(), verifier);
+
+    let always =
+        MirrorFS::builder(&temp.path).fsync_policy(FsyncPolicy::Always).build().expect("build");
+    let file = always.lookup(always.root_dir(), &b"log".as_slice().into()).await?;
+    let (_, committed, _) =
+        always.write(file, 0, b"DATA", nfs3::file::stable_how::UNSTABLE).await?;
+    assert!(matches!(committed, nfs3::file::stable_how::FILE_SYNC));
+    Ok(())
+}
+
+#[tokio::test]
+async fn wrappers_report_the_write_verifier() -> Result<(), nfs3::nfsstat3> {
+    let temp = TempDir::new("mirrorfs_verifier").expect("temp dir");
+    std::fs::create_dir(temp.path.join("sub")).expect("mkdir");
+    let fs = Arc::new(MirrorFS::builder(&temp.path).build().expect("build"));
+    let verifier = fs.server_id();
+
+    assert_eq!(SubtreeFS::open(fs.clone(), "sub").await?.server_id(), verifier);
+    assert_eq!(UnionFS::new(fs.clone()).server_id(), verifier);
+    let parent = Arc::new(MemFS::new());
+    let alone = CompositeFS::new(parent.clone()).server_id();
+    let composite = CompositeFS::new(parent).graft("mirror", fs);
+    assert_ne!(composite.server_id(), alone, "grafts must contribute their verifiers");
+    assert_eq!(composite.server_id(), composite.server_id());
+    Ok(())
+}
+
+#[tokio::test]
+async fn cached_descriptors_follow_file_changes() -> Result<(), nfs3::nfsstat3> {
+    let temp = TempDir::new("mirrorfs_fd_cache").expect("temp dir");
+    for open_files in [0, 1, 256] {
+        let fs = MirrorFS::builder(&temp.path).open_files(open_files).build().expect("build");
+        let root = fs.root_dir();
+        let name: nfs3::filename3 = format!("file{open_files}").as_bytes().into();
+        let path = temp.path.join(format!("file{open_files}"));
+        let (file, _) = fs.create(root, &name, nfs3::sattr3::default()).await?;
+        let (other, _) =
+            fs.create(root, &b"other".as_slice().into(), nfs3::sattr3::default()).await?;
+
+        let unstable = nfs3::file::stable_how::UNSTABLE;
+        fs.write(file, 0, b"hello world", unstable).await?;
+        fs.write(other, 0, b"other", unstable).await?;
+        fs.write(file, 6, b"there", unstable).await?;
+        assert_eq!(fs.read(file, 0, 100).await?, (b"hello there".to_vec(), true));
+        assert_eq!(fs.read(file, 6, 3).await?, (b"the".to_vec(), false));
+
+        // Replaced outside the server: the new file is read, not the open one.
+        std::fs::write(temp.path.join("replacement"), b"replaced").expect("write");
+        std::fs::rename(temp.path.join("replacement"), &path).expect("rename");
+        let file = fs.lookup(root, &name).await?;
+        assert_eq!(fs.read(file, 0, 100).await?, (b"replaced".to_vec(), true));
+
+        let truncate = nfs3::sattr3 { size: Some(3), ..Default::default() };
+        fs.setattr(file, truncate).await?;
+        assert_eq!(fs.read(file, 0, 100).await?, (b"rep".to_vec(), true));
+        fs.write(file, 3, b"aid", unstable).await?;
+        assert_eq!(fs.commit(file, 0, 0).await?.size, 6);
+        assert_eq!(std::fs::read(&path).expect("read"), b"repaid");
+
+        fs.remove(root, &name).await?;
+        assert!(fs.read(file, 0, 100).await.is_err());
+        fs.remove(root, &b"other".as_slice().into()).await?;
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn watcher_applies_changes_made_outside_the_server() -> Result<(), nfs3::nfsstat3> {
+    let temp = TempDir::new("mirrorfs_watch").expect("temp dir");
+    std::fs::create_dir(temp.path.join("sub")).expect("mkdir");
+    std::fs::write(temp.path.join("sub/inner"), b"inner").expect("write");
+    std::fs::write(temp.path.join("log"), b"one\n").expect("write");
+    std::fs::write(temp.path.join("gone"), b"").expect("write");
+    let fs = MirrorFS::builder(&temp.path).watch(true).build().expect("build");
+    let root = fs.root_dir();
+    assert_eq!(names(&fs, root).await, [".", "..", "gone", "log", "sub"]);
+    let sub = fs.lookup(root, &b"sub".as_slice().into()).await?;
+    assert_eq!(names(&fs, sub).await, [".", "..", "inner"]);
+    let inner = fs.lookup(sub, &b"inner".as_slice().into()).await?;
+    let log = fs.lookup(root, &b"log".as_slice().into()).await?;
+
+    std::fs::write(temp.path.join("new"), b"new").expect("write");
+    std::fs::write(temp.path.join("log"), b"one\ntwo\n").expect("write");
+    std::fs::rename(temp.path.join("sub"), temp.path.join("moved")).expect("rename");
+    std::fs::remove_file(temp.path.join("gone")).expect("remove");
+
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    while names(&fs, root).await != [".", "..", "log", "moved", "new"] {
+        assert!(std::time::Instant::now() < deadline, "changes not applied");
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    assert_eq!(fs.getattr(log).await?.size, 8);
+    let moved = fs.lookup(root, &b"moved".as_slice().into()).await?;
+    assert_eq!(moved, sub);
+    assert_eq!(fs.lookup(moved, &b"inner".as_slice().into()).await?, inner);
+    assert_eq!(fs.read(inner, 0, 100).await?, (b"inner".to_vec(), true));
+
+    // Changes made through the server are seen as well.
+    let (created, _) = fs.create(moved, &b"made".as_slice().into(), Default::default()).await?;
+    assert_eq!(names(&fs, moved).await, [".", "..", "inner", "made"]);
+    fs.remove(moved, &b"made".as_slice().into()).await?;
+    assert!(fs.getattr(created).await.is_err());
+    assert_eq!(names(&fs, moved).await, [".", "..", "inner"]);
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+#[test]
+fn caller_credentials_are_refused_where_they_cannot_be_enforced() {
+    let temp = TempDir::new("mirrorfs_caller").expect("temp dir");
+    let err = MirrorFS::builder(&temp.path).caller_credentials(true).build().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn caller_credentials_apply_to_disk_operations() -> Result<(), nfs3::nfsstat3> {
+    use fernfs::protocol::rpc::with_caller;
+    use fernfs::xdr::rpc::auth_unix;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    if unsafe { libc::geteuid() } != 0 {
+        // Switching credentials needs root.
+        return Ok(());
+    }
+    let temp = TempDir::new("mirrorfs_caller").expect("temp dir");
+    std::fs::set_permissions(&temp.path, std::fs::Permissions::from_mode(0o777)).unwrap();
+    std::fs::create_dir(temp.path.join("private")).unwrap();
+    std::fs::write(temp.path.join("shared"), b"root's").unwrap();
+    std::fs::write(temp.path.join("secret"), b"root's").unwrap();
+    std::fs::set_permissions(temp.path.join("secret"), std::fs::Permissions::from_mode(0o600))
+        .unwrap();
+    let fs = MirrorFS::builder(&temp.path).caller_credentials(true).build().expect("mirror");
+    let root = fs.root_dir();
+    let user = |uid, gid| auth_unix { uid, gid, gids: vec![gid], ..Default::default() };
+    let alice = user(1000, 1000);
+    let name = |name: &str| -> nfs3::filename3 { name.as_bytes().into() };
+
+    // New objects belong to the caller.
+    let (_, attr) =
+        with_caller(alice.clone(), fs.create(root, &name("mine"), Default::default())).await?;
+    assert_eq!((attr.uid, attr.gid), (1000, 1000));
+    let (_, attr) = with_caller(alice.clone(), fs.mkdir(root, &name("dir"))).await?;
+    assert_eq!((attr.uid, attr.gid), (1000, 1000));
+    let meta = std::fs::metadata(temp.path.join("mine")).unwrap();
+    assert_eq!((meta.uid(), meta.gid()), (1000, 1000));
+
+    // The kernel checks the caller's permissions.
+    let private = fs.lookup(root, &name("private")).await?;
+    let denied = with_caller(alice.clone(), fs.mkdir(private, &name("x"))).await;
+    assert_eq!(denied.unwrap_err(), nfs3::nfsstat3::NFS3ERR_ACCES);
+    let shared = fs.lookup(root, &name("shared")).await?;
+    let written =
+        with_caller(alice.clone(), fs.write(shared, 0, b"x", nfs3::file::stable_how::FILE_SYNC))
+            .await;
+    assert_eq!(written.unwrap_err(), nfs3::nfsstat3::NFS3ERR_ACCES);
+    let chmod = nfs3::sattr3 { mode: nfs3::set_mode3::Some(0o777), ..Default::default() };
+    let changed = with_caller(alice.clone(), fs.setattr(shared, chmod)).await;
+    assert_eq!(changed.unwrap_err(), nfs3::nfsstat3::NFS3ERR_PERM);
+    let removed = with_caller(alice.clone(), fs.remove(private, &name("x"))).await;
+    assert!(removed.is_err());
+
+    // A descriptor opened for one caller is not reused for another.
+    let secret = fs.lookup(root, &name("secret")).await?;
+    let (data, _) = with_caller(user(0, 0), fs.read(secret, 0, 16)).await?;
+    assert_eq!(data, b"root's");
+    let read = with_caller(alice.clone(), fs.read(secret, 0, 16)).await;
+    assert_eq!(read.unwrap_err(), nfs3::nfsstat3::NFS3ERR_ACCES);
+
+    // Calls without credentials act as nobody.
+    let (_, attr) = fs.create(root, &name("anonymous"), Default::default()).await?;
+    assert_eq!((attr.uid, attr.gid), (65534, 65534));
+
+    assert_eq!(std::fs::read(temp.path.join("shared")).unwrap(), b"root's");
+    Ok(())
+}
+
+/// A tmpfs mounted for a test, unmounted on drop
+#[cfg(target_os = "linux")]
+struct Tmpfs(std::ffi::CString);
+
+#[cfg(target_os = "linux")]
+impl Tmpfs {
+    /// Mounts a tmpfs of `size` at `path`, or returns `None` where mounting is not
+    /// allowed.
+    fn mount(path: &std::path::Path, size: &str) -> Option<Self> {
+        use std::os::unix::ffi::OsStrExt;
+        let target = std::ffi::CString::new(path.as_os_str().as_bytes()).unwrap();
+        let options = std::ffi::CString::new(format!("size={size}")).unwrap();
+        let rc = unsafe {
+            libc::mount(
+                c"none".as_ptr(),
+                target.as_ptr(),
+                c"tmpfs".as_ptr(),
+                0,
+                options.as_ptr().cast(),
+            )
+        };
+        (rc == 0).then(|| Self(target))
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Tmpfs {
+    fn drop(&mut self) {
+        unsafe { libc::umount2(self.0.as_ptr(), libc::MNT_DETACH) };
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn mount_policy_separates_or_hides_mounted_file_systems() -> Result<(), nfs3::nfsstat3> {
+    let temp = TempDir::new("mirrorfs_mounts").expect("temp dir");
+    std::fs::create_dir(temp.path.join("mnt")).expect("mkdir");
+    std::fs::write(temp.path.join("file"), b"root").expect("write");
+    let Some(_tmpfs) = Tmpfs::mount(&temp.path.join("mnt"), "1m") else {
+        // Mounting needs privileges the test may not have.
+        return Ok(());
+    };
+    std::fs::write(temp.path.join("mnt/inner"), b"mounted").expect("write");
+    let name = |name: &str| -> nfs3::filename3 { name.as_bytes().into() };
+
+    // By default everything is one file system.
+    let fs = MirrorFS::new(temp.path.clone());
+    let root = fs.root_dir();
+    let mnt = fs.lookup(root, &name("mnt")).await?;
+    let inner = fs.lookup(mnt, &name("inner")).await?;
+    assert_eq!(fs.getattr(inner).await?.fsid, 0);
+    assert_eq!(fs.fsstat(mnt).await?.tbytes, fs.fsstat(root).await?.tbytes);
+
+    let fs = MirrorFS::builder(&temp.path).mount_policy(MountPolicy::Cross).build().expect("build");
+    let root = fs.root_dir();
+    let file = fs.lookup(root, &name("file")).await?;
+    let mnt = fs.lookup(root, &name("mnt")).await?;
+    let inner = fs.lookup(mnt, &name("inner")).await?;
+    assert_eq!(fs.getattr(root).await?.fsid, 0);
+    assert_eq!(fs.getattr(file).await?.fsid, 0);
+    let mounted = fs.getattr(mnt).await?.fsid;
+    assert_ne!(mounted, 0);
+    assert_eq!(fs.getattr(inner).await?.fsid, mounted);
+    let listing = fs.readdir(mnt, 0, 10).await?;
+    assert!(listing.entries.iter().all(|e| e.name.as_ref() == b".." || e.attr.fsid == mounted));
+    assert_eq!(fs.fsstat(mnt).await?.tbytes, 1 << 20);
+    assert_ne!(fs.fsstat(root).await?.tbytes, 1 << 20);
+
+    let fs = MirrorFS::builder(&temp.path).mount_policy(MountPolicy::Hide).build().expect("build");
+    let root = fs.root_dir();
+    assert_eq!(names(&fs, root).await, [".", "..", "file"]);
+    assert_eq!(fs.lookup(root, &name("mnt")).await.unwrap_err(), nfs3::nfsstat3::NFS3ERR_NOENT);
+    Ok(())
+}
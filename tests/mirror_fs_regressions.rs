@@ -1,27 +1,12 @@
 use std::path::PathBuf;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use fernfs::mirror_fs::{FSMap, MirrorFS};
 use fernfs::vfs::NFSFileSystem;
 use fernfs::xdr::nfs3;
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 
-#[allow(dead_code)]
-#[path = "../src/bin/fernfs/create_fs_object.rs"]
-mod create_fs_object;
-#[allow(dead_code)]
-#[path = "../src/bin/fernfs/error_handling.rs"]
-mod error_handling;
-#[allow(dead_code)]
-#[path = "../src/bin/fernfs/fs_entry.rs"]
-mod fs_entry;
-#[allow(dead_code)]
-#[path = "../src/bin/fernfs/fs_map.rs"]
-mod fs_map;
-#[allow(dead_code)]
-#[path = "../src/bin/fernfs/fs.rs"]
-mod mirror_fs;
-
 struct TempDir {
     path: PathBuf,
 }
@@ -45,7 +30,7 @@ impl Drop for TempDir {
 #[tokio::test]
 async fn readdir_paginates_with_duplicate_fileids() -> Result<(), nfs3::nfsstat3> {
     let temp = TempDir::new("mirrorfs_readdir").expect("temp dir");
-    let fs = mirror_fs::MirrorFS::new(temp.path.clone());
+    let fs = MirrorFS::new(temp.path.clone());
     let root = fs.root_dir();
 
     for i in 0..5 {
@@ -75,14 +60,64 @@ async fn readdir_paginates_with_duplicate_fileids() -> Result<(), nfs3::nfsstat3
         }
     }
 
+    // Entries come in the order the file system lists them.
+    names[2..].sort();
     assert_eq!(names, vec![".", "..", "file", "file2"]);
     Ok(())
 }
 
+#[tokio::test]
+async fn readdir_streams_large_directories() -> Result<(), nfs3::nfsstat3> {
+    let temp = TempDir::new("mirrorfs_readdir_stream").expect("temp dir");
+    for i in 0..3000 {
+        std::fs::write(temp.path.join(format!("f{i:04}")), b"").expect("write");
+    }
+    std::fs::write(temp.path.join("skip.tmp"), b"").expect("write");
+    let fs = MirrorFS::builder(&temp.path).exclude("*.tmp").build().expect("mirror");
+    let root = fs.root_dir();
+
+    let first = fs.readdir_index(root, 0, 10).await?;
+    assert_eq!(first.entries.len(), 10);
+    assert!(!first.end);
+    let name: nfs3::filename3 = first.entries[5].name.clone();
+    assert_eq!(fs.lookup(root, &name).await?, first.entries[5].fileid);
+
+    // Pages continue where the previous one ended, and any page can be asked again.
+    let mut names = Vec::new();
+    let mut index = 0;
+    loop {
+        let page = fs.readdir_index(root, index, 97).await?;
+        let again = fs.readdir_index(root, index, 97).await?;
+        assert_eq!(page.entries.len(), again.entries.len());
+        assert_eq!(page.entries.last().map(|e| e.fileid), again.entries.last().map(|e| e.fileid));
+        index += page.entries.len();
+        names.extend(page.entries.iter().map(|e| String::from_utf8_lossy(&e.name).to_string()));
+        if page.end {
+            break;
+        }
+        assert!(!page.entries.is_empty());
+    }
+    let mut expected: Vec<String> = (0..3000).map(|i| format!("f{i:04}")).collect();
+    expected.splice(0..0, [".".to_string(), "..".to_string()]);
+    names[2..].sort();
+    assert_eq!(names, expected);
+
+    let past_end = fs.readdir_index(root, index + 1, 10).await;
+    assert_eq!(past_end.unwrap_err(), nfs3::nfsstat3::NFS3ERR_BAD_COOKIE);
+
+    // A listing started over sees changes.
+    std::fs::write(temp.path.join("new"), b"").expect("write");
+    let listing = fs.readdir_index(root, 0, 4000).await?;
+    assert!(listing.end);
+    assert_eq!(listing.entries.len(), 3003);
+    assert!(listing.entries.iter().any(|e| e.name.as_ref() == b"new"));
+    Ok(())
+}
+
 #[tokio::test]
 async fn rename_directory_updates_descendant_aliases() -> Result<(), nfs3::nfsstat3> {
     let temp = TempDir::new("mirrorfs_rename").expect("temp dir");
-    let fs = mirror_fs::MirrorFS::new(temp.path.clone());
+    let fs = MirrorFS::new(temp.path.clone());
     let root = fs.root_dir();
 
     let old_name: nfs3::filename3 = b"old".as_ref().into();
@@ -105,12 +140,20 @@ async fn rename_directory_updates_descendant_aliases() -> Result<(), nfs3::nfsst
     Ok(())
 }
 
+#[test]
+fn map_of_an_unusable_root_is_an_error() {
+    let temp = TempDir::new("mirrorfs_map_root").expect("temp dir");
+    std::fs::write(temp.path.join("file"), b"").expect("write file");
+    assert!(FSMap::new(temp.path.join("missing")).is_err());
+    assert!(FSMap::new(temp.path.join("file")).is_err());
+}
+
 #[tokio::test]
 async fn refresh_dir_list_does_not_skip_when_metadata_is_stale() -> Result<(), nfs3::nfsstat3> {
     let temp = TempDir::new("mirrorfs_refresh_dir_list").expect("temp dir");
     std::fs::write(temp.path.join("a"), b"seed").expect("write seed");
 
-    let mut fsmap = fs_map::FSMap::new(temp.path.clone());
+    let mut fsmap = FSMap::new(temp.path.clone()).expect("map");
     fsmap.refresh_dir_list(0).await?;
 
     let original_meta = fsmap.id_to_path.get(&0).expect("root entry").fsmeta;
@@ -145,7 +188,7 @@ async fn refresh_dir_list_does_not_skip_when_metadata_is_stale() -> Result<(), n
 #[tokio::test]
 async fn lookup_returns_noent_after_out_of_band_delete() -> Result<(), nfs3::nfsstat3> {
     let temp = TempDir::new("mirrorfs_lookup_stale").expect("temp dir");
-    let fs = mirror_fs::MirrorFS::new(temp.path.clone());
+    let fs = MirrorFS::new(temp.path.clone());
     let root = fs.root_dir();
 
     let name: nfs3::filename3 = b"stale.txt".as_ref().into();
@@ -163,7 +206,7 @@ async fn lookup_returns_noent_after_out_of_band_delete() -> Result<(), nfs3::nfs
 #[tokio::test]
 async fn lookup_succeeds_after_atomic_replace() -> Result<(), nfs3::nfsstat3> {
     let temp = TempDir::new("mirrorfs_lookup_replace").expect("temp dir");
-    let fs = mirror_fs::MirrorFS::new(temp.path.clone());
+    let fs = MirrorFS::new(temp.path.clone());
     let root = fs.root_dir();
 
     let name: nfs3::filename3 = b"swap.txt".as_ref().into();
@@ -183,11 +226,62 @@ async fn lookup_succeeds_after_atomic_replace() -> Result<(), nfs3::nfsstat3> {
     Ok(())
 }
 
+/// Capability sets of the calling thread, as `capget(2)` reads them
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// Keeps root from overriding permission bits while alive, so tests of permission
+/// errors also hold when run as root. Applies to the calling thread and the threads
+/// it starts, such as the runtime's blocking pool.
+#[cfg(target_os = "linux")]
+struct Unprivileged([CapData; 2]);
+
+#[cfg(target_os = "linux")]
+impl Unprivileged {
+    /// `_LINUX_CAPABILITY_VERSION_3`
+    const VERSION: u32 = 0x2008_0522;
+    const CAP_DAC_OVERRIDE: u32 = 1;
+    const CAP_DAC_READ_SEARCH: u32 = 2;
+
+    fn enter() -> Self {
+        let saved = Self::capset(None);
+        let mut dropped = saved;
+        dropped[0].effective &= !(1 << Self::CAP_DAC_OVERRIDE | 1 << Self::CAP_DAC_READ_SEARCH);
+        Self::capset(Some(dropped));
+        Self(saved)
+    }
+
+    /// Sets the thread's capabilities to `data` if given, returning them
+    fn capset(data: Option<[CapData; 2]>) -> [CapData; 2] {
+        let mut header = [Self::VERSION, 0];
+        let mut current = data.unwrap_or_default();
+        let call = if data.is_some() { libc::SYS_capset } else { libc::SYS_capget };
+        let result = unsafe { libc::syscall(call, header.as_mut_ptr(), current.as_mut_ptr()) };
+        assert_eq!(result, 0, "{}", std::io::Error::last_os_error());
+        current
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Unprivileged {
+    fn drop(&mut self) {
+        Self::capset(Some(self.0));
+    }
+}
+
 #[tokio::test]
 #[cfg(unix)]
 async fn readdir_errors_when_directory_is_unreadable() -> Result<(), nfs3::nfsstat3> {
+    #[cfg(target_os = "linux")]
+    let _unprivileged = Unprivileged::enter();
     let temp = TempDir::new("mirrorfs_readdir_perm").expect("temp dir");
-    let fs = mirror_fs::MirrorFS::new(temp.path.clone());
+    let fs = MirrorFS::new(temp.path.clone());
     let root = fs.root_dir();
 
     let dir_name: nfs3::filename3 = b"private".as_ref().into();
@@ -218,7 +312,7 @@ async fn readdir_errors_when_directory_is_unreadable() -> Result<(), nfs3::nfsst
 #[tokio::test]
 async fn readdir_reflects_file_size_after_write() -> Result<(), nfs3::nfsstat3> {
     let temp = TempDir::new("mirrorfs_readdir_size").expect("temp dir");
-    let fs = mirror_fs::MirrorFS::new(temp.path.clone());
+    let fs = MirrorFS::new(temp.path.clone());
     let root = fs.root_dir();
 
     let name: nfs3::filename3 = b"data.bin".as_ref().into();
@@ -240,3 +334,119 @@ async fn readdir_reflects_file_size_after_write() -> Result<(), nfs3::nfsstat3>
 
     Ok(())
 }
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_creates_survive_relisting() -> Result<(), nfs3::nfsstat3> {
+    let temp = TempDir::new("mirrorfs_concurrent").expect("temp dir");
+    let fs = std::sync::Arc::new(MirrorFS::new(temp.path.clone()));
+    let root = fs.root_dir();
+
+    let mut tasks = Vec::new();
+    for d in 0..4 {
+        let dir_name: nfs3::filename3 = format!("dir{d}").into_bytes().into();
+        let (dir, _) = fs.mkdir(root, &dir_name).await?;
+        let creator = fs.clone();
+        tasks.push(tokio::spawn(async move {
+            let mut created = Vec::new();
+            for i in 0..50 {
+                let name: nfs3::filename3 = format!("file{i}").into_bytes().into();
+                let (id, _) = creator.create(dir, &name, nfs3::sattr3::default()).await?;
+                created.push((name, id));
+            }
+            Ok::<_, nfs3::nfsstat3>((dir, created))
+        }));
+        let lister = fs.clone();
+        tasks.push(tokio::spawn(async move {
+            for _ in 0..50 {
+                lister.readdir(dir, 0, usize::MAX).await?;
+                lister.getattr(dir).await?;
+            }
+            Ok((dir, Vec::new()))
+        }));
+    }
+
+    for task in tasks {
+        let (dir, created) = task.await.expect("task panicked")?;
+        for (name, id) in created.iter() {
+            assert_eq!(fs.lookup(dir, name).await?, *id);
+            assert_eq!(fs.getattr(*id).await?.fileid, *id);
+        }
+        if !created.is_empty() {
+            // ".", ".." and the new files
+            assert_eq!(fs.readdir(dir, 0, usize::MAX).await?.entries.len(), 52);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn mknod_creates_special_files() -> Result<(), nfs3::nfsstat3> {
+    use fernfs::protocol::rpc::with_caller;
+    use fernfs::xdr::rpc::auth_unix;
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+    let temp = TempDir::new("mirrorfs_mknod").expect("temp dir");
+    let fs = MirrorFS::new(temp.path.clone());
+    let root = fs.root_dir();
+    let name = |name: &str| -> nfs3::filename3 { name.as_bytes().into() };
+    let mode = nfs3::sattr3 { mode: nfs3::set_mode3::Some(0o640), ..Default::default() };
+    let none = nfs3::specdata3::default();
+
+    let (fifo, attr) = fs.mknod(root, &name("fifo"), nfs3::ftype3::NF3FIFO, none, &mode).await?;
+    assert!(matches!(attr.ftype, nfs3::ftype3::NF3FIFO));
+    assert_eq!(attr.mode, 0o640);
+    let meta = std::fs::symlink_metadata(temp.path.join("fifo")).unwrap();
+    assert!(meta.file_type().is_fifo());
+    assert_eq!(meta.permissions().mode() & 0o777, 0o640);
+    // The server neither waits for a writer nor reads the pipe.
+    assert_eq!(fs.read(fifo, 0, 16).await.unwrap_err(), nfs3::nfsstat3::NFS3ERR_INVAL);
+
+    let (_, attr) = fs.mknod(root, &name("sock"), nfs3::ftype3::NF3SOCK, none, &mode).await?;
+    assert!(matches!(attr.ftype, nfs3::ftype3::NF3SOCK));
+    assert!(std::fs::symlink_metadata(temp.path.join("sock")).unwrap().file_type().is_socket());
+
+    // Devices need a caller with root credentials.
+    let null = nfs3::specdata3 { specdata1: 1, specdata2: 3 };
+    let denied = fs.mknod(root, &name("null"), nfs3::ftype3::NF3CHR, null, &mode).await;
+    assert_eq!(denied.unwrap_err(), nfs3::nfsstat3::NFS3ERR_PERM);
+    let user = auth_unix { uid: 1000, gid: 1000, ..Default::default() };
+    let denied =
+        with_caller(user, fs.mknod(root, &name("null"), nfs3::ftype3::NF3CHR, null, &mode)).await;
+    assert_eq!(denied.unwrap_err(), nfs3::nfsstat3::NFS3ERR_PERM);
+    assert!(!temp.path.join("null").exists());
+
+    if unsafe { libc::geteuid() } != 0 {
+        return Ok(());
+    }
+    let root_user = auth_unix::default();
+    let created = with_caller(
+        root_user.clone(),
+        fs.mknod(root, &name("null"), nfs3::ftype3::NF3CHR, null, &mode),
+    )
+    .await;
+    let (device, attr) = match created {
+        // Device creation may be forbidden to containers even as root.
+        Err(nfs3::nfsstat3::NFS3ERR_PERM) => return Ok(()),
+        result => result?,
+    };
+    assert!(matches!(attr.ftype, nfs3::ftype3::NF3CHR));
+    assert_eq!((attr.rdev.specdata1, attr.rdev.specdata2), (1, 3));
+    let meta = std::fs::symlink_metadata(temp.path.join("null")).unwrap();
+    assert!(meta.file_type().is_char_device());
+    assert_eq!((libc::major(meta.rdev()), libc::minor(meta.rdev())), (1, 3));
+    let attr = fs.getattr(device).await?;
+    assert_eq!((attr.rdev.specdata1, attr.rdev.specdata2), (1, 3));
+    // The server does not open devices for clients.
+    assert_eq!(fs.read(device, 0, 16).await.unwrap_err(), nfs3::nfsstat3::NFS3ERR_INVAL);
+    let written = fs.write(device, 0, b"x", nfs3::file::stable_how::FILE_SYNC).await;
+    assert_eq!(written.unwrap_err(), nfs3::nfsstat3::NFS3ERR_INVAL);
+
+    let loop0 = nfs3::specdata3 { specdata1: 7, specdata2: 0 };
+    let (_, attr) =
+        with_caller(root_user, fs.mknod(root, &name("loop0"), nfs3::ftype3::NF3BLK, loop0, &mode))
+            .await?;
+    assert!(matches!(attr.ftype, nfs3::ftype3::NF3BLK));
+    assert_eq!((attr.rdev.specdata1, attr.rdev.specdata2), (7, 0));
+    Ok(())
+}
@@ -0,0 +1,172 @@
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use fernfs::mirror_fs::MirrorFS;
+use fernfs::vfs::NFSFileSystem;
+use fernfs::xdr::nfs3;
+
+struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    fn new(prefix: &str) -> std::io::Result<Self> {
+        let mut path = std::env::temp_dir();
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+        path.push(format!("fernfs_{prefix}_{nanos}"));
+        std::fs::create_dir(&path)?;
+        Ok(Self { path })
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+const SECRET: &[u8] = b"outside the export";
+
+/// Creates `export/d/f` and, next to the export, `outside/f` and `outside/victim`
+fn setup(temp: &TempDir) -> (PathBuf, PathBuf) {
+    let export = temp.path.join("export");
+    let outside = temp.path.join("outside");
+    std::fs::create_dir_all(export.join("d")).unwrap();
+    std::fs::write(export.join("d/f"), b"inside").unwrap();
+    std::fs::create_dir(&outside).unwrap();
+    std::fs::write(outside.join("f"), SECRET).unwrap();
+    std::fs::set_permissions(outside.join("f"), std::fs::Permissions::from_mode(0o600)).unwrap();
+    std::fs::write(outside.join("victim"), SECRET).unwrap();
+    (export, outside)
+}
+
+fn assert_untouched(outside: &Path) {
+    assert_eq!(std::fs::read(outside.join("f")).unwrap(), SECRET);
+    let mode = std::fs::metadata(outside.join("f")).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    assert_eq!(std::fs::read(outside.join("victim")).unwrap(), SECRET);
+    let mut names: Vec<_> = std::fs::read_dir(outside)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(names, vec!["f", "victim"]);
+}
+
+fn chmod(mode: u32) -> nfs3::sattr3 {
+    nfs3::sattr3 { mode: nfs3::set_mode3::Some(mode), ..Default::default() }
+}
+
+fn truncate() -> nfs3::sattr3 {
+    nfs3::sattr3 { size: nfs3::set_size3::Some(0), ..Default::default() }
+}
+
+#[tokio::test]
+async fn directory_replaced_by_symlink_is_not_followed() -> Result<(), nfs3::nfsstat3> {
+    let temp = TempDir::new("mirrorfs_symlink_swap").expect("temp dir");
+    let (export, outside) = setup(&temp);
+    let fs = MirrorFS::new(export.clone());
+    let root = fs.root_dir();
+
+    let d_name: nfs3::filename3 = b"d".as_ref().into();
+    let f_name: nfs3::filename3 = b"f".as_ref().into();
+    let dir_id = fs.lookup(root, &d_name).await?;
+    let file_id = fs.lookup(dir_id, &f_name).await?;
+
+    std::fs::rename(export.join("d"), export.join("d.real")).unwrap();
+    std::os::unix::fs::symlink(&outside, export.join("d")).unwrap();
+
+    if let Ok((data, _)) = fs.read(file_id, 0, 64).await {
+        assert_ne!(data, SECRET);
+    }
+    let _ = fs.write(file_id, 0, b"escaped", nfs3::file::stable_how::FILE_SYNC).await;
+    let _ = fs.setattr(file_id, chmod(0o666)).await;
+    let _ = fs.setattr(file_id, truncate()).await;
+    let new_name: nfs3::filename3 = b"new".as_ref().into();
+    let _ = fs.create(dir_id, &new_name, nfs3::sattr3::default()).await;
+    let _ = fs.mkdir(dir_id, &new_name).await;
+    let victim: nfs3::filename3 = b"victim".as_ref().into();
+    let _ = fs.remove(dir_id, &victim).await;
+    let _ = fs.rename(dir_id, &victim, root, &new_name).await;
+    let _ = fs.link(file_id, dir_id, &new_name).await;
+    assert!(fs.lookup(dir_id, &victim).await.is_err());
+    if let Ok(entries) = fs.readdir(dir_id, 0, 10).await {
+        assert!(entries.entries.iter().all(|entry| entry.name.as_ref() != b"victim"));
+    }
+
+    assert_untouched(&outside);
+    assert_eq!(std::fs::read(export.join("d.real/f")).unwrap(), b"inside");
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn racing_symlink_swaps_never_escape_the_export() {
+    let temp = TempDir::new("mirrorfs_symlink_race").expect("temp dir");
+    let (export, outside) = setup(&temp);
+    std::os::unix::fs::symlink(&outside, export.join("d.link")).unwrap();
+    let fs = Arc::new(MirrorFS::new(export.clone()));
+    let root = fs.root_dir();
+
+    // Swap `d` between the real directory and a symlink out of the export.
+    let stop = Arc::new(AtomicBool::new(false));
+    let swapper = {
+        let (stop, export) = (stop.clone(), export.clone());
+        std::thread::spawn(move || {
+            let (d, real, link) = (export.join("d"), export.join("d.real"), export.join("d.link"));
+            while !stop.load(Ordering::Relaxed) {
+                std::fs::rename(&d, &real).unwrap();
+                std::fs::rename(&link, &d).unwrap();
+                std::thread::yield_now();
+                std::fs::rename(&d, &link).unwrap();
+                std::fs::rename(&real, &d).unwrap();
+                std::thread::yield_now();
+            }
+        })
+    };
+
+    let d_name: nfs3::filename3 = b"d".as_ref().into();
+    let f_name: nfs3::filename3 = b"f".as_ref().into();
+    let victim: nfs3::filename3 = b"victim".as_ref().into();
+    let deadline = Instant::now() + Duration::from_secs(2);
+    let mut tasks = Vec::new();
+    for worker in 0..4 {
+        let fs = fs.clone();
+        let (d_name, f_name, victim) = (d_name.clone(), f_name.clone(), victim.clone());
+        tasks.push(tokio::spawn(async move {
+            let (mut dir_id, mut file_id) = (root, root);
+            let mut round = 0;
+            while Instant::now() < deadline {
+                round += 1;
+                // IDs from an earlier round may name the swapped path by now.
+                if let Ok(id) = fs.lookup(root, &d_name).await {
+                    dir_id = id;
+                }
+                if let Ok(id) = fs.lookup(dir_id, &f_name).await {
+                    file_id = id;
+                }
+                if let Ok((data, _)) = fs.read(file_id, 0, 64).await {
+                    assert_ne!(data, SECRET);
+                }
+                let _ = fs.write(file_id, 0, b"inside", nfs3::file::stable_how::UNSTABLE).await;
+                let _ = fs.setattr(file_id, chmod(0o644)).await;
+                if round % 8 == 0 {
+                    let _ = fs.setattr(file_id, truncate()).await;
+                }
+                let name: nfs3::filename3 = format!("new{worker}_{round}").into_bytes().into();
+                let _ = fs.create(dir_id, &name, nfs3::sattr3::default()).await;
+                let _ = fs.remove(dir_id, &victim).await;
+                let _ = fs.readdir(dir_id, 0, 10).await;
+            }
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+    stop.store(true, Ordering::Relaxed);
+    swapper.join().unwrap();
+
+    assert_untouched(&outside);
+}
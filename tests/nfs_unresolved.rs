@@ -14,6 +14,8 @@ use fernfs::xdr::{self, nfs3, Serialize};
 
 const ROOT_ID: nfs3::fileid3 = 1;
 
+type WriteResult = Result<(nfs3::fattr3, nfs3::file::stable_how, nfs3::count3), nfs3::nfsstat3>;
+
 struct TestFS {
     generation: u64,
     capabilities: Capabilities,
@@ -25,8 +27,7 @@ struct TestFS {
     create_result: Mutex<Option<Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3>>>,
     mknod_result: Mutex<Option<Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3>>>,
     mknod_attrs: Mutex<Option<nfs3::sattr3>>,
-    write_result:
-        Mutex<Option<Result<(nfs3::fattr3, nfs3::file::stable_how, nfs3::count3), nfs3::nfsstat3>>>,
+    write_result: Mutex<Option<WriteResult>>,
     readdir_result: Mutex<Option<Result<ReadDirResult, nfs3::nfsstat3>>>,
     fsinfo_result: Mutex<Option<Result<nfs3::fs::fsinfo3, nfs3::nfsstat3>>>,
     setattr_calls: Mutex<Vec<nfs3::sattr3>>,
@@ -675,8 +676,7 @@ async fn mkdir_applies_requested_attributes() {
     fs.insert_attr(2, dir_attr(2));
     let context = make_context(fs.clone());
 
-    let mut attrs = nfs3::sattr3::default();
-    attrs.mode = nfs3::set_mode3::Some(0o755);
+    let attrs = nfs3::sattr3 { mode: nfs3::set_mode3::Some(0o755), ..Default::default() };
     let args = nfs3::dir::MKDIR3args {
         dirops: nfs3::diropargs3 { dir: fs.id_to_fh(ROOT_ID), name: b"dir".as_ref().into() },
         attributes: attrs,
@@ -723,8 +723,7 @@ async fn create_unchecked_updates_existing_attributes() {
     fs.insert_lookup(ROOT_ID, b"file", 2);
     let context = make_context(fs.clone());
 
-    let mut attrs = nfs3::sattr3::default();
-    attrs.mode = nfs3::set_mode3::Some(0o644);
+    let attrs = nfs3::sattr3 { mode: nfs3::set_mode3::Some(0o644), ..Default::default() };
 
     let dirops = nfs3::diropargs3 { dir: fs.id_to_fh(ROOT_ID), name: b"file".as_ref().into() };
 
@@ -769,8 +768,7 @@ async fn mknod_passes_pipe_attributes() {
     fs.insert_attr(ROOT_ID, dir_attr(ROOT_ID));
     let context = make_context(fs.clone());
 
-    let mut attrs = nfs3::sattr3::default();
-    attrs.mode = nfs3::set_mode3::Some(0o600);
+    let attrs = nfs3::sattr3 { mode: nfs3::set_mode3::Some(0o600), ..Default::default() };
 
     let dirops = nfs3::diropargs3 { dir: fs.id_to_fh(ROOT_ID), name: b"pipe".as_ref().into() };
 
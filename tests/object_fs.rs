@@ -0,0 +1,196 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use fernfs::conformance::ConformanceSuite;
+use fernfs::object_fs::{LocalObjectStore, Metadata, ObjectFS, ObjectStore};
+use fernfs::vfs::NFSFileSystem;
+use fernfs::xdr::nfs3;
+
+struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    fn new(prefix: &str) -> std::io::Result<Self> {
+        let mut path = std::env::temp_dir();
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+        path.push(format!("fernfs_{prefix}_{nanos}"));
+        std::fs::create_dir(&path)?;
+        Ok(Self { path })
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+fn name(name: &str) -> nfs3::filename3 {
+    name.as_bytes().into()
+}
+
+fn pattern(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i * 31 + seed as usize) as u8).collect()
+}
+
+async fn names(fs: &ObjectFS, dir: nfs3::fileid3) -> Vec<String> {
+    let listing = fs.readdir(dir, 0, 10_000).await.expect("readdir");
+    assert!(listing.end);
+    listing.entries.iter().map(|e| String::from_utf8_lossy(&e.name).to_string()).collect()
+}
+
+#[tokio::test]
+async fn passes_conformance_suite() {
+    let temp = TempDir::new("objectfs_conformance").expect("temp dir");
+    let count = AtomicUsize::new(0);
+    ConformanceSuite::new(|| {
+        let dir = temp.path.join(count.fetch_add(1, Ordering::Relaxed).to_string());
+        ObjectFS::new(Arc::new(LocalObjectStore::new(dir).expect("open store")))
+    })
+    .run()
+    .await
+    .assert_passed();
+}
+
+#[tokio::test]
+async fn directories_come_from_key_prefixes() -> Result<(), nfs3::nfsstat3> {
+    let temp = TempDir::new("objectfs_prefixes").expect("temp dir");
+    let store = Arc::new(LocalObjectStore::new(&temp.path).expect("open store"));
+    for key in ["exports/a.txt", "exports/photos/2024/b.jpg", "exports/photos/c.jpg", "other/d"] {
+        store.put(key, key.as_bytes().to_vec(), &Metadata::new()).await.expect("put");
+    }
+    let fs = ObjectFS::new(store.clone()).prefix("/exports/");
+    let root = fs.root_dir();
+    assert_eq!(names(&fs, root).await, [".", "..", "a.txt", "photos"]);
+
+    let photos = fs.lookup(root, &name("photos")).await?;
+    let attr = fs.getattr(photos).await?;
+    assert!(matches!(attr.ftype, nfs3::ftype3::NF3DIR));
+    assert_eq!(attr.nlink, 3);
+    assert_eq!(names(&fs, photos).await, [".", "..", "2024", "c.jpg"]);
+    let year = fs.lookup(photos, &name("2024")).await?;
+    let file = fs.lookup(year, &name("b.jpg")).await?;
+    assert_eq!(fs.read(file, 0, 100).await?, (b"exports/photos/2024/b.jpg".to_vec(), true));
+    assert_eq!(fs.read(file, 8, 6).await?.0, b"photos");
+
+    // A directory that only exists through its keys survives losing the last of them.
+    fs.remove(year, &name("b.jpg")).await?;
+    assert_eq!(names(&fs, photos).await, [".", "..", "2024", "c.jpg"]);
+    assert!(store.head("exports/photos/2024/").await.expect("head").is_some());
+    fs.remove(photos, &name("2024")).await?;
+    assert!(matches!(fs.getattr(year).await, Err(nfs3::nfsstat3::NFS3ERR_STALE)));
+
+    let moved = fs.mkdir(root, &name("moved")).await?.0;
+    fs.rename(root, &name("photos"), moved, &name("pictures")).await?;
+    assert_eq!(fs.lookup(moved, &name("pictures")).await?, photos);
+    assert!(store.head("exports/moved/pictures/c.jpg").await.expect("head").is_some());
+    assert!(store.head("exports/photos/c.jpg").await.expect("head").is_none());
+    assert!(store.head("other/d").await.expect("head").is_some());
+    Ok(())
+}
+
+#[tokio::test]
+async fn writes_are_uploaded_in_parts_and_published_on_commit() -> Result<(), nfs3::nfsstat3> {
+    let temp = TempDir::new("objectfs_multipart").expect("temp dir");
+    let store = Arc::new(LocalObjectStore::new(&temp.path).expect("open store"));
+    let fs = ObjectFS::new(store.clone()).part_size(4096);
+    let (id, _) = fs.create(fs.root_dir(), &name("big"), nfs3::sattr3::default()).await?;
+
+    let mut expected = pattern(20_000, 1);
+    for (i, piece) in expected.chunks(1500).enumerate() {
+        let (attr, committed, _) =
+            fs.write(id, i as u64 * 1500, piece, nfs3::file::stable_how::UNSTABLE).await?;
+        assert_eq!(committed, nfs3::file::stable_how::UNSTABLE);
+        assert_eq!(attr.size, (i * 1500 + piece.len()) as u64);
+    }
+    // Other clients of the bucket still see the empty object.
+    assert_eq!(store.head("big").await.expect("head").expect("object").size, 0);
+    assert!(std::fs::read_dir(temp.path.join("uploads")).expect("uploads").next().is_some());
+    // The last, partial part is read back from memory.
+    assert_eq!(fs.read(id, 19_000, 2000).await?, (expected[19_000..].to_vec(), true));
+
+    assert_eq!(fs.commit(id, 0, 0).await?.size, 20_000);
+    assert_eq!(store.get_range("big", 0, u64::MAX).await.expect("get"), expected);
+    assert!(std::fs::read_dir(temp.path.join("uploads")).expect("uploads").next().is_none());
+
+    // Writes into uploaded parts and past the end start a new upload from the object.
+    for (offset, len, seed) in [(100, 50, 2), (8000, 300, 3), (30_000, 10, 4)] {
+        let data = pattern(len, seed);
+        fs.write(id, offset as u64, &data, nfs3::file::stable_how::UNSTABLE).await?;
+        if expected.len() < offset + len {
+            expected.resize(offset + len, 0);
+        }
+        expected[offset..offset + len].copy_from_slice(&data);
+    }
+    assert_eq!(fs.read(id, 0, 64 * 1024).await?, (expected.clone(), true));
+    fs.write(id, 0, b"sync", nfs3::file::stable_how::FILE_SYNC).await?;
+    expected[..4].copy_from_slice(b"sync");
+    assert_eq!(store.get_range("big", 0, u64::MAX).await.expect("get"), expected);
+    Ok(())
+}
+
+#[tokio::test]
+async fn attributes_are_kept_in_object_metadata() -> Result<(), nfs3::nfsstat3> {
+    let temp = TempDir::new("objectfs_metadata").expect("temp dir");
+    let store = Arc::new(LocalObjectStore::new(&temp.path).expect("open store"));
+    {
+        let fs = ObjectFS::new(store.clone()).owner(1000, 1000);
+        let attr = nfs3::sattr3 { mode: Some(0o600), uid: Some(42), ..Default::default() };
+        let (id, attr) = fs.create(fs.root_dir(), &name("secret"), attr).await?;
+        assert_eq!((attr.mode, attr.uid, attr.gid), (0o600, 42, 1000));
+        fs.write(id, 0, b"data", nfs3::file::stable_how::FILE_SYNC).await?;
+    }
+
+    // A new server finds the attributes on the object.
+    let fs = ObjectFS::new(store);
+    let found = fs.lookup(fs.root_dir(), &name("secret")).await?;
+    let attr = fs.getattr(found).await?;
+    assert_eq!((attr.mode, attr.uid, attr.gid, attr.size), (0o600, 42, 0, 4));
+    Ok(())
+}
+
+#[tokio::test]
+async fn large_directories_are_listed_across_pages() -> Result<(), nfs3::nfsstat3> {
+    let temp = TempDir::new("objectfs_pages").expect("temp dir");
+    let store = Arc::new(LocalObjectStore::new(&temp.path).expect("open store"));
+    for i in 0..1100 {
+        store.put(&format!("many/{i:04}"), Vec::new(), &Metadata::new()).await.expect("put");
+    }
+    let first = store.list("many/", None).await.expect("list");
+    assert_eq!(first.objects.len(), 1000);
+    let next = store.list("many/", first.next.as_deref()).await.expect("list");
+    assert_eq!((next.objects.len(), next.next), (100, None));
+
+    let fs = ObjectFS::new(store);
+    let dir = fs.lookup(fs.root_dir(), &name("many")).await?;
+    let page = fs.readdir(dir, 1098, 10).await?;
+    let listed: Vec<_> =
+        page.entries.iter().map(|e| String::from_utf8_lossy(&e.name).to_string()).collect();
+    assert_eq!(listed, ["1096", "1097", "1098", "1099"]);
+    assert!(page.end);
+    Ok(())
+}
+
+#[tokio::test]
+async fn writes_survive_a_failed_commit() -> Result<(), nfs3::nfsstat3> {
+    let temp = TempDir::new("objectfs_failed_commit").expect("temp dir");
+    let store = Arc::new(LocalObjectStore::new(&temp.path).expect("open store"));
+    let fs = ObjectFS::new(store.clone());
+    let (id, _) = fs.create(fs.root_dir(), &name("file"), nfs3::sattr3::default()).await?;
+    fs.write(id, 0, b"unstable", nfs3::file::stable_how::UNSTABLE).await?;
+
+    // A directory in place of the object makes publishing it fail.
+    let object = temp.path.join("objects").join("file.o");
+    std::fs::remove_file(&object).expect("remove object");
+    std::fs::create_dir(&object).expect("block object");
+    assert_eq!(fs.commit(id, 0, 0).await.unwrap_err(), nfs3::nfsstat3::NFS3ERR_IO);
+    assert_eq!(fs.read(id, 0, 100).await?, (b"unstable".to_vec(), true));
+
+    std::fs::remove_dir(&object).expect("unblock object");
+    assert_eq!(fs.commit(id, 0, 0).await?.size, 8);
+    assert_eq!(store.get_range("file", 0, u64::MAX).await.expect("get"), b"unstable");
+    Ok(())
+}
@@ -0,0 +1,270 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use fernfs::vfs::path_fs::{PathFSAdapter, PathFileSystem};
+use fernfs::vfs::{Capabilities, NFSFileSystem};
+use fernfs::xdr::nfs3;
+
+#[derive(Clone)]
+enum Node {
+    Dir,
+    File { inode: u64 },
+}
+
+/// A path-keyed store where hard links share an inode number.
+struct PathStore {
+    nodes: Mutex<BTreeMap<Vec<u8>, Node>>,
+    next_inode: Mutex<u64>,
+}
+
+impl PathStore {
+    fn new() -> Self {
+        Self {
+            nodes: Mutex::new(BTreeMap::from([(Vec::new(), Node::Dir)])),
+            next_inode: Mutex::new(100),
+        }
+    }
+
+    fn attr(node: &Node) -> nfs3::fattr3 {
+        match node {
+            Node::Dir => {
+                nfs3::fattr3 { ftype: nfs3::ftype3::NF3DIR, mode: 0o755, ..Default::default() }
+            }
+            Node::File { inode } => nfs3::fattr3 {
+                ftype: nfs3::ftype3::NF3REG,
+                mode: 0o644,
+                fileid: *inode,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn children(nodes: &BTreeMap<Vec<u8>, Node>, dir: &[u8]) -> Vec<(Vec<u8>, Node)> {
+        nodes
+            .iter()
+            .filter_map(|(path, node)| {
+                let rest = if dir.is_empty() {
+                    path.as_slice()
+                } else {
+                    path.strip_prefix(dir)?.strip_prefix(b"/")?
+                };
+                if rest.is_empty() || rest.contains(&b'/') {
+                    return None;
+                }
+                Some((rest.to_vec(), node.clone()))
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl PathFileSystem for PathStore {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::ReadWrite
+    }
+
+    async fn getattr(&self, path: &[u8]) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        let nodes = self.nodes.lock().unwrap();
+        nodes.get(path).map(Self::attr).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)
+    }
+
+    async fn readdir(
+        &self,
+        path: &[u8],
+    ) -> Result<Vec<(nfs3::filename3, nfs3::fattr3)>, nfs3::nfsstat3> {
+        let nodes = self.nodes.lock().unwrap();
+        Ok(Self::children(&nodes, path)
+            .into_iter()
+            .map(|(name, node)| (name.into(), Self::attr(&node)))
+            .collect())
+    }
+
+    async fn read(
+        &self,
+        path: &[u8],
+        _offset: u64,
+        _count: u32,
+    ) -> Result<(Vec<u8>, bool), nfs3::nfsstat3> {
+        self.nodes.lock().unwrap().get(path).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?;
+        Ok((path.to_vec(), true))
+    }
+
+    async fn create(
+        &self,
+        path: &[u8],
+        _attr: nfs3::sattr3,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        let mut inode = self.next_inode.lock().unwrap();
+        *inode += 1;
+        let node = Node::File { inode: *inode };
+        self.nodes.lock().unwrap().insert(path.to_vec(), node.clone());
+        Ok(Self::attr(&node))
+    }
+
+    async fn mkdir(&self, path: &[u8]) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        self.nodes.lock().unwrap().insert(path.to_vec(), Node::Dir);
+        Ok(Self::attr(&Node::Dir))
+    }
+
+    async fn remove(&self, path: &[u8]) -> Result<(), nfs3::nfsstat3> {
+        self.nodes.lock().unwrap().remove(path).map(|_| ()).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)
+    }
+
+    async fn rename(&self, from: &[u8], to: &[u8]) -> Result<(), nfs3::nfsstat3> {
+        let mut nodes = self.nodes.lock().unwrap();
+        let moved: Vec<Vec<u8>> = nodes
+            .keys()
+            .filter(|path| {
+                path.as_slice() == from
+                    || (path.starts_with(from) && path.get(from.len()) == Some(&b'/'))
+            })
+            .cloned()
+            .collect();
+        for old in moved {
+            let node = nodes.remove(&old).unwrap();
+            let mut new = to.to_vec();
+            new.extend_from_slice(&old[from.len()..]);
+            nodes.insert(new, node);
+        }
+        Ok(())
+    }
+
+    async fn link(&self, from: &[u8], to: &[u8]) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        let mut nodes = self.nodes.lock().unwrap();
+        let node = nodes.get(from).cloned().ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?;
+        nodes.insert(to.to_vec(), node.clone());
+        Ok(Self::attr(&node))
+    }
+}
+
+fn name(s: &str) -> nfs3::filename3 {
+    s.as_bytes().into()
+}
+
+#[tokio::test]
+async fn lookup_returns_stable_ids_and_rewrites_fileid() -> Result<(), nfs3::nfsstat3> {
+    let fs = PathFSAdapter::new(PathStore::new());
+    let root = fs.root_dir();
+    let (dir_id, _) = fs.mkdir(root, &name("dir")).await?;
+    let (file_id, attr) = fs.create(dir_id, &name("file"), nfs3::sattr3::default()).await?;
+    assert_eq!(attr.fileid, file_id);
+
+    assert_eq!(fs.lookup(root, &name("dir")).await?, dir_id);
+    assert_eq!(fs.lookup(dir_id, &name("file")).await?, file_id);
+    assert_eq!(fs.lookup(dir_id, &name("..")).await?, root);
+    assert_eq!(fs.getattr(file_id).await?.fileid, file_id);
+    Ok(())
+}
+
+#[tokio::test]
+async fn rename_rewrites_paths_below_directory() -> Result<(), nfs3::nfsstat3> {
+    let fs = PathFSAdapter::new(PathStore::new());
+    let root = fs.root_dir();
+    let (dir_id, _) = fs.mkdir(root, &name("old")).await?;
+    let (file_id, _) = fs.create(dir_id, &name("file"), nfs3::sattr3::default()).await?;
+
+    fs.rename(root, &name("old"), root, &name("new")).await?;
+
+    assert_eq!(fs.id_to_path(file_id).as_deref(), Some(b"new/file".as_slice()));
+    assert_eq!(fs.getattr(file_id).await?.fileid, file_id);
+    assert_eq!(fs.lookup(root, &name("new")).await?, dir_id);
+    assert_eq!(fs.lookup(root, &name("old")).await, Err(nfs3::nfsstat3::NFS3ERR_NOENT));
+    Ok(())
+}
+
+#[tokio::test]
+async fn hard_links_share_a_fileid() -> Result<(), nfs3::nfsstat3> {
+    let fs = PathFSAdapter::new(PathStore::new());
+    let root = fs.root_dir();
+    let (file_id, _) = fs.create(root, &name("a"), nfs3::sattr3::default()).await?;
+    let attr = fs.link(file_id, root, &name("b")).await?;
+    assert_eq!(attr.fileid, file_id);
+    assert_eq!(fs.lookup(root, &name("b")).await?, file_id);
+
+    // Removing one name keeps the object reachable through the other.
+    fs.remove(root, &name("a")).await?;
+    assert_eq!(fs.getattr(file_id).await?.fileid, file_id);
+    assert_eq!(fs.id_to_path(file_id).as_deref(), Some(b"b".as_slice()));
+
+    let listing = fs.readdir(root, 0, 10).await?;
+    let names: Vec<_> =
+        listing.entries.iter().map(|e| String::from_utf8_lossy(&e.name).to_string()).collect();
+    assert_eq!(names, vec![".", "..", "b"]);
+    assert_eq!(listing.entries[2].fileid, file_id);
+    Ok(())
+}
+
+#[tokio::test]
+async fn ids_are_evicted_when_backend_paths_vanish() -> Result<(), nfs3::nfsstat3> {
+    let store = PathStore::new();
+    let fs = PathFSAdapter::new(store);
+    let root = fs.root_dir();
+    let (file_id, _) = fs.create(root, &name("gone"), nfs3::sattr3::default()).await?;
+
+    // Delete behind the adapter's back.
+    fs.inner().nodes.lock().unwrap().remove(b"gone".as_slice());
+
+    assert_eq!(fs.getattr(file_id).await.unwrap_err(), nfs3::nfsstat3::NFS3ERR_STALE);
+    assert_eq!(fs.id_to_path(file_id), None);
+    Ok(())
+}
+
+#[tokio::test]
+async fn link_replaces_a_stale_mapping_of_the_new_name() -> Result<(), nfs3::nfsstat3> {
+    let fs = PathFSAdapter::new(PathStore::new());
+    let root = fs.root_dir();
+    let (file_id, _) = fs.create(root, &name("a"), nfs3::sattr3::default()).await?;
+    let (old_id, _) = fs.create(root, &name("b"), nfs3::sattr3::default()).await?;
+
+    // Delete behind the adapter's back, then reuse the name for a link.
+    fs.inner().nodes.lock().unwrap().remove(b"b".as_slice());
+    fs.link(file_id, root, &name("b")).await?;
+
+    assert_eq!(fs.lookup(root, &name("b")).await?, file_id);
+    assert_eq!(fs.id_to_path(old_id), None);
+    assert_eq!(fs.getattr(old_id).await.unwrap_err(), nfs3::nfsstat3::NFS3ERR_STALE);
+    Ok(())
+}
+
+#[tokio::test]
+async fn renaming_a_missing_name_onto_itself_fails() -> Result<(), nfs3::nfsstat3> {
+    let fs = PathFSAdapter::new(PathStore::new());
+    let root = fs.root_dir();
+    fs.create(root, &name("a"), nfs3::sattr3::default()).await?;
+    fs.rename(root, &name("a"), root, &name("a")).await?;
+    let err = fs.rename(root, &name("b"), root, &name("b")).await.unwrap_err();
+    assert_eq!(err, nfs3::nfsstat3::NFS3ERR_NOENT);
+    Ok(())
+}
+
+#[tokio::test]
+async fn names_of_one_inode_share_a_fileid_once_it_is_reported() -> Result<(), nfs3::nfsstat3> {
+    let fs = PathFSAdapter::new(PathStore::new());
+    let root = fs.root_dir();
+    let (file_id, _) = fs.create(root, &name("a"), nfs3::sattr3::default()).await?;
+    fs.inner().nodes.lock().unwrap().insert(b"b".to_vec(), Node::File { inode: 0 });
+    let unlinked = fs.lookup(root, &name("b")).await?;
+    assert_ne!(unlinked, file_id);
+
+    // The backend now reports "b" as a hard link to "a".
+    let inode = fs.inner().getattr(b"a").await?.fileid;
+    fs.inner().nodes.lock().unwrap().insert(b"b".to_vec(), Node::File { inode });
+    assert_eq!(fs.lookup(root, &name("b")).await?, file_id);
+    assert_eq!(fs.getattr(unlinked).await.unwrap_err(), nfs3::nfsstat3::NFS3ERR_STALE);
+    Ok(())
+}
+
+#[tokio::test]
+async fn reads_follow_renames_made_outside_the_adapter() -> Result<(), nfs3::nfsstat3> {
+    let fs = PathFSAdapter::new(PathStore::new());
+    let root = fs.root_dir();
+    let (file_id, _) = fs.create(root, &name("a"), nfs3::sattr3::default()).await?;
+
+    fs.inner().rename(b"a", b"c").await?;
+    assert_eq!(fs.lookup(root, &name("c")).await?, file_id);
+    assert_eq!(fs.read(file_id, 0, 10).await?.0, b"c");
+    assert_eq!(fs.id_to_path(file_id).as_deref(), Some(b"c".as_slice()));
+    Ok(())
+}
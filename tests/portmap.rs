@@ -39,9 +39,9 @@ fn multiple_contexts(amount: u32) -> Vec<Context> {
     for i in 1..=amount {
         result.push(Context {
             local_port: DEFAULT_PROG,
-            client_addr: format!("0.0.0.0:{}", i),
+            client_addr: format!("0.0.0.0:{i}"),
             auth: xdr::rpc::auth_unix::default(),
-            vfs: Arc::new(DemoFS::default()),
+            vfs: Arc::new(DemoFS),
             mount_signal: None,
             export_name: Arc::from(DEFAULT_EXPORT_NAME.to_string()),
             transaction_tracker: Arc::new(rpc::TransactionTracker::new(Duration::from_secs(60))),
@@ -181,7 +181,7 @@ mod tests {
             local_port: DEFAULT_PORT,
             client_addr: DEFAULT_ADDRESS.to_string(),
             auth: xdr::rpc::auth_unix::default(),
-            vfs: Arc::new(DemoFS::default()),
+            vfs: Arc::new(DemoFS),
             mount_signal: None,
             export_name: Arc::from(DEFAULT_EXPORT_NAME.to_string()),
             transaction_tracker: Arc::new(rpc::TransactionTracker::new(Duration::from_secs(60))),
@@ -205,7 +205,7 @@ mod tests {
             local_port: DEFAULT_PORT,
             client_addr: DEFAULT_ADDRESS.to_string(),
             auth: xdr::rpc::auth_unix::default(),
-            vfs: Arc::new(DemoFS::default()),
+            vfs: Arc::new(DemoFS),
             mount_signal: None,
             export_name: Arc::from(DEFAULT_EXPORT_NAME.to_string()),
             transaction_tracker: Arc::new(rpc::TransactionTracker::new(Duration::from_secs(60))),
@@ -235,7 +235,7 @@ mod tests {
             local_port: DEFAULT_PORT,
             client_addr: DEFAULT_ADDRESS.to_string(),
             auth: xdr::rpc::auth_unix::default(),
-            vfs: Arc::new(DemoFS::default()),
+            vfs: Arc::new(DemoFS),
             mount_signal: None,
             export_name: Arc::from(DEFAULT_EXPORT_NAME.to_string()),
             transaction_tracker: Arc::new(rpc::TransactionTracker::new(Duration::from_secs(60))),
@@ -261,7 +261,7 @@ mod tests {
             local_port: DEFAULT_PORT,
             client_addr: DEFAULT_ADDRESS.to_string(),
             auth: xdr::rpc::auth_unix::default(),
-            vfs: Arc::new(DemoFS::default()),
+            vfs: Arc::new(DemoFS),
             mount_signal: None,
             export_name: Arc::from(DEFAULT_EXPORT_NAME.to_string()),
             transaction_tracker: Arc::new(rpc::TransactionTracker::new(Duration::from_secs(60))),
@@ -365,7 +365,7 @@ mod tests {
             local_port: DEFAULT_PORT,
             client_addr: DEFAULT_ADDRESS.to_string(),
             auth: xdr::rpc::auth_unix::default(),
-            vfs: Arc::new(DemoFS::default()),
+            vfs: Arc::new(DemoFS),
             mount_signal: None,
             export_name: Arc::from(DEFAULT_EXPORT_NAME.to_string()),
             transaction_tracker: Arc::new(rpc::TransactionTracker::new(Duration::from_secs(60))),
@@ -387,7 +387,7 @@ mod tests {
             local_port: DEFAULT_PORT,
             client_addr: DEFAULT_ADDRESS.to_string(),
             auth: xdr::rpc::auth_unix::default(),
-            vfs: Arc::new(DemoFS::default()),
+            vfs: Arc::new(DemoFS),
             mount_signal: None,
             export_name: Arc::from(DEFAULT_EXPORT_NAME.to_string()),
             transaction_tracker: Arc::new(rpc::TransactionTracker::new(Duration::from_secs(60))),
@@ -413,7 +413,7 @@ mod tests {
             local_port: DEFAULT_PORT,
             client_addr: DEFAULT_ADDRESS.to_string(),
             auth: xdr::rpc::auth_unix::default(),
-            vfs: Arc::new(DemoFS::default()),
+            vfs: Arc::new(DemoFS),
             mount_signal: None,
             export_name: Arc::from(DEFAULT_EXPORT_NAME.to_string()),
             transaction_tracker: Arc::new(rpc::TransactionTracker::new(Duration::from_secs(60))),
@@ -522,7 +522,7 @@ mod tests {
             local_port: DEFAULT_PORT,
             client_addr: DEFAULT_ADDRESS.to_string(),
             auth: xdr::rpc::auth_unix::default(),
-            vfs: Arc::new(DemoFS::default()),
+            vfs: Arc::new(DemoFS),
             mount_signal: None,
             export_name: Arc::from(DEFAULT_EXPORT_NAME.to_string()),
             transaction_tracker: Arc::new(rpc::TransactionTracker::new(Duration::from_secs(60))),
@@ -18,7 +18,7 @@ fn test_context() -> Context {
         local_port: 0,
         client_addr: "127.0.0.1:1234".to_string(),
         auth: xdr::rpc::auth_unix::default(),
-        vfs: Arc::new(DemoFS::default()),
+        vfs: Arc::new(DemoFS),
         mount_signal: None,
         export_name: Arc::from("/".to_string()),
         transaction_tracker: Arc::new(TransactionTracker::new(Duration::from_secs(60))),
@@ -109,10 +109,10 @@ async fn returns_prog_mismatch_for_unsupported_nfs_version() {
                     assert_eq!(info.low, nfs3::VERSION);
                     assert_eq!(info.high, nfs3::VERSION);
                 }
-                other => panic!("expected PROG_MISMATCH, got {:?}", other),
+                other => panic!("expected PROG_MISMATCH, got {other:?}"),
             }
         }
-        other => panic!("expected MSG_ACCEPTED, got {:?}", other),
+        other => panic!("expected MSG_ACCEPTED, got {other:?}"),
     }
 }
 
@@ -0,0 +1,107 @@
+use std::sync::Arc;
+
+use fernfs::conformance::ConformanceSuite;
+use fernfs::mem_fs::MemFS;
+use fernfs::subtree_fs::SubtreeFS;
+use fernfs::vfs::NFSFileSystem;
+use fernfs::xdr::nfs3;
+
+fn name(name: &str) -> nfs3::filename3 {
+    name.as_bytes().into()
+}
+
+/// A MemFS with `projects/foo/src/main.rs` and `projects/bar/secret`.
+async fn projects() -> Result<Arc<MemFS>, nfs3::nfsstat3> {
+    let fs = MemFS::new();
+    let (projects, _) = fs.mkdir(fs.root_dir(), &name("projects")).await?;
+    let (project, _) = fs.mkdir(projects, &name("foo")).await?;
+    let (src, _) = fs.mkdir(project, &name("src")).await?;
+    fs.create(src, &name("main.rs"), nfs3::sattr3::default()).await?;
+    let (bar, _) = fs.mkdir(projects, &name("bar")).await?;
+    fs.create(bar, &name("secret"), nfs3::sattr3::default()).await?;
+    Ok(Arc::new(fs))
+}
+
+#[tokio::test]
+async fn passes_conformance_suite() {
+    ConformanceSuite::new(|| {
+        let inner = futures::executor::block_on(projects()).unwrap();
+        futures::executor::block_on(SubtreeFS::open(inner, "projects/foo")).unwrap()
+    })
+    .run()
+    .await
+    .assert_passed();
+}
+
+#[tokio::test]
+async fn nothing_above_the_root_is_reachable() -> Result<(), nfs3::nfsstat3> {
+    let inner = projects().await?;
+    let fs = SubtreeFS::open(inner.clone(), "projects/foo").await?;
+    let root = fs.root_dir();
+    assert_eq!(root, inner.path_to_id(b"projects/foo").await?);
+
+    assert_eq!(fs.lookup(root, &name("..")).await?, root);
+    let entries = fs.readdir(root, 0, 10).await?.entries;
+    assert_eq!((&entries[1].name[..], entries[1].fileid), (&b".."[..], root));
+    assert_eq!(fs.path_to_id(b"../bar").await.unwrap_err(), nfs3::nfsstat3::NFS3ERR_NOENT);
+    let main = fs.path_to_id(b"src/main.rs").await?;
+    assert_eq!(fs.getattr(main).await?.fileid, main);
+
+    // Objects outside the subtree are valid in the wrapped file system but not here.
+    for outside in [&b"projects"[..], b"", b"projects/bar/secret"] {
+        let id = inner.path_to_id(outside).await?;
+        assert_eq!(fs.fh_to_id(&fs.id_to_fh(id)).unwrap_err(), nfs3::nfsstat3::NFS3ERR_STALE);
+        assert_eq!(fs.getattr(id).await.unwrap_err(), nfs3::nfsstat3::NFS3ERR_STALE);
+    }
+    assert_eq!(fs.fh_to_id(&fs.id_to_fh(main)), Ok(main));
+    Ok(())
+}
+
+#[tokio::test]
+async fn tracks_ancestry_through_changes() -> Result<(), nfs3::nfsstat3> {
+    let fs = SubtreeFS::open(projects().await?, "projects/foo").await?;
+    let root = fs.root_dir();
+    let src = fs.lookup(root, &name("src")).await?;
+    let (docs, _) = fs.mkdir(root, &name("docs")).await?;
+
+    fs.rename(root, &name("docs"), src, &name("docs")).await?;
+    assert_eq!(fs.lookup(docs, &name("..")).await?, src);
+    assert_eq!(fs.lookup(src, &name("..")).await?, root);
+
+    fs.remove(src, &name("docs")).await?;
+    assert_eq!(fs.getattr(docs).await.unwrap_err(), nfs3::nfsstat3::NFS3ERR_STALE);
+    Ok(())
+}
+
+#[tokio::test]
+async fn forgets_objects_beyond_the_tracking_limit() -> Result<(), nfs3::nfsstat3> {
+    let fs = SubtreeFS::open(projects().await?, "projects/foo").await?.max_tracked(4);
+    let root = fs.root_dir();
+    let mut files = Vec::new();
+    for i in 0..8 {
+        files.push(fs.create(root, &name(&format!("file{i}")), nfs3::sattr3::default()).await?.0);
+    }
+
+    // The earliest handles are stale until looked up again; the latest still work.
+    assert_eq!(fs.getattr(files[0]).await.unwrap_err(), nfs3::nfsstat3::NFS3ERR_STALE);
+    assert_eq!(fs.getattr(files[7]).await?.fileid, files[7]);
+    assert_eq!(fs.lookup(root, &name("file0")).await?, files[0]);
+    assert_eq!(fs.getattr(files[0]).await?.fileid, files[0]);
+    assert_eq!(fs.getattr(root).await?.fileid, root);
+
+    // A file replaced by a rename is forgotten along with its last link.
+    fs.rename(root, &name("file7"), root, &name("file0")).await?;
+    assert_eq!(fs.getattr(files[0]).await.unwrap_err(), nfs3::nfsstat3::NFS3ERR_STALE);
+    assert_eq!(fs.lookup(root, &name("file0")).await?, files[7]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn root_must_be_a_directory() -> Result<(), nfs3::nfsstat3> {
+    let inner = projects().await?;
+    let err = SubtreeFS::open(inner.clone(), "projects/bar/secret").await.err();
+    assert_eq!(err, Some(nfs3::nfsstat3::NFS3ERR_NOTDIR));
+    let err = SubtreeFS::open(inner, "projects/missing").await.err();
+    assert_eq!(err, Some(nfs3::nfsstat3::NFS3ERR_NOENT));
+    Ok(())
+}
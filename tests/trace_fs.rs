@@ -0,0 +1,139 @@
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use fernfs::conformance::ConformanceSuite;
+use fernfs::mem_fs::MemFS;
+use fernfs::trace_fs::{Call, Replayer, Reply, TraceFS, TraceReader};
+use fernfs::vfs::NFSFileSystem;
+use fernfs::xdr::nfs3;
+
+fn name(name: &str) -> nfs3::filename3 {
+    name.as_bytes().into()
+}
+
+/// A trace destination that can be read back while the writer is in use.
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn reader(&self) -> TraceReader<io::Cursor<Vec<u8>>> {
+        TraceReader::new(io::Cursor::new(self.0.lock().unwrap().clone())).unwrap()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs a small session against a traced MemFS and returns the trace.
+async fn record_session() -> Result<SharedBuffer, nfs3::nfsstat3> {
+    let trace = SharedBuffer::default();
+    let fs = TraceFS::new(Arc::new(MemFS::new()), trace.clone()).unwrap();
+    let root = fs.root_dir();
+    let (docs, _) = fs.mkdir(root, &name("docs")).await?;
+    let (file, _) = fs.create(docs, &name("a.txt"), nfs3::sattr3::default()).await?;
+    fs.write(file, 0, b"hello world", nfs3::file::stable_how::FILE_SYNC).await?;
+    fs.read(file, 6, 100).await?;
+    fs.rename(docs, &name("a.txt"), root, &name("b.txt")).await?;
+    fs.readdir(root, 0, 10).await?;
+    assert!(fs.lookup(docs, &name("a.txt")).await.is_err());
+    fs.remove(root, &name("docs")).await?;
+    Ok(trace)
+}
+
+#[tokio::test]
+async fn passes_conformance_suite() {
+    ConformanceSuite::new(|| TraceFS::new(Arc::new(MemFS::new()), io::sink()).unwrap())
+        .run()
+        .await
+        .assert_passed();
+}
+
+#[tokio::test]
+async fn records_calls_with_results() -> Result<(), nfs3::nfsstat3> {
+    let trace = record_session().await?;
+    let records: Vec<_> = trace.reader().collect::<io::Result<_>>().unwrap();
+    assert_eq!(records.len(), 8);
+    assert!(matches!(&records[0].call, Call::Mkdir { name, .. } if &name[..] == b"docs"));
+    match (&records[2].call, &records[2].result) {
+        (Call::Write { data, offset: 0, .. }, Ok(Reply::Write { count: 11, .. })) => {
+            assert_eq!(data, b"hello world");
+        }
+        other => panic!("unexpected record {other:?}"),
+    }
+    match &records[3].result {
+        Ok(Reply::Read { data, eof: true }) => assert_eq!(data, b"world"),
+        other => panic!("unexpected read result {other:?}"),
+    }
+    match &records[5].result {
+        Ok(Reply::Readdir(result)) => {
+            let names: Vec<_> = result.entries.iter().map(|e| e.name.0.clone()).collect();
+            assert_eq!(names, [&b"."[..], b"..", b"docs", b"b.txt"]);
+        }
+        other => panic!("unexpected readdir result {other:?}"),
+    }
+    assert_eq!(records[6].result.as_ref().unwrap_err(), &nfs3::nfsstat3::NFS3ERR_NOENT);
+
+    // A trace cut off in the middle of a record is reported as such.
+    let mut bytes = trace.0.lock().unwrap().clone();
+    bytes.truncate(bytes.len() - 3);
+    let reader = TraceReader::new(io::Cursor::new(bytes)).unwrap();
+    let err = reader.collect::<io::Result<Vec<_>>>().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    Ok(())
+}
+
+#[tokio::test]
+async fn commit_returns_once_the_trace_is_flushed() -> Result<(), nfs3::nfsstat3> {
+    let trace = SharedBuffer::default();
+    let fs = TraceFS::new(Arc::new(MemFS::new()), trace.clone()).unwrap();
+    let (file, _) = fs.create(fs.root_dir(), &name("a"), nfs3::sattr3::default()).await?;
+    fs.write(file, 0, b"data", nfs3::file::stable_how::UNSTABLE).await?;
+    fs.commit(file, 0, 0).await?;
+
+    let records: Vec<_> = trace.reader().collect::<io::Result<_>>().unwrap();
+    assert_eq!(records.len(), 3);
+    assert!(matches!(records[2].call, Call::Commit { id, .. } if id == file));
+    Ok(())
+}
+
+#[tokio::test]
+async fn replays_cleanly_with_different_fileids() -> Result<(), nfs3::nfsstat3> {
+    let trace = record_session().await?;
+    let target = MemFS::new();
+    // Use up some fileids so the replayed objects get different ones.
+    for i in 0..3 {
+        let file = name(&format!("tmp{i}"));
+        target.create(target.root_dir(), &file, nfs3::sattr3::default()).await?;
+        target.remove(target.root_dir(), &file).await?;
+    }
+    let report = Replayer::new(Arc::new(target)).replay(trace.reader()).await.unwrap();
+    assert_eq!(report.calls, 8);
+    assert!(report.is_clean(), "{:?}", report.mismatches);
+    Ok(())
+}
+
+#[tokio::test]
+async fn reports_differing_results() -> Result<(), nfs3::nfsstat3> {
+    let trace = record_session().await?;
+    let target = MemFS::new();
+    let (docs, _) = target.mkdir(target.root_dir(), &name("docs")).await?;
+    let (file, _) = target.create(docs, &name("keep"), nfs3::sattr3::default()).await?;
+    target.write(file, 0, b"x", nfs3::file::stable_how::FILE_SYNC).await?;
+
+    let report = Replayer::new(Arc::new(target)).replay(trace.reader()).await.unwrap();
+    assert_eq!(report.calls, 8);
+    let failed: Vec<_> = report.mismatches.iter().map(|m| m.index).collect();
+    // MKDIR finds the existing directory, and the extra file keeps it from being removed.
+    assert_eq!(failed, [0, 7]);
+    assert_eq!(report.mismatches[0].actual.as_ref().unwrap_err(), &nfs3::nfsstat3::NFS3ERR_EXIST);
+    assert!(report.mismatches[1].to_string().starts_with("call 7 Remove"));
+    Ok(())
+}
@@ -0,0 +1,165 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use fernfs::conformance::ConformanceSuite;
+use fernfs::mem_fs::MemFS;
+use fernfs::union_fs::{Layer, UnionFS};
+use fernfs::vfs::NFSFileSystem;
+use fernfs::xdr::nfs3;
+
+fn name(name: &str) -> nfs3::filename3 {
+    name.as_bytes().into()
+}
+
+const FILE_SYNC: nfs3::file::stable_how = nfs3::file::stable_how::FILE_SYNC;
+
+/// Builds a read-only-to-be layer holding `files`, creating parent directories as needed.
+async fn layer(files: &[(&str, &[u8])]) -> Result<Arc<MemFS>, nfs3::nfsstat3> {
+    let fs = MemFS::new();
+    for (path, data) in files {
+        let mut dir = fs.root_dir();
+        let mut parts: Vec<_> = path.split('/').collect();
+        let file = parts.pop().unwrap();
+        for part in parts {
+            dir = match fs.lookup(dir, &name(part)).await {
+                Ok(id) => id,
+                Err(_) => fs.mkdir(dir, &name(part)).await?.0,
+            };
+        }
+        let mode = nfs3::sattr3 { mode: Some(0o750), ..Default::default() };
+        let (id, _) = fs.create(dir, &name(file), mode).await?;
+        fs.write(id, 0, data, FILE_SYNC).await?;
+    }
+    Ok(Arc::new(fs))
+}
+
+async fn names(fs: &impl NFSFileSystem, dir: nfs3::fileid3) -> Result<Vec<String>, nfs3::nfsstat3> {
+    let entries = fs.readdir(dir, 0, 100).await?.entries;
+    Ok(entries.into_iter().map(|e| String::from_utf8(e.name.0).unwrap()).collect())
+}
+
+#[tokio::test]
+async fn passes_conformance_suite() -> Result<(), nfs3::nfsstat3> {
+    let lower: Layer = layer(&[("toolchain/bin/cc", b"cc")]).await?;
+    ConformanceSuite::new(move || UnionFS::new(Arc::new(MemFS::new())).with_lower(lower.clone()))
+        .run()
+        .await
+        .assert_passed();
+    Ok(())
+}
+
+#[tokio::test]
+async fn copy_up_leaves_lower_layers_untouched() -> Result<(), nfs3::nfsstat3> {
+    let lower = layer(&[("bin/tool", b"original"), ("bin/other", b"other")]).await?;
+    let upper = Arc::new(MemFS::new());
+    let fs = UnionFS::new(upper.clone()).with_lower(lower.clone());
+
+    let tool = fs.path_to_id(b"bin/tool").await?;
+    let before = fs.getattr(tool).await?;
+    let (attr, _, _) = fs.write(tool, 0, b"patched", FILE_SYNC).await?;
+    assert_eq!(attr.fileid, tool, "copy-up must keep the fileid");
+    assert_eq!(attr.mode, 0o750);
+    assert_eq!(fs.read(tool, 0, 100).await?.0, b"patchedl");
+    assert_eq!(fs.path_to_id(b"bin/tool").await?, tool);
+    assert_eq!(before.fileid, tool);
+
+    // Only the changed file and its parent directory reach the upper layer.
+    let lower_tool = lower.path_to_id(b"bin/tool").await?;
+    assert_eq!(lower.read(lower_tool, 0, 100).await?.0, b"original");
+    let upper_bin = upper.path_to_id(b"bin").await?;
+    assert_eq!(names(&*upper, upper_bin).await?, [".", "..", "tool"]);
+    let bin = fs.path_to_id(b"bin").await?;
+    assert_eq!(names(&fs, bin).await?, [".", "..", "tool", "other"]);
+
+    // Truncating a lower file copies it up without its data.
+    let other = fs.path_to_id(b"bin/other").await?;
+    let truncated = fs.setattr(other, nfs3::sattr3 { size: Some(0), ..Default::default() }).await?;
+    assert_eq!((truncated.size, truncated.mode), (0, 0o750));
+    Ok(())
+}
+
+#[tokio::test]
+async fn whiteouts_hide_lower_entries() -> Result<(), nfs3::nfsstat3> {
+    let lower = layer(&[("etc/a", b"a"), ("etc/b", b"b"), ("cache/x", b"x")]).await?;
+    let upper = Arc::new(MemFS::new());
+    let fs = UnionFS::new(upper.clone()).with_lower(lower);
+    let root = fs.root_dir();
+    let etc = fs.path_to_id(b"etc").await?;
+
+    fs.remove(etc, &name("a")).await?;
+    assert_eq!(fs.lookup(etc, &name("a")).await.unwrap_err(), nfs3::nfsstat3::NFS3ERR_NOENT);
+    fs.rename(etc, &name("b"), root, &name("b")).await?;
+    assert_eq!(names(&fs, etc).await?, [".", ".."]);
+    assert_eq!(fs.read(fs.path_to_id(b"b").await?, 0, 10).await?.0, b"b");
+    let upper_etc = upper.path_to_id(b"etc").await?;
+    assert_eq!(names(&*upper, upper_etc).await?, [".", "..", ".wh.a", ".wh.b"]);
+
+    // A directory recreated over a whiteout does not merge with the old one.
+    let cache = fs.path_to_id(b"cache").await?;
+    fs.remove(cache, &name("x")).await?;
+    fs.remove(root, &name("cache")).await?;
+    let (cache, _) = fs.mkdir(root, &name("cache")).await?;
+    assert_eq!(names(&fs, cache).await?, [".", ".."]);
+    fs.create(cache, &name("y"), nfs3::sattr3::default()).await?;
+    assert_eq!(names(&fs, cache).await?, [".", "..", "y"]);
+
+    // Whiteout names are reserved.
+    let err = fs.create(root, &name(".wh.b"), nfs3::sattr3::default()).await.unwrap_err();
+    assert_eq!(err, nfs3::nfsstat3::NFS3ERR_INVAL);
+    assert_eq!(fs.lookup(etc, &name(".wh.a")).await.unwrap_err(), nfs3::nfsstat3::NFS3ERR_NOENT);
+    Ok(())
+}
+
+#[tokio::test]
+async fn copied_up_files_stay_hidden_once_removed_or_renamed() -> Result<(), nfs3::nfsstat3> {
+    let lower = layer(&[("a", b"lower a"), ("b", b"lower b")]).await?;
+    let fs = UnionFS::new(Arc::new(MemFS::new())).with_lower(lower);
+    let root = fs.root_dir();
+    let stable = nfs3::file::stable_how::FILE_SYNC;
+
+    let a = fs.lookup(root, &name("a")).await?;
+    fs.write(a, 0, b"upper", stable).await?;
+    fs.remove(root, &name("a")).await?;
+    assert_eq!(fs.lookup(root, &name("a")).await.unwrap_err(), nfs3::nfsstat3::NFS3ERR_NOENT);
+
+    let b = fs.lookup(root, &name("b")).await?;
+    fs.setattr(b, nfs3::sattr3 { mode: Some(0o600), ..Default::default() }).await?;
+    fs.rename(root, &name("b"), root, &name("c")).await?;
+    assert_eq!(fs.lookup(root, &name("b")).await.unwrap_err(), nfs3::nfsstat3::NFS3ERR_NOENT);
+    assert_eq!(fs.read(fs.lookup(root, &name("c")).await?, 0, 10).await?.0, b"lower b");
+    assert_eq!(names(&fs, root).await?, [".", "..", "c"]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn merges_directories_with_distinct_fileids() -> Result<(), nfs3::nfsstat3> {
+    // Both lowers number "share/one" and "share/two" identically.
+    let top = layer(&[("share/one", b"top"), ("only-top", b"")]).await?;
+    let bottom = layer(&[("share/two", b"bottom"), ("only-bottom", b"")]).await?;
+    let fs = UnionFS::new(Arc::new(MemFS::new())).with_lower(top).with_lower(bottom);
+    let share = fs.path_to_id(b"share").await?;
+
+    let entries = fs.readdir(share, 0, 100).await?.entries;
+    let listed: Vec<_> =
+        entries.iter().map(|e| String::from_utf8(e.name.0.clone()).unwrap()).collect();
+    assert_eq!(listed, [".", "..", "one", "two"]);
+    let ids: HashSet<_> = entries.iter().map(|e| e.fileid).collect();
+    assert_eq!(ids.len(), entries.len(), "fileids must not collide across layers");
+    for entry in &entries[2..] {
+        assert_eq!(entry.attr.fileid, entry.fileid);
+        assert_eq!(fs.lookup(share, &entry.name).await?, entry.fileid);
+    }
+
+    // Cookies index the merged listing, so paging sees every entry exactly once.
+    let page = fs.readdir(share, 3, 100).await?;
+    assert_eq!(page.entries.len(), 1);
+    assert_eq!(page.entries[0].fileid, entries[3].fileid);
+    assert!(page.end);
+
+    // Directories that exist in a lower layer cannot be moved.
+    let root = fs.root_dir();
+    let err = fs.rename(root, &name("share"), root, &name("moved")).await.unwrap_err();
+    assert_eq!(err, nfs3::nfsstat3::NFS3ERR_XDEV);
+    assert_eq!(names(&fs, root).await?, [".", "..", "share", "only-top", "only-bottom"]);
+    Ok(())
+}